@@ -14,6 +14,15 @@
 
 use clap::Parser;
 use engine::*;
+use signal_hook::{
+    consts::{SIGHUP, SIGINT, SIGTERM},
+    iterator::Signals,
+};
+use std::{sync::Arc, thread::JoinHandle, time::Duration};
+
+const DRAIN_TIMEOUT: Duration = Duration::from_secs(10);
+
+const SUBMIT_TIMEOUT: Duration = Duration::from_secs(60);
 
 fn print_banner() {
     println!(
@@ -56,29 +65,166 @@ fn print_banner() {
 ///
 fn start() {
     let (id, coredump) = snapshot::load().unwrap();
-    let (connector, state) = fusotao::sync().unwrap();
+    let (id, coredump) = replication::follow(id, coredump);
+    let (connector, state) = if C.fusotao.standalone {
+        (None, fusotao::standalone())
+    } else {
+        let (connector, state) = fusotao::sync().unwrap();
+        (Some(connector), state)
+    };
     let shared = Shared::new(state.clone(), C.fusotao.get_x25519());
-    let (output_tx, output_rx) = std::sync::mpsc::channel();
-    let (event_tx, event_rx) = std::sync::mpsc::channel();
+    let (output_tx, output_rx) = ring::named("executor-market", C.server.ring_capacity);
+    let (event_tx, event_rx) = ring::named("sequencer-executor", C.server.ring_capacity);
     let (input_tx, input_rx) = std::sync::mpsc::channel();
     let (reply_tx, reply_rx) = std::sync::mpsc::channel();
-    market::init(output_rx, reply_tx.clone());
-    committer::init(connector.clone(), state.clone());
-    executor::init(event_rx, output_tx, reply_tx.clone(), coredump);
+    let market = market::init(output_rx, reply_tx.clone());
+    match connector {
+        Some(ref connector) => committer::init(connector.clone(), state.clone()),
+        None => committer::sink(state.clone()),
+    }
+    let executor = executor::init(
+        event_rx,
+        output_tx,
+        reply_tx.clone(),
+        input_tx.clone(),
+        coredump,
+    );
     sequencer::init(input_rx, event_tx, reply_tx, id);
-    scanner::init(input_tx.clone(), connector, state);
+    replication::init();
+    let st = state.clone();
+    std::thread::spawn(move || shutdown(executor, market, st));
+    std::thread::spawn(reload);
+    if let Some(connector) = connector {
+        scanner::init(input_tx.clone(), connector, state);
+    }
     server::init(reply_rx, input_tx, shared);
 }
 
+/// reload the config on SIGHUP, the failures are only logged and the config is untouched
+fn reload() {
+    let mut signals = Signals::new([SIGHUP]).unwrap();
+    for _ in signals.forever() {
+        if let Err(e) = admin::reload_config() {
+            log::error!("unable to reload the config, {}", e);
+        }
+    }
+}
+
+/// on SIGTERM or SIGINT, stop accepting inputs, execute the sequenced events, dump the final
+/// snapshot and submit the pending proofs before exiting, so nothing is re-executed on restarting
+fn shutdown(
+    executor: JoinHandle<anyhow::Result<()>>,
+    market: JoinHandle<anyhow::Result<()>>,
+    state: Arc<FusoState>,
+) {
+    let mut signals = Signals::new([SIGTERM, SIGINT]).unwrap();
+    signals.forever().next();
+    log::info!("shutting down, send the signal again to exit immediately");
+    std::thread::spawn(move || {
+        signals.forever().next();
+        log::error!("shutting down interrupted");
+        std::process::exit(1);
+    });
+    server::shutdown(DRAIN_TIMEOUT);
+    sequencer::stop();
+    let mut code = 0;
+    for (name, handle) in [("executor", executor), ("market", market)] {
+        match handle.join() {
+            Ok(Ok(())) => {}
+            Ok(Err(e)) => {
+                log::error!("{} stopped with error {:?}", name, e);
+                code = 1;
+            }
+            Err(_) => {
+                log::error!("{} panicked", name);
+                code = 1;
+            }
+        }
+    }
+    if !committer::flush(&state, SUBMIT_TIMEOUT) {
+        code = 1;
+    }
+    log::info!("bye!");
+    std::process::exit(code);
+}
+
+fn load_config(opts: &config::GaloisCli) -> config::Config {
+    opts.load_config().unwrap_or_else(|e| {
+        eprintln!("{}: {}", opts.file.display(), e);
+        std::process::exit(1)
+    })
+}
+
 fn main() {
-    env_logger::init();
-    let opts = config::GaloisCli::parse();
-    match opts.sub {
+    let mut opts = config::GaloisCli::parse();
+    if opts.validate_config {
+        load_config(&opts);
+        println!("{}: ok", opts.file.display());
+        return;
+    }
+    match opts.sub.take() {
         Some(config::SubCmd::Encrypt) => config::print_config(&opts.file).unwrap(),
-        Some(config::SubCmd::Migrate(c)) => migration::migrate(c),
+        Some(config::SubCmd::Migrate(c)) => {
+            env_logger::init();
+            config::install(load_config(&opts));
+            migration::migrate(c)
+        }
+        Some(config::SubCmd::Replay(c)) => {
+            env_logger::init();
+            let mut config = load_config(&opts);
+            // nothing is written but the report
+            config.dry_run = Some(u64::MAX);
+            config::install(config);
+            if !replay::run(c).unwrap() {
+                std::process::exit(2);
+            }
+        }
+        Some(config::SubCmd::Bench(c)) => {
+            env_logger::init();
+            let mut config = load_config(&opts);
+            // nothing is written
+            config.dry_run = Some(u64::MAX);
+            config::install(config);
+            bench::run(c).unwrap();
+        }
+        Some(config::SubCmd::Upgrade(c)) => {
+            env_logger::init();
+            snapshot::upgrade(c).unwrap();
+        }
+        Some(config::SubCmd::Verify(c)) => {
+            env_logger::init();
+            if !verify::run(c).unwrap() {
+                std::process::exit(2);
+            }
+        }
+        Some(config::SubCmd::Journal(c)) => {
+            env_logger::init();
+            if !journal::export(c).unwrap() {
+                std::process::exit(2);
+            }
+        }
+        Some(config::SubCmd::Audit(c)) => {
+            env_logger::init();
+            if !audit::check(c).unwrap() {
+                std::process::exit(2);
+            }
+        }
+        Some(config::SubCmd::Genesis(c)) => {
+            env_logger::init();
+            if !genesis::run(c).unwrap() {
+                std::process::exit(2);
+            }
+        }
+        Some(config::SubCmd::Signer(c)) => {
+            env_logger::init();
+            config::install(load_config(&opts));
+            signer::run(c).unwrap();
+        }
         None => {
             print_banner();
-            lazy_static::initialize(&C);
+            let config = load_config(&opts);
+            config::install_reloadable(config, opts);
+            logger::init(&C.log).unwrap();
             if C.dry_run.is_some() {
                 log::info!("running in dry-run mode");
             }
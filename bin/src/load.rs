@@ -0,0 +1,465 @@
+// Copyright 2021-2023 UINB Technologies Pte. Ltd.
+
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// 	http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use clap::{Parser, ValueEnum};
+use engine::{
+    cipher::{Initiator, SessionCipher},
+    cmd::*,
+    core::*,
+    Message,
+};
+use rand::{rngs::StdRng, seq::SliceRandom, Rng, SeedableRng};
+use serde_json::{json, Value};
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader},
+    net::{tcp::OwnedReadHalf, TcpStream},
+};
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+enum SizeDist {
+    Uniform,
+    Exponential,
+}
+
+/// Synthetic order flow against a galois instance, DO NOT run it against production
+#[derive(Debug, Clone, Parser)]
+#[command(author = "UINB Tech", version)]
+struct LoadCli {
+    #[arg(long, default_value = "127.0.0.1:8097")]
+    addr: String,
+    #[arg(
+        long,
+        help = "The x25519 public key of galois in hex, logged on starting or by GET_X25519_KEY"
+    )]
+    x25519_pub: String,
+    #[arg(
+        long,
+        value_delimiter = ',',
+        default_value = "1/0",
+        help = "Symbols in the form of base/quote, separated by comma"
+    )]
+    symbols: Vec<String>,
+    #[arg(long, default_value_t = 4)]
+    connections: u32,
+    #[arg(long, default_value_t = 1000, help = "Total requests per second")]
+    rate: u32,
+    #[arg(long, default_value_t = 30, help = "Seconds to run")]
+    duration: u64,
+    #[arg(long, default_value_t = 100)]
+    users: u64,
+    #[arg(long, default_value_t = 100.0, help = "The mid price of the orders")]
+    price: f64,
+    #[arg(
+        long,
+        default_value_t = 0.01,
+        help = "Prices are drawn from mid * (1 ± spread), the crossing ones are taken"
+    )]
+    spread: f64,
+    #[arg(long, default_value_t = 2)]
+    price_scale: usize,
+    #[arg(long, default_value_t = 2)]
+    amount_scale: usize,
+    #[arg(long, default_value_t = 1.0)]
+    min_amount: f64,
+    #[arg(long, default_value_t = 10.0)]
+    max_amount: f64,
+    #[arg(long, value_enum, default_value_t = SizeDist::Uniform)]
+    size_dist: SizeDist,
+    #[arg(long, default_value_t = 0.3, help = "Ratio of cancels to all requests")]
+    cancel_ratio: f64,
+    #[arg(
+        long,
+        value_name = "AMOUNT",
+        help = "Transfer in AMOUNT of every currency to every user before starting, by the admin socket"
+    )]
+    fund: Option<f64>,
+    #[arg(long, default_value = "127.0.0.1:8100")]
+    admin_addr: String,
+    #[arg(long, help = "The token of the admin socket, required by --fund")]
+    admin_token: Option<String>,
+}
+
+#[derive(Default)]
+struct Stats {
+    sent: AtomicU64,
+    replied: AtomicU64,
+    rejected: AtomicU64,
+    // micros
+    latencies: Mutex<Vec<u64>>,
+}
+
+struct Placed {
+    symbol: Symbol,
+    user: String,
+    order_id: u64,
+}
+
+/// (symbol, user) if placing an order
+type Placing = Option<(Symbol, String)>;
+
+#[derive(Default)]
+struct Inflight {
+    // req_id -> (sent at, placing)
+    requests: Mutex<HashMap<u64, (Instant, Placing)>>,
+    placed: Mutex<Vec<Placed>>,
+}
+
+fn parse_symbol(s: &str) -> anyhow::Result<Symbol> {
+    let (base, quote) = s
+        .split_once('/')
+        .ok_or(anyhow::anyhow!("invalid symbol {}", s))?;
+    Ok((base.parse()?, quote.parse()?))
+}
+
+fn user_of(i: u64) -> String {
+    let mut id = [0u8; 32];
+    id[24..].copy_from_slice(&(i + 1).to_be_bytes());
+    UserId::new(id).to_string()
+}
+
+impl LoadCli {
+    fn amount(&self, rng: &mut StdRng) -> String {
+        let range = (self.max_amount - self.min_amount).max(0.0);
+        let a = match self.size_dist {
+            SizeDist::Uniform => self.min_amount + range * rng.gen::<f64>(),
+            // mean at 1/4 of the range, clipped to the max
+            SizeDist::Exponential => {
+                self.min_amount + (-(1.0 - rng.gen::<f64>()).ln() * range / 4.0).min(range)
+            }
+        };
+        format!("{:.*}", self.amount_scale, a)
+    }
+
+    fn price(&self, rng: &mut StdRng) -> String {
+        let p = self.price * (1.0 + self.spread * rng.gen_range(-1.0..=1.0));
+        format!("{:.*}", self.price_scale, p)
+    }
+
+    fn next_cmd(
+        &self,
+        rng: &mut StdRng,
+        symbols: &[Symbol],
+        inflight: &Inflight,
+    ) -> (Value, Placing) {
+        if rng.gen::<f64>() < self.cancel_ratio {
+            let mut placed = inflight.placed.lock().unwrap();
+            if !placed.is_empty() {
+                let i = rng.gen_range(0..placed.len());
+                let o = placed.swap_remove(i);
+                let cancel = json!({
+                    "cmd": CANCEL,
+                    "base": o.symbol.0,
+                    "quote": o.symbol.1,
+                    "user_id": o.user,
+                    "order_id": o.order_id,
+                    "nonce": 1,
+                    "signature": "",
+                });
+                return (cancel, None);
+            }
+        }
+        let symbol = *symbols.choose(rng).expect("at least one symbol;qed");
+        let user = user_of(rng.gen_range(0..self.users));
+        let cmd = if rng.gen_bool(0.5) {
+            ASK_LIMIT
+        } else {
+            BID_LIMIT
+        };
+        let place = json!({
+            "cmd": cmd,
+            "base": symbol.0,
+            "quote": symbol.1,
+            "user_id": user,
+            "price": self.price(rng),
+            "amount": self.amount(rng),
+            "nonce": 1,
+            "signature": "",
+        });
+        (place, Some((symbol, user)))
+    }
+}
+
+/// the sessions are sealed after the handshake, the broadcasts before the reply are dropped
+async fn handshake(stream: &mut TcpStream, pinned: &str) -> anyhow::Result<SessionCipher> {
+    const HANDSHAKE_REQ_ID: u64 = u64::MAX;
+    let initiator = Initiator::new();
+    let payload = serde_json::to_vec(&json!({
+        "cmd": X25519_HANDSHAKE,
+        "x25519": initiator.public(),
+    }))?;
+    stream
+        .write_all(&Message::new_req(HANDSHAKE_REQ_ID, payload).encode())
+        .await?;
+    loop {
+        let mut header = [0_u8; 8];
+        let mut req_id = [0_u8; 8];
+        stream.read_exact(&mut header).await?;
+        stream.read_exact(&mut req_id).await?;
+        let header = u64::from_be_bytes(header);
+        anyhow::ensure!(Message::check_magic(header), "invalid magic number");
+        let mut payload = vec![0_u8; Message::get_len(header)];
+        stream.read_exact(&mut payload).await?;
+        if u64::from_be_bytes(req_id) != HANDSHAKE_REQ_ID {
+            continue;
+        }
+        let reply = serde_json::from_slice::<Value>(&payload)?;
+        let server = reply
+            .get("x25519")
+            .and_then(|v| v.as_str())
+            .ok_or(anyhow::anyhow!("handshake rejected by galois"))?;
+        return initiator.complete(server, pinned);
+    }
+}
+
+async fn read_loop(
+    mut stream: OwnedReadHalf,
+    cipher: Arc<SessionCipher>,
+    inflight: Arc<Inflight>,
+    stats: Arc<Stats>,
+) {
+    let mut buf = Vec::<u8>::with_capacity(4096);
+    loop {
+        let mut header = [0_u8; 8];
+        let mut req_id = [0_u8; 8];
+        if stream.read_exact(&mut header).await.is_err() {
+            break;
+        }
+        if stream.read_exact(&mut req_id).await.is_err() {
+            break;
+        }
+        let header = u64::from_be_bytes(header);
+        if !Message::check_magic(header) {
+            break;
+        }
+        let req_id = u64::from_be_bytes(req_id);
+        let mut tmp = vec![0_u8; Message::get_len(header)];
+        if stream.read_exact(&mut tmp).await.is_err() {
+            break;
+        }
+        buf.extend_from_slice(&tmp[..]);
+        if Message::has_next_frame(header) {
+            continue;
+        }
+        // the broadcasts are sealed as well, so all the payloads are opened in order
+        buf = match cipher.open(&buf) {
+            Ok(plain) => plain,
+            Err(_) => break,
+        };
+        // broadcasts are ignored
+        let req = inflight.requests.lock().unwrap().remove(&req_id);
+        if let Some((at, placing)) = req {
+            let elapsed = at.elapsed().as_micros() as u64;
+            stats.latencies.lock().unwrap().push(elapsed);
+            stats.replied.fetch_add(1, Ordering::Relaxed);
+            let reply = serde_json::from_slice::<Value>(&buf).unwrap_or_default();
+            if reply.get("error").is_some() {
+                stats.rejected.fetch_add(1, Ordering::Relaxed);
+            } else if let (Some((symbol, user)), Some(order_id)) =
+                (placing, reply.get("id").and_then(|id| id.as_u64()))
+            {
+                inflight.placed.lock().unwrap().push(Placed {
+                    symbol,
+                    user,
+                    order_id,
+                });
+            }
+        }
+        buf.clear();
+    }
+}
+
+// the transfers are rejected on the data path, they are faked by the admin socket
+async fn fund(opts: &LoadCli, symbols: &[Symbol], amount: f64) -> anyhow::Result<()> {
+    let token = opts
+        .admin_token
+        .as_deref()
+        .ok_or_else(|| anyhow::anyhow!("--admin-token is required by --fund"))?;
+    let (r, mut w) = TcpStream::connect(&opts.admin_addr).await?.into_split();
+    let mut replies = BufReader::new(r).lines();
+    let mut currencies = symbols.iter().flat_map(|s| [s.0, s.1]).collect::<Vec<_>>();
+    currencies.sort();
+    currencies.dedup();
+    for i in 0..opts.users {
+        for currency in currencies.iter() {
+            let transfer = json!({
+                "token": token,
+                "cmd": "fake_transfer",
+                "currency": currency,
+                "user_id": user_of(i),
+                "amount": amount.to_string(),
+            });
+            w.write_all(format!("{}\n", transfer).as_bytes()).await?;
+            let reply = replies
+                .next_line()
+                .await?
+                .ok_or_else(|| anyhow::anyhow!("the admin socket is closed"))?;
+            let reply: Value = serde_json::from_str(&reply)?;
+            if let Some(e) = reply.get("error") {
+                anyhow::bail!("funding {} rejected: {}", user_of(i), e);
+            }
+        }
+    }
+    // the transfers are sequenced, give the engine a moment to apply them
+    tokio::time::sleep(Duration::from_secs(1)).await;
+    Ok(())
+}
+
+async fn connection(
+    opts: Arc<LoadCli>,
+    symbols: Arc<Vec<Symbol>>,
+    seed: u64,
+    stats: Arc<Stats>,
+) -> anyhow::Result<()> {
+    let mut stream = TcpStream::connect(&opts.addr).await?;
+    stream.set_nodelay(true)?;
+    let cipher = Arc::new(handshake(&mut stream, &opts.x25519_pub).await?);
+    let (r, mut w) = stream.into_split();
+    let inflight = Arc::new(Inflight::default());
+    let reader = tokio::spawn(read_loop(
+        r,
+        cipher.clone(),
+        inflight.clone(),
+        stats.clone(),
+    ));
+    let rate = (opts.rate / opts.connections).max(1);
+    let mut ticker = tokio::time::interval(Duration::from_secs(1) / rate);
+    let mut rng = StdRng::seed_from_u64(seed);
+    let until = Instant::now() + Duration::from_secs(opts.duration);
+    let mut req_id = 0u64;
+    while Instant::now() < until {
+        ticker.tick().await;
+        req_id += 1;
+        let (cmd, placing) = opts.next_cmd(&mut rng, &symbols, &inflight);
+        let msg = Message::new_req(req_id, cipher.seal(&serde_json::to_vec(&cmd)?));
+        inflight
+            .requests
+            .lock()
+            .unwrap()
+            .insert(req_id, (Instant::now(), placing));
+        w.write_all(&msg.encode()).await?;
+        stats.sent.fetch_add(1, Ordering::Relaxed);
+    }
+    // wait for the pending replies
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while !inflight.requests.lock().unwrap().is_empty() && Instant::now() < deadline {
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    reader.abort();
+    Ok(())
+}
+
+fn percentile(sorted: &[u64], p: f64) -> u64 {
+    if sorted.is_empty() {
+        return 0;
+    }
+    let i = ((sorted.len() as f64 * p).ceil() as usize).clamp(1, sorted.len());
+    sorted[i - 1]
+}
+
+fn report(stats: &Stats, elapsed: Duration) {
+    let mut latencies = stats.latencies.lock().unwrap().clone();
+    latencies.sort_unstable();
+    let secs = elapsed.as_secs_f64();
+    let sent = stats.sent.load(Ordering::Relaxed);
+    let replied = stats.replied.load(Ordering::Relaxed);
+    println!("elapsed: {:.1}s", secs);
+    println!(
+        "sent: {}, replied: {}, rejected: {}, lost: {}",
+        sent,
+        replied,
+        stats.rejected.load(Ordering::Relaxed),
+        sent - replied
+    );
+    println!("throughput: {:.1} req/s", replied as f64 / secs);
+    println!(
+        "latency(us): p50={} p90={} p99={} p99.9={} max={}",
+        percentile(&latencies, 0.5),
+        percentile(&latencies, 0.9),
+        percentile(&latencies, 0.99),
+        percentile(&latencies, 0.999),
+        latencies.last().copied().unwrap_or_default(),
+    );
+}
+
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+    let opts = LoadCli::parse();
+    anyhow::ensure!(opts.connections > 0 && opts.users > 0, "nothing to do");
+    let symbols = opts
+        .symbols
+        .iter()
+        .map(|s| parse_symbol(s))
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    anyhow::ensure!(!symbols.is_empty(), "no symbols");
+    if let Some(amount) = opts.fund {
+        fund(&opts, &symbols, amount).await?;
+    }
+    let stats = Arc::new(Stats::default());
+    let opts = Arc::new(opts);
+    let symbols = Arc::new(symbols);
+    let start = Instant::now();
+    let progress = {
+        let stats = stats.clone();
+        tokio::spawn(async move {
+            let mut last = 0;
+            loop {
+                tokio::time::sleep(Duration::from_secs(1)).await;
+                let replied = stats.replied.load(Ordering::Relaxed);
+                println!(
+                    "sent: {}, replied: {}/s",
+                    stats.sent.load(Ordering::Relaxed),
+                    replied - last
+                );
+                last = replied;
+            }
+        })
+    };
+    let conns = (0..opts.connections)
+        .map(|i| {
+            tokio::spawn(connection(
+                opts.clone(),
+                symbols.clone(),
+                i as u64,
+                stats.clone(),
+            ))
+        })
+        .collect::<Vec<_>>();
+    for conn in conns {
+        if let Err(e) = conn.await? {
+            eprintln!("connection interrupted, {:?}", e);
+        }
+    }
+    progress.abort();
+    report(&stats, start.elapsed());
+    Ok(())
+}
+
+#[test]
+pub fn test_percentile() {
+    let sorted = (1..=1000).collect::<Vec<u64>>();
+    assert_eq!(500, percentile(&sorted, 0.5));
+    assert_eq!(990, percentile(&sorted, 0.99));
+    assert_eq!(999, percentile(&sorted, 0.999));
+    assert_eq!(0, percentile(&[], 0.5));
+    assert_eq!((1, 0), parse_symbol("1/0").unwrap());
+    assert!(parse_symbol("1").is_err());
+}
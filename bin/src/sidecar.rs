@@ -12,7 +12,11 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use sidecar::*;
+use sidecar::{
+    endpoint::{binance, rest},
+    *,
+};
+use std::sync::Arc;
 
 #[tokio::main]
 async fn main() {
@@ -23,7 +27,36 @@ async fn main() {
 async fn start() -> anyhow::Result<()> {
     let config = config::init_config_file()?;
     let bind_addr = config.bind_addr.clone();
-    let context = context::Context::new(config);
+    let rest_addr = config.rest_addr.clone();
+    let binance = config.binance.clone();
+    let tls = config.tls.clone();
+    let context = Arc::new(context::Context::new(config));
+    if let Some(addr) = rest_addr {
+        let addr = addr.parse::<std::net::SocketAddr>()?;
+        let context = context.clone();
+        tokio::spawn(async move {
+            log::info!(
+                "REST gateway interrupted, {:?}",
+                rest::serve(addr, context).await
+            );
+        });
+    }
+    if let Some(binance) = binance {
+        let addr = binance.bind_addr.parse::<std::net::SocketAddr>()?;
+        let context = context.clone();
+        tokio::spawn(async move {
+            log::info!(
+                "Binance compatible gateway interrupted, {:?}",
+                binance::serve(addr, binance, context).await
+            );
+        });
+    }
+    let bind_addr = bind_addr.parse::<std::net::SocketAddr>()?;
+    // the websocket endpoint is only reachable through the TLS relay
+    let ws_addr = match tls {
+        Some(_) => std::net::SocketAddr::from(([127, 0, 0, 1], 0)),
+        None => bind_addr,
+    };
     let builder = tower::ServiceBuilder::new()
         .layer(context::BrokerVerifyLayer::new(context.backend.clone()));
     let server = jsonrpsee::server::ServerBuilder::new()
@@ -31,8 +64,22 @@ async fn start() -> anyhow::Result<()> {
         .set_middleware(builder)
         .max_connections(10000)
         .max_subscriptions_per_connection(1024)
-        .build(bind_addr.parse::<std::net::SocketAddr>()?)
+        .build(ws_addr)
         .await?;
+    if let Some(tls) = tls {
+        let certs = Arc::new(engine::input::tls::TlsCerts::open(
+            &tls.cert_path,
+            &tls.key_path,
+        )?);
+        let listener = tokio::net::TcpListener::bind(bind_addr).await?;
+        let upstream = server.local_addr()?;
+        tokio::spawn(async move {
+            log::info!(
+                "TLS relay interrupted, {:?}",
+                sidecar::tls::relay(listener, upstream, certs).await
+            );
+        });
+    }
     server.start(endpoint::export_rpc(context))?.stopped().await;
     Ok(())
 }
@@ -0,0 +1,278 @@
+// Copyright 2021-2023 UINB Technologies Pte. Ltd.
+
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// 	http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use serde::{ser::SerializeMap, Deserialize, Deserializer, Serialize, Serializer};
+use std::{
+    borrow::Borrow,
+    collections::{hash_map, hash_map::DefaultHasher, HashMap},
+    hash::{Hash, Hasher},
+    iter::FlatMap,
+    ops::Index,
+    slice,
+    sync::Arc,
+};
+
+const CHUNKS: usize = 64;
+
+type ChunkIter<'a, K, V> = fn(&'a Arc<HashMap<K, V>>) -> hash_map::Iter<'a, K, V>;
+
+pub type Iter<'a, K, V> =
+    FlatMap<slice::Iter<'a, Arc<HashMap<K, V>>>, hash_map::Iter<'a, K, V>, ChunkIter<'a, K, V>>;
+
+/// a hash map split into the chunks shared by the clones, a mutation after cloning copies only
+/// the chunk of the key rather than the whole map, serialized the same as `HashMap<K, V>`
+#[derive(Debug)]
+pub struct ChunkedMap<K, V> {
+    chunks: Vec<Arc<HashMap<K, V>>>,
+}
+
+impl<K, V> Clone for ChunkedMap<K, V> {
+    fn clone(&self) -> Self {
+        Self {
+            chunks: self.chunks.clone(),
+        }
+    }
+}
+
+impl<K, V> Default for ChunkedMap<K, V> {
+    fn default() -> Self {
+        Self {
+            chunks: (0..CHUNKS).map(|_| Arc::new(HashMap::new())).collect(),
+        }
+    }
+}
+
+// the chunk of a key is stable across the processes
+fn chunk_of<Q: Hash + ?Sized>(k: &Q) -> usize {
+    let mut hasher = DefaultHasher::new();
+    k.hash(&mut hasher);
+    (hasher.finish() % CHUNKS as u64) as usize
+}
+
+impl<K, V> ChunkedMap<K, V> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.chunks.iter().map(|c| c.len()).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.chunks.iter().all(|c| c.is_empty())
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.chunks.iter().map(|c| c.capacity()).sum()
+    }
+
+    pub fn iter(&self) -> Iter<'_, K, V> {
+        self.chunks.iter().flat_map(|c| c.iter())
+    }
+
+    pub fn keys(&self) -> impl Iterator<Item = &K> {
+        self.iter().map(|(k, _)| k)
+    }
+
+    pub fn values(&self) -> impl Iterator<Item = &V> {
+        self.iter().map(|(_, v)| v)
+    }
+
+    /// the chunks not shared with `other`
+    pub fn diverged(&self, other: &Self) -> usize {
+        self.chunks
+            .iter()
+            .zip(other.chunks.iter())
+            .filter(|(a, b)| !Arc::ptr_eq(a, b))
+            .count()
+    }
+}
+
+impl<K: Hash + Eq, V> ChunkedMap<K, V> {
+    pub fn get<Q>(&self, k: &Q) -> Option<&V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.chunks[chunk_of(k)].get(k)
+    }
+
+    pub fn get_key_value<Q>(&self, k: &Q) -> Option<(&K, &V)>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.chunks[chunk_of(k)].get_key_value(k)
+    }
+
+    pub fn contains_key<Q>(&self, k: &Q) -> bool
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.chunks[chunk_of(k)].contains_key(k)
+    }
+}
+
+impl<K: Hash + Eq + Clone, V: Clone> ChunkedMap<K, V> {
+    /// the chunk is copied only if the key exists
+    pub fn get_mut<Q>(&mut self, k: &Q) -> Option<&mut V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        let chunk = &mut self.chunks[chunk_of(k)];
+        if chunk.contains_key(k) {
+            Arc::make_mut(chunk).get_mut(k)
+        } else {
+            None
+        }
+    }
+
+    pub fn entry(&mut self, k: K) -> hash_map::Entry<'_, K, V> {
+        Arc::make_mut(&mut self.chunks[chunk_of(&k)]).entry(k)
+    }
+
+    pub fn insert(&mut self, k: K, v: V) -> Option<V> {
+        Arc::make_mut(&mut self.chunks[chunk_of(&k)]).insert(k, v)
+    }
+
+    pub fn remove<Q>(&mut self, k: &Q) -> Option<V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.remove_entry(k).map(|(_, v)| v)
+    }
+
+    pub fn remove_entry<Q>(&mut self, k: &Q) -> Option<(K, V)>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        let chunk = &mut self.chunks[chunk_of(k)];
+        if chunk.contains_key(k) {
+            Arc::make_mut(chunk).remove_entry(k)
+        } else {
+            None
+        }
+    }
+}
+
+impl<K, V, Q> Index<&Q> for ChunkedMap<K, V>
+where
+    K: Hash + Eq + Borrow<Q>,
+    Q: Hash + Eq + ?Sized,
+{
+    type Output = V;
+
+    fn index(&self, k: &Q) -> &V {
+        self.get(k).expect("no entry found for key")
+    }
+}
+
+impl<K: Hash + Eq, V: PartialEq> PartialEq for ChunkedMap<K, V> {
+    fn eq(&self, other: &Self) -> bool {
+        self.chunks == other.chunks
+    }
+}
+
+impl<K: Hash + Eq, V: Eq> Eq for ChunkedMap<K, V> {}
+
+impl<K: Hash + Eq + Clone, V: Clone> Extend<(K, V)> for ChunkedMap<K, V> {
+    fn extend<I: IntoIterator<Item = (K, V)>>(&mut self, iter: I) {
+        for (k, v) in iter {
+            self.insert(k, v);
+        }
+    }
+}
+
+impl<K: Hash + Eq + Clone, V: Clone> FromIterator<(K, V)> for ChunkedMap<K, V> {
+    fn from_iter<I: IntoIterator<Item = (K, V)>>(iter: I) -> Self {
+        let mut map = Self::new();
+        map.extend(iter);
+        map
+    }
+}
+
+impl<K: Clone, V: Clone> IntoIterator for ChunkedMap<K, V> {
+    type IntoIter = std::iter::Flatten<std::vec::IntoIter<HashMap<K, V>>>;
+    type Item = (K, V);
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.chunks
+            .into_iter()
+            .map(|c| Arc::try_unwrap(c).unwrap_or_else(|c| (*c).clone()))
+            .collect::<Vec<_>>()
+            .into_iter()
+            .flatten()
+    }
+}
+
+impl<'a, K, V> IntoIterator for &'a ChunkedMap<K, V> {
+    type IntoIter = Iter<'a, K, V>;
+    type Item = (&'a K, &'a V);
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+impl<K: Serialize, V: Serialize> Serialize for ChunkedMap<K, V> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut map = serializer.serialize_map(Some(self.len()))?;
+        for (k, v) in self.iter() {
+            map.serialize_entry(k, v)?;
+        }
+        map.end()
+    }
+}
+
+impl<'de, K, V> Deserialize<'de> for ChunkedMap<K, V>
+where
+    K: Deserialize<'de> + Hash + Eq + Clone,
+    V: Deserialize<'de> + Clone,
+{
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        HashMap::<K, V>::deserialize(deserializer).map(|m| m.into_iter().collect())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    pub fn test_chunked_map() {
+        let mut map = (0u64..1000).map(|i| (i, i)).collect::<ChunkedMap<_, _>>();
+        let shared = map.clone();
+        assert_eq!(0, map.diverged(&shared));
+        *map.get_mut(&7).unwrap() += 1;
+        map.entry(8).and_modify(|v| *v += 1);
+        // missing
+        assert!(map.get_mut(&1000).is_none());
+        assert!(map.remove(&1001).is_none());
+        assert!(map.diverged(&shared) <= 2);
+        assert_eq!(8, map[&7]);
+        assert_eq!(7, shared[&7]);
+        assert_eq!(1000, map.len());
+        let bytes = bincode::serialize(&map).unwrap();
+        let plain: HashMap<u64, u64> = bincode::deserialize(&bytes).unwrap();
+        assert_eq!(9, plain[&8]);
+        let restored: ChunkedMap<u64, u64> =
+            bincode::deserialize(&bincode::serialize(&plain).unwrap()).unwrap();
+        assert_eq!(map, restored);
+        assert_eq!(map.into_iter().map(|(_, v)| v).sum::<u64>(), 999 * 500 + 2);
+    }
+}
@@ -13,25 +13,50 @@
 // limitations under the License.
 
 use crate::{
-    assets,
-    core::*,
+    assets, fixed,
     matcher::{Match, Role, State},
     orderbook::AskOrBid,
-    output::Output,
+    primitives::*,
 };
 use rust_decimal::{prelude::Zero, Decimal};
 
+/// the balance changes of an order caused by a match
+#[derive(Debug, Clone)]
+pub struct Output {
+    pub event_id: u64,
+    pub order_id: u64,
+    pub user_id: UserId,
+    pub symbol: Symbol,
+    pub state: State,
+    pub role: Role,
+    pub ask_or_bid: AskOrBid,
+    pub price: Price,
+    pub quote_charge: Amount,
+    pub quote_delta: Amount,
+    pub quote_available: Amount,
+    pub quote_frozen: Amount,
+    pub base_charge: Amount,
+    pub base_delta: Amount,
+    pub base_available: Amount,
+    pub base_frozen: Amount,
+    pub timestamp: u64,
+    /// the microseconds of matching, the start of `timestamp` unless stamped by the executor
+    pub timestamp_us: u64,
+}
+
 pub fn clear(
     accounts: &mut Accounts,
     event_id: u64,
     symbol: &Symbol,
     taker_fee: Fee,
     maker_fee: Fee,
+    broker_share: Fee,
     mr: &Match,
     time: u64,
 ) -> Vec<Output> {
     let base = symbol.0;
     let quote = symbol.1;
+    let time_us = time.saturating_mul(1_000_000);
     match mr.taker.state {
         State::Placed => {
             let base_account = assets::get_balance_to_owned(accounts, &mr.taker.user_id, base);
@@ -54,6 +79,7 @@ pub fn clear(
                 base_frozen: base_account.frozen,
                 quote_frozen: quote_account.frozen,
                 timestamp: time,
+                timestamp_us: time_us,
             }]
         }
         State::Canceled => {
@@ -84,6 +110,7 @@ pub fn clear(
                         base_frozen: base_account.frozen,
                         quote_frozen: quote_account.frozen,
                         timestamp: time,
+                        timestamp_us: time_us,
                     }]
                 }
                 AskOrBid::Bid => {
@@ -92,7 +119,7 @@ pub fn clear(
                         accounts,
                         &mr.taker.user_id,
                         quote,
-                        mr.taker.unfilled * mr.taker.price,
+                        fixed::mul(mr.taker.unfilled, mr.taker.price),
                     )
                     .unwrap();
                     let base_account =
@@ -117,6 +144,7 @@ pub fn clear(
                         base_frozen: base_account.frozen,
                         quote_frozen: quote_account.frozen,
                         timestamp: time,
+                        timestamp_us: time_us,
                     }]
                 }
             }
@@ -130,21 +158,30 @@ pub fn clear(
                     // maker quote acount frozen decr filled * price
                     let mut base_sum = Decimal::zero();
                     let mut quote_sum = Decimal::zero();
+                    let mut rebates = Decimal::zero();
                     for m in &mr.maker {
                         base_sum += m.filled;
-                        let quote_decr = m.filled * m.price;
+                        let quote_decr = fixed::mul(m.filled, m.price);
                         quote_sum += quote_decr;
                         // maker is bid, incr base available(filled), decr quote frozen(quot_decr)
                         assets::add_to_available(accounts, &m.user_id, base, m.filled).unwrap();
-                        let quote_account =
-                            assets::deduct_frozen(accounts, &m.user_id, quote, quote_decr).unwrap();
+                        assets::deduct_frozen(accounts, &m.user_id, quote, quote_decr).unwrap();
                         // charge fee for maker
                         // maker is bid, incr base, decr quote, so we charge base
-                        let charge_fee = m.filled * maker_fee;
+                        // or rebate quote out of the taker fee
+                        let (charge_fee, rebate) = match m.fee.unwrap_or(maker_fee) {
+                            fee if fee < Decimal::zero() => {
+                                (Decimal::zero(), rebate(quote_decr, fee, taker_fee))
+                            }
+                            fee => (fixed::mul(m.filled, fee), Decimal::zero()),
+                        };
                         let base_account =
                             assets::deduct_available(accounts, &m.user_id, base, charge_fee)
                                 .unwrap();
-                        assets::add_to_available(accounts, &SYSTEM, base, charge_fee).unwrap();
+                        collect_fee(accounts, m.broker, broker_share, base, charge_fee);
+                        let quote_account =
+                            assets::add_to_available(accounts, &m.user_id, quote, rebate).unwrap();
+                        rebates += rebate;
                         cr.push(Output {
                             event_id,
                             order_id: m.order_id,
@@ -157,12 +194,13 @@ pub fn clear(
                             base_delta: m.filled,
                             quote_delta: -quote_decr,
                             base_charge: -charge_fee,
-                            quote_charge: Decimal::zero(),
+                            quote_charge: rebate,
                             base_available: base_account.available,
                             quote_available: quote_account.available,
                             base_frozen: base_account.frozen,
                             quote_frozen: quote_account.frozen,
                             timestamp: time,
+                            timestamp_us: time_us,
                         });
                     }
                     // taker base account frozen decr sum(filled)
@@ -176,12 +214,19 @@ pub fn clear(
                     assets::add_to_available(accounts, &mr.taker.user_id, quote, quote_sum)
                         .unwrap();
                     // charge fee for taker
-                    let charge_fee = quote_sum * taker_fee;
+                    let charge_fee = fixed::mul(quote_sum, taker_fee);
                     // taker is ask, incr quote, decr base, so we charge quote
                     let quote_account =
                         assets::deduct_available(accounts, &mr.taker.user_id, quote, charge_fee)
                             .unwrap();
-                    assets::add_to_available(accounts, &SYSTEM, quote, charge_fee).unwrap();
+                    // the rebates of the makers are paid out of the taker fee
+                    collect_fee(
+                        accounts,
+                        mr.taker.broker,
+                        broker_share,
+                        quote,
+                        charge_fee - rebates,
+                    );
                     cr.push(Output {
                         event_id,
                         order_id: mr.taker.order_id,
@@ -200,6 +245,7 @@ pub fn clear(
                         base_frozen: base_account.frozen,
                         quote_frozen: quote_account.frozen,
                         timestamp: time,
+                        timestamp_us: time_us,
                     });
                     cr
                     // makers deal
@@ -214,22 +260,32 @@ pub fn clear(
                     let mut base_sum = Decimal::zero();
                     let mut quote_sum = Decimal::zero();
                     let mut return_quote = Decimal::zero();
+                    let mut rebates = Decimal::zero();
                     for m in &mr.maker {
                         base_sum += m.filled;
-                        let quote_incr = m.filled * m.price;
+                        let quote_incr = fixed::mul(m.filled, m.price);
                         quote_sum += quote_incr;
-                        return_quote += m.filled * mr.taker.price - m.filled * m.price;
+                        return_quote +=
+                            fixed::mul(m.filled, mr.taker.price) - fixed::mul(m.filled, m.price);
                         // maker is ask, incr quote available(quote_incr), decr base frozen(filled)
-                        let base_account =
-                            assets::deduct_frozen(accounts, &m.user_id, base, m.filled).unwrap();
+                        assets::deduct_frozen(accounts, &m.user_id, base, m.filled).unwrap();
                         assets::add_to_available(accounts, &m.user_id, quote, quote_incr).unwrap();
                         // charge fee for maker
                         // maker is ask, incr quote, decr base, so we charge quote
-                        let charge_fee = quote_incr * maker_fee;
+                        // or rebate base out of the taker fee
+                        let (charge_fee, rebate) = match m.fee.unwrap_or(maker_fee) {
+                            fee if fee < Decimal::zero() => {
+                                (Decimal::zero(), rebate(m.filled, fee, taker_fee))
+                            }
+                            fee => (fixed::mul(quote_incr, fee), Decimal::zero()),
+                        };
                         let quote_account =
                             assets::deduct_available(accounts, &m.user_id, quote, charge_fee)
                                 .unwrap();
-                        assets::add_to_available(accounts, &SYSTEM, quote, charge_fee).unwrap();
+                        collect_fee(accounts, m.broker, broker_share, quote, charge_fee);
+                        let base_account =
+                            assets::add_to_available(accounts, &m.user_id, base, rebate).unwrap();
+                        rebates += rebate;
                         cr.push(Output {
                             event_id,
                             order_id: m.order_id,
@@ -241,13 +297,14 @@ pub fn clear(
                             price: m.price,
                             base_delta: -m.filled,
                             quote_delta: quote_incr,
-                            base_charge: Decimal::zero(),
+                            base_charge: rebate,
                             quote_charge: -charge_fee,
                             base_available: base_account.available,
                             quote_available: quote_account.available,
                             base_frozen: base_account.frozen,
                             quote_frozen: quote_account.frozen,
                             timestamp: time,
+                            timestamp_us: time_us,
                         });
                     }
                     // taker base account available incr sum(filled)
@@ -255,11 +312,18 @@ pub fn clear(
                     assets::add_to_available(accounts, &mr.taker.user_id, base, base_sum).unwrap();
                     assets::deduct_frozen(accounts, &mr.taker.user_id, quote, quote_sum).unwrap();
                     // charge fee for taker
-                    let charge_fee = base_sum * taker_fee;
+                    let charge_fee = fixed::mul(base_sum, taker_fee);
                     // taker is bid, incr base, decr quote, so we charge base
                     assets::deduct_available(accounts, &mr.taker.user_id, base, charge_fee)
                         .unwrap();
-                    assets::add_to_available(accounts, &SYSTEM, base, charge_fee).unwrap();
+                    // the rebates of the makers are paid out of the taker fee
+                    collect_fee(
+                        accounts,
+                        mr.taker.broker,
+                        broker_share,
+                        base,
+                        charge_fee - rebates,
+                    );
                     // maker has the dealing right
                     // for taker bid, maker ask, bid_price >= ask_price
                     // so we return some quote to taker as below formula:
@@ -269,16 +333,21 @@ pub fn clear(
                     //  ...
                     //   +
                     // bid_price(taker) * maker_filledn - ask_pricen(makern) * maker_filledn
+                    //
+                    // or the quote budget minus quote_sum if the taker is specified by quote
+                    if let Some(vol) = mr.taker.vol {
+                        return_quote = vol - quote_sum;
+                    }
                     if return_quote > Decimal::zero() {
                         assets::try_unfreeze(accounts, &mr.taker.user_id, quote, return_quote)
                             .unwrap();
                     }
-                    if mr.taker.state == State::ConditionallyCanceled {
+                    if mr.taker.state == State::ConditionallyCanceled && mr.taker.vol.is_none() {
                         assets::try_unfreeze(
                             accounts,
                             &mr.taker.user_id,
                             quote,
-                            mr.taker.unfilled * mr.taker.price,
+                            fixed::mul(mr.taker.unfilled, mr.taker.price),
                         )
                         .unwrap();
                     }
@@ -304,6 +373,7 @@ pub fn clear(
                         base_frozen: base_account.frozen,
                         quote_frozen: quote_account.frozen,
                         timestamp: time,
+                        timestamp_us: time_us,
                     });
                     cr
                 }
@@ -312,10 +382,104 @@ pub fn clear(
     }
 }
 
+/// check the arithmetic of `clear` against overflowing without applying it, the balances debited
+/// are frozen or available already so only the credits are checked
+#[cfg(feature = "overflow-audit")]
+pub fn audit(
+    accounts: &Accounts,
+    symbol: &Symbol,
+    taker_fee: Fee,
+    maker_fee: Fee,
+    broker_share: Fee,
+    mr: &Match,
+) -> anyhow::Result<()> {
+    let overflow = || anyhow::anyhow!("overflow while clearing");
+    let mul = |a: Amount, b: Amount| a.checked_mul(b).ok_or_else(overflow);
+    let add = |a: Amount, b: Amount| a.checked_add(b).ok_or_else(overflow);
+    let (base, quote) = *symbol;
+    let mut credits = Vec::<(UserId, Currency, Amount)>::new();
+    let (mut base_sum, mut quote_sum) = (Amount::zero(), Amount::zero());
+    let (mut base_fees, mut quote_fees) = (Amount::zero(), Amount::zero());
+    let mut return_quote = Amount::zero();
+    for m in &mr.maker {
+        let vol = mul(m.filled, m.price)?;
+        base_sum = add(base_sum, m.filled)?;
+        quote_sum = add(quote_sum, vol)?;
+        // the rebates never exceed the taker fee collected by `SYSTEM`
+        let fee = m.fee.unwrap_or(maker_fee).max(Fee::zero());
+        let rebate = |v| mul(v, (-m.fee.unwrap_or(maker_fee)).max(Fee::zero()));
+        match mr.taker.ask_or_bid {
+            AskOrBid::Ask => {
+                base_fees = add(base_fees, mul(m.filled, fee)?)?;
+                credits.push((m.user_id, base, m.filled));
+                credits.push((m.user_id, quote, rebate(vol)?));
+            }
+            AskOrBid::Bid => {
+                quote_fees = add(quote_fees, mul(vol, fee)?)?;
+                return_quote = add(return_quote, mul(m.filled, mr.taker.price)? - vol)?;
+                credits.push((m.user_id, quote, vol));
+                credits.push((m.user_id, base, rebate(m.filled)?));
+            }
+        }
+    }
+    match mr.taker.ask_or_bid {
+        AskOrBid::Ask => {
+            quote_fees = add(quote_fees, mul(quote_sum, taker_fee)?)?;
+            credits.push((mr.taker.user_id, quote, quote_sum));
+        }
+        AskOrBid::Bid => {
+            base_fees = add(base_fees, mul(base_sum, taker_fee)?)?;
+            mul(mr.taker.unfilled, mr.taker.price)?;
+            credits.push((mr.taker.user_id, base, base_sum));
+            credits.push((mr.taker.user_id, quote, return_quote));
+        }
+    }
+    // the cuts of the brokers never exceed the fees collected by `SYSTEM`
+    mul(base_fees, broker_share)?;
+    mul(quote_fees, broker_share)?;
+    credits.push((SYSTEM, base, base_fees));
+    credits.push((SYSTEM, quote, quote_fees));
+    for (user_id, currency, amount) in credits {
+        let balance = assets::get_balance_to_owned(accounts, &user_id, currency);
+        add(add(balance.available, balance.frozen)?, amount)?;
+    }
+    Ok(())
+}
+
+/// the rebate of a maker with a negative fee, sourced from the taker fee of the same fill so
+/// it's limited by the taker fee
+pub fn rebate(vol: Amount, maker_fee: Fee, taker_fee: Fee) -> Amount {
+    fixed::mul(vol, (-maker_fee).min(taker_fee).max(Fee::zero()))
+}
+
+/// the part of a fee accrued to the broker
+pub fn broker_cut(fee: Amount, broker_share: Fee) -> Amount {
+    fixed::mul(fee, broker_share)
+}
+
+/// the fee is collected by `SYSTEM` except the cut of the broker
+fn collect_fee(
+    accounts: &mut Accounts,
+    broker: Option<UserId>,
+    broker_share: Fee,
+    currency: Currency,
+    fee: Amount,
+) {
+    let cut = match broker {
+        Some(broker) if !broker_share.is_zero() => {
+            let cut = broker_cut(fee, broker_share);
+            assets::add_to_available(accounts, &broker_account(&broker), currency, cut).unwrap();
+            cut
+        }
+        _ => Amount::zero(),
+    };
+    assets::add_to_available(accounts, &SYSTEM, currency, fee - cut).unwrap();
+}
+
 #[allow(unused_must_use)]
 #[cfg(test)]
 pub mod test {
-    use crate::{assets, core::*, matcher::*, orderbook::*};
+    use crate::{assets, matcher::*, orderbook::*, primitives::*};
     use rust_decimal::{prelude::Zero, Decimal};
     use rust_decimal_macros::dec;
     use std::collections::BTreeMap;
@@ -354,6 +518,7 @@ pub mod test {
             &symbol,
             Decimal::zero(),
             Decimal::zero(),
+            Decimal::zero(),
             &mr,
             0,
         );
@@ -405,6 +570,7 @@ pub mod test {
             &symbol,
             Decimal::zero(),
             Decimal::zero(),
+            Decimal::zero(),
             &mr,
             0,
         );
@@ -449,6 +615,7 @@ pub mod test {
             &symbol,
             Decimal::zero(),
             Decimal::zero(),
+            Decimal::zero(),
             &mr,
             0,
         );
@@ -482,6 +649,7 @@ pub mod test {
             &symbol,
             Decimal::zero(),
             Decimal::zero(),
+            Decimal::zero(),
             &mr,
             0,
         );
@@ -525,6 +693,7 @@ pub mod test {
             &symbol,
             Decimal::zero(),
             Decimal::zero(),
+            Decimal::zero(),
             &mr,
             0,
         );
@@ -559,6 +728,7 @@ pub mod test {
             &symbol,
             Decimal::zero(),
             Decimal::zero(),
+            Decimal::zero(),
             &mr,
             0,
         );
@@ -591,7 +761,16 @@ pub mod test {
             taker: Taker::taker_filled(UserId::from_low_u64_be(2), 2, dec!(9999), AskOrBid::Ask),
             page_delta: BTreeMap::from([(dec!(9999), (dec!(1), dec!(0)))]),
         };
-        super::clear(&mut accounts, 2, &symbol, dec!(0.001), dec!(0.001), &mr, 0);
+        super::clear(
+            &mut accounts,
+            2,
+            &symbol,
+            dec!(0.001),
+            dec!(0.001),
+            Decimal::zero(),
+            &mr,
+            0,
+        );
 
         let b2_100 = assets::get_balance_to_owned(&accounts, &UserId::from_low_u64_be(2), 100);
         assert_eq!(dec!(9990), b2_100.available);
@@ -665,7 +844,16 @@ pub mod test {
         );
 
         let symbol = (101, 100);
-        let out = super::clear(&mut accounts, 2, &symbol, taker_fee, maker_fee, &mr, 0);
+        let out = super::clear(
+            &mut accounts,
+            2,
+            &symbol,
+            taker_fee,
+            maker_fee,
+            Decimal::zero(),
+            &mr,
+            0,
+        );
         assert_eq!(out[0].base_delta, Decimal::zero());
         assert_eq!(out[0].quote_delta, Decimal::zero());
         assert_eq!(out[0].base_charge, Decimal::zero());
@@ -738,7 +926,16 @@ pub mod test {
         );
 
         let symbol = (101, 100);
-        let out = super::clear(&mut accounts, 2, &symbol, taker_fee, maker_fee, &mr, 0);
+        let out = super::clear(
+            &mut accounts,
+            2,
+            &symbol,
+            taker_fee,
+            maker_fee,
+            Decimal::zero(),
+            &mr,
+            0,
+        );
         assert_eq!(out[0].base_delta, dec!(-0.1));
         assert_eq!(out[0].quote_delta, dec!(1000));
         assert_eq!(out[0].base_charge, Decimal::zero());
@@ -821,7 +1018,16 @@ pub mod test {
         );
 
         let symbol = (101, 100);
-        let out = super::clear(&mut accounts, 2, &symbol, taker_fee, maker_fee, &mr, 0);
+        let out = super::clear(
+            &mut accounts,
+            2,
+            &symbol,
+            taker_fee,
+            maker_fee,
+            Decimal::zero(),
+            &mr,
+            0,
+        );
         // 2: maker bid
         assert_eq!(out[0].base_delta, dec!(0.1));
         assert_eq!(out[0].quote_delta, dec!(-1333.3));
@@ -849,4 +1055,242 @@ pub mod test {
         assert_eq!(system_100, dec!(1.3333));
         assert_eq!(system_101, dec!(0.0001));
     }
+
+    #[test]
+    pub fn test_clearing_on_bid_taker_vol() {
+        let mut book = OrderBook::new(
+            5,
+            1,
+            Decimal::zero(),
+            Decimal::zero(),
+            Decimal::zero(),
+            Decimal::zero(),
+            1,
+            dec!(0.1),
+            dec!(1),
+            true,
+            true,
+        );
+        let mut accounts = Accounts::new();
+        assets::add_to_available(&mut accounts, &UserId::from_low_u64_be(1), 100, dec!(200));
+        for (user, price, amount) in [(2, dec!(105), dec!(1)), (3, dec!(106), dec!(2))] {
+            let user = UserId::from_low_u64_be(user);
+            assets::add_to_available(&mut accounts, &user, 101, amount);
+            assets::try_freeze(&mut accounts, &user, 101, amount).unwrap();
+            execute_limit(&mut book, user, price, amount, AskOrBid::Ask);
+        }
+        let vol = dec!(160);
+        let (price, amount) = book.sweep_vol(vol, None).unwrap();
+        assert_eq!((dec!(106), dec!(1.51886)), (price, amount));
+        assets::try_freeze(&mut accounts, &UserId::from_low_u64_be(1), 100, vol).unwrap();
+        let mut mr = execute_market(
+            &mut book,
+            UserId::from_low_u64_be(1),
+            None,
+            price,
+            amount,
+            AskOrBid::Bid,
+        );
+        mr.taker.vol = Some(vol);
+        let out = super::clear(
+            &mut accounts,
+            3,
+            &(101, 100),
+            Decimal::zero(),
+            Decimal::zero(),
+            Decimal::zero(),
+            &mr,
+            0,
+        );
+        assert_eq!(State::Filled, out[2].state);
+        assert_eq!(dec!(-159.99916), out[2].quote_delta);
+        let b1_100 = assets::get_balance_to_owned(&accounts, &UserId::from_low_u64_be(1), 100);
+        assert_eq!(dec!(40.00084), b1_100.available);
+        assert_eq!(Decimal::zero(), b1_100.frozen);
+        let b1_101 = assets::get_balance_to_owned(&accounts, &UserId::from_low_u64_be(1), 101);
+        assert_eq!(dec!(1.51886), b1_101.available);
+        let b3_100 = assets::get_balance_to_owned(&accounts, &UserId::from_low_u64_be(3), 100);
+        assert_eq!(dec!(54.99916), b3_100.available);
+    }
+
+    #[test]
+    pub fn test_broker_share() {
+        let mut book = OrderBook::new(
+            2,
+            2,
+            dec!(0.002),
+            dec!(0.001),
+            dec!(0.002),
+            dec!(0.001),
+            1,
+            dec!(0.01),
+            dec!(1),
+            true,
+            true,
+        );
+        let (maker, taker) = (UserId::from_low_u64_be(1), UserId::from_low_u64_be(2));
+        let (maker_broker, taker_broker) = (UserId::from_low_u64_be(8), UserId::from_low_u64_be(9));
+        let mut accounts = Accounts::new();
+        assets::add_to_available(&mut accounts, &maker, 101, dec!(1));
+        assets::try_freeze(&mut accounts, &maker, 101, dec!(1)).unwrap();
+        assets::add_to_available(&mut accounts, &taker, 100, dec!(100));
+        assets::try_freeze(&mut accounts, &taker, 100, dec!(100)).unwrap();
+        execute_iceberg(
+            &mut book,
+            maker,
+            Some(maker_broker),
+            dec!(100),
+            dec!(1),
+            Decimal::zero(),
+            AskOrBid::Ask,
+        );
+        let mr = execute_market(
+            &mut book,
+            taker,
+            Some(taker_broker),
+            dec!(100),
+            dec!(1),
+            AskOrBid::Bid,
+        );
+        assert_eq!(Some(maker_broker), mr.maker[0].broker);
+        super::clear(
+            &mut accounts,
+            2,
+            &(101, 100),
+            dec!(0.002),
+            dec!(0.001),
+            dec!(0.25),
+            &mr,
+            0,
+        );
+        let maker_cut =
+            assets::get_balance_to_owned(&accounts, &broker_account(&maker_broker), 100);
+        assert_eq!(dec!(0.025), maker_cut.available);
+        let taker_cut =
+            assets::get_balance_to_owned(&accounts, &broker_account(&taker_broker), 101);
+        assert_eq!(dec!(0.0005), taker_cut.available);
+        let system_100 = assets::get_balance_to_owned(&accounts, &SYSTEM, 100).available;
+        assert_eq!(dec!(0.075), system_100);
+        let system_101 = assets::get_balance_to_owned(&accounts, &SYSTEM, 101).available;
+        assert_eq!(dec!(0.0015), system_101);
+        assert!(assets::get_balance_to_owned(&accounts, &maker_broker, 100)
+            .available
+            .is_zero());
+    }
+
+    #[test]
+    pub fn test_maker_rebates() {
+        let mut book = OrderBook::new(
+            2,
+            2,
+            dec!(0.002),
+            dec!(0.001),
+            dec!(0.002),
+            dec!(0.001),
+            1,
+            dec!(0.01),
+            dec!(1),
+            true,
+            true,
+        );
+        let makers = [1, 2, 3].map(UserId::from_low_u64_be);
+        let (taker, taker_broker) = (UserId::from_low_u64_be(4), UserId::from_low_u64_be(9));
+        let mut accounts = Accounts::new();
+        for (maker, price) in makers[..2].iter().zip([dec!(100), dec!(101)]) {
+            assets::add_to_available(&mut accounts, maker, 101, dec!(1));
+            assets::try_freeze(&mut accounts, maker, 101, dec!(1)).unwrap();
+            execute_limit(&mut book, *maker, price, dec!(1), AskOrBid::Ask);
+        }
+        assets::add_to_available(&mut accounts, &taker, 100, dec!(202));
+        assets::try_freeze(&mut accounts, &taker, 100, dec!(202)).unwrap();
+        let mut mr = execute_market(
+            &mut book,
+            taker,
+            Some(taker_broker),
+            dec!(101),
+            dec!(2),
+            AskOrBid::Bid,
+        );
+        // the second one is limited by the taker fee
+        mr.maker[0].fee = Some(dec!(-0.001));
+        mr.maker[1].fee = Some(dec!(-0.005));
+        let out = super::clear(
+            &mut accounts,
+            1,
+            &(101, 100),
+            dec!(0.002),
+            dec!(0.001),
+            dec!(0.25),
+            &mr,
+            0,
+        );
+        assert_eq!(
+            vec![(dec!(0.001), dec!(0)), (dec!(0.002), dec!(0))],
+            out[..2]
+                .iter()
+                .map(|o| (o.base_charge, o.quote_charge))
+                .collect::<Vec<_>>()
+        );
+        let maker = assets::get_balance_to_owned(&accounts, &makers[0], 101);
+        assert_eq!((dec!(0.001), dec!(0)), (maker.available, maker.frozen));
+        let maker = assets::get_balance_to_owned(&accounts, &makers[1], 100);
+        assert_eq!(dec!(101), maker.available);
+        let taker_base = assets::get_balance_to_owned(&accounts, &taker, 101);
+        assert_eq!(dec!(1.996), taker_base.available);
+        // the cut of the broker is taken from the remainder
+        let taker_cut =
+            assets::get_balance_to_owned(&accounts, &broker_account(&taker_broker), 101);
+        assert_eq!(dec!(0.00025), taker_cut.available);
+        let system_101 = assets::get_balance_to_owned(&accounts, &SYSTEM, 101).available;
+        assert_eq!(dec!(0.00075), system_101);
+
+        assets::add_to_available(&mut accounts, &makers[2], 100, dec!(99));
+        assets::try_freeze(&mut accounts, &makers[2], 100, dec!(99)).unwrap();
+        execute_limit(&mut book, makers[2], dec!(99), dec!(1), AskOrBid::Bid);
+        let mut mr = execute_market(&mut book, taker, None, dec!(99), dec!(1), AskOrBid::Ask);
+        mr.maker[0].fee = Some(dec!(-0.001));
+        assets::try_freeze(&mut accounts, &taker, 101, dec!(1)).unwrap();
+        let out = super::clear(
+            &mut accounts,
+            2,
+            &(101, 100),
+            dec!(0.002),
+            dec!(0.001),
+            Decimal::zero(),
+            &mr,
+            0,
+        );
+        assert_eq!(
+            (dec!(0), dec!(0.099)),
+            (out[0].base_charge, out[0].quote_charge)
+        );
+        assert_eq!(dec!(-0.198), out[1].quote_charge);
+        let maker = assets::get_balance_to_owned(&accounts, &makers[2], 100);
+        assert_eq!((dec!(0.099), dec!(0)), (maker.available, maker.frozen));
+        let maker = assets::get_balance_to_owned(&accounts, &makers[2], 101);
+        assert_eq!(dec!(1), maker.available);
+        let system_100 = assets::get_balance_to_owned(&accounts, &SYSTEM, 100).available;
+        assert_eq!(dec!(0.099), system_100);
+    }
+
+    #[cfg(feature = "overflow-audit")]
+    #[test]
+    pub fn test_audit_overflow() {
+        let accounts = Accounts::new();
+        let symbol = (101, 100);
+        let mut mr = Match {
+            maker: vec![Maker::maker_filled(
+                UserId::from_low_u64_be(2),
+                1,
+                dec!(9999),
+                dec!(1),
+            )],
+            taker: Taker::taker_filled(UserId::from_low_u64_be(1), 2, dec!(10000), AskOrBid::Bid),
+            page_delta: BTreeMap::from([(dec!(9999), (dec!(1), dec!(0)))]),
+        };
+        let fee = dec!(0.001);
+        assert!(super::audit(&accounts, &symbol, fee, fee, Decimal::zero(), &mr).is_ok());
+        mr.maker[0].filled = Decimal::MAX;
+        assert!(super::audit(&accounts, &symbol, fee, fee, Decimal::zero(), &mr).is_err());
+    }
 }
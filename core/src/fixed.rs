@@ -0,0 +1,168 @@
+// Copyright 2021-2023 UINB Technologies Pte. Ltd.
+
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// 	http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use rust_decimal::Decimal;
+use std::cmp::Ordering;
+
+/// the scale of `Fixed`, the same as the amounts on chain
+pub const SCALE: u32 = 18;
+
+const ONE: i128 = 10i128.pow(SCALE);
+
+const POW10: [i128; SCALE as usize + 1] = {
+    let mut pow = [1i128; SCALE as usize + 1];
+    let mut i = 1;
+    while i <= SCALE as usize {
+        pow[i] = pow[i - 1] * 10;
+        i += 1;
+    }
+    pow
+};
+
+/// the fixed-point number of 18 decimals, i.e. the raw of `1` is `10^18`, which is converted
+/// from and to `Decimal` at the boundaries, the operations are checked and never round
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Ord, PartialOrd, Hash, Default)]
+pub struct Fixed(i128);
+
+impl Fixed {
+    pub const ONE: Fixed = Fixed(ONE);
+    pub const ZERO: Fixed = Fixed(0);
+
+    pub const fn from_raw(raw: i128) -> Self {
+        Self(raw)
+    }
+
+    /// the same as the amount on chain if not negative
+    pub const fn raw(self) -> i128 {
+        self.0
+    }
+
+    /// `None` if more than 18 decimals or overflowing
+    pub fn from_decimal(d: Decimal) -> Option<Self> {
+        let scale = d.scale();
+        if scale > SCALE {
+            return None;
+        }
+        d.mantissa()
+            .checked_mul(POW10[(SCALE - scale) as usize])
+            .map(Self)
+    }
+
+    /// `None` if the value can't be represented in `scale` decimals
+    pub fn to_decimal(self, scale: u32) -> Option<Decimal> {
+        let mantissa = match scale.cmp(&SCALE) {
+            Ordering::Equal => self.0,
+            Ordering::Less => {
+                let pow = POW10[(SCALE - scale) as usize];
+                (self.0 % pow == 0).then_some(self.0 / pow)?
+            }
+            Ordering::Greater => self.0.checked_mul(10i128.checked_pow(scale - SCALE)?)?,
+        };
+        Decimal::try_from_i128_with_scale(mantissa, scale).ok()
+    }
+
+    pub fn checked_add(self, rhs: Self) -> Option<Self> {
+        self.0.checked_add(rhs.0).map(Self)
+    }
+
+    pub fn checked_sub(self, rhs: Self) -> Option<Self> {
+        self.0.checked_sub(rhs.0).map(Self)
+    }
+
+    /// `None` unless the product is exact in 18 decimals
+    pub fn checked_mul(self, rhs: Self) -> Option<Self> {
+        let negative = (self.0 < 0) != (rhs.0 < 0);
+        let (a, b) = (self.0.unsigned_abs(), rhs.0.unsigned_abs());
+        let (ai, af, bi, bf) = (
+            a / ONE as u128,
+            a % ONE as u128,
+            b / ONE as u128,
+            b % ONE as u128,
+        );
+        // (ai + af) * (bi + bf) in the units of 10^-18, the fractions multiplied fit in u128
+        let ff = af * bf;
+        if ff % ONE as u128 != 0 {
+            return None;
+        }
+        let v = ai
+            .checked_mul(bi)?
+            .checked_mul(ONE as u128)?
+            .checked_add(ai.checked_mul(bf)?)?
+            .checked_add(af.checked_mul(bi)?)?
+            .checked_add(ff / ONE as u128)?;
+        let v = i128::try_from(v).ok()?;
+        Some(Self(if negative { -v } else { v }))
+    }
+}
+
+/// `a * b` of the same value and scale as `Decimal`, computed in fixed point if exact
+#[inline]
+pub fn mul(a: Decimal, b: Decimal) -> Decimal {
+    #[cfg(feature = "fixed-point")]
+    if let Some(v) = Fixed::from_decimal(a)
+        .zip(Fixed::from_decimal(b))
+        .and_then(|(x, y)| x.checked_mul(y))
+        .and_then(|v| v.to_decimal(a.scale() + b.scale()))
+    {
+        return v;
+    }
+    a * b
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use rust_decimal_macros::dec;
+
+    #[test]
+    pub fn test_fixed() {
+        let a = Fixed::from_decimal(dec!(1.25)).unwrap();
+        assert_eq!(a.raw(), 1_250_000_000_000_000_000);
+        assert_eq!(a.to_decimal(2), Some(dec!(1.25)));
+        assert_eq!(a.to_decimal(2).unwrap().scale(), 2);
+        assert_eq!(a.to_decimal(1), None);
+        assert_eq!(Fixed::from_decimal(dec!(0.0000000000000000001)), None);
+        assert_eq!(
+            Fixed::from_decimal(dec!(-3)).unwrap().checked_mul(a),
+            Fixed::from_decimal(dec!(-3.75))
+        );
+
+        // the products equal to `Decimal` in the value and the scale
+        for (x, y) in [
+            (dec!(123.4567), dec!(0.001)),
+            (dec!(99999.99), dec!(1234567.1234567)),
+            (dec!(0.0000001), dec!(0.0000001)),
+            (dec!(0.000000001), dec!(-0.0005)),
+            (dec!(18446744073709551615), dec!(1)),
+        ] {
+            let d = x * y;
+            assert_eq!(mul(x, y), d);
+            assert_eq!(mul(x, y).scale(), d.scale());
+            let f = Fixed::from_decimal(x)
+                .unwrap()
+                .checked_mul(Fixed::from_decimal(y).unwrap())
+                .unwrap();
+            assert_eq!(f.to_decimal(d.scale()), Some(d));
+        }
+        // beyond 18 decimals or overflowing
+        let tiny = Fixed::from_decimal(dec!(0.0000000001)).unwrap();
+        assert_eq!(tiny.checked_mul(tiny), None);
+        let huge = Fixed::from_decimal(dec!(100000000000000000000)).unwrap();
+        assert_eq!(huge.checked_mul(huge), None);
+        assert_eq!(
+            mul(dec!(0.0000000001), dec!(0.0000000001)),
+            dec!(0.00000000000000000001)
+        );
+    }
+}
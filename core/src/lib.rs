@@ -0,0 +1,26 @@
+// Copyright 2021-2023 UINB Technologies Pte. Ltd.
+
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// 	http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+#![allow(clippy::from_over_into)]
+#![allow(clippy::too_many_arguments)]
+
+pub mod assets;
+pub mod chunked;
+pub mod clearing;
+pub mod fixed;
+pub mod matcher;
+pub mod orderbook;
+pub mod primitives;
+
+pub use primitives::*;
@@ -0,0 +1,1237 @@
+// Copyright 2021-2023 UINB Technologies Pte. Ltd.
+
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// 	http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::{
+    orderbook::{AskOrBid, Order, OrderBook, OrderPage},
+    primitives::*,
+};
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Eq, PartialEq, Clone, Copy)]
+pub enum State {
+    Placed,
+    Canceled,
+    Filled,
+    PartiallyFilled,
+    ConditionallyCanceled,
+}
+
+/// `GTC` rests the unfilled part on the book, `IOC` cancels it and `FOK` is rejected
+/// unless the order could be filled entirely
+#[derive(Debug, Eq, PartialEq, Clone, Copy, Default, Serialize, Deserialize)]
+pub enum TimeInForce {
+    #[default]
+    #[serde(rename = "GTC")]
+    GoodTillCancel,
+    #[serde(rename = "IOC")]
+    ImmediateOrCancel,
+    #[serde(rename = "FOK")]
+    FillOrKill,
+}
+
+/// the taker never trades against the resting orders of the same user, the matching stops at
+/// them and the rest of the taker is canceled by `CancelNewest`; `CancelOldest` cancels the
+/// resting ones before executing the taker and `CancelBoth` cancels the one it stops at as well
+#[derive(Debug, Eq, PartialEq, Clone, Copy, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SelfTradePrevention {
+    #[default]
+    CancelNewest,
+    CancelOldest,
+    CancelBoth,
+}
+
+#[derive(Debug, Eq, PartialEq, Clone, Copy)]
+pub enum Role {
+    Taker,
+    Maker,
+}
+
+impl Into<u32> for Role {
+    fn into(self) -> u32 {
+        match self {
+            Role::Maker => 0,
+            Role::Taker => 1,
+        }
+    }
+}
+
+impl Into<u32> for State {
+    fn into(self) -> u32 {
+        match self {
+            State::Placed => 0,
+            State::Canceled => 1,
+            State::Filled => 2,
+            State::PartiallyFilled => 3,
+            State::ConditionallyCanceled => 4,
+        }
+    }
+}
+
+impl Into<u8> for State {
+    fn into(self) -> u8 {
+        match self {
+            State::Placed => 0,
+            State::Canceled => 1,
+            State::Filled => 2,
+            State::PartiallyFilled => 3,
+            State::ConditionallyCanceled => 4,
+        }
+    }
+}
+
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub struct Taker {
+    pub user_id: UserId,
+    pub order_id: u64,
+    pub price: Price,
+    pub unfilled: Amount,
+    pub ask_or_bid: AskOrBid,
+    pub state: State,
+    /// the quote budget of a bid frozen entirely, the unspent is returned on clearing
+    pub vol: Option<Vol>,
+    pub broker: Option<UserId>,
+}
+
+impl Taker {
+    pub fn taker(order: Order, ask_or_bid: AskOrBid, state: State) -> Self {
+        Self {
+            user_id: order.user,
+            order_id: order.id,
+            price: order.price,
+            unfilled: order.unfilled,
+            ask_or_bid,
+            state,
+            vol: None,
+            broker: order.broker,
+        }
+    }
+
+    pub fn taker_filled(
+        user_id: UserId,
+        order_id: u64,
+        price: Price,
+        ask_or_bid: AskOrBid,
+    ) -> Self {
+        Self {
+            user_id,
+            order_id,
+            price,
+            unfilled: Amount::ZERO,
+            ask_or_bid,
+            state: State::Filled,
+            vol: None,
+            broker: None,
+        }
+    }
+
+    pub const fn taker_placed(
+        user_id: UserId,
+        order_id: u64,
+        price: Price,
+        unfilled: Amount,
+        ask_or_bid: AskOrBid,
+    ) -> Self {
+        Self {
+            user_id,
+            order_id,
+            price,
+            unfilled,
+            ask_or_bid,
+            state: State::PartiallyFilled,
+            vol: None,
+            broker: None,
+        }
+    }
+
+    pub const fn cancel(
+        user_id: UserId,
+        order_id: u64,
+        price: Price,
+        unfilled: Amount,
+        ask_or_bid: AskOrBid,
+    ) -> Self {
+        Self {
+            user_id,
+            order_id,
+            price,
+            unfilled,
+            ask_or_bid,
+            state: State::Canceled,
+            vol: None,
+            broker: None,
+        }
+    }
+}
+
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub struct Maker {
+    pub user_id: UserId,
+    pub order_id: u64,
+    pub price: Price,
+    pub filled: Amount,
+    pub state: State,
+    pub broker: Option<UserId>,
+    /// overrides the maker fee of the symbol for the owner, e.g. by the volume tiers
+    pub fee: Option<Fee>,
+}
+
+impl Maker {
+    pub const fn maker_filled(
+        user_id: UserId,
+        order_id: u64,
+        price: Price,
+        filled: Amount,
+    ) -> Self {
+        Self {
+            user_id,
+            order_id,
+            price,
+            filled,
+            state: State::Filled,
+            broker: None,
+            fee: None,
+        }
+    }
+
+    pub const fn maker_so_far(
+        user_id: UserId,
+        order_id: u64,
+        price: Price,
+        filled: Amount,
+    ) -> Self {
+        Self {
+            user_id,
+            order_id,
+            price,
+            filled,
+            state: State::PartiallyFilled,
+            broker: None,
+            fee: None,
+        }
+    }
+
+    pub fn with_broker(mut self, broker: Option<UserId>) -> Self {
+        self.broker = broker;
+        self
+    }
+}
+
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub struct Match {
+    pub maker: Vec<Maker>,
+    pub taker: Taker,
+    pub page_delta: std::collections::BTreeMap<Price, (Amount, Amount)>,
+}
+
+/// best execution metrics of a taker order
+#[derive(Debug, Eq, PartialEq, Clone, Default, Serialize, Deserialize)]
+pub struct Execution {
+    /// the best price of the opposite side when the order arrived
+    pub best_price: Price,
+    pub average_price: Price,
+    pub levels: u32,
+    pub filled: Amount,
+}
+
+impl Execution {
+    /// positive for price improvement and negative for slippage, in quote
+    pub fn improvement(&self, ask_or_bid: AskOrBid) -> Amount {
+        match ask_or_bid {
+            AskOrBid::Ask => (self.average_price - self.best_price) * self.filled,
+            AskOrBid::Bid => (self.best_price - self.average_price) * self.filled,
+        }
+    }
+}
+
+impl Match {
+    pub fn execution(&self, best_price: Option<Price>) -> Option<Execution> {
+        let filled = self.maker.iter().map(|m| m.filled).sum::<Amount>();
+        if filled == Amount::ZERO {
+            return None;
+        }
+        let vol = self
+            .maker
+            .iter()
+            .map(|m| m.filled * m.price)
+            .sum::<Amount>();
+        let mut levels = self.maker.iter().map(|m| m.price).collect::<Vec<_>>();
+        levels.dedup();
+        Some(Execution {
+            best_price: best_price.unwrap_or(Amount::ZERO),
+            average_price: vol / filled,
+            levels: levels.len() as u32,
+            filled,
+        })
+    }
+}
+
+// TODO move to config
+const MAX_MAKERS: u32 = 20;
+
+pub fn execute_limit(
+    book: &mut OrderBook,
+    user_id: UserId,
+    price: Price,
+    amount: Amount,
+    ask_or_bid: AskOrBid,
+) -> Match {
+    execute_iceberg(book, user_id, None, price, amount, Amount::ZERO, ask_or_bid)
+}
+
+/// the taker is matched entirely as a limit order, only `display` of the part resting on the
+/// book is shown and the rest is replenished once the shown part filled
+pub fn execute_iceberg(
+    book: &mut OrderBook,
+    user_id: UserId,
+    broker: Option<UserId>,
+    price: Price,
+    amount: Amount,
+    display: Amount,
+    ask_or_bid: AskOrBid,
+) -> Match {
+    use rust_decimal::prelude::Zero;
+    let order_id = book.incr_then_fetch_order_id();
+    let mut order = Order::new(order_id, user_id, price, amount).with_broker(broker);
+    let (makers, mut page_delta, interrupted) = sweep(book, &mut order, ask_or_bid);
+    if order.is_filled() {
+        return Match {
+            maker: makers,
+            taker: Taker::taker(order, ask_or_bid, State::Filled),
+            page_delta,
+        };
+    }
+    if interrupted {
+        return Match {
+            taker: Taker::taker(order, ask_or_bid, State::ConditionallyCanceled),
+            maker: makers,
+            page_delta,
+        };
+    }
+    let order = order.with_display(display);
+    let size_before = book.get_page_size(&order.price).unwrap_or(Amount::zero());
+    page_delta
+        .entry(order.price)
+        .and_modify(|v| v.1 += order.unfilled)
+        .or_insert((size_before, size_before + order.unfilled));
+    book.insert(order.clone(), ask_or_bid);
+    Match {
+        taker: match makers.is_empty() {
+            true => Taker::taker(order, ask_or_bid, State::Placed),
+            false => Taker::taker(order, ask_or_bid, State::PartiallyFilled),
+        },
+        maker: makers,
+        page_delta,
+    }
+}
+
+/// check whether a taker could be filled entirely within `price` without touching the book,
+/// the self-trading and the makers limit interrupt the matching as `sweep` does
+pub fn is_fillable(
+    book: &OrderBook,
+    user_id: UserId,
+    price: Price,
+    amount: Amount,
+    ask_or_bid: AskOrBid,
+) -> bool {
+    let pages: Box<dyn Iterator<Item = &OrderPage>> = match ask_or_bid {
+        AskOrBid::Ask => Box::new(book.bids.values().rev().take_while(|p| p.price >= price)),
+        AskOrBid::Bid => Box::new(book.asks.values().take_while(|p| p.price <= price)),
+    };
+    let mut left = amount;
+    let mut makers = MAX_MAKERS;
+    for maker in pages.flat_map(|p| p.orders.values()) {
+        if makers == 0 || maker.user == user_id {
+            return false;
+        }
+        if maker.unfilled >= left {
+            return true;
+        }
+        left -= maker.unfilled;
+        makers -= 1;
+    }
+    false
+}
+
+/// the resting orders of `user_id` a taker would reach before being filled, in the order of
+/// matching, the makers limit is respected as `sweep` does
+pub fn find_self_trades(
+    book: &OrderBook,
+    user_id: UserId,
+    price: Price,
+    amount: Amount,
+    ask_or_bid: AskOrBid,
+) -> Vec<Order> {
+    let pages: Box<dyn Iterator<Item = &OrderPage>> = match ask_or_bid {
+        AskOrBid::Ask => Box::new(book.bids.values().rev().take_while(|p| p.price >= price)),
+        AskOrBid::Bid => Box::new(book.asks.values().take_while(|p| p.price <= price)),
+    };
+    let mut left = amount;
+    let mut makers = MAX_MAKERS;
+    let mut orders = vec![];
+    for maker in pages.flat_map(|p| p.orders.values()) {
+        if left <= Amount::ZERO || makers == 0 {
+            break;
+        }
+        if maker.user == user_id {
+            orders.push(maker.clone());
+            continue;
+        }
+        left -= maker.unfilled;
+        makers -= 1;
+    }
+    orders
+}
+
+/// market orders never rest on the book, `price` is the worst price the taker accepts,
+/// the unfilled part is canceled once the liquidity within `price` is exhausted,
+/// which is also how `IOC` and `FOK` orders are executed
+pub fn execute_market(
+    book: &mut OrderBook,
+    user_id: UserId,
+    broker: Option<UserId>,
+    price: Price,
+    amount: Amount,
+    ask_or_bid: AskOrBid,
+) -> Match {
+    let order_id = book.incr_then_fetch_order_id();
+    let mut order = Order::new(order_id, user_id, price, amount).with_broker(broker);
+    let (makers, page_delta, _) = sweep(book, &mut order, ask_or_bid);
+    let state = match order.is_filled() {
+        true => State::Filled,
+        false => State::ConditionallyCanceled,
+    };
+    Match {
+        taker: Taker::taker(order, ask_or_bid, state),
+        maker: makers,
+        page_delta,
+    }
+}
+
+fn sweep(
+    book: &mut OrderBook,
+    order: &mut Order,
+    ask_or_bid: AskOrBid,
+) -> (
+    Vec<Maker>,
+    std::collections::BTreeMap<Price, (Amount, Amount)>,
+    bool,
+) {
+    let mut max_makers = MAX_MAKERS;
+    let mut page_delta = std::collections::BTreeMap::<Price, (Amount, Amount)>::new();
+    let mut makers = Vec::<Maker>::new();
+    while !order.is_filled() {
+        let mut best = match book.get_best_if_match(ask_or_bid, &order.price) {
+            Some(best) => best,
+            None => break,
+        };
+        let page = best.get_mut();
+        let (mut traded, interrupted) = take(page, order, &mut max_makers);
+        let taking_at_page = traded.iter().map(|o| o.filled).sum::<Amount>();
+        if !taking_at_page.is_zero() {
+            page_delta.insert(page.price, (taking_at_page + page.amount, page.amount));
+        }
+        if page.is_empty() {
+            best.remove();
+        }
+        traded.iter().for_each(|m| {
+            book.incr_notional(m.user_id, -(m.price * m.filled));
+            if m.state == State::Filled {
+                book.indices.remove(&m.order_id);
+            }
+        });
+        makers.append(&mut traded);
+        if interrupted {
+            return (makers, page_delta, true);
+        }
+    }
+    (makers, page_delta, false)
+}
+
+fn take(page: &mut OrderPage, taker: &mut Order, limit: &mut u32) -> (Vec<Maker>, bool) {
+    let mut matches = Vec::<Maker>::new();
+    while !taker.is_filled() && !page.is_empty() {
+        if *limit == 0u32 {
+            return (matches, true);
+        }
+        let oldest = page.orders.front().unwrap();
+        if oldest.user == taker.user {
+            return (matches, true);
+        }
+        let visible = oldest.visible();
+        let m = if oldest.hidden.is_zero() && taker.unfilled >= visible {
+            let maker = page.orders.pop_front().unwrap();
+            Maker::maker_filled(maker.user, maker.id, maker.price, maker.unfilled)
+                .with_broker(maker.broker)
+        } else if taker.unfilled >= visible {
+            // the shown slice of an iceberg is taken, the next one queues behind the others
+            let mut maker = page.orders.pop_front().unwrap();
+            maker.fill(visible);
+            page.hidden -= maker.replenish();
+            let m = Maker::maker_so_far(maker.user, maker.id, maker.price, visible)
+                .with_broker(maker.broker);
+            page.orders.insert(maker.id, maker);
+            m
+        } else {
+            let maker = page.orders.front_mut().unwrap();
+            maker.fill(taker.unfilled);
+            Maker::maker_so_far(maker.user, maker.id, maker.price, taker.unfilled)
+                .with_broker(maker.broker)
+        };
+        taker.fill(m.filled);
+        page.decr_size(&m.filled);
+        // an iceberg taken again is still a single maker
+        match matches.iter_mut().find(|x| x.order_id == m.order_id) {
+            Some(x) => {
+                x.filled += m.filled;
+                x.state = m.state;
+            }
+            None => {
+                *limit -= 1;
+                matches.push(m);
+            }
+        }
+    }
+    (matches, false)
+}
+
+pub fn cancel(orderbook: &mut OrderBook, order_id: u64) -> Option<Match> {
+    orderbook.remove(order_id).map(|(order, from)| {
+        use rust_decimal::prelude::Zero;
+        let after_removed = orderbook
+            .get_page_size(&order.price)
+            .unwrap_or(Amount::zero());
+        let page_delta = std::collections::BTreeMap::from([(
+            order.price,
+            (after_removed + order.unfilled, after_removed),
+        )]);
+        Match {
+            maker: vec![],
+            taker: Taker::taker(order, from, State::Canceled),
+            page_delta,
+        }
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use rust_decimal_macros::dec;
+
+    use crate::{matcher::*, orderbook::*};
+
+    #[test]
+    pub fn test_best() {
+        let base_scale = 5;
+        let quote_scale = 1;
+        let taker_fee = dec!(0.001);
+        let maker_fee = dec!(0.001);
+        let min_amount = dec!(1);
+        let min_vol = dec!(1);
+        let mut book = OrderBook::new(
+            base_scale,
+            quote_scale,
+            taker_fee,
+            maker_fee,
+            taker_fee,
+            maker_fee,
+            1,
+            min_amount,
+            min_vol,
+            true,
+            true,
+        );
+
+        let price = dec!(0.1);
+        let amount = dec!(100);
+        let mr = execute_limit(
+            &mut book,
+            UserId::from_low_u64_be(1),
+            price,
+            amount,
+            AskOrBid::Bid,
+        );
+        assert_eq!(State::Placed, mr.taker.state);
+        assert!(mr.maker.is_empty());
+        assert_eq!(
+            dec!(0.1),
+            *book
+                .get_best_if_match(AskOrBid::Ask, &dec!(0.1))
+                .unwrap()
+                .key()
+        );
+        // best bid = 0.1, now ask with 0.11, no matches
+        assert!(book.get_best_if_match(AskOrBid::Ask, &dec!(0.11)).is_none());
+        // best bid = 0.1, now ask with 0.09, matches
+        assert!(book.get_best_if_match(AskOrBid::Ask, &dec!(0.09)).is_some());
+        assert_eq!(
+            dec!(100),
+            book.get_best_if_match(AskOrBid::Ask, &dec!(0.1))
+                .unwrap()
+                .get()
+                .amount
+        );
+        assert!(book.indices.contains_key(&1));
+    }
+
+    #[test]
+    pub fn test_trade() {
+        let base_scale = 5;
+        let quote_scale = 1;
+        let taker_fee = dec!(0.001);
+        let maker_fee = dec!(0.001);
+        let min_amount = dec!(1);
+        let min_vol = dec!(1);
+        let mut book = OrderBook::new(
+            base_scale,
+            quote_scale,
+            taker_fee,
+            maker_fee,
+            taker_fee,
+            maker_fee,
+            1,
+            min_amount,
+            min_vol,
+            true,
+            true,
+        );
+
+        let price = dec!(0.1);
+        let amount = dec!(100);
+        let mr = execute_limit(
+            &mut book,
+            UserId::from_low_u64_be(1),
+            price,
+            amount,
+            AskOrBid::Bid,
+        );
+        assert_eq!(1, mr.taker.order_id);
+        assert_eq!(State::Placed, mr.taker.state);
+        assert!(mr.maker.is_empty());
+
+        let price = dec!(0.1);
+        let amount = dec!(1000);
+        let mr = execute_limit(
+            &mut book,
+            UserId::from_low_u64_be(1),
+            price,
+            amount,
+            AskOrBid::Bid,
+        );
+        assert_eq!(2, mr.taker.order_id);
+        assert_eq!(State::Placed, mr.taker.state);
+        assert!(mr.maker.is_empty());
+        let price = dec!(0.08);
+        let amount = dec!(200);
+        let mr = execute_limit(
+            &mut book,
+            UserId::from_low_u64_be(2),
+            price,
+            amount,
+            AskOrBid::Ask,
+        );
+        assert_eq!(3, mr.taker.order_id);
+        assert_eq!(State::Filled, mr.taker.state);
+        assert!(!mr.maker.is_empty());
+        assert!(!book.indices.contains_key(&1));
+        assert!(book.indices.contains_key(&2));
+        assert!(!book.indices.contains_key(&3));
+        assert_eq!(
+            &Maker::maker_filled(UserId::from_low_u64_be(1), 1, dec!(0.1), dec!(100)),
+            mr.maker.first().unwrap()
+        );
+        assert_eq!(
+            &Maker::maker_so_far(UserId::from_low_u64_be(1), 2, dec!(0.1), dec!(100)),
+            mr.maker.get(1).unwrap()
+        );
+        assert_eq!(
+            Taker::taker_filled(UserId::from_low_u64_be(2), 3, price, AskOrBid::Ask),
+            mr.taker
+        );
+        assert_eq!(dec!(90), book.open_notional());
+        assert_eq!(dec!(90), book.open_notional_of(&UserId::from_low_u64_be(1)));
+        assert!(book.open_notional_of(&UserId::from_low_u64_be(2)).is_zero());
+        assert_eq!(
+            dec!(0.1),
+            *book
+                .get_best_if_match(AskOrBid::Ask, &dec!(0.1))
+                .unwrap()
+                .key()
+        );
+        assert_eq!(
+            dec!(900),
+            book.get_best_if_match(AskOrBid::Ask, &dec!(0.1))
+                .unwrap()
+                .get()
+                .amount
+        );
+
+        let price = dec!(0.12);
+        let amount = dec!(100);
+        let mr = execute_limit(
+            &mut book,
+            UserId::from_low_u64_be(2),
+            price,
+            amount,
+            AskOrBid::Ask,
+        );
+        assert_eq!(4, mr.taker.order_id);
+        assert_eq!(State::Placed, mr.taker.state);
+        assert!(book.get_best_if_match(AskOrBid::Bid, &dec!(0.11)).is_none());
+        assert_eq!(
+            dec!(0.12),
+            *book
+                .get_best_if_match(AskOrBid::Bid, &dec!(0.12))
+                .unwrap()
+                .key()
+        );
+        assert_eq!(
+            dec!(100),
+            book.get_best_if_match(AskOrBid::Bid, &dec!(0.12))
+                .unwrap()
+                .get()
+                .amount
+        );
+        assert!(book.indices.contains_key(&4));
+
+        let mr = cancel(&mut book, 2);
+        let price = dec!(0.1);
+        let unfilled = dec!(900);
+        assert_eq!(
+            Taker::cancel(
+                UserId::from_low_u64_be(1),
+                2,
+                price,
+                unfilled,
+                AskOrBid::Bid,
+            ),
+            mr.unwrap().taker
+        );
+        assert!(!book.indices.contains_key(&1002));
+        assert!(book.bids.is_empty());
+
+        let price = dec!(0.12);
+        let unfilled = dec!(100);
+        let mr = cancel(&mut book, 4);
+        assert!(mr.is_some());
+        assert_eq!(
+            Taker::cancel(
+                UserId::from_low_u64_be(2),
+                4,
+                price,
+                unfilled,
+                AskOrBid::Ask,
+            ),
+            mr.unwrap().taker
+        );
+        assert!(!book.indices.contains_key(&4));
+        assert!(book.asks.is_empty());
+
+        let mr = cancel(&mut book, 4);
+        assert!(mr.is_none());
+        assert!(!book.indices.contains_key(&4));
+        assert!(book.asks.is_empty());
+
+        let mr = cancel(&mut book, 4);
+        assert!(mr.is_none());
+        assert!(!book.indices.contains_key(&4));
+        assert!(book.asks.is_empty());
+    }
+
+    #[test]
+    pub fn test_self_trade_on_best() {
+        let base_scale = 5;
+        let quote_scale = 1;
+        let taker_fee = dec!(0.001);
+        let maker_fee = dec!(0.001);
+        let min_amount = dec!(1);
+        let min_vol = dec!(1);
+        let mut book = OrderBook::new(
+            base_scale,
+            quote_scale,
+            taker_fee,
+            maker_fee,
+            taker_fee,
+            maker_fee,
+            1,
+            min_amount,
+            min_vol,
+            true,
+            true,
+        );
+
+        let price = dec!(0.1);
+        let amount = dec!(100);
+        execute_limit(
+            &mut book,
+            UserId::from_low_u64_be(1),
+            price,
+            amount,
+            AskOrBid::Bid,
+        );
+        let mr = execute_limit(
+            &mut book,
+            UserId::from_low_u64_be(1),
+            price,
+            amount,
+            AskOrBid::Ask,
+        );
+        assert_eq!(2, mr.taker.order_id);
+        assert_eq!(mr.taker.state, State::ConditionallyCanceled);
+        assert!(mr.maker.is_empty());
+        assert!(book.find_order(1).is_some());
+        assert!(book.find_order(2).is_none());
+    }
+
+    #[test]
+    pub fn test_self_trade_on_second_position() {
+        let base_scale = 5;
+        let quote_scale = 1;
+        let taker_fee = dec!(0.001);
+        let maker_fee = dec!(0.001);
+        let min_amount = dec!(1);
+        let min_vol = dec!(1);
+        let mut book = OrderBook::new(
+            base_scale,
+            quote_scale,
+            taker_fee,
+            maker_fee,
+            taker_fee,
+            maker_fee,
+            1,
+            min_amount,
+            min_vol,
+            true,
+            true,
+        );
+
+        let price = dec!(0.1);
+        let amount = dec!(100);
+        execute_limit(
+            &mut book,
+            UserId::from_low_u64_be(2),
+            price,
+            amount,
+            AskOrBid::Bid,
+        );
+        execute_limit(
+            &mut book,
+            UserId::from_low_u64_be(1),
+            price,
+            amount,
+            AskOrBid::Bid,
+        );
+        let mr = execute_limit(
+            &mut book,
+            UserId::from_low_u64_be(1),
+            price,
+            amount * dec!(2),
+            AskOrBid::Ask,
+        );
+        assert_eq!(3, mr.taker.order_id);
+        assert_eq!(mr.taker.state, State::ConditionallyCanceled);
+        assert_eq!(mr.taker.unfilled, amount);
+        assert_eq!(mr.maker.len(), 1);
+        assert_eq!(mr.maker[0].filled, amount);
+        assert!(book.find_order(1).is_none());
+        assert!(book.find_order(2).is_some());
+        assert!(book.find_order(3).is_none());
+    }
+
+    #[test]
+    pub fn test_find_self_trades() {
+        let mut book = OrderBook::new(
+            5,
+            1,
+            dec!(0.001),
+            dec!(0.001),
+            dec!(0.001),
+            dec!(0.001),
+            1,
+            dec!(1),
+            dec!(1),
+            true,
+            true,
+        );
+        let (alice, bob) = (UserId::from_low_u64_be(1), UserId::from_low_u64_be(2));
+        execute_limit(&mut book, bob, dec!(10), dec!(10), AskOrBid::Ask);
+        execute_limit(&mut book, alice, dec!(10), dec!(10), AskOrBid::Ask);
+        execute_limit(&mut book, alice, dec!(11), dec!(10), AskOrBid::Ask);
+        execute_limit(&mut book, alice, dec!(12), dec!(10), AskOrBid::Ask);
+        let orders = find_self_trades(&book, alice, dec!(11), dec!(5), AskOrBid::Bid);
+        assert!(orders.is_empty());
+        let orders = find_self_trades(&book, alice, dec!(11), dec!(20), AskOrBid::Bid);
+        assert_eq!(vec![2, 3], orders.iter().map(|o| o.id).collect::<Vec<_>>());
+        let orders = find_self_trades(&book, alice, dec!(12), dec!(20), AskOrBid::Bid);
+        assert_eq!(3, orders.len());
+        // filled by the others before reaching the last one
+        let orders = find_self_trades(&book, bob, dec!(12), dec!(20), AskOrBid::Bid);
+        assert_eq!(vec![1], orders.iter().map(|o| o.id).collect::<Vec<_>>());
+        let orders = find_self_trades(&book, bob, dec!(10), dec!(5), AskOrBid::Bid);
+        assert_eq!(1, orders.len());
+        // the matching stops at the first one
+        let mr = execute_limit(&mut book, alice, dec!(11), dec!(20), AskOrBid::Bid);
+        assert_eq!(mr.taker.state, State::ConditionallyCanceled);
+        assert_eq!(dec!(10), mr.taker.unfilled);
+        assert_eq!(1, mr.maker.len());
+    }
+
+    #[test]
+    pub fn test_max_makers() {
+        let base_scale = 5;
+        let quote_scale = 1;
+        let taker_fee = dec!(0.001);
+        let maker_fee = dec!(0.001);
+        let min_amount = dec!(1);
+        let min_vol = dec!(1);
+        let mut book = OrderBook::new(
+            base_scale,
+            quote_scale,
+            taker_fee,
+            maker_fee,
+            taker_fee,
+            maker_fee,
+            1,
+            min_amount,
+            min_vol,
+            true,
+            true,
+        );
+
+        let price = dec!(0.1);
+        let amount = dec!(1);
+        for _i in 1..30 {
+            execute_limit(
+                &mut book,
+                UserId::from_low_u64_be(2),
+                price,
+                amount,
+                AskOrBid::Bid,
+            );
+        }
+
+        let mr = execute_limit(
+            &mut book,
+            UserId::from_low_u64_be(1),
+            price,
+            amount * dec!(100),
+            AskOrBid::Ask,
+        );
+        assert_eq!(30, mr.taker.order_id);
+        assert_eq!(mr.taker.state, State::ConditionallyCanceled);
+        assert_eq!(mr.taker.unfilled, amount * dec!(80));
+        assert_eq!(mr.maker.len(), 20);
+        assert_eq!(mr.maker[0].filled, amount);
+        assert!(book.find_order(1).is_none());
+        assert!(book.find_order(20).is_none());
+        assert!(book.find_order(21).is_some());
+    }
+
+    // useless, the order id should be global unique rather than just in orderbook scope
+    #[test]
+    pub fn test_order_replay() {
+        let base_scale = 5;
+        let quote_scale = 1;
+        let taker_fee = dec!(0.001);
+        let maker_fee = dec!(0.001);
+        let min_amount = dec!(1);
+        let min_vol = dec!(1);
+        let mut book = OrderBook::new(
+            base_scale,
+            quote_scale,
+            taker_fee,
+            maker_fee,
+            taker_fee,
+            taker_fee,
+            1,
+            min_amount,
+            min_vol,
+            true,
+            true,
+        );
+
+        let price = dec!(0.1);
+        let amount = dec!(100);
+        let mr = execute_limit(
+            &mut book,
+            UserId::from_low_u64_be(1),
+            price,
+            amount,
+            AskOrBid::Bid,
+        );
+        assert!(book.find_order(1).is_some());
+        assert_eq!(State::Placed, mr.taker.state);
+        assert!(mr.maker.is_empty());
+    }
+
+    #[test]
+    pub fn test_execution_metrics() {
+        let mut book = OrderBook::new(
+            5,
+            1,
+            dec!(0.001),
+            dec!(0.001),
+            dec!(0.001),
+            dec!(0.001),
+            1,
+            dec!(1),
+            dec!(1),
+            true,
+            true,
+        );
+        execute_limit(
+            &mut book,
+            UserId::from_low_u64_be(1),
+            dec!(10),
+            dec!(1),
+            AskOrBid::Ask,
+        );
+        execute_limit(
+            &mut book,
+            UserId::from_low_u64_be(1),
+            dec!(11),
+            dec!(1),
+            AskOrBid::Ask,
+        );
+        let best = book.get_best_ask();
+        let mr = execute_limit(
+            &mut book,
+            UserId::from_low_u64_be(2),
+            dec!(12),
+            dec!(2),
+            AskOrBid::Bid,
+        );
+        let execution = mr.execution(best).unwrap();
+        assert_eq!(execution.best_price, dec!(10));
+        assert_eq!(execution.average_price, dec!(10.5));
+        assert_eq!(execution.levels, 2);
+        assert_eq!(execution.improvement(AskOrBid::Bid), dec!(-1));
+        let mr = execute_limit(
+            &mut book,
+            UserId::from_low_u64_be(2),
+            dec!(12),
+            dec!(2),
+            AskOrBid::Bid,
+        );
+        assert!(mr.execution(None).is_none());
+    }
+    #[test]
+    pub fn test_market() {
+        let mut book = OrderBook::new(
+            5,
+            1,
+            dec!(0.001),
+            dec!(0.001),
+            dec!(0.001),
+            dec!(0.001),
+            1,
+            dec!(1),
+            dec!(1),
+            true,
+            true,
+        );
+        execute_limit(
+            &mut book,
+            UserId::from_low_u64_be(1),
+            dec!(10),
+            dec!(1),
+            AskOrBid::Ask,
+        );
+        execute_limit(
+            &mut book,
+            UserId::from_low_u64_be(1),
+            dec!(11),
+            dec!(2),
+            AskOrBid::Ask,
+        );
+        let price = book.sweep_price(dec!(2), AskOrBid::Bid).unwrap();
+        assert_eq!(dec!(11), price);
+        let mr = execute_market(
+            &mut book,
+            UserId::from_low_u64_be(2),
+            None,
+            price,
+            dec!(2),
+            AskOrBid::Bid,
+        );
+        assert_eq!(State::Filled, mr.taker.state);
+        assert_eq!(2, mr.maker.len());
+        assert_eq!(
+            dec!(1),
+            book.get_best_if_match(AskOrBid::Bid, &price)
+                .unwrap()
+                .get()
+                .amount
+        );
+        // liquidity exhausted, the rest is canceled rather than placed
+        let price = book.sweep_price(dec!(5), AskOrBid::Bid).unwrap();
+        let mr = execute_market(
+            &mut book,
+            UserId::from_low_u64_be(2),
+            None,
+            price,
+            dec!(5),
+            AskOrBid::Bid,
+        );
+        assert_eq!(State::ConditionallyCanceled, mr.taker.state);
+        assert_eq!(dec!(4), mr.taker.unfilled);
+        assert!(book.asks.is_empty());
+        assert!(book.bids.is_empty());
+        assert!(book.sweep_price(dec!(1), AskOrBid::Bid).is_none());
+    }
+
+    #[test]
+    pub fn test_fillable() {
+        let mut book = OrderBook::new(
+            5,
+            1,
+            dec!(0.001),
+            dec!(0.001),
+            dec!(0.001),
+            dec!(0.001),
+            1,
+            dec!(1),
+            dec!(1),
+            true,
+            true,
+        );
+        execute_limit(
+            &mut book,
+            UserId::from_low_u64_be(1),
+            dec!(10),
+            dec!(1),
+            AskOrBid::Ask,
+        );
+        execute_limit(
+            &mut book,
+            UserId::from_low_u64_be(1),
+            dec!(11),
+            dec!(2),
+            AskOrBid::Ask,
+        );
+        let taker = UserId::from_low_u64_be(2);
+        assert!(is_fillable(&book, taker, dec!(10), dec!(1), AskOrBid::Bid));
+        assert!(!is_fillable(&book, taker, dec!(10), dec!(2), AskOrBid::Bid));
+        assert!(is_fillable(&book, taker, dec!(11), dec!(3), AskOrBid::Bid));
+        assert!(!is_fillable(&book, taker, dec!(12), dec!(4), AskOrBid::Bid));
+        assert!(!is_fillable(&book, taker, dec!(11), dec!(1), AskOrBid::Ask));
+        // self-trading interrupts the matching
+        assert!(!is_fillable(
+            &book,
+            UserId::from_low_u64_be(1),
+            dec!(11),
+            dec!(1),
+            AskOrBid::Bid
+        ));
+        for _ in 0..MAX_MAKERS {
+            execute_limit(
+                &mut book,
+                UserId::from_low_u64_be(3),
+                dec!(9),
+                dec!(1),
+                AskOrBid::Bid,
+            );
+        }
+        assert!(is_fillable(&book, taker, dec!(9), dec!(20), AskOrBid::Ask));
+        assert!(!is_fillable(&book, taker, dec!(9), dec!(21), AskOrBid::Ask));
+        let mr = execute_market(&mut book, taker, None, dec!(9), dec!(20), AskOrBid::Ask);
+        assert_eq!(State::Filled, mr.taker.state);
+        assert!(book.bids.is_empty());
+    }
+
+    #[test]
+    pub fn test_iceberg() {
+        let mut book = OrderBook::new(
+            5,
+            1,
+            dec!(0.001),
+            dec!(0.001),
+            dec!(0.001),
+            dec!(0.001),
+            1,
+            dec!(1),
+            dec!(1),
+            true,
+            true,
+        );
+        let iceberg = UserId::from_low_u64_be(1);
+        let mr = execute_iceberg(
+            &mut book,
+            iceberg,
+            None,
+            dec!(10),
+            dec!(10),
+            dec!(2),
+            AskOrBid::Ask,
+        );
+        assert_eq!(State::Placed, mr.taker.state);
+        assert_eq!(dec!(10), mr.taker.unfilled);
+        execute_limit(
+            &mut book,
+            UserId::from_low_u64_be(2),
+            dec!(10),
+            dec!(3),
+            AskOrBid::Ask,
+        );
+        let page = book.asks.get(&dec!(10)).unwrap();
+        assert_eq!(dec!(13), page.amount);
+        assert_eq!(dec!(8), page.hidden);
+        let depth = page.merge(5, 1, dec!(0));
+        assert_eq!(dec!(5), depth.1);
+        // the shown slice is taken, the replenished one queues behind the other order
+        let taker = UserId::from_low_u64_be(3);
+        let mr = execute_limit(&mut book, taker, dec!(10), dec!(4), AskOrBid::Bid);
+        assert_eq!(State::Filled, mr.taker.state);
+        assert_eq!(2, mr.maker.len());
+        assert_eq!(dec!(2), mr.maker[0].filled);
+        assert_eq!(State::PartiallyFilled, mr.maker[0].state);
+        assert_eq!(dec!(2), mr.maker[1].filled);
+        let page = book.asks.get(&dec!(10)).unwrap();
+        assert_eq!(dec!(9), page.amount);
+        assert_eq!(dec!(6), page.hidden);
+        let orders = page.orders.values().collect::<Vec<_>>();
+        assert_eq!(UserId::from_low_u64_be(2), orders[0].user);
+        assert_eq!(dec!(1), orders[0].unfilled);
+        assert_eq!(dec!(2), orders[1].visible());
+        // taken again by the same taker, it's still a single maker
+        let mr = execute_limit(&mut book, taker, dec!(10), dec!(6), AskOrBid::Bid);
+        assert_eq!(State::Filled, mr.taker.state);
+        assert_eq!(2, mr.maker.len());
+        assert_eq!(dec!(1), mr.maker[0].filled);
+        assert_eq!(mr.maker[1].order_id, 1);
+        assert_eq!(dec!(5), mr.maker[1].filled);
+        assert_eq!(State::PartiallyFilled, mr.maker[1].state);
+        let order = book.find_order(1).unwrap();
+        assert_eq!(dec!(3), order.unfilled);
+        assert_eq!(dec!(1), order.visible());
+        assert_eq!(dec!(2), order.hidden);
+        let mr = execute_market(&mut book, taker, None, dec!(10), dec!(5), AskOrBid::Bid);
+        assert_eq!(dec!(3), mr.maker[0].filled);
+        assert_eq!(State::Filled, mr.maker[0].state);
+        assert!(book.asks.is_empty());
+        assert!(book.indices.is_empty());
+        // the hidden is returned on canceling
+        execute_iceberg(
+            &mut book,
+            iceberg,
+            None,
+            dec!(10),
+            dec!(10),
+            dec!(2),
+            AskOrBid::Ask,
+        );
+        let mr = cancel(&mut book, 6).unwrap();
+        assert_eq!(dec!(10), mr.taker.unfilled);
+        assert!(book.asks.is_empty());
+    }
+}
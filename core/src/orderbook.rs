@@ -0,0 +1,949 @@
+// Copyright 2021-2023 UINB Technologies Pte. Ltd.
+
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// 	http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::{
+    matcher::SelfTradePrevention,
+    primitives::{Amount, Fee, OrderId, Price, UserId, Vol},
+};
+use anyhow::ensure;
+use rust_decimal::prelude::Zero;
+use serde::{
+    de::{MapAccess, Visitor},
+    ser::SerializeMap,
+    Deserialize, Deserializer, Serialize, Serializer,
+};
+use std::collections::{btree_map::OccupiedEntry, BTreeMap, HashMap};
+
+const DEFAULT_PAGE_SIZE: usize = 256;
+
+#[derive(Debug, Eq, PartialEq, Clone, Copy, Deserialize, Serialize)]
+pub enum AskOrBid {
+    Ask,
+    Bid,
+}
+
+impl Into<u32> for AskOrBid {
+    fn into(self) -> u32 {
+        match self {
+            AskOrBid::Ask => 0,
+            AskOrBid::Bid => 1,
+        }
+    }
+}
+
+impl Into<u8> for AskOrBid {
+    fn into(self) -> u8 {
+        match self {
+            AskOrBid::Ask => 0,
+            AskOrBid::Bid => 1,
+        }
+    }
+}
+
+impl std::ops::Not for AskOrBid {
+    type Output = Self;
+
+    fn not(self) -> Self::Output {
+        match self {
+            Self::Bid => Self::Ask,
+            Self::Ask => Self::Bid,
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, Eq, PartialEq, Clone)]
+pub struct Order {
+    pub id: OrderId,
+    pub user: UserId,
+    pub price: Price,
+    pub unfilled: Amount,
+    /// the size shown of an iceberg on replenishing, zero if not an iceberg
+    #[serde(default)]
+    pub display: Amount,
+    /// the reserve of an iceberg not shown on the book, a part of `unfilled`
+    #[serde(default)]
+    pub hidden: Amount,
+    /// the broker sharing the fees charged from this order
+    #[serde(default)]
+    pub broker: Option<UserId>,
+}
+
+impl Order {
+    pub const fn new(id: OrderId, user: UserId, price: Price, unfilled: Amount) -> Self {
+        Self {
+            id,
+            user,
+            price,
+            unfilled,
+            display: Amount::ZERO,
+            hidden: Amount::ZERO,
+            broker: None,
+        }
+    }
+
+    pub fn with_broker(mut self, broker: Option<UserId>) -> Self {
+        self.broker = broker;
+        self
+    }
+
+    /// show only `display` of the unfilled, the rest is hidden until the shown part filled
+    pub fn with_display(mut self, display: Amount) -> Self {
+        if display > Amount::ZERO && display < self.unfilled {
+            self.display = display;
+            self.hidden = self.unfilled - display;
+        }
+        self
+    }
+
+    pub fn visible(&self) -> Amount {
+        self.unfilled - self.hidden
+    }
+
+    /// show the next slice of the hidden, return the amount shown
+    pub fn replenish(&mut self) -> Amount {
+        let shown = self.hidden.min(self.display);
+        self.hidden -= shown;
+        shown
+    }
+
+    /// `delta` never exceeds the visible
+    pub fn fill(&mut self, delta: Amount) {
+        self.unfilled -= delta;
+    }
+
+    pub fn is_filled(&self) -> bool {
+        self.unfilled == Amount::ZERO
+    }
+}
+
+const NIL: usize = usize::MAX;
+
+#[derive(Clone)]
+struct Slot {
+    order: Option<Order>,
+    prev: usize,
+    next: usize,
+}
+
+/// the orders of a page in time priority, kept in a slab and linked by the slot indices, the
+/// freed slots are chained as a freelist and reused by the next orders rather than reallocated;
+/// encoded as a map like `LinkedHashMap`
+#[derive(Clone)]
+pub struct OrderQueue {
+    slots: Vec<Slot>,
+    index: HashMap<OrderId, usize>,
+    head: usize,
+    tail: usize,
+    free: usize,
+}
+
+impl Default for OrderQueue {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl OrderQueue {
+    pub fn new() -> Self {
+        Self {
+            slots: Vec::new(),
+            index: HashMap::new(),
+            head: NIL,
+            tail: NIL,
+            free: NIL,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.index.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.index.is_empty()
+    }
+
+    /// the slots allocated, including the free ones
+    pub fn capacity(&self) -> usize {
+        self.slots.len()
+    }
+
+    /// append to the back, an existing order of `id` is moved to the back as well
+    pub fn insert(&mut self, id: OrderId, order: Order) -> Option<Order> {
+        let replaced = self.remove(&id);
+        let slot = Slot {
+            order: Some(order),
+            prev: self.tail,
+            next: NIL,
+        };
+        let i = match self.free {
+            NIL => {
+                self.slots.push(slot);
+                self.slots.len() - 1
+            }
+            i => {
+                self.free = self.slots[i].next;
+                self.slots[i] = slot;
+                i
+            }
+        };
+        match self.tail {
+            NIL => self.head = i,
+            tail => self.slots[tail].next = i,
+        }
+        self.tail = i;
+        self.index.insert(id, i);
+        replaced
+    }
+
+    pub fn remove(&mut self, id: &OrderId) -> Option<Order> {
+        let i = self.index.remove(id)?;
+        let (prev, next) = (self.slots[i].prev, self.slots[i].next);
+        match prev {
+            NIL => self.head = next,
+            prev => self.slots[prev].next = next,
+        }
+        match next {
+            NIL => self.tail = prev,
+            next => self.slots[next].prev = prev,
+        }
+        let slot = &mut self.slots[i];
+        slot.next = self.free;
+        self.free = i;
+        slot.order.take()
+    }
+
+    pub fn get(&self, id: &OrderId) -> Option<&Order> {
+        self.index
+            .get(id)
+            .and_then(|i| self.slots[*i].order.as_ref())
+    }
+
+    pub fn get_mut(&mut self, id: &OrderId) -> Option<&mut Order> {
+        self.index
+            .get(id)
+            .and_then(|i| self.slots[*i].order.as_mut())
+    }
+
+    /// the oldest order
+    pub fn front(&self) -> Option<&Order> {
+        self.slots.get(self.head).and_then(|s| s.order.as_ref())
+    }
+
+    pub fn front_mut(&mut self) -> Option<&mut Order> {
+        self.slots.get_mut(self.head).and_then(|s| s.order.as_mut())
+    }
+
+    pub fn pop_front(&mut self) -> Option<Order> {
+        let id = self.front()?.id;
+        self.remove(&id)
+    }
+
+    pub fn values(&self) -> impl Iterator<Item = &Order> {
+        let mut i = self.head;
+        std::iter::from_fn(move || {
+            let slot = self.slots.get(i)?;
+            i = slot.next;
+            slot.order.as_ref()
+        })
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&OrderId, &Order)> {
+        self.values().map(|o| (&o.id, o))
+    }
+}
+
+impl FromIterator<(OrderId, Order)> for OrderQueue {
+    fn from_iter<T: IntoIterator<Item = (OrderId, Order)>>(iter: T) -> Self {
+        let mut queue = Self::new();
+        for (id, order) in iter {
+            queue.insert(id, order);
+        }
+        queue
+    }
+}
+
+impl PartialEq for OrderQueue {
+    fn eq(&self, other: &Self) -> bool {
+        self.len() == other.len() && self.iter().eq(other.iter())
+    }
+}
+
+impl Eq for OrderQueue {}
+
+impl std::fmt::Debug for OrderQueue {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_map().entries(self.iter()).finish()
+    }
+}
+
+impl Serialize for OrderQueue {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut map = serializer.serialize_map(Some(self.len()))?;
+        for (id, order) in self.iter() {
+            map.serialize_entry(id, order)?;
+        }
+        map.end()
+    }
+}
+
+impl<'de> Deserialize<'de> for OrderQueue {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct QueueVisitor;
+
+        impl<'de> Visitor<'de> for QueueVisitor {
+            type Value = OrderQueue;
+
+            fn expecting(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+                f.write_str("a map of orders")
+            }
+
+            fn visit_map<M: MapAccess<'de>>(self, mut access: M) -> Result<OrderQueue, M::Error> {
+                let mut queue = OrderQueue::new();
+                while let Some((id, order)) = access.next_entry()? {
+                    queue.insert(id, order);
+                }
+                Ok(queue)
+            }
+        }
+
+        deserializer.deserialize_map(QueueVisitor)
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Eq, PartialEq, Clone)]
+pub struct OrderPage {
+    pub orders: OrderQueue,
+    pub amount: Amount,
+    pub price: Price,
+    /// the hidden of the icebergs, included in `amount` but not in the depth
+    #[serde(default)]
+    pub hidden: Amount,
+}
+
+pub type Level = (Price, Amount, Amount);
+
+impl OrderPage {
+    fn with_init_order(order: Order) -> Self {
+        let amount = order.unfilled;
+        let price = order.price;
+        let hidden = order.hidden;
+        let mut orders = OrderQueue::new();
+        orders.insert(order.id, order);
+        Self {
+            orders,
+            amount,
+            price,
+            hidden,
+        }
+    }
+
+    /// the visible level, the hidden of the icebergs excluded
+    pub fn merge(&self, base_scale: u32, quote_scale: u32, total: Amount) -> Level {
+        let mut amount = self.amount - self.hidden;
+        let mut price = self.price;
+        amount.rescale(base_scale);
+        price.rescale(quote_scale);
+        (price, amount, total + amount)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.orders.is_empty()
+    }
+
+    pub fn decr_size(&mut self, amount: &Amount) {
+        self.amount -= amount;
+    }
+
+    fn remove(&mut self, order_id: OrderId) -> Option<Order> {
+        self.orders.remove(&order_id).map(|x| {
+            self.amount -= x.unfilled;
+            self.hidden -= x.hidden;
+            x
+        })
+    }
+
+    fn get(&self, order_id: OrderId) -> Option<&Order> {
+        self.orders.get(&order_id)
+    }
+}
+
+pub type Tape = BTreeMap<Price, OrderPage>;
+
+pub type Index = HashMap<OrderId, Price>;
+
+/// the takers are rejected for `halt` seconds once a trade moves the price more than `max_move`,
+/// e.g. `0.1` for 10%, from the first trade of the `window` seconds, zero `max_move` if unchecked
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Eq, PartialEq, Default)]
+pub struct CircuitBreaker {
+    pub max_move: Fee,
+    pub window: u64,
+    pub halt: u64,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Eq, PartialEq, Default)]
+pub struct OrderBook {
+    pub asks: Tape,
+    pub bids: Tape,
+    pub indices: Index,
+    pub base_scale: u32,
+    pub quote_scale: u32,
+    pub taker_fee: Fee,
+    pub maker_fee: Fee,
+    pub base_taker_fee: Fee,
+    pub base_maker_fee: Fee,
+    pub fee_times: u32,
+    /// the part of the fees accrued to the broker of an order
+    pub broker_share: Fee,
+    /// the prices must be the multiples of, zero if only the `quote_scale` is checked
+    pub tick_size: Price,
+    /// the amounts must be the multiples of, zero if only the `base_scale` is checked
+    pub lot_size: Amount,
+    /// the limit prices must be within the ratio away from the last or mid price, zero if unchecked
+    pub price_band: Fee,
+    /// the policy of the takers crossing the resting orders of the same user, overridden by the
+    /// orders specifying one
+    pub self_trade_prevention: SelfTradePrevention,
+    /// the passive orders resting beyond the total open notional are rejected, zero if unchecked
+    pub max_open_notional: Vol,
+    /// the running open notional of the users, rebuilt from the tapes on loading
+    #[serde(skip)]
+    pub user_notional: HashMap<UserId, Vol>,
+    #[serde(skip)]
+    pub total_notional: Vol,
+    /// the passive orders of each user resting beyond are rejected, zero if unchecked
+    pub max_open_orders: u32,
+    /// the daily windows in UTC accepting new orders, e.g. `"09:30-16:00"`, always open if empty
+    pub trading_hours: Vec<String>,
+    pub circuit_breaker: CircuitBreaker,
+    /// the block trades below are rejected, zero if block trades are disabled
+    pub block_trade_min_amount: Amount,
+    /// seconds to hold the public report, the klines and the exports of the block trades, the
+    /// fills are replied to the parties immediately
+    pub block_trade_report_delay: u64,
+    pub min_amount: Amount,
+    pub min_vol: Amount,
+    pub enable_market_order: bool,
+    pub open: bool,
+    pub max_id: OrderId,
+}
+
+impl OrderBook {
+    pub fn new(
+        base_scale: u32,
+        quote_scale: u32,
+        taker_fee: Fee,
+        maker_fee: Fee,
+        base_taker_fee: Fee,
+        base_maker_fee: Fee,
+        fee_times: u32,
+        min_amount: Amount,
+        min_vol: Amount,
+        enable_market_order: bool,
+        open: bool,
+    ) -> Self {
+        Self {
+            asks: Tape::new(),
+            bids: Tape::new(),
+            indices: Index::with_capacity(DEFAULT_PAGE_SIZE),
+            base_scale,
+            quote_scale,
+            taker_fee,
+            maker_fee,
+            base_taker_fee,
+            base_maker_fee,
+            fee_times,
+            broker_share: Fee::zero(),
+            tick_size: Price::zero(),
+            lot_size: Amount::zero(),
+            price_band: Fee::zero(),
+            self_trade_prevention: SelfTradePrevention::default(),
+            max_open_notional: Vol::zero(),
+            user_notional: HashMap::new(),
+            total_notional: Vol::zero(),
+            max_open_orders: 0,
+            trading_hours: Vec::new(),
+            circuit_breaker: CircuitBreaker::default(),
+            block_trade_min_amount: Amount::zero(),
+            block_trade_report_delay: 0,
+            min_amount,
+            min_vol,
+            enable_market_order,
+            open,
+            // since we always use incr then fetch, so the first order is 1
+            max_id: 0,
+        }
+    }
+
+    pub fn incr_then_fetch_order_id(&mut self) -> OrderId {
+        self.max_id += 1;
+        self.max_id
+    }
+
+    pub fn size(&self) -> (Amount, Amount) {
+        (
+            self.asks.values().fold(Amount::zero(), |x, a| x + a.amount),
+            self.bids.values().fold(Amount::zero(), |x, b| x + b.amount),
+        )
+    }
+
+    pub fn insert(&mut self, order: Order, ask_or_bid: AskOrBid) {
+        self.incr_notional(order.user, order.price * order.unfilled);
+        match ask_or_bid {
+            AskOrBid::Ask => Self::insert_into(&mut self.asks, &mut self.indices, order),
+            AskOrBid::Bid => Self::insert_into(&mut self.bids, &mut self.indices, order),
+        }
+    }
+
+    fn insert_into(tape: &mut Tape, index: &mut Index, order: Order) {
+        index.insert(order.id, order.price);
+        tape.entry(order.price)
+            .and_modify(|page| {
+                page.amount += order.unfilled;
+                page.hidden += order.hidden;
+                page.orders.insert(order.id, order.clone());
+            })
+            .or_insert_with(|| OrderPage::with_init_order(order));
+    }
+
+    pub fn remove(&mut self, order_id: OrderId) -> Option<(Order, AskOrBid)> {
+        let price = self.indices.remove(&order_id)?;
+        let removed = match (self.get_best_ask(), self.get_best_bid()) {
+            (Some(best_ask), Some(_)) => {
+                if price >= best_ask {
+                    Self::remove_from(&mut self.asks, order_id, &price).map(|o| (o, AskOrBid::Ask))
+                } else {
+                    Self::remove_from(&mut self.bids, order_id, &price).map(|o| (o, AskOrBid::Bid))
+                }
+            }
+            (None, Some(_)) => {
+                Self::remove_from(&mut self.bids, order_id, &price).map(|o| (o, AskOrBid::Bid))
+            }
+            (Some(_), None) => {
+                Self::remove_from(&mut self.asks, order_id, &price).map(|o| (o, AskOrBid::Ask))
+            }
+            _ => None,
+        };
+        if let Some((order, _)) = removed.as_ref() {
+            self.incr_notional(order.user, -(order.price * order.unfilled));
+        }
+        removed
+    }
+
+    fn remove_from(tape: &mut Tape, order_id: OrderId, price: &Price) -> Option<Order> {
+        let page = tape.get_mut(price)?;
+        let removed = page.remove(order_id);
+        if page.is_empty() {
+            tape.remove(&price);
+        }
+        removed
+    }
+
+    fn get_size_from(tape: &Tape, price: &Price) -> Option<Amount> {
+        tape.get(price).map(|page| page.amount)
+    }
+
+    pub fn get_best_if_match(
+        &mut self,
+        ask_or_bid: AskOrBid,
+        taker_price: &Price,
+    ) -> Option<OccupiedEntry<Price, OrderPage>> {
+        match ask_or_bid {
+            AskOrBid::Bid => self.asks.first_entry().filter(|v| taker_price >= v.key()),
+            AskOrBid::Ask => self.bids.last_entry().filter(|v| taker_price <= v.key()),
+        }
+    }
+
+    pub fn get_best_ask(&self) -> Option<Price> {
+        self.asks.first_key_value().map(|(price, _)| *price)
+    }
+
+    pub fn get_best_bid(&self) -> Option<Price> {
+        self.bids.last_key_value().map(|(price, _)| *price)
+    }
+
+    /// both sides are required
+    pub fn get_mid_price(&self) -> Option<Price> {
+        match (self.get_best_ask(), self.get_best_bid()) {
+            (Some(ask), Some(bid)) => Some((ask + bid) / Price::TWO),
+            _ => None,
+        }
+    }
+
+    pub fn get_page_size(&self, price: &Price) -> Option<Amount> {
+        match (self.get_best_ask(), self.get_best_bid()) {
+            (Some(best_ask), Some(_)) => {
+                if price >= &best_ask {
+                    Self::get_size_from(&self.asks, price)
+                } else {
+                    Self::get_size_from(&self.bids, price)
+                }
+            }
+            (None, Some(_)) => Self::get_size_from(&self.bids, price),
+            (Some(_), None) => Self::get_size_from(&self.asks, price),
+            _ => None,
+        }
+    }
+
+    pub fn get_size_of_best(&self) -> (Option<(Price, Amount)>, Option<(Price, Amount)>) {
+        (
+            self.asks
+                .first_key_value()
+                .map(|(price, v)| (*price, v.amount)),
+            self.bids
+                .last_key_value()
+                .map(|(price, v)| (*price, v.amount)),
+        )
+    }
+
+    pub fn find_order(&self, order_id: OrderId) -> Option<&Order> {
+        let price = self.indices.get(&order_id)?;
+        match (self.get_best_ask(), self.get_best_bid()) {
+            (Some(best_ask), Some(_)) => {
+                if *price >= best_ask {
+                    self.asks.get(price).and_then(|page| page.get(order_id))
+                } else {
+                    self.bids.get(price).and_then(|page| page.get(order_id))
+                }
+            }
+            (None, Some(_)) => self.bids.get(price).and_then(|page| page.get(order_id)),
+            (Some(_), None) => self.asks.get(price).and_then(|page| page.get(order_id)),
+            _ => None,
+        }
+    }
+
+    /// all resting orders, optionally of `user`
+    pub fn list_orders(&self, user: Option<UserId>) -> Vec<&Order> {
+        self.asks
+            .values()
+            .chain(self.bids.values())
+            .flat_map(|page| page.orders.values())
+            .filter(|o| user.map_or(true, |u| o.user == u))
+            .collect()
+    }
+
+    /// sum of `price * unfilled` of all resting orders
+    pub fn open_notional(&self) -> Vol {
+        self.total_notional
+    }
+
+    /// sum of `price * unfilled` of the resting orders of `user`
+    pub fn open_notional_of(&self, user: &UserId) -> Vol {
+        self.user_notional.get(user).copied().unwrap_or_default()
+    }
+
+    /// the resting orders of `user` are added or filled by `delta`
+    pub fn incr_notional(&mut self, user: UserId, delta: Vol) {
+        self.total_notional += delta;
+        let notional = self.user_notional.entry(user).or_default();
+        *notional += delta;
+        if notional.is_zero() {
+            self.user_notional.remove(&user);
+        }
+    }
+
+    pub fn rebuild_notional(&mut self) {
+        let mut user_notional = HashMap::<UserId, Vol>::new();
+        self.asks
+            .values()
+            .chain(self.bids.values())
+            .flat_map(|page| page.orders.values())
+            .for_each(|o| *user_notional.entry(o.user).or_default() += o.price * o.unfilled);
+        self.total_notional = user_notional.values().sum();
+        self.user_notional = user_notional;
+    }
+
+    /// the amount that would rest on the book after crossing the opposite side,
+    /// self-trading and the makers limit are not considered
+    pub fn estimate_resting(&self, price: Price, amount: Amount, ask_or_bid: AskOrBid) -> Amount {
+        let crossed = match ask_or_bid {
+            AskOrBid::Ask => self
+                .bids
+                .range(price..)
+                .fold(Amount::zero(), |x, (_, page)| x + page.amount),
+            AskOrBid::Bid => self
+                .asks
+                .range(..=price)
+                .fold(Amount::zero(), |x, (_, page)| x + page.amount),
+        };
+        if crossed >= amount {
+            Amount::zero()
+        } else {
+            amount - crossed
+        }
+    }
+
+    /// the worst opposite price a taker of `amount` would reach, or the last price if the
+    /// liquidity is insufficient, self-trading and the makers limit are not considered
+    pub fn sweep_price(&self, amount: Amount, ask_or_bid: AskOrBid) -> Option<Price> {
+        let mut left = amount;
+        let mut last = None;
+        let pages: Box<dyn Iterator<Item = &OrderPage>> = match ask_or_bid {
+            AskOrBid::Ask => Box::new(self.bids.values().rev()),
+            AskOrBid::Bid => Box::new(self.asks.values()),
+        };
+        for page in pages {
+            last = Some(page.price);
+            if page.amount >= left {
+                break;
+            }
+            left -= page.amount;
+        }
+        last
+    }
+
+    /// the worst price and the amount a bid could take within the quote budget `vol`,
+    /// stopping at `limit` if given, the amount is truncated to the `base_scale`
+    pub fn sweep_vol(&self, vol: Vol, limit: Option<Price>) -> Option<(Price, Amount)> {
+        use rust_decimal::RoundingStrategy;
+        let mut left = vol;
+        let mut taken = Amount::zero();
+        let mut last = None;
+        for page in self
+            .asks
+            .values()
+            .take_while(|p| limit.map_or(true, |l| p.price <= l))
+        {
+            let cost = page.amount * page.price;
+            let amount = match cost <= left {
+                true => page.amount,
+                false => (left / page.price)
+                    .round_dp_with_strategy(self.base_scale, RoundingStrategy::ToZero),
+            };
+            if amount.is_zero() {
+                break;
+            }
+            taken += amount;
+            left -= amount * page.price;
+            last = Some(page.price);
+            if amount < page.amount {
+                break;
+            }
+        }
+        let taken = self.floor_lot(taken);
+        last.filter(|_| !taken.is_zero()).map(|p| (p, taken))
+    }
+
+    /// round down to the multiple of `lot_size`
+    pub fn floor_lot(&self, amount: Amount) -> Amount {
+        match self.lot_size.is_zero() {
+            true => amount,
+            false => amount - amount % self.lot_size,
+        }
+    }
+
+    /// a zero `price` is not checked against the tick size, e.g. the market orders
+    pub fn check_order(&self, price: Price, amount: Amount) -> anyhow::Result<()> {
+        ensure!(self.open, "the symbol is closed");
+        ensure!(
+            amount >= self.min_amount,
+            "the amount {} is less than {}",
+            amount,
+            self.min_amount
+        );
+        ensure!(
+            price.scale() <= self.quote_scale,
+            "the price {} exceeds the scale {}",
+            price,
+            self.quote_scale
+        );
+        ensure!(
+            amount.scale() <= self.base_scale,
+            "the amount {} exceeds the scale {}",
+            amount,
+            self.base_scale
+        );
+        ensure!(
+            self.tick_size.is_zero() || (price % self.tick_size).is_zero(),
+            "the price {} is not a multiple of the tick size {}",
+            price,
+            self.tick_size
+        );
+        ensure!(
+            self.lot_size.is_zero() || (amount % self.lot_size).is_zero(),
+            "the amount {} is not a multiple of the lot size {}",
+            amount,
+            self.lot_size
+        );
+        Ok(())
+    }
+
+    /// the reference is the last price of the symbol, or the mid price if it's never traded
+    pub fn check_price_band(&self, price: Price, last_price: Option<Price>) -> anyhow::Result<()> {
+        if self.price_band.is_zero() {
+            return Ok(());
+        }
+        if let Some(reference) = last_price.or_else(|| self.get_mid_price()) {
+            ensure!(
+                (price - reference).abs() <= reference * self.price_band,
+                "the price {} is beyond the band {} of {}",
+                price,
+                self.price_band,
+                reference
+            );
+        }
+        Ok(())
+    }
+
+    pub fn should_accept(&self, price: Price, amount: Amount) -> bool {
+        self.check_order(price, amount).is_ok()
+    }
+}
+
+#[test]
+pub fn test_orderbook() {
+    use rust_decimal_macros::dec;
+    let base_scale = 5;
+    let quote_scale = 1;
+    let taker_fee = dec!(0.001);
+    let maker_fee = dec!(0.001);
+    let min_amount = dec!(1);
+    let min_vol = dec!(1);
+    let mut book = OrderBook::new(
+        base_scale,
+        quote_scale,
+        taker_fee,
+        maker_fee,
+        taker_fee,
+        maker_fee,
+        1,
+        min_amount,
+        min_vol,
+        true,
+        true,
+    );
+    book.insert(
+        Order::new(1, UserId::zero(), dec!(100), dec!(1)),
+        AskOrBid::Bid,
+    );
+    assert!(book.indices.contains_key(&1));
+    assert_eq!(book.size(), (dec!(0), dec!(1)));
+    assert!(!book.bids.is_empty());
+    assert!(book.asks.is_empty());
+    assert_eq!(book.get_best_bid().unwrap(), dec!(100));
+    assert!(book.get_best_ask().is_none());
+    assert!(book.find_order(1).is_some());
+    book.insert(
+        Order::new(2, UserId::zero(), dec!(105), dec!(1)),
+        AskOrBid::Ask,
+    );
+    assert!(book.indices.contains_key(&2));
+    assert_eq!(book.size().0, dec!(1));
+    assert!(book.find_order(2).is_some());
+    assert_eq!(book.list_orders(None).len(), 2);
+    assert!(book
+        .list_orders(Some(UserId::from_low_u64_be(1)))
+        .is_empty());
+    assert!(!book.asks.is_empty());
+    assert_eq!(book.get_best_ask().unwrap(), dec!(105));
+    assert_eq!(book.open_notional(), dec!(205));
+    assert_eq!(book.open_notional_of(&UserId::zero()), dec!(205));
+    assert_eq!(
+        book.estimate_resting(dec!(105), dec!(3), AskOrBid::Bid),
+        dec!(2)
+    );
+    assert_eq!(
+        book.estimate_resting(dec!(104), dec!(3), AskOrBid::Bid),
+        dec!(3)
+    );
+    assert_eq!(
+        book.estimate_resting(dec!(100), dec!(1), AskOrBid::Ask),
+        dec!(0)
+    );
+    book.insert(
+        Order::new(3, UserId::zero(), dec!(106), dec!(2)),
+        AskOrBid::Ask,
+    );
+    assert_eq!(book.sweep_price(dec!(1), AskOrBid::Bid), Some(dec!(105)));
+    assert_eq!(book.sweep_price(dec!(2), AskOrBid::Bid), Some(dec!(106)));
+    assert_eq!(book.sweep_price(dec!(9), AskOrBid::Bid), Some(dec!(106)));
+    assert_eq!(book.sweep_price(dec!(9), AskOrBid::Ask), Some(dec!(100)));
+    assert_eq!(book.sweep_vol(dec!(105), None), Some((dec!(105), dec!(1))));
+    assert_eq!(
+        book.sweep_vol(dec!(50), None),
+        Some((dec!(105), dec!(0.47619)))
+    );
+    assert_eq!(
+        book.sweep_vol(dec!(158), None),
+        Some((dec!(106), dec!(1.5)))
+    );
+    assert_eq!(book.sweep_vol(dec!(1000), None), Some((dec!(106), dec!(3))));
+    assert_eq!(
+        book.sweep_vol(dec!(158), Some(dec!(105))),
+        Some((dec!(105), dec!(1)))
+    );
+    assert!(book.sweep_vol(dec!(0.0001), None).is_none());
+    book.remove(1);
+    assert!(book.sweep_price(dec!(1), AskOrBid::Ask).is_none());
+    assert_eq!(book.open_notional(), dec!(317));
+    let mut rebuilt = book.clone();
+    rebuilt.rebuild_notional();
+    assert_eq!(book, rebuilt);
+    book.tick_size = dec!(0.5);
+    book.lot_size = dec!(0.1);
+    assert_eq!(
+        book.sweep_vol(dec!(150), None),
+        Some((dec!(106), dec!(1.4)))
+    );
+    assert!(book.check_order(dec!(100.5), dec!(1.2)).is_ok());
+    assert!(book.check_order(Price::zero(), dec!(1.2)).is_ok());
+    assert_eq!(
+        "the price 100.3 is not a multiple of the tick size 0.5",
+        book.check_order(dec!(100.3), dec!(1.2))
+            .unwrap_err()
+            .to_string()
+    );
+    assert!(!book.should_accept(dec!(100.5), dec!(1.25)));
+    assert_eq!(dec!(1.2), book.floor_lot(dec!(1.25)));
+    book.price_band = dec!(0.1);
+    assert!(book.check_price_band(dec!(1000), None).is_ok());
+    assert!(book.check_price_band(dec!(110), Some(dec!(100))).is_ok());
+    assert_eq!(
+        "the price 89.5 is beyond the band 0.1 of 100",
+        book.check_price_band(dec!(89.5), Some(dec!(100)))
+            .unwrap_err()
+            .to_string()
+    );
+}
+
+#[test]
+pub fn test_order_queue() {
+    use rust_decimal_macros::dec;
+    let order = |id| Order::new(id, UserId::zero(), dec!(100), dec!(1));
+    let mut queue = (1..=3).map(|id| (id, order(id))).collect::<OrderQueue>();
+    assert_eq!(3, queue.len());
+    assert_eq!(Some(1), queue.front().map(|o| o.id));
+    assert_eq!(Some(order(2)), queue.remove(&2));
+    assert!(queue.get(&2).is_none());
+    assert_eq!(vec![1, 3], queue.values().map(|o| o.id).collect::<Vec<_>>());
+    // the freed slot is reused
+    queue.insert(4, order(4));
+    assert_eq!(3, queue.capacity());
+    assert_eq!(Some(order(1)), queue.pop_front());
+    queue.insert(3, order(3));
+    assert_eq!(vec![4, 3], queue.values().map(|o| o.id).collect::<Vec<_>>());
+    queue.front_mut().unwrap().fill(dec!(0.5));
+    assert_eq!(dec!(0.5), queue.get(&4).unwrap().unfilled);
+    assert_eq!(3, queue.capacity());
+    // encoded as the entries like `LinkedHashMap`
+    let entries = queue
+        .iter()
+        .map(|(id, o)| (*id, o.clone()))
+        .collect::<Vec<_>>();
+    let v = bincode::serialize(&queue).unwrap();
+    assert_eq!(bincode::serialize(&entries).unwrap(), v);
+    let des: OrderQueue = bincode::deserialize(&v).unwrap();
+    assert_eq!(queue, des);
+    while queue.pop_front().is_some() {}
+    assert!(queue.is_empty() && queue.front().is_none());
+}
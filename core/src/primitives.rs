@@ -0,0 +1,187 @@
+// Copyright 2021-2023 UINB Technologies Pte. Ltd.
+
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// 	http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::assets::Balance;
+use parity_scale_codec::{WrapperTypeDecode, WrapperTypeEncode};
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+pub type Base = u32;
+pub type Quote = u32;
+pub type Price = Decimal;
+pub type Amount = Decimal;
+pub type Vol = Decimal;
+pub type Currency = u32;
+pub type Symbol = (Base, Quote);
+pub type EventId = u64;
+pub type OrderId = u64;
+pub type Fee = Decimal;
+pub type Scale = u32;
+pub type Timestamp = u64;
+pub type Account = HashMap<Currency, Balance>;
+pub type Accounts = crate::chunked::ChunkedMap<UserId, Account>;
+pub type UserId = B256;
+
+#[derive(Clone, Copy, Eq, PartialEq, Ord, PartialOrd, Hash, Serialize, Deserialize, Default)]
+pub struct B256(pub [u8; 32]);
+
+impl B256 {
+    pub const fn zero() -> Self {
+        Self([0; 32])
+    }
+
+    pub fn new(x: [u8; 32]) -> Self {
+        Self(x)
+    }
+
+    // adapt to legacy code
+    pub fn from_low_u64_be(x: u64) -> Self {
+        let mut s = [0u8; 32];
+        s[24..].copy_from_slice(&x.to_be_bytes());
+        Self::new(s)
+    }
+
+    pub fn from_hex_str(s: &str) -> anyhow::Result<Self> {
+        let hex = s.trim_start_matches("0x");
+        if hex.len() == 64 {
+            let mut bytes = [0u8; 32];
+            hex::decode_to_slice(hex, &mut bytes)
+                .map_err(|_| anyhow::anyhow!("invalid hex string"))
+                .map(|_| Self::from(bytes))
+        } else {
+            Err(anyhow::anyhow!("invalid hex string"))
+        }
+    }
+}
+
+impl std::ops::Deref for B256 {
+    type Target = [u8; 32];
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl std::str::FromStr for B256 {
+    type Err = anyhow::Error;
+
+    #[cfg(feature = "ss58")]
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        if s.starts_with("0x") {
+            Self::from_hex_str(s)
+        } else {
+            use sp_core::crypto::Ss58Codec;
+            Self::from_ss58check(s).map_err(|_| anyhow::anyhow!("invalid ss58 format"))
+        }
+    }
+
+    #[cfg(not(feature = "ss58"))]
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        Self::from_hex_str(s)
+    }
+}
+
+#[cfg(feature = "ss58")]
+impl std::string::ToString for B256 {
+    fn to_string(&self) -> String {
+        use sp_core::crypto::Ss58Codec;
+        self.to_ss58check()
+    }
+}
+
+#[cfg(feature = "ss58")]
+impl std::fmt::Debug for B256 {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        use sp_core::crypto::Ss58Codec;
+        let s = self.to_ss58check();
+        write!(f, "{}", &s)
+    }
+}
+
+#[cfg(not(feature = "ss58"))]
+impl std::fmt::Debug for B256 {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "0x{}", hex::encode(self.0))
+    }
+}
+
+impl AsRef<[u8]> for B256 {
+    fn as_ref(&self) -> &[u8] {
+        &self.0[..]
+    }
+}
+
+impl AsMut<[u8]> for B256 {
+    fn as_mut(&mut self) -> &mut [u8] {
+        &mut self.0[..]
+    }
+}
+
+impl AsRef<[u8; 32]> for B256 {
+    fn as_ref(&self) -> &[u8; 32] {
+        &self.0
+    }
+}
+
+impl AsMut<[u8; 32]> for B256 {
+    fn as_mut(&mut self) -> &mut [u8; 32] {
+        &mut self.0
+    }
+}
+
+impl From<[u8; 32]> for B256 {
+    fn from(x: [u8; 32]) -> Self {
+        Self::new(x)
+    }
+}
+
+#[cfg(feature = "ss58")]
+impl sp_core::ByteArray for B256 {
+    const LEN: usize = 32;
+}
+
+impl<'a> TryFrom<&'a [u8]> for B256 {
+    type Error = ();
+
+    fn try_from(value: &'a [u8]) -> Result<Self, Self::Error> {
+        if value.len() != 32 {
+            return Err(());
+        }
+        let mut out = [0u8; 32];
+        out.copy_from_slice(&value[..]);
+        return Ok(B256::new(out));
+    }
+}
+
+#[cfg(feature = "ss58")]
+impl sp_core::crypto::Ss58Codec for B256 {}
+
+impl WrapperTypeEncode for B256 {}
+
+impl WrapperTypeDecode for B256 {
+    type Wrapped = [u8; 32];
+}
+
+pub const SYSTEM: UserId = UserId::zero();
+
+/// the system sub-account where the fee share of a broker accrues, no one holds its key
+pub fn broker_account(broker: &UserId) -> UserId {
+    UserId::new(broker.0.map(|b| !b))
+}
+
+#[must_use]
+pub fn max_number() -> Amount {
+    u64::MAX.into()
+}
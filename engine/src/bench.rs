@@ -0,0 +1,335 @@
+// Copyright 2021-2023 UINB Technologies Pte. Ltd.
+
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// 	http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::{
+    config::BenchCmd,
+    core::*,
+    executor::Replayer,
+    input::{AssetsCmd, Event, LimitCmd, SymbolCmd},
+    matcher,
+};
+use galois_core::fixed;
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use rust_decimal::{prelude::Zero, Decimal};
+use serde::Serialize;
+use std::time::{Duration, Instant};
+
+const SYMBOL: Symbol = (1, 0);
+
+// the mid price in ticks of 0.01 where the flow starts
+const MID: i64 = 10000;
+
+// the limit prices spread around the mid in ticks, about half of the orders cross the book
+const SPREAD: i64 = 20;
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Report {
+    pub orders: usize,
+    /// the takers resting on the book
+    pub adds: usize,
+    /// the makers filled by the takers
+    pub matches: usize,
+    pub elapsed_ms: u128,
+    pub adds_per_sec: f64,
+    pub matches_per_sec: f64,
+    pub p50_us: f64,
+    pub p99_us: f64,
+    pub max_us: f64,
+}
+
+/// the same seed generates the same limit orders, the mid price walks randomly
+struct Flow {
+    rng: StdRng,
+    users: u64,
+    mid: i64,
+}
+
+impl Flow {
+    fn new(seed: u64, users: u64) -> Self {
+        Self {
+            rng: StdRng::seed_from_u64(seed),
+            users: users.max(1),
+            mid: MID,
+        }
+    }
+
+    fn next(&mut self) -> (UserId, Price, Amount, AskOrBid) {
+        if self.rng.gen_ratio(1, 100) {
+            self.mid = (self.mid + self.rng.gen_range(-5..=5)).max(SPREAD + 1);
+        }
+        let user_id = UserId::from_low_u64_be(self.rng.gen_range(1..=self.users));
+        let ask_or_bid = if self.rng.gen_bool(0.5) {
+            AskOrBid::Ask
+        } else {
+            AskOrBid::Bid
+        };
+        let price = Decimal::new(self.mid + self.rng.gen_range(-SPREAD..=SPREAD), 2);
+        let amount = Decimal::new(self.rng.gen_range(1..=1000), 1);
+        (user_id, price, amount, ask_or_bid)
+    }
+}
+
+struct Recorder {
+    latencies: Vec<Duration>,
+    adds: usize,
+    matches: usize,
+}
+
+impl Recorder {
+    fn new(orders: usize) -> Self {
+        Self {
+            latencies: Vec::with_capacity(orders),
+            adds: 0,
+            matches: 0,
+        }
+    }
+
+    fn record(&mut self, latency: Duration, added: bool, matches: usize) {
+        self.latencies.push(latency);
+        self.adds += added as usize;
+        self.matches += matches;
+    }
+
+    fn report(mut self) -> Report {
+        self.latencies.sort_unstable();
+        let elapsed = self.latencies.iter().sum::<Duration>();
+        let secs = elapsed.as_secs_f64().max(f64::EPSILON);
+        let percentile = |q: f64| {
+            self.latencies
+                .get(((self.latencies.len().max(1) - 1) as f64 * q).round() as usize)
+                .map_or(0.0, |d| d.as_secs_f64() * 1e6)
+        };
+        Report {
+            orders: self.latencies.len(),
+            adds: self.adds,
+            matches: self.matches,
+            elapsed_ms: elapsed.as_millis(),
+            adds_per_sec: self.adds as f64 / secs,
+            matches_per_sec: self.matches as f64 / secs,
+            p50_us: percentile(0.5),
+            p99_us: percentile(0.99),
+            max_us: percentile(1.0),
+        }
+    }
+}
+
+/// the orders are matched against a bare orderbook, without clearing and proving
+fn bench_matcher(c: &BenchCmd) -> Report {
+    let mut book = OrderBook::default();
+    let mut flow = Flow::new(c.seed, c.users);
+    let mut recorder = Recorder::new(c.orders);
+    for _ in 0..c.orders {
+        let (user_id, price, amount, ask_or_bid) = flow.next();
+        let start = Instant::now();
+        let mr = matcher::execute_limit(&mut book, user_id, price, amount, ask_or_bid);
+        recorder.record(
+            start.elapsed(),
+            !mr.taker.unfilled.is_zero(),
+            mr.maker.len(),
+        );
+    }
+    recorder.report()
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct NumericReport {
+    /// whether the clearing multiplies in fixed point
+    pub fixed_point: bool,
+    pub ops: usize,
+    pub decimal_ns_per_op: f64,
+    pub fixed_ns_per_op: f64,
+    pub speedup: f64,
+}
+
+/// the volumes, fees and sums of the flow in `Decimal` and in `Fixed`, which must be identical
+fn bench_numeric(c: &BenchCmd) -> anyhow::Result<NumericReport> {
+    let mut flow = Flow::new(c.seed, c.users);
+    let fee = Decimal::new(1, 3);
+    let orders = (0..c.orders)
+        .map(|_| {
+            let (_, price, amount, _) = flow.next();
+            (price, amount)
+        })
+        .collect::<Vec<_>>();
+    let start = Instant::now();
+    let mut sum = Decimal::zero();
+    for (price, amount) in &orders {
+        let vol = price * amount;
+        sum += vol + vol * fee;
+    }
+    let decimal = start.elapsed();
+    let to_fixed = |d: Decimal| {
+        fixed::Fixed::from_decimal(d).ok_or_else(|| anyhow::anyhow!("{} out of fixed point", d))
+    };
+    let orders = orders
+        .into_iter()
+        .map(|(p, a)| Ok((to_fixed(p)?, to_fixed(a)?)))
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    let (fee, overflow) = (to_fixed(fee)?, || anyhow::anyhow!("overflow"));
+    let start = Instant::now();
+    let mut fixed_sum = fixed::Fixed::ZERO;
+    for (price, amount) in &orders {
+        let vol = price.checked_mul(*amount).ok_or_else(overflow)?;
+        let charge = vol.checked_mul(fee).ok_or_else(overflow)?;
+        fixed_sum = fixed_sum
+            .checked_add(vol.checked_add(charge).ok_or_else(overflow)?)
+            .ok_or_else(overflow)?;
+    }
+    let fixed = start.elapsed();
+    anyhow::ensure!(
+        fixed_sum.to_decimal(sum.scale()) == Some(sum),
+        "fixed point diverged from decimal"
+    );
+    let ops = orders.len() * 4;
+    let ns_per_op = |d: Duration| d.as_nanos() as f64 / ops.max(1) as f64;
+    Ok(NumericReport {
+        fixed_point: cfg!(feature = "fixed-point"),
+        ops,
+        decimal_ns_per_op: ns_per_op(decimal),
+        fixed_ns_per_op: ns_per_op(fixed),
+        speedup: decimal.as_secs_f64() / fixed.as_secs_f64().max(f64::EPSILON),
+    })
+}
+
+/// the orders are executed as the sequenced events, including clearing and proving
+fn bench_executor(c: &BenchCmd) -> anyhow::Result<Report> {
+    let mut data = Data::new();
+    let mut replayer = Replayer::new();
+    let mut id = 0;
+    let mut next_id = || {
+        id += 1;
+        id
+    };
+    let symbol = SymbolCmd {
+        symbol: SYMBOL,
+        open: true,
+        base_scale: 4,
+        quote_scale: 2,
+        taker_fee: Decimal::new(1, 3),
+        maker_fee: Decimal::new(1, 3),
+        base_maker_fee: Decimal::new(1, 3),
+        base_taker_fee: Decimal::new(1, 3),
+        fee_times: 1,
+        min_amount: Decimal::new(1, 1),
+        min_vol: Decimal::zero(),
+        enable_market_order: false,
+        broker_share: None,
+        tick_size: None,
+        lot_size: None,
+        price_band: None,
+        max_open_notional: None,
+        max_open_orders: None,
+        trading_hours: None,
+        circuit_breaker: None,
+        block_trade_min_amount: None,
+        block_trade_report_delay: None,
+        self_trade_prevention: None,
+        timestamp: 0,
+        actor: "bench".to_string(),
+    };
+    replayer.execute(Event::UpdateSymbol(next_id(), symbol), &mut data)?;
+    // enough to fill all the orders of each user
+    for user in 1..=c.users.max(1) {
+        for currency in [SYMBOL.0, SYMBOL.1] {
+            let deposit = AssetsCmd {
+                user_id: UserId::from_low_u64_be(user),
+                in_or_out: InOrOut::In,
+                currency,
+                amount: Decimal::new(1_000_000_000, 0),
+                block_number: user as u32,
+                extrinsic_hash: vec![],
+                timestamp: 0,
+            };
+            replayer.execute(Event::TransferIn(next_id(), deposit), &mut data)?;
+        }
+    }
+    let mut flow = Flow::new(c.seed, c.users);
+    let mut recorder = Recorder::new(c.orders);
+    for _ in 0..c.orders {
+        let (user_id, price, amount, ask_or_bid) = flow.next();
+        let cmd = LimitCmd {
+            symbol: SYMBOL,
+            user_id,
+            price,
+            amount,
+            ask_or_bid,
+            nonce: 0,
+            signature: vec![],
+            broker: None,
+            time_in_force: Default::default(),
+            self_trade_prevention: None,
+            vol: None,
+            display: None,
+            oco: None,
+            client_order_id: None,
+        };
+        let event = Event::Limit(next_id(), cmd, 0, 0, 0);
+        let start = Instant::now();
+        let outputs = replayer.execute_collecting(event, &mut data)?;
+        let latency = start.elapsed();
+        let added = outputs.iter().any(|o| {
+            o.role == Role::Taker
+                && matches!(o.state, OrderState::Placed | OrderState::PartiallyFilled)
+        });
+        let matches = outputs.iter().filter(|o| o.role == Role::Maker).count();
+        recorder.record(latency, added, matches);
+    }
+    Ok(recorder.report())
+}
+
+/// the latencies are measured order by order, so the throughputs exclude generating the flow
+pub fn run(c: BenchCmd) -> anyhow::Result<()> {
+    log::info!(
+        "benchmarking {} orders of {} users with seed {}",
+        c.orders,
+        c.users,
+        c.seed
+    );
+    let matcher = bench_matcher(&c);
+    let executor = bench_executor(&c)?;
+    let numeric = bench_numeric(&c)?;
+    let report = serde_json::json!({
+        "seed": c.seed,
+        "matcher": matcher,
+        "executor": executor,
+        "numeric": numeric,
+    });
+    println!("{}", serde_json::to_string_pretty(&report)?);
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    pub fn test_bench_matcher() {
+        let mut f0 = Flow::new(7, 10);
+        let mut f1 = Flow::new(7, 10);
+        for _ in 0..1000 {
+            assert_eq!(f0.next(), f1.next());
+        }
+        let c = BenchCmd {
+            orders: 2000,
+            users: 10,
+            seed: 7,
+        };
+        let (r0, r1) = (bench_matcher(&c), bench_matcher(&c));
+        assert_eq!(2000, r0.orders);
+        assert!(r0.adds > 0 && r0.matches > 0);
+        assert_eq!((r0.adds, r0.matches), (r1.adds, r1.matches));
+        assert!(r0.p50_us <= r0.p99_us && r0.p99_us <= r0.max_us);
+        assert_eq!(8000, bench_numeric(&c).unwrap().ops);
+    }
+}
@@ -14,6 +14,12 @@
 
 use clap::Parser;
 use serde::{Deserialize, Serialize};
+use sp_core::crypto::Ss58Codec;
+use std::{
+    path::PathBuf,
+    sync::{OnceLock, RwLock},
+};
+use thiserror::Error;
 
 #[derive(Debug, Parser)]
 #[command(author = "UINB Tech", version)]
@@ -22,6 +28,8 @@ pub struct GaloisCli {
     pub file: std::path::PathBuf,
     #[arg(long)]
     pub skip_decrypt: bool,
+    #[arg(long, help = "Validate the config file then exit")]
+    pub validate_config: bool,
     #[clap(subcommand)]
     pub sub: Option<SubCmd>,
     #[command(flatten)]
@@ -40,6 +48,46 @@ pub enum SubCmd {
         about = "Migrate coredump file and sequence storages"
     )]
     Migrate(MigrateCmd),
+    #[clap(
+        name = "verify",
+        about = "Verify the consistency of coredump file and print the report in json"
+    )]
+    Verify(VerifyCmd),
+    #[clap(
+        name = "replay",
+        about = "Replay the sequenced events against coredump file and compare the merkle root with the chain"
+    )]
+    Replay(ReplayCmd),
+    #[clap(
+        name = "upgrade",
+        about = "Upgrade coredump file of a former layout to the current version"
+    )]
+    Upgrade(UpgradeCmd),
+    #[clap(
+        name = "bench",
+        about = "Benchmark the matcher and the executor with the reproducible order flow and print the report in json"
+    )]
+    Bench(BenchCmd),
+    #[clap(
+        name = "journal",
+        about = "Check the checksums of the journal and print the events as json lines"
+    )]
+    Journal(JournalCmd),
+    #[clap(
+        name = "audit",
+        about = "Verify the hash chains of the audit logs and print the summaries as json lines"
+    )]
+    Audit(AuditCmd),
+    #[clap(
+        name = "genesis",
+        about = "Sign or verify the genesis file of the balances and the markets and print the summary in json"
+    )]
+    Genesis(GenesisCmd),
+    #[clap(
+        name = "signer",
+        about = "Sign the proofs and the broker settlements by `fusotao.key_seed` for the galois configured with `fusotao.remote_signer`"
+    )]
+    Signer(SignerCmd),
 }
 
 #[derive(Debug, clap::Args)]
@@ -72,26 +120,450 @@ pub struct MigrateCmd {
     pub core_only: bool,
 }
 
+#[derive(Debug, clap::Args)]
+pub struct UpgradeCmd {
+    #[arg(
+        long,
+        short = 'o',
+        value_name = "PATH",
+        help = "The new coredump file path"
+    )]
+    pub output_path: String,
+    #[arg(
+        long,
+        short = 'i',
+        value_name = "PATH",
+        help = "The old coredump file path"
+    )]
+    pub input_path: String,
+}
+
+#[derive(Debug, clap::Args)]
+pub struct BenchCmd {
+    #[arg(long, default_value_t = 100000, help = "The limit orders to generate")]
+    pub orders: usize,
+    #[arg(long, default_value_t = 1000, help = "The users placing the orders")]
+    pub users: u64,
+    #[arg(
+        long,
+        default_value_t = 0,
+        help = "The seed of the order flow, the same seed generates the same orders"
+    )]
+    pub seed: u64,
+}
+
+#[derive(Debug, clap::Args)]
+pub struct VerifyCmd {
+    #[arg(
+        long,
+        short = 'i',
+        value_name = "PATH",
+        help = "The coredump file path"
+    )]
+    pub input_path: String,
+    #[arg(
+        long,
+        short = 'o',
+        value_name = "PATH",
+        help = "Write the report to the file instead of stdout"
+    )]
+    pub output_path: Option<String>,
+}
+
+#[derive(Debug, clap::Args)]
+pub struct ReplayCmd {
+    #[arg(
+        long,
+        short = 'i',
+        value_name = "PATH",
+        help = "The coredump file path"
+    )]
+    pub input_path: String,
+    #[arg(
+        long,
+        value_name = "EVENT_ID",
+        help = "Replay from the event, next to the id of the coredump file by default"
+    )]
+    pub from: Option<u64>,
+    #[arg(
+        long,
+        value_name = "EVENT_ID",
+        help = "Replay to the event, the on-chain proving progress by default"
+    )]
+    pub to: Option<u64>,
+    #[arg(
+        long,
+        value_name = "DIR",
+        help = "Replay the events of the journal instead of the sequence storage"
+    )]
+    pub journal: Option<String>,
+}
+
+#[derive(Debug, clap::Args)]
+pub struct JournalCmd {
+    #[arg(long, short = 'i', value_name = "DIR", help = "The journal directory")]
+    pub input_path: String,
+    #[arg(
+        long,
+        value_name = "EVENT_ID",
+        default_value_t = 1,
+        help = "Export from the event"
+    )]
+    pub from: u64,
+    #[arg(
+        long,
+        value_name = "EVENT_ID",
+        help = "Export to the event, the last one by default"
+    )]
+    pub to: Option<u64>,
+}
+
+#[derive(Debug, clap::Args)]
+pub struct AuditCmd {
+    #[arg(
+        long,
+        short = 'i',
+        value_name = "DIR",
+        help = "The audit directory or the one of a symbol"
+    )]
+    pub input_path: String,
+}
+
+#[derive(Debug, clap::Args)]
+pub struct GenesisCmd {
+    #[arg(
+        long,
+        short = 'i',
+        value_name = "PATH",
+        help = "The genesis file path, the balances only if ends with `.csv`"
+    )]
+    pub input_path: String,
+    #[arg(
+        long,
+        value_name = "SEED",
+        help = "Sign the genesis file with the sr25519 seed and write the signature to `{PATH}.sig`"
+    )]
+    pub sign: Option<String>,
+    #[arg(
+        long,
+        value_name = "SS58",
+        help = "Verify the signature against the signer, the one signing by default"
+    )]
+    pub signer: Option<String>,
+}
+
+#[derive(Debug, clap::Args)]
+pub struct SignerCmd {
+    #[arg(
+        long,
+        value_name = "PATH",
+        help = "The unix socket to listen on, accessible by the owner only"
+    )]
+    pub listen: String,
+}
+
 #[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(deny_unknown_fields)]
 pub struct Config {
     pub server: ServerConfig,
     pub sequence: SequenceConfig,
     pub fusotao: FusotaoConfig,
     #[cfg(feature = "v1-to-v2")]
     pub mysql: MysqlConfig,
-    #[serde(skip_serializing)]
+    #[cfg(feature = "parquet-export")]
+    pub export: ExportConfig,
+    /// the grpc server is disabled if absent
+    #[cfg(feature = "grpc")]
+    #[serde(default)]
+    pub grpc: Option<GrpcConfig>,
+    /// the fix gateway is disabled if absent
+    #[cfg(feature = "fix")]
+    #[serde(default)]
+    pub fix: Option<FixConfig>,
+    /// the replication is disabled if absent
+    #[serde(default)]
+    pub replication: Option<ReplicationConfig>,
+    /// the admin commands are only accepted on the admin socket if present
+    #[serde(default)]
+    pub admin: Option<AdminConfig>,
+    /// the requests are unlimited if absent
+    #[serde(default)]
+    pub rate_limit: Option<RateLimitConfig>,
+    /// the balances of `QUERY_ACCOUNTS` can't be valued if absent
+    #[serde(default)]
+    pub valuation: Option<ValuationConfig>,
+    /// the matches aren't audited if absent
+    #[serde(default)]
+    pub audit: Option<AuditConfig>,
+    /// the names of the currencies, taking precedence over the tokens registered on chain
+    #[serde(default, rename = "token")]
+    pub tokens: Vec<TokenConfig>,
+    #[serde(default)]
+    pub log: LogConfig,
+    #[serde(skip)]
     pub dry_run: Option<u64>,
 }
 
+impl Config {
+    /// check the values that are well-typed but would fail or misbehave at runtime
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        let mut errors = vec![];
+        if self
+            .server
+            .bind_addr
+            .rsplit_once(':')
+            .and_then(|(_, port)| port.parse::<u16>().ok())
+            .is_none()
+        {
+            errors.push(format!(
+                "server.bind_addr: `{}` is not a valid address",
+                self.server.bind_addr
+            ));
+        }
+        if self.server.data_home.is_empty() {
+            errors.push("server.data_home: must not be empty".to_string());
+        }
+        if self.server.ring_capacity == 0 {
+            errors.push("server.ring_capacity: must be greater than 0".to_string());
+        }
+        if self.server.persist_tree && self.server.tree_cache == 0 {
+            errors.push("server.tree_cache: must be greater than 0".to_string());
+        }
+        if let Some(ref tls) = self.server.tls {
+            if let Err(e) = crate::input::tls::load(&tls.cert_path, &tls.key_path) {
+                errors.push(format!("server.tls: {}", e));
+            }
+        }
+        if self.sequence.checkpoint == 0 {
+            errors.push("sequence.checkpoint: must be greater than 0".to_string());
+        }
+        if self
+            .sequence
+            .journal
+            .as_ref()
+            .is_some_and(|j| j.segment_size == 0)
+        {
+            errors.push("sequence.journal.segment_size: must be greater than 0".to_string());
+        }
+        if let Some(ref genesis) = self.sequence.genesis {
+            if sp_core::sr25519::Public::from_ss58check(&genesis.signer).is_err() {
+                errors.push(format!(
+                    "sequence.genesis.signer: `{}` is not a ss58 address",
+                    genesis.signer
+                ));
+            }
+        }
+        for url in self
+            .fusotao
+            .get_node_urls()
+            .into_iter()
+            .filter(|_| !self.fusotao.standalone)
+        {
+            if !url.starts_with("ws://") && !url.starts_with("wss://") {
+                errors.push(format!(
+                    "fusotao.node_url: `{}` is not a websocket url",
+                    url
+                ));
+            }
+        }
+        if self.fusotao.key_seed.is_empty() && self.fusotao.remote_signer.is_none() {
+            errors.push("fusotao.key_seed: must not be empty without remote_signer".to_string());
+        }
+        if self.fusotao.proof_batch_limit == 0 {
+            errors.push("fusotao.proof_batch_limit: must be greater than 0".to_string());
+        }
+        let x25519 = self.fusotao.x25519_priv.trim_start_matches("0x");
+        if x25519.len() != 64 || hex::decode(x25519).is_err() {
+            errors.push("fusotao.x25519_priv: must be 32 bytes in hex".to_string());
+        }
+        if self.log.level.parse::<log::LevelFilter>().is_err() {
+            errors.push(format!("log.level: unknown level `{}`", self.log.level));
+        }
+        for (module, level) in self.log.modules.iter() {
+            if level.parse::<log::LevelFilter>().is_err() {
+                errors.push(format!("log.modules.{}: unknown level `{}`", module, level));
+            }
+        }
+        for (i, t) in self.tokens.iter().enumerate() {
+            if self.tokens[..i].iter().any(|p| p.currency == t.currency) {
+                errors.push(format!("token[{}]: duplicated currency {}", i, t.currency));
+            }
+            if t.symbol.trim().is_empty() {
+                errors.push(format!("token[{}].symbol: must not be empty", i));
+            }
+        }
+        #[cfg(feature = "parquet-export")]
+        {
+            if self.export.batch_size == 0 {
+                errors.push("export.batch_size: must be greater than 0".to_string());
+            }
+            if self.export.flush_interval == 0 {
+                errors.push("export.flush_interval: must be greater than 0".to_string());
+            }
+            if self.export.writers == 0 {
+                errors.push("export.writers: must be greater than 0".to_string());
+            }
+        }
+        #[cfg(feature = "grpc")]
+        if let Some(ref grpc) = self.grpc {
+            if grpc.bind_addr.parse::<std::net::SocketAddr>().is_err() {
+                errors.push(format!(
+                    "grpc.bind_addr: `{}` is not a valid address",
+                    grpc.bind_addr
+                ));
+            }
+        }
+        #[cfg(feature = "fix")]
+        if let Some(ref fix) = self.fix {
+            if fix.bind_addr.parse::<std::net::SocketAddr>().is_err() {
+                errors.push(format!(
+                    "fix.bind_addr: `{}` is not a valid address",
+                    fix.bind_addr
+                ));
+            }
+            if fix.comp_id.is_empty() {
+                errors.push("fix.comp_id: must not be empty".to_string());
+            }
+        }
+        if let Some(ref replication) = self.replication {
+            if let Some(ref addr) = replication.bind_addr {
+                if addr.parse::<std::net::SocketAddr>().is_err() {
+                    errors.push(format!(
+                        "replication.bind_addr: `{}` is not a valid address",
+                        addr
+                    ));
+                }
+            }
+            if replication.takeover_timeout == 0 {
+                errors.push("replication.takeover_timeout: must be greater than 0".to_string());
+            }
+        }
+        if let Some(ref admin) = self.admin {
+            if admin.bind_addr.parse::<std::net::SocketAddr>().is_err() {
+                errors.push(format!(
+                    "admin.bind_addr: `{}` is not a valid address",
+                    admin.bind_addr
+                ));
+            }
+            if admin.token.len() < 16 {
+                errors.push("admin.token: must be at least 16 characters".to_string());
+            }
+        }
+        if let Some(ref rate_limit) = self.rate_limit {
+            for (class, limit) in [
+                ("orders", rate_limit.orders),
+                ("cancels", rate_limit.cancels),
+                ("queries", rate_limit.queries),
+            ] {
+                if limit.map_or(false, |l| l.rate == 0 || l.burst == 0) {
+                    errors.push(format!(
+                        "rate_limit.{}: rate and burst must be greater than 0",
+                        class
+                    ));
+                }
+            }
+        }
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(ConfigError::Invalid(errors))
+        }
+    }
+
+    pub fn get_token(&self, currency: u32) -> Option<&TokenConfig> {
+        self.tokens.iter().find(|t| t.currency == currency)
+    }
+}
+
 pub trait EncryptedConfig {
     fn decrypt(&mut self, key: &str) -> anyhow::Result<()>;
     fn encrypt(&mut self, key: &str) -> anyhow::Result<()>;
 }
 
+#[derive(Debug, Error)]
+pub enum ConfigError {
+    #[error("unable to read {0}, {1}")]
+    Io(PathBuf, std::io::Error),
+    /// unknown keys, missing sections and mistyped values
+    #[error("{0}")]
+    Parse(#[from] toml::de::Error),
+    #[error("environment variable {0} is invalid, {1}")]
+    Env(String, String),
+    #[error("unable to decrypt the config, {0}")]
+    Decrypt(String),
+    #[error("invalid config\n  {}", .0.join("\n  "))]
+    Invalid(Vec<String>),
+    /// the changes to be applied by restarting
+    #[error("unable to reload {}, restart instead", .0.join(", "))]
+    Unreloadable(Vec<String>),
+}
+
 #[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(deny_unknown_fields)]
 pub struct ServerConfig {
     pub bind_addr: String,
     pub data_home: String,
+    /// persist the recent trades(`QUERY_TRADES`) on checkpoints, otherwise only the trades
+    /// replayed since the latest checkpoint are served after restarting
+    #[serde(default)]
+    pub persist_trades: bool,
+    /// execute the orders of the uncorrelated symbols in parallel by partitioning the symbols
+    /// into the shards, sequentially if less than 2
+    #[serde(default)]
+    pub shards: usize,
+    /// the slots of the ring buffers from the sequencer to the executor and from the executor
+    /// to the market, rounded up to a power of 2, the sender blocks while the ring is full
+    #[serde(default = "default_ring_capacity")]
+    pub ring_capacity: usize,
+    /// publish the accounts and orderbooks at most every `replica_interval` milliseconds, so
+    /// `QUERY_BALANCE`, `QUERY_ACCOUNTS` and `QUERY_ORDER` are served without sequencing but
+    /// lag behind the executor, disabled if 0
+    #[serde(default)]
+    pub replica_interval: u64,
+    /// persist the merkle tree into rocksdb instead of the snapshots, so it's restored without
+    /// replaying and only `tree_cache` nodes are kept in memory; the snapshots dumped since are
+    /// left without the tree, so it can't be turned off again
+    #[serde(default)]
+    pub persist_tree: bool,
+    /// the nodes of the persisted merkle tree cached in memory, the updates are written in a
+    /// batch once the prover is idle or as many nodes are pending
+    #[serde(default = "default_tree_cache")]
+    pub tree_cache: usize,
+    /// terminate TLS on `bind_addr`, plain TCP if absent
+    #[serde(default)]
+    pub tls: Option<TlsConfig>,
+    /// seconds without any request, e.g. `PING`, before closing the connection, 0 to disable
+    #[serde(default)]
+    pub idle_timeout: u64,
+    /// seconds to buffer the replies of a disconnected session for `RESUME_SESSION`, 0 to
+    /// disable
+    #[serde(default = "default_resume_timeout")]
+    pub resume_timeout: u64,
+    /// match and clear the limit orders again by the reference matcher after executing and log
+    /// the divergences of the books and the balances, which slows down the executor
+    #[serde(default)]
+    pub shadow: bool,
+}
+
+/// PEM files, reloaded for the new connections once modified
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct TlsConfig {
+    /// the chain from the end-entity certificate
+    pub cert_path: String,
+    pub key_path: String,
+}
+
+fn default_ring_capacity() -> usize {
+    65536
+}
+
+fn default_tree_cache() -> usize {
+    1 << 20
+}
+
+fn default_resume_timeout() -> u64 {
+    30
 }
 
 impl ServerConfig {
@@ -107,18 +579,304 @@ impl ServerConfig {
         format!("{}/proof/", self.data_home)
     }
 
+    pub fn get_tree_path(&self) -> String {
+        format!("{}/merkle/", self.data_home)
+    }
+
+    pub fn get_journal_path(&self) -> String {
+        format!("{}/journal/", self.data_home)
+    }
+
+    pub fn get_audit_path(&self) -> String {
+        format!("{}/audit/", self.data_home)
+    }
+
     pub fn get_output_path(&self) -> String {
         format!("{}/market/", self.data_home)
     }
+
+    pub fn get_usage_path(&self) -> String {
+        format!("{}/usage.json", self.data_home)
+    }
+
+    pub fn get_klines_path(&self) -> String {
+        format!("{}/klines.bin", self.data_home)
+    }
+
+    pub fn get_config_history_path(&self) -> String {
+        format!("{}/config_history.jsonl", self.data_home)
+    }
+
+    pub fn get_trades_path(&self) -> String {
+        format!("{}/trades.bin", self.data_home)
+    }
 }
 
 #[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(deny_unknown_fields)]
 pub struct SequenceConfig {
     pub checkpoint: u64,
     pub enable_from_genesis: bool,
+    /// the saved events are appended to the journal before executing if present, they are kept
+    /// after the checkpoints for the recovery to any event and the audits
+    #[serde(default)]
+    pub journal: Option<JournalConfig>,
+    /// the balances and the markets are imported from the signed file if starting from genesis
+    #[serde(default)]
+    pub genesis: Option<GenesisConfig>,
+    /// the commands signed for another domain are rejected, e.g. the mainnet ones on the testnet
+    #[serde(default)]
+    pub domain: Option<String>,
+}
+
+/// the signature of `path` is read from `{path}.sig`, see the `genesis` subcommand
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct GenesisConfig {
+    pub path: String,
+    /// the ss58 address of the sr25519 key signing the file
+    pub signer: String,
+}
+
+/// the journal in `{data_home}/journal/` is rotated into a new segment once `segment_size` bytes
+/// are written, only the latest `retain` segments are kept unless 0
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct JournalConfig {
+    #[serde(default = "default_segment_size")]
+    pub segment_size: u64,
+    #[serde(default)]
+    pub retain: usize,
+}
+
+/// the matches are appended to the hash-chained logs in `{data_home}/audit/{base}-{quote}/` along
+/// with the merkle roots after, which are rotated like the journal but never removed
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct AuditConfig {
+    #[serde(default = "default_segment_size")]
+    pub segment_size: u64,
+}
+
+fn default_segment_size() -> u64 {
+    64 * 1024 * 1024
+}
+
+/// the ticker of `currency` replied along with the markets and balances, e.g. `"USDT"`
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct TokenConfig {
+    pub currency: u32,
+    pub symbol: String,
+}
+
+/// the balances are valued in `quote` by the prices of the markets `(currency, quote)`, or the
+/// inverse of `(quote, currency)`
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct ValuationConfig {
+    pub quote: u32,
+    #[serde(default)]
+    pub source: PriceSource,
 }
 
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, Default, Eq, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum PriceSource {
+    /// the last maker price filled on the book
+    #[default]
+    LastPrice,
+    /// the middle of the best bid and ask, or the only side of the book
+    MidPrice,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, Eq, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    Text,
+    Json,
+}
+
+/// levels of modules could be changed at runtime through `set_log_level` of the admin socket
 #[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct LogConfig {
+    #[serde(default = "default_log_level")]
+    pub level: String,
+    #[serde(default = "default_log_format")]
+    pub format: LogFormat,
+    /// write to stderr if absent
+    #[serde(default)]
+    pub file: Option<String>,
+    /// rotate the file once it exceeds `max_size` bytes, 0 to disable
+    #[serde(default = "default_log_max_size")]
+    pub max_size: u64,
+    #[serde(default = "default_log_max_files")]
+    pub max_files: usize,
+    /// module alias(matcher, prover, scanner, server) or target prefix -> level
+    #[serde(default)]
+    pub modules: std::collections::HashMap<String, String>,
+}
+
+impl Default for LogConfig {
+    fn default() -> Self {
+        Self {
+            level: default_log_level(),
+            format: default_log_format(),
+            file: None,
+            max_size: default_log_max_size(),
+            max_files: default_log_max_files(),
+            modules: Default::default(),
+        }
+    }
+}
+
+// compatible with the `RUST_LOG` of env_logger if it is a plain level
+fn default_log_level() -> String {
+    std::env::var("RUST_LOG")
+        .ok()
+        .filter(|l| l.parse::<log::LevelFilter>().is_ok())
+        .unwrap_or_else(|| "info".to_string())
+}
+
+fn default_log_format() -> LogFormat {
+    LogFormat::Text
+}
+
+fn default_log_max_size() -> u64 {
+    100 * 1024 * 1024
+}
+
+fn default_log_max_files() -> usize {
+    10
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct ExportConfig {
+    pub path: String,
+    #[serde(default = "default_export_batch_size")]
+    pub batch_size: usize,
+    #[serde(default = "default_export_flush_interval")]
+    pub flush_interval: u64,
+    /// the threads writing the partitions, the symbols are spread over them
+    #[serde(default = "default_export_writers")]
+    pub writers: usize,
+}
+
+fn default_export_batch_size() -> usize {
+    100000
+}
+
+fn default_export_flush_interval() -> u64 {
+    600
+}
+
+fn default_export_writers() -> usize {
+    4
+}
+
+/// serving the same commands as `server.bind_addr`, so it should be exposed to the trusted
+/// network only as well
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct GrpcConfig {
+    pub bind_addr: String,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct FixConfig {
+    pub bind_addr: String,
+    /// the SenderCompID of the gateway, i.e. the TargetCompID of the clients
+    pub comp_id: String,
+    /// the `Password`(554) of the logons, any accepted if absent
+    #[serde(default)]
+    pub password: Option<String>,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct ReplicationConfig {
+    /// stream the saved events to the standbys connecting to it
+    #[serde(default)]
+    pub bind_addr: Option<String>,
+    /// run as a standby of the primary until it's unreachable
+    #[serde(default)]
+    pub primary_addr: Option<String>,
+    /// seconds without hearing from the primary before taking over
+    #[serde(default = "default_takeover_timeout")]
+    pub takeover_timeout: u64,
+    /// the events from the primary buffered after the missing ones at most
+    #[serde(default = "default_reorder_window")]
+    pub reorder_window: usize,
+    /// seconds of the missing events before requesting them again
+    #[serde(default = "default_gap_timeout")]
+    pub gap_timeout: u64,
+}
+
+fn default_takeover_timeout() -> u64 {
+    5
+}
+
+fn default_reorder_window() -> usize {
+    1024
+}
+
+fn default_gap_timeout() -> u64 {
+    10
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct AdminConfig {
+    pub bind_addr: String,
+    /// encrypted like the keys of `fusotao`
+    pub token: String,
+}
+
+/// the token buckets of each user or broker, a class is unlimited if absent
+#[derive(Debug, Deserialize, Serialize, Clone, Default)]
+#[serde(deny_unknown_fields)]
+pub struct RateLimitConfig {
+    /// the limit, market and block orders
+    #[serde(default)]
+    pub orders: Option<RateLimit>,
+    #[serde(default)]
+    pub cancels: Option<RateLimit>,
+    #[serde(default)]
+    pub queries: Option<RateLimit>,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, Eq, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct RateLimit {
+    /// the requests refilled per second
+    pub rate: u32,
+    /// the requests allowed at once
+    pub burst: u32,
+}
+
+impl EncryptedConfig for AdminConfig {
+    fn decrypt(&mut self, key: &str) -> anyhow::Result<()> {
+        use magic_crypt::MagicCryptTrait;
+        let mc = magic_crypt::new_magic_crypt!(key, 64);
+        let dec = mc.decrypt_base64_to_string(&self.token)?;
+        self.token.replace_range(.., &dec);
+        Ok(())
+    }
+
+    fn encrypt(&mut self, key: &str) -> anyhow::Result<()> {
+        use magic_crypt::MagicCryptTrait;
+        let mc = magic_crypt::new_magic_crypt!(key, 64);
+        let enc = mc.encrypt_str_to_base64(&self.token);
+        self.token.replace_range(.., &enc);
+        Ok(())
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(deny_unknown_fields)]
 pub struct MysqlConfig {
     pub url: String,
 }
@@ -142,18 +900,126 @@ impl EncryptedConfig for MysqlConfig {
 }
 
 #[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(deny_unknown_fields)]
 pub struct FusotaoConfig {
     pub node_url: String,
+    /// switched to in turn once the active node drops
+    #[serde(default)]
+    pub fallback_urls: Vec<String>,
+    /// not required with `remote_signer`
+    #[serde(default)]
     pub key_seed: String,
+    /// the unix socket of `galois signer` holding the key on another host or in a vault, the
+    /// proofs and the broker settlements are signed through it
+    #[serde(default)]
+    pub remote_signer: Option<String>,
     pub claim_block: u32,
     pub proof_batch_limit: usize,
+    /// milliseconds to wait for a batch to be filled up before submitting it, the proofs
+    /// are submitted as soon as they are saved if zero
+    #[serde(default)]
+    pub proof_batch_window: u64,
     pub x25519_priv: String,
+    /// run without any fusotao node, the proofs are dropped as if verified on chain and
+    /// the transfers are faked through the admin socket
+    #[serde(default)]
+    pub standalone: bool,
+    /// the proof commands the verifier on chain is upgraded to, the commands proven by the
+    /// others are rejected before sequencing
+    #[serde(default)]
+    pub proof_extensions: Vec<ProofExtension>,
+}
+
+/// the proof commands beyond the orders, the cancels and the transfers
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, Eq, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum ProofExtension {
+    SubTransfer,
+    Route,
+    WithdrawFees,
+    BlockTrade,
+    /// the negative maker fees, the rebates are proven by the leaves of the makers
+    MakerRebate,
+    /// the fees shared to the brokers, proven by the leaves of their system sub-accounts
+    BrokerShare,
+    /// the commands proven in one event, e.g. the self-trades cancelled before the taker
+    Batch,
+    /// the fees of the volume tiers, the makers charged other than the maker fee of the symbol
+    /// are proven along with their fees
+    FeeTier,
+}
+
+impl ProofExtension {
+    /// the extensions required to prove the command and the ones following it
+    pub fn of(cmd: &crate::input::Command) -> Vec<Self> {
+        match cmd.cmd {
+            crate::cmd::UPDATE_SYMBOL => [
+                cmd.maker_fee
+                    .is_some_and(|f| f.is_sign_negative())
+                    .then_some(Self::MakerRebate),
+                cmd.broker_share
+                    .is_some_and(|s| !s.is_zero())
+                    .then_some(Self::BrokerShare),
+                (cmd.self_trade_prevention
+                    == Some(crate::matcher::SelfTradePrevention::CancelOldest))
+                .then_some(Self::Batch),
+            ]
+            .into_iter()
+            .flatten()
+            .collect(),
+            crate::cmd::ASK_LIMIT
+            | crate::cmd::BID_LIMIT
+            | crate::cmd::MARKET_ASK
+            | crate::cmd::MARKET_BID
+                if cmd.self_trade_prevention
+                    == Some(crate::matcher::SelfTradePrevention::CancelOldest) =>
+            {
+                vec![Self::Batch]
+            }
+            crate::cmd::CANCEL_ALL => vec![Self::Batch],
+            crate::cmd::SUB_TRANSFER => vec![Self::SubTransfer],
+            crate::cmd::ROUTE_ASK | crate::cmd::ROUTE_BID => vec![Self::Route],
+            crate::cmd::WITHDRAW_FEES => vec![Self::WithdrawFees],
+            crate::cmd::BLOCK_ASK | crate::cmd::BLOCK_BID => vec![Self::BlockTrade],
+            crate::cmd::UPDATE_FEE_TIERS => {
+                let tiers = cmd.fee_tiers.as_deref().unwrap_or_default();
+                [
+                    (!tiers.is_empty()).then_some(Self::FeeTier),
+                    tiers
+                        .iter()
+                        .any(|t| t.maker_fee.is_sign_negative())
+                        .then_some(Self::MakerRebate),
+                ]
+                .into_iter()
+                .flatten()
+                .collect()
+            }
+            _ => vec![],
+        }
+    }
 }
 
 impl FusotaoConfig {
+    /// all the proofs are dropped in the standalone mode
+    pub fn supports(&self, ext: ProofExtension) -> bool {
+        self.standalone || self.proof_extensions.contains(&ext)
+    }
+
+    pub fn is_provable(&self, cmd: &crate::input::Command) -> bool {
+        ProofExtension::of(cmd)
+            .into_iter()
+            .all(|ext| self.supports(ext))
+    }
+
     pub fn get_x25519(&self) -> String {
         self.x25519_priv.clone()
     }
+
+    pub fn get_node_urls(&self) -> Vec<String> {
+        std::iter::once(self.node_url.clone())
+            .chain(self.fallback_urls.iter().cloned())
+            .collect()
+    }
 }
 
 impl EncryptedConfig for FusotaoConfig {
@@ -201,25 +1067,197 @@ impl EncryptedConfig for RedisConfig {
     }
 }
 
-lazy_static::lazy_static! {
-    pub static ref C: Config = init_config_file().unwrap();
+// the replaced ones are leaked, they are rare and could be still borrowed
+static CONFIG: OnceLock<RwLock<&'static Config>> = OnceLock::new();
+
+// where the config is reloaded from
+static SOURCE: OnceLock<GaloisCli> = OnceLock::new();
+
+/// the global config, available after `install`
+pub struct GlobalConfig;
+
+pub static C: GlobalConfig = GlobalConfig;
+
+impl std::ops::Deref for GlobalConfig {
+    type Target = Config;
+
+    fn deref(&self) -> &Config {
+        *CONFIG
+            .get()
+            .expect("config not installed;qed")
+            .read()
+            .unwrap()
+    }
+}
+
+pub fn install(config: Config) {
+    assert!(
+        CONFIG.set(RwLock::new(Box::leak(Box::new(config)))).is_ok(),
+        "config installed twice"
+    );
+}
+
+/// the config could be reloaded from the file of `cli` after installing
+pub fn install_reloadable(config: Config, cli: GaloisCli) {
+    install(config);
+    assert!(SOURCE.set(cli).is_ok(), "config installed twice");
+}
+
+/// read the config file again and swap the reloadable fields, returning the changed ones, the
+/// other changes are rejected as a whole
+pub fn reload() -> Result<Vec<&'static str>, ConfigError> {
+    let cli = SOURCE
+        .get()
+        .ok_or_else(|| ConfigError::Unreloadable(vec!["the config".to_string()]))?;
+    let lock = CONFIG.get().expect("config not installed;qed");
+    let mut current = lock.write().unwrap();
+    let (config, changed) = merge_reloadable(&current, cli.load_config()?)?;
+    if !changed.is_empty() {
+        *current = Box::leak(Box::new(config));
+    }
+    Ok(changed)
+}
+
+/// the fields applied at runtime, none of them changes the results of the events replayed
+fn merge_reloadable(
+    current: &Config,
+    new: Config,
+) -> Result<(Config, Vec<&'static str>), ConfigError> {
+    let mut merged = current.clone();
+    let mut changed = vec![];
+    macro_rules! merge {
+        ($name:literal, $($field:ident).+) => {
+            if serde_json::to_value(&merged.$($field).+).ok()
+                != serde_json::to_value(&new.$($field).+).ok()
+            {
+                merged.$($field).+ = new.$($field).+.clone();
+                changed.push($name);
+            }
+        };
+    }
+    merge!("log.level", log.level);
+    merge!("log.format", log.format);
+    merge!("log.modules", log.modules);
+    merge!("rate_limit", rate_limit);
+    merge!("fusotao.node_url", fusotao.node_url);
+    merge!("fusotao.fallback_urls", fusotao.fallback_urls);
+    let (before, after) = (
+        serde_json::to_value(&merged).unwrap_or_default(),
+        serde_json::to_value(&new).unwrap_or_default(),
+    );
+    let unreloadable = match (before.as_object(), after.as_object()) {
+        (Some(before), Some(after)) => before
+            .keys()
+            .chain(after.keys())
+            .filter(|k| before.get(*k) != after.get(*k))
+            .cloned()
+            .collect::<std::collections::BTreeSet<_>>(),
+        _ => Default::default(),
+    };
+    if !unreloadable.is_empty() {
+        return Err(ConfigError::Unreloadable(
+            unreloadable.into_iter().collect(),
+        ));
+    }
+    Ok((merged, changed))
+}
+
+/// prefix of the environment variables overriding the config file, e.g.
+/// `GALOIS_SERVER__BIND_ADDR` overrides `bind_addr` of `[server]`
+const ENV_PREFIX: &str = "GALOIS_";
+const ENV_SEPARATOR: &str = "__";
+
+impl GaloisCli {
+    /// read the config file, apply the environment overrides, decrypt and validate it
+    pub fn load_config(&self) -> Result<Config, ConfigError> {
+        let toml = std::fs::read_to_string(&self.file)
+            .map_err(|e| ConfigError::Io(self.file.clone(), e))?;
+        let key = if self.skip_decrypt {
+            None
+        } else {
+            Some(
+                std::env::var("MAGIC_KEY")
+                    .map_err(|_| ConfigError::Decrypt("env MAGIC_KEY not set".to_string()))?,
+            )
+        };
+        let mut cfg = load_config(&toml, key.as_deref(), std::env::vars())?;
+        cfg.dry_run = self.run.dry_run;
+        Ok(cfg)
+    }
+}
+
+fn apply_env_overrides(
+    value: &mut toml::Value,
+    vars: impl IntoIterator<Item = (String, String)>,
+) -> Result<bool, ConfigError> {
+    let mut applied = false;
+    for (name, raw) in vars {
+        let path = match name.strip_prefix(ENV_PREFIX) {
+            // all configurable items are grouped in sections
+            Some(path) if path.contains(ENV_SEPARATOR) => path.to_lowercase(),
+            _ => continue,
+        };
+        let keys = path.split(ENV_SEPARATOR).collect::<Vec<_>>();
+        // numbers and booleans are parsed as toml values, anything else is a string
+        let v = toml::from_str::<toml::value::Table>(&format!("v = {}", raw))
+            .ok()
+            .and_then(|mut t| t.remove("v"))
+            .unwrap_or_else(|| toml::Value::String(raw.clone()));
+        let mut table = value
+            .as_table_mut()
+            .ok_or(ConfigError::Env(name.clone(), "not a table".to_string()))?;
+        for key in &keys[..keys.len() - 1] {
+            table = table
+                .entry(key.to_string())
+                .or_insert_with(|| toml::Value::Table(Default::default()))
+                .as_table_mut()
+                .ok_or(ConfigError::Env(
+                    name.clone(),
+                    format!("`{}` is not a section", key),
+                ))?;
+        }
+        table.insert(keys[keys.len() - 1].to_string(), v);
+        applied = true;
+    }
+    Ok(applied)
 }
 
-fn init_config_file() -> anyhow::Result<Config> {
-    let opts = GaloisCli::parse();
-    if opts.skip_decrypt {
-        init_config(&std::fs::read_to_string(&opts.file)?, None)
+pub fn load_config(
+    toml: &str,
+    key: Option<&str>,
+    env: impl IntoIterator<Item = (String, String)>,
+) -> Result<Config, ConfigError> {
+    let mut value: toml::Value = toml::from_str(toml)?;
+    let mut cfg: Config = if apply_env_overrides(&mut value, env)? {
+        value.try_into()?
     } else {
-        let key = std::env::var_os("MAGIC_KEY").ok_or(anyhow::anyhow!("env MAGIC_KEY not set"))?;
-        init_config(
-            &std::fs::read_to_string(&opts.file)?,
-            key.to_str().map(|s| s.to_string()),
-        )
+        // parsing from the text keeps the line numbers in the errors
+        toml::from_str(toml)?
+    };
+    if let Some(key) = key {
+        cfg.fusotao
+            .decrypt(key)
+            .map_err(|e| ConfigError::Decrypt(e.to_string()))?;
+        if let Some(ref mut admin) = cfg.admin {
+            admin
+                .decrypt(key)
+                .map_err(|e| ConfigError::Decrypt(e.to_string()))?;
+        }
     }
-    .map(|mut c| {
-        c.dry_run = opts.run.dry_run;
-        c
-    })
+    cfg.validate()?;
+    Ok(cfg)
+}
+
+/// the example in dry-run mode for the tests executing the events, installed once
+#[cfg(test)]
+pub fn install_example() {
+    static ONCE: std::sync::Once = std::sync::Once::new();
+    ONCE.call_once(|| {
+        let mut config = load_config(include_str!("../../galois.toml.example"), None, vec![])
+            .expect("the example is valid;qed");
+        config.dry_run = Some(u64::MAX);
+        install(config);
+    });
 }
 
 pub fn print_config(f: &std::path::PathBuf) -> anyhow::Result<()> {
@@ -231,14 +1269,190 @@ pub fn print_config(f: &std::path::PathBuf) -> anyhow::Result<()> {
     let toml = std::fs::read_to_string(f)?;
     let mut cfg: Config = toml::from_str(&toml)?;
     cfg.fusotao.encrypt(&key)?;
+    if let Some(ref mut admin) = cfg.admin {
+        admin.encrypt(&key)?;
+    }
     println!("{}", toml::to_string(&cfg)?);
     Ok(())
 }
 
-fn init_config(toml: &str, key: Option<String>) -> anyhow::Result<Config> {
-    let mut cfg: Config = toml::from_str(toml)?;
-    if let Some(key) = key {
-        cfg.fusotao.decrypt(&key)?;
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const EXAMPLE: &str = include_str!("../../galois.toml.example");
+
+    fn cmd(cmd: u32) -> crate::input::Command {
+        crate::input::Command {
+            cmd,
+            ..Default::default()
+        }
+    }
+
+    fn env(vars: &[(&str, &str)]) -> Vec<(String, String)> {
+        vars.iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect()
+    }
+
+    #[test]
+    pub fn test_load_config() {
+        let cfg = load_config(EXAMPLE, None, vec![]).unwrap();
+        assert_eq!(cfg.sequence.checkpoint, 100000);
+        assert_eq!(cfg.fusotao.proof_batch_window, 0);
+        assert!(!cfg.fusotao.is_provable(&cmd(crate::cmd::SUB_TRANSFER)));
+        assert!(cfg.fusotao.is_provable(&cmd(crate::cmd::ASK_LIMIT)));
+        let upgraded = EXAMPLE.replace(
+            "# proof_extensions = [\"sub_transfer\"]",
+            "proof_extensions = [\"sub_transfer\"]",
+        );
+        let cfg = load_config(&upgraded, None, vec![]).unwrap();
+        assert!(cfg.fusotao.is_provable(&cmd(crate::cmd::SUB_TRANSFER)));
+        assert!(!cfg.fusotao.is_provable(&cmd(crate::cmd::ROUTE_BID)));
+        assert!(!cfg.fusotao.is_provable(&cmd(crate::cmd::WITHDRAW_FEES)));
+        assert!(!cfg.fusotao.is_provable(&cmd(crate::cmd::BLOCK_BID)));
+        assert!(!cfg.fusotao.is_provable(&cmd(crate::cmd::CANCEL_ALL)));
+        let rebate = crate::input::Command {
+            maker_fee: Some(rust_decimal_macros::dec!(-0.0001)),
+            ..cmd(crate::cmd::UPDATE_SYMBOL)
+        };
+        assert!(!cfg.fusotao.is_provable(&rebate));
+        let share = crate::input::Command {
+            broker_share: Some(rust_decimal_macros::dec!(0.2)),
+            ..cmd(crate::cmd::UPDATE_SYMBOL)
+        };
+        assert!(!cfg.fusotao.is_provable(&share));
+        let stp = crate::input::Command {
+            self_trade_prevention: Some(crate::matcher::SelfTradePrevention::CancelOldest),
+            ..cmd(crate::cmd::BID_LIMIT)
+        };
+        assert!(!cfg.fusotao.is_provable(&stp));
+        assert!(!cfg.fusotao.is_provable(&crate::input::Command {
+            cmd: crate::cmd::UPDATE_SYMBOL,
+            ..stp
+        }));
+        assert!(cfg.fusotao.is_provable(&cmd(crate::cmd::UPDATE_SYMBOL)));
+        let tiers = crate::input::Command {
+            fee_tiers: Some(vec![crate::tiers::FeeTier {
+                currency: 0,
+                volume: rust_decimal_macros::dec!(100),
+                taker_fee: rust_decimal_macros::dec!(0.0008),
+                maker_fee: rust_decimal_macros::dec!(0),
+            }]),
+            ..cmd(crate::cmd::UPDATE_FEE_TIERS)
+        };
+        assert!(!cfg.fusotao.is_provable(&tiers));
+        assert!(cfg.fusotao.is_provable(&cmd(crate::cmd::UPDATE_FEE_TIERS)));
+        let cfg = load_config(
+            EXAMPLE,
+            None,
+            env(&[
+                ("GALOIS_SERVER__BIND_ADDR", "0.0.0.0:8097"),
+                ("GALOIS_SEQUENCE__CHECKPOINT", "1000"),
+                ("GALOIS_LOG__MODULES__PROVER", "debug"),
+                ("GALOIS_FUSOTAO__PROOF_BATCH_WINDOW", "3000"),
+                ("GALOIS_HOME", "/opt/galois"),
+                ("HOME", "/root"),
+            ]),
+        )
+        .unwrap();
+        assert_eq!(cfg.server.bind_addr, "0.0.0.0:8097");
+        assert_eq!(cfg.sequence.checkpoint, 1000);
+        assert_eq!(cfg.log.modules.get("prover").unwrap(), "debug");
+        assert_eq!(cfg.fusotao.proof_batch_window, 3000);
+        let r = load_config(
+            EXAMPLE,
+            None,
+            env(&[("GALOIS_SERVER__BIND_ADDR__PORT", "8097")]),
+        );
+        assert!(matches!(r, Err(ConfigError::Env(..))));
+    }
+
+    #[test]
+    pub fn test_invalid_config() {
+        let unknown = EXAMPLE.replace("checkpoint", "check_point");
+        let r = load_config(&unknown, None, vec![]);
+        assert!(matches!(r, Err(ConfigError::Parse(e)) if e.to_string().contains("check_point")));
+        let missing = EXAMPLE.replace("[sequence]", "[sequences]");
+        assert!(matches!(
+            load_config(&missing, None, vec![]),
+            Err(ConfigError::Parse(_))
+        ));
+        let r = load_config(
+            EXAMPLE,
+            None,
+            env(&[
+                ("GALOIS_SEQUENCE__CHECKPOINT", "0"),
+                ("GALOIS_LOG__LEVEL", "verbose"),
+            ]),
+        );
+        match r {
+            Err(ConfigError::Invalid(errors)) => {
+                assert_eq!(errors.len(), 2);
+                assert!(errors[0].starts_with("sequence.checkpoint"));
+                assert!(errors[1].starts_with("log.level"));
+            }
+            _ => panic!("should be invalid"),
+        }
+        let token = "[[token]]\ncurrency = 1\nsymbol = \"USDT\"\n";
+        let tokens = format!("{}\n{}{}", EXAMPLE, token, token.replace("USDT", " "));
+        match load_config(&tokens, None, vec![]) {
+            Err(ConfigError::Invalid(errors)) => {
+                assert_eq!(errors.len(), 2);
+                assert!(errors[0].starts_with("token[1]: duplicated"));
+                assert!(errors[1].starts_with("token[1].symbol"));
+            }
+            _ => panic!("should be invalid"),
+        }
+        let fallback = EXAMPLE.replace(
+            "# fallback_urls = [\"ws://localhost:9945\"]",
+            "fallback_urls = [\"wss://localhost:9945\", \"http://localhost:9933\"]",
+        );
+        match load_config(&fallback, None, vec![]) {
+            Err(ConfigError::Invalid(errors)) => {
+                assert_eq!(errors.len(), 1);
+                assert!(errors[0].contains("http://localhost:9933"));
+            }
+            _ => panic!("should be invalid"),
+        }
+    }
+
+    #[test]
+    pub fn test_reload_config() {
+        let current = load_config(EXAMPLE, None, vec![]).unwrap();
+        let new = load_config(
+            &EXAMPLE
+                .replace("# [rate_limit]\n# orders", "[rate_limit]\norders")
+                .replace("# fallback_urls", "fallback_urls"),
+            None,
+            env(&[("GALOIS_LOG__LEVEL", "debug")]),
+        )
+        .unwrap();
+        let (merged, changed) = merge_reloadable(&current, new).unwrap();
+        assert_eq!(
+            changed,
+            vec!["log.level", "rate_limit", "fusotao.fallback_urls"]
+        );
+        assert_eq!(merged.log.level, "debug");
+        assert_eq!(merged.rate_limit.unwrap().orders.unwrap().burst, 100);
+        assert_eq!(merged.fusotao.get_node_urls().len(), 2);
+        let (_, changed) = merge_reloadable(&current, current.clone()).unwrap();
+        assert!(changed.is_empty());
+        let new = load_config(
+            EXAMPLE,
+            None,
+            env(&[
+                ("GALOIS_LOG__LEVEL", "debug"),
+                ("GALOIS_SERVER__BIND_ADDR", "0.0.0.0:8097"),
+                ("GALOIS_SEQUENCE__CHECKPOINT", "1000"),
+            ]),
+        )
+        .unwrap();
+        match merge_reloadable(&current, new) {
+            Err(ConfigError::Unreloadable(sections)) => {
+                assert_eq!(sections, vec!["sequence", "server"])
+            }
+            _ => panic!("should be unreloadable"),
+        }
     }
-    Ok(cfg)
 }
@@ -14,21 +14,35 @@
 
 pub use crate::{
     assets::Balance,
+    breaker::Breaker,
     fusotao::GlobalStates,
+    history::ConfigHistory,
     input::InOrOut,
-    matcher::{Role, State as OrderState},
-    orderbook::{AskOrBid, OrderBook},
-    orders::{PendingOrder, UserOrders},
+    matcher::{Execution, Role, State as OrderState},
+    orderbook::{AskOrBid, CircuitBreaker, OrderBook},
+    orders::{FillReport, PendingOrder, UserOrders},
+    tiers::{FeeTier, TradedVolumes},
+    trades::RecentTrades,
 };
-use flate2::{read::ZlibDecoder, write::ZlibEncoder, Compression};
+use crate::{
+    client_orders::ClientOrders,
+    fusotao::prover::{Pipeline, StateDelta},
+    matcher::{Match, SelfTradePrevention},
+    output::{Depth, DepthBook, DepthDelta, DepthSnapshot},
+    prints::BlockPrints,
+    snapshot,
+};
+use flate2::{write::ZlibEncoder, Compression};
 use indexmap::IndexSet;
-use rust_decimal::{prelude::Zero, Decimal};
-use serde::{Deserialize, Serialize};
-use sp_core::ByteArray;
+use rust_decimal::prelude::Zero;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
 use std::{
-    collections::HashMap,
+    collections::{BTreeMap, BTreeSet, HashMap},
     fs::File,
-    io::{BufReader, BufWriter},
+    io::BufWriter,
+    ops::{Deref, DerefMut},
+    str::FromStr,
+    sync::Arc,
 };
 
 lazy_static::lazy_static! {
@@ -37,207 +51,1841 @@ lazy_static::lazy_static! {
     pub static ref OUTPUT_STORE: rocksdb::DB = rocksdb::DB::open_default(&crate::C.server.get_output_path()).unwrap();
 }
 
-pub type Base = u32;
-pub type Quote = u32;
-pub type Price = Decimal;
-pub type Amount = Decimal;
-pub type Vol = Decimal;
-pub type Currency = u32;
-pub type Symbol = (Base, Quote);
-pub type EventId = u64;
-pub type OrderId = u64;
-pub type Fee = Decimal;
-pub type Scale = u32;
-pub type Timestamp = u64;
-pub type Account = HashMap<Currency, Balance>;
-pub type Accounts = HashMap<UserId, Account>;
-pub type UserId = B256;
-
-#[derive(Clone, Copy, Eq, PartialEq, Ord, PartialOrd, Hash, Serialize, Deserialize, Default)]
-pub struct B256(pub [u8; 32]);
-
-impl B256 {
-    pub const fn zero() -> Self {
-        Self([0; 32])
-    }
-
-    pub fn new(x: [u8; 32]) -> Self {
-        Self(x)
-    }
-
-    pub fn from_hex_str(s: &str) -> anyhow::Result<Self> {
-        let hex = s.trim_start_matches("0x");
-        if hex.len() == 64 {
-            let mut bytes = [0u8; 32];
-            hex::decode_to_slice(hex, &mut bytes)
-                .map_err(|_| anyhow::anyhow!("invalid hex string"))
-                .map(|_| Self::from(bytes))
+pub use galois_core::{chunked::ChunkedMap, primitives::*};
+
+pub const MAX_PENDING_ORDERS_PER_USER: usize = 100;
+
+// we only keep the last 1000 transfer_in/out receipts to remove duplicates
+const RECEIPTS_RECORDS_CAPACITY: usize = 1000;
+
+// the sessions are numbered increasingly, the oldest ones are dropped first
+const SESSION_PROGRESS_CAPACITY: usize = 65536;
+
+/// best execution statistics of the taker orders from a broker, orders without broker are
+/// counted under `SYSTEM`; process-local, neither counted in replaying nor kept in the snapshot
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct BrokerExecution {
+    pub taker_orders: u64,
+    pub filled_orders: u64,
+    pub levels: u64,
+    pub base_filled: Amount,
+    pub quote_filled: Amount,
+    pub price_improvement: Amount,
+}
+
+impl BrokerExecution {
+    pub fn merge(&mut self, other: &BrokerExecution) {
+        self.taker_orders += other.taker_orders;
+        self.filled_orders += other.filled_orders;
+        self.levels += other.levels;
+        self.base_filled += other.base_filled;
+        self.quote_filled += other.quote_filled;
+        self.price_improvement += other.price_improvement;
+    }
+}
+
+/// the price of the last maker filled on the book, the block trades are excluded
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Serialize, Deserialize)]
+pub struct LastPrice {
+    pub symbol: Symbol,
+    pub price: Price,
+    pub event_id: u64,
+    pub timestamp: Timestamp,
+}
+
+pub struct Ephemeral {
+    onchain_receipt_records: IndexSet<(u32, UserId)>,
+    // aggregated since the engine started, excluding the replayed events
+    broker_executions: HashMap<UserId, BrokerExecution>,
+    // the sessions placing the resting orders, the private fills are sent to them only
+    order_sessions: HashMap<Symbol, HashMap<OrderId, u64>>,
+    pub config_history: ConfigHistory,
+    // the last published depth of each symbol
+    depths: HashMap<Symbol, DepthBook>,
+    // the prices changed since the depth was published, including the replayed and the system's
+    touched: HashMap<Symbol, BTreeSet<Price>>,
+    pub recent_trades: RecentTrades,
+    // the highest event id of the writes from each living session
+    session_progress: BTreeMap<u64, u64>,
+    // the deltas are proven in order after merging the shards
+    pub deferred_proofs: Option<Vec<StateDelta>>,
+    // the proofs are generated by the pipeline in the executor thread
+    pub prover: Option<Pipeline>,
+    pub block_prints: BlockPrints,
+}
+
+impl Ephemeral {
+    pub fn new() -> Self {
+        Self {
+            onchain_receipt_records: IndexSet::with_capacity(RECEIPTS_RECORDS_CAPACITY),
+            broker_executions: HashMap::new(),
+            order_sessions: HashMap::new(),
+            config_history: ConfigHistory::default(),
+            depths: HashMap::new(),
+            touched: HashMap::new(),
+            recent_trades: RecentTrades::default(),
+            session_progress: BTreeMap::new(),
+            deferred_proofs: None,
+            prover: None,
+            block_prints: BlockPrints::default(),
+        }
+    }
+
+    /// move the depths and trades of the symbols to a shard, the proofs of the shard are deferred
+    pub fn fork(&mut self, symbols: &[Symbol]) -> Self {
+        let depths = symbols
+            .iter()
+            .filter_map(|s| self.depths.remove_entry(s))
+            .collect();
+        let touched = symbols
+            .iter()
+            .filter_map(|s| self.touched.remove_entry(s))
+            .collect();
+        let order_sessions = symbols
+            .iter()
+            .filter_map(|s| self.order_sessions.remove_entry(s))
+            .collect();
+        Self {
+            onchain_receipt_records: IndexSet::new(),
+            broker_executions: HashMap::new(),
+            order_sessions,
+            config_history: ConfigHistory::default(),
+            depths,
+            touched,
+            recent_trades: self.recent_trades.fork(symbols),
+            session_progress: BTreeMap::new(),
+            deferred_proofs: Some(vec![]),
+            prover: None,
+            block_prints: BlockPrints::default(),
+        }
+    }
+
+    pub fn join(&mut self, shard: Self) {
+        self.depths.extend(shard.depths);
+        self.touched.extend(shard.touched);
+        self.order_sessions.extend(shard.order_sessions);
+        self.recent_trades.join(shard.recent_trades);
+        for (broker, execution) in shard.broker_executions.iter() {
+            self.broker_executions
+                .entry(*broker)
+                .or_default()
+                .merge(execution);
+        }
+    }
+
+    pub fn record_execution(
+        &mut self,
+        broker: Option<UserId>,
+        ask_or_bid: AskOrBid,
+        execution: Option<&Execution>,
+    ) {
+        let stat = self
+            .broker_executions
+            .entry(broker.unwrap_or(SYSTEM))
+            .or_default();
+        stat.taker_orders += 1;
+        if let Some(e) = execution {
+            stat.filled_orders += 1;
+            stat.levels += e.levels as u64;
+            stat.base_filled += e.filled;
+            stat.quote_filled += e.average_price * e.filled;
+            stat.price_improvement += e.improvement(ask_or_bid);
+        }
+    }
+
+    pub fn rest_order(&mut self, symbol: Symbol, order_id: OrderId, session: u64) {
+        self.order_sessions
+            .entry(symbol)
+            .or_default()
+            .insert(order_id, session);
+    }
+
+    /// the session placed the order, forgotten once the order is closed; `None` if placed
+    /// before restarting
+    pub fn order_session(
+        &mut self,
+        symbol: Symbol,
+        order_id: OrderId,
+        closed: bool,
+    ) -> Option<u64> {
+        let sessions = self.order_sessions.get_mut(&symbol)?;
+        if closed {
+            sessions.remove(&order_id)
+        } else {
+            sessions.get(&order_id).copied()
+        }
+    }
+
+    pub fn get_broker_execution(&self, broker: &UserId) -> BrokerExecution {
+        self.broker_executions
+            .get(broker)
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    pub fn touch_depth(&mut self, symbol: Symbol, mr: &Match) {
+        self.touched
+            .entry(symbol)
+            .or_default()
+            .extend(mr.page_delta.keys());
+    }
+
+    /// the levels are published from the whole book again, e.g. after the scales changed
+    pub fn reset_depth(&mut self, symbol: &Symbol) {
+        self.depths.remove(symbol);
+        self.touched.remove(symbol);
+    }
+
+    /// the changed levels since the last update of the symbol, `None` if nothing changed, a full
+    /// snapshot if never published
+    pub fn update_depth(
+        &mut self,
+        update_id: u64,
+        symbol: Symbol,
+        orderbook: &OrderBook,
+    ) -> Option<DepthDelta> {
+        let touched = self.touched.remove(&symbol).unwrap_or_default();
+        match self.depths.get_mut(&symbol) {
+            Some(book) => Some(book.patch(update_id, symbol, orderbook, &touched))
+                .filter(|delta| !delta.is_empty()),
+            None => {
+                let book = DepthBook::new(update_id, &Depth::from((symbol, orderbook)));
+                let delta = DepthDelta::snapshot(&book.snapshot(symbol, None));
+                self.depths.insert(symbol, book);
+                Some(delta)
+            }
+        }
+    }
+
+    /// the best `limit` levels last published
+    pub fn get_depth(&self, symbol: &Symbol, limit: Option<usize>) -> Option<DepthSnapshot> {
+        self.depths.get(symbol).map(|b| b.snapshot(*symbol, limit))
+    }
+
+    pub fn save_receipt(&mut self, id: (u32, UserId)) -> bool {
+        if self.onchain_receipt_records.len() >= RECEIPTS_RECORDS_CAPACITY {
+            self.onchain_receipt_records.pop();
+        }
+        self.onchain_receipt_records.insert(id)
+    }
+
+    /// the event is sequenced and executed no matter accepted or rejected
+    pub fn ack(&mut self, session: u64, event_id: u64) {
+        let progress = self.session_progress.entry(session).or_default();
+        *progress = (*progress).max(event_id);
+        if self.session_progress.len() > SESSION_PROGRESS_CAPACITY {
+            self.session_progress.pop_first();
+        }
+    }
+
+    pub fn get_session_progress(&self, session: u64) -> Option<u64> {
+        self.session_progress.get(&session).copied()
+    }
+}
+
+/// shared by the executor and the dumping snapshots, the value is cloned on the first mutation
+/// after being shared so a dump doesn't copy anything on the executor thread, serialized as `T`;
+/// the balances, pending orders and volumes are `ChunkedMap`s so the clone copies one chunk only
+#[derive(Debug, Default, PartialEq)]
+pub struct CopyOnWrite<T>(Arc<T>);
+
+impl<T> Clone for CopyOnWrite<T> {
+    fn clone(&self) -> Self {
+        Self(self.0.clone())
+    }
+}
+
+impl<T> CopyOnWrite<T> {
+    pub fn new(v: T) -> Self {
+        Self(Arc::new(v))
+    }
+
+    pub fn is_shared(&self) -> bool {
+        Arc::strong_count(&self.0) > 1
+    }
+}
+
+impl<T: Clone> CopyOnWrite<T> {
+    pub fn into_inner(self) -> T {
+        Arc::try_unwrap(self.0).unwrap_or_else(|v| (*v).clone())
+    }
+}
+
+impl<T> From<T> for CopyOnWrite<T> {
+    fn from(v: T) -> Self {
+        Self::new(v)
+    }
+}
+
+impl<T> Deref for CopyOnWrite<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.0
+    }
+}
+
+impl<T: Clone> DerefMut for CopyOnWrite<T> {
+    fn deref_mut(&mut self) -> &mut T {
+        Arc::make_mut(&mut self.0)
+    }
+}
+
+impl<T: Serialize> Serialize for CopyOnWrite<T> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.0.serialize(serializer)
+    }
+}
+
+impl<'de, T: Deserialize<'de>> Deserialize<'de> for CopyOnWrite<T> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        T::deserialize(deserializer).map(Self::new)
+    }
+}
+
+/// the operations allowed of a currency, e.g. suspending the deposits of a compromised token
+/// while the users are still able to exit their positions, cancelling is always allowed
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CurrencyMode {
+    #[default]
+    Normal,
+    TradeOnly,
+    WithdrawOnly,
+    Frozen,
+}
+
+impl CurrencyMode {
+    pub fn is_tradable(&self) -> bool {
+        matches!(self, CurrencyMode::Normal | CurrencyMode::TradeOnly)
+    }
+
+    pub fn is_withdrawable(&self) -> bool {
+        matches!(self, CurrencyMode::Normal | CurrencyMode::WithdrawOnly)
+    }
+
+    pub fn is_depositable(&self) -> bool {
+        *self == CurrencyMode::Normal
+    }
+}
+
+impl FromStr for CurrencyMode {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        match s {
+            "normal" => Ok(CurrencyMode::Normal),
+            "trade_only" => Ok(CurrencyMode::TradeOnly),
+            "withdraw_only" => Ok(CurrencyMode::WithdrawOnly),
+            "frozen" => Ok(CurrencyMode::Frozen),
+            _ => Err(anyhow::anyhow!("unknown currency mode {}", s)),
+        }
+    }
+}
+
+/// the orderbooks are shared one by one so the idle ones are never cloned after dumping
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Data {
+    pub orderbooks: HashMap<Symbol, CopyOnWrite<OrderBook>>,
+    pub accounts: CopyOnWrite<Accounts>,
+    pub merkle_tree: CopyOnWrite<GlobalStates>,
+    pub current_event_id: u64,
+    pub tvl: Amount,
+    pub orders: CopyOnWrite<UserOrders>,
+    pub last_prices: HashMap<Symbol, LastPrice>,
+    /// the currencies not in `CurrencyMode::Normal`
+    pub currencies: BTreeMap<Currency, CurrencyMode>,
+    pub volumes: CopyOnWrite<TradedVolumes>,
+    /// the legs of the OCO pairs resting on the books, linked in both directions
+    pub links: HashMap<Symbol, HashMap<OrderId, OrderId>>,
+    /// the circuit breakers of the symbols traded or halted
+    pub breakers: HashMap<Symbol, Breaker>,
+    /// the client order ids recently used by the users of the symbols
+    pub client_orders: ClientOrders,
+    /// the fees overridden by the traded volumes, replaced by `UPDATE_FEE_TIERS`
+    pub fee_tiers: Vec<FeeTier>,
+    /// the max frozen balance of each user in the currencies, set by `UPDATE_CURRENCY`
+    pub frozen_limits: BTreeMap<Currency, Amount>,
+}
+
+impl Data {
+    pub fn new() -> Self {
+        Self {
+            orderbooks: HashMap::new(),
+            accounts: Default::default(),
+            merkle_tree: Default::default(),
+            current_event_id: 0,
+            tvl: Amount::zero(),
+            orders: UserOrders::new().into(),
+            last_prices: HashMap::new(),
+            currencies: BTreeMap::new(),
+            volumes: TradedVolumes::new().into(),
+            links: HashMap::new(),
+            breakers: HashMap::new(),
+            client_orders: HashMap::new(),
+            fee_tiers: Vec::new(),
+            frozen_limits: BTreeMap::new(),
+        }
+    }
+
+    /// the snapshots dumped before tracking the last prices, the currency modes, the icebergs, the
+    /// brokers, the traded volumes, the OCO links, the circuit breakers, the price bands, the client
+    /// order ids, the self-trade prevention, the max open notional of the symbols, the fee tiers, the
+    /// risk limits, the trading hours or the block trade limits of the symbols are loaded as well
+    pub fn from_raw(file: File) -> anyhow::Result<Self> {
+        snapshot::read(file).map(|(_, data)| data)
+    }
+
+    /// deserialize the layout of `version`, the former ones are migrated to the current
+    pub fn from_version(version: u32, raw: &[u8]) -> bincode::Result<Self> {
+        let data: bincode::Result<Self> = match version {
+            snapshot::VERSION => bincode::deserialize(raw),
+            17 => bincode::deserialize::<v17::DataV17>(raw).map(|v17| v17.into()),
+            16 => bincode::deserialize::<v16::DataV16>(raw).map(|v16| v16.into()),
+            15 => bincode::deserialize::<v15::DataV15>(raw).map(|v15| v15.into()),
+            14 => bincode::deserialize::<v14::DataV14>(raw).map(|v14| v14.into()),
+            13 => bincode::deserialize::<v13::DataV13>(raw).map(|v13| v13.into()),
+            12 => bincode::deserialize::<v12::DataV12>(raw).map(|v12| v12.into()),
+            11 => bincode::deserialize::<v11::DataV11>(raw).map(|v11| v11.into()),
+            10 => bincode::deserialize::<v10::DataV10>(raw).map(|v10| v10.into()),
+            9 => bincode::deserialize::<v9::DataV9>(raw).map(|v9| v9.into()),
+            8 => bincode::deserialize::<v8::DataV8>(raw).map(|v8| v8.into()),
+            7 => bincode::deserialize::<v7::DataV7>(raw).map(|v7| v7.into()),
+            6 => bincode::deserialize::<v6::DataV6>(raw).map(|v6| v6.into()),
+            5 => bincode::deserialize::<v5::DataV5>(raw).map(|v5| v5.into()),
+            4 => bincode::deserialize::<v4::DataV4>(raw).map(|v4| v4.into()),
+            3 => bincode::deserialize::<v3::DataV3>(raw).map(|v3| v3.into()),
+            2 => bincode::deserialize::<v2::DataV2>(raw).map(|v2| v2.into()),
+            _ => Err(Box::new(bincode::ErrorKind::Custom(format!(
+                "unknown layout v{}",
+                version
+            )))),
+        };
+        // the running open notional isn't dumped
+        data.map(|mut data| {
+            data.orderbooks
+                .values_mut()
+                .for_each(|book| book.rebuild_notional());
+            data
+        })
+    }
+
+    pub fn currency_mode(&self, currency: Currency) -> CurrencyMode {
+        self.currencies.get(&currency).copied().unwrap_or_default()
+    }
+
+    pub fn set_currency_mode(&mut self, currency: Currency, mode: CurrencyMode) {
+        match mode {
+            CurrencyMode::Normal => self.currencies.remove(&currency),
+            _ => self.currencies.insert(currency, mode),
+        };
+    }
+
+    /// zero to unlimit the frozen balances of the currency
+    pub fn set_frozen_limit(&mut self, currency: Currency, max_frozen: Amount) {
+        if max_frozen.is_zero() {
+            self.frozen_limits.remove(&currency);
         } else {
-            Err(anyhow::anyhow!("invalid hex string"))
+            self.frozen_limits.insert(currency, max_frozen);
+        }
+    }
+
+    pub fn is_tradable(&self, symbol: &Symbol) -> bool {
+        self.currency_mode(symbol.0).is_tradable() && self.currency_mode(symbol.1).is_tradable()
+    }
+
+    pub fn last_price(&self, symbol: &Symbol) -> Option<Price> {
+        self.last_prices.get(symbol).map(|p| p.price)
+    }
+
+    /// move the orderbooks of the symbols with the orders on them to a shard and copy the
+    /// balances claimed by it, the merkle tree of the shard is empty since the proofs are deferred
+    pub fn fork(&mut self, symbols: &[Symbol], balances: &[(UserId, Currency)]) -> Self {
+        let mut shard = Data::new();
+        shard.current_event_id = self.current_event_id;
+        shard.tvl = self.tvl;
+        shard.currencies = self.currencies.clone();
+        let (mut accounts, mut orders) = (Accounts::new(), UserOrders::new());
+        for symbol in symbols {
+            if let Some(orderbook) = self.orderbooks.remove(symbol) {
+                shard.orderbooks.insert(*symbol, orderbook);
+            }
+            if let Some(price) = self.last_prices.remove(symbol) {
+                shard.last_prices.insert(*symbol, price);
+            }
+            if let Some(links) = self.links.remove(symbol) {
+                shard.links.insert(*symbol, links);
+            }
+            if let Some(breaker) = self.breakers.remove(symbol) {
+                shard.breakers.insert(*symbol, breaker);
+            }
+            if let Some(ids) = self.client_orders.remove(symbol) {
+                shard.client_orders.insert(*symbol, ids);
+            }
+        }
+        for (user_id, currency) in balances {
+            if let Some(balance) = self.accounts.get(user_id).and_then(|a| a.get(currency)) {
+                accounts
+                    .entry(*user_id)
+                    .or_default()
+                    .insert(*currency, balance.clone());
+            }
+            for symbol in symbols
+                .iter()
+                .filter(|s| s.0 == *currency || s.1 == *currency)
+            {
+                if let Some(o) = self.orders.orders.remove(&(*user_id, *symbol)) {
+                    orders.orders.insert((*user_id, *symbol), o);
+                }
+            }
+        }
+        shard.accounts = accounts.into();
+        shard.orders = orders.into();
+        shard.volumes = self.volumes.split_off(balances).into();
+        shard
+    }
+
+    /// the users of shards are disjoint except `SYSTEM`, whose fees are added
+    pub fn join(&mut self, shard: Self) {
+        self.current_event_id = self.current_event_id.max(shard.current_event_id);
+        self.orderbooks.extend(shard.orderbooks);
+        self.last_prices.extend(shard.last_prices);
+        self.links.extend(shard.links);
+        self.breakers.extend(shard.breakers);
+        self.client_orders.extend(shard.client_orders);
+        for (user_id, account) in shard.accounts.into_inner() {
+            let to = self.accounts.entry(user_id).or_default();
+            for (currency, balance) in account {
+                if user_id == SYSTEM {
+                    to.entry(currency).or_default().available += balance.available;
+                } else {
+                    to.insert(currency, balance);
+                }
+            }
+        }
+        self.orders.orders.extend(shard.orders.into_inner().orders);
+        self.volumes.extend(shard.volumes.into_inner());
+    }
+
+    pub fn into_raw(&self, file: File) -> anyhow::Result<()> {
+        let writer = BufWriter::new(file);
+        let mut compress = ZlibEncoder::new(writer, Compression::best());
+        snapshot::encode(self, &mut compress)?;
+        compress.finish()?;
+        Ok(())
+    }
+}
+
+mod v2 {
+    use super::*;
+
+    #[derive(Deserialize)]
+    pub struct DataV2 {
+        pub orderbooks: HashMap<Symbol, v4::OrderBookV4>,
+        pub accounts: Accounts,
+        pub merkle_tree: GlobalStates,
+        pub current_event_id: u64,
+        pub tvl: Amount,
+        pub orders: UserOrders,
+    }
+
+    impl From<DataV2> for Data {
+        fn from(data: DataV2) -> Data {
+            Data {
+                orderbooks: data
+                    .orderbooks
+                    .into_iter()
+                    .map(|(k, v)| (k, OrderBook::from(v).into()))
+                    .collect(),
+                accounts: data.accounts.into(),
+                merkle_tree: data.merkle_tree.into(),
+                current_event_id: data.current_event_id,
+                tvl: data.tvl,
+                orders: data.orders.into(),
+                last_prices: HashMap::new(),
+                currencies: BTreeMap::new(),
+                volumes: Default::default(),
+                links: HashMap::new(),
+                breakers: HashMap::new(),
+                client_orders: HashMap::new(),
+                fee_tiers: Vec::new(),
+                frozen_limits: BTreeMap::new(),
+            }
+        }
+    }
+}
+
+mod v3 {
+    use super::*;
+
+    #[derive(Deserialize)]
+    pub struct DataV3 {
+        pub orderbooks: HashMap<Symbol, v4::OrderBookV4>,
+        pub accounts: CopyOnWrite<Accounts>,
+        pub merkle_tree: CopyOnWrite<GlobalStates>,
+        pub current_event_id: u64,
+        pub tvl: Amount,
+        pub orders: CopyOnWrite<UserOrders>,
+        pub last_prices: HashMap<Symbol, LastPrice>,
+    }
+
+    impl From<DataV3> for Data {
+        fn from(data: DataV3) -> Data {
+            Data {
+                orderbooks: data
+                    .orderbooks
+                    .into_iter()
+                    .map(|(k, v)| (k, OrderBook::from(v).into()))
+                    .collect(),
+                accounts: data.accounts,
+                merkle_tree: data.merkle_tree,
+                current_event_id: data.current_event_id,
+                tvl: data.tvl,
+                orders: data.orders,
+                last_prices: data.last_prices,
+                currencies: BTreeMap::new(),
+                volumes: Default::default(),
+                links: HashMap::new(),
+                breakers: HashMap::new(),
+                client_orders: HashMap::new(),
+                fee_tiers: Vec::new(),
+                frozen_limits: BTreeMap::new(),
+            }
+        }
+    }
+}
+
+mod v4 {
+    use super::*;
+    use galois_core::orderbook::{Index, Order, OrderPage, Tape};
+
+    #[derive(Clone, Serialize, Deserialize)]
+    pub struct OrderV4 {
+        pub id: OrderId,
+        pub user: UserId,
+        pub price: Price,
+        pub unfilled: Amount,
+    }
+
+    /// the `LinkedHashMap` is encoded as the sequence of its entries
+    #[derive(Clone, Serialize, Deserialize)]
+    pub struct OrderPageV4 {
+        pub orders: Vec<(OrderId, OrderV4)>,
+        pub amount: Amount,
+        pub price: Price,
+    }
+
+    #[derive(Clone, Serialize, Deserialize)]
+    pub struct OrderBookV4 {
+        pub asks: BTreeMap<Price, OrderPageV4>,
+        pub bids: BTreeMap<Price, OrderPageV4>,
+        pub indices: Index,
+        pub base_scale: u32,
+        pub quote_scale: u32,
+        pub taker_fee: Fee,
+        pub maker_fee: Fee,
+        pub base_taker_fee: Fee,
+        pub base_maker_fee: Fee,
+        pub fee_times: u32,
+        pub min_amount: Amount,
+        pub min_vol: Amount,
+        pub enable_market_order: bool,
+        pub open: bool,
+        pub max_id: OrderId,
+    }
+
+    fn into_tape(pages: BTreeMap<Price, OrderPageV4>) -> Tape {
+        pages
+            .into_iter()
+            .map(|(price, page)| {
+                let orders = page
+                    .orders
+                    .into_iter()
+                    .map(|(id, o)| (id, Order::new(o.id, o.user, o.price, o.unfilled)))
+                    .collect();
+                let page = OrderPage {
+                    orders,
+                    amount: page.amount,
+                    price: page.price,
+                    hidden: Amount::zero(),
+                };
+                (price, page)
+            })
+            .collect()
+    }
+
+    impl From<OrderBookV4> for OrderBook {
+        fn from(book: OrderBookV4) -> OrderBook {
+            OrderBook {
+                asks: into_tape(book.asks),
+                bids: into_tape(book.bids),
+                indices: book.indices,
+                base_scale: book.base_scale,
+                quote_scale: book.quote_scale,
+                taker_fee: book.taker_fee,
+                maker_fee: book.maker_fee,
+                base_taker_fee: book.base_taker_fee,
+                base_maker_fee: book.base_maker_fee,
+                fee_times: book.fee_times,
+                broker_share: Fee::zero(),
+                tick_size: Price::zero(),
+                lot_size: Amount::zero(),
+                price_band: Fee::zero(),
+                self_trade_prevention: SelfTradePrevention::default(),
+                max_open_notional: Vol::zero(),
+                user_notional: HashMap::new(),
+                total_notional: Vol::zero(),
+                max_open_orders: 0,
+                trading_hours: Vec::new(),
+                circuit_breaker: CircuitBreaker::default(),
+                block_trade_min_amount: Amount::zero(),
+                block_trade_report_delay: 0,
+                min_amount: book.min_amount,
+                min_vol: book.min_vol,
+                enable_market_order: book.enable_market_order,
+                open: book.open,
+                max_id: book.max_id,
+            }
+        }
+    }
+
+    #[derive(Deserialize)]
+    pub struct DataV4 {
+        pub orderbooks: HashMap<Symbol, OrderBookV4>,
+        pub accounts: CopyOnWrite<Accounts>,
+        pub merkle_tree: CopyOnWrite<GlobalStates>,
+        pub current_event_id: u64,
+        pub tvl: Amount,
+        pub orders: CopyOnWrite<UserOrders>,
+        pub last_prices: HashMap<Symbol, LastPrice>,
+        pub currencies: BTreeMap<Currency, CurrencyMode>,
+    }
+
+    impl From<DataV4> for Data {
+        fn from(data: DataV4) -> Data {
+            Data {
+                orderbooks: data
+                    .orderbooks
+                    .into_iter()
+                    .map(|(k, v)| (k, OrderBook::from(v).into()))
+                    .collect(),
+                accounts: data.accounts,
+                merkle_tree: data.merkle_tree,
+                current_event_id: data.current_event_id,
+                tvl: data.tvl,
+                orders: data.orders,
+                last_prices: data.last_prices,
+                currencies: data.currencies,
+                volumes: Default::default(),
+                links: HashMap::new(),
+                breakers: HashMap::new(),
+                client_orders: HashMap::new(),
+                fee_tiers: Vec::new(),
+                frozen_limits: BTreeMap::new(),
+            }
+        }
+    }
+}
+
+mod v5 {
+    use super::*;
+    use galois_core::orderbook::{Index, Order, OrderPage, Tape};
+
+    #[derive(Clone, Serialize, Deserialize)]
+    pub struct OrderV5 {
+        pub id: OrderId,
+        pub user: UserId,
+        pub price: Price,
+        pub unfilled: Amount,
+        pub display: Amount,
+        pub hidden: Amount,
+    }
+
+    #[derive(Clone, Serialize, Deserialize)]
+    pub struct OrderPageV5 {
+        pub orders: Vec<(OrderId, OrderV5)>,
+        pub amount: Amount,
+        pub price: Price,
+        pub hidden: Amount,
+    }
+
+    #[derive(Clone, Serialize, Deserialize)]
+    pub struct OrderBookV5 {
+        pub asks: BTreeMap<Price, OrderPageV5>,
+        pub bids: BTreeMap<Price, OrderPageV5>,
+        pub indices: Index,
+        pub base_scale: u32,
+        pub quote_scale: u32,
+        pub taker_fee: Fee,
+        pub maker_fee: Fee,
+        pub base_taker_fee: Fee,
+        pub base_maker_fee: Fee,
+        pub fee_times: u32,
+        pub min_amount: Amount,
+        pub min_vol: Amount,
+        pub enable_market_order: bool,
+        pub open: bool,
+        pub max_id: OrderId,
+    }
+
+    fn into_tape(pages: BTreeMap<Price, OrderPageV5>) -> Tape {
+        pages
+            .into_iter()
+            .map(|(price, page)| {
+                let orders = page
+                    .orders
+                    .into_iter()
+                    .map(|(id, o)| {
+                        let mut order = Order::new(o.id, o.user, o.price, o.unfilled);
+                        order.display = o.display;
+                        order.hidden = o.hidden;
+                        (id, order)
+                    })
+                    .collect();
+                let page = OrderPage {
+                    orders,
+                    amount: page.amount,
+                    price: page.price,
+                    hidden: page.hidden,
+                };
+                (price, page)
+            })
+            .collect()
+    }
+
+    impl From<OrderBookV5> for OrderBook {
+        fn from(book: OrderBookV5) -> OrderBook {
+            OrderBook {
+                asks: into_tape(book.asks),
+                bids: into_tape(book.bids),
+                indices: book.indices,
+                base_scale: book.base_scale,
+                quote_scale: book.quote_scale,
+                taker_fee: book.taker_fee,
+                maker_fee: book.maker_fee,
+                base_taker_fee: book.base_taker_fee,
+                base_maker_fee: book.base_maker_fee,
+                fee_times: book.fee_times,
+                broker_share: Fee::zero(),
+                tick_size: Price::zero(),
+                lot_size: Amount::zero(),
+                price_band: Fee::zero(),
+                self_trade_prevention: SelfTradePrevention::default(),
+                max_open_notional: Vol::zero(),
+                user_notional: HashMap::new(),
+                total_notional: Vol::zero(),
+                max_open_orders: 0,
+                trading_hours: Vec::new(),
+                circuit_breaker: CircuitBreaker::default(),
+                block_trade_min_amount: Amount::zero(),
+                block_trade_report_delay: 0,
+                min_amount: book.min_amount,
+                min_vol: book.min_vol,
+                enable_market_order: book.enable_market_order,
+                open: book.open,
+                max_id: book.max_id,
+            }
+        }
+    }
+
+    #[derive(Deserialize)]
+    pub struct DataV5 {
+        pub orderbooks: HashMap<Symbol, OrderBookV5>,
+        pub accounts: CopyOnWrite<Accounts>,
+        pub merkle_tree: CopyOnWrite<GlobalStates>,
+        pub current_event_id: u64,
+        pub tvl: Amount,
+        pub orders: CopyOnWrite<UserOrders>,
+        pub last_prices: HashMap<Symbol, LastPrice>,
+        pub currencies: BTreeMap<Currency, CurrencyMode>,
+    }
+
+    impl From<DataV5> for Data {
+        fn from(data: DataV5) -> Data {
+            Data {
+                orderbooks: data
+                    .orderbooks
+                    .into_iter()
+                    .map(|(k, v)| (k, OrderBook::from(v).into()))
+                    .collect(),
+                accounts: data.accounts,
+                merkle_tree: data.merkle_tree,
+                current_event_id: data.current_event_id,
+                tvl: data.tvl,
+                orders: data.orders,
+                last_prices: data.last_prices,
+                currencies: data.currencies,
+                volumes: Default::default(),
+                links: HashMap::new(),
+                breakers: HashMap::new(),
+                client_orders: HashMap::new(),
+                fee_tiers: Vec::new(),
+                frozen_limits: BTreeMap::new(),
+            }
+        }
+    }
+}
+
+mod v6 {
+    use super::*;
+
+    #[derive(Deserialize)]
+    pub struct DataV6 {
+        pub orderbooks: HashMap<Symbol, v7::OrderBookV7>,
+        pub accounts: CopyOnWrite<Accounts>,
+        pub merkle_tree: CopyOnWrite<GlobalStates>,
+        pub current_event_id: u64,
+        pub tvl: Amount,
+        pub orders: CopyOnWrite<UserOrders>,
+        pub last_prices: HashMap<Symbol, LastPrice>,
+        pub currencies: BTreeMap<Currency, CurrencyMode>,
+    }
+
+    impl From<DataV6> for Data {
+        fn from(data: DataV6) -> Data {
+            Data {
+                orderbooks: data
+                    .orderbooks
+                    .into_iter()
+                    .map(|(k, v)| (k, OrderBook::from(v).into()))
+                    .collect(),
+                accounts: data.accounts,
+                merkle_tree: data.merkle_tree,
+                current_event_id: data.current_event_id,
+                tvl: data.tvl,
+                orders: data.orders,
+                last_prices: data.last_prices,
+                currencies: data.currencies,
+                volumes: Default::default(),
+                links: HashMap::new(),
+                breakers: HashMap::new(),
+                client_orders: HashMap::new(),
+                fee_tiers: Vec::new(),
+                frozen_limits: BTreeMap::new(),
+            }
+        }
+    }
+}
+
+mod v7 {
+    use super::*;
+    use galois_core::orderbook::{Index, Tape};
+
+    #[derive(Clone, Serialize, Deserialize)]
+    pub struct OrderBookV7 {
+        pub asks: Tape,
+        pub bids: Tape,
+        pub indices: Index,
+        pub base_scale: u32,
+        pub quote_scale: u32,
+        pub taker_fee: Fee,
+        pub maker_fee: Fee,
+        pub base_taker_fee: Fee,
+        pub base_maker_fee: Fee,
+        pub fee_times: u32,
+        pub broker_share: Fee,
+        pub min_amount: Amount,
+        pub min_vol: Amount,
+        pub enable_market_order: bool,
+        pub open: bool,
+        pub max_id: OrderId,
+    }
+
+    impl From<OrderBookV7> for OrderBook {
+        fn from(book: OrderBookV7) -> OrderBook {
+            OrderBook {
+                asks: book.asks,
+                bids: book.bids,
+                indices: book.indices,
+                base_scale: book.base_scale,
+                quote_scale: book.quote_scale,
+                taker_fee: book.taker_fee,
+                maker_fee: book.maker_fee,
+                base_taker_fee: book.base_taker_fee,
+                base_maker_fee: book.base_maker_fee,
+                fee_times: book.fee_times,
+                broker_share: book.broker_share,
+                tick_size: Price::zero(),
+                lot_size: Amount::zero(),
+                price_band: Fee::zero(),
+                self_trade_prevention: SelfTradePrevention::default(),
+                max_open_notional: Vol::zero(),
+                user_notional: HashMap::new(),
+                total_notional: Vol::zero(),
+                max_open_orders: 0,
+                trading_hours: Vec::new(),
+                circuit_breaker: CircuitBreaker::default(),
+                block_trade_min_amount: Amount::zero(),
+                block_trade_report_delay: 0,
+                min_amount: book.min_amount,
+                min_vol: book.min_vol,
+                enable_market_order: book.enable_market_order,
+                open: book.open,
+                max_id: book.max_id,
+            }
+        }
+    }
+
+    #[derive(Deserialize)]
+    pub struct DataV7 {
+        pub orderbooks: HashMap<Symbol, OrderBookV7>,
+        pub accounts: CopyOnWrite<Accounts>,
+        pub merkle_tree: CopyOnWrite<GlobalStates>,
+        pub current_event_id: u64,
+        pub tvl: Amount,
+        pub orders: CopyOnWrite<UserOrders>,
+        pub last_prices: HashMap<Symbol, LastPrice>,
+        pub currencies: BTreeMap<Currency, CurrencyMode>,
+        pub volumes: CopyOnWrite<TradedVolumes>,
+    }
+
+    impl From<DataV7> for Data {
+        fn from(data: DataV7) -> Data {
+            Data {
+                orderbooks: data
+                    .orderbooks
+                    .into_iter()
+                    .map(|(k, v)| (k, OrderBook::from(v).into()))
+                    .collect(),
+                accounts: data.accounts,
+                merkle_tree: data.merkle_tree,
+                current_event_id: data.current_event_id,
+                tvl: data.tvl,
+                orders: data.orders,
+                last_prices: data.last_prices,
+                currencies: data.currencies,
+                volumes: data.volumes,
+                links: HashMap::new(),
+                breakers: HashMap::new(),
+                client_orders: HashMap::new(),
+                fee_tiers: Vec::new(),
+                frozen_limits: BTreeMap::new(),
+            }
+        }
+    }
+}
+
+mod v8 {
+    use super::*;
+
+    #[derive(Deserialize)]
+    pub struct DataV8 {
+        pub orderbooks: HashMap<Symbol, v10::OrderBookV10>,
+        pub accounts: CopyOnWrite<Accounts>,
+        pub merkle_tree: CopyOnWrite<GlobalStates>,
+        pub current_event_id: u64,
+        pub tvl: Amount,
+        pub orders: CopyOnWrite<UserOrders>,
+        pub last_prices: HashMap<Symbol, LastPrice>,
+        pub currencies: BTreeMap<Currency, CurrencyMode>,
+        pub volumes: CopyOnWrite<TradedVolumes>,
+    }
+
+    impl From<DataV8> for Data {
+        fn from(data: DataV8) -> Data {
+            Data {
+                orderbooks: data
+                    .orderbooks
+                    .into_iter()
+                    .map(|(k, v)| (k, OrderBook::from(v).into()))
+                    .collect(),
+                accounts: data.accounts,
+                merkle_tree: data.merkle_tree,
+                current_event_id: data.current_event_id,
+                tvl: data.tvl,
+                orders: data.orders,
+                last_prices: data.last_prices,
+                currencies: data.currencies,
+                volumes: data.volumes,
+                links: HashMap::new(),
+                breakers: HashMap::new(),
+                client_orders: HashMap::new(),
+                fee_tiers: Vec::new(),
+                frozen_limits: BTreeMap::new(),
+            }
+        }
+    }
+}
+
+mod v9 {
+    use super::*;
+
+    #[derive(Deserialize)]
+    pub struct DataV9 {
+        pub orderbooks: HashMap<Symbol, v10::OrderBookV10>,
+        pub accounts: CopyOnWrite<Accounts>,
+        pub merkle_tree: CopyOnWrite<GlobalStates>,
+        pub current_event_id: u64,
+        pub tvl: Amount,
+        pub orders: CopyOnWrite<UserOrders>,
+        pub last_prices: HashMap<Symbol, LastPrice>,
+        pub currencies: BTreeMap<Currency, CurrencyMode>,
+        pub volumes: CopyOnWrite<TradedVolumes>,
+        pub links: HashMap<Symbol, HashMap<OrderId, OrderId>>,
+    }
+
+    impl From<DataV9> for Data {
+        fn from(data: DataV9) -> Data {
+            Data {
+                orderbooks: data
+                    .orderbooks
+                    .into_iter()
+                    .map(|(k, v)| (k, OrderBook::from(v).into()))
+                    .collect(),
+                accounts: data.accounts,
+                merkle_tree: data.merkle_tree,
+                current_event_id: data.current_event_id,
+                tvl: data.tvl,
+                orders: data.orders,
+                last_prices: data.last_prices,
+                currencies: data.currencies,
+                volumes: data.volumes,
+                links: data.links,
+                breakers: HashMap::new(),
+                client_orders: HashMap::new(),
+                fee_tiers: Vec::new(),
+                frozen_limits: BTreeMap::new(),
+            }
         }
     }
 }
 
-impl std::ops::Deref for B256 {
-    type Target = [u8; 32];
+mod v10 {
+    use super::*;
+    use galois_core::orderbook::{Index, Tape};
 
-    fn deref(&self) -> &Self::Target {
-        &self.0
+    #[derive(Clone, Serialize, Deserialize)]
+    pub struct OrderBookV10 {
+        pub asks: Tape,
+        pub bids: Tape,
+        pub indices: Index,
+        pub base_scale: u32,
+        pub quote_scale: u32,
+        pub taker_fee: Fee,
+        pub maker_fee: Fee,
+        pub base_taker_fee: Fee,
+        pub base_maker_fee: Fee,
+        pub fee_times: u32,
+        pub broker_share: Fee,
+        pub tick_size: Price,
+        pub lot_size: Amount,
+        pub min_amount: Amount,
+        pub min_vol: Amount,
+        pub enable_market_order: bool,
+        pub open: bool,
+        pub max_id: OrderId,
     }
-}
 
-impl std::str::FromStr for B256 {
-    type Err = anyhow::Error;
+    impl From<OrderBookV10> for OrderBook {
+        fn from(book: OrderBookV10) -> OrderBook {
+            OrderBook {
+                asks: book.asks,
+                bids: book.bids,
+                indices: book.indices,
+                base_scale: book.base_scale,
+                quote_scale: book.quote_scale,
+                taker_fee: book.taker_fee,
+                maker_fee: book.maker_fee,
+                base_taker_fee: book.base_taker_fee,
+                base_maker_fee: book.base_maker_fee,
+                fee_times: book.fee_times,
+                broker_share: book.broker_share,
+                tick_size: book.tick_size,
+                lot_size: book.lot_size,
+                price_band: Fee::zero(),
+                self_trade_prevention: SelfTradePrevention::default(),
+                max_open_notional: Vol::zero(),
+                user_notional: HashMap::new(),
+                total_notional: Vol::zero(),
+                max_open_orders: 0,
+                trading_hours: Vec::new(),
+                circuit_breaker: CircuitBreaker::default(),
+                block_trade_min_amount: Amount::zero(),
+                block_trade_report_delay: 0,
+                min_amount: book.min_amount,
+                min_vol: book.min_vol,
+                enable_market_order: book.enable_market_order,
+                open: book.open,
+                max_id: book.max_id,
+            }
+        }
+    }
 
-    fn from_str(s: &str) -> anyhow::Result<Self> {
-        if s.starts_with("0x") {
-            Self::from_hex_str(s)
-        } else {
-            use sp_core::crypto::Ss58Codec;
-            Self::from_ss58check(s).map_err(|_| anyhow::anyhow!("invalid ss58 format"))
+    #[derive(Deserialize)]
+    pub struct DataV10 {
+        pub orderbooks: HashMap<Symbol, OrderBookV10>,
+        pub accounts: CopyOnWrite<Accounts>,
+        pub merkle_tree: CopyOnWrite<GlobalStates>,
+        pub current_event_id: u64,
+        pub tvl: Amount,
+        pub orders: CopyOnWrite<UserOrders>,
+        pub last_prices: HashMap<Symbol, LastPrice>,
+        pub currencies: BTreeMap<Currency, CurrencyMode>,
+        pub volumes: CopyOnWrite<TradedVolumes>,
+        pub links: HashMap<Symbol, HashMap<OrderId, OrderId>>,
+        pub breakers: HashMap<Symbol, Breaker>,
+    }
+
+    impl From<DataV10> for Data {
+        fn from(data: DataV10) -> Data {
+            Data {
+                orderbooks: data
+                    .orderbooks
+                    .into_iter()
+                    .map(|(k, v)| (k, OrderBook::from(v).into()))
+                    .collect(),
+                accounts: data.accounts,
+                merkle_tree: data.merkle_tree,
+                current_event_id: data.current_event_id,
+                tvl: data.tvl,
+                orders: data.orders,
+                last_prices: data.last_prices,
+                currencies: data.currencies,
+                volumes: data.volumes,
+                links: data.links,
+                breakers: data.breakers,
+                client_orders: HashMap::new(),
+                fee_tiers: Vec::new(),
+                frozen_limits: BTreeMap::new(),
+            }
         }
     }
 }
 
-impl std::string::ToString for B256 {
-    fn to_string(&self) -> String {
-        use sp_core::crypto::Ss58Codec;
-        self.to_ss58check()
+mod v11 {
+    use super::*;
+
+    #[derive(Deserialize)]
+    pub struct DataV11 {
+        pub orderbooks: HashMap<Symbol, v12::OrderBookV12>,
+        pub accounts: CopyOnWrite<Accounts>,
+        pub merkle_tree: CopyOnWrite<GlobalStates>,
+        pub current_event_id: u64,
+        pub tvl: Amount,
+        pub orders: CopyOnWrite<UserOrders>,
+        pub last_prices: HashMap<Symbol, LastPrice>,
+        pub currencies: BTreeMap<Currency, CurrencyMode>,
+        pub volumes: CopyOnWrite<TradedVolumes>,
+        pub links: HashMap<Symbol, HashMap<OrderId, OrderId>>,
+        pub breakers: HashMap<Symbol, Breaker>,
     }
-}
 
-impl std::fmt::Debug for B256 {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        use sp_core::crypto::Ss58Codec;
-        let s = self.to_ss58check();
-        write!(f, "{}", &s)
+    impl From<DataV11> for Data {
+        fn from(data: DataV11) -> Data {
+            Data {
+                orderbooks: data
+                    .orderbooks
+                    .into_iter()
+                    .map(|(k, v)| (k, OrderBook::from(v).into()))
+                    .collect(),
+                accounts: data.accounts,
+                merkle_tree: data.merkle_tree,
+                current_event_id: data.current_event_id,
+                tvl: data.tvl,
+                orders: data.orders,
+                last_prices: data.last_prices,
+                currencies: data.currencies,
+                volumes: data.volumes,
+                links: data.links,
+                breakers: data.breakers,
+                client_orders: HashMap::new(),
+                fee_tiers: Vec::new(),
+                frozen_limits: BTreeMap::new(),
+            }
+        }
     }
 }
 
-impl AsRef<[u8]> for B256 {
-    fn as_ref(&self) -> &[u8] {
-        &self.0[..]
+mod v12 {
+    use super::*;
+    use galois_core::orderbook::{Index, Tape};
+
+    #[derive(Clone, Serialize, Deserialize)]
+    pub struct OrderBookV12 {
+        pub asks: Tape,
+        pub bids: Tape,
+        pub indices: Index,
+        pub base_scale: u32,
+        pub quote_scale: u32,
+        pub taker_fee: Fee,
+        pub maker_fee: Fee,
+        pub base_taker_fee: Fee,
+        pub base_maker_fee: Fee,
+        pub fee_times: u32,
+        pub broker_share: Fee,
+        pub tick_size: Price,
+        pub lot_size: Amount,
+        pub price_band: Fee,
+        pub min_amount: Amount,
+        pub min_vol: Amount,
+        pub enable_market_order: bool,
+        pub open: bool,
+        pub max_id: OrderId,
     }
-}
 
-impl AsMut<[u8]> for B256 {
-    fn as_mut(&mut self) -> &mut [u8] {
-        &mut self.0[..]
+    impl From<OrderBookV12> for OrderBook {
+        fn from(book: OrderBookV12) -> OrderBook {
+            OrderBook {
+                asks: book.asks,
+                bids: book.bids,
+                indices: book.indices,
+                base_scale: book.base_scale,
+                quote_scale: book.quote_scale,
+                taker_fee: book.taker_fee,
+                maker_fee: book.maker_fee,
+                base_taker_fee: book.base_taker_fee,
+                base_maker_fee: book.base_maker_fee,
+                fee_times: book.fee_times,
+                broker_share: book.broker_share,
+                tick_size: book.tick_size,
+                lot_size: book.lot_size,
+                price_band: book.price_band,
+                self_trade_prevention: SelfTradePrevention::default(),
+                max_open_notional: Vol::zero(),
+                user_notional: HashMap::new(),
+                total_notional: Vol::zero(),
+                max_open_orders: 0,
+                trading_hours: Vec::new(),
+                circuit_breaker: CircuitBreaker::default(),
+                block_trade_min_amount: Amount::zero(),
+                block_trade_report_delay: 0,
+                min_amount: book.min_amount,
+                min_vol: book.min_vol,
+                enable_market_order: book.enable_market_order,
+                open: book.open,
+                max_id: book.max_id,
+            }
+        }
     }
-}
 
-impl AsRef<[u8; 32]> for B256 {
-    fn as_ref(&self) -> &[u8; 32] {
-        &self.0
+    #[derive(Deserialize)]
+    pub struct DataV12 {
+        pub orderbooks: HashMap<Symbol, OrderBookV12>,
+        pub accounts: CopyOnWrite<Accounts>,
+        pub merkle_tree: CopyOnWrite<GlobalStates>,
+        pub current_event_id: u64,
+        pub tvl: Amount,
+        pub orders: CopyOnWrite<UserOrders>,
+        pub last_prices: HashMap<Symbol, LastPrice>,
+        pub currencies: BTreeMap<Currency, CurrencyMode>,
+        pub volumes: CopyOnWrite<TradedVolumes>,
+        pub links: HashMap<Symbol, HashMap<OrderId, OrderId>>,
+        pub breakers: HashMap<Symbol, Breaker>,
+        pub client_orders: ClientOrders,
     }
-}
 
-impl AsMut<[u8; 32]> for B256 {
-    fn as_mut(&mut self) -> &mut [u8; 32] {
-        &mut self.0
+    impl From<DataV12> for Data {
+        fn from(data: DataV12) -> Data {
+            Data {
+                orderbooks: data
+                    .orderbooks
+                    .into_iter()
+                    .map(|(k, v)| (k, OrderBook::from(v).into()))
+                    .collect(),
+                accounts: data.accounts,
+                merkle_tree: data.merkle_tree,
+                current_event_id: data.current_event_id,
+                tvl: data.tvl,
+                orders: data.orders,
+                last_prices: data.last_prices,
+                currencies: data.currencies,
+                volumes: data.volumes,
+                links: data.links,
+                breakers: data.breakers,
+                client_orders: data.client_orders,
+                fee_tiers: Vec::new(),
+                frozen_limits: BTreeMap::new(),
+            }
+        }
     }
 }
 
-impl From<[u8; 32]> for B256 {
-    fn from(x: [u8; 32]) -> Self {
-        Self::new(x)
+mod v13 {
+    use super::*;
+    use galois_core::orderbook::{Index, Tape};
+
+    #[derive(Clone, Serialize, Deserialize)]
+    pub struct OrderBookV13 {
+        pub asks: Tape,
+        pub bids: Tape,
+        pub indices: Index,
+        pub base_scale: u32,
+        pub quote_scale: u32,
+        pub taker_fee: Fee,
+        pub maker_fee: Fee,
+        pub base_taker_fee: Fee,
+        pub base_maker_fee: Fee,
+        pub fee_times: u32,
+        pub broker_share: Fee,
+        pub tick_size: Price,
+        pub lot_size: Amount,
+        pub price_band: Fee,
+        pub self_trade_prevention: SelfTradePrevention,
+        pub min_amount: Amount,
+        pub min_vol: Amount,
+        pub enable_market_order: bool,
+        pub open: bool,
+        pub max_id: OrderId,
     }
-}
 
-impl ByteArray for B256 {
-    const LEN: usize = 32;
-}
+    impl From<OrderBookV13> for OrderBook {
+        fn from(book: OrderBookV13) -> OrderBook {
+            OrderBook {
+                asks: book.asks,
+                bids: book.bids,
+                indices: book.indices,
+                base_scale: book.base_scale,
+                quote_scale: book.quote_scale,
+                taker_fee: book.taker_fee,
+                maker_fee: book.maker_fee,
+                base_taker_fee: book.base_taker_fee,
+                base_maker_fee: book.base_maker_fee,
+                fee_times: book.fee_times,
+                broker_share: book.broker_share,
+                tick_size: book.tick_size,
+                lot_size: book.lot_size,
+                price_band: book.price_band,
+                self_trade_prevention: book.self_trade_prevention,
+                max_open_notional: Vol::zero(),
+                user_notional: HashMap::new(),
+                total_notional: Vol::zero(),
+                max_open_orders: 0,
+                trading_hours: Vec::new(),
+                circuit_breaker: CircuitBreaker::default(),
+                block_trade_min_amount: Amount::zero(),
+                block_trade_report_delay: 0,
+                min_amount: book.min_amount,
+                min_vol: book.min_vol,
+                enable_market_order: book.enable_market_order,
+                open: book.open,
+                max_id: book.max_id,
+            }
+        }
+    }
 
-impl<'a> TryFrom<&'a [u8]> for B256 {
-    type Error = ();
+    #[derive(Deserialize)]
+    pub struct DataV13 {
+        pub orderbooks: HashMap<Symbol, OrderBookV13>,
+        pub accounts: CopyOnWrite<Accounts>,
+        pub merkle_tree: CopyOnWrite<GlobalStates>,
+        pub current_event_id: u64,
+        pub tvl: Amount,
+        pub orders: CopyOnWrite<UserOrders>,
+        pub last_prices: HashMap<Symbol, LastPrice>,
+        pub currencies: BTreeMap<Currency, CurrencyMode>,
+        pub volumes: CopyOnWrite<TradedVolumes>,
+        pub links: HashMap<Symbol, HashMap<OrderId, OrderId>>,
+        pub breakers: HashMap<Symbol, Breaker>,
+        pub client_orders: ClientOrders,
+    }
 
-    fn try_from(value: &'a [u8]) -> Result<Self, Self::Error> {
-        if value.len() != 32 {
-            return Err(());
+    impl From<DataV13> for Data {
+        fn from(data: DataV13) -> Data {
+            Data {
+                orderbooks: data
+                    .orderbooks
+                    .into_iter()
+                    .map(|(k, v)| (k, OrderBook::from(v).into()))
+                    .collect(),
+                accounts: data.accounts,
+                merkle_tree: data.merkle_tree,
+                current_event_id: data.current_event_id,
+                tvl: data.tvl,
+                orders: data.orders,
+                last_prices: data.last_prices,
+                currencies: data.currencies,
+                volumes: data.volumes,
+                links: data.links,
+                breakers: data.breakers,
+                client_orders: data.client_orders,
+                fee_tiers: Vec::new(),
+                frozen_limits: BTreeMap::new(),
+            }
         }
-        let mut out = [0u8; 32];
-        out.copy_from_slice(&value[..]);
-        return Ok(B256::new(out));
     }
 }
 
-impl sp_core::crypto::Ss58Codec for B256 {}
+mod v14 {
+    use super::*;
 
-pub const SYSTEM: UserId = UserId::zero();
+    #[derive(Deserialize)]
+    pub struct DataV14 {
+        pub orderbooks: HashMap<Symbol, v15::OrderBookV15>,
+        pub accounts: CopyOnWrite<Accounts>,
+        pub merkle_tree: CopyOnWrite<GlobalStates>,
+        pub current_event_id: u64,
+        pub tvl: Amount,
+        pub orders: CopyOnWrite<UserOrders>,
+        pub last_prices: HashMap<Symbol, LastPrice>,
+        pub currencies: BTreeMap<Currency, CurrencyMode>,
+        pub volumes: CopyOnWrite<TradedVolumes>,
+        pub links: HashMap<Symbol, HashMap<OrderId, OrderId>>,
+        pub breakers: HashMap<Symbol, Breaker>,
+        pub client_orders: ClientOrders,
+    }
 
-#[must_use]
-pub fn max_number() -> Amount {
-    u64::MAX.into()
+    impl From<DataV14> for Data {
+        fn from(data: DataV14) -> Data {
+            Data {
+                orderbooks: data
+                    .orderbooks
+                    .into_iter()
+                    .map(|(k, v)| (k, OrderBook::from(v).into()))
+                    .collect(),
+                accounts: data.accounts,
+                merkle_tree: data.merkle_tree,
+                current_event_id: data.current_event_id,
+                tvl: data.tvl,
+                orders: data.orders,
+                last_prices: data.last_prices,
+                currencies: data.currencies,
+                volumes: data.volumes,
+                links: data.links,
+                breakers: data.breakers,
+                client_orders: data.client_orders,
+                fee_tiers: Vec::new(),
+                frozen_limits: BTreeMap::new(),
+            }
+        }
+    }
 }
 
-pub const MAX_PENDING_ORDERS_PER_USER: usize = 100;
+mod v15 {
+    use super::*;
+    use galois_core::orderbook::{Index, Tape};
 
-// we only keep the last 1000 transfer_in/out receipts to remove duplicates
-const RECEIPTS_RECORDS_CAPACITY: usize = 1000;
+    #[derive(Clone, Serialize, Deserialize)]
+    pub struct OrderBookV15 {
+        pub asks: Tape,
+        pub bids: Tape,
+        pub indices: Index,
+        pub base_scale: u32,
+        pub quote_scale: u32,
+        pub taker_fee: Fee,
+        pub maker_fee: Fee,
+        pub base_taker_fee: Fee,
+        pub base_maker_fee: Fee,
+        pub fee_times: u32,
+        pub broker_share: Fee,
+        pub tick_size: Price,
+        pub lot_size: Amount,
+        pub price_band: Fee,
+        pub self_trade_prevention: SelfTradePrevention,
+        pub max_open_notional: Vol,
+        pub min_amount: Amount,
+        pub min_vol: Amount,
+        pub enable_market_order: bool,
+        pub open: bool,
+        pub max_id: OrderId,
+    }
 
-#[derive(Clone, Debug)]
-pub struct Ephemeral {
-    onchain_receipt_records: IndexSet<(u32, UserId)>,
+    impl From<OrderBookV15> for OrderBook {
+        fn from(book: OrderBookV15) -> OrderBook {
+            OrderBook {
+                asks: book.asks,
+                bids: book.bids,
+                indices: book.indices,
+                base_scale: book.base_scale,
+                quote_scale: book.quote_scale,
+                taker_fee: book.taker_fee,
+                maker_fee: book.maker_fee,
+                base_taker_fee: book.base_taker_fee,
+                base_maker_fee: book.base_maker_fee,
+                fee_times: book.fee_times,
+                broker_share: book.broker_share,
+                tick_size: book.tick_size,
+                lot_size: book.lot_size,
+                price_band: book.price_band,
+                self_trade_prevention: book.self_trade_prevention,
+                max_open_notional: book.max_open_notional,
+                user_notional: HashMap::new(),
+                total_notional: Vol::zero(),
+                max_open_orders: 0,
+                trading_hours: Vec::new(),
+                circuit_breaker: CircuitBreaker::default(),
+                block_trade_min_amount: Amount::zero(),
+                block_trade_report_delay: 0,
+                min_amount: book.min_amount,
+                min_vol: book.min_vol,
+                enable_market_order: book.enable_market_order,
+                open: book.open,
+                max_id: book.max_id,
+            }
+        }
+    }
+
+    #[derive(Deserialize)]
+    pub struct DataV15 {
+        pub orderbooks: HashMap<Symbol, OrderBookV15>,
+        pub accounts: CopyOnWrite<Accounts>,
+        pub merkle_tree: CopyOnWrite<GlobalStates>,
+        pub current_event_id: u64,
+        pub tvl: Amount,
+        pub orders: CopyOnWrite<UserOrders>,
+        pub last_prices: HashMap<Symbol, LastPrice>,
+        pub currencies: BTreeMap<Currency, CurrencyMode>,
+        pub volumes: CopyOnWrite<TradedVolumes>,
+        pub links: HashMap<Symbol, HashMap<OrderId, OrderId>>,
+        pub breakers: HashMap<Symbol, Breaker>,
+        pub client_orders: ClientOrders,
+        pub fee_tiers: Vec<FeeTier>,
+    }
+
+    impl From<DataV15> for Data {
+        fn from(data: DataV15) -> Data {
+            Data {
+                orderbooks: data
+                    .orderbooks
+                    .into_iter()
+                    .map(|(k, v)| (k, OrderBook::from(v).into()))
+                    .collect(),
+                accounts: data.accounts,
+                merkle_tree: data.merkle_tree,
+                current_event_id: data.current_event_id,
+                tvl: data.tvl,
+                orders: data.orders,
+                last_prices: data.last_prices,
+                currencies: data.currencies,
+                volumes: data.volumes,
+                links: data.links,
+                breakers: data.breakers,
+                client_orders: data.client_orders,
+                fee_tiers: data.fee_tiers,
+                frozen_limits: BTreeMap::new(),
+            }
+        }
+    }
 }
 
-impl Ephemeral {
-    pub fn new() -> Self {
-        Self {
-            onchain_receipt_records: IndexSet::with_capacity(RECEIPTS_RECORDS_CAPACITY),
+mod v16 {
+    use super::*;
+    use galois_core::orderbook::{Index, Tape};
+
+    #[derive(Clone, Serialize, Deserialize)]
+    pub struct OrderBookV16 {
+        pub asks: Tape,
+        pub bids: Tape,
+        pub indices: Index,
+        pub base_scale: u32,
+        pub quote_scale: u32,
+        pub taker_fee: Fee,
+        pub maker_fee: Fee,
+        pub base_taker_fee: Fee,
+        pub base_maker_fee: Fee,
+        pub fee_times: u32,
+        pub broker_share: Fee,
+        pub tick_size: Price,
+        pub lot_size: Amount,
+        pub price_band: Fee,
+        pub self_trade_prevention: SelfTradePrevention,
+        pub max_open_notional: Vol,
+        pub max_open_orders: u32,
+        pub min_amount: Amount,
+        pub min_vol: Amount,
+        pub enable_market_order: bool,
+        pub open: bool,
+        pub max_id: OrderId,
+    }
+
+    impl From<OrderBookV16> for OrderBook {
+        fn from(book: OrderBookV16) -> OrderBook {
+            OrderBook {
+                asks: book.asks,
+                bids: book.bids,
+                indices: book.indices,
+                base_scale: book.base_scale,
+                quote_scale: book.quote_scale,
+                taker_fee: book.taker_fee,
+                maker_fee: book.maker_fee,
+                base_taker_fee: book.base_taker_fee,
+                base_maker_fee: book.base_maker_fee,
+                fee_times: book.fee_times,
+                broker_share: book.broker_share,
+                tick_size: book.tick_size,
+                lot_size: book.lot_size,
+                price_band: book.price_band,
+                self_trade_prevention: book.self_trade_prevention,
+                max_open_notional: book.max_open_notional,
+                user_notional: HashMap::new(),
+                total_notional: Vol::zero(),
+                max_open_orders: book.max_open_orders,
+                trading_hours: Vec::new(),
+                circuit_breaker: CircuitBreaker::default(),
+                block_trade_min_amount: Amount::zero(),
+                block_trade_report_delay: 0,
+                min_amount: book.min_amount,
+                min_vol: book.min_vol,
+                enable_market_order: book.enable_market_order,
+                open: book.open,
+                max_id: book.max_id,
+            }
         }
     }
 
-    pub fn save_receipt(&mut self, id: (u32, UserId)) -> bool {
-        if self.onchain_receipt_records.len() >= RECEIPTS_RECORDS_CAPACITY {
-            self.onchain_receipt_records.pop();
+    #[derive(Deserialize)]
+    pub struct DataV16 {
+        pub orderbooks: HashMap<Symbol, OrderBookV16>,
+        pub accounts: CopyOnWrite<Accounts>,
+        pub merkle_tree: CopyOnWrite<GlobalStates>,
+        pub current_event_id: u64,
+        pub tvl: Amount,
+        pub orders: CopyOnWrite<UserOrders>,
+        pub last_prices: HashMap<Symbol, LastPrice>,
+        pub currencies: BTreeMap<Currency, CurrencyMode>,
+        pub volumes: CopyOnWrite<TradedVolumes>,
+        pub links: HashMap<Symbol, HashMap<OrderId, OrderId>>,
+        pub breakers: HashMap<Symbol, Breaker>,
+        pub client_orders: ClientOrders,
+        pub fee_tiers: Vec<FeeTier>,
+        pub frozen_limits: BTreeMap<Currency, Amount>,
+    }
+
+    impl From<DataV16> for Data {
+        fn from(data: DataV16) -> Data {
+            Data {
+                orderbooks: data
+                    .orderbooks
+                    .into_iter()
+                    .map(|(k, v)| (k, OrderBook::from(v).into()))
+                    .collect(),
+                accounts: data.accounts,
+                merkle_tree: data.merkle_tree,
+                current_event_id: data.current_event_id,
+                tvl: data.tvl,
+                orders: data.orders,
+                last_prices: data.last_prices,
+                currencies: data.currencies,
+                volumes: data.volumes,
+                links: data.links,
+                breakers: data.breakers,
+                client_orders: data.client_orders,
+                fee_tiers: data.fee_tiers,
+                frozen_limits: data.frozen_limits,
+            }
         }
-        self.onchain_receipt_records.insert(id)
     }
 }
 
-#[derive(Clone, Serialize, Deserialize)]
-pub struct Data {
-    pub orderbooks: HashMap<Symbol, OrderBook>,
-    pub accounts: Accounts,
-    pub merkle_tree: GlobalStates,
-    pub current_event_id: u64,
-    pub tvl: Amount,
-    pub orders: UserOrders,
-}
+mod v17 {
+    use super::*;
+    use galois_core::orderbook::{Index, Tape};
 
-impl Data {
-    pub fn new() -> Self {
-        Self {
-            orderbooks: HashMap::new(),
-            accounts: HashMap::new(),
-            merkle_tree: GlobalStates::default(),
-            current_event_id: 0,
-            tvl: Amount::zero(),
-            orders: UserOrders::new(),
+    #[derive(Clone, Serialize, Deserialize)]
+    pub struct OrderBookV17 {
+        pub asks: Tape,
+        pub bids: Tape,
+        pub indices: Index,
+        pub base_scale: u32,
+        pub quote_scale: u32,
+        pub taker_fee: Fee,
+        pub maker_fee: Fee,
+        pub base_taker_fee: Fee,
+        pub base_maker_fee: Fee,
+        pub fee_times: u32,
+        pub broker_share: Fee,
+        pub tick_size: Price,
+        pub lot_size: Amount,
+        pub price_band: Fee,
+        pub self_trade_prevention: SelfTradePrevention,
+        pub max_open_notional: Vol,
+        pub max_open_orders: u32,
+        pub trading_hours: Vec<String>,
+        pub circuit_breaker: CircuitBreaker,
+        pub min_amount: Amount,
+        pub min_vol: Amount,
+        pub enable_market_order: bool,
+        pub open: bool,
+        pub max_id: OrderId,
+    }
+
+    impl From<OrderBookV17> for OrderBook {
+        fn from(book: OrderBookV17) -> OrderBook {
+            OrderBook {
+                asks: book.asks,
+                bids: book.bids,
+                indices: book.indices,
+                base_scale: book.base_scale,
+                quote_scale: book.quote_scale,
+                taker_fee: book.taker_fee,
+                maker_fee: book.maker_fee,
+                base_taker_fee: book.base_taker_fee,
+                base_maker_fee: book.base_maker_fee,
+                fee_times: book.fee_times,
+                broker_share: book.broker_share,
+                tick_size: book.tick_size,
+                lot_size: book.lot_size,
+                price_band: book.price_band,
+                self_trade_prevention: book.self_trade_prevention,
+                max_open_notional: book.max_open_notional,
+                user_notional: HashMap::new(),
+                total_notional: Vol::zero(),
+                max_open_orders: book.max_open_orders,
+                trading_hours: book.trading_hours,
+                circuit_breaker: book.circuit_breaker,
+                block_trade_min_amount: Amount::zero(),
+                block_trade_report_delay: 0,
+                min_amount: book.min_amount,
+                min_vol: book.min_vol,
+                enable_market_order: book.enable_market_order,
+                open: book.open,
+                max_id: book.max_id,
+            }
         }
     }
 
-    pub fn from_raw(file: File) -> anyhow::Result<Self> {
-        let reader = BufReader::new(file);
-        let mut decompress = ZlibDecoder::new(reader);
-        Ok(bincode::deserialize_from(&mut decompress)?)
+    #[derive(Deserialize)]
+    pub struct DataV17 {
+        pub orderbooks: HashMap<Symbol, OrderBookV17>,
+        pub accounts: CopyOnWrite<Accounts>,
+        pub merkle_tree: CopyOnWrite<GlobalStates>,
+        pub current_event_id: u64,
+        pub tvl: Amount,
+        pub orders: CopyOnWrite<UserOrders>,
+        pub last_prices: HashMap<Symbol, LastPrice>,
+        pub currencies: BTreeMap<Currency, CurrencyMode>,
+        pub volumes: CopyOnWrite<TradedVolumes>,
+        pub links: HashMap<Symbol, HashMap<OrderId, OrderId>>,
+        pub breakers: HashMap<Symbol, Breaker>,
+        pub client_orders: ClientOrders,
+        pub fee_tiers: Vec<FeeTier>,
+        pub frozen_limits: BTreeMap<Currency, Amount>,
     }
 
-    pub fn into_raw(&self, file: File) -> anyhow::Result<()> {
-        let writer = BufWriter::new(file);
-        let mut compress = ZlibEncoder::new(writer, Compression::best());
-        bincode::serialize_into(&mut compress, &self)?;
-        Ok(())
+    impl From<DataV17> for Data {
+        fn from(data: DataV17) -> Data {
+            Data {
+                orderbooks: data
+                    .orderbooks
+                    .into_iter()
+                    .map(|(k, v)| (k, OrderBook::from(v).into()))
+                    .collect(),
+                accounts: data.accounts,
+                merkle_tree: data.merkle_tree,
+                current_event_id: data.current_event_id,
+                tvl: data.tvl,
+                orders: data.orders,
+                last_prices: data.last_prices,
+                currencies: data.currencies,
+                volumes: data.volumes,
+                links: data.links,
+                breakers: data.breakers,
+                client_orders: data.client_orders,
+                fee_tiers: data.fee_tiers,
+                frozen_limits: data.frozen_limits,
+            }
+        }
     }
 }
 
 #[cfg(feature = "v1-to-v2")]
 pub mod v1 {
     use super::*;
+    use flate2::read::ZlibDecoder;
+    use std::io::BufReader;
 
     #[derive(Clone, Serialize, Deserialize)]
     pub struct DataV1 {
-        pub orderbooks: HashMap<Symbol, OrderBook>,
+        pub orderbooks: HashMap<Symbol, v4::OrderBookV4>,
         pub accounts: Accounts,
         pub merkle_tree: GlobalStates,
         pub current_event_id: u64,
@@ -277,12 +1925,24 @@ pub mod v1 {
                 orders.insert(order);
             });
             Data {
-                orderbooks: data.orderbooks,
-                accounts: data.accounts,
-                merkle_tree: data.merkle_tree,
+                orderbooks: data
+                    .orderbooks
+                    .into_iter()
+                    .map(|(k, v)| (k, OrderBook::from(v).into()))
+                    .collect(),
+                accounts: data.accounts.into(),
+                merkle_tree: data.merkle_tree.into(),
                 current_event_id: data.current_event_id,
                 tvl: data.tvl,
-                orders,
+                orders: orders.into(),
+                last_prices: HashMap::new(),
+                currencies: BTreeMap::new(),
+                volumes: Default::default(),
+                links: HashMap::new(),
+                breakers: HashMap::new(),
+                client_orders: HashMap::new(),
+                fee_tiers: Vec::new(),
+                frozen_limits: BTreeMap::new(),
             }
         }
     }
@@ -293,7 +1953,7 @@ pub fn test_dump() {
     use crate::orderbook::Order;
     use rust_decimal_macros::dec;
 
-    let order = Order::new(0, UserId::zero(), Decimal::new(1, 0), Decimal::new(1, 0));
+    let order = Order::new(0, UserId::zero(), Price::new(1, 0), Amount::new(1, 0));
     let v = bincode::serialize(&order).unwrap();
     let des: Order = bincode::deserialize(&v).unwrap();
     assert_eq!(des, order);
@@ -328,7 +1988,8 @@ pub fn test_dump() {
             dec!(1.0),
             false,
             true,
-        ),
+        )
+        .into(),
     );
     let temp_dir = tempdir::TempDir::new(".").unwrap();
     let file_path = temp_dir.path().join("bin.gz");
@@ -337,6 +1998,945 @@ pub fn test_dump() {
     let de = Data::from_raw(File::open(&file_path).unwrap()).unwrap();
     assert_eq!(test.orderbooks, de.orderbooks);
     assert_eq!(test.accounts, de.accounts);
+
+    // the orderbooks without the orders dumped before the icebergs
+    let legacy = |book: &OrderBook| v4::OrderBookV4 {
+        asks: BTreeMap::new(),
+        bids: BTreeMap::new(),
+        indices: HashMap::new(),
+        base_scale: book.base_scale,
+        quote_scale: book.quote_scale,
+        taker_fee: book.taker_fee,
+        maker_fee: book.maker_fee,
+        base_taker_fee: book.base_taker_fee,
+        base_maker_fee: book.base_maker_fee,
+        fee_times: book.fee_times,
+        min_amount: book.min_amount,
+        min_vol: book.min_vol,
+        enable_market_order: book.enable_market_order,
+        open: book.open,
+        max_id: book.max_id,
+    };
+
+    // dumped before tracking the last prices
+    #[derive(Serialize)]
+    struct DataV2<'a> {
+        orderbooks: HashMap<Symbol, v4::OrderBookV4>,
+        accounts: &'a Accounts,
+        merkle_tree: &'a GlobalStates,
+        current_event_id: u64,
+        tvl: Amount,
+        orders: &'a UserOrders,
+    }
+    test.last_prices.insert(
+        (101, 100),
+        LastPrice {
+            symbol: (101, 100),
+            price: dec!(1.5),
+            event_id: 1,
+            timestamp: 0,
+        },
+    );
+    let v2 = DataV2 {
+        orderbooks: test
+            .orderbooks
+            .iter()
+            .map(|(k, v)| (*k, legacy(v)))
+            .collect(),
+        accounts: &test.accounts,
+        merkle_tree: &test.merkle_tree,
+        current_event_id: 1,
+        tvl: test.tvl,
+        orders: &test.orders,
+    };
+    let file_path = temp_dir.path().join("v2.gz");
+    let mut compress = ZlibEncoder::new(File::create(&file_path).unwrap(), Compression::best());
+    bincode::serialize_into(&mut compress, &v2).unwrap();
+    compress.finish().unwrap();
+    let de = Data::from_raw(File::open(&file_path).unwrap()).unwrap();
+    assert_eq!(test.orderbooks, de.orderbooks);
+    assert_eq!(1, de.current_event_id);
+    assert!(de.last_prices.is_empty());
+
+    // dumped before the currency modes
+    #[derive(Serialize)]
+    struct DataV3<'a> {
+        orderbooks: HashMap<Symbol, v4::OrderBookV4>,
+        accounts: &'a CopyOnWrite<Accounts>,
+        merkle_tree: &'a CopyOnWrite<GlobalStates>,
+        current_event_id: u64,
+        tvl: Amount,
+        orders: &'a CopyOnWrite<UserOrders>,
+        last_prices: &'a HashMap<Symbol, LastPrice>,
+    }
+    let v3 = DataV3 {
+        orderbooks: test
+            .orderbooks
+            .iter()
+            .map(|(k, v)| (*k, legacy(v)))
+            .collect(),
+        accounts: &test.accounts,
+        merkle_tree: &test.merkle_tree,
+        current_event_id: 1,
+        tvl: test.tvl,
+        orders: &test.orders,
+        last_prices: &test.last_prices,
+    };
+    let file_path = temp_dir.path().join("v3.gz");
+    let mut compress = ZlibEncoder::new(File::create(&file_path).unwrap(), Compression::best());
+    bincode::serialize_into(&mut compress, &v3).unwrap();
+    compress.finish().unwrap();
+    let de = Data::from_raw(File::open(&file_path).unwrap()).unwrap();
+    assert_eq!(Some(dec!(1.5)), de.last_price(&(101, 100)));
+    assert!(de.currencies.is_empty());
+    test.set_currency_mode(101, CurrencyMode::TradeOnly);
+
+    // dumped before the icebergs
+    #[derive(Serialize)]
+    struct DataV4<'a> {
+        orderbooks: HashMap<Symbol, v4::OrderBookV4>,
+        accounts: &'a CopyOnWrite<Accounts>,
+        merkle_tree: &'a CopyOnWrite<GlobalStates>,
+        current_event_id: u64,
+        tvl: Amount,
+        orders: &'a CopyOnWrite<UserOrders>,
+        last_prices: &'a HashMap<Symbol, LastPrice>,
+        currencies: &'a BTreeMap<Currency, CurrencyMode>,
+    }
+    let book = &test.orderbooks[&(101, 100)];
+    let order = v4::OrderV4 {
+        id: 1,
+        user: UserId::zero(),
+        price: dec!(2),
+        unfilled: dec!(1),
+    };
+    let page = v4::OrderPageV4 {
+        orders: vec![(1, order)],
+        amount: dec!(1),
+        price: dec!(2),
+    };
+    let book = v4::OrderBookV4 {
+        asks: BTreeMap::from([(dec!(2), page)]),
+        indices: HashMap::from([(1, dec!(2))]),
+        max_id: 1,
+        ..legacy(book)
+    };
+    let v4 = DataV4 {
+        orderbooks: HashMap::from([((101, 100), book)]),
+        accounts: &test.accounts,
+        merkle_tree: &test.merkle_tree,
+        current_event_id: 1,
+        tvl: test.tvl,
+        orders: &test.orders,
+        last_prices: &test.last_prices,
+        currencies: &test.currencies,
+    };
+    let file_path = temp_dir.path().join("v4.gz");
+    let mut compress = ZlibEncoder::new(File::create(&file_path).unwrap(), Compression::best());
+    bincode::serialize_into(&mut compress, &v4).unwrap();
+    compress.finish().unwrap();
+    let de = Data::from_raw(File::open(&file_path).unwrap()).unwrap();
+    let book = &de.orderbooks[&(101, 100)];
+    assert_eq!(
+        Some(&Order::new(1, UserId::zero(), dec!(2), dec!(1))),
+        book.find_order(1)
+    );
+    assert_eq!(dec!(1), book.asks[&dec!(2)].amount);
+    assert_eq!(CurrencyMode::TradeOnly, de.currency_mode(101));
+
+    // dumped before the brokers
+    #[derive(Serialize)]
+    struct DataV5<'a> {
+        orderbooks: HashMap<Symbol, v5::OrderBookV5>,
+        accounts: &'a CopyOnWrite<Accounts>,
+        merkle_tree: &'a CopyOnWrite<GlobalStates>,
+        current_event_id: u64,
+        tvl: Amount,
+        orders: &'a CopyOnWrite<UserOrders>,
+        last_prices: &'a HashMap<Symbol, LastPrice>,
+        currencies: &'a BTreeMap<Currency, CurrencyMode>,
+    }
+    let book = &test.orderbooks[&(101, 100)];
+    let order = v5::OrderV5 {
+        id: 1,
+        user: UserId::zero(),
+        price: dec!(2),
+        unfilled: dec!(5),
+        display: dec!(1),
+        hidden: dec!(4),
+    };
+    let page = v5::OrderPageV5 {
+        orders: vec![(1, order)],
+        amount: dec!(5),
+        price: dec!(2),
+        hidden: dec!(4),
+    };
+    let legacy = v5::OrderBookV5 {
+        asks: BTreeMap::from([(dec!(2), page)]),
+        bids: BTreeMap::new(),
+        indices: HashMap::from([(1, dec!(2))]),
+        base_scale: book.base_scale,
+        quote_scale: book.quote_scale,
+        taker_fee: book.taker_fee,
+        maker_fee: book.maker_fee,
+        base_taker_fee: book.base_taker_fee,
+        base_maker_fee: book.base_maker_fee,
+        fee_times: book.fee_times,
+        min_amount: book.min_amount,
+        min_vol: book.min_vol,
+        enable_market_order: book.enable_market_order,
+        open: book.open,
+        max_id: 1,
+    };
+    let v5 = DataV5 {
+        orderbooks: HashMap::from([((101, 100), legacy)]),
+        accounts: &test.accounts,
+        merkle_tree: &test.merkle_tree,
+        current_event_id: 1,
+        tvl: test.tvl,
+        orders: &test.orders,
+        last_prices: &test.last_prices,
+        currencies: &test.currencies,
+    };
+    let file_path = temp_dir.path().join("v5.gz");
+    let mut compress = ZlibEncoder::new(File::create(&file_path).unwrap(), Compression::best());
+    bincode::serialize_into(&mut compress, &v5).unwrap();
+    compress.finish().unwrap();
+    let de = Data::from_raw(File::open(&file_path).unwrap()).unwrap();
+    let book = &de.orderbooks[&(101, 100)];
+    assert_eq!(
+        Some(&Order::new(1, UserId::zero(), dec!(2), dec!(5)).with_display(dec!(1))),
+        book.find_order(1)
+    );
+    assert_eq!(dec!(4), book.asks[&dec!(2)].hidden);
+    assert!(book.broker_share.is_zero());
+
+    let book = test.orderbooks.get_mut(&(101, 100)).unwrap();
+    book.broker_share = dec!(0.2);
+    book.insert(
+        Order::new(2, UserId::zero(), dec!(2), dec!(5))
+            .with_display(dec!(1))
+            .with_broker(Some(UserId::from_low_u64_be(9))),
+        AskOrBid::Ask,
+    );
+    // the orderbooks dumped before the tick and lot sizes
+    let legacy = |books: &HashMap<Symbol, CopyOnWrite<OrderBook>>| {
+        books
+            .iter()
+            .map(|(k, book)| {
+                let book = v7::OrderBookV7 {
+                    asks: book.asks.clone(),
+                    bids: book.bids.clone(),
+                    indices: book.indices.clone(),
+                    base_scale: book.base_scale,
+                    quote_scale: book.quote_scale,
+                    taker_fee: book.taker_fee,
+                    maker_fee: book.maker_fee,
+                    base_taker_fee: book.base_taker_fee,
+                    base_maker_fee: book.base_maker_fee,
+                    fee_times: book.fee_times,
+                    broker_share: book.broker_share,
+                    min_amount: book.min_amount,
+                    min_vol: book.min_vol,
+                    enable_market_order: book.enable_market_order,
+                    open: book.open,
+                    max_id: book.max_id,
+                };
+                (*k, book)
+            })
+            .collect::<HashMap<_, _>>()
+    };
+    // dumped before the traded volumes
+    #[derive(Serialize)]
+    struct DataV6<'a> {
+        orderbooks: HashMap<Symbol, v7::OrderBookV7>,
+        accounts: &'a CopyOnWrite<Accounts>,
+        merkle_tree: &'a CopyOnWrite<GlobalStates>,
+        current_event_id: u64,
+        tvl: Amount,
+        orders: &'a CopyOnWrite<UserOrders>,
+        last_prices: &'a HashMap<Symbol, LastPrice>,
+        currencies: &'a BTreeMap<Currency, CurrencyMode>,
+    }
+    let v6 = DataV6 {
+        orderbooks: legacy(&test.orderbooks),
+        accounts: &test.accounts,
+        merkle_tree: &test.merkle_tree,
+        current_event_id: 1,
+        tvl: test.tvl,
+        orders: &test.orders,
+        last_prices: &test.last_prices,
+        currencies: &test.currencies,
+    };
+    let file_path = temp_dir.path().join("v6.gz");
+    let mut compress = ZlibEncoder::new(File::create(&file_path).unwrap(), Compression::best());
+    bincode::serialize_into(&mut compress, &v6).unwrap();
+    compress.finish().unwrap();
+    let de = Data::from_raw(File::open(&file_path).unwrap()).unwrap();
+    assert_eq!(test.orderbooks, de.orderbooks);
+    assert_eq!(dec!(0.2), de.orderbooks[&(101, 100)].broker_share);
+    assert!(de.volumes.is_empty());
+
+    test.volumes.add(UserId::zero(), 100, dec!(10), 0);
+    // dumped before the tick and lot sizes and the versioned header
+    #[derive(Serialize)]
+    struct DataV7<'a> {
+        orderbooks: HashMap<Symbol, v7::OrderBookV7>,
+        accounts: &'a CopyOnWrite<Accounts>,
+        merkle_tree: &'a CopyOnWrite<GlobalStates>,
+        current_event_id: u64,
+        tvl: Amount,
+        orders: &'a CopyOnWrite<UserOrders>,
+        last_prices: &'a HashMap<Symbol, LastPrice>,
+        currencies: &'a BTreeMap<Currency, CurrencyMode>,
+        volumes: &'a CopyOnWrite<TradedVolumes>,
+    }
+    let v7 = DataV7 {
+        orderbooks: legacy(&test.orderbooks),
+        accounts: &test.accounts,
+        merkle_tree: &test.merkle_tree,
+        current_event_id: 1,
+        tvl: test.tvl,
+        orders: &test.orders,
+        last_prices: &test.last_prices,
+        currencies: &test.currencies,
+        volumes: &test.volumes,
+    };
+    let file_path = temp_dir.path().join("v7.gz");
+    let mut compress = ZlibEncoder::new(File::create(&file_path).unwrap(), Compression::best());
+    bincode::serialize_into(&mut compress, &v7).unwrap();
+    compress.finish().unwrap();
+    let de = Data::from_raw(File::open(&file_path).unwrap()).unwrap();
+    assert_eq!(test.orderbooks, de.orderbooks);
+    assert_eq!(dec!(10), de.volumes.get(&UserId::zero(), 100, 0));
+
+    let book = test.orderbooks.get_mut(&(101, 100)).unwrap();
+    book.tick_size = dec!(0.5);
+    book.lot_size = dec!(0.1);
+    let file_path = temp_dir.path().join("v8.gz");
+    test.into_raw(File::create(&file_path).unwrap()).unwrap();
+    let de = Data::from_raw(File::open(&file_path).unwrap()).unwrap();
+    assert_eq!(test.orderbooks, de.orderbooks);
+    assert_eq!(dec!(0.5), de.orderbooks[&(101, 100)].tick_size);
+    assert_eq!(dec!(4), de.orderbooks[&(101, 100)].asks[&dec!(2)].hidden);
+    assert_eq!(dec!(10), de.volumes.get(&UserId::zero(), 100, 0));
+    assert_eq!(Some(dec!(1.5)), de.last_price(&(101, 100)));
+    assert_eq!(CurrencyMode::TradeOnly, de.currency_mode(101));
+    assert_eq!(CurrencyMode::Normal, de.currency_mode(100));
+
+    // dumped before the price bands
+    #[derive(Serialize)]
+    struct DataV10<'a> {
+        orderbooks: HashMap<Symbol, v10::OrderBookV10>,
+        accounts: &'a CopyOnWrite<Accounts>,
+        merkle_tree: &'a CopyOnWrite<GlobalStates>,
+        current_event_id: u64,
+        tvl: Amount,
+        orders: &'a CopyOnWrite<UserOrders>,
+        last_prices: &'a HashMap<Symbol, LastPrice>,
+        currencies: &'a BTreeMap<Currency, CurrencyMode>,
+        volumes: &'a CopyOnWrite<TradedVolumes>,
+        links: &'a HashMap<Symbol, HashMap<OrderId, OrderId>>,
+        breakers: &'a HashMap<Symbol, Breaker>,
+    }
+    let orderbooks = test
+        .orderbooks
+        .iter()
+        .map(|(k, book)| {
+            let book = v10::OrderBookV10 {
+                asks: book.asks.clone(),
+                bids: book.bids.clone(),
+                indices: book.indices.clone(),
+                base_scale: book.base_scale,
+                quote_scale: book.quote_scale,
+                taker_fee: book.taker_fee,
+                maker_fee: book.maker_fee,
+                base_taker_fee: book.base_taker_fee,
+                base_maker_fee: book.base_maker_fee,
+                fee_times: book.fee_times,
+                broker_share: book.broker_share,
+                tick_size: book.tick_size,
+                lot_size: book.lot_size,
+                min_amount: book.min_amount,
+                min_vol: book.min_vol,
+                enable_market_order: book.enable_market_order,
+                open: book.open,
+                max_id: book.max_id,
+            };
+            (*k, book)
+        })
+        .collect();
+    let v10 = DataV10 {
+        orderbooks,
+        accounts: &test.accounts,
+        merkle_tree: &test.merkle_tree,
+        current_event_id: 1,
+        tvl: test.tvl,
+        orders: &test.orders,
+        last_prices: &test.last_prices,
+        currencies: &test.currencies,
+        volumes: &test.volumes,
+        links: &test.links,
+        breakers: &test.breakers,
+    };
+    let de = Data::from_version(10, &bincode::serialize(&v10).unwrap()).unwrap();
+    assert_eq!(test.orderbooks, de.orderbooks);
+    assert_eq!(dec!(0.5), de.orderbooks[&(101, 100)].tick_size);
+    assert!(de.orderbooks[&(101, 100)].price_band.is_zero());
+
+    test.orderbooks.get_mut(&(101, 100)).unwrap().price_band = dec!(0.05);
+    let v12_books = |books: &HashMap<Symbol, CopyOnWrite<OrderBook>>| {
+        books
+            .iter()
+            .map(|(k, book)| {
+                let book = v12::OrderBookV12 {
+                    asks: book.asks.clone(),
+                    bids: book.bids.clone(),
+                    indices: book.indices.clone(),
+                    base_scale: book.base_scale,
+                    quote_scale: book.quote_scale,
+                    taker_fee: book.taker_fee,
+                    maker_fee: book.maker_fee,
+                    base_taker_fee: book.base_taker_fee,
+                    base_maker_fee: book.base_maker_fee,
+                    fee_times: book.fee_times,
+                    broker_share: book.broker_share,
+                    tick_size: book.tick_size,
+                    lot_size: book.lot_size,
+                    price_band: book.price_band,
+                    min_amount: book.min_amount,
+                    min_vol: book.min_vol,
+                    enable_market_order: book.enable_market_order,
+                    open: book.open,
+                    max_id: book.max_id,
+                };
+                (*k, book)
+            })
+            .collect::<HashMap<_, _>>()
+    };
+
+    // dumped before the client order ids
+    #[derive(Serialize)]
+    struct DataV11<'a> {
+        orderbooks: HashMap<Symbol, v12::OrderBookV12>,
+        accounts: &'a CopyOnWrite<Accounts>,
+        merkle_tree: &'a CopyOnWrite<GlobalStates>,
+        current_event_id: u64,
+        tvl: Amount,
+        orders: &'a CopyOnWrite<UserOrders>,
+        last_prices: &'a HashMap<Symbol, LastPrice>,
+        currencies: &'a BTreeMap<Currency, CurrencyMode>,
+        volumes: &'a CopyOnWrite<TradedVolumes>,
+        links: &'a HashMap<Symbol, HashMap<OrderId, OrderId>>,
+        breakers: &'a HashMap<Symbol, Breaker>,
+    }
+    let v11 = DataV11 {
+        orderbooks: v12_books(&test.orderbooks),
+        accounts: &test.accounts,
+        merkle_tree: &test.merkle_tree,
+        current_event_id: 1,
+        tvl: test.tvl,
+        orders: &test.orders,
+        last_prices: &test.last_prices,
+        currencies: &test.currencies,
+        volumes: &test.volumes,
+        links: &test.links,
+        breakers: &test.breakers,
+    };
+    let de = Data::from_version(11, &bincode::serialize(&v11).unwrap()).unwrap();
+    assert_eq!(dec!(0.05), de.orderbooks[&(101, 100)].price_band);
+    assert!(de.client_orders.is_empty());
+
+    crate::client_orders::record(
+        &mut test.client_orders,
+        &(101, 100),
+        &UserId::zero(),
+        "c-1".to_string(),
+        1,
+    );
+
+    // dumped before the self-trade prevention of the symbols
+    #[derive(Serialize)]
+    struct DataV12<'a> {
+        orderbooks: HashMap<Symbol, v12::OrderBookV12>,
+        accounts: &'a CopyOnWrite<Accounts>,
+        merkle_tree: &'a CopyOnWrite<GlobalStates>,
+        current_event_id: u64,
+        tvl: Amount,
+        orders: &'a CopyOnWrite<UserOrders>,
+        last_prices: &'a HashMap<Symbol, LastPrice>,
+        currencies: &'a BTreeMap<Currency, CurrencyMode>,
+        volumes: &'a CopyOnWrite<TradedVolumes>,
+        links: &'a HashMap<Symbol, HashMap<OrderId, OrderId>>,
+        breakers: &'a HashMap<Symbol, Breaker>,
+        client_orders: &'a ClientOrders,
+    }
+    let v12 = DataV12 {
+        orderbooks: v12_books(&test.orderbooks),
+        accounts: &test.accounts,
+        merkle_tree: &test.merkle_tree,
+        current_event_id: 1,
+        tvl: test.tvl,
+        orders: &test.orders,
+        last_prices: &test.last_prices,
+        currencies: &test.currencies,
+        volumes: &test.volumes,
+        links: &test.links,
+        breakers: &test.breakers,
+        client_orders: &test.client_orders,
+    };
+    let de = Data::from_version(12, &bincode::serialize(&v12).unwrap()).unwrap();
+    assert_eq!(test.orderbooks, de.orderbooks);
+    assert_eq!(test.client_orders, de.client_orders);
+
+    test.orderbooks
+        .get_mut(&(101, 100))
+        .unwrap()
+        .self_trade_prevention = SelfTradePrevention::CancelOldest;
+
+    // dumped before the max open notional of the symbols
+    #[derive(Serialize)]
+    struct DataV13<'a> {
+        orderbooks: HashMap<Symbol, v13::OrderBookV13>,
+        accounts: &'a CopyOnWrite<Accounts>,
+        merkle_tree: &'a CopyOnWrite<GlobalStates>,
+        current_event_id: u64,
+        tvl: Amount,
+        orders: &'a CopyOnWrite<UserOrders>,
+        last_prices: &'a HashMap<Symbol, LastPrice>,
+        currencies: &'a BTreeMap<Currency, CurrencyMode>,
+        volumes: &'a CopyOnWrite<TradedVolumes>,
+        links: &'a HashMap<Symbol, HashMap<OrderId, OrderId>>,
+        breakers: &'a HashMap<Symbol, Breaker>,
+        client_orders: &'a ClientOrders,
+    }
+    let v13 = DataV13 {
+        orderbooks: test
+            .orderbooks
+            .iter()
+            .map(|(k, book)| {
+                let book = v13::OrderBookV13 {
+                    asks: book.asks.clone(),
+                    bids: book.bids.clone(),
+                    indices: book.indices.clone(),
+                    base_scale: book.base_scale,
+                    quote_scale: book.quote_scale,
+                    taker_fee: book.taker_fee,
+                    maker_fee: book.maker_fee,
+                    base_taker_fee: book.base_taker_fee,
+                    base_maker_fee: book.base_maker_fee,
+                    fee_times: book.fee_times,
+                    broker_share: book.broker_share,
+                    tick_size: book.tick_size,
+                    lot_size: book.lot_size,
+                    price_band: book.price_band,
+                    self_trade_prevention: book.self_trade_prevention,
+                    min_amount: book.min_amount,
+                    min_vol: book.min_vol,
+                    enable_market_order: book.enable_market_order,
+                    open: book.open,
+                    max_id: book.max_id,
+                };
+                (*k, book)
+            })
+            .collect(),
+        accounts: &test.accounts,
+        merkle_tree: &test.merkle_tree,
+        current_event_id: 1,
+        tvl: test.tvl,
+        orders: &test.orders,
+        last_prices: &test.last_prices,
+        currencies: &test.currencies,
+        volumes: &test.volumes,
+        links: &test.links,
+        breakers: &test.breakers,
+        client_orders: &test.client_orders,
+    };
+    let de = Data::from_version(13, &bincode::serialize(&v13).unwrap()).unwrap();
+    assert_eq!(test.orderbooks, de.orderbooks);
+    assert!(de.orderbooks[&(101, 100)].max_open_notional.is_zero());
+
+    test.orderbooks
+        .get_mut(&(101, 100))
+        .unwrap()
+        .max_open_notional = dec!(1000);
+
+    let v15_books = |books: &HashMap<Symbol, CopyOnWrite<OrderBook>>| {
+        books
+            .iter()
+            .map(|(k, book)| {
+                let book = v15::OrderBookV15 {
+                    asks: book.asks.clone(),
+                    bids: book.bids.clone(),
+                    indices: book.indices.clone(),
+                    base_scale: book.base_scale,
+                    quote_scale: book.quote_scale,
+                    taker_fee: book.taker_fee,
+                    maker_fee: book.maker_fee,
+                    base_taker_fee: book.base_taker_fee,
+                    base_maker_fee: book.base_maker_fee,
+                    fee_times: book.fee_times,
+                    broker_share: book.broker_share,
+                    tick_size: book.tick_size,
+                    lot_size: book.lot_size,
+                    price_band: book.price_band,
+                    self_trade_prevention: book.self_trade_prevention,
+                    max_open_notional: book.max_open_notional,
+                    min_amount: book.min_amount,
+                    min_vol: book.min_vol,
+                    enable_market_order: book.enable_market_order,
+                    open: book.open,
+                    max_id: book.max_id,
+                };
+                (*k, book)
+            })
+            .collect::<HashMap<_, _>>()
+    };
+
+    // dumped before the fee tiers
+    #[derive(Serialize)]
+    struct DataV14<'a> {
+        orderbooks: HashMap<Symbol, v15::OrderBookV15>,
+        accounts: &'a CopyOnWrite<Accounts>,
+        merkle_tree: &'a CopyOnWrite<GlobalStates>,
+        current_event_id: u64,
+        tvl: Amount,
+        orders: &'a CopyOnWrite<UserOrders>,
+        last_prices: &'a HashMap<Symbol, LastPrice>,
+        currencies: &'a BTreeMap<Currency, CurrencyMode>,
+        volumes: &'a CopyOnWrite<TradedVolumes>,
+        links: &'a HashMap<Symbol, HashMap<OrderId, OrderId>>,
+        breakers: &'a HashMap<Symbol, Breaker>,
+        client_orders: &'a ClientOrders,
+    }
+    let v14 = DataV14 {
+        orderbooks: v15_books(&test.orderbooks),
+        accounts: &test.accounts,
+        merkle_tree: &test.merkle_tree,
+        current_event_id: 1,
+        tvl: test.tvl,
+        orders: &test.orders,
+        last_prices: &test.last_prices,
+        currencies: &test.currencies,
+        volumes: &test.volumes,
+        links: &test.links,
+        breakers: &test.breakers,
+        client_orders: &test.client_orders,
+    };
+    let de = Data::from_version(14, &bincode::serialize(&v14).unwrap()).unwrap();
+    assert_eq!(test.orderbooks, de.orderbooks);
+    assert_eq!(dec!(1000), de.orderbooks[&(101, 100)].max_open_notional);
+    assert!(de.fee_tiers.is_empty());
+
+    test.fee_tiers = vec![FeeTier {
+        currency: 100,
+        volume: dec!(1000),
+        taker_fee: dec!(0.0005),
+        maker_fee: dec!(-0.0001),
+    }];
+
+    // dumped before the risk limits
+    #[derive(Serialize)]
+    struct DataV15<'a> {
+        orderbooks: HashMap<Symbol, v15::OrderBookV15>,
+        accounts: &'a CopyOnWrite<Accounts>,
+        merkle_tree: &'a CopyOnWrite<GlobalStates>,
+        current_event_id: u64,
+        tvl: Amount,
+        orders: &'a CopyOnWrite<UserOrders>,
+        last_prices: &'a HashMap<Symbol, LastPrice>,
+        currencies: &'a BTreeMap<Currency, CurrencyMode>,
+        volumes: &'a CopyOnWrite<TradedVolumes>,
+        links: &'a HashMap<Symbol, HashMap<OrderId, OrderId>>,
+        breakers: &'a HashMap<Symbol, Breaker>,
+        client_orders: &'a ClientOrders,
+        fee_tiers: &'a Vec<FeeTier>,
+    }
+    let v15 = DataV15 {
+        orderbooks: v15_books(&test.orderbooks),
+        accounts: &test.accounts,
+        merkle_tree: &test.merkle_tree,
+        current_event_id: 1,
+        tvl: test.tvl,
+        orders: &test.orders,
+        last_prices: &test.last_prices,
+        currencies: &test.currencies,
+        volumes: &test.volumes,
+        links: &test.links,
+        breakers: &test.breakers,
+        client_orders: &test.client_orders,
+        fee_tiers: &test.fee_tiers,
+    };
+    let de = Data::from_version(15, &bincode::serialize(&v15).unwrap()).unwrap();
+    assert_eq!(test.orderbooks, de.orderbooks);
+    assert_eq!(test.fee_tiers, de.fee_tiers);
+    assert!(de.frozen_limits.is_empty());
+
+    test.orderbooks
+        .get_mut(&(101, 100))
+        .unwrap()
+        .max_open_orders = 200;
+    test.set_frozen_limit(100, dec!(10000));
+
+    // dumped before the trading hours and the circuit breakers of the symbols
+    #[derive(Serialize)]
+    struct DataV16<'a> {
+        orderbooks: HashMap<Symbol, v16::OrderBookV16>,
+        accounts: &'a CopyOnWrite<Accounts>,
+        merkle_tree: &'a CopyOnWrite<GlobalStates>,
+        current_event_id: u64,
+        tvl: Amount,
+        orders: &'a CopyOnWrite<UserOrders>,
+        last_prices: &'a HashMap<Symbol, LastPrice>,
+        currencies: &'a BTreeMap<Currency, CurrencyMode>,
+        volumes: &'a CopyOnWrite<TradedVolumes>,
+        links: &'a HashMap<Symbol, HashMap<OrderId, OrderId>>,
+        breakers: &'a HashMap<Symbol, Breaker>,
+        client_orders: &'a ClientOrders,
+        fee_tiers: &'a Vec<FeeTier>,
+        frozen_limits: &'a BTreeMap<Currency, Amount>,
+    }
+    let v16 = DataV16 {
+        orderbooks: test
+            .orderbooks
+            .iter()
+            .map(|(k, book)| {
+                let book = v16::OrderBookV16 {
+                    asks: book.asks.clone(),
+                    bids: book.bids.clone(),
+                    indices: book.indices.clone(),
+                    base_scale: book.base_scale,
+                    quote_scale: book.quote_scale,
+                    taker_fee: book.taker_fee,
+                    maker_fee: book.maker_fee,
+                    base_taker_fee: book.base_taker_fee,
+                    base_maker_fee: book.base_maker_fee,
+                    fee_times: book.fee_times,
+                    broker_share: book.broker_share,
+                    tick_size: book.tick_size,
+                    lot_size: book.lot_size,
+                    price_band: book.price_band,
+                    self_trade_prevention: book.self_trade_prevention,
+                    max_open_notional: book.max_open_notional,
+                    max_open_orders: book.max_open_orders,
+                    min_amount: book.min_amount,
+                    min_vol: book.min_vol,
+                    enable_market_order: book.enable_market_order,
+                    open: book.open,
+                    max_id: book.max_id,
+                };
+                (*k, book)
+            })
+            .collect(),
+        accounts: &test.accounts,
+        merkle_tree: &test.merkle_tree,
+        current_event_id: 1,
+        tvl: test.tvl,
+        orders: &test.orders,
+        last_prices: &test.last_prices,
+        currencies: &test.currencies,
+        volumes: &test.volumes,
+        links: &test.links,
+        breakers: &test.breakers,
+        client_orders: &test.client_orders,
+        fee_tiers: &test.fee_tiers,
+        frozen_limits: &test.frozen_limits,
+    };
+    let de = Data::from_version(16, &bincode::serialize(&v16).unwrap()).unwrap();
+    assert_eq!(test.orderbooks, de.orderbooks);
+    assert_eq!(test.frozen_limits, de.frozen_limits);
+    assert!(de.orderbooks[&(101, 100)].trading_hours.is_empty());
+
+    let book = test.orderbooks.get_mut(&(101, 100)).unwrap();
+    book.trading_hours = vec!["09:30-16:00".to_string()];
+    book.circuit_breaker = CircuitBreaker {
+        max_move: dec!(0.1),
+        window: 300,
+        halt: 600,
+    };
+
+    // dumped before the block trade limits of the symbols
+    #[derive(Serialize)]
+    struct DataV17<'a> {
+        orderbooks: HashMap<Symbol, v17::OrderBookV17>,
+        accounts: &'a CopyOnWrite<Accounts>,
+        merkle_tree: &'a CopyOnWrite<GlobalStates>,
+        current_event_id: u64,
+        tvl: Amount,
+        orders: &'a CopyOnWrite<UserOrders>,
+        last_prices: &'a HashMap<Symbol, LastPrice>,
+        currencies: &'a BTreeMap<Currency, CurrencyMode>,
+        volumes: &'a CopyOnWrite<TradedVolumes>,
+        links: &'a HashMap<Symbol, HashMap<OrderId, OrderId>>,
+        breakers: &'a HashMap<Symbol, Breaker>,
+        client_orders: &'a ClientOrders,
+        fee_tiers: &'a Vec<FeeTier>,
+        frozen_limits: &'a BTreeMap<Currency, Amount>,
+    }
+    let v17 = DataV17 {
+        orderbooks: test
+            .orderbooks
+            .iter()
+            .map(|(k, book)| {
+                let book = v17::OrderBookV17 {
+                    asks: book.asks.clone(),
+                    bids: book.bids.clone(),
+                    indices: book.indices.clone(),
+                    base_scale: book.base_scale,
+                    quote_scale: book.quote_scale,
+                    taker_fee: book.taker_fee,
+                    maker_fee: book.maker_fee,
+                    base_taker_fee: book.base_taker_fee,
+                    base_maker_fee: book.base_maker_fee,
+                    fee_times: book.fee_times,
+                    broker_share: book.broker_share,
+                    tick_size: book.tick_size,
+                    lot_size: book.lot_size,
+                    price_band: book.price_band,
+                    self_trade_prevention: book.self_trade_prevention,
+                    max_open_notional: book.max_open_notional,
+                    max_open_orders: book.max_open_orders,
+                    trading_hours: book.trading_hours.clone(),
+                    circuit_breaker: book.circuit_breaker,
+                    min_amount: book.min_amount,
+                    min_vol: book.min_vol,
+                    enable_market_order: book.enable_market_order,
+                    open: book.open,
+                    max_id: book.max_id,
+                };
+                (*k, book)
+            })
+            .collect(),
+        accounts: &test.accounts,
+        merkle_tree: &test.merkle_tree,
+        current_event_id: 1,
+        tvl: test.tvl,
+        orders: &test.orders,
+        last_prices: &test.last_prices,
+        currencies: &test.currencies,
+        volumes: &test.volumes,
+        links: &test.links,
+        breakers: &test.breakers,
+        client_orders: &test.client_orders,
+        fee_tiers: &test.fee_tiers,
+        frozen_limits: &test.frozen_limits,
+    };
+    let de = Data::from_version(17, &bincode::serialize(&v17).unwrap()).unwrap();
+    assert_eq!(test.orderbooks, de.orderbooks);
+    assert!(de.orderbooks[&(101, 100)].block_trade_min_amount.is_zero());
+
+    let book = test.orderbooks.get_mut(&(101, 100)).unwrap();
+    book.block_trade_min_amount = dec!(1000);
+    book.block_trade_report_delay = 900;
+    let file_path = temp_dir.path().join("v18.gz");
+    test.into_raw(File::create(&file_path).unwrap()).unwrap();
+    let de = Data::from_raw(File::open(&file_path).unwrap()).unwrap();
+    assert_eq!(dec!(0.05), de.orderbooks[&(101, 100)].price_band);
+    assert_eq!(test.client_orders, de.client_orders);
+    assert_eq!(
+        SelfTradePrevention::CancelOldest,
+        de.orderbooks[&(101, 100)].self_trade_prevention
+    );
+    assert_eq!(dec!(1000), de.orderbooks[&(101, 100)].max_open_notional);
+    assert_eq!(test.fee_tiers, de.fee_tiers);
+    assert_eq!(200, de.orderbooks[&(101, 100)].max_open_orders);
+    assert_eq!(Some(&dec!(10000)), de.frozen_limits.get(&100));
+    assert_eq!(
+        vec!["09:30-16:00".to_string()],
+        de.orderbooks[&(101, 100)].trading_hours
+    );
+    assert_eq!(
+        dec!(0.1),
+        de.orderbooks[&(101, 100)].circuit_breaker.max_move
+    );
+    assert_eq!(
+        dec!(1000),
+        de.orderbooks[&(101, 100)].block_trade_min_amount
+    );
+    assert_eq!(900, de.orderbooks[&(101, 100)].block_trade_report_delay);
+    assert_eq!(test.orderbooks, de.orderbooks);
+}
+
+#[test]
+pub fn test_currency_mode() {
+    let mut data = Data::new();
+    assert!(data.is_tradable(&(1, 0)));
+    data.set_currency_mode(1, CurrencyMode::WithdrawOnly);
+    assert!(!data.is_tradable(&(1, 0)));
+    assert!(data.currency_mode(1).is_withdrawable());
+    assert!(!data.currency_mode(1).is_depositable());
+    data.set_currency_mode(0, "trade_only".parse().unwrap());
+    assert!(!data.currency_mode(0).is_withdrawable());
+    data.set_currency_mode(1, CurrencyMode::Normal);
+    assert!(data.is_tradable(&(1, 0)));
+    assert_eq!(1, data.currencies.len());
+    assert!(CurrencyMode::from_str("halted").is_err());
+}
+
+#[test]
+pub fn test_copy_on_write() {
+    use rust_decimal_macros::dec;
+
+    let mut data = Data::new();
+    for symbol in [(1, 0), (2, 0)] {
+        data.orderbooks.insert(
+            symbol,
+            OrderBook::new(
+                3,
+                3,
+                dec!(0.001),
+                dec!(0.001),
+                dec!(0.001),
+                dec!(0.001),
+                1,
+                dec!(0.001),
+                dec!(1.0),
+                false,
+                true,
+            )
+            .into(),
+        );
+    }
+    let dumping = data.clone();
+    assert!(data.accounts.is_shared());
+    data.orderbooks.get_mut(&(1, 0)).unwrap().open = false;
+    crate::assets::add_to_available(&mut data.accounts, &UserId::zero(), 1, dec!(1)).unwrap();
+    assert!(!data.accounts.is_shared());
+    // only the chunk of the user is copied
+    assert_eq!(1, data.accounts.diverged(&dumping.accounts));
+    assert!(dumping.accounts.is_empty());
+    assert!(dumping.orderbooks.get(&(1, 0)).unwrap().open);
+    assert!(!data.orderbooks.get(&(1, 0)).unwrap().is_shared());
+    // untouched
+    assert!(data.orderbooks.get(&(2, 0)).unwrap().is_shared());
+    assert!(data.merkle_tree.is_shared());
+}
+
+#[test]
+pub fn test_session_progress() {
+    let mut ephemeral = Ephemeral::new();
+    ephemeral.ack(3, 10);
+    ephemeral.ack(3, 12);
+    // the same event acked after replaying
+    ephemeral.ack(3, 11);
+    assert_eq!(Some(12), ephemeral.get_session_progress(3));
+    assert_eq!(None, ephemeral.get_session_progress(4));
+    (0..SESSION_PROGRESS_CAPACITY as u64).for_each(|s| ephemeral.ack(s + 4, 1));
+    assert_eq!(None, ephemeral.get_session_progress(3));
+    assert_eq!(Some(1), ephemeral.get_session_progress(4));
+}
+
+#[test]
+pub fn test_order_sessions() {
+    let mut ephemeral = Ephemeral::new();
+    let symbol = (1, 0);
+    ephemeral.rest_order(symbol, 7, 3);
+    let mut shard = ephemeral.fork(&[symbol]);
+    assert_eq!(None, ephemeral.order_session(symbol, 7, false));
+    assert_eq!(Some(3), shard.order_session(symbol, 7, false));
+    ephemeral.join(shard);
+    assert_eq!(Some(3), ephemeral.order_session(symbol, 7, true));
+    assert_eq!(None, ephemeral.order_session(symbol, 7, false));
 }
 
 #[test]
@@ -0,0 +1,183 @@
+// Copyright 2021-2023 UINB Technologies Pte. Ltd.
+
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// 	http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::{core::*, orders::PendingOrder};
+use anyhow::anyhow;
+use rocksdb::{Direction, IteratorMode};
+use serde::{Deserialize, Serialize};
+
+pub const MAX_ORDER_HISTORY: usize = 1000;
+
+pub const DEFAULT_ORDER_HISTORY: usize = 100;
+
+/// the filled or canceled order with the accumulated fills and fees
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ClosedOrder {
+    pub order: PendingOrder,
+    pub event_id: u64,
+    pub closed_at: Timestamp,
+}
+
+/// the closed orders sorted by `(closed_at, order_id)`, `next` is the cursor of the remaining ones
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct OrderHistoryPage {
+    pub orders: Vec<ClosedOrder>,
+    pub truncated: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub next: Option<String>,
+}
+
+fn history_prefix(user_id: &UserId, symbol: &Symbol) -> Vec<u8> {
+    [
+        &b"ordrhist"[..],
+        &user_id.0[..],
+        &symbol.0.to_be_bytes()[..],
+        &symbol.1.to_be_bytes()[..],
+    ]
+    .concat()
+}
+
+fn history_key(prefix: &[u8], closed_at: Timestamp, order_id: OrderId) -> Vec<u8> {
+    [
+        prefix,
+        &closed_at.to_be_bytes()[..],
+        &order_id.to_be_bytes()[..],
+    ]
+    .concat()
+}
+
+/// `<closed_at>:<order_id>` of the first order to resume from
+fn parse_cursor(cursor: &str) -> anyhow::Result<(Timestamp, OrderId)> {
+    let (t, id) = cursor
+        .split_once(':')
+        .ok_or(anyhow!("invalid cursor {}", cursor))?;
+    Ok((t.parse()?, id.parse()?))
+}
+
+/// the replayed orders are written again with the same keys
+pub fn archive(
+    db: &rocksdb::DB,
+    order: PendingOrder,
+    event_id: u64,
+    closed_at: Timestamp,
+) -> anyhow::Result<()> {
+    let key = history_key(
+        &history_prefix(&order.user_id, &order.symbol),
+        closed_at,
+        order.order_id,
+    );
+    let closed = ClosedOrder {
+        order,
+        event_id,
+        closed_at,
+    };
+    db.put(&key, bincode::serialize(&closed)?)?;
+    Ok(())
+}
+
+/// the orders closed in `[from, to]`
+pub fn query(
+    db: &rocksdb::DB,
+    user_id: &UserId,
+    symbol: &Symbol,
+    from: Option<Timestamp>,
+    to: Option<Timestamp>,
+    cursor: Option<&str>,
+    limit: usize,
+) -> anyhow::Result<OrderHistoryPage> {
+    let prefix = history_prefix(user_id, symbol);
+    let (from, from_id) = match cursor.map(parse_cursor).transpose()? {
+        Some((t, id)) if t >= from.unwrap_or_default() => (t, id),
+        _ => (from.unwrap_or_default(), 0),
+    };
+    let start = history_key(&prefix, from, from_id);
+    let mut page = OrderHistoryPage::default();
+    for item in db.iterator(IteratorMode::From(&start, Direction::Forward)) {
+        let (key, value) = item?;
+        if !key.starts_with(&prefix) {
+            break;
+        }
+        let closed = bincode::deserialize::<ClosedOrder>(&value)?;
+        if to.map_or(false, |t| closed.closed_at > t) {
+            break;
+        }
+        if page.orders.len() == limit {
+            page.truncated = true;
+            page.next = Some(format!("{}:{}", closed.closed_at, closed.order.order_id));
+            break;
+        }
+        page.orders.push(closed);
+    }
+    Ok(page)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use rust_decimal::{prelude::Zero, Decimal};
+
+    fn order(order_id: OrderId, symbol: Symbol) -> PendingOrder {
+        PendingOrder {
+            order_id,
+            user_id: UserId::zero(),
+            symbol,
+            direction: 0,
+            create_timestamp: 0,
+            amount: Decimal::zero(),
+            price: Decimal::zero(),
+            status: 0,
+            matched_quote_amount: Decimal::zero(),
+            matched_base_amount: Decimal::zero(),
+            base_fee: Decimal::zero(),
+            quote_fee: Decimal::zero(),
+        }
+    }
+
+    #[test]
+    pub fn test_order_history() {
+        let dir = tempdir::TempDir::new("galois-archive").unwrap();
+        let db = rocksdb::DB::open_default(dir.path()).unwrap();
+        for (id, t) in [(3, 100), (1, 100), (2, 90), (4, 200), (5, 300)] {
+            archive(&db, order(id, (1, 0)), id, t).unwrap();
+        }
+        archive(&db, order(6, (2, 0)), 6, 100).unwrap();
+        // replayed
+        archive(&db, order(4, (1, 0)), 4, 200).unwrap();
+        let ids = |page: &OrderHistoryPage| {
+            page.orders
+                .iter()
+                .map(|o| o.order.order_id)
+                .collect::<Vec<_>>()
+        };
+        let user = UserId::zero();
+        let page = query(&db, &user, &(1, 0), None, None, None, 2).unwrap();
+        assert_eq!(vec![2, 1], ids(&page));
+        assert!(page.truncated);
+        assert_eq!(Some("100:3".to_string()), page.next);
+        let page = query(&db, &user, &(1, 0), None, None, page.next.as_deref(), 2).unwrap();
+        assert_eq!(vec![3, 4], ids(&page));
+        let page = query(&db, &user, &(1, 0), None, None, page.next.as_deref(), 2).unwrap();
+        assert_eq!(vec![5], ids(&page));
+        assert!(!page.truncated);
+        assert_eq!(None, page.next);
+        let page = query(&db, &user, &(1, 0), Some(100), Some(200), None, 10).unwrap();
+        assert_eq!(vec![1, 3, 4], ids(&page));
+        assert!(!page.truncated);
+        assert_eq!(
+            1,
+            ids(&query(&db, &user, &(2, 0), None, None, None, 10).unwrap()).len()
+        );
+        assert!(query(&db, &user, &(1, 0), None, None, Some("x"), 10).is_err());
+    }
+}
@@ -0,0 +1,124 @@
+// Copyright 2021-2023 UINB Technologies Pte. Ltd.
+
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// 	http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::core::*;
+use rust_decimal::prelude::Zero;
+use serde::{Deserialize, Serialize};
+
+const DAY: u64 = 86400;
+
+/// the takers of a symbol are rejected until `halted_until`, which is `Timestamp::MAX` if halted by
+/// the operators
+#[derive(Clone, Debug, Default, Serialize, Deserialize, Eq, PartialEq)]
+pub struct Breaker {
+    pub halted_until: Timestamp,
+    /// the price of the first trade in the window, zero if the window isn't started
+    pub reference: Price,
+    pub window_start: Timestamp,
+}
+
+impl Breaker {
+    pub fn is_halted(&self, time: Timestamp) -> bool {
+        time < self.halted_until
+    }
+
+    /// return `true` if the trade moves the price more than `max_move` from the reference
+    pub fn trade(&mut self, price: Price, time: Timestamp, config: &CircuitBreaker) -> bool {
+        if self.reference.is_zero() || time >= self.window_start.saturating_add(config.window) {
+            self.reference = price;
+            self.window_start = time;
+            return false;
+        }
+        if (price - self.reference).abs() <= self.reference * config.max_move {
+            return false;
+        }
+        self.halted_until = self.halted_until.max(time.saturating_add(config.halt));
+        // a new window starts after resuming
+        self.reference = Price::zero();
+        true
+    }
+}
+
+/// `"HH:MM-HH:MM"` in UTC to the seconds of the day, the window crosses the midnight if `from > to`
+pub fn parse_window(window: &str) -> Option<(u64, u64)> {
+    let seconds = |t: &str| {
+        let (h, m) = t.trim().split_once(':')?;
+        let (h, m) = (h.parse::<u64>().ok()?, m.parse::<u64>().ok()?);
+        (h <= 24 && m < 60 && h * 3600 + m * 60 <= DAY).then_some(h * 3600 + m * 60)
+    };
+    let (from, to) = window.split_once('-')?;
+    let (from, to) = (seconds(from)?, seconds(to)?);
+    (from != to).then_some((from, to))
+}
+
+/// always open if no windows configured
+pub fn is_trading_hours(windows: &[String], time: Timestamp) -> bool {
+    let t = time % DAY;
+    windows.is_empty()
+        || windows
+            .iter()
+            .filter_map(|w| parse_window(w))
+            .any(|(from, to)| match from < to {
+                true => from <= t && t < to,
+                false => from <= t || t < to,
+            })
+}
+
+/// the limit order would be matched on arrival
+pub fn is_crossing(orderbook: &OrderBook, price: Price, ask_or_bid: AskOrBid) -> bool {
+    match ask_or_bid {
+        AskOrBid::Bid => orderbook.get_best_ask().filter(|a| price >= *a).is_some(),
+        AskOrBid::Ask => orderbook.get_best_bid().filter(|b| price <= *b).is_some(),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use rust_decimal_macros::dec;
+
+    #[test]
+    pub fn test_circuit_breaker() {
+        let config = CircuitBreaker {
+            max_move: dec!(0.1),
+            window: 60,
+            halt: 300,
+        };
+        let mut breaker = Breaker::default();
+        assert!(!breaker.trade(dec!(100), 1000, &config));
+        assert!(!breaker.trade(dec!(109), 1010, &config));
+        assert!(!breaker.trade(dec!(91), 1020, &config));
+        // a new window from 1060
+        assert!(!breaker.trade(dec!(120), 1060, &config));
+        assert!(!breaker.is_halted(1061));
+        assert!(breaker.trade(dec!(107.9), 1070, &config));
+        assert!(breaker.is_halted(1071));
+        assert!(breaker.is_halted(1369));
+        assert!(!breaker.is_halted(1370));
+        assert!(!breaker.trade(dec!(80), 1370, &config));
+        assert_eq!(dec!(80), breaker.reference);
+
+        assert_eq!(Some((34200, 57600)), parse_window("09:30-16:00"));
+        assert_eq!(Some((79200, 86400)), parse_window("22:00-24:00"));
+        assert!(parse_window("09:30").is_none());
+        assert!(parse_window("09:60-10:00").is_none());
+        assert!(parse_window("10:00-10:00").is_none());
+        let windows = vec!["09:30-16:00".to_string(), "22:00-02:00".to_string()];
+        assert!(is_trading_hours(&[], 0));
+        assert!(is_trading_hours(&windows, 34200));
+        assert!(!is_trading_hours(&windows, 57600));
+        assert!(is_trading_hours(&windows, DAY * 3 + 3600));
+        assert!(!is_trading_hours(&windows, DAY * 3 + 7200));
+    }
+}
@@ -0,0 +1,79 @@
+// Copyright 2021-2023 UINB Technologies Pte. Ltd.
+
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// 	http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::core::*;
+use std::collections::{HashMap, VecDeque};
+
+/// the ids remembered of each user and symbol, the least recently used are forgotten first
+pub const MAX_CLIENT_ORDER_IDS: usize = 100;
+
+pub const MAX_CLIENT_ORDER_ID_LEN: usize = 64;
+
+/// the recent client order ids of the users and the orders accepted with them
+pub type ClientOrders = HashMap<Symbol, HashMap<UserId, VecDeque<(String, OrderId)>>>;
+
+/// the order accepted with the id before, which is used again
+pub fn find(
+    ids: &mut ClientOrders,
+    symbol: &Symbol,
+    user_id: &UserId,
+    client_order_id: &str,
+) -> Option<OrderId> {
+    let recent = ids.get_mut(symbol)?.get_mut(user_id)?;
+    let i = recent.iter().position(|(c, _)| c == client_order_id)?;
+    let used = recent.remove(i).expect("found;qed");
+    let order_id = used.1;
+    recent.push_back(used);
+    Some(order_id)
+}
+
+pub fn record(
+    ids: &mut ClientOrders,
+    symbol: &Symbol,
+    user_id: &UserId,
+    client_order_id: String,
+    order_id: OrderId,
+) {
+    let recent = ids.entry(*symbol).or_default().entry(*user_id).or_default();
+    recent.push_back((client_order_id, order_id));
+    if recent.len() > MAX_CLIENT_ORDER_IDS {
+        recent.pop_front();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    pub fn test_client_order_ids() {
+        let mut ids = ClientOrders::new();
+        let (alice, bob) = (UserId::from_low_u64_be(1), UserId::from_low_u64_be(2));
+        let symbol = (1, 0);
+        record(&mut ids, &symbol, &alice, "a-0".to_string(), 1);
+        assert_eq!(Some(1), find(&mut ids, &symbol, &alice, "a-0"));
+        assert_eq!(None, find(&mut ids, &symbol, &bob, "a-0"));
+        assert_eq!(None, find(&mut ids, &(2, 0), &alice, "a-0"));
+        for i in 1..MAX_CLIENT_ORDER_IDS as u64 {
+            record(&mut ids, &symbol, &alice, format!("a-{}", i), i + 1);
+        }
+        // used recently
+        assert_eq!(Some(1), find(&mut ids, &symbol, &alice, "a-0"));
+        record(&mut ids, &symbol, &alice, "a-new".to_string(), 1000);
+        assert_eq!(Some(1), find(&mut ids, &symbol, &alice, "a-0"));
+        assert_eq!(None, find(&mut ids, &symbol, &alice, "a-1"));
+        assert_eq!(Some(3), find(&mut ids, &symbol, &alice, "a-2"));
+        assert_eq!(MAX_CLIENT_ORDER_IDS, ids[&symbol][&alice].len());
+    }
+}
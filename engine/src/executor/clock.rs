@@ -0,0 +1,73 @@
+// Copyright 2021-2023 UINB Technologies Pte. Ltd.
+
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// 	http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::core::Timestamp;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+static LAST: AtomicU64 = AtomicU64::new(0);
+
+const MICROS: u64 = 1_000_000;
+
+fn now_micros() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .expect("after 1970;qed")
+        .as_micros() as u64
+}
+
+/// the microseconds of matching an event sequenced at `time` in seconds, strictly increasing
+/// across the shards. the wall clock is taken only within the sequenced second, so the replayed
+/// events are stamped at the start of their seconds and the bars built from them won't drift.
+pub fn stamp(time: Timestamp) -> u64 {
+    let now = now_micros();
+    let prev = LAST
+        .fetch_update(Ordering::AcqRel, Ordering::Acquire, |last| {
+            Some(next(last, time, now))
+        })
+        .expect("always some;qed");
+    next(prev, time, now)
+}
+
+/// the stamp following `last` of an event sequenced at `time`, given the wall clock `now`
+fn next(last: u64, time: Timestamp, now: u64) -> u64 {
+    let floor = time.saturating_mul(MICROS);
+    let candidate = if now >= floor && now - floor < MICROS {
+        now
+    } else {
+        floor
+    };
+    candidate.max(last.saturating_add(1))
+}
+
+#[cfg(test)]
+mod test {
+
+    #[test]
+    pub fn test_stamp() {
+        use super::{next, MICROS};
+        let now = 1_672_531_200_250_000;
+        let time = now / MICROS;
+        let a = next(0, time, now);
+        assert_eq!(a, now);
+        let b = next(a, time, now);
+        assert_eq!(b, a + 1);
+        // replayed long after sequencing
+        let c = next(b, time - 3600, now);
+        assert_eq!(c, b + 1);
+        assert_eq!(next(0, time - 3600, now), (time - 3600) * MICROS);
+        let d = next(c, time + 60, now);
+        assert_eq!(d, (time + 60) * MICROS);
+        assert_eq!(next(d, time + 60, now), d + 1);
+    }
+}
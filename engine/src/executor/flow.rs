@@ -0,0 +1,368 @@
+// Copyright 2021-2023 UINB Technologies Pte. Ltd.
+
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// 	http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::{
+    core::*,
+    matcher::Role,
+    output::{
+        canonical::{fixed, CURRENCY_SCALE},
+        Output,
+    },
+};
+use parity_scale_codec::Encode;
+use rocksdb::WriteBatchWithTransaction;
+use rust_decimal::{prelude::Zero, Decimal};
+use serde::{Deserialize, Serialize};
+use sp_core::{crypto::Ss58Codec, sr25519, Pair};
+use std::{
+    collections::{hash_map::Entry, HashMap},
+    sync::mpsc::Sender,
+};
+
+/// the broker flow is settled by utc days
+pub const EPOCH_SECS: u64 = 86400;
+
+// the signed payloads are prefixed, so a settlement isn't mistaken for the other signatures of
+// the prover key, nor a query for the other signatures of the broker
+const SETTLEMENT_DOMAIN: &[u8] = b"galois/broker-flow";
+const QUERY_DOMAIN: &[u8] = b"galois/broker-flow-query";
+
+lazy_static::lazy_static! {
+    static ref RECORDER: Sender<Taker> = start_recorder();
+}
+
+pub const fn epoch_of(timestamp: Timestamp) -> u64 {
+    timestamp / EPOCH_SECS
+}
+
+/// the matched taker orders originated by a broker in a symbol during an epoch
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub struct BrokerFlow {
+    pub symbol: Symbol,
+    /// the replayed events are skipped
+    pub last_event_id: u64,
+    pub orders: u64,
+    pub base_volume: Amount,
+    pub quote_volume: Amount,
+    pub base_fee: Amount,
+    pub quote_fee: Amount,
+}
+
+impl BrokerFlow {
+    pub fn new(symbol: Symbol) -> Self {
+        Self {
+            symbol,
+            last_event_id: 0,
+            orders: 0,
+            base_volume: Decimal::zero(),
+            quote_volume: Decimal::zero(),
+            base_fee: Decimal::zero(),
+            quote_fee: Decimal::zero(),
+        }
+    }
+
+    /// accumulate the taker of an event, `false` if the event is applied already
+    pub fn apply(&mut self, event_id: u64, outputs: &[Output]) -> bool {
+        if event_id <= self.last_event_id {
+            return false;
+        }
+        let mut filled = false;
+        for o in outputs.iter().filter(|o| o.role == Role::Taker) {
+            self.base_volume += o.base_delta.abs();
+            self.quote_volume += o.quote_delta.abs();
+            self.base_fee += o.base_charge.abs();
+            self.quote_fee += o.quote_charge.abs();
+            filled |= !o.base_delta.is_zero();
+        }
+        if filled {
+            self.orders += 1;
+        }
+        self.last_event_id = event_id;
+        true
+    }
+
+    fn canonical(mut self) -> Self {
+        self.base_volume = fixed(self.base_volume, CURRENCY_SCALE);
+        self.quote_volume = fixed(self.quote_volume, CURRENCY_SCALE);
+        self.base_fee = fixed(self.base_fee, CURRENCY_SCALE);
+        self.quote_fee = fixed(self.quote_fee, CURRENCY_SCALE);
+        self
+    }
+}
+
+/// the flows of a broker during an epoch signed by the prover, the `signature` is made over
+/// `galois/broker-flow` followed by the SCALE encoded `(broker, epoch, [(base, quote, orders,
+/// base_volume, quote_volume, base_fee, quote_fee)])` with the decimals as the strings in the
+/// report
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub struct BrokerSettlement {
+    pub broker: String,
+    pub epoch: u64,
+    pub flows: Vec<BrokerFlow>,
+    pub signer: String,
+    pub signature: String,
+}
+
+impl BrokerSettlement {
+    fn payload(broker: &UserId, epoch: u64, flows: &[BrokerFlow]) -> Vec<u8> {
+        let flows = flows
+            .iter()
+            .map(|f| {
+                (
+                    f.symbol.0,
+                    f.symbol.1,
+                    f.orders,
+                    f.base_volume.to_string(),
+                    f.quote_volume.to_string(),
+                    f.base_fee.to_string(),
+                    f.quote_fee.to_string(),
+                )
+            })
+            .collect::<Vec<_>>();
+        [SETTLEMENT_DOMAIN, &(broker.0, epoch, flows).encode()].concat()
+    }
+
+    pub fn sign(
+        broker: &UserId,
+        epoch: u64,
+        flows: Vec<BrokerFlow>,
+        signer: &sr25519::Pair,
+    ) -> Self {
+        let flows = flows
+            .into_iter()
+            .map(BrokerFlow::canonical)
+            .collect::<Vec<_>>();
+        let signature = signer.sign(&Self::payload(broker, epoch, &flows));
+        Self {
+            broker: broker.to_string(),
+            epoch,
+            flows,
+            signer: signer.public().to_ss58check(),
+            signature: format!("0x{}", hex::encode(signature.0)),
+        }
+    }
+
+    pub fn verify(&self) -> anyhow::Result<bool> {
+        let broker: UserId = self.broker.parse()?;
+        let signer = sr25519::Public::from_ss58check(&self.signer)
+            .map_err(|_| anyhow::anyhow!("invalid signer"))?;
+        let mut signature = [0u8; 64];
+        hex::decode_to_slice(self.signature.trim_start_matches("0x"), &mut signature)?;
+        Ok(sr25519::Pair::verify(
+            &sr25519::Signature::from_raw(signature),
+            Self::payload(&broker, self.epoch, &self.flows),
+            &signer,
+        ))
+    }
+}
+
+fn query_payload(broker: &UserId, epoch: u64) -> Vec<u8> {
+    [QUERY_DOMAIN, &(broker.0, epoch).encode()].concat()
+}
+
+/// the flows are only settled for the broker, who signs `galois/broker-flow-query` followed by
+/// the SCALE encoded `(broker, epoch)`
+pub fn verify_query(broker: &UserId, epoch: u64, signature: &str) -> anyhow::Result<()> {
+    let mut sig = [0u8; 64];
+    hex::decode_to_slice(signature.trim_start_matches("0x"), &mut sig)?;
+    anyhow::ensure!(
+        sr25519::Pair::verify(
+            &sr25519::Signature::from_raw(sig),
+            query_payload(broker, epoch),
+            &sr25519::Public::from_raw(broker.0),
+        ),
+        "invalid signature of the broker"
+    );
+    Ok(())
+}
+
+fn flow_prefix(broker: &UserId, epoch: u64) -> Vec<u8> {
+    [&b"brkrflow"[..], &broker.0[..], &epoch.to_be_bytes()[..]].concat()
+}
+
+fn flow_key(broker: &UserId, epoch: u64, symbol: &Symbol) -> Vec<u8> {
+    [
+        &flow_prefix(broker, epoch)[..],
+        &symbol.0.to_be_bytes()[..],
+        &symbol.1.to_be_bytes()[..],
+    ]
+    .concat()
+}
+
+// a taker order originated by a broker, accumulated by the recorder
+struct Taker {
+    broker: UserId,
+    symbol: Symbol,
+    event_id: u64,
+    timestamp: Timestamp,
+    outputs: Vec<Output>,
+}
+
+/// queue the flow of a taker order originated by `broker`, persisted to the output store by the
+/// recorder thread so the matching never waits for the store
+pub fn record(
+    broker: &UserId,
+    symbol: &Symbol,
+    event_id: u64,
+    timestamp: Timestamp,
+    outputs: &[Output],
+) -> anyhow::Result<()> {
+    let taker = Taker {
+        broker: *broker,
+        symbol: *symbol,
+        event_id,
+        timestamp,
+        outputs: outputs
+            .iter()
+            .filter(|o| o.role == Role::Taker)
+            .cloned()
+            .collect(),
+    };
+    RECORDER
+        .send(taker)
+        .map_err(|_| anyhow::anyhow!("the recorder of broker flows stopped"))
+}
+
+fn start_recorder() -> Sender<Taker> {
+    // unbounded, the bursts are queued rather than blocking the executor
+    let (tx, rx) = std::sync::mpsc::channel::<Taker>();
+    std::thread::Builder::new()
+        .name("broker-flow".to_string())
+        .spawn(move || {
+            while let Ok(first) = rx.recv() {
+                // the takers queued while writing are written as one batch
+                let takers = std::iter::once(first).chain(rx.try_iter());
+                if let Err(e) = persist(takers) {
+                    log::error!("unable to record the flows of brokers, {:?}", e);
+                }
+            }
+        })
+        .expect("spawn;qed");
+    tx
+}
+
+fn persist(takers: impl Iterator<Item = Taker>) -> anyhow::Result<()> {
+    let mut flows = HashMap::<Vec<u8>, (BrokerFlow, bool)>::new();
+    for taker in takers {
+        let key = flow_key(&taker.broker, epoch_of(taker.timestamp), &taker.symbol);
+        let (flow, changed) = match flows.entry(key) {
+            Entry::Occupied(e) => e.into_mut(),
+            Entry::Vacant(e) => {
+                let flow = match OUTPUT_STORE.get(e.key())? {
+                    Some(v) => bincode::deserialize::<BrokerFlow>(&v)?,
+                    None => BrokerFlow::new(taker.symbol),
+                };
+                e.insert((flow, false))
+            }
+        };
+        *changed |= flow.apply(taker.event_id, &taker.outputs);
+    }
+    let mut batch = WriteBatchWithTransaction::<false>::default();
+    for (key, (flow, _)) in flows.into_iter().filter(|(_, (_, changed))| *changed) {
+        batch.put(key, bincode::serialize(&flow)?);
+    }
+    OUTPUT_STORE.write(batch)?;
+    Ok(())
+}
+
+pub fn query(broker: &UserId, epoch: u64) -> anyhow::Result<Vec<BrokerFlow>> {
+    let prefix = flow_prefix(broker, epoch);
+    let mut flows = vec![];
+    for item in OUTPUT_STORE.prefix_iterator(&prefix) {
+        let (key, value) = item?;
+        if !key.starts_with(&prefix) {
+            break;
+        }
+        flows.push(bincode::deserialize(&value)?);
+    }
+    Ok(flows)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::orderbook::AskOrBid;
+    use rust_decimal_macros::dec;
+    use std::str::FromStr;
+
+    fn taker(event_id: u64, base: Amount, quote: Amount, fee: Amount) -> Output {
+        Output {
+            event_id,
+            order_id: 1,
+            user_id: UserId::zero(),
+            symbol: (1, 0),
+            state: OrderState::Filled,
+            role: Role::Taker,
+            ask_or_bid: AskOrBid::Bid,
+            price: dec!(10),
+            quote_charge: Decimal::zero(),
+            quote_delta: -quote,
+            quote_available: Decimal::zero(),
+            quote_frozen: Decimal::zero(),
+            base_charge: -fee,
+            base_delta: base,
+            base_available: Decimal::zero(),
+            base_frozen: Decimal::zero(),
+            timestamp: 0,
+            timestamp_us: 0,
+        }
+    }
+
+    #[test]
+    pub fn test_broker_flow() {
+        assert_eq!(1, epoch_of(EPOCH_SECS));
+        let mut flow = BrokerFlow::new((1, 0));
+        assert!(flow.apply(1, &[taker(1, dec!(1), dec!(10), dec!(0.001))]));
+        // placed without filling
+        assert!(flow.apply(2, &[taker(2, dec!(0), dec!(0), dec!(0))]));
+        assert!(flow.apply(3, &[taker(3, dec!(2), dec!(19), dec!(0.002))]));
+        // replayed
+        assert!(!flow.apply(3, &[taker(3, dec!(2), dec!(19), dec!(0.002))]));
+        assert_eq!(2, flow.orders);
+        assert_eq!(dec!(3), flow.base_volume);
+        assert_eq!(dec!(29), flow.quote_volume);
+        assert_eq!(dec!(0.003), flow.base_fee);
+
+        let signer = sr25519::Pair::from_string("//Alice", None).unwrap();
+        let broker = UserId::from_str("5DaYdJ1fXoFetSCaA44PrK6iQeTwg9AtjzLrxaQXooRrx9RK").unwrap();
+        let report = BrokerSettlement::sign(&broker, 19000, vec![flow], &signer);
+        assert_eq!(
+            "3.000000000000000000",
+            report.flows[0].base_volume.to_string()
+        );
+        assert!(report.verify().unwrap());
+        let json = serde_json::to_string(&report).unwrap();
+        let mut tampered = serde_json::from_str::<BrokerSettlement>(&json).unwrap();
+        assert!(tampered.verify().unwrap());
+        tampered.flows[0].quote_fee = dec!(1);
+        assert!(!tampered.verify().unwrap());
+
+        let sig = format!(
+            "0x{}",
+            hex::encode(signer.sign(&query_payload(&broker, 19000)).0)
+        );
+        // signed by alice rather than the broker
+        assert!(verify_query(&broker, 19000, &sig).is_err());
+        let broker = UserId::new(signer.public().0);
+        let sig = format!(
+            "0x{}",
+            hex::encode(signer.sign(&query_payload(&broker, 19000)).0)
+        );
+        assert!(verify_query(&broker, 19000, &sig).is_ok());
+        assert!(verify_query(&broker, 19001, &sig).is_err());
+        // the settlement can't pass as a query
+        let report = BrokerSettlement::sign(&broker, 19000, vec![], &signer);
+        assert!(verify_query(&broker, 19000, &report.signature).is_err());
+    }
+}
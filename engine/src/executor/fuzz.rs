@@ -0,0 +1,244 @@
+// Copyright 2021-2023 UINB Technologies Pte. Ltd.
+
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// 	http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use super::Replayer;
+use crate::{
+    assets, config,
+    core::*,
+    input::{AssetsCmd, CancelCmd, Event, LimitCmd, SymbolCmd},
+    matcher::TimeInForce,
+    verify,
+};
+use proptest::prelude::*;
+use rust_decimal::Decimal;
+use rust_decimal_macros::dec;
+use std::collections::BTreeMap;
+
+const SYMBOL: Symbol = (1, 0);
+
+const USERS: u64 = 4;
+
+/// the operations on the symbol, the amounts are in the units of the scales below
+#[derive(Debug, Clone, Copy)]
+enum Op {
+    Deposit(u64, Currency, i64),
+    Withdraw(u64, Currency, i64),
+    Limit {
+        user: u64,
+        ask_or_bid: AskOrBid,
+        price: i64,
+        amount: i64,
+        time_in_force: TimeInForce,
+        display: i64,
+    },
+    /// the nth of the resting orders cancelled by the owner
+    Cancel(usize),
+}
+
+fn op() -> impl Strategy<Value = Op> {
+    let side = prop_oneof![Just(AskOrBid::Ask), Just(AskOrBid::Bid)];
+    let time_in_force = prop_oneof![
+        3 => Just(TimeInForce::GoodTillCancel),
+        1 => Just(TimeInForce::ImmediateOrCancel),
+        1 => Just(TimeInForce::FillOrKill),
+    ];
+    prop_oneof![
+        1 => (1..=USERS, 0..=1u32, 1..=100_000i64).prop_map(|(u, c, a)| Op::Deposit(u, c, a)),
+        1 => (1..=USERS, 0..=1u32, 1..=100_000i64).prop_map(|(u, c, a)| Op::Withdraw(u, c, a)),
+        6 => (
+            1..=USERS,
+            side,
+            95..=105i64,
+            1..=50i64,
+            time_in_force,
+            prop_oneof![4 => Just(0i64), 1 => 1..=10i64],
+        )
+            .prop_map(|(user, ask_or_bid, price, amount, time_in_force, display)| Op::Limit {
+                user,
+                ask_or_bid,
+                price,
+                amount,
+                time_in_force,
+                display,
+            }),
+        2 => any::<usize>().prop_map(Op::Cancel),
+    ]
+}
+
+fn symbol(maker_fee: Fee) -> SymbolCmd {
+    SymbolCmd {
+        symbol: SYMBOL,
+        open: true,
+        base_scale: 1,
+        quote_scale: 1,
+        taker_fee: dec!(0.002),
+        maker_fee,
+        base_maker_fee: maker_fee.max(Fee::ZERO),
+        base_taker_fee: dec!(0.002),
+        fee_times: 1,
+        min_amount: dec!(0.1),
+        min_vol: Decimal::ZERO,
+        enable_market_order: true,
+        broker_share: None,
+        tick_size: None,
+        lot_size: None,
+        price_band: None,
+        max_open_notional: None,
+        max_open_orders: None,
+        trading_hours: None,
+        circuit_breaker: None,
+        block_trade_min_amount: None,
+        block_trade_report_delay: None,
+        self_trade_prevention: None,
+        timestamp: 0,
+        actor: "fuzz".to_string(),
+    }
+}
+
+fn transfer(id: u64, user: u64, currency: Currency, amount: i64, in_or_out: InOrOut) -> AssetsCmd {
+    AssetsCmd {
+        user_id: UserId::from_low_u64_be(user),
+        in_or_out,
+        currency,
+        amount: Decimal::new(amount, 2),
+        block_number: id as u32,
+        extrinsic_hash: vec![],
+        timestamp: 0,
+    }
+}
+
+/// the event of the operation, `None` if nothing to cancel
+fn to_event(id: u64, op: &Op, data: &Data) -> Option<Event> {
+    match *op {
+        Op::Deposit(user, currency, amount) => Some(Event::TransferIn(
+            id,
+            transfer(id, user, currency, amount, InOrOut::In),
+        )),
+        Op::Withdraw(user, currency, amount) => Some(Event::TransferOut(
+            id,
+            transfer(id, user, currency, amount, InOrOut::Out),
+        )),
+        Op::Limit {
+            user,
+            ask_or_bid,
+            price,
+            amount,
+            time_in_force,
+            display,
+        } => {
+            let cmd = LimitCmd {
+                symbol: SYMBOL,
+                user_id: UserId::from_low_u64_be(user),
+                price: Decimal::new(price, 1),
+                amount: Decimal::new(amount, 1),
+                ask_or_bid,
+                nonce: 0,
+                signature: vec![],
+                broker: None,
+                time_in_force,
+                self_trade_prevention: None,
+                vol: None,
+                display: (display > 0 && time_in_force == TimeInForce::GoodTillCancel)
+                    .then(|| Decimal::new(display, 1)),
+                oco: None,
+                client_order_id: None,
+            };
+            Some(Event::Limit(id, cmd, 0, 0, 0))
+        }
+        Op::Cancel(nth) => {
+            let orderbook = data.orderbooks.get(&SYMBOL)?;
+            let mut ids = orderbook.indices.keys().copied().collect::<Vec<_>>();
+            ids.sort_unstable();
+            let order_id = *ids.get(nth % ids.len().max(1))?;
+            let cmd = CancelCmd {
+                symbol: SYMBOL,
+                user_id: orderbook.find_order(order_id)?.user,
+                order_id,
+                nonce: 0,
+                signature: vec![],
+            };
+            Some(Event::Cancel(id, cmd, 0, 0, 0))
+        }
+    }
+}
+
+/// execute the operations and check the invariants of `verify` after each, i.e. no negative
+/// balances, the frozen required by the resting orders, the pages summing up the orders and the
+/// merkle leaves of the balances and the book, along with the sums of the currencies
+fn execute(maker_fee: Fee, ops: Vec<Op>) -> Result<(), TestCaseError> {
+    config::install_example();
+    let mut data = Data::new();
+    let mut replayer = Replayer::new();
+    replayer
+        .execute(Event::UpdateSymbol(1, symbol(maker_fee)), &mut data)
+        .map_err(|e| TestCaseError::fail(e.to_string()))?;
+    let mut totals = BTreeMap::<Currency, Amount>::new();
+    for (i, op) in ops.iter().enumerate() {
+        let id = i as u64 + 2;
+        let Some(event) = to_event(id, op, &data) else {
+            continue;
+        };
+        let withdrawing = match *op {
+            Op::Withdraw(user, currency, amount) => Some((
+                UserId::from_low_u64_be(user),
+                currency,
+                Decimal::new(amount, 2),
+            )),
+            _ => None,
+        };
+        let before = withdrawing.map(|(user, currency, _)| {
+            assets::get_balance_to_owned(&data.accounts, &user, currency)
+        });
+        replayer
+            .execute(event, &mut data)
+            .map_err(|e| TestCaseError::fail(e.to_string()))?;
+        match (*op, withdrawing, before) {
+            (Op::Deposit(_, currency, amount), _, _) => {
+                *totals.entry(currency).or_default() += Decimal::new(amount, 2)
+            }
+            (_, Some((user, currency, amount)), Some(before)) => {
+                let after = assets::get_balance_to_owned(&data.accounts, &user, currency);
+                let withdrawn = before.available - after.available;
+                prop_assert!(withdrawn.is_zero() || withdrawn == amount);
+                prop_assert!(!withdrawn.is_zero() || before.available < amount);
+                *totals.entry(currency).or_default() -= withdrawn;
+            }
+            _ => {}
+        }
+        let report = verify::verify(&data);
+        prop_assert!(report.passed, "{:?} after {:?}", report.violations, op);
+        for currency in [SYMBOL.0, SYMBOL.1] {
+            prop_assert_eq!(
+                report.tvl.get(&currency).copied().unwrap_or_default(),
+                totals.get(&currency).copied().unwrap_or_default(),
+                "the sum of {} after {:?}",
+                currency,
+                op
+            );
+        }
+    }
+    Ok(())
+}
+
+proptest! {
+    #![proptest_config(ProptestConfig::with_cases(64))]
+
+    #[test]
+    fn test_invariants(
+        maker_fee in prop_oneof![Just(dec!(-0.0005)), Just(Fee::ZERO), Just(dec!(0.001))],
+        ops in prop::collection::vec(op(), 1..120),
+    ) {
+        execute(maker_fee, ops)?;
+    }
+}
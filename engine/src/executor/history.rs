@@ -0,0 +1,314 @@
+// Copyright 2021-2023 UINB Technologies Pte. Ltd.
+
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// 	http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::{core::*, input::SymbolCmd, matcher::SelfTradePrevention};
+use serde::{Deserialize, Serialize};
+use std::{
+    fs::OpenOptions,
+    io::{BufRead, BufReader, Write},
+    path::{Path, PathBuf},
+};
+
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub struct SymbolConfig {
+    pub open: bool,
+    pub base_scale: Scale,
+    pub quote_scale: Scale,
+    pub taker_fee: Fee,
+    pub maker_fee: Fee,
+    pub base_taker_fee: Fee,
+    pub base_maker_fee: Fee,
+    pub fee_times: u32,
+    pub min_amount: Amount,
+    pub min_vol: Vol,
+    pub enable_market_order: bool,
+    #[serde(default)]
+    pub broker_share: Fee,
+    #[serde(default)]
+    pub tick_size: Price,
+    #[serde(default)]
+    pub lot_size: Amount,
+    #[serde(default)]
+    pub price_band: Fee,
+    #[serde(default)]
+    pub self_trade_prevention: SelfTradePrevention,
+    #[serde(default)]
+    pub max_open_notional: Vol,
+    #[serde(default)]
+    pub max_open_orders: u32,
+    #[serde(default)]
+    pub trading_hours: Vec<String>,
+    #[serde(default)]
+    pub circuit_breaker: CircuitBreaker,
+    #[serde(default)]
+    pub block_trade_min_amount: Amount,
+    #[serde(default)]
+    pub block_trade_report_delay: u64,
+}
+
+impl From<&OrderBook> for SymbolConfig {
+    fn from(book: &OrderBook) -> Self {
+        Self {
+            open: book.open,
+            base_scale: book.base_scale,
+            quote_scale: book.quote_scale,
+            taker_fee: book.taker_fee,
+            maker_fee: book.maker_fee,
+            base_taker_fee: book.base_taker_fee,
+            base_maker_fee: book.base_maker_fee,
+            fee_times: book.fee_times,
+            min_amount: book.min_amount,
+            min_vol: book.min_vol,
+            enable_market_order: book.enable_market_order,
+            broker_share: book.broker_share,
+            tick_size: book.tick_size,
+            lot_size: book.lot_size,
+            price_band: book.price_band,
+            self_trade_prevention: book.self_trade_prevention,
+            max_open_notional: book.max_open_notional,
+            max_open_orders: book.max_open_orders,
+            trading_hours: book.trading_hours.clone(),
+            circuit_breaker: book.circuit_breaker,
+            block_trade_min_amount: book.block_trade_min_amount,
+            block_trade_report_delay: book.block_trade_report_delay,
+        }
+    }
+}
+
+impl From<&SymbolCmd> for SymbolConfig {
+    fn from(cmd: &SymbolCmd) -> Self {
+        Self {
+            open: cmd.open,
+            base_scale: cmd.base_scale,
+            quote_scale: cmd.quote_scale,
+            taker_fee: cmd.taker_fee,
+            maker_fee: cmd.maker_fee,
+            base_taker_fee: cmd.base_taker_fee,
+            base_maker_fee: cmd.base_maker_fee,
+            fee_times: cmd.fee_times,
+            min_amount: cmd.min_amount,
+            min_vol: cmd.min_vol,
+            enable_market_order: cmd.enable_market_order,
+            broker_share: cmd.broker_share.unwrap_or_default(),
+            tick_size: cmd.tick_size.unwrap_or_default(),
+            lot_size: cmd.lot_size.unwrap_or_default(),
+            price_band: cmd.price_band.unwrap_or_default(),
+            self_trade_prevention: cmd.self_trade_prevention.unwrap_or_default(),
+            max_open_notional: cmd.max_open_notional.unwrap_or_default(),
+            max_open_orders: cmd.max_open_orders.unwrap_or_default(),
+            trading_hours: cmd.trading_hours.clone().unwrap_or_default(),
+            circuit_breaker: cmd.circuit_breaker.unwrap_or_default(),
+            block_trade_min_amount: cmd.block_trade_min_amount.unwrap_or_default(),
+            block_trade_report_delay: cmd.block_trade_report_delay.unwrap_or_default(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub struct ConfigChange {
+    pub event_id: u64,
+    pub symbol: Symbol,
+    pub timestamp: Timestamp,
+    pub actor: String,
+    /// `None` if the symbol is created by the change
+    pub before: Option<SymbolConfig>,
+    pub after: SymbolConfig,
+}
+
+impl ConfigChange {
+    /// `None` if nothing changed, e.g. the same market event scanned again
+    pub fn diff(
+        event_id: u64,
+        before: Option<&OrderBook>,
+        cmd: &SymbolCmd,
+    ) -> Option<ConfigChange> {
+        let before = before.map(SymbolConfig::from);
+        let mut after = SymbolConfig::from(cmd);
+        // the broker share, tick and lot sizes, price band, self-trade prevention, max open
+        // notional, max open orders, trading hours, circuit breaker and block trade limits are
+        // kept if not specified
+        if let Some(before) = before.as_ref() {
+            if cmd.broker_share.is_none() {
+                after.broker_share = before.broker_share;
+            }
+            if cmd.tick_size.is_none() {
+                after.tick_size = before.tick_size;
+            }
+            if cmd.lot_size.is_none() {
+                after.lot_size = before.lot_size;
+            }
+            if cmd.price_band.is_none() {
+                after.price_band = before.price_band;
+            }
+            if cmd.self_trade_prevention.is_none() {
+                after.self_trade_prevention = before.self_trade_prevention;
+            }
+            if cmd.max_open_notional.is_none() {
+                after.max_open_notional = before.max_open_notional;
+            }
+            if cmd.max_open_orders.is_none() {
+                after.max_open_orders = before.max_open_orders;
+            }
+            if cmd.trading_hours.is_none() {
+                after.trading_hours = before.trading_hours.clone();
+            }
+            if cmd.circuit_breaker.is_none() {
+                after.circuit_breaker = before.circuit_breaker;
+            }
+            if cmd.block_trade_min_amount.is_none() {
+                after.block_trade_min_amount = before.block_trade_min_amount;
+            }
+            if cmd.block_trade_report_delay.is_none() {
+                after.block_trade_report_delay = before.block_trade_report_delay;
+            }
+        }
+        (before.as_ref() != Some(&after)).then(|| ConfigChange {
+            event_id,
+            symbol: cmd.symbol,
+            timestamp: cmd.timestamp,
+            actor: cmd.actor.clone(),
+            before,
+            after,
+        })
+    }
+}
+
+/// the append-only mutations of the markets, one json per line
+#[derive(Debug, Clone, Default)]
+pub struct ConfigHistory {
+    path: Option<PathBuf>,
+    changes: Vec<ConfigChange>,
+}
+
+impl ConfigHistory {
+    pub fn open(path: impl AsRef<Path>) -> anyhow::Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let mut changes = vec![];
+        if path.exists() {
+            let file = std::fs::File::open(&path)?;
+            for line in BufReader::new(file).lines() {
+                let line = line?;
+                if !line.trim().is_empty() {
+                    changes.push(serde_json::from_str(&line)?);
+                }
+            }
+        }
+        Ok(Self {
+            path: Some(path),
+            changes,
+        })
+    }
+
+    /// the events after the last snapshot are replayed on restarting, they are recorded already
+    pub fn record(&mut self, change: ConfigChange) -> anyhow::Result<()> {
+        if self
+            .changes
+            .last()
+            .map_or(false, |c| c.event_id >= change.event_id)
+        {
+            return Ok(());
+        }
+        if let Some(ref path) = self.path {
+            let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+            let mut line = serde_json::to_vec(&change)?;
+            line.push(b'\n');
+            file.write_all(&line)?;
+        }
+        self.changes.push(change);
+        Ok(())
+    }
+
+    pub fn query(&self, symbol: Option<Symbol>) -> Vec<ConfigChange> {
+        self.changes
+            .iter()
+            .filter(|c| symbol.map_or(true, |s| s == c.symbol))
+            .cloned()
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use rust_decimal_macros::dec;
+
+    fn cmd(symbol: Symbol, open: bool, taker_fee: Fee) -> SymbolCmd {
+        SymbolCmd {
+            symbol,
+            open,
+            base_scale: 5,
+            quote_scale: 1,
+            taker_fee,
+            maker_fee: dec!(0.001),
+            base_maker_fee: dec!(0.001),
+            base_taker_fee: dec!(0.001),
+            fee_times: 1,
+            min_amount: dec!(1),
+            min_vol: dec!(1),
+            enable_market_order: false,
+            broker_share: None,
+            tick_size: None,
+            lot_size: None,
+            price_band: None,
+            max_open_notional: None,
+            max_open_orders: None,
+            trading_hours: None,
+            circuit_breaker: None,
+            block_trade_min_amount: None,
+            block_trade_report_delay: None,
+            self_trade_prevention: None,
+            timestamp: 100,
+            actor: "chain@10".to_string(),
+        }
+    }
+
+    #[test]
+    pub fn test_config_history() {
+        let dir = tempdir::TempDir::new("galois-history").unwrap();
+        let path = dir.path().join("history.jsonl");
+        let mut history = ConfigHistory::open(&path).unwrap();
+        let create = cmd((1, 0), true, dec!(0.001));
+        let created = ConfigChange::diff(1, None, &create).unwrap();
+        assert!(created.before.is_none());
+        history.record(created).unwrap();
+        let book = OrderBook::new(
+            5,
+            1,
+            dec!(0.001),
+            dec!(0.001),
+            dec!(0.001),
+            dec!(0.001),
+            1,
+            dec!(1),
+            dec!(1),
+            false,
+            true,
+        );
+        assert!(ConfigChange::diff(2, Some(&book), &create).is_none());
+        let change = ConfigChange::diff(3, Some(&book), &cmd((1, 0), false, dec!(0.002))).unwrap();
+        assert!(change.before.as_ref().unwrap().open);
+        assert!(!change.after.open);
+        assert_eq!(dec!(0.002), change.after.taker_fee);
+        history.record(change.clone()).unwrap();
+        // replayed
+        history.record(change).unwrap();
+        history
+            .record(ConfigChange::diff(4, None, &cmd((2, 0), true, dec!(0.001))).unwrap())
+            .unwrap();
+        assert_eq!(2, history.query(Some((1, 0))).len());
+        assert_eq!(3, history.query(None).len());
+        let restored = ConfigHistory::open(&path).unwrap();
+        assert_eq!(history.query(None), restored.query(None));
+    }
+}
@@ -12,31 +12,61 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+pub mod archive;
 pub mod assets;
-pub mod clearing;
-pub mod matcher;
-pub mod orderbook;
+pub mod breaker;
+pub mod client_orders;
+pub mod clock;
+pub mod flow;
+#[cfg(test)]
+mod fuzz;
+pub mod history;
+mod oco;
 pub mod orders;
+pub mod prints;
+pub mod replica;
+pub mod route;
+pub mod shadow;
+mod shard;
+pub mod statement;
+pub mod stats;
+pub mod tiers;
+pub mod trades;
+pub mod valuation;
+
+pub use galois_core::{clearing, matcher, orderbook};
 
 use crate::{
+    config::C,
     core::*,
-    input::{self, Event, Message},
+    fusotao::FusoCommand,
+    input::{self, encoding, Command, Event, Input, Message, SymbolCmd},
+    latency::{self, Stage, Stamps},
+    matcher::{SelfTradePrevention, TimeInForce},
     orderbook::*,
-    output::{Depth, Output},
-    prover, snapshot,
+    output::{
+        audit,
+        canonical::{self, Canonical, Scales},
+        imbalance, kline, ticker, Depth, DepthSnapshot, Output, Trade,
+    },
+    prover, ring, snapshot, verify,
 };
 use anyhow::anyhow;
+use dashmap::DashMap;
 use rust_decimal::{prelude::*, Decimal};
 use serde_json::{json, to_vec};
 use std::{
     collections::HashMap,
-    sync::mpsc::{Receiver, Sender},
+    sync::mpsc::{Receiver, RecvTimeoutError, Sender},
+    time::{Duration, Instant},
 };
 use thiserror::Error;
 
-type DriverChannel = Receiver<Event>;
-type MarketChannel = Sender<Vec<Output>>;
+type DriverChannel = ring::Consumer<(Event, Option<Stamps>)>;
+/// the outputs along with the instant they are sent
+type MarketChannel = ring::Producer<(Vec<Output>, Instant)>;
 type ResponseChannel = Sender<(u64, Message)>;
+type SequencerChannel = Sender<Input>;
 
 #[derive(Debug, Error)]
 pub enum EventsError {
@@ -50,33 +80,314 @@ pub enum EventsError {
     EventIgnored(u64, anyhow::Error),
 }
 
+/// rejecting reasons that clients should be able to distinguish, replied as `code`
+#[derive(Debug, Error, Clone, Copy, Eq, PartialEq)]
+pub enum RejectReason {
+    #[error("open notional of the symbol exceeds the limit")]
+    OpenNotionalExceeded,
+    #[error("the order can't be filled entirely")]
+    Unfillable,
+    #[error("the currency is suspended from trading")]
+    CurrencySuspended,
+    /// replied by the server without sequencing
+    #[error("too many requests")]
+    RateLimited,
+    /// replied by the sequencer, the nonce must be unused and not below the window of the last
+    /// `NONCE_WINDOW` ones of the user
+    #[error("the nonce has been used")]
+    InvalidNonce,
+    /// only with the `overflow-audit` feature, the event is rejected without touching the state
+    #[error("the amounts overflow while clearing")]
+    Overflow,
+    #[error("open orders of the user exceed the limit")]
+    OpenOrdersExceeded,
+    #[error("frozen balance of the user exceeds the limit")]
+    FrozenExceeded,
+    #[error("the symbol is out of its trading hours")]
+    MarketClosed,
+    /// only the cancels and the orders not crossing the book are accepted while halted
+    #[error("the trading of the symbol is halted")]
+    TradingHalted,
+    /// the client order id was used recently by the user, replied along with the order accepted
+    #[error("the client order id is duplicated")]
+    DuplicateClientOrderId(OrderId),
+    #[error("the average price of the route exceeds the limit")]
+    RouteLimitExceeded,
+    /// replied by the sequencer, the `expiry` of the command is earlier than now
+    #[error("the signature has expired")]
+    SignatureExpired,
+    /// replied by the sequencer, the command is signed for another environment
+    #[error("the signature is bound to another domain")]
+    DomainMismatch,
+    /// the counterparty of a block trade didn't sign the terms
+    #[error("the signature of the counterparty is invalid")]
+    InvalidSignature,
+    /// replied by the sequencer, the proof command isn't in `fusotao.proof_extensions`
+    #[error("the command can't be verified on chain yet")]
+    Unprovable,
+}
+
+impl RejectReason {
+    pub fn code(&self) -> u32 {
+        match self {
+            RejectReason::OpenNotionalExceeded => 1,
+            RejectReason::Unfillable => 2,
+            RejectReason::CurrencySuspended => 3,
+            RejectReason::RateLimited => 4,
+            RejectReason::InvalidNonce => 5,
+            RejectReason::Overflow => 6,
+            RejectReason::OpenOrdersExceeded => 7,
+            RejectReason::FrozenExceeded => 8,
+            RejectReason::MarketClosed => 9,
+            RejectReason::TradingHalted => 10,
+            RejectReason::DuplicateClientOrderId(_) => 11,
+            RejectReason::RouteLimitExceeded => 12,
+            RejectReason::SignatureExpired => 13,
+            RejectReason::DomainMismatch => 14,
+            RejectReason::InvalidSignature => 15,
+            RejectReason::Unprovable => 16,
+        }
+    }
+}
+
 pub type ExecutionResult = Result<(), EventsError>;
 
-pub fn init(recv: DriverChannel, market: MarketChannel, response: ResponseChannel, mut data: Data) {
+lazy_static::lazy_static! {
+    // the configs of the orderbooks, published for the queries served without the executor
+    pub static ref MARKETS: DashMap<Symbol, history::SymbolConfig> = DashMap::new();
+}
+
+/// the outputs are drained after each event while replaying or executing in shards
+const REPLAYING_CAPACITY: usize = 1024;
+
+/// the executor thread returns after a final snapshot once the sequencer stopped
+pub fn init(
+    recv: DriverChannel,
+    market: MarketChannel,
+    response: ResponseChannel,
+    sequencer: SequencerChannel,
+    mut data: Data,
+) -> std::thread::JoinHandle<anyhow::Result<()>> {
     std::thread::spawn(move || -> anyhow::Result<()> {
         let mut ephemeral = Ephemeral::new();
+        ephemeral.config_history =
+            history::ConfigHistory::open(C.server.get_config_history_path())?;
+        if C.server.persist_trades {
+            ephemeral.recent_trades = trades::RecentTrades::open(C.server.get_trades_path())?;
+        }
+        data.orderbooks.iter().for_each(|(symbol, orderbook)| {
+            MARKETS.insert(*symbol, (&**orderbook).into());
+            imbalance::update(data.current_event_id, &(*symbol, &**orderbook).into());
+        });
+        let mut replica = replica::Publisher::new(C.server.replica_interval);
+        replica.publish(&data);
+        // before the tree is moved to the pipeline
+        stats::measure(data.current_event_id, &data);
+        ephemeral.prover = Some(prover::Pipeline::start(
+            std::mem::take(&mut data.merkle_tree),
+            data.current_event_id,
+            C.server.ring_capacity,
+        )?);
         log::info!("executor initialized");
+        let mut pending = None;
         loop {
-            let event = recv.recv()?;
-            match do_execute(event, &mut data, &mut ephemeral, &market, &response) {
-                Ok(_) => {}
-                Err(EventsError::EventRejected(id, session, req_id, e)) => {
-                    log::debug!("event {} rejected: {}", id, e);
-                    let msg = json!({"error": e.to_string()});
-                    let v = to_vec(&msg).unwrap_or_default();
-                    let _ = response.send((session, Message::new_req(req_id, v)));
+            if !release_prints(prints::now(), &mut ephemeral, &market, &response) {
+                return Err(anyhow!("executor thread exited"));
+            }
+            let timeout = replica
+                .pending()
+                .into_iter()
+                .chain(ephemeral.block_prints.due_in())
+                .min();
+            let (event, stamps) = match (pending.take(), timeout) {
+                (Some(event), _) => event,
+                (None, Some(timeout)) => match recv.recv_timeout(timeout) {
+                    Ok(event) => event,
+                    Err(RecvTimeoutError::Timeout) => {
+                        if replica.pending() == Some(Duration::ZERO) {
+                            replica.publish(&data);
+                        }
+                        continue;
+                    }
+                    Err(RecvTimeoutError::Disconnected) => break,
+                },
+                (None, None) => match recv.recv() {
+                    Ok(event) => event,
+                    Err(_) => break,
+                },
+            };
+            let taken = Instant::now();
+            if let Some(ref stamps) = stamps {
+                latency::record(Stage::Queue, taken - stamps.sequenced);
+            }
+            if let Some((session, id)) = event.sequenced() {
+                ephemeral.ack(session, id);
+            }
+            if C.server.shards < 2 || !shard::is_shardable(&event) {
+                let r = do_execute(
+                    event,
+                    &mut data,
+                    &mut ephemeral,
+                    &market,
+                    &response,
+                    &sequencer,
+                );
+                record_executed(taken, stamps.iter());
+                if !handle_result(r, &response) {
+                    return Err(anyhow!("executor thread exited"));
                 }
-                Err(EventsError::EventIgnored(id, e)) => {
-                    log::info!("event {} ignored: {}", id, e);
+                replica.executed(&data);
+                continue;
+            }
+            let mut batch = shard::Batch::new(C.server.shards);
+            let mut batched = vec![stamps];
+            let first = batch.try_push(&data, event);
+            debug_assert!(first.is_none(), "the first order is always accepted;qed");
+            while batch.len() < shard::MAX_BATCH {
+                match recv.try_recv() {
+                    Ok((event, stamps)) => {
+                        let sequenced = event.sequenced();
+                        // acked and stamped on being taken from `pending`
+                        pending = batch.try_push(&data, event).map(|e| (e, stamps));
+                        if pending.is_some() {
+                            break;
+                        }
+                        if let Some(ref stamps) = stamps {
+                            latency::record_since(Stage::Queue, stamps.sequenced);
+                        }
+                        if let Some((session, id)) = sequenced {
+                            ephemeral.ack(session, id);
+                        }
+                        batched.push(stamps);
+                    }
+                    Err(_) => break,
                 }
-                Err(EventsError::Interrupted(id)) => {
-                    log::info!("executor thread interrupted at {}", id);
-                    break;
+            }
+            let results = batch.execute(&mut data, &mut ephemeral, &market, &response, &sequencer);
+            // the events of a batch are executed together
+            record_executed(taken, batched.iter().flatten());
+            if !results.into_iter().all(|r| handle_result(r, &response)) {
+                return Err(anyhow!("executor thread exited"));
+            }
+            replica.executed(&data);
+        }
+        let id = data.current_event_id;
+        if let Some(pipeline) = ephemeral.prover.take() {
+            data.merkle_tree = pipeline.finish(id)?;
+        }
+        snapshot::dump_final(id, &data)?;
+        ephemeral.recent_trades.save()?;
+        log::info!("executor stopped at {}", id);
+        Ok(())
+    })
+}
+
+fn record_executed<'a>(taken: Instant, stamps: impl Iterator<Item = &'a Stamps>) {
+    let executed = Instant::now();
+    for stamps in stamps {
+        latency::record(Stage::Execute, executed - taken);
+        latency::record(Stage::Total, executed - stamps.received);
+    }
+}
+
+/// reply the rejected events, `false` if the executor is interrupted
+fn handle_result(r: ExecutionResult, response: &ResponseChannel) -> bool {
+    match r {
+        Ok(_) => {}
+        Err(EventsError::EventRejected(id, session, req_id, e)) => {
+            tracing::debug!("event {} rejected: {}", id, e);
+            // issued by the system, e.g. the cancels of the linked orders
+            if session == 0 {
+                return true;
+            }
+            let msg = match e.downcast_ref::<RejectReason>() {
+                Some(RejectReason::DuplicateClientOrderId(order_id)) => json!({
+                    "error": e.to_string(),
+                    "code": RejectReason::DuplicateClientOrderId(*order_id).code(),
+                    "event_id": id,
+                    "order_id": order_id,
+                }),
+                Some(r) => {
+                    json!({"error": e.to_string(), "code": r.code(), "event_id": id})
                 }
+                None => json!({"error": e.to_string(), "event_id": id}),
+            };
+            let _ = response.send((session, encoding::reject(session, req_id, &msg)));
+        }
+        Err(EventsError::EventIgnored(id, e)) => {
+            log::info!("event {} ignored: {}", id, e);
+        }
+        Err(EventsError::Interrupted(id)) => {
+            log::info!("executor thread interrupted at {}", id);
+            return false;
+        }
+    }
+    true
+}
+
+/// executes the sequenced events in the caller thread without serving, the outputs are dropped
+/// unless collected
+pub struct Replayer {
+    ephemeral: Ephemeral,
+    market: (MarketChannel, ring::Consumer<(Vec<Output>, Instant)>),
+    response: (ResponseChannel, Receiver<(u64, Message)>),
+    sequencer: (SequencerChannel, Receiver<Input>),
+}
+
+impl Default for Replayer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Replayer {
+    pub fn new() -> Self {
+        Self {
+            ephemeral: Ephemeral::new(),
+            market: ring::channel(REPLAYING_CAPACITY),
+            response: std::sync::mpsc::channel(),
+            sequencer: std::sync::mpsc::channel(),
+        }
+    }
+
+    /// the rejected or ignored events are executed as well
+    pub fn execute(&mut self, event: Event, data: &mut Data) -> anyhow::Result<()> {
+        self.execute_collecting(event, data).map(|_| ())
+    }
+
+    /// the outputs of the event are returned instead of dropped
+    pub fn execute_collecting(
+        &mut self,
+        event: Event,
+        data: &mut Data,
+    ) -> anyhow::Result<Vec<Output>> {
+        let r = do_execute(
+            event,
+            data,
+            &mut self.ephemeral,
+            &self.market.0,
+            &self.response.0,
+            &self.sequencer.0,
+        );
+        // the block trades replayed are printed already
+        release_prints(
+            Timestamp::MAX,
+            &mut self.ephemeral,
+            &self.market.0,
+            &self.response.0,
+        );
+        let outputs = self.market.1.try_iter().flat_map(|(out, _)| out).collect();
+        self.response.1.try_iter().for_each(drop);
+        self.sequencer.1.try_iter().for_each(drop);
+        match r {
+            Err(EventsError::Interrupted(id)) => Err(anyhow!("replaying interrupted at {}", id)),
+            Err(EventsError::EventIgnored(id, e)) => {
+                log::info!("event {} ignored: {}", id, e);
+                Ok(outputs)
             }
+            _ => Ok(outputs),
         }
-        Err(anyhow!("executor thread exited"))
-    });
+    }
 }
 
 fn do_execute(
@@ -85,223 +396,652 @@ fn do_execute(
     ephemeral: &mut Ephemeral,
     market: &MarketChannel,
     response: &ResponseChannel,
+    sequencer: &SequencerChannel,
 ) -> ExecutionResult {
+    let _span = match event.id() {
+        Some(id) => tracing::debug_span!("execute", event_id = id),
+        None => tracing::trace_span!("query"),
+    }
+    .entered();
     match event {
-        Event::Limit(id, cmd, time, session, req_id) => {
+        Event::Limit(id, mut cmd, time, session, req_id) => {
+            data.current_event_id = id;
+            if !data.is_tradable(&cmd.symbol) {
+                return Err(EventsError::EventRejected(
+                    id,
+                    session,
+                    req_id,
+                    RejectReason::CurrencySuspended.into(),
+                ));
+            }
+            if let Some(order_id) = cmd.client_order_id.as_ref().and_then(|c| {
+                client_orders::find(&mut data.client_orders, &cmd.symbol, &cmd.user_id, c)
+            }) {
+                return Err(EventsError::EventRejected(
+                    id,
+                    session,
+                    req_id,
+                    RejectReason::DuplicateClientOrderId(order_id).into(),
+                ));
+            }
+            if let Some(vol) = cmd.vol {
+                cmd.amount = data
+                    .orderbooks
+                    .get(&cmd.symbol)
+                    .and_then(|b| b.sweep_vol(vol, Some(cmd.price)))
+                    .map(|(_, amount)| amount)
+                    .ok_or(EventsError::EventRejected(
+                        id,
+                        session,
+                        req_id,
+                        anyhow!("no liquidity"),
+                    ))?;
+            }
+            let orderbook = data
+                .orderbooks
+                .get(&cmd.symbol)
+                .ok_or(anyhow!("order can't be accepted"))
+                .and_then(|b| {
+                    b.check_order(cmd.price, cmd.amount)?;
+                    if let Some(display) = cmd.display {
+                        b.check_order(cmd.price, display)?;
+                    }
+                    b.check_price_band(cmd.price, data.last_price(&cmd.symbol))?;
+                    Ok(b)
+                })
+                .map_err(|e| EventsError::EventRejected(id, session, req_id, e))?;
+            check_trading_session(data, &cmd.symbol, time, || {
+                breaker::is_crossing(orderbook, cmd.price, cmd.ask_or_bid)
+            })
+            .map_err(|r| EventsError::EventRejected(id, session, req_id, r.into()))?;
+            let stp = self_trade_prevention(orderbook, cmd.self_trade_prevention);
+            let crossed = match stp {
+                SelfTradePrevention::CancelNewest => vec![],
+                _ => matcher::find_self_trades(
+                    orderbook,
+                    cmd.user_id,
+                    cmd.price,
+                    cmd.amount,
+                    cmd.ask_or_bid,
+                ),
+            };
+            let cancelled = match stp {
+                SelfTradePrevention::CancelOldest => cancel_self_trades(
+                    id,
+                    &cmd.symbol,
+                    &crossed,
+                    time,
+                    session,
+                    req_id,
+                    data,
+                    ephemeral,
+                    response,
+                    sequencer,
+                )?,
+                _ => vec![],
+            };
+            if let Err(e) = check_limit(id, &cmd, session, req_id, data) {
+                return reject_taker(
+                    id,
+                    &cmd.symbol,
+                    session,
+                    cancelled,
+                    e,
+                    data,
+                    ephemeral,
+                    market,
+                    response,
+                );
+            }
+            let symbol = cmd.symbol;
+            take_order(
+                id, cmd, time, session, req_id, cancelled, data, ephemeral, market, response,
+                sequencer,
+            )?;
+            // the taker stopped at the first one
+            if let Some(o) = crossed
+                .first()
+                .filter(|_| stp == SelfTradePrevention::CancelBoth && session != 0)
+            {
+                sequencer
+                    .send(Input::new(system_cancel(&symbol, o, time)))
+                    .map_err(|_| EventsError::Interrupted(id))?;
+            }
+            Ok(())
+        }
+        Event::Market(id, cmd, time, session, req_id) => {
+            data.current_event_id = id;
+            if !data.is_tradable(&cmd.symbol) {
+                return Err(EventsError::EventRejected(
+                    id,
+                    session,
+                    req_id,
+                    RejectReason::CurrencySuspended.into(),
+                ));
+            }
+            let orderbook = data
+                .orderbooks
+                .get(&cmd.symbol)
+                .filter(|b| b.enable_market_order)
+                .ok_or(EventsError::EventRejected(
+                    id,
+                    session,
+                    req_id,
+                    anyhow!("order can't be accepted"),
+                ))?;
+            // a market order is proved as a limit order at the worst price it may reach
+            let sweep = |book: &OrderBook| match cmd.vol {
+                Some(vol) => book.sweep_vol(vol, None),
+                None => book
+                    .sweep_price(cmd.amount, cmd.ask_or_bid)
+                    .map(|price| (price, cmd.amount)),
+            };
+            let (price, amount) = sweep(orderbook).ok_or(EventsError::EventRejected(
+                id,
+                session,
+                req_id,
+                anyhow!("no liquidity"),
+            ))?;
+            orderbook
+                .check_order(Price::zero(), amount)
+                .map_err(|e| EventsError::EventRejected(id, session, req_id, e))?;
+            check_trading_session(data, &cmd.symbol, time, || true)
+                .map_err(|r| EventsError::EventRejected(id, session, req_id, r.into()))?;
+            let stp = self_trade_prevention(orderbook, cmd.self_trade_prevention);
+            let crossed = match stp {
+                SelfTradePrevention::CancelNewest => vec![],
+                _ => {
+                    matcher::find_self_trades(orderbook, cmd.user_id, price, amount, cmd.ask_or_bid)
+                }
+            };
+            let cancelled = match stp {
+                SelfTradePrevention::CancelOldest => cancel_self_trades(
+                    id,
+                    &cmd.symbol,
+                    &crossed,
+                    time,
+                    session,
+                    req_id,
+                    data,
+                    ephemeral,
+                    response,
+                    sequencer,
+                )?,
+                _ => vec![],
+            };
+            // swept again without the liquidity of the cancelled ones
+            let (price, amount) = match cancelled.is_empty() {
+                true => (price, amount),
+                false => {
+                    let book = &data.orderbooks[&cmd.symbol];
+                    match sweep(book).filter(|(_, a)| book.check_order(Price::zero(), *a).is_ok()) {
+                        Some(swept) => swept,
+                        None => {
+                            let e = EventsError::EventRejected(
+                                id,
+                                session,
+                                req_id,
+                                anyhow!("no liquidity"),
+                            );
+                            return reject_taker(
+                                id,
+                                &cmd.symbol,
+                                session,
+                                cancelled,
+                                e,
+                                data,
+                                ephemeral,
+                                market,
+                                response,
+                            );
+                        }
+                    }
+                }
+            };
+            let cmd = input::LimitCmd {
+                symbol: cmd.symbol,
+                user_id: cmd.user_id,
+                price,
+                amount,
+                ask_or_bid: cmd.ask_or_bid,
+                nonce: cmd.nonce,
+                signature: cmd.signature,
+                broker: cmd.broker,
+                time_in_force: TimeInForce::ImmediateOrCancel,
+                self_trade_prevention: Some(stp),
+                vol: cmd.vol,
+                display: None,
+                oco: None,
+                client_order_id: None,
+            };
+            let symbol = cmd.symbol;
+            take_order(
+                id, cmd, time, session, req_id, cancelled, data, ephemeral, market, response,
+                sequencer,
+            )?;
+            if let Some(o) = crossed
+                .first()
+                .filter(|_| stp == SelfTradePrevention::CancelBoth && session != 0)
+            {
+                sequencer
+                    .send(Input::new(system_cancel(&symbol, o, time)))
+                    .map_err(|_| EventsError::Interrupted(id))?;
+            }
+            Ok(())
+        }
+        Event::Cancel(id, cmd, time, session, req_id) => {
+            data.current_event_id = id;
+            let (delta, out) = cancel_order(
+                id, cmd, time, session, req_id, true, data, ephemeral, response, sequencer,
+            )?;
+            save_proof(delta, &mut data.merkle_tree, ephemeral)?;
+            market
+                .send((out, Instant::now()))
+                .map_err(|_| EventsError::Interrupted(id))?;
+            Ok(())
+        }
+        Event::BlockTrade(id, cmd, time, session, req_id) => {
             data.current_event_id = id;
+            // the nonces of both sides are consumed on sequencing, so a rejected block trade
+            // can't be submitted again once the balances allow
+            if !cmd.is_countersigned() {
+                return Err(EventsError::EventRejected(
+                    id,
+                    session,
+                    req_id,
+                    RejectReason::InvalidSignature.into(),
+                ));
+            }
+            if !data.is_tradable(&cmd.symbol) {
+                return Err(EventsError::EventRejected(
+                    id,
+                    session,
+                    req_id,
+                    RejectReason::CurrencySuspended.into(),
+                ));
+            }
             let orderbook = data
                 .orderbooks
                 .get_mut(&cmd.symbol)
                 .filter(|b| b.should_accept(cmd.price, cmd.amount))
+                .filter(|_| cmd.user_id != cmd.counterparty)
+                .filter(|b| {
+                    !b.block_trade_min_amount.is_zero() && cmd.amount >= b.block_trade_min_amount
+                })
                 .ok_or(EventsError::EventRejected(
                     id,
                     session,
                     req_id,
-                    anyhow!("order can't be accepted"),
+                    anyhow!("block trade can't be accepted"),
                 ))?;
-            log::debug!(
-                "predicate root=0x{} before applying {}",
-                hex::encode(data.merkle_tree.root()),
-                id
+            let symbol = cmd.symbol;
+            let scales = Scales::from(&**orderbook);
+            let delay = orderbook.block_trade_report_delay;
+            let maker_before = (
+                assets::get_balance_to_owned(&data.accounts, &cmd.counterparty, symbol.0),
+                assets::get_balance_to_owned(&data.accounts, &cmd.counterparty, symbol.1),
+            );
+            let taker_before = (
+                assets::get_balance_to_owned(&data.accounts, &cmd.user_id, symbol.0),
+                assets::get_balance_to_owned(&data.accounts, &cmd.user_id, symbol.1),
             );
-            let (ask_size, bid_size) = orderbook.size();
-            let (best_ask_before, best_bid_before) = orderbook.get_size_of_best();
-            let taker_base_before =
-                assets::get_balance_to_owned(&data.accounts, &cmd.user_id, cmd.symbol.0);
-            let taker_quote_before =
-                assets::get_balance_to_owned(&data.accounts, &cmd.user_id, cmd.symbol.1);
-            let (c, val) = assets::freeze_if(&cmd.symbol, cmd.ask_or_bid, cmd.price, cmd.amount);
-            assets::try_freeze(&mut data.accounts, &cmd.user_id, c, val)
+            #[cfg(feature = "overflow-audit")]
+            if cmd.price.checked_mul(cmd.amount).is_none() {
+                return Err(EventsError::EventRejected(
+                    id,
+                    session,
+                    req_id,
+                    RejectReason::Overflow.into(),
+                ));
+            }
+            let (mc, mv) = assets::freeze_if(&symbol, !cmd.ask_or_bid, cmd.price, cmd.amount);
+            assets::try_freeze(&mut data.accounts, &cmd.counterparty, mc, mv)
                 .map_err(|e| EventsError::EventRejected(id, session, req_id, e))?;
-            let mr = matcher::execute_limit(
-                orderbook,
-                cmd.user_id,
-                cmd.price,
-                cmd.amount,
-                cmd.ask_or_bid,
+            let (tc, tv) = assets::freeze_if(&symbol, cmd.ask_or_bid, cmd.price, cmd.amount);
+            if let Err(e) = assets::try_freeze(&mut data.accounts, &cmd.user_id, tc, tv) {
+                assets::try_unfreeze(&mut data.accounts, &cmd.counterparty, mc, mv)
+                    .expect("just frozen;qed");
+                return Err(EventsError::EventRejected(id, session, req_id, e));
+            }
+            #[cfg(feature = "overflow-audit")]
+            let before = orderbook.clone();
+            let maker_id = orderbook.incr_then_fetch_order_id();
+            let taker_id = orderbook.incr_then_fetch_order_id();
+            let mr = matcher::Match {
+                maker: vec![matcher::Maker::maker_filled(
+                    cmd.counterparty,
+                    maker_id,
+                    cmd.price,
+                    cmd.amount,
+                )],
+                taker: matcher::Taker::taker_filled(
+                    cmd.user_id,
+                    taker_id,
+                    cmd.price,
+                    cmd.ask_or_bid,
+                ),
+                page_delta: Default::default(),
+            };
+            let taker_fee = data
+                .volumes
+                .tier(&data.fee_tiers, &cmd.user_id, symbol.1, time)
+                .map_or(orderbook.taker_fee, |t| t.taker_fee);
+            let maker_fee = data
+                .volumes
+                .tier(&data.fee_tiers, &cmd.counterparty, symbol.1, time)
+                .map_or(orderbook.maker_fee, |t| t.maker_fee);
+            #[cfg(feature = "overflow-audit")]
+            if let Err(e) = clearing::audit(
+                &data.accounts,
+                &symbol,
+                taker_fee,
+                maker_fee,
+                orderbook.broker_share,
+                &mr,
+            ) {
+                *orderbook = before;
+                assets::try_unfreeze(&mut data.accounts, &cmd.counterparty, mc, mv)
+                    .expect("just frozen;qed");
+                assets::try_unfreeze(&mut data.accounts, &cmd.user_id, tc, tv)
+                    .expect("just frozen;qed");
+                log::warn!("rejecting event {}: {:?}", id, e);
+                return Err(EventsError::EventRejected(
+                    id,
+                    session,
+                    req_id,
+                    RejectReason::Overflow.into(),
+                ));
+            }
+            for (order_id, user_id, direction) in [
+                (maker_id, cmd.counterparty, !cmd.ask_or_bid),
+                (taker_id, cmd.user_id, cmd.ask_or_bid),
+            ] {
+                data.orders.insert(PendingOrder {
+                    order_id,
+                    user_id,
+                    symbol,
+                    direction: direction.into(),
+                    create_timestamp: time,
+                    amount: cmd.amount,
+                    price: cmd.price,
+                    status: OrderState::Placed.into(),
+                    matched_quote_amount: Decimal::zero(),
+                    matched_base_amount: Decimal::zero(),
+                    base_fee: Decimal::zero(),
+                    quote_fee: Decimal::zero(),
+                });
+            }
+            let mut out = clearing::clear(
+                &mut data.accounts,
+                id,
+                &symbol,
+                taker_fee,
+                maker_fee,
+                orderbook.broker_share,
+                &mr,
+                time,
             );
-            data.orders.insert(PendingOrder {
-                order_id: mr.taker.order_id,
-                user_id: cmd.user_id,
-                symbol: cmd.symbol,
-                direction: mr.taker.ask_or_bid.into(),
-                create_timestamp: time,
-                amount: cmd.amount,
-                price: cmd.price,
-                status: OrderState::Placed.into(),
-                matched_quote_amount: Decimal::zero(),
-                matched_base_amount: Decimal::zero(),
-                base_fee: Decimal::zero(),
-                quote_fee: Decimal::zero(),
-            });
-            // compatiable with old version since we don't use mysql auto increment id anymore
-            // session=0 indicates replaying from snapshot
+            let micros = clock::stamp(time);
+            stamp(&mut out, micros);
+            data.volumes.record(&out, time);
+            record_statements(id, &out);
+            audit::stage(id, symbol, time, &mr, &out);
             if session != 0 {
                 response
                     .send((
                         session,
-                        Message::new_req(
+                        encoding::reply(
+                            session,
                             req_id,
-                            to_vec(&json!({
-                                "id": mr.taker.order_id
-                            }))
-                            .expect("qed;"),
-                        ),
-                    ))
-                    .map_err(|_| EventsError::Interrupted(id))?;
-                let orderbook: &_ = orderbook;
-                let depth: Depth = (cmd.symbol, orderbook).into();
-                response
-                    .send((
-                        0,
-                        Message::new_broadcast(
-                            input::DEPTH_UPDATED,
-                            to_vec(&depth).unwrap_or_default(),
+                            &json!({
+                                "id": taker_id,
+                                "counterparty_id": maker_id,
+                                "event_id": id,
+                            }),
                         ),
                     ))
                     .map_err(|_| EventsError::Interrupted(id))?;
             }
-            let out = clearing::clear(
-                &mut data.accounts,
-                id,
-                &cmd.symbol,
-                orderbook.taker_fee,
-                orderbook.maker_fee,
-                &mr,
-                time,
-            );
             for cr in out.iter() {
-                let o = data.orders.merge(&cr);
+                let o = merge_order(&mut data.orders, cr);
                 if session != 0 {
-                    // broadcast to all sessions
+                    // the fills of both parties are private, only to the submitter
                     response
                         .send((
-                            0,
+                            session,
                             Message::new_broadcast(
                                 input::ORDER_MATCHED,
-                                to_vec(&o).unwrap_or_default(),
+                                to_vec(&o.canonical(&scales)).unwrap_or_default(),
                             ),
                         ))
                         .map_err(|_| EventsError::Interrupted(id))?;
                 }
             }
-            let (maker_fee, taker_fee) = (orderbook.maker_fee, orderbook.taker_fee);
-            let proof = prover::prove_trade_cmd(
+            let report = json!({
+                "base": symbol.0,
+                "quote": symbol.1,
+                "price": scales.price(cmd.price),
+                "amount": scales.amount(cmd.amount),
+                "timestamp": time,
+                "timestamp_us": micros,
+                "block_trade": true,
+            });
+            let delta = prover::prove_block_trade(
                 data,
-                cmd.nonce,
-                cmd.signature.clone(),
                 (cmd, maker_fee, taker_fee).into(),
-                ask_size,
-                bid_size,
-                best_ask_before.unwrap_or((Decimal::zero(), Decimal::zero())),
-                best_bid_before.unwrap_or((Decimal::zero(), Decimal::zero())),
-                &taker_base_before,
-                &taker_quote_before,
+                (&maker_before.0, &maker_before.1),
+                (&taker_before.0, &taker_before.1),
                 &out,
-                &mr,
             );
-            prover::save_proof(proof)
-                .inspect_err(|e| log::error!("{}", e))
-                .map_err(|_| EventsError::Interrupted(id))?;
-            market.send(out).map_err(|_| EventsError::Interrupted(id))?;
+            save_proof(delta, &mut data.merkle_tree, ephemeral)?;
+            // the klines and the exports are public as well
+            let print = prints::Print {
+                report: to_vec(&report).unwrap_or_default(),
+                outputs: out,
+                broadcast: session != 0,
+            };
+            ephemeral.block_prints.hold(time + delay, id, print);
             Ok(())
         }
-        Event::Cancel(id, cmd, time, session, req_id) => {
+        Event::Route(id, cmd, time, session, req_id) => {
             data.current_event_id = id;
-            // 0. symbol exsits
-            // 1. check order's owner
-            let orderbook =
+            let (first, second) = match cmd.ask_or_bid {
+                AskOrBid::Ask => route::books(&cmd),
+                AskOrBid::Bid => {
+                    let (ab, bc) = route::books(&cmd);
+                    (bc, ab)
+                }
+            };
+            if !data.is_tradable(&first) || !data.is_tradable(&second) {
+                return Err(EventsError::EventRejected(
+                    id,
+                    session,
+                    req_id,
+                    RejectReason::CurrencySuspended.into(),
+                ));
+            }
+            for symbol in [&first, &second] {
+                check_trading_session(data, symbol, time, || true)
+                    .map_err(|r| EventsError::EventRejected(id, session, req_id, r.into()))?;
+            }
+            // the fees of both legs are known before the first one is taken
+            let book = |symbol: &Symbol| {
                 data.orderbooks
-                    .get_mut(&cmd.symbol)
+                    .get(symbol)
+                    .filter(|b| b.enable_market_order)
+                    .map(|b| {
+                        let fee = data
+                            .volumes
+                            .tier(&data.fee_tiers, &cmd.user_id, symbol.1, time)
+                            .map_or(b.taker_fee, |t| t.taker_fee);
+                        (&**b, fee)
+                    })
                     .ok_or(EventsError::EventRejected(
                         id,
                         session,
                         req_id,
-                        anyhow!("orderbook not found"),
-                    ))?;
-            orderbook
-                .find_order(cmd.order_id)
-                .filter(|o| o.user == cmd.user_id)
-                .ok_or(EventsError::EventRejected(
+                        anyhow!("no route through {}", cmd.via),
+                    ))
+            };
+            let legs = route::plan(&cmd, book(&first)?, book(&second)?)
+                .map_err(|r| EventsError::EventRejected(id, session, req_id, r.into()))?;
+            for leg in [&legs.0, &legs.1] {
+                data.orderbooks[&leg.symbol]
+                    .check_order(Price::zero(), leg.amount)
+                    .map_err(|e| EventsError::EventRejected(id, session, req_id, e))?;
+            }
+            let to_limit = |leg: route::Leg| input::LimitCmd {
+                symbol: leg.symbol,
+                user_id: cmd.user_id,
+                price: leg.price,
+                amount: leg.amount,
+                ask_or_bid: leg.ask_or_bid,
+                nonce: cmd.nonce,
+                signature: cmd.signature.clone(),
+                broker: None,
+                time_in_force: TimeInForce::ImmediateOrCancel,
+                self_trade_prevention: None,
+                vol: leg.vol,
+                display: None,
+                oco: None,
+                client_order_id: None,
+            };
+            let (first, second) = (to_limit(legs.0), to_limit(legs.1));
+            let (first_delta, first_out, first_id) = fill_order(
+                id, first, time, session, req_id, false, data, ephemeral, response, sequencer,
+            )?;
+            // the second leg is checked fillable before, in case it's rejected unexpectedly, e.g.
+            // overflowing, the first one is proven alone to keep the proofs along with the state
+            let (delta, second_out, second_id) = match fill_order(
+                id, second, time, session, req_id, false, data, ephemeral, response, sequencer,
+            ) {
+                Ok((delta, out, order_id)) => {
+                    (prover::prove_route(first_delta, delta), out, order_id)
+                }
+                Err(e) => {
+                    log::error!("the second leg of route {} is rejected, {}", id, e);
+                    save_proof(first_delta, &mut data.merkle_tree, ephemeral)?;
+                    market
+                        .send((first_out, Instant::now()))
+                        .map_err(|_| EventsError::Interrupted(id))?;
+                    return Err(e);
+                }
+            };
+            save_proof(delta, &mut data.merkle_tree, ephemeral)?;
+            for out in [first_out, second_out] {
+                market
+                    .send((out, Instant::now()))
+                    .map_err(|_| EventsError::Interrupted(id))?;
+            }
+            if session != 0 {
+                let accepted = json!({
+                    "legs": [first_id, second_id],
+                    "event_id": id,
+                });
+                response
+                    .send((session, encoding::reply(session, req_id, &accepted)))
+                    .map_err(|_| EventsError::Interrupted(id))?;
+            }
+            Ok(())
+        }
+        Event::SubTransfer(id, cmd, time, session, req_id) => {
+            data.current_event_id = id;
+            if !data.currency_mode(cmd.currency).is_tradable() {
+                return Err(EventsError::EventRejected(
                     id,
                     session,
                     req_id,
-                    anyhow!("order doesn't exist"),
-                ))?;
-            log::debug!(
-                "predicate root=0x{} before applying {}",
-                hex::encode(data.merkle_tree.root()),
-                id
-            );
-            let size = orderbook.size();
-            let (best_ask_before, best_bid_before) = orderbook.get_size_of_best();
-            let taker_base_before =
-                assets::get_balance_to_owned(&data.accounts, &cmd.user_id, cmd.symbol.0);
-            let taker_quote_before =
-                assets::get_balance_to_owned(&data.accounts, &cmd.user_id, cmd.symbol.1);
-            let mr = matcher::cancel(orderbook, cmd.order_id).ok_or(EventsError::EventRejected(
-                id,
-                session,
-                req_id,
-                anyhow!("order doesn't exist"),
-            ))?;
+                    RejectReason::CurrencySuspended.into(),
+                ));
+            }
+            let (from, to) = cmd.parties();
+            let from_before = assets::get_balance_to_owned(&data.accounts, &from, cmd.currency);
+            let to_before = assets::get_balance_to_owned(&data.accounts, &to, cmd.currency);
+            let from_after =
+                assets::deduct_available(&mut data.accounts, &from, cmd.currency, cmd.amount)
+                    .map_err(|_| {
+                        EventsError::EventRejected(
+                            id,
+                            session,
+                            req_id,
+                            anyhow!("insufficient available balance"),
+                        )
+                    })?;
+            let to_after =
+                assets::add_to_available(&mut data.accounts, &to, cmd.currency, cmd.amount)
+                    .expect("deducted from the other;qed");
+            record_sub_transfer(id, time, &cmd, (&from_after, &to_after));
             if session != 0 {
+                publish_transfer(
+                    id,
+                    statement::sub_transfer_entries(id, time, &cmd, (&from_after, &to_after)),
+                    response,
+                )?;
                 response
                     .send((
                         session,
-                        Message::new_req(
+                        encoding::reply(
+                            session,
                             req_id,
-                            to_vec(&json!({
-                                "id": cmd.order_id
-                            }))
-                            .expect("qed;"),
-                        ),
-                    ))
-                    .map_err(|_| EventsError::Interrupted(id))?;
-                let orderbook: &_ = orderbook;
-                let depth: Depth = (cmd.symbol, orderbook).into();
-                response
-                    .send((
-                        0,
-                        Message::new_broadcast(
-                            input::DEPTH_UPDATED,
-                            to_vec(&depth).unwrap_or_default(),
+                            &json!({
+                                "id": id,
+                                "event_id": id,
+                            }),
                         ),
                     ))
                     .map_err(|_| EventsError::Interrupted(id))?;
             }
-            let out = clearing::clear(
-                &mut data.accounts,
+            let delta = prover::prove_sub_transfer(
                 id,
-                &cmd.symbol,
-                orderbook.taker_fee,
-                orderbook.maker_fee,
-                &mr,
-                time,
-            );
-            for cr in out.iter() {
-                data.orders.merge(&cr);
-            }
-            let proof = prover::prove_trade_cmd(
-                data,
-                cmd.nonce,
-                cmd.signature.clone(),
-                cmd.into(),
-                size.0,
-                size.1,
-                best_ask_before.unwrap_or((Decimal::zero(), Decimal::zero())),
-                best_bid_before.unwrap_or((Decimal::zero(), Decimal::zero())),
-                &taker_base_before,
-                &taker_quote_before,
-                &out,
-                &mr,
+                cmd,
+                (&from_before, &from_after),
+                (&to_before, &to_after),
             );
-            prover::save_proof(proof)
-                .inspect_err(|e| log::error!("{}", e))
-                .map_err(|_| EventsError::Interrupted(id))?;
-            market.send(out).map_err(|_| EventsError::Interrupted(id))?;
+            save_proof(delta, &mut data.merkle_tree, ephemeral)?;
             Ok(())
         }
+        Event::CancelAll(id, symbol, user_id, time, session, req_id) => {
+            data.current_event_id = id;
+            let orders = data
+                .orderbooks
+                .get(&symbol)
+                .map(|b| {
+                    b.list_orders(user_id)
+                        .into_iter()
+                        .map(|o| (o.user, o.id))
+                        .collect::<Vec<_>>()
+                })
+                .unwrap_or_default();
+            log::info!("cancelling {} orders of {:?}", orders.len(), symbol);
+            let mut ids = vec![];
+            let mut cancelled = vec![];
+            for (user_id, order_id) in orders {
+                let cmd = input::CancelCmd {
+                    symbol,
+                    user_id,
+                    order_id,
+                    nonce: 0,
+                    signature: vec![],
+                };
+                cancelled.push(cancel_order(
+                    id, cmd, time, session, req_id, false, data, ephemeral, response, sequencer,
+                )?);
+                ids.push(order_id);
+            }
+            if session != 0 {
+                let v = json!({
+                    "ids": ids,
+                    "event_id": id,
+                });
+                response
+                    .send((session, encoding::reply(session, req_id, &v)))
+                    .map_err(|_| EventsError::Interrupted(id))?;
+                if let Some(orderbook) = data.orderbooks.get(&symbol).filter(|_| !ids.is_empty()) {
+                    publish_depth(id, symbol, orderbook, ephemeral, response)?;
+                }
+            }
+            save_batch(id, cancelled, data, ephemeral, market)
+        }
         Event::TransferOut(id, cmd) => {
             data.current_event_id = id;
             if !ephemeral.save_receipt((cmd.block_number, cmd.user_id)) {
@@ -310,18 +1050,20 @@ fn do_execute(
                     anyhow!("Duplicated transfer_out extrinsic"),
                 ));
             }
-            log::debug!(
-                "predicate root=0x{} before applying {}",
-                hex::encode(data.merkle_tree.root()),
-                id
-            );
             let before = assets::get_balance_to_owned(&data.accounts, &cmd.user_id, cmd.currency);
+            if !data.currency_mode(cmd.currency).is_withdrawable() {
+                let currency = cmd.currency;
+                let delta = prover::prove_cmd_rejected(id, cmd, &before);
+                save_proof(delta, &mut data.merkle_tree, ephemeral)?;
+                return Err(EventsError::EventIgnored(
+                    id,
+                    anyhow!("withdrawing currency {} is suspended", currency),
+                ));
+            }
             if data.tvl < cmd.amount {
-                let proof = prover::prove_cmd_rejected(&mut data.merkle_tree, id, cmd, &before);
+                let delta = prover::prove_cmd_rejected(id, cmd, &before);
                 log::error!("TVL less than transfer_out amount, event={}", id);
-                prover::save_proof(proof)
-                    .inspect_err(|e| log::error!("{}", e))
-                    .map_err(|_| EventsError::Interrupted(id))?;
+                save_proof(delta, &mut data.merkle_tree, ephemeral)?;
                 return Err(EventsError::EventIgnored(id, anyhow!("TVL not enough")));
             }
             match assets::deduct_available(
@@ -332,18 +1074,15 @@ fn do_execute(
             ) {
                 Ok(after) => {
                     data.tvl -= cmd.amount;
-                    let proof =
-                        prover::prove_assets_cmd(&mut data.merkle_tree, id, cmd, &before, &after);
-                    prover::save_proof(proof)
-                        .inspect_err(|e| log::error!("{}", e))
-                        .map_err(|_| EventsError::Interrupted(id))?;
+                    record_transfer(id, &cmd, &after);
+                    publish_transfer(id, statement::transfer_entries(id, &cmd, &after), response)?;
+                    let delta = prover::prove_assets_cmd(id, cmd, &before, &after);
+                    save_proof(delta, &mut data.merkle_tree, ephemeral)?;
                     Ok(())
                 }
                 Err(e) => {
-                    let proof = prover::prove_cmd_rejected(&mut data.merkle_tree, id, cmd, &before);
-                    prover::save_proof(proof)
-                        .inspect_err(|e| log::error!("{}", e))
-                        .map_err(|_| EventsError::Interrupted(id))?;
+                    let delta = prover::prove_cmd_rejected(id, cmd, &before);
+                    save_proof(delta, &mut data.merkle_tree, ephemeral)?;
                     Err(EventsError::EventIgnored(id, e))
                 }
             }
@@ -356,22 +1095,25 @@ fn do_execute(
                     anyhow!("Duplicated transfer_in extrinsic"),
                 ));
             }
+            if !data.currency_mode(cmd.currency).is_depositable() {
+                let currency = cmd.currency;
+                let before =
+                    assets::get_balance_to_owned(&data.accounts, &cmd.user_id, cmd.currency);
+                let delta = prover::prove_rejecting_no_reason(id, cmd, &before);
+                save_proof(delta, &mut data.merkle_tree, ephemeral)?;
+                return Err(EventsError::EventIgnored(
+                    id,
+                    anyhow!("depositing currency {} is suspended", currency),
+                ));
+            }
             if data.tvl + cmd.amount >= crate::core::max_number() {
                 let before =
                     assets::get_balance_to_owned(&data.accounts, &cmd.user_id, cmd.currency);
-                let proof =
-                    prover::prove_rejecting_no_reason(&mut data.merkle_tree, id, cmd, &before);
-                prover::save_proof(proof)
-                    .inspect_err(|e| log::error!("{}", e))
-                    .map_err(|_| EventsError::Interrupted(id))?;
+                let delta = prover::prove_rejecting_no_reason(id, cmd, &before);
+                save_proof(delta, &mut data.merkle_tree, ephemeral)?;
                 log::error!("TVL out of limit, event={}", id);
                 return Err(EventsError::EventIgnored(id, anyhow!("TVL out of limit")));
             }
-            log::debug!(
-                "predicate root=0x{} before applying {}",
-                hex::encode(data.merkle_tree.root()),
-                id
-            );
             let before = assets::get_balance_to_owned(&data.accounts, &cmd.user_id, cmd.currency);
             let after = assets::add_to_available(
                 &mut data.accounts,
@@ -381,31 +1123,34 @@ fn do_execute(
             )
             .map_err(|e| EventsError::EventIgnored(id, e))?;
             data.tvl = data.tvl + cmd.amount;
-            let proof = prover::prove_assets_cmd(&mut data.merkle_tree, id, cmd, &before, &after);
-            prover::save_proof(proof)
-                .inspect_err(|e| log::error!("{}", e))
-                .map_err(|_| EventsError::Interrupted(id))?;
+            record_transfer(id, &cmd, &after);
+            publish_transfer(id, statement::transfer_entries(id, &cmd, &after), response)?;
+            let delta = prover::prove_assets_cmd(id, cmd, &before, &after);
+            save_proof(delta, &mut data.merkle_tree, ephemeral)?;
             Ok(())
         }
         Event::UpdateSymbol(id, cmd) => {
             data.current_event_id = id;
+            if let Some(change) = history::ConfigChange::diff(
+                id,
+                data.orderbooks.get(&cmd.symbol).map(|b| &**b),
+                &cmd,
+            ) {
+                if let Err(e) = ephemeral.config_history.record(change) {
+                    log::error!("unable to record the change of {:?}, {:?}", cmd.symbol, e);
+                }
+            }
             if !data.orderbooks.contains_key(&cmd.symbol) {
-                let orderbook = OrderBook::new(
-                    cmd.base_scale,
-                    cmd.quote_scale,
-                    cmd.taker_fee,
-                    cmd.maker_fee,
-                    cmd.base_taker_fee,
-                    cmd.base_maker_fee,
-                    cmd.fee_times,
-                    cmd.min_amount,
-                    cmd.min_vol,
-                    cmd.enable_market_order,
-                    cmd.open,
-                );
-                data.orderbooks.insert(cmd.symbol, orderbook);
+                data.orderbooks
+                    .insert(cmd.symbol, new_orderbook(&cmd).into());
             } else {
                 let orderbook = data.orderbooks.get_mut(&cmd.symbol).unwrap();
+                // the levels are rescaled
+                if orderbook.base_scale != cmd.base_scale
+                    || orderbook.quote_scale != cmd.quote_scale
+                {
+                    ephemeral.reset_depth(&cmd.symbol);
+                }
                 orderbook.base_scale = cmd.base_scale;
                 orderbook.quote_scale = cmd.quote_scale;
                 orderbook.taker_fee = cmd.taker_fee;
@@ -417,35 +1162,150 @@ fn do_execute(
                 orderbook.min_vol = cmd.min_vol;
                 orderbook.enable_market_order = cmd.enable_market_order;
                 orderbook.open = cmd.open;
+                if let Some(broker_share) = cmd.broker_share {
+                    orderbook.broker_share = broker_share;
+                }
+                if let Some(tick_size) = cmd.tick_size {
+                    orderbook.tick_size = tick_size;
+                }
+                if let Some(lot_size) = cmd.lot_size {
+                    orderbook.lot_size = lot_size;
+                }
+                if let Some(price_band) = cmd.price_band {
+                    orderbook.price_band = price_band;
+                }
+                if let Some(stp) = cmd.self_trade_prevention {
+                    orderbook.self_trade_prevention = stp;
+                }
+                if let Some(cap) = cmd.max_open_notional {
+                    orderbook.max_open_notional = cap;
+                }
+                if let Some(max) = cmd.max_open_orders {
+                    orderbook.max_open_orders = max;
+                }
+                if let Some(ref hours) = cmd.trading_hours {
+                    orderbook.trading_hours = hours.clone();
+                }
+                if let Some(cb) = cmd.circuit_breaker {
+                    orderbook.circuit_breaker = cb;
+                }
+                if let Some(min) = cmd.block_trade_min_amount {
+                    orderbook.block_trade_min_amount = min;
+                }
+                if let Some(delay) = cmd.block_trade_report_delay {
+                    orderbook.block_trade_report_delay = delay;
+                }
+            }
+            let orderbook = data
+                .orderbooks
+                .get(&cmd.symbol)
+                .expect("inserted above;qed");
+            MARKETS.insert(cmd.symbol, (&**orderbook).into());
+            Ok(())
+        }
+        Event::SetSymbolOpen(id, symbol, open, timestamp) => {
+            data.current_event_id = id;
+            let orderbook = data
+                .orderbooks
+                .get(&symbol)
+                .ok_or(EventsError::EventIgnored(
+                    id,
+                    anyhow!("orderbook {:?} not found", symbol),
+                ))?;
+            let cmd = SymbolCmd {
+                symbol,
+                open,
+                base_scale: orderbook.base_scale,
+                quote_scale: orderbook.quote_scale,
+                taker_fee: orderbook.taker_fee,
+                maker_fee: orderbook.maker_fee,
+                base_maker_fee: orderbook.base_maker_fee,
+                base_taker_fee: orderbook.base_taker_fee,
+                fee_times: orderbook.fee_times,
+                min_amount: orderbook.min_amount,
+                min_vol: orderbook.min_vol,
+                enable_market_order: orderbook.enable_market_order,
+                broker_share: None,
+                tick_size: None,
+                lot_size: None,
+                price_band: None,
+                max_open_notional: None,
+                max_open_orders: None,
+                trading_hours: None,
+                circuit_breaker: None,
+                block_trade_min_amount: None,
+                block_trade_report_delay: None,
+                self_trade_prevention: None,
+                timestamp,
+                actor: "admin".to_string(),
+            };
+            do_execute(
+                Event::UpdateSymbol(id, cmd),
+                data,
+                ephemeral,
+                market,
+                response,
+                sequencer,
+            )
+        }
+        Event::SetTradingHalt(id, symbol, halted) => {
+            data.current_event_id = id;
+            if !data.orderbooks.contains_key(&symbol) {
+                return Err(EventsError::EventIgnored(
+                    id,
+                    anyhow!("orderbook {:?} not found", symbol),
+                ));
+            }
+            log::info!(
+                "trading of {:?} halted={} by admin at {}",
+                symbol,
+                halted,
+                id
+            );
+            // lifting resets the circuit breaker as well
+            match halted {
+                true => data.breakers.entry(symbol).or_default().halted_until = Timestamp::MAX,
+                false => {
+                    data.breakers.remove(&symbol);
+                }
             }
             Ok(())
         }
         Event::QueryOrder(symbol, order_id, session, req_id) => {
-            let v = match data.orderbooks.get(&symbol) {
-                Some(orderbook) => orderbook
-                    .find_order(order_id)
-                    .map_or(vec![], |order| to_vec(order).unwrap_or_default()),
-                None => vec![],
+            let v = match replica::order(&data.orderbooks, &symbol, order_id) {
+                Some(order) => encoding::reply(session, req_id, &order),
+                None => encoding::empty(session, req_id),
             };
-            let _ = response.send((session, Message::new_req(req_id, v)));
+            let _ = response.send((session, v));
             Ok(())
         }
-        Event::QueryUserOrders(symbol, user_id, session, req_id) => {
-            let o = data.orders.list(user_id, symbol);
-            let v = to_vec(&o).unwrap_or_default();
-            let _ = response.send((session, Message::new_req(req_id, v)));
+        Event::QueryUserOrders(symbol, user_id, from, limit, session, req_id) => {
+            let o = data.orders.page(user_id, symbol, from, limit);
+            let o = match data.orderbooks.get(&symbol) {
+                Some(orderbook) => o.canonical(&Scales::from(&**orderbook)),
+                None => o,
+            };
+            let _ = response.send((session, encoding::reply(session, req_id, &o)));
             Ok(())
         }
         Event::QueryBalance(user_id, currency, session, req_id) => {
-            let a = assets::get_balance_to_owned(&data.accounts, &user_id, currency);
-            let v = to_vec(&a).unwrap_or_default();
-            let _ = response.send((session, Message::new_req(req_id, v)));
+            let v = replica::balance(&data.accounts, &user_id, currency);
+            let _ = response.send((session, encoding::reply(session, req_id, &v)));
             Ok(())
         }
-        Event::QueryAccounts(user_id, session, req_id) => {
-            let a = assets::get_account_to_owned(&data.accounts, &user_id);
-            let v = to_vec(&a).unwrap_or_default();
-            let _ = response.send((session, Message::new_req(req_id, v)));
+        Event::QueryAccounts(user_id, valued, session, req_id) => {
+            let v = if valued {
+                let a = replica::valued_account(
+                    &data.accounts,
+                    &data.orderbooks,
+                    &data.last_prices,
+                    &user_id,
+                );
+                encoding::reply(session, req_id, &a)
+            } else {
+                encoding::reply(session, req_id, &replica::account(&data.accounts, &user_id))
+            };
+            let _ = response.send((session, v));
             Ok(())
         }
         Event::QueryExchangeFee(symbol, session, req_id) => {
@@ -455,23 +1315,1081 @@ fn do_execute(
                 .map(|b| (b.maker_fee, b.taker_fee))
                 .unwrap_or_default();
             let map = HashMap::from([("maker_fee", maker), ("taker_fee", taker)]);
-            let v = to_vec(&map).unwrap_or_default();
-            let _ = response.send((session, Message::new_req(req_id, v)));
+            let _ = response.send((session, encoding::reply(session, req_id, &map)));
             Ok(())
         }
         Event::QueryAllOrderbooks(session, req_id) => {
             let depth = data
                 .orderbooks
                 .iter()
-                .map(|(s, o)| (*s, o).into())
+                .map(|(s, o)| (*s, &**o).into())
                 .collect::<Vec<Depth>>();
-            let v = to_vec(&depth).unwrap_or_default();
-            let _ = response.send((session, Message::new_req(req_id, v)));
+            let _ = response.send((session, encoding::reply(session, req_id, &depth)));
+            Ok(())
+        }
+        Event::QueryBrokerExecution(broker, session, req_id) => {
+            let stat = ephemeral.get_broker_execution(&broker).canonical();
+            let _ = response.send((session, encoding::reply(session, req_id, &stat)));
+            Ok(())
+        }
+        Event::QueryBrokerRevenue(broker, session, req_id) => {
+            let a = assets::get_account_to_owned(&data.accounts, &broker_account(&broker))
+                .into_iter()
+                .map(|(c, b)| (c, canonical::balance(b)))
+                .collect::<Account>();
+            let _ = response.send((session, encoding::reply(session, req_id, &a)));
+            Ok(())
+        }
+        Event::QueryTrades(symbol, limit, session, req_id) => {
+            let trades = ephemeral.recent_trades.query(&symbol, limit);
+            let trades = match data.orderbooks.get(&symbol) {
+                Some(orderbook) => trades.canonical(&Scales::from(&**orderbook)),
+                None => trades,
+            };
+            let _ = response.send((session, encoding::reply(session, req_id, &trades)));
+            Ok(())
+        }
+        Event::QueryDepth(symbol, limit, tick, session, req_id) => {
+            let snapshot = match data.orderbooks.get(&symbol) {
+                Some(orderbook) => {
+                    // the changes made by the system(e.g. the expanded cancels) are not published
+                    // yet, publish them before serving the snapshot so the deltas keep chained
+                    let _ = publish_depth(
+                        data.current_event_id,
+                        symbol,
+                        orderbook,
+                        ephemeral,
+                        response,
+                    );
+                    ephemeral
+                        .get_depth(&symbol, None)
+                        .map(|s| match (limit, tick) {
+                            (None, None) => s,
+                            _ => DepthSnapshot {
+                                update_id: s.update_id,
+                                depth: s.depth.aggregate(limit, tick),
+                            },
+                        })
+                }
+                None => None,
+            };
+            let _ = response.send((session, encoding::reply(session, req_id, &snapshot)));
+            Ok(())
+        }
+        Event::QueryLastPrice(symbol, session, req_id) => {
+            let canonical = |p: &LastPrice| match data.orderbooks.get(&p.symbol) {
+                Some(orderbook) => p.canonical(&Scales::from(&**orderbook)),
+                None => *p,
+            };
+            let v = match symbol {
+                Some(symbol) => encoding::reply(
+                    session,
+                    req_id,
+                    &data.last_prices.get(&symbol).map(canonical),
+                ),
+                None => {
+                    let mut prices = data.last_prices.values().map(canonical).collect::<Vec<_>>();
+                    prices.sort_by_key(|p| p.symbol);
+                    encoding::reply(session, req_id, &prices)
+                }
+            };
+            let _ = response.send((session, v));
+            Ok(())
+        }
+        Event::UpdateCurrency(id, cmd) => {
+            data.current_event_id = id;
+            log::info!(
+                "currency {} switched to {:?} at {}",
+                cmd.currency,
+                cmd.mode,
+                id
+            );
+            data.set_currency_mode(cmd.currency, cmd.mode);
+            if let Some(max_frozen) = cmd.max_frozen {
+                data.set_frozen_limit(cmd.currency, max_frozen);
+            }
+            Ok(())
+        }
+        Event::UpdateFeeTiers(id, tiers) => {
+            data.current_event_id = id;
+            log::info!("fee tiers replaced by {:?} at {}", tiers, id);
+            data.fee_tiers = tiers;
+            Ok(())
+        }
+        Event::QueryBookStats(symbol, session, req_id) => {
+            let from = std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .map(|d| d.as_secs())
+                .unwrap_or_default()
+                .saturating_sub(ticker::WINDOW);
+            let klines = kline::KLINES.read().unwrap();
+            let stats = |(symbol, orderbook): (&Symbol, &CopyOnWrite<OrderBook>)| {
+                let daily = klines.rolling(*symbol, from);
+                stats::book_stats(
+                    *symbol,
+                    orderbook,
+                    data.last_prices.get(symbol),
+                    daily.as_ref(),
+                )
+                .canonical(&Scales::from(&**orderbook))
+            };
+            let v = match symbol {
+                Some(symbol) => {
+                    let stats = data.orderbooks.get_key_value(&symbol).map(stats);
+                    encoding::reply(session, req_id, &stats)
+                }
+                None => {
+                    let mut all = data.orderbooks.iter().map(stats).collect::<Vec<_>>();
+                    all.sort_by_key(|s| s.symbol);
+                    encoding::reply(session, req_id, &all)
+                }
+            };
+            let _ = response.send((session, v));
+            Ok(())
+        }
+        Event::QueryCurrencies(session, req_id) => {
+            let _ = response.send((session, encoding::reply(session, req_id, &data.currencies)));
+            Ok(())
+        }
+        Event::QuerySessionProgress(target, session, req_id) => {
+            let v = json!({
+                "session": target,
+                "event_id": ephemeral.get_session_progress(target),
+            });
+            let _ = response.send((session, encoding::reply(session, req_id, &v)));
+            Ok(())
+        }
+        Event::QueryConfigHistory(symbol, session, req_id) => {
+            let _ = response.send((
+                session,
+                encoding::reply(session, req_id, &ephemeral.config_history.query(symbol)),
+            ));
+            Ok(())
+        }
+        Event::QuerySystemFees(session, req_id) => {
+            let v = replica::account(&data.accounts, &SYSTEM);
+            let _ = response.send((session, encoding::reply(session, req_id, &v)));
+            Ok(())
+        }
+        Event::WithdrawFees(id, cmd) => {
+            data.current_event_id = id;
+            if cmd.treasury == SYSTEM {
+                return Err(EventsError::EventIgnored(
+                    id,
+                    anyhow!("the treasury can't be SYSTEM"),
+                ));
+            }
+            let accrued = assets::get_balance_to_owned(&data.accounts, &SYSTEM, cmd.currency);
+            let amount = cmd.amount.unwrap_or(accrued.available);
+            if amount.is_zero() || amount > accrued.available {
+                return Err(EventsError::EventIgnored(
+                    id,
+                    anyhow!(
+                        "{} of currency {} can't be withdrawn from the accrued {}",
+                        amount,
+                        cmd.currency,
+                        accrued.available
+                    ),
+                ));
+            }
+            let before = assets::get_balance_to_owned(&data.accounts, &cmd.treasury, cmd.currency);
+            assets::deduct_available(&mut data.accounts, &SYSTEM, cmd.currency, amount)
+                .expect("checked;qed");
+            let after =
+                assets::add_to_available(&mut data.accounts, &cmd.treasury, cmd.currency, amount)
+                    .expect("deducted from SYSTEM;qed");
+            log::info!(
+                "{} of currency {} withdrawn to the treasury {:?} at {}",
+                amount,
+                cmd.currency,
+                cmd.treasury,
+                id
+            );
+            if C.dry_run.is_none() {
+                if let Err(e) =
+                    statement::record_fee_withdrawal(&OUTPUT_STORE, id, &cmd, amount, &after)
+                {
+                    log::error!("unable to record the fee withdrawal at {}, {:?}", id, e);
+                }
+            }
+            publish_transfer(
+                id,
+                statement::fee_withdrawal_entries(id, &cmd, amount, &after),
+                response,
+            )?;
+            let delta = prover::prove_fee_withdrawal(id, &cmd, amount, &before, &after);
+            save_proof(delta, &mut data.merkle_tree, ephemeral)?;
             Ok(())
         }
         Event::Dump(id) => {
-            snapshot::dump(id, data);
+            match ephemeral.prover.as_ref() {
+                // the tree is attached by the pipeline after the proofs before
+                Some(pipeline) => pipeline
+                    .dump(id, data.clone())
+                    .map_err(|_| EventsError::Interrupted(id))?,
+                None => snapshot::dump(id, data),
+            }
+            if let Err(e) = ephemeral.recent_trades.save() {
+                log::error!("unable to save recent trades at {}, {:?}", id, e);
+            }
+            // exported as metrics on every checkpoint
+            log::info!(
+                "memory stats at {}: {}",
+                id,
+                serde_json::to_string(&stats::measure(id, data)).unwrap_or_default()
+            );
+            log::info!(
+                "ring buffer stats at {}: {}",
+                id,
+                serde_json::to_string(&ring::stats()).unwrap_or_default()
+            );
+            Ok(())
+        }
+        Event::CheckMerkle(user_id, symbol, check_id) => {
+            let id = data.current_event_id;
+            let leaves = verify::select_leaves(data, user_id, symbol);
+            match ephemeral.prover.as_ref() {
+                // checked by the pipeline once the proofs before are generated
+                Some(pipeline) => pipeline
+                    .check(check_id, id, leaves)
+                    .map_err(|_| EventsError::Interrupted(id))?,
+                None => verify::publish_root_check(
+                    check_id,
+                    Ok(verify::check_root(&data.merkle_tree, id, leaves)),
+                ),
+            }
             Ok(())
         }
     }
 }
+
+/// the orderbook of a new symbol, also opened from the genesis file
+pub(crate) fn new_orderbook(cmd: &SymbolCmd) -> OrderBook {
+    let orderbook = OrderBook::new(
+        cmd.base_scale,
+        cmd.quote_scale,
+        cmd.taker_fee,
+        cmd.maker_fee,
+        cmd.base_taker_fee,
+        cmd.base_maker_fee,
+        cmd.fee_times,
+        cmd.min_amount,
+        cmd.min_vol,
+        cmd.enable_market_order,
+        cmd.open,
+    );
+    OrderBook {
+        broker_share: cmd.broker_share.unwrap_or_default(),
+        tick_size: cmd.tick_size.unwrap_or_default(),
+        lot_size: cmd.lot_size.unwrap_or_default(),
+        price_band: cmd.price_band.unwrap_or_default(),
+        self_trade_prevention: cmd.self_trade_prevention.unwrap_or_default(),
+        max_open_notional: cmd.max_open_notional.unwrap_or_default(),
+        max_open_orders: cmd.max_open_orders.unwrap_or_default(),
+        trading_hours: cmd.trading_hours.clone().unwrap_or_default(),
+        circuit_breaker: cmd.circuit_breaker.unwrap_or_default(),
+        block_trade_min_amount: cmd.block_trade_min_amount.unwrap_or_default(),
+        block_trade_report_delay: cmd.block_trade_report_delay.unwrap_or_default(),
+        ..orderbook
+    }
+}
+
+/// the deltas of the sharded orders are proven in order after merging, the others are sent to
+/// the prover pipeline if started, otherwise proven in place, e.g. replaying offline
+fn save_proof(
+    delta: prover::StateDelta,
+    merkle_tree: &mut GlobalStates,
+    ephemeral: &mut Ephemeral,
+) -> Result<(), EventsError> {
+    let id = delta.event_id;
+    match (
+        ephemeral.deferred_proofs.as_mut(),
+        ephemeral.prover.as_ref(),
+    ) {
+        (Some(deltas), _) => deltas.push(delta),
+        (None, Some(pipeline)) => pipeline
+            .prove(delta)
+            .inspect_err(|e| log::error!("{}", e))
+            .map_err(|_| EventsError::Interrupted(id))?,
+        (None, None) => prover::save_proof(delta.prove(merkle_tree))
+            .inspect_err(|e| log::error!("{}", e))
+            .map_err(|_| EventsError::Interrupted(id))?,
+    }
+    Ok(())
+}
+
+/// the statements shouldn't block the matching
+fn record_statements(id: u64, out: &[Output]) {
+    if C.dry_run.is_some() {
+        return;
+    }
+    if let Err(e) = statement::record(&OUTPUT_STORE, out) {
+        log::error!("unable to record the statements at {}, {:?}", id, e);
+    }
+}
+
+fn record_transfer(id: u64, cmd: &input::AssetsCmd, after: &Balance) {
+    if C.dry_run.is_some() {
+        return;
+    }
+    if let Err(e) = statement::record_transfer(&OUTPUT_STORE, id, cmd, after) {
+        log::error!("unable to record the transfer at {}, {:?}", id, e);
+    }
+}
+
+fn record_sub_transfer(
+    id: u64,
+    time: Timestamp,
+    cmd: &input::SubTransferCmd,
+    after: (&Balance, &Balance),
+) {
+    if C.dry_run.is_some() {
+        return;
+    }
+    if let Err(e) = statement::record_sub_transfer(&OUTPUT_STORE, id, time, cmd, after) {
+        log::error!("unable to record the sub-transfer at {}, {:?}", id, e);
+    }
+}
+
+/// the transfers from the chain carry no session so they are broadcasted on replaying as well,
+/// the receivers dedup them by `event_id`
+fn publish_transfer(
+    id: u64,
+    entries: Vec<(UserId, statement::Entry)>,
+    response: &ResponseChannel,
+) -> ExecutionResult {
+    let reports = statement::TransferReport::from_entries(entries);
+    response
+        .send((
+            0,
+            Message::new_broadcast(
+                input::TRANSFER_EXECUTED,
+                to_vec(&reports).unwrap_or_default(),
+            ),
+        ))
+        .map_err(|_| EventsError::Interrupted(id))
+}
+
+/// the outputs of one match share the stamp
+fn stamp(out: &mut [Output], micros: u64) {
+    for o in out.iter_mut() {
+        o.timestamp_us = micros;
+    }
+}
+
+/// the closed orders are moved into the order history
+fn merge_order(orders: &mut orders::UserOrders, cr: &Output) -> Option<PendingOrder> {
+    let o = orders.merge(cr);
+    let closed = matches!(
+        cr.state,
+        OrderState::Filled | OrderState::Canceled | OrderState::ConditionallyCanceled
+    );
+    if let Some(order) = o.clone().filter(|_| closed && C.dry_run.is_none()) {
+        // the history shouldn't block the matching
+        if let Err(e) = archive::archive(&OUTPUT_STORE, order, cr.event_id, cr.timestamp) {
+            log::error!("unable to archive order at {}, {:?}", cr.event_id, e);
+        }
+    }
+    o
+}
+
+fn system_cancel(symbol: &Symbol, order: &Order, timestamp: Timestamp) -> Command {
+    Command {
+        cmd: input::cmd::CANCEL,
+        base: Some(symbol.0),
+        quote: Some(symbol.1),
+        user_id: Some(order.user.to_string()),
+        order_id: Some(order.id),
+        nonce: Some(0),
+        signature: Some(String::new()),
+        timestamp: Some(timestamp),
+        ..Default::default()
+    }
+}
+
+/// the limit taker is checked on the book with its self-trades cancelled
+fn check_limit(
+    id: u64,
+    cmd: &input::LimitCmd,
+    session: u64,
+    req_id: u64,
+    data: &Data,
+) -> ExecutionResult {
+    let orderbook = &data.orderbooks[&cmd.symbol];
+    if cmd.time_in_force == TimeInForce::FillOrKill
+        && !matcher::is_fillable(
+            orderbook,
+            cmd.user_id,
+            cmd.price,
+            cmd.amount,
+            cmd.ask_or_bid,
+        )
+    {
+        return Err(EventsError::EventRejected(
+            id,
+            session,
+            req_id,
+            RejectReason::Unfillable.into(),
+        ));
+    }
+    let cap = orderbook.max_open_notional;
+    if !cap.is_zero() && cmd.time_in_force == TimeInForce::GoodTillCancel {
+        let resting = orderbook.estimate_resting(cmd.price, cmd.amount, cmd.ask_or_bid);
+        if !resting.is_zero() && orderbook.open_notional() + resting * cmd.price > cap {
+            return Err(EventsError::EventRejected(
+                id,
+                session,
+                req_id,
+                RejectReason::OpenNotionalExceeded.into(),
+            ));
+        }
+    }
+    if cmd.time_in_force == TimeInForce::GoodTillCancel {
+        let resting = orderbook.estimate_resting(cmd.price, cmd.amount, cmd.ask_or_bid);
+        let (currency, _) = assets::freeze_if(&cmd.symbol, cmd.ask_or_bid, cmd.price, resting);
+        check_risk_limits(
+            data,
+            cmd,
+            resting,
+            (orderbook.max_open_orders != 0).then_some(orderbook.max_open_orders),
+            data.frozen_limits.get(&currency).copied(),
+        )
+        .map_err(|r| EventsError::EventRejected(id, session, req_id, r.into()))?;
+    }
+    if let Some(other) = cmd.oco {
+        orderbook
+            .find_order(other)
+            .filter(|o| o.user == cmd.user_id)
+            .filter(|_| !oco::is_linked(&data.links, &cmd.symbol, other))
+            .ok_or(EventsError::EventRejected(
+                id,
+                session,
+                req_id,
+                anyhow!("the linked order doesn't exist or is linked already"),
+            ))?;
+    }
+    Ok(())
+}
+
+/// the orders are rejected out of the trading hours, and the takers while halted
+fn check_trading_session(
+    data: &Data,
+    symbol: &Symbol,
+    time: Timestamp,
+    is_taker: impl FnOnce() -> bool,
+) -> Result<(), RejectReason> {
+    if let Some(book) = data.orderbooks.get(symbol) {
+        if !breaker::is_trading_hours(&book.trading_hours, time) {
+            return Err(RejectReason::MarketClosed);
+        }
+    }
+    match data.breakers.get(symbol) {
+        Some(b) if b.is_halted(time) && is_taker() => Err(RejectReason::TradingHalted),
+        _ => Ok(()),
+    }
+}
+
+/// only the orders resting on the book are limited, the takers are always accepted
+fn check_risk_limits(
+    data: &Data,
+    cmd: &input::LimitCmd,
+    resting: Amount,
+    max_open_orders: Option<u32>,
+    max_frozen: Option<Amount>,
+) -> Result<(), RejectReason> {
+    if resting.is_zero() {
+        return Ok(());
+    }
+    if max_open_orders
+        .filter(|max| data.orders.count(cmd.user_id, cmd.symbol) >= *max as usize)
+        .is_some()
+    {
+        return Err(RejectReason::OpenOrdersExceeded);
+    }
+    let (currency, val) = assets::freeze_if(&cmd.symbol, cmd.ask_or_bid, cmd.price, resting);
+    let frozen = assets::get_balance_to_owned(&data.accounts, &cmd.user_id, currency).frozen;
+    if max_frozen.filter(|max| frozen + val > *max).is_some() {
+        return Err(RejectReason::FrozenExceeded);
+    }
+    Ok(())
+}
+
+fn self_trade_prevention(
+    orderbook: &OrderBook,
+    order: Option<SelfTradePrevention>,
+) -> SelfTradePrevention {
+    order.unwrap_or(orderbook.self_trade_prevention)
+}
+
+/// the other legs of the OCO pairs are cancelled by the system after the triggering event
+fn cancel_others(
+    id: u64,
+    symbol: &Symbol,
+    orderbook: &OrderBook,
+    others: &[OrderId],
+    timestamp: Timestamp,
+    sequencer: &SequencerChannel,
+) -> Result<(), EventsError> {
+    for o in others.iter().filter_map(|o| orderbook.find_order(*o)) {
+        sequencer
+            .send(Input::new(system_cancel(symbol, o, timestamp)))
+            .map_err(|_| EventsError::Interrupted(id))?;
+    }
+    Ok(())
+}
+
+/// broadcast the block trades due at `time` and publish their outputs, `false` if interrupted
+fn release_prints(
+    time: Timestamp,
+    ephemeral: &mut Ephemeral,
+    market: &MarketChannel,
+    response: &ResponseChannel,
+) -> bool {
+    for print in ephemeral.block_prints.release(time) {
+        if print.broadcast {
+            let msg = Message::new_broadcast(input::BLOCK_TRADE_REPORTED, print.report);
+            if response.send((0, msg)).is_err() {
+                return false;
+            }
+        }
+        if market.send((print.outputs, Instant::now())).is_err() {
+            return false;
+        }
+    }
+    true
+}
+
+/// broadcast the changed levels of the symbol since last time
+fn publish_depth(
+    id: u64,
+    symbol: Symbol,
+    orderbook: &OrderBook,
+    ephemeral: &mut Ephemeral,
+    response: &ResponseChannel,
+) -> Result<(), EventsError> {
+    if let Some(delta) = ephemeral.update_depth(id, symbol, orderbook) {
+        response
+            .send((
+                0,
+                Message::new_broadcast(input::DEPTH_DELTA, to_vec(&delta).unwrap_or_default()),
+            ))
+            .map_err(|_| EventsError::Interrupted(id))?;
+        if let Some(top) = ephemeral.get_depth(&symbol, Some(imbalance::LEVELS)) {
+            imbalance::update(id, &top.depth);
+        }
+    }
+    Ok(())
+}
+
+/// cancel a resting order of the user, the proof and the outputs are left to the caller, the
+/// cancel is acknowledged only if `ack`
+fn cancel_order(
+    id: u64,
+    cmd: input::CancelCmd,
+    time: Timestamp,
+    session: u64,
+    req_id: u64,
+    ack: bool,
+    data: &mut Data,
+    ephemeral: &mut Ephemeral,
+    response: &ResponseChannel,
+    sequencer: &SequencerChannel,
+) -> Result<(prover::StateDelta, Vec<Output>), EventsError> {
+    // 0. symbol exsits
+    // 1. check order's owner
+    let orderbook = data
+        .orderbooks
+        .get_mut(&cmd.symbol)
+        .ok_or(EventsError::EventRejected(
+            id,
+            session,
+            req_id,
+            anyhow!("orderbook not found"),
+        ))?;
+    orderbook
+        .find_order(cmd.order_id)
+        .filter(|o| o.user == cmd.user_id)
+        .ok_or(EventsError::EventRejected(
+            id,
+            session,
+            req_id,
+            anyhow!("order doesn't exist"),
+        ))?;
+    let size = orderbook.size();
+    let (best_ask_before, best_bid_before) = orderbook.get_size_of_best();
+    let taker_base_before =
+        assets::get_balance_to_owned(&data.accounts, &cmd.user_id, cmd.symbol.0);
+    let taker_quote_before =
+        assets::get_balance_to_owned(&data.accounts, &cmd.user_id, cmd.symbol.1);
+    let mr = matcher::cancel(orderbook, cmd.order_id).ok_or(EventsError::EventRejected(
+        id,
+        session,
+        req_id,
+        anyhow!("order doesn't exist"),
+    ))?;
+    ephemeral.touch_depth(cmd.symbol, &mr);
+    ephemeral.order_session(cmd.symbol, cmd.order_id, true);
+    if session != 0 && ack {
+        response
+            .send((
+                session,
+                encoding::reply(
+                    session,
+                    req_id,
+                    &json!({
+                        "id": cmd.order_id,
+                        "event_id": id,
+                    }),
+                ),
+            ))
+            .map_err(|_| EventsError::Interrupted(id))?;
+        publish_depth(id, cmd.symbol, orderbook, ephemeral, response)?;
+    }
+    let mut out = clearing::clear(
+        &mut data.accounts,
+        id,
+        &cmd.symbol,
+        orderbook.taker_fee,
+        orderbook.maker_fee,
+        orderbook.broker_share,
+        &mr,
+        time,
+    );
+    stamp(&mut out, clock::stamp(time));
+    for cr in out.iter() {
+        merge_order(&mut data.orders, cr);
+    }
+    let others = oco::unlink(&mut data.links, &cmd.symbol, &out);
+    if session != 0 {
+        cancel_others(id, &cmd.symbol, orderbook, &others, time, sequencer)?;
+    }
+    let delta = prover::prove_trade_cmd(
+        data,
+        cmd.nonce,
+        cmd.signature.clone(),
+        cmd.into(),
+        size.0,
+        size.1,
+        best_ask_before.unwrap_or((Decimal::zero(), Decimal::zero())),
+        best_bid_before.unwrap_or((Decimal::zero(), Decimal::zero())),
+        &taker_base_before,
+        &taker_quote_before,
+        &out,
+        &mr,
+    );
+    Ok((delta, out))
+}
+
+/// cancel the resting orders of the user a taker would cross, proven before the taker in the
+/// same event
+fn cancel_self_trades(
+    id: u64,
+    symbol: &Symbol,
+    crossed: &[Order],
+    time: Timestamp,
+    session: u64,
+    req_id: u64,
+    data: &mut Data,
+    ephemeral: &mut Ephemeral,
+    response: &ResponseChannel,
+    sequencer: &SequencerChannel,
+) -> Result<Vec<(prover::StateDelta, Vec<Output>)>, EventsError> {
+    tracing::debug!(
+        "event {} cancelling {} orders of the same user",
+        id,
+        crossed.len()
+    );
+    crossed
+        .iter()
+        .map(|o| {
+            let cmd = input::CancelCmd {
+                symbol: *symbol,
+                user_id: o.user,
+                order_id: o.id,
+                nonce: 0,
+                signature: vec![],
+            };
+            cancel_order(
+                id, cmd, time, session, req_id, false, data, ephemeral, response, sequencer,
+            )
+        })
+        .collect()
+}
+
+/// the taker is rejected after cancelling its self-trades, which are proven and published alone
+fn reject_taker(
+    id: u64,
+    symbol: &Symbol,
+    session: u64,
+    cancelled: Vec<(prover::StateDelta, Vec<Output>)>,
+    e: EventsError,
+    data: &mut Data,
+    ephemeral: &mut Ephemeral,
+    market: &MarketChannel,
+    response: &ResponseChannel,
+) -> ExecutionResult {
+    if !cancelled.is_empty() && session != 0 {
+        publish_depth(id, *symbol, &data.orderbooks[symbol], ephemeral, response)?;
+    }
+    save_batch(id, cancelled, data, ephemeral, market)?;
+    Err(e)
+}
+
+/// the deltas of one event are proven together and the outputs are sent in order
+fn save_batch(
+    id: u64,
+    batch: Vec<(prover::StateDelta, Vec<Output>)>,
+    data: &mut Data,
+    ephemeral: &mut Ephemeral,
+    market: &MarketChannel,
+) -> ExecutionResult {
+    if batch.is_empty() {
+        return Ok(());
+    }
+    let (deltas, outs): (Vec<_>, Vec<_>) = batch.into_iter().unzip();
+    save_proof(
+        prover::prove_batch(deltas),
+        &mut data.merkle_tree,
+        ephemeral,
+    )?;
+    for out in outs {
+        market
+            .send((out, Instant::now()))
+            .map_err(|_| EventsError::Interrupted(id))?;
+    }
+    Ok(())
+}
+
+/// freeze, match, clear and prove a taker order, only `GTC` orders may rest on the book, the
+/// self-trades `cancelled` before are proven along with it
+fn take_order(
+    id: u64,
+    cmd: input::LimitCmd,
+    time: Timestamp,
+    session: u64,
+    req_id: u64,
+    cancelled: Vec<(prover::StateDelta, Vec<Output>)>,
+    data: &mut Data,
+    ephemeral: &mut Ephemeral,
+    market: &MarketChannel,
+    response: &ResponseChannel,
+    sequencer: &SequencerChannel,
+) -> ExecutionResult {
+    let symbol = cmd.symbol;
+    let taken = fill_order(
+        id, cmd, time, session, req_id, true, data, ephemeral, response, sequencer,
+    );
+    let (delta, out, _) = match taken {
+        Ok(taken) => taken,
+        Err(e) => {
+            return reject_taker(
+                id, &symbol, session, cancelled, e, data, ephemeral, market, response,
+            )
+        }
+    };
+    let mut batch = cancelled;
+    batch.push((delta, out));
+    save_batch(id, batch, data, ephemeral, market)
+}
+
+/// the proof and the outputs are left to the caller, the order is acknowledged only if `ack`
+fn fill_order(
+    id: u64,
+    mut cmd: input::LimitCmd,
+    time: Timestamp,
+    session: u64,
+    req_id: u64,
+    ack: bool,
+    data: &mut Data,
+    ephemeral: &mut Ephemeral,
+    response: &ResponseChannel,
+    sequencer: &SequencerChannel,
+) -> Result<(prover::StateDelta, Vec<Output>, OrderId), EventsError> {
+    let orderbook = data.orderbooks.get_mut(&cmd.symbol).expect("checked;qed");
+    let scales = Scales::from(&**orderbook);
+    let (ask_size, bid_size) = orderbook.size();
+    let (best_ask_before, best_bid_before) = orderbook.get_size_of_best();
+    let taker_base_before =
+        assets::get_balance_to_owned(&data.accounts, &cmd.user_id, cmd.symbol.0);
+    let taker_quote_before =
+        assets::get_balance_to_owned(&data.accounts, &cmd.user_id, cmd.symbol.1);
+    #[cfg(feature = "overflow-audit")]
+    if cmd.vol.is_none() && cmd.price.checked_mul(cmd.amount).is_none() {
+        return Err(EventsError::EventRejected(
+            id,
+            session,
+            req_id,
+            RejectReason::Overflow.into(),
+        ));
+    }
+    // the fees of the symbol are overridden by the volume tiers of the taker and the makers
+    let taker_fee = data
+        .volumes
+        .tier(&data.fee_tiers, &cmd.user_id, cmd.symbol.1, time)
+        .map_or(orderbook.taker_fee, |t| t.taker_fee);
+    let shadow = C
+        .server
+        .shadow
+        .then(|| {
+            shadow::Shadow::capture(id, &cmd, orderbook, &data.accounts, taker_fee, |u| {
+                data.volumes
+                    .tier(&data.fee_tiers, u, cmd.symbol.1, time)
+                    .map_or(orderbook.maker_fee, |t| t.maker_fee)
+            })
+        })
+        .flatten();
+    let (c, val) = match cmd.vol {
+        Some(vol) => (cmd.symbol.1, vol),
+        None => assets::freeze_if(&cmd.symbol, cmd.ask_or_bid, cmd.price, cmd.amount),
+    };
+    assets::try_freeze(&mut data.accounts, &cmd.user_id, c, val)
+        .map_err(|e| EventsError::EventRejected(id, session, req_id, e))?;
+    // the book is copied on matching while shared so the rejection restores it cheaply
+    #[cfg(feature = "overflow-audit")]
+    let before = orderbook.clone();
+    let mut mr = match cmd.time_in_force {
+        TimeInForce::GoodTillCancel => matcher::execute_iceberg(
+            orderbook,
+            cmd.user_id,
+            cmd.broker,
+            cmd.price,
+            cmd.amount,
+            cmd.display.unwrap_or_default(),
+            cmd.ask_or_bid,
+        ),
+        // a `FOK` order is checked fillable before
+        TimeInForce::ImmediateOrCancel | TimeInForce::FillOrKill => matcher::execute_market(
+            orderbook,
+            cmd.user_id,
+            cmd.broker,
+            cmd.price,
+            cmd.amount,
+            cmd.ask_or_bid,
+        ),
+    };
+    // the quote budget is frozen entirely, the unspent is returned on clearing
+    mr.taker.vol = cmd.vol;
+    for m in mr.maker.iter_mut() {
+        m.fee = data
+            .volumes
+            .tier(&data.fee_tiers, &m.user_id, cmd.symbol.1, time)
+            .map(|t| t.maker_fee);
+    }
+    #[cfg(feature = "overflow-audit")]
+    if let Err(e) = clearing::audit(
+        &data.accounts,
+        &cmd.symbol,
+        taker_fee,
+        orderbook.maker_fee,
+        orderbook.broker_share,
+        &mr,
+    ) {
+        *orderbook = before;
+        assets::try_unfreeze(&mut data.accounts, &cmd.user_id, c, val).expect("just frozen;qed");
+        log::warn!("rejecting event {}: {:?}", id, e);
+        return Err(EventsError::EventRejected(
+            id,
+            session,
+            req_id,
+            RejectReason::Overflow.into(),
+        ));
+    }
+    ephemeral.touch_depth(cmd.symbol, &mr);
+    data.orders.insert(PendingOrder {
+        order_id: mr.taker.order_id,
+        user_id: cmd.user_id,
+        symbol: cmd.symbol,
+        direction: mr.taker.ask_or_bid.into(),
+        create_timestamp: time,
+        amount: cmd.amount,
+        price: cmd.price,
+        status: OrderState::Placed.into(),
+        matched_quote_amount: Decimal::zero(),
+        matched_base_amount: Decimal::zero(),
+        base_fee: Decimal::zero(),
+        quote_fee: Decimal::zero(),
+    });
+    if let Some(ref c) = cmd.client_order_id {
+        client_orders::record(
+            &mut data.client_orders,
+            &cmd.symbol,
+            &cmd.user_id,
+            c.clone(),
+            mr.taker.order_id,
+        );
+    }
+    let micros = clock::stamp(time);
+    // compatiable with old version since we don't use mysql auto increment id anymore
+    // session=0 indicates replaying from snapshot
+    let trades = mr
+        .maker
+        .iter()
+        .map(|m| Trade {
+            event_id: id,
+            symbol: cmd.symbol,
+            price: m.price,
+            amount: m.filled,
+            taker_side: cmd.ask_or_bid,
+            timestamp: time,
+            timestamp_us: micros,
+        })
+        .collect::<Vec<_>>();
+    if let Some(m) = mr.maker.last() {
+        data.last_prices.insert(
+            cmd.symbol,
+            LastPrice {
+                symbol: cmd.symbol,
+                price: m.price,
+                event_id: id,
+                timestamp: time,
+            },
+        );
+    }
+    let config = orderbook.circuit_breaker;
+    if !config.max_move.is_zero() {
+        let breaker = data.breakers.entry(cmd.symbol).or_default();
+        if mr
+            .maker
+            .iter()
+            .any(|m| breaker.trade(m.price, time, &config))
+        {
+            log::warn!(
+                "circuit breaker of {:?} tripped at {}, halted until {}",
+                cmd.symbol,
+                id,
+                breaker.halted_until
+            );
+        }
+    }
+    // the replayed trades are recorded as well
+    ephemeral.recent_trades.record(&trades);
+    if session != 0 {
+        if ack {
+            let mut accepted = json!({
+                "id": mr.taker.order_id,
+                "event_id": id,
+            });
+            if let Some(ref c) = cmd.client_order_id {
+                accepted["client_order_id"] = c.clone().into();
+            }
+            response
+                .send((session, encoding::reply(session, req_id, &accepted)))
+                .map_err(|_| EventsError::Interrupted(id))?;
+        }
+        publish_depth(id, cmd.symbol, orderbook, ephemeral, response)?;
+        if !trades.is_empty() {
+            response
+                .send((
+                    0,
+                    Message::new_broadcast(
+                        input::TRADE_EXECUTED,
+                        to_vec(&trades.canonical(&scales)).unwrap_or_default(),
+                    ),
+                ))
+                .map_err(|_| EventsError::Interrupted(id))?;
+        }
+    }
+    let mut out = clearing::clear(
+        &mut data.accounts,
+        id,
+        &cmd.symbol,
+        taker_fee,
+        orderbook.maker_fee,
+        orderbook.broker_share,
+        &mr,
+        time,
+    );
+    stamp(&mut out, micros);
+    if let Some(shadow) = shadow {
+        shadow.check(orderbook, &data.accounts);
+    }
+    data.volumes.record(&out, time);
+    record_statements(id, &out);
+    // appended to the audit log once the merkle root is known
+    audit::stage(id, cmd.symbol, time, &mr, &out);
+    let best_price = match cmd.ask_or_bid {
+        AskOrBid::Ask => best_bid_before.map(|b| b.0),
+        AskOrBid::Bid => best_ask_before.map(|a| a.0),
+    };
+    let execution = mr.execution(best_price);
+    if session != 0 {
+        ephemeral.record_execution(cmd.broker, cmd.ask_or_bid, execution.as_ref());
+    }
+    if let Some(broker) = cmd.broker.filter(|_| C.dry_run.is_none()) {
+        // the statistics shouldn't block the matching
+        if let Err(e) = flow::record(&broker, &cmd.symbol, id, time, &out) {
+            log::error!("unable to record the flow of broker at {}, {:?}", id, e);
+        }
+    }
+    if session != 0 && orderbook.find_order(mr.taker.order_id).is_some() {
+        ephemeral.rest_order(cmd.symbol, mr.taker.order_id, session);
+    }
+    for cr in out.iter() {
+        let o = merge_order(&mut data.orders, cr);
+        let closed = matches!(
+            cr.state,
+            OrderState::Filled | OrderState::Canceled | OrderState::ConditionallyCanceled
+        );
+        let owner = match cr.role {
+            Role::Taker => Some(session).filter(|s| *s != 0),
+            Role::Maker => ephemeral.order_session(cmd.symbol, cr.order_id, closed),
+        };
+        // private to the session placed the order
+        if let Some(owner) = owner {
+            let report = o.map(|order| {
+                FillReport {
+                    order,
+                    execution: execution.clone().filter(|_| cr.role == Role::Taker),
+                    timestamp_us: cr.timestamp_us,
+                }
+                .canonical(&scales)
+            });
+            response
+                .send((
+                    owner,
+                    Message::new_broadcast(
+                        input::ORDER_MATCHED,
+                        to_vec(&report).unwrap_or_default(),
+                    ),
+                ))
+                .map_err(|_| EventsError::Interrupted(id))?;
+        }
+    }
+    let mut others = oco::unlink(&mut data.links, &cmd.symbol, &out);
+    if let Some(other) = cmd.oco {
+        match orderbook.find_order(mr.taker.order_id) {
+            Some(_) if mr.maker.is_empty() => {
+                oco::link(&mut data.links, &cmd.symbol, mr.taker.order_id, other)
+            }
+            _ => others.push(other),
+        }
+    }
+    // replaying, the cancels are sequenced already
+    if session != 0 {
+        cancel_others(id, &cmd.symbol, orderbook, &others, time, sequencer)?;
+    }
+    let maker_fee = orderbook.maker_fee;
+    // a bid by quote is proved as a filled limit order of the actual amount, which leaves
+    // the same balances as freezing and returning the budget
+    if cmd.vol.is_some() {
+        cmd.amount = mr.maker.iter().map(|m| m.filled).sum();
+    }
+    let (nonce, signature) = (cmd.nonce, cmd.signature.clone());
+    // the makers of the fee tiers are proven along with their own fees
+    let encoded: FusoCommand = (cmd, maker_fee, taker_fee).into();
+    let delta = prover::prove_trade_cmd(
+        data,
+        nonce,
+        signature,
+        encoded.with_maker_fees(maker_fee, &mr.maker),
+        ask_size,
+        bid_size,
+        best_ask_before.unwrap_or((Decimal::zero(), Decimal::zero())),
+        best_bid_before.unwrap_or((Decimal::zero(), Decimal::zero())),
+        &taker_base_before,
+        &taker_quote_before,
+        &out,
+        &mr,
+    );
+    Ok((delta, out, mr.taker.order_id))
+}
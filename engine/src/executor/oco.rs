@@ -0,0 +1,105 @@
+// Copyright 2021-2023 UINB Technologies Pte. Ltd.
+
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// 	http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::{core::*, matcher::State, output::Output};
+use std::collections::HashMap;
+
+pub type Links = HashMap<Symbol, HashMap<OrderId, OrderId>>;
+
+pub fn link(links: &mut Links, symbol: &Symbol, a: OrderId, b: OrderId) {
+    let pairs = links.entry(*symbol).or_default();
+    pairs.insert(a, b);
+    pairs.insert(b, a);
+}
+
+pub fn is_linked(links: &Links, symbol: &Symbol, order_id: OrderId) -> bool {
+    links
+        .get(symbol)
+        .is_some_and(|pairs| pairs.contains_key(&order_id))
+}
+
+/// unlink the legs filled or cancelled in the outputs, return the other legs to cancel
+pub fn unlink(links: &mut Links, symbol: &Symbol, out: &[Output]) -> Vec<OrderId> {
+    let Some(pairs) = links.get_mut(symbol) else {
+        return vec![];
+    };
+    let mut others = vec![];
+    for o in out.iter().filter(|o| o.state != State::Placed) {
+        if let Some(other) = pairs.remove(&o.order_id) {
+            pairs.remove(&other);
+            others.push(other);
+        }
+    }
+    if pairs.is_empty() {
+        links.remove(symbol);
+    }
+    others
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{matcher::Role, orderbook::AskOrBid};
+    use rust_decimal::Decimal;
+
+    fn output(order_id: OrderId, state: State) -> Output {
+        Output {
+            event_id: 1,
+            order_id,
+            user_id: UserId::from_low_u64_be(1),
+            symbol: (1, 0),
+            state,
+            role: Role::Maker,
+            ask_or_bid: AskOrBid::Ask,
+            price: Decimal::ONE,
+            quote_charge: Decimal::ZERO,
+            quote_delta: Decimal::ZERO,
+            quote_available: Decimal::ZERO,
+            quote_frozen: Decimal::ZERO,
+            base_charge: Decimal::ZERO,
+            base_delta: Decimal::ZERO,
+            base_available: Decimal::ZERO,
+            base_frozen: Decimal::ZERO,
+            timestamp: 0,
+            timestamp_us: 0,
+        }
+    }
+
+    #[test]
+    pub fn test_oco_links() {
+        let mut links = Links::new();
+        let symbol = (1, 0);
+        link(&mut links, &symbol, 1, 2);
+        link(&mut links, &symbol, 3, 4);
+        assert!(is_linked(&links, &symbol, 2));
+        assert!(!is_linked(&links, &(2, 0), 2));
+        assert!(unlink(&mut links, &symbol, &[output(1, State::Placed)]).is_empty());
+        assert!(unlink(&mut links, &(2, 0), &[output(1, State::Filled)]).is_empty());
+        // the partially filled ones cancel the others as well
+        assert_eq!(
+            vec![1, 4],
+            unlink(
+                &mut links,
+                &symbol,
+                &[
+                    output(2, State::PartiallyFilled),
+                    output(3, State::Canceled),
+                    output(5, State::Filled)
+                ]
+            )
+        );
+        assert!(!is_linked(&links, &symbol, 1));
+        assert!(links.is_empty());
+    }
+}
@@ -17,6 +17,9 @@ use rust_decimal::Decimal;
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 
+/// the pending orders replied by `QUERY_USER_ORDERS` at most
+pub const MAX_USER_ORDERS: usize = 1000;
+
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct PendingOrder {
     pub order_id: OrderId,
@@ -43,23 +46,71 @@ impl PendingOrder {
     }
 }
 
+/// the private fill report broadcasted as `ORDER_MATCHED`
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct FillReport {
+    #[serde(flatten)]
+    pub order: PendingOrder,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub execution: Option<Execution>,
+    /// the microseconds of matching
+    #[serde(default)]
+    pub timestamp_us: u64,
+}
+
+/// the pending orders sorted by id, `next` is the first order id of the remaining ones
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct UserOrdersPage {
+    pub orders: Vec<PendingOrder>,
+    pub truncated: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub next: Option<OrderId>,
+}
+
 #[derive(Clone, Default, Serialize, Deserialize)]
 pub struct UserOrders {
-    pub orders: HashMap<(UserId, Symbol), HashMap<OrderId, PendingOrder>>,
+    pub orders: ChunkedMap<(UserId, Symbol), HashMap<OrderId, PendingOrder>>,
 }
 
 impl UserOrders {
     pub fn new() -> Self {
         Self {
-            orders: HashMap::new(),
+            orders: ChunkedMap::new(),
         }
     }
 
-    pub fn list(&self, user_id: UserId, symbol: Symbol) -> Vec<PendingOrder> {
-        self.orders
-            .get(&(user_id, symbol))
-            .map(|orders| orders.values().cloned().collect())
-            .unwrap_or_default()
+    pub fn count(&self, user_id: UserId, symbol: Symbol) -> usize {
+        self.orders.get(&(user_id, symbol)).map_or(0, |o| o.len())
+    }
+
+    /// the orders with id not less than `from`
+    pub fn page(
+        &self,
+        user_id: UserId,
+        symbol: Symbol,
+        from: Option<OrderId>,
+        limit: usize,
+    ) -> UserOrdersPage {
+        let orders = match self.orders.get(&(user_id, symbol)) {
+            Some(orders) => orders,
+            None => return UserOrdersPage::default(),
+        };
+        let mut ids = orders
+            .keys()
+            .filter(|id| from.map_or(true, |f| **id >= f))
+            .copied()
+            .collect::<Vec<_>>();
+        ids.sort_unstable();
+        let next = ids.get(limit).copied();
+        UserOrdersPage {
+            orders: ids
+                .iter()
+                .take(limit)
+                .map(|id| orders[id].clone())
+                .collect(),
+            truncated: next.is_some(),
+            next,
+        }
     }
 
     pub fn insert(&mut self, order: PendingOrder) {
@@ -121,3 +172,55 @@ impl UserOrders {
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use rust_decimal::prelude::Zero;
+
+    fn order(order_id: OrderId) -> PendingOrder {
+        PendingOrder {
+            order_id,
+            user_id: UserId::zero(),
+            symbol: (1, 0),
+            direction: 0,
+            create_timestamp: 0,
+            amount: Decimal::zero(),
+            price: Decimal::zero(),
+            status: 0,
+            matched_quote_amount: Decimal::zero(),
+            matched_base_amount: Decimal::zero(),
+            base_fee: Decimal::zero(),
+            quote_fee: Decimal::zero(),
+        }
+    }
+
+    #[test]
+    pub fn test_page() {
+        let mut orders = UserOrders::new();
+        [5, 1, 9, 3, 7]
+            .into_iter()
+            .for_each(|id| orders.insert(order(id)));
+        assert_eq!(5, orders.count(UserId::zero(), (1, 0)));
+        assert_eq!(0, orders.count(UserId::zero(), (2, 0)));
+        let ids =
+            |page: &UserOrdersPage| page.orders.iter().map(|o| o.order_id).collect::<Vec<_>>();
+        let page = orders.page(UserId::zero(), (1, 0), None, 2);
+        assert_eq!(vec![1, 3], ids(&page));
+        assert!(page.truncated);
+        assert_eq!(Some(5), page.next);
+        let page = orders.page(UserId::zero(), (1, 0), page.next, 2);
+        assert_eq!(vec![5, 7], ids(&page));
+        let page = orders.page(UserId::zero(), (1, 0), page.next, 2);
+        assert_eq!(vec![9], ids(&page));
+        assert!(!page.truncated);
+        assert_eq!(None, page.next);
+        let page = orders.page(UserId::zero(), (1, 0), None, MAX_USER_ORDERS);
+        assert_eq!(5, page.orders.len());
+        assert!(!page.truncated);
+        assert!(orders
+            .page(UserId::zero(), (2, 0), None, 2)
+            .orders
+            .is_empty());
+    }
+}
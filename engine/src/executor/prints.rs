@@ -0,0 +1,107 @@
+// Copyright 2021-2023 UINB Technologies Pte. Ltd.
+
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// 	http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::{core::*, output::Output};
+use std::{
+    collections::BTreeMap,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+/// the public report of a block trade and its outputs for the klines and the exports
+#[derive(Debug, Clone)]
+pub struct Print {
+    pub report: Vec<u8>,
+    pub outputs: Vec<Output>,
+    /// the prints due before replaying are broadcasted already
+    pub broadcast: bool,
+}
+
+/// the prints of the block trades held for `block_trade_report_delay` seconds of their symbols
+/// after the trades, the fills are replied to the parties immediately
+#[derive(Debug, Default)]
+pub struct BlockPrints {
+    // by the due time and the event id, the delays differ among the symbols
+    held: BTreeMap<(Timestamp, u64), Print>,
+}
+
+impl BlockPrints {
+    pub fn hold(&mut self, due: Timestamp, event_id: u64, mut print: Print) {
+        print.broadcast |= due > now();
+        self.held.insert((due, event_id), print);
+    }
+
+    /// the prints due at `time` in order
+    pub fn release(&mut self, time: Timestamp) -> Vec<Print> {
+        let held = match time.checked_add(1) {
+            Some(after) => self.held.split_off(&(after, 0)),
+            None => BTreeMap::new(),
+        };
+        std::mem::replace(&mut self.held, held)
+            .into_values()
+            .collect()
+    }
+
+    /// the time to wait for the next print
+    pub fn due_in(&self) -> Option<Duration> {
+        self.held
+            .keys()
+            .next()
+            .map(|(due, _)| Duration::from_secs(due.saturating_sub(now())))
+    }
+}
+
+pub fn now() -> Timestamp {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("clock;qed")
+        .as_secs()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn print(report: &[u8]) -> Print {
+        Print {
+            report: report.to_vec(),
+            outputs: vec![],
+            broadcast: false,
+        }
+    }
+
+    #[test]
+    pub fn test_block_prints() {
+        let mut prints = BlockPrints::default();
+        let now = now();
+        prints.hold(now + 900, 2, print(b"2"));
+        prints.hold(now + 60, 3, print(b"3"));
+        prints.hold(now - 10, 1, print(b"1"));
+        assert_eq!(Some(Duration::ZERO), prints.due_in());
+        let released = prints.release(now);
+        assert_eq!(1, released.len());
+        // replayed after due
+        assert!(!released[0].broadcast);
+        assert!(prints.due_in().unwrap() > Duration::from_secs(50));
+        let released = prints.release(now + 900);
+        assert_eq!(
+            vec![b"3".to_vec(), b"2".to_vec()],
+            released
+                .iter()
+                .map(|p| p.report.clone())
+                .collect::<Vec<_>>()
+        );
+        assert!(released.iter().all(|p| p.broadcast));
+        assert_eq!(None, prints.due_in());
+    }
+}
@@ -0,0 +1,236 @@
+// Copyright 2021-2023 UINB Technologies Pte. Ltd.
+
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// 	http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::{
+    assets,
+    config::C,
+    core::*,
+    fusotao,
+    orderbook::{Order, OrderBook},
+    output::canonical::{self, Canonical, Scales},
+    valuation,
+};
+use serde::Serialize;
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::{Arc, RwLock},
+    time::{Duration, Instant},
+};
+
+lazy_static::lazy_static! {
+    static ref REPLICA: RwLock<Option<Arc<Replica>>> = RwLock::new(None);
+}
+
+/// the accounts and orderbooks published by the executor, shared rather than copied so the
+/// executor clones what it mutates afterwards
+pub struct Replica {
+    pub event_id: u64,
+    pub accounts: CopyOnWrite<Accounts>,
+    pub orderbooks: HashMap<Symbol, CopyOnWrite<OrderBook>>,
+    pub last_prices: HashMap<Symbol, LastPrice>,
+}
+
+impl Replica {
+    pub fn query_balance(&self, user_id: &UserId, currency: Currency) -> NamedBalance {
+        balance(&self.accounts, user_id, currency)
+    }
+
+    pub fn query_account(&self, user_id: &UserId) -> BTreeMap<Currency, NamedBalance> {
+        account(&self.accounts, user_id)
+    }
+
+    pub fn query_valued_account(&self, user_id: &UserId) -> valuation::ValuedAccount {
+        valued_account(&self.accounts, &self.orderbooks, &self.last_prices, user_id)
+    }
+
+    pub fn query_order(&self, symbol: &Symbol, order_id: OrderId) -> Option<Order> {
+        order(&self.orderbooks, symbol, order_id)
+    }
+}
+
+/// the latest replica, `None` unless `replica_interval` is set
+pub fn get() -> Option<Arc<Replica>> {
+    REPLICA.read().unwrap().clone()
+}
+
+pub fn is_published() -> bool {
+    REPLICA.read().unwrap().is_some()
+}
+
+/// publishes the executed state at most once per interval, and once the executor is idle
+pub struct Publisher {
+    interval: Duration,
+    published: Instant,
+    dirty: bool,
+}
+
+impl Publisher {
+    /// disabled if `interval` is 0
+    pub fn new(interval: u64) -> Self {
+        Self {
+            interval: Duration::from_millis(interval),
+            published: Instant::now(),
+            dirty: false,
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        !self.interval.is_zero()
+    }
+
+    /// the time to wait for the next event before publishing the pending changes
+    pub fn pending(&self) -> Option<Duration> {
+        self.dirty
+            .then(|| self.interval.saturating_sub(self.published.elapsed()))
+    }
+
+    pub fn executed(&mut self, data: &Data) {
+        if !self.is_enabled() {
+            return;
+        }
+        self.dirty = true;
+        if self.published.elapsed() >= self.interval {
+            self.publish(data);
+        }
+    }
+
+    pub fn publish(&mut self, data: &Data) {
+        if !self.is_enabled() {
+            return;
+        }
+        let replica = Replica {
+            event_id: data.current_event_id,
+            accounts: data.accounts.clone(),
+            orderbooks: data.orderbooks.clone(),
+            last_prices: data.last_prices.clone(),
+        };
+        *REPLICA.write().unwrap() = Some(Arc::new(replica));
+        self.published = Instant::now();
+        self.dirty = false;
+    }
+}
+
+/// compatible with `Balance`, the name is absent unless the currency is named
+#[derive(Debug, Eq, PartialEq, Serialize)]
+pub struct NamedBalance {
+    #[serde(flatten)]
+    balance: assets::Balance,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    name: Option<String>,
+}
+
+impl NamedBalance {
+    fn new(currency: Currency, balance: assets::Balance) -> Self {
+        Self {
+            balance: canonical::balance(balance),
+            name: fusotao::token_name(currency),
+        }
+    }
+}
+
+pub(crate) fn balance(accounts: &Accounts, user_id: &UserId, currency: Currency) -> NamedBalance {
+    NamedBalance::new(
+        currency,
+        assets::get_balance_to_owned(accounts, user_id, currency),
+    )
+}
+
+pub(crate) fn account(accounts: &Accounts, user_id: &UserId) -> BTreeMap<Currency, NamedBalance> {
+    assets::get_account_to_owned(accounts, user_id)
+        .into_iter()
+        .map(|(c, b)| (c, NamedBalance::new(c, b)))
+        .collect()
+}
+
+/// joined with the tokens on chain and valued by `[valuation]` if configured
+pub(crate) fn valued_account(
+    accounts: &Accounts,
+    orderbooks: &HashMap<Symbol, CopyOnWrite<OrderBook>>,
+    last_prices: &HashMap<Symbol, LastPrice>,
+    user_id: &UserId,
+) -> valuation::ValuedAccount {
+    valuation::value(
+        assets::get_account_to_owned(accounts, user_id),
+        orderbooks,
+        last_prices,
+        C.valuation.as_ref(),
+    )
+}
+
+pub(crate) fn order(
+    orderbooks: &HashMap<Symbol, CopyOnWrite<OrderBook>>,
+    symbol: &Symbol,
+    order_id: OrderId,
+) -> Option<Order> {
+    let orderbook = orderbooks.get(symbol)?;
+    orderbook
+        .find_order(order_id)
+        .map(|order| order.clone().canonical(&Scales::from(&**orderbook)))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use rust_decimal_macros::dec;
+    use std::str::FromStr;
+
+    #[test]
+    pub fn test_publish_replica() {
+        let mut data = Data::new();
+        let alice = UserId::from_str("5GrwvaEF5zXb26Fz9rcQpDWS57CtERHpNehXCPcNoHGKutQY").unwrap();
+        assets::add_to_available(&mut data.accounts, &alice, 1, dec!(1)).unwrap();
+        data.current_event_id = 1;
+        let mut disabled = Publisher::new(0);
+        disabled.executed(&data);
+        assert!(disabled.pending().is_none());
+
+        let mut publisher = Publisher::new(60_000);
+        publisher.publish(&data);
+        let replica = get().unwrap();
+        assert_eq!(1, replica.event_id);
+        assert_eq!(
+            balance(&data.accounts, &alice, 1),
+            replica.query_balance(&alice, 1)
+        );
+        // the replica is kept until the next publishing
+        assets::add_to_available(&mut data.accounts, &alice, 1, dec!(1)).unwrap();
+        data.current_event_id = 2;
+        publisher.executed(&data);
+        assert!(publisher.pending().is_some());
+        assert_eq!(1, get().unwrap().event_id);
+        assert_eq!(dec!(1), replica.query_balance(&alice, 1).balance.available);
+        publisher.publish(&data);
+        assert!(publisher.pending().is_none());
+        assert_eq!(
+            dec!(2),
+            get().unwrap().query_balance(&alice, 1).balance.available
+        );
+        assert!(get().unwrap().query_order(&(1, 0), 1).is_none());
+
+        fusotao::TOKENS.insert(
+            905,
+            fusotao::TokenInfo {
+                symbol: "CCC".to_string(),
+                decimals: 18,
+                stable: false,
+            },
+        );
+        assets::add_to_available(&mut data.accounts, &alice, 905, dec!(1)).unwrap();
+        let a = serde_json::to_value(account(&data.accounts, &alice)).unwrap();
+        assert_eq!("CCC", a["905"]["name"]);
+        assert!(a["1"].get("name").is_none());
+        let a: Account = serde_json::from_value(a).unwrap();
+        assert_eq!(dec!(1), a[&905].available);
+    }
+}
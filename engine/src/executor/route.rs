@@ -0,0 +1,266 @@
+// Copyright 2021-2023 UINB Technologies Pte. Ltd.
+
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// 	http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use super::RejectReason;
+use crate::{core::*, input::RouteCmd, matcher, orderbook::*};
+use rust_decimal::{prelude::*, RoundingStrategy};
+
+/// one side of a route, taken as an `IOC` order at the worst price it reaches
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Leg {
+    pub symbol: Symbol,
+    pub ask_or_bid: AskOrBid,
+    pub price: Price,
+    pub amount: Amount,
+    /// the quote budget of a bid, the intermediate currency received by the first leg
+    pub vol: Option<Vol>,
+}
+
+/// the books of `(base, via)` and `(via, quote)` of the symbol
+pub fn books(cmd: &RouteCmd) -> (Symbol, Symbol) {
+    ((cmd.symbol.0, cmd.via), (cmd.via, cmd.symbol.1))
+}
+
+/// the worst price and the quote taken by `amount` on the opposite side, `None` if the
+/// liquidity is insufficient, the makers are walked in the order of matching
+fn walk(book: &OrderBook, amount: Amount, ask_or_bid: AskOrBid) -> Option<(Price, Vol)> {
+    let pages: Box<dyn Iterator<Item = &OrderPage>> = match ask_or_bid {
+        AskOrBid::Ask => Box::new(book.bids.values().rev()),
+        AskOrBid::Bid => Box::new(book.asks.values()),
+    };
+    let mut left = amount;
+    let mut vol = Vol::zero();
+    for page in pages {
+        for maker in page.orders.values() {
+            let filled = left.min(maker.unfilled);
+            vol += filled * page.price;
+            left -= filled;
+            if left.is_zero() {
+                return Some((page.price, vol));
+            }
+        }
+    }
+    None
+}
+
+fn ceil_lot(book: &OrderBook, amount: Amount) -> Amount {
+    let amount = amount.round_dp_with_strategy(book.base_scale, RoundingStrategy::AwayFromZero);
+    match book.floor_lot(amount) {
+        floor if floor == amount => amount,
+        floor => floor + book.lot_size,
+    }
+}
+
+/// the legs of a route in the order of execution, both must be filled entirely and the average
+/// price net of the taker fees must be within the limit of the command
+///
+/// selling `A` of `A/C` sells `A` on `A/B` then all the `B` received on `B/C`, buying `A` buys
+/// the `B` it costs on `B/C` then `A` on `A/B`, the dust of `B` is left to the user
+pub fn plan(
+    cmd: &RouteCmd,
+    first: (&OrderBook, Fee),
+    second: (&OrderBook, Fee),
+) -> Result<(Leg, Leg), RejectReason> {
+    let (ab, bc) = books(cmd);
+    let limit = cmd.price * cmd.amount;
+    let fillable = |book: &OrderBook, price, amount, ask_or_bid| {
+        matcher::is_fillable(book, cmd.user_id, price, amount, ask_or_bid)
+            .then_some(())
+            .ok_or(RejectReason::Unfillable)
+    };
+    match cmd.ask_or_bid {
+        AskOrBid::Ask => {
+            let ((ab_book, ab_fee), (bc_book, bc_fee)) = (first, second);
+            let (p1, proceeds) =
+                walk(ab_book, cmd.amount, AskOrBid::Ask).ok_or(RejectReason::Unfillable)?;
+            fillable(ab_book, p1, cmd.amount, AskOrBid::Ask)?;
+            let net = proceeds * (Decimal::ONE - ab_fee);
+            let amount = bc_book.floor_lot(
+                net.round_dp_with_strategy(bc_book.base_scale, RoundingStrategy::ToZero),
+            );
+            if amount.is_zero() {
+                return Err(RejectReason::Unfillable);
+            }
+            let (p2, received) =
+                walk(bc_book, amount, AskOrBid::Ask).ok_or(RejectReason::Unfillable)?;
+            fillable(bc_book, p2, amount, AskOrBid::Ask)?;
+            if received * (Decimal::ONE - bc_fee) < limit {
+                return Err(RejectReason::RouteLimitExceeded);
+            }
+            Ok((
+                Leg {
+                    symbol: ab,
+                    ask_or_bid: AskOrBid::Ask,
+                    price: p1,
+                    amount: cmd.amount,
+                    vol: None,
+                },
+                Leg {
+                    symbol: bc,
+                    ask_or_bid: AskOrBid::Ask,
+                    price: p2,
+                    amount,
+                    vol: None,
+                },
+            ))
+        }
+        AskOrBid::Bid => {
+            let ((bc_book, bc_fee), (ab_book, _)) = (first, second);
+            let (p2, cost) =
+                walk(ab_book, cmd.amount, AskOrBid::Bid).ok_or(RejectReason::Unfillable)?;
+            fillable(ab_book, p2, cmd.amount, AskOrBid::Bid)?;
+            // the taker fee of a bid is charged in the base
+            if bc_fee >= Decimal::ONE {
+                return Err(RejectReason::Unfillable);
+            }
+            let amount = ceil_lot(bc_book, cost / (Decimal::ONE - bc_fee));
+            let (p1, spent) =
+                walk(bc_book, amount, AskOrBid::Bid).ok_or(RejectReason::Unfillable)?;
+            fillable(bc_book, p1, amount, AskOrBid::Bid)?;
+            if spent > limit {
+                return Err(RejectReason::RouteLimitExceeded);
+            }
+            Ok((
+                Leg {
+                    symbol: bc,
+                    ask_or_bid: AskOrBid::Bid,
+                    price: p1,
+                    amount,
+                    vol: None,
+                },
+                Leg {
+                    symbol: ab,
+                    ask_or_bid: AskOrBid::Bid,
+                    price: p2,
+                    amount: cmd.amount,
+                    vol: Some(cost),
+                },
+            ))
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use rust_decimal_macros::dec;
+
+    fn book(orders: &[(Price, Amount, AskOrBid)]) -> OrderBook {
+        let mut book = OrderBook::new(
+            4,
+            4,
+            dec!(0.001),
+            dec!(0.001),
+            dec!(0.001),
+            dec!(0.001),
+            1,
+            dec!(0.1),
+            dec!(0.1),
+            true,
+            true,
+        );
+        let maker = UserId::from_low_u64_be(1);
+        for (price, amount, ask_or_bid) in orders {
+            matcher::execute_limit(&mut book, maker, *price, *amount, *ask_or_bid);
+        }
+        book
+    }
+
+    fn cmd(price: Price, amount: Amount, ask_or_bid: AskOrBid) -> RouteCmd {
+        RouteCmd {
+            symbol: (1, 3),
+            via: 2,
+            user_id: UserId::from_low_u64_be(2),
+            price,
+            amount,
+            ask_or_bid,
+            nonce: 1,
+            signature: vec![],
+        }
+    }
+
+    #[test]
+    pub fn test_route_plan() {
+        // A/B and B/C
+        let ab = book(&[
+            (dec!(10), dec!(1), AskOrBid::Bid),
+            (dec!(9), dec!(1), AskOrBid::Bid),
+            (dec!(11), dec!(1), AskOrBid::Ask),
+            (dec!(12), dec!(1), AskOrBid::Ask),
+        ]);
+        let bc = book(&[
+            (dec!(0.5), dec!(100), AskOrBid::Bid),
+            (dec!(0.6), dec!(100), AskOrBid::Ask),
+        ]);
+        let fee = dec!(0.001);
+        let (first, second) = plan(
+            &cmd(dec!(4), dec!(2), AskOrBid::Ask),
+            (&ab, fee),
+            (&bc, fee),
+        )
+        .unwrap();
+        assert_eq!((1, 2), first.symbol);
+        assert_eq!((dec!(9), dec!(2)), (first.price, first.amount));
+        // 19 * 0.999 truncated to the scale of B/C
+        assert_eq!((2, 3), second.symbol);
+        assert_eq!((dec!(0.5), dec!(18.981)), (second.price, second.amount));
+        // 18.981 * 0.5 * 0.999 < 4.75 * 2
+        assert_eq!(
+            Err(RejectReason::RouteLimitExceeded),
+            plan(
+                &cmd(dec!(4.75), dec!(2), AskOrBid::Ask),
+                (&ab, fee),
+                (&bc, fee)
+            )
+        );
+        assert_eq!(
+            Err(RejectReason::Unfillable),
+            plan(
+                &cmd(dec!(4), dec!(3), AskOrBid::Ask),
+                (&ab, fee),
+                (&bc, fee)
+            )
+        );
+        let (first, second) = plan(
+            &cmd(dec!(14), dec!(2), AskOrBid::Bid),
+            (&bc, fee),
+            (&ab, fee),
+        )
+        .unwrap();
+        // 23 / 0.999 rounded up
+        assert_eq!((2, 3), first.symbol);
+        assert_eq!((dec!(0.6), dec!(23.0231)), (first.price, first.amount));
+        assert_eq!((1, 2), second.symbol);
+        assert_eq!(
+            (dec!(12), dec!(2), Some(dec!(23))),
+            (second.price, second.amount, second.vol)
+        );
+        assert!(first.amount * (Decimal::ONE - fee) >= dec!(23));
+        assert_eq!(
+            Err(RejectReason::RouteLimitExceeded),
+            plan(
+                &cmd(dec!(6.9), dec!(2), AskOrBid::Bid),
+                (&bc, fee),
+                (&ab, fee)
+            )
+        );
+        // the makers of the user itself are never taken
+        let mut own = cmd(dec!(4), dec!(1), AskOrBid::Ask);
+        own.user_id = UserId::from_low_u64_be(1);
+        assert_eq!(
+            Err(RejectReason::Unfillable),
+            plan(&own, (&ab, fee), (&bc, fee))
+        );
+    }
+}
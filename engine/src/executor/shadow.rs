@@ -0,0 +1,405 @@
+// Copyright 2021-2023 UINB Technologies Pte. Ltd.
+
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// 	http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::{assets, core::*, input::LimitCmd, matcher::TimeInForce, orderbook::OrderPage};
+use rust_decimal::prelude::Zero;
+use serde::Serialize;
+use std::{
+    collections::{BTreeMap, VecDeque},
+    sync::Mutex,
+};
+
+/// the makers a taker could reach, the same limit as the matcher
+const MAX_MAKERS: usize = 20;
+
+/// the divergences kept for `shadow_stats`
+const RECENT_DIVERGENCES: usize = 16;
+
+/// `(available, frozen)` of the users in the currencies of the symbol
+type Balances = BTreeMap<(UserId, Currency), (Amount, Amount)>;
+
+lazy_static::lazy_static! {
+    static ref STATS: Mutex<ShadowStats> = Mutex::new(ShadowStats::default());
+}
+
+/// replied by the admin command `shadow_stats`
+#[derive(Serialize, Debug, Default, Clone)]
+pub struct ShadowStats {
+    pub checked: u64,
+    /// the orders beyond the reference, e.g. icebergs or bids by quote
+    pub skipped: u64,
+    pub diverged: u64,
+    pub recent: VecDeque<Divergence>,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct Divergence {
+    pub event_id: u64,
+    pub symbol: Symbol,
+    pub diffs: Vec<String>,
+}
+
+pub fn stats() -> ShadowStats {
+    STATS.lock().expect("shadow lock poisoned;qed").clone()
+}
+
+#[derive(Debug, Clone)]
+struct Resting {
+    id: OrderId,
+    user: UserId,
+    price: Price,
+    unfilled: Amount,
+    broker: Option<UserId>,
+    fee: Fee,
+}
+
+/// the orders within the reach of a limit order and the balances before it, matched and
+/// cleared again by the reference after the executor to compare the results
+#[derive(Debug, Clone)]
+pub struct Shadow {
+    event_id: u64,
+    symbol: Symbol,
+    user: UserId,
+    price: Price,
+    amount: Amount,
+    ask_or_bid: AskOrBid,
+    broker: Option<UserId>,
+    rest: bool,
+    taker_fee: Fee,
+    next_id: OrderId,
+    makers: Vec<Resting>,
+    balances: Balances,
+}
+
+impl Shadow {
+    /// before freezing the taker, `None` if the order is beyond the reference
+    pub fn capture(
+        event_id: u64,
+        cmd: &LimitCmd,
+        orderbook: &OrderBook,
+        accounts: &Accounts,
+        taker_fee: Fee,
+        maker_fee: impl Fn(&UserId) -> Fee,
+    ) -> Option<Self> {
+        let opposite = match cmd.ask_or_bid {
+            AskOrBid::Ask => Box::new(
+                orderbook
+                    .bids
+                    .values()
+                    .rev()
+                    .take_while(|p| p.price >= cmd.price),
+            ) as Box<dyn Iterator<Item = &OrderPage>>,
+            AskOrBid::Bid => Box::new(orderbook.asks.values().take_while(|p| p.price <= cmd.price)),
+        };
+        let makers = opposite
+            .flat_map(|p| p.orders.values())
+            .take(MAX_MAKERS + 1)
+            .collect::<Vec<_>>();
+        if cmd.vol.is_some()
+            || cmd.display.is_some_and(|d| !d.is_zero())
+            || makers.iter().any(|o| !o.hidden.is_zero())
+        {
+            STATS.lock().expect("shadow lock poisoned;qed").skipped += 1;
+            return None;
+        }
+        let makers = makers
+            .into_iter()
+            .map(|o| Resting {
+                id: o.id,
+                user: o.user,
+                price: o.price,
+                unfilled: o.unfilled,
+                broker: o.broker,
+                fee: maker_fee(&o.user),
+            })
+            .collect::<Vec<_>>();
+        let mut balances = BTreeMap::new();
+        for user in makers.iter().map(|m| m.user).chain([cmd.user_id, SYSTEM]) {
+            for currency in [cmd.symbol.0, cmd.symbol.1] {
+                let b = assets::get_balance_to_owned(accounts, &user, currency);
+                balances.insert((user, currency), (b.available, b.frozen));
+            }
+        }
+        Some(Self {
+            event_id,
+            symbol: cmd.symbol,
+            user: cmd.user_id,
+            price: cmd.price,
+            amount: cmd.amount,
+            ask_or_bid: cmd.ask_or_bid,
+            broker: cmd.broker,
+            rest: cmd.time_in_force == TimeInForce::GoodTillCancel,
+            taker_fee,
+            next_id: orderbook.max_id + 1,
+            makers,
+            balances,
+        })
+    }
+
+    /// match and clear the captured orders naively, return the fills, the unfilled of the
+    /// taker resting on the book and the balances after
+    fn reference(&self) -> (Vec<Amount>, Option<Amount>, Balances) {
+        let (base, quote) = self.symbol;
+        let mut left = self.amount;
+        let mut fills = vec![];
+        let mut interrupted = false;
+        for (i, m) in self.makers.iter().enumerate() {
+            if left.is_zero() {
+                break;
+            }
+            // the matching stops at the makers limit or the orders of the taker
+            if i == MAX_MAKERS || m.user == self.user {
+                interrupted = true;
+                break;
+            }
+            let filled = left.min(m.unfilled);
+            fills.push(filled);
+            left -= filled;
+        }
+        let resting = (self.rest && !interrupted && !left.is_zero()).then_some(left);
+        let mut balances = self.balances.clone();
+        let mut add = |user: UserId, currency: Currency, available: Amount, frozen: Amount| {
+            let b = balances.entry((user, currency)).or_default();
+            b.0 += available;
+            b.1 += frozen;
+        };
+        let (received, paid) = match self.ask_or_bid {
+            AskOrBid::Ask => (quote, base),
+            AskOrBid::Bid => (base, quote),
+        };
+        let mut earned = Amount::zero();
+        for (m, filled) in self.makers.iter().zip(fills.iter().copied()) {
+            let vol = filled * m.price;
+            // the maker pays what the taker receives and receives what the taker pays
+            let (maker_pays, maker_receives) = match self.ask_or_bid {
+                AskOrBid::Ask => (vol, filled),
+                AskOrBid::Bid => (filled, vol),
+            };
+            add(m.user, received, Amount::zero(), -maker_pays);
+            add(m.user, paid, maker_receives, Amount::zero());
+            if m.fee < Fee::zero() {
+                let rebate = maker_pays * (-m.fee).min(self.taker_fee).max(Fee::zero());
+                add(m.user, received, rebate, Amount::zero());
+                add(SYSTEM, received, -rebate, Amount::zero());
+            } else {
+                add(m.user, paid, -maker_receives * m.fee, Amount::zero());
+                add(SYSTEM, paid, maker_receives * m.fee, Amount::zero());
+            }
+            earned += maker_pays;
+        }
+        let taker_fee = earned * self.taker_fee;
+        add(self.user, received, earned - taker_fee, Amount::zero());
+        add(SYSTEM, received, taker_fee, Amount::zero());
+        let spent = match self.ask_or_bid {
+            AskOrBid::Ask => fills.iter().copied().sum::<Amount>(),
+            AskOrBid::Bid => self
+                .makers
+                .iter()
+                .zip(fills.iter())
+                .map(|(m, filled)| *filled * m.price)
+                .sum(),
+        };
+        let frozen = match (self.ask_or_bid, resting) {
+            (_, None) => Amount::zero(),
+            (AskOrBid::Ask, Some(left)) => left,
+            (AskOrBid::Bid, Some(left)) => left * self.price,
+        };
+        add(self.user, paid, -spent - frozen, frozen);
+        (fills, resting, balances)
+    }
+
+    /// compare the book and the balances after executing the order with the reference
+    pub fn check(self, orderbook: &OrderBook, accounts: &Accounts) {
+        let (fills, resting, balances) = self.reference();
+        let mut diffs = vec![];
+        for (i, m) in self.makers.iter().enumerate() {
+            let left = m.unfilled - fills.get(i).copied().unwrap_or_default();
+            let actual = orderbook.find_order(m.id).map(|o| o.unfilled);
+            if actual != Some(left).filter(|l| !l.is_zero()) {
+                diffs.push(format!(
+                    "maker {} unfilled {:?}, expected {}",
+                    m.id, actual, left
+                ));
+            }
+        }
+        let taker = orderbook
+            .find_order(self.next_id)
+            .map(|o| (o.price, o.unfilled));
+        if orderbook.max_id != self.next_id || taker != resting.map(|l| (self.price, l)) {
+            diffs.push(format!(
+                "taker {} resting {:?}, expected {:?}",
+                self.next_id, taker, resting
+            ));
+        }
+        // the brokers share the fees of `SYSTEM`
+        let shared = self.broker.is_some()
+            || self
+                .makers
+                .iter()
+                .take(fills.len())
+                .any(|m| m.broker.is_some() || m.user == SYSTEM)
+            || self.user == SYSTEM;
+        for ((user, currency), expected) in balances
+            .into_iter()
+            .filter(|((user, _), _)| !shared || *user != SYSTEM)
+        {
+            let b = assets::get_balance_to_owned(accounts, &user, currency);
+            if (b.available, b.frozen) != expected {
+                diffs.push(format!(
+                    "balance of {:?} in {} ({}, {}), expected {:?}",
+                    user, currency, b.available, b.frozen, expected
+                ));
+            }
+        }
+        let mut stats = STATS.lock().expect("shadow lock poisoned;qed");
+        stats.checked += 1;
+        if diffs.is_empty() {
+            return;
+        }
+        log::error!(
+            "the executor diverged from the reference matcher at {}: {}",
+            self.event_id,
+            diffs.join("; ")
+        );
+        stats.diverged += 1;
+        if stats.recent.len() == RECENT_DIVERGENCES {
+            stats.recent.pop_front();
+        }
+        stats.recent.push_back(Divergence {
+            event_id: self.event_id,
+            symbol: self.symbol,
+            diffs,
+        });
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{clearing, matcher};
+    use rust_decimal_macros::dec;
+
+    fn limit(user_id: UserId, price: Price, amount: Amount, ask_or_bid: AskOrBid) -> LimitCmd {
+        LimitCmd {
+            symbol: (1, 0),
+            user_id,
+            price,
+            amount,
+            ask_or_bid,
+            nonce: 0,
+            signature: vec![],
+            broker: None,
+            time_in_force: TimeInForce::GoodTillCancel,
+            self_trade_prevention: None,
+            vol: None,
+            display: None,
+            oco: None,
+            client_order_id: None,
+        }
+    }
+
+    fn execute(
+        id: u64,
+        cmd: &LimitCmd,
+        orderbook: &mut OrderBook,
+        accounts: &mut Accounts,
+        fee: impl Fn(&UserId) -> Fee + Copy,
+    ) -> Option<Shadow> {
+        let shadow = Shadow::capture(id, cmd, orderbook, accounts, dec!(0.002), fee);
+        let (c, val) = assets::freeze_if(&cmd.symbol, cmd.ask_or_bid, cmd.price, cmd.amount);
+        assets::try_freeze(accounts, &cmd.user_id, c, val).unwrap();
+        let mut mr = matcher::execute_limit(
+            orderbook,
+            cmd.user_id,
+            cmd.price,
+            cmd.amount,
+            cmd.ask_or_bid,
+        );
+        mr.maker
+            .iter_mut()
+            .for_each(|m| m.fee = Some(fee(&m.user_id)));
+        clearing::clear(
+            accounts,
+            id,
+            &cmd.symbol,
+            dec!(0.002),
+            orderbook.maker_fee,
+            orderbook.broker_share,
+            &mr,
+            0,
+        );
+        shadow
+    }
+
+    #[test]
+    pub fn test_shadow() {
+        let mut orderbook = OrderBook::new(
+            4,
+            4,
+            dec!(0.002),
+            dec!(-0.0005),
+            dec!(0.002),
+            dec!(0),
+            1,
+            dec!(0.0001),
+            dec!(0.0001),
+            true,
+            true,
+        );
+        let mut accounts = Accounts::new();
+        let (alice, bob, charlie) = (
+            UserId::from_low_u64_be(1),
+            UserId::from_low_u64_be(2),
+            UserId::from_low_u64_be(3),
+        );
+        assets::add_to_available(&mut accounts, &alice, 1, dec!(10)).unwrap();
+        assets::add_to_available(&mut accounts, &bob, 1, dec!(10)).unwrap();
+        assets::add_to_available(&mut accounts, &charlie, 0, dec!(100)).unwrap();
+        // bob pays the maker fee while alice is rebated
+        let fee = |u: &UserId| match *u == bob {
+            true => dec!(0.001),
+            false => dec!(-0.0005),
+        };
+        let makers = [
+            limit(alice, dec!(10), dec!(1), AskOrBid::Ask),
+            limit(bob, dec!(11), dec!(2), AskOrBid::Ask),
+        ];
+        for (id, cmd) in makers.iter().enumerate() {
+            let shadow = execute(id as u64 + 1, cmd, &mut orderbook, &mut accounts, fee).unwrap();
+            shadow.check(&orderbook, &accounts);
+        }
+        // filled 1 at 10 and 2 at 11, 0.5 rests at 11
+        let taker = limit(charlie, dec!(11), dec!(3.5), AskOrBid::Bid);
+        let shadow = execute(3, &taker, &mut orderbook, &mut accounts, fee).unwrap();
+        shadow.clone().check(&orderbook, &accounts);
+        let s = stats();
+        assert_eq!((s.checked, s.diverged), (3, 0));
+        assert_eq!(orderbook.find_order(3).unwrap().unfilled, dec!(0.5));
+
+        // the balances changed behind the executor
+        assets::add_to_available(&mut accounts, &bob, 0, dec!(1)).unwrap();
+        shadow.check(&orderbook, &accounts);
+        let s = stats();
+        assert_eq!((s.checked, s.diverged), (4, 1));
+        assert_eq!(s.recent[0].event_id, 3);
+        assert_eq!(s.recent[0].diffs.len(), 1);
+
+        let iceberg = LimitCmd {
+            display: Some(dec!(0.1)),
+            ..limit(alice, dec!(11), dec!(1), AskOrBid::Ask)
+        };
+        assert!(Shadow::capture(4, &iceberg, &orderbook, &accounts, dec!(0.002), fee).is_none());
+        assert_eq!(stats().skipped, 1);
+    }
+}
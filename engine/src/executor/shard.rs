@@ -0,0 +1,363 @@
+// Copyright 2021-2023 UINB Technologies Pte. Ltd.
+
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// 	http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use super::{
+    do_execute, save_proof, EventsError, ExecutionResult, MarketChannel, ResponseChannel,
+    SequencerChannel, REPLAYING_CAPACITY,
+};
+use crate::{
+    core::*,
+    input::{Event, Input, Message},
+    orderbook::*,
+    output::Output,
+    prover::StateDelta,
+    ring,
+};
+use rayon::prelude::*;
+use std::{collections::HashMap, time::Instant};
+
+/// the events collected into a batch at most
+pub const MAX_BATCH: usize = 1024;
+
+pub fn is_shardable(event: &Event) -> bool {
+    matches!(
+        event,
+        Event::Limit(..) | Event::Market(..) | Event::Cancel(..)
+    )
+}
+
+/// the symbol of the event and the users whose balances may be changed by it, i.e. the taker
+/// and the owners of the crossed makers as well as the accounts of their brokers, `None` if the
+/// event must be executed alone
+fn footprint(event: &Event, data: &Data) -> Option<(Symbol, Vec<UserId>)> {
+    let (symbol, user_id, broker, taking) = match event {
+        Event::Limit(_, cmd, ..) => (
+            cmd.symbol,
+            cmd.user_id,
+            cmd.broker,
+            Some((cmd.ask_or_bid, Some(cmd.price))),
+        ),
+        Event::Market(_, cmd, ..) => (
+            cmd.symbol,
+            cmd.user_id,
+            cmd.broker,
+            Some((cmd.ask_or_bid, None)),
+        ),
+        Event::Cancel(_, cmd, ..) => (cmd.symbol, cmd.user_id, None, None),
+        _ => return None,
+    };
+    let mut users = vec![user_id];
+    if let (Some((ask_or_bid, price)), Some(orderbook)) = (taking, data.orderbooks.get(&symbol)) {
+        let crossed: Box<dyn Iterator<Item = (&Price, &OrderPage)>> = match (ask_or_bid, price) {
+            (AskOrBid::Bid, Some(price)) => Box::new(orderbook.asks.range(..=price)),
+            (AskOrBid::Ask, Some(price)) => Box::new(orderbook.bids.range(price..)),
+            (AskOrBid::Bid, None) => Box::new(orderbook.asks.iter()),
+            (AskOrBid::Ask, None) => Box::new(orderbook.bids.iter()),
+        };
+        let makers = crossed.flat_map(|(_, page)| page.orders.values());
+        if orderbook.broker_share.is_zero() {
+            users.extend(makers.map(|o| o.user));
+        } else {
+            users.extend(broker.iter().map(broker_account));
+            for o in makers {
+                users.push(o.user);
+                users.extend(o.broker.iter().map(broker_account));
+            }
+        }
+    }
+    // the fees of `SYSTEM` are added up while joining the shards
+    users.retain(|u| *u != SYSTEM);
+    users.sort();
+    users.dedup();
+    Some((symbol, users))
+}
+
+/// the symbols are partitioned into the shards, the events of a batch are executed in parallel
+/// if they don't touch the same balances from different shards
+pub struct Batch {
+    shards: usize,
+    events: Vec<(usize, Symbol, Event)>,
+    claimed: HashMap<(UserId, Currency), usize>,
+}
+
+/// the outputs of an event executed in a shard, forwarded in order after joining
+struct Executed {
+    index: usize,
+    result: ExecutionResult,
+    proofs: Vec<StateDelta>,
+    outputs: Vec<Vec<Output>>,
+    responses: Vec<(u64, Message)>,
+    inputs: Vec<Input>,
+}
+
+impl Batch {
+    pub fn new(shards: usize) -> Self {
+        Self {
+            shards: shards.max(1),
+            events: vec![],
+            claimed: HashMap::new(),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.events.len()
+    }
+
+    fn shard_of(&self, symbol: &Symbol) -> usize {
+        // stable across the restarts, unlike the `RandomState`
+        ((symbol.0 as u64).wrapping_mul(0x9e3779b97f4a7c15) ^ symbol.1 as u64) as usize
+            % self.shards
+    }
+
+    /// the event is returned back if it can't be executed in the batch
+    pub fn try_push(&mut self, data: &Data, event: Event) -> Option<Event> {
+        let (symbol, users) = match footprint(&event, data) {
+            Some(v) => v,
+            None => return Some(event),
+        };
+        let shard = self.shard_of(&symbol);
+        let conflicted = users.iter().any(|u| {
+            [symbol.0, symbol.1]
+                .iter()
+                .any(|c| self.claimed.get(&(*u, *c)).is_some_and(|s| *s != shard))
+        });
+        if conflicted {
+            return Some(event);
+        }
+        for u in users {
+            self.claimed.insert((u, symbol.0), shard);
+            self.claimed.insert((u, symbol.1), shard);
+        }
+        self.events.push((shard, symbol, event));
+        None
+    }
+
+    /// the proofs are generated and the outputs are forwarded in the order of the events,
+    /// so the results are the same with executing sequentially
+    pub fn execute(
+        self,
+        data: &mut Data,
+        ephemeral: &mut Ephemeral,
+        market: &MarketChannel,
+        response: &ResponseChannel,
+        sequencer: &SequencerChannel,
+    ) -> Vec<ExecutionResult> {
+        let Batch {
+            shards,
+            events,
+            claimed,
+        } = self;
+        if events.iter().all(|(s, ..)| *s == events[0].0) {
+            return events
+                .into_iter()
+                .map(|(_, _, event)| {
+                    do_execute(event, data, ephemeral, market, response, sequencer)
+                })
+                .collect();
+        }
+        let mut parts = (0..shards)
+            .map(|_| (vec![], vec![], vec![]))
+            .collect::<Vec<(Vec<Symbol>, Vec<(UserId, Currency)>, Vec<(usize, Event)>)>>();
+        let total = events.len();
+        for (index, (shard, symbol, event)) in events.into_iter().enumerate() {
+            if !parts[shard].0.contains(&symbol) {
+                parts[shard].0.push(symbol);
+            }
+            parts[shard].2.push((index, event));
+        }
+        for (balance, shard) in claimed {
+            parts[shard].1.push(balance);
+        }
+        let forks = parts
+            .into_iter()
+            .filter(|(_, _, events)| !events.is_empty())
+            .map(|(symbols, balances, events)| {
+                (
+                    data.fork(&symbols, &balances),
+                    ephemeral.fork(&symbols),
+                    events,
+                )
+            })
+            .collect::<Vec<_>>();
+        let joined = forks
+            .into_par_iter()
+            .map(|(mut data, mut ephemeral, events)| {
+                let executed = execute_shard(&mut data, &mut ephemeral, events);
+                (data, ephemeral, executed)
+            })
+            .collect::<Vec<_>>();
+        let mut all = Vec::with_capacity(total);
+        for (shard, e, executed) in joined {
+            data.join(shard);
+            ephemeral.join(e);
+            all.extend(executed);
+        }
+        all.sort_by_key(|e| e.index);
+        all.into_iter()
+            .map(|e| forward(e, data, ephemeral, market, response, sequencer))
+            .collect()
+    }
+}
+
+fn execute_shard(
+    data: &mut Data,
+    ephemeral: &mut Ephemeral,
+    events: Vec<(usize, Event)>,
+) -> Vec<Executed> {
+    let market = ring::channel(REPLAYING_CAPACITY);
+    let response = std::sync::mpsc::channel();
+    let sequencer = std::sync::mpsc::channel();
+    events
+        .into_iter()
+        .map(|(index, event)| {
+            let result = do_execute(event, data, ephemeral, &market.0, &response.0, &sequencer.0);
+            Executed {
+                index,
+                result,
+                proofs: ephemeral
+                    .deferred_proofs
+                    .as_mut()
+                    .map(std::mem::take)
+                    .unwrap_or_default(),
+                outputs: market.1.try_iter().map(|(out, _)| out).collect(),
+                responses: response.1.try_iter().collect(),
+                inputs: sequencer.1.try_iter().collect(),
+            }
+        })
+        .collect()
+}
+
+fn forward(
+    executed: Executed,
+    data: &mut Data,
+    ephemeral: &mut Ephemeral,
+    market: &MarketChannel,
+    response: &ResponseChannel,
+    sequencer: &SequencerChannel,
+) -> ExecutionResult {
+    let id = data.current_event_id;
+    for delta in executed.proofs {
+        save_proof(delta, &mut data.merkle_tree, ephemeral)?;
+    }
+    for input in executed.inputs {
+        sequencer
+            .send(input)
+            .map_err(|_| EventsError::Interrupted(id))?;
+    }
+    for msg in executed.responses {
+        response
+            .send(msg)
+            .map_err(|_| EventsError::Interrupted(id))?;
+    }
+    for out in executed.outputs {
+        market
+            .send((out, Instant::now()))
+            .map_err(|_| EventsError::Interrupted(id))?;
+    }
+    executed.result
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{assets, input::*};
+    use rust_decimal_macros::dec;
+
+    fn limit(
+        id: u64,
+        symbol: Symbol,
+        user_id: UserId,
+        price: Price,
+        ask_or_bid: AskOrBid,
+    ) -> Event {
+        let cmd = LimitCmd {
+            symbol,
+            user_id,
+            price,
+            amount: dec!(1),
+            ask_or_bid,
+            nonce: 1,
+            signature: vec![],
+            broker: None,
+            time_in_force: Default::default(),
+            self_trade_prevention: None,
+            vol: None,
+            display: None,
+            oco: None,
+            client_order_id: None,
+        };
+        Event::Limit(id, cmd, 0, 1, 1)
+    }
+
+    #[test]
+    pub fn test_batch() {
+        let (btc_usdt, eth_usdt) = ((1, 0), (2, 0));
+        let (alice, bob, carol) = (
+            UserId::from_low_u64_be(1),
+            UserId::from_low_u64_be(2),
+            UserId::from_low_u64_be(3),
+        );
+        let mut data = Data::new();
+        let mut orderbook = OrderBook::default();
+        orderbook.insert(Order::new(1, bob, dec!(100), dec!(1)), AskOrBid::Ask);
+        data.orderbooks.insert(btc_usdt, orderbook.into());
+        data.orderbooks
+            .insert(eth_usdt, OrderBook::default().into());
+        assets::add_to_available(&mut data.accounts, &bob, 1, dec!(1)).unwrap();
+        let mut batch = Batch::new(usize::MAX);
+        let (s0, s1) = (batch.shard_of(&btc_usdt), batch.shard_of(&eth_usdt));
+        assert_ne!(s0, s1);
+        // alice takes the ask of bob
+        assert!(batch
+            .try_push(&data, limit(1, btc_usdt, alice, dec!(100), AskOrBid::Bid))
+            .is_none());
+        // carol doesn't cross anything
+        assert!(batch
+            .try_push(&data, limit(2, eth_usdt, carol, dec!(10), AskOrBid::Bid))
+            .is_none());
+        // bob's usdt is claimed by the shard of btc/usdt
+        assert!(batch
+            .try_push(&data, limit(3, eth_usdt, bob, dec!(10), AskOrBid::Bid))
+            .is_some());
+        assert!(batch.try_push(&data, Event::Dump(4)).is_some());
+        assert_eq!(2, batch.len());
+
+        data.links
+            .insert(btc_usdt, [(1, 2), (2, 1)].into_iter().collect());
+        data.breakers.entry(btc_usdt).or_default().halted_until = 100;
+        let mut shard = data.fork(&[btc_usdt], &[(bob, 1), (bob, 0)]);
+        assert!(!data.orderbooks.contains_key(&btc_usdt));
+        assert!(data.links.is_empty() && shard.links.contains_key(&btc_usdt));
+        assert!(data.breakers.is_empty() && shard.breakers[&btc_usdt].is_halted(99));
+        assert_eq!(
+            dec!(1),
+            assets::get_balance_to_owned(&shard.accounts, &bob, 1).available
+        );
+        assets::add_to_available(&mut shard.accounts, &SYSTEM, 0, dec!(0.1)).unwrap();
+        assets::add_to_available(&mut data.accounts, &SYSTEM, 0, dec!(0.2)).unwrap();
+        assets::try_freeze(&mut shard.accounts, &bob, 1, dec!(1)).unwrap();
+        data.join(shard);
+        assert!(data.orderbooks.contains_key(&btc_usdt));
+        assert_eq!(Some(&2), data.links[&btc_usdt].get(&1));
+        assert!(data.breakers.contains_key(&btc_usdt));
+        assert_eq!(
+            dec!(1),
+            assets::get_balance_to_owned(&data.accounts, &bob, 1).frozen
+        );
+        assert_eq!(
+            dec!(0.3),
+            assets::get_balance_to_owned(&data.accounts, &SYSTEM, 0).available
+        );
+    }
+}
@@ -0,0 +1,482 @@
+// Copyright 2021-2023 UINB Technologies Pte. Ltd.
+
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// 	http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::{
+    core::*,
+    input::{AssetsCmd, SubTransferCmd, WithdrawFeesCmd},
+    output::{
+        canonical::{fixed, CURRENCY_SCALE},
+        Output,
+    },
+};
+use anyhow::anyhow;
+use rocksdb::{Direction, IteratorMode, WriteBatchWithTransaction};
+use serde::{Deserialize, Serialize};
+
+pub const MAX_STATEMENT_ENTRIES: usize = 1000;
+
+pub const DEFAULT_STATEMENT_ENTRIES: usize = 100;
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EntryKind {
+    Trade,
+    Fee,
+    TransferIn,
+    TransferOut,
+    SubTransfer,
+    FeeWithdrawal,
+}
+
+impl EntryKind {
+    fn as_str(&self) -> &'static str {
+        match self {
+            EntryKind::Trade => "trade",
+            EntryKind::Fee => "fee",
+            EntryKind::TransferIn => "transfer_in",
+            EntryKind::TransferOut => "transfer_out",
+            EntryKind::SubTransfer => "sub_transfer",
+            EntryKind::FeeWithdrawal => "fee_withdrawal",
+        }
+    }
+}
+
+/// a change of the balance of `currency`, `balance` is the available and frozen after it
+#[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
+pub struct Entry {
+    pub event_id: u64,
+    pub timestamp: Timestamp,
+    pub kind: EntryKind,
+    pub currency: Currency,
+    pub amount: Amount,
+    pub balance: Amount,
+    /// the trades and fees only
+    pub symbol: Option<Symbol>,
+    pub order_id: Option<OrderId>,
+    /// the average price of the order filled by the event
+    pub price: Option<Price>,
+    /// the transfers only
+    pub block_number: Option<u32>,
+}
+
+impl Entry {
+    fn canonical(mut self) -> Self {
+        self.amount = fixed(self.amount, CURRENCY_SCALE);
+        self.balance = fixed(self.balance, CURRENCY_SCALE);
+        self.price = self.price.map(|p| p.normalize());
+        self
+    }
+}
+
+/// the entries sorted by `(timestamp, event_id)`, `next` is the cursor of the remaining ones
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct StatementPage {
+    pub entries: Vec<Entry>,
+    pub truncated: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub next: Option<String>,
+}
+
+/// a transfer of the user broadcasted as `TRANSFER_EXECUTED`
+#[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
+pub struct TransferReport {
+    pub user_id: UserId,
+    #[serde(flatten)]
+    pub entry: Entry,
+}
+
+impl TransferReport {
+    pub fn from_entries(entries: Vec<(UserId, Entry)>) -> Vec<Self> {
+        entries
+            .into_iter()
+            .map(|(user_id, entry)| Self {
+                user_id,
+                entry: entry.canonical(),
+            })
+            .collect()
+    }
+}
+
+impl StatementPage {
+    pub fn canonical(mut self) -> Self {
+        self.entries = self.entries.into_iter().map(Entry::canonical).collect();
+        self
+    }
+}
+
+fn statement_prefix(user_id: &UserId) -> Vec<u8> {
+    [&b"stmtentr"[..], &user_id.0[..]].concat()
+}
+
+fn statement_key(prefix: &[u8], timestamp: Timestamp, event_id: u64, seq: u32) -> Vec<u8> {
+    [
+        prefix,
+        &timestamp.to_be_bytes()[..],
+        &event_id.to_be_bytes()[..],
+        &seq.to_be_bytes()[..],
+    ]
+    .concat()
+}
+
+/// `<timestamp>:<event_id>:<seq>` of the first entry to resume from
+fn parse_cursor(cursor: &str) -> anyhow::Result<(Timestamp, u64, u32)> {
+    let mut parts = cursor.splitn(3, ':');
+    match (parts.next(), parts.next(), parts.next()) {
+        (Some(t), Some(id), Some(seq)) => Ok((t.parse()?, id.parse()?, seq.parse()?)),
+        _ => Err(anyhow!("invalid cursor {}", cursor)),
+    }
+}
+
+fn trade_entries(o: &Output) -> Vec<Entry> {
+    if o.base_delta.is_zero() {
+        return vec![];
+    }
+    let base_balance = o.base_available + o.base_frozen;
+    let quote_balance = o.quote_available + o.quote_frozen;
+    let entry = |kind, currency, amount, balance| Entry {
+        event_id: o.event_id,
+        timestamp: o.timestamp,
+        kind,
+        currency,
+        amount,
+        balance,
+        symbol: Some(o.symbol),
+        order_id: Some(o.order_id),
+        price: Some((o.quote_delta / o.base_delta).abs()),
+        block_number: None,
+    };
+    let mut entries = vec![
+        entry(
+            EntryKind::Trade,
+            o.symbol.0,
+            o.base_delta,
+            base_balance - o.base_charge,
+        ),
+        entry(
+            EntryKind::Trade,
+            o.symbol.1,
+            o.quote_delta,
+            quote_balance - o.quote_charge,
+        ),
+    ];
+    if !o.base_charge.is_zero() {
+        entries.push(entry(
+            EntryKind::Fee,
+            o.symbol.0,
+            o.base_charge,
+            base_balance,
+        ));
+    }
+    if !o.quote_charge.is_zero() {
+        entries.push(entry(
+            EntryKind::Fee,
+            o.symbol.1,
+            o.quote_charge,
+            quote_balance,
+        ));
+    }
+    entries
+}
+
+fn write(db: &rocksdb::DB, entries: Vec<(UserId, Entry)>) -> anyhow::Result<()> {
+    let mut batch = WriteBatchWithTransaction::<false>::default();
+    let mut seqs = std::collections::HashMap::<UserId, u32>::new();
+    for (user_id, entry) in entries {
+        let seq = seqs.entry(user_id).or_default();
+        let key = statement_key(
+            &statement_prefix(&user_id),
+            entry.timestamp,
+            entry.event_id,
+            *seq,
+        );
+        batch.put(&key, bincode::serialize(&entry)?);
+        *seq += 1;
+    }
+    db.write(batch)?;
+    Ok(())
+}
+
+/// the fills of an event, the replayed ones are written again with the same keys
+pub fn record(db: &rocksdb::DB, outputs: &[Output]) -> anyhow::Result<()> {
+    let entries = outputs
+        .iter()
+        .flat_map(|o| trade_entries(o).into_iter().map(|e| (o.user_id, e)))
+        .collect::<Vec<_>>();
+    if entries.is_empty() {
+        return Ok(());
+    }
+    write(db, entries)
+}
+
+pub fn record_transfer(
+    db: &rocksdb::DB,
+    event_id: u64,
+    cmd: &AssetsCmd,
+    after: &Balance,
+) -> anyhow::Result<()> {
+    write(db, transfer_entries(event_id, cmd, after))
+}
+
+pub fn transfer_entries(event_id: u64, cmd: &AssetsCmd, after: &Balance) -> Vec<(UserId, Entry)> {
+    let (kind, amount) = match cmd.in_or_out {
+        InOrOut::In => (EntryKind::TransferIn, cmd.amount),
+        InOrOut::Out => (EntryKind::TransferOut, -cmd.amount),
+    };
+    let entry = Entry {
+        event_id,
+        timestamp: cmd.timestamp,
+        kind,
+        currency: cmd.currency,
+        amount,
+        balance: after.available + after.frozen,
+        symbol: None,
+        order_id: None,
+        price: None,
+        block_number: Some(cmd.block_number),
+    };
+    vec![(cmd.user_id, entry)]
+}
+
+pub fn record_sub_transfer(
+    db: &rocksdb::DB,
+    event_id: u64,
+    timestamp: Timestamp,
+    cmd: &SubTransferCmd,
+    after: (&Balance, &Balance),
+) -> anyhow::Result<()> {
+    write(db, sub_transfer_entries(event_id, timestamp, cmd, after))
+}
+
+/// both accounts of the transfer, the sending one with a negative `amount`
+pub fn sub_transfer_entries(
+    event_id: u64,
+    timestamp: Timestamp,
+    cmd: &SubTransferCmd,
+    after: (&Balance, &Balance),
+) -> Vec<(UserId, Entry)> {
+    let (from, to) = cmd.parties();
+    let entry = |amount: Amount, after: &Balance| Entry {
+        event_id,
+        timestamp,
+        kind: EntryKind::SubTransfer,
+        currency: cmd.currency,
+        amount,
+        balance: after.available + after.frozen,
+        symbol: None,
+        order_id: None,
+        price: None,
+        block_number: None,
+    };
+    vec![
+        (from, entry(-cmd.amount, after.0)),
+        (to, entry(cmd.amount, after.1)),
+    ]
+}
+
+pub fn record_fee_withdrawal(
+    db: &rocksdb::DB,
+    event_id: u64,
+    cmd: &WithdrawFeesCmd,
+    amount: Amount,
+    after: &Balance,
+) -> anyhow::Result<()> {
+    write(db, fee_withdrawal_entries(event_id, cmd, amount, after))
+}
+
+/// only the treasury receiving the fees, `SYSTEM` has no statements
+pub fn fee_withdrawal_entries(
+    event_id: u64,
+    cmd: &WithdrawFeesCmd,
+    amount: Amount,
+    after: &Balance,
+) -> Vec<(UserId, Entry)> {
+    let entry = Entry {
+        event_id,
+        timestamp: cmd.timestamp,
+        kind: EntryKind::FeeWithdrawal,
+        currency: cmd.currency,
+        amount,
+        balance: after.available + after.frozen,
+        symbol: None,
+        order_id: None,
+        price: None,
+        block_number: None,
+    };
+    vec![(cmd.treasury, entry)]
+}
+
+/// the entries in `[from, to]`
+pub fn query(
+    db: &rocksdb::DB,
+    user_id: &UserId,
+    from: Option<Timestamp>,
+    to: Option<Timestamp>,
+    cursor: Option<&str>,
+    limit: usize,
+) -> anyhow::Result<StatementPage> {
+    let prefix = statement_prefix(user_id);
+    let (from, from_id, from_seq) = match cursor.map(parse_cursor).transpose()? {
+        Some((t, id, seq)) if t >= from.unwrap_or_default() => (t, id, seq),
+        _ => (from.unwrap_or_default(), 0, 0),
+    };
+    let start = statement_key(&prefix, from, from_id, from_seq);
+    let mut page = StatementPage::default();
+    for item in db.iterator(IteratorMode::From(&start, Direction::Forward)) {
+        let (key, value) = item?;
+        if !key.starts_with(&prefix) {
+            break;
+        }
+        let entry = bincode::deserialize::<Entry>(&value)?;
+        if to.is_some_and(|t| entry.timestamp > t) {
+            break;
+        }
+        if page.entries.len() == limit {
+            let seq = u32::from_be_bytes(key[key.len() - 4..].try_into().expect("u32;qed"));
+            page.truncated = true;
+            page.next = Some(format!("{}:{}:{}", entry.timestamp, entry.event_id, seq));
+            break;
+        }
+        page.entries.push(entry);
+    }
+    Ok(page)
+}
+
+/// the entries in CSV with a header line, the absent fields are left empty
+pub fn to_csv(entries: &[Entry]) -> String {
+    let opt = |v: Option<String>| v.unwrap_or_default();
+    let mut csv = String::from(
+        "event_id,timestamp,kind,currency,amount,balance,base,quote,order_id,price,block_number\n",
+    );
+    for e in entries {
+        csv.push_str(&format!(
+            "{},{},{},{},{},{},{},{},{},{},{}\n",
+            e.event_id,
+            e.timestamp,
+            e.kind.as_str(),
+            e.currency,
+            e.amount,
+            e.balance,
+            opt(e.symbol.map(|s| s.0.to_string())),
+            opt(e.symbol.map(|s| s.1.to_string())),
+            opt(e.order_id.map(|id| id.to_string())),
+            opt(e.price.map(|p| p.to_string())),
+            opt(e.block_number.map(|b| b.to_string())),
+        ));
+    }
+    csv
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use rust_decimal_macros::dec;
+
+    fn filled(event_id: u64, user_id: UserId, timestamp: Timestamp) -> Output {
+        Output {
+            event_id,
+            order_id: event_id,
+            user_id,
+            symbol: (1, 0),
+            state: OrderState::Filled,
+            role: Role::Taker,
+            ask_or_bid: AskOrBid::Bid,
+            price: dec!(10),
+            quote_charge: dec!(0),
+            quote_delta: dec!(-20),
+            quote_available: dec!(80),
+            quote_frozen: dec!(0),
+            base_charge: dec!(-0.002),
+            base_delta: dec!(2),
+            base_available: dec!(1.998),
+            base_frozen: dec!(0),
+            timestamp,
+            timestamp_us: timestamp * 1_000_000,
+        }
+    }
+
+    #[test]
+    pub fn test_statement() {
+        let dir = tempdir::TempDir::new("galois-statement").unwrap();
+        let db = rocksdb::DB::open_default(dir.path()).unwrap();
+        let user = UserId::from_low_u64_be(1);
+        let deposit = AssetsCmd {
+            user_id: user,
+            in_or_out: InOrOut::In,
+            currency: 0,
+            amount: dec!(100),
+            block_number: 7,
+            extrinsic_hash: vec![],
+            timestamp: 90,
+        };
+        let after = Balance {
+            available: dec!(100),
+            frozen: dec!(0),
+        };
+        record_transfer(&db, 1, &deposit, &after).unwrap();
+        let mut placed = filled(2, user, 100);
+        placed.base_delta = dec!(0);
+        record(&db, &[placed, filled(3, UserId::from_low_u64_be(2), 100)]).unwrap();
+        record(&db, &[filled(4, user, 100)]).unwrap();
+        // replayed
+        record(&db, &[filled(4, user, 100)]).unwrap();
+        record(&db, &[filled(5, user, 200)]).unwrap();
+        let kinds = |page: &StatementPage| {
+            page.entries
+                .iter()
+                .map(|e| (e.event_id, e.kind, e.currency))
+                .collect::<Vec<_>>()
+        };
+        let page = query(&db, &user, None, None, None, 2).unwrap();
+        assert_eq!(
+            vec![(1, EntryKind::TransferIn, 0), (4, EntryKind::Trade, 1)],
+            kinds(&page)
+        );
+        assert_eq!(Some(7), page.entries[0].block_number);
+        assert_eq!(Some("100:4:1".to_string()), page.next);
+        let page = query(&db, &user, None, None, page.next.as_deref(), 2).unwrap();
+        assert_eq!(
+            vec![(4, EntryKind::Trade, 0), (4, EntryKind::Fee, 1)],
+            kinds(&page)
+        );
+        assert_eq!(dec!(80), page.entries[0].balance);
+        assert_eq!(dec!(-0.002), page.entries[1].amount);
+        assert_eq!(dec!(1.998), page.entries[1].balance);
+        assert_eq!(Some(dec!(10)), page.entries[1].price);
+        let page = query(&db, &user, Some(100), Some(100), None, 10).unwrap();
+        assert_eq!(3, page.entries.len());
+        assert!(!page.truncated);
+        let page = query(&db, &user, Some(150), None, None, 10).unwrap();
+        assert_eq!(3, page.entries.len());
+        assert!(query(&db, &user, None, None, Some("100:4"), 10).is_err());
+        let csv = to_csv(&page.canonical().entries[..1]);
+        let mut lines = csv.lines();
+        assert!(lines.next().unwrap().starts_with("event_id,timestamp,kind"));
+        assert_eq!(
+            Some("5,200,trade,1,2.000000000000000000,2.000000000000000000,1,0,5,10,"),
+            lines.next()
+        );
+
+        let reports = TransferReport::from_entries(transfer_entries(1, &deposit, &after));
+        let v = serde_json::to_value(&reports).unwrap();
+        assert_eq!(
+            user,
+            serde_json::from_value(v[0]["user_id"].clone()).unwrap()
+        );
+        assert_eq!("transfer_in", v[0]["kind"]);
+        assert_eq!(
+            reports,
+            serde_json::from_value::<Vec<TransferReport>>(v).unwrap()
+        );
+    }
+}
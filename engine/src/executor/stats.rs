@@ -0,0 +1,329 @@
+// Copyright 2021-2023 UINB Technologies Pte. Ltd.
+
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// 	http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::{
+    core::*,
+    orderbook::{Order, OrderPage, Tape},
+    output::{
+        canonical::{Canonical, Scales},
+        kline::Kline,
+    },
+};
+use rust_decimal::prelude::Zero;
+use serde::{Deserialize, Serialize};
+use std::{collections::HashMap, mem::size_of, sync::RwLock};
+
+// a btree node holds up to 11 entries plus pointers, assume 2/3 full
+const BTREE_OVERHEAD: usize = 2;
+// the prev/next indices of the slots in the order queues
+const SLOT_OVERHEAD: usize = 2 * size_of::<usize>();
+
+// the last measured and the event id, too slow to measure on querying
+static MEASURED: RwLock<Option<(u64, MemoryStats)>> = RwLock::new(None);
+
+#[derive(Debug, Clone, Default, Eq, PartialEq, Serialize, Deserialize)]
+pub struct SymbolMemory {
+    pub symbol: Symbol,
+    pub pages: usize,
+    pub orders: usize,
+    pub orderbook_bytes: usize,
+    pub pending_orders: usize,
+    pub pending_orders_bytes: usize,
+    /// leaves of the global states that belong to the symbol
+    pub smt_leaves: usize,
+    /// proportional share of the global states by leaves
+    pub smt_bytes: usize,
+}
+
+#[derive(Debug, Clone, Default, Eq, PartialEq, Serialize, Deserialize)]
+pub struct MemoryStats {
+    pub symbols: Vec<SymbolMemory>,
+    pub orderbooks_bytes: usize,
+    pub pending_orders_bytes: usize,
+    pub accounts: usize,
+    pub accounts_bytes: usize,
+    pub smt_leaves: usize,
+    pub smt_bytes: usize,
+    pub total_bytes: usize,
+}
+
+/// the aggregates of a symbol for the dashboards, the amounts on the book exclude the hidden of
+/// the icebergs and the volumes are summed from the 1m klines of the last 24 hours
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub struct BookStats {
+    pub symbol: Symbol,
+    pub orders: usize,
+    pub ask_levels: usize,
+    pub bid_levels: usize,
+    pub ask_volume: Amount,
+    pub bid_volume: Amount,
+    pub best_ask: Option<Price>,
+    pub best_bid: Option<Price>,
+    pub last_price: Option<Price>,
+    pub volume_24h: Amount,
+    pub quote_volume_24h: Amount,
+}
+
+impl Canonical for BookStats {
+    fn canonical(mut self, scales: &Scales) -> Self {
+        self.ask_volume = scales.amount(self.ask_volume);
+        self.bid_volume = scales.amount(self.bid_volume);
+        self.best_ask = self.best_ask.map(|p| scales.price(p));
+        self.best_bid = self.best_bid.map(|p| scales.price(p));
+        self.last_price = self.last_price.map(|p| scales.price(p));
+        self.volume_24h = scales.amount(self.volume_24h);
+        self.quote_volume_24h = scales.vol(self.quote_volume_24h);
+        self
+    }
+}
+
+/// the amounts of the levels are kept along with the orders, so only the levels are summed
+pub fn book_stats(
+    symbol: Symbol,
+    book: &OrderBook,
+    last_price: Option<&LastPrice>,
+    daily: Option<&Kline>,
+) -> BookStats {
+    let visible = |pages: &Tape| {
+        pages
+            .values()
+            .fold(Amount::zero(), |v, p| v + p.amount - p.hidden)
+    };
+    BookStats {
+        symbol,
+        orders: book.indices.len(),
+        ask_levels: book.asks.len(),
+        bid_levels: book.bids.len(),
+        ask_volume: visible(&book.asks),
+        bid_volume: visible(&book.bids),
+        best_ask: book.get_best_ask(),
+        best_bid: book.get_best_bid(),
+        last_price: last_price.map(|p| p.price),
+        volume_24h: daily.map(|k| k.volume).unwrap_or_default(),
+        quote_volume_24h: daily.map(|k| k.quote_volume).unwrap_or_default(),
+    }
+}
+
+fn hashmap_bytes<K, V>(capacity: usize) -> usize {
+    // one control byte per bucket
+    capacity * (size_of::<K>() + size_of::<V>() + 1)
+}
+
+fn btreemap_bytes<K, V>(len: usize) -> usize {
+    len * (size_of::<K>() + size_of::<V>()) * BTREE_OVERHEAD
+}
+
+fn orderbook_memory(symbol: Symbol, book: &OrderBook) -> SymbolMemory {
+    let pages = book.asks.len() + book.bids.len();
+    let (orders, slots) = book
+        .asks
+        .values()
+        .chain(book.bids.values())
+        .fold((0, 0), |(n, s), p| {
+            (n + p.orders.len(), s + p.orders.capacity())
+        });
+    // the freed slots are kept for reuse
+    let orderbook_bytes = size_of::<OrderBook>()
+        + btreemap_bytes::<Price, OrderPage>(pages)
+        + slots * (size_of::<Option<Order>>() + SLOT_OVERHEAD)
+        + hashmap_bytes::<OrderId, usize>(orders)
+        + hashmap_bytes::<OrderId, Price>(book.indices.capacity());
+    SymbolMemory {
+        symbol,
+        pages,
+        orders,
+        orderbook_bytes,
+        // orderbook, best price and live pages
+        smt_leaves: 2 + pages,
+        ..Default::default()
+    }
+}
+
+/// memory accounting for capacity planning, the numbers are estimated from the sizes of
+/// the entries and the capacities of the containers rather than measured from the allocator
+pub fn memory_stats(data: &Data) -> MemoryStats {
+    let mut symbols = data
+        .orderbooks
+        .iter()
+        .map(|(symbol, book)| (*symbol, orderbook_memory(*symbol, book)))
+        .collect::<HashMap<_, _>>();
+    for ((_, symbol), orders) in data.orders.orders.iter() {
+        let m = symbols.entry(*symbol).or_insert_with(|| SymbolMemory {
+            symbol: *symbol,
+            ..Default::default()
+        });
+        m.pending_orders += orders.len();
+        m.pending_orders_bytes += size_of::<(UserId, Symbol)>()
+            + size_of::<HashMap<OrderId, PendingOrder>>()
+            + hashmap_bytes::<OrderId, PendingOrder>(orders.capacity());
+    }
+    let accounts = data.accounts.values().map(|a| a.len()).sum::<usize>();
+    let accounts_bytes = hashmap_bytes::<UserId, Account>(data.accounts.capacity())
+        + data
+            .accounts
+            .values()
+            .map(|a| hashmap_bytes::<Currency, Balance>(a.capacity()))
+            .sum::<usize>();
+    // the tree of the executor is moved to the prover pipeline
+    let smt_bytes = (bincode::serialized_size(&data.merkle_tree).unwrap_or_default() as usize)
+        .max(crate::prover::pipelined_tree_bytes());
+    let smt_leaves = accounts + symbols.values().map(|m| m.smt_leaves).sum::<usize>();
+    let mut symbols = symbols.into_values().collect::<Vec<_>>();
+    symbols.sort_by_key(|m| m.symbol);
+    for m in symbols.iter_mut() {
+        m.smt_bytes = smt_bytes * m.smt_leaves / smt_leaves.max(1);
+    }
+    let orderbooks_bytes = symbols.iter().map(|m| m.orderbook_bytes).sum::<usize>();
+    let pending_orders_bytes = symbols
+        .iter()
+        .map(|m| m.pending_orders_bytes)
+        .sum::<usize>();
+    MemoryStats {
+        symbols,
+        orderbooks_bytes,
+        pending_orders_bytes,
+        accounts,
+        accounts_bytes,
+        smt_leaves,
+        smt_bytes,
+        total_bytes: orderbooks_bytes + pending_orders_bytes + accounts_bytes + smt_bytes,
+    }
+}
+
+/// measured on starting and every checkpoint, served by `memory_stats` of the admin socket
+pub fn measure(id: u64, data: &Data) -> MemoryStats {
+    let stats = memory_stats(data);
+    *MEASURED.write().unwrap() = Some((id, stats.clone()));
+    stats
+}
+
+pub fn measured() -> Option<(u64, MemoryStats)> {
+    MEASURED.read().unwrap().clone()
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{core::*, matcher, stats::*};
+    use rust_decimal_macros::dec;
+
+    #[test]
+    pub fn test_memory_stats() {
+        let mut data = Data::new();
+        let symbol = (1, 0);
+        data.orderbooks.insert(
+            symbol,
+            OrderBook::new(
+                5,
+                1,
+                dec!(0.001),
+                dec!(0.001),
+                dec!(0.001),
+                dec!(0.001),
+                1,
+                dec!(1),
+                dec!(1),
+                true,
+                true,
+            )
+            .into(),
+        );
+        let empty = memory_stats(&data);
+        assert_eq!(1, empty.symbols.len());
+        assert_eq!(0, empty.symbols[0].orders);
+        assert_eq!(2, empty.smt_leaves);
+        let book = data.orderbooks.get_mut(&symbol).unwrap();
+        for i in 0..10 {
+            let user_id = UserId::from_low_u64_be(1);
+            let mr = matcher::execute_limit(
+                book,
+                user_id,
+                dec!(10) + Amount::from(i % 3),
+                dec!(1),
+                AskOrBid::Ask,
+            );
+            data.orders.insert(PendingOrder {
+                order_id: mr.taker.order_id,
+                user_id,
+                symbol,
+                direction: 0,
+                create_timestamp: 0,
+                amount: dec!(1),
+                price: mr.taker.price,
+                status: 0,
+                matched_quote_amount: Amount::ZERO,
+                matched_base_amount: Amount::ZERO,
+                base_fee: Amount::ZERO,
+                quote_fee: Amount::ZERO,
+            });
+        }
+        let stats = memory_stats(&data);
+        let m = &stats.symbols[0];
+        assert_eq!(3, m.pages);
+        assert_eq!(10, m.orders);
+        assert_eq!(10, m.pending_orders);
+        assert_eq!(5, m.smt_leaves);
+        assert!(m.orderbook_bytes > empty.symbols[0].orderbook_bytes);
+        assert!(stats.total_bytes > empty.total_bytes);
+    }
+
+    #[test]
+    pub fn test_book_stats() {
+        let mut book = OrderBook::new(
+            5,
+            1,
+            dec!(0.001),
+            dec!(0.001),
+            dec!(0.001),
+            dec!(0.001),
+            1,
+            dec!(1),
+            dec!(1),
+            true,
+            true,
+        );
+        let user_id = UserId::from_low_u64_be(1);
+        for (price, ask_or_bid) in [
+            (dec!(11), AskOrBid::Ask),
+            (dec!(12), AskOrBid::Ask),
+            (dec!(12), AskOrBid::Ask),
+            (dec!(9), AskOrBid::Bid),
+        ] {
+            matcher::execute_limit(&mut book, user_id, price, dec!(2), ask_or_bid);
+        }
+        let taker = UserId::from_low_u64_be(2);
+        matcher::execute_limit(&mut book, taker, dec!(11), dec!(1), AskOrBid::Bid);
+        let last = LastPrice {
+            symbol: (1, 0),
+            price: dec!(11),
+            event_id: 5,
+            timestamp: 0,
+        };
+        let stats = book_stats((1, 0), &book, Some(&last), None);
+        assert_eq!(4, stats.orders);
+        assert_eq!((2, 1), (stats.ask_levels, stats.bid_levels));
+        assert_eq!((dec!(5), dec!(2)), (stats.ask_volume, stats.bid_volume));
+        assert_eq!(
+            (Some(dec!(11)), Some(dec!(9))),
+            (stats.best_ask, stats.best_bid)
+        );
+        assert_eq!(Some(dec!(11)), stats.last_price);
+        assert_eq!(Amount::ZERO, stats.volume_24h);
+        let stats = stats.canonical(&Scales::from(&book));
+        assert_eq!("5.00000", stats.ask_volume.to_string());
+        assert_eq!(
+            Some("11.0".to_string()),
+            stats.best_ask.map(|p| p.to_string())
+        );
+    }
+}
@@ -0,0 +1,232 @@
+// Copyright 2021-2023 UINB Technologies Pte. Ltd.
+
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// 	http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::{core::*, flow::epoch_of, output::Output};
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+
+/// the traded volumes are rolled by the utc days of the broker flow
+pub const WINDOW_EPOCHS: u64 = 30;
+
+/// the taker and maker fees of the symbols quoted in `currency` are overridden for the users who
+/// traded at least `volume` of it during the last `WINDOW_EPOCHS`
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub struct FeeTier {
+    pub currency: Currency,
+    pub volume: Amount,
+    pub taker_fee: Fee,
+    pub maker_fee: Fee,
+}
+
+/// the schedule replacing the current one by `UPDATE_FEE_TIERS`
+pub fn validate(tiers: &[FeeTier]) -> anyhow::Result<()> {
+    for (i, t) in tiers.iter().enumerate() {
+        if tiers[..i]
+            .iter()
+            .any(|p| p.currency == t.currency && p.volume == t.volume)
+        {
+            return Err(anyhow::anyhow!(
+                "duplicated volume {} of currency {}",
+                t.volume,
+                t.currency
+            ));
+        }
+        if !t.volume.is_sign_positive() || t.volume.is_zero() {
+            return Err(anyhow::anyhow!("volume of the tiers must be positive"));
+        }
+        if t.taker_fee.is_sign_negative() || -t.maker_fee > t.taker_fee {
+            return Err(anyhow::anyhow!(
+                "the fees must not be negative except the maker rebate within the taker fee"
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// the quote volumes traded by the users during the last `WINDOW_EPOCHS`, as either takers or
+/// makers, one bucket for each epoch
+#[derive(Debug, Clone, Default, Eq, PartialEq, Serialize, Deserialize)]
+pub struct TradedVolumes {
+    buckets: ChunkedMap<(UserId, Currency), VecDeque<(u64, Amount)>>,
+}
+
+impl TradedVolumes {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.buckets.is_empty()
+    }
+
+    /// accumulate the quote of the outputs
+    pub fn record(&mut self, outputs: &[Output], timestamp: Timestamp) {
+        for o in outputs.iter().filter(|o| !o.quote_delta.is_zero()) {
+            self.add(o.user_id, o.symbol.1, o.quote_delta.abs(), timestamp);
+        }
+    }
+
+    /// the expired buckets of the user are dropped
+    pub fn add(
+        &mut self,
+        user_id: UserId,
+        currency: Currency,
+        volume: Amount,
+        timestamp: Timestamp,
+    ) {
+        let epoch = epoch_of(timestamp);
+        let buckets = self.buckets.entry((user_id, currency)).or_default();
+        match buckets.back_mut() {
+            Some((e, v)) if *e == epoch => *v += volume,
+            _ => buckets.push_back((epoch, volume)),
+        }
+        while buckets
+            .front()
+            .map_or(false, |(e, _)| e + WINDOW_EPOCHS <= epoch)
+        {
+            buckets.pop_front();
+        }
+    }
+
+    pub fn get(&self, user_id: &UserId, currency: Currency, timestamp: Timestamp) -> Amount {
+        let epoch = epoch_of(timestamp);
+        self.buckets
+            .get(&(*user_id, currency))
+            .map(|b| {
+                b.iter()
+                    .filter(|(e, _)| e + WINDOW_EPOCHS > epoch)
+                    .map(|(_, v)| *v)
+                    .sum()
+            })
+            .unwrap_or_default()
+    }
+
+    /// the tier reached by the user in the quote currency
+    pub fn tier<'a>(
+        &self,
+        tiers: &'a [FeeTier],
+        user_id: &UserId,
+        currency: Currency,
+        timestamp: Timestamp,
+    ) -> Option<&'a FeeTier> {
+        if tiers.is_empty() {
+            return None;
+        }
+        tier_of(tiers, currency, self.get(user_id, currency, timestamp))
+    }
+
+    /// move the volumes of the users in the currencies to a shard
+    pub fn split_off(&mut self, balances: &[(UserId, Currency)]) -> Self {
+        let buckets = balances
+            .iter()
+            .filter_map(|k| self.buckets.remove_entry(k))
+            .collect();
+        Self { buckets }
+    }
+
+    pub fn extend(&mut self, shard: Self) {
+        self.buckets.extend(shard.buckets);
+    }
+}
+
+/// the highest tier reached by the volume of the quote currency
+pub fn tier_of(tiers: &[FeeTier], currency: Currency, volume: Amount) -> Option<&FeeTier> {
+    tiers
+        .iter()
+        .filter(|t| t.currency == currency && t.volume <= volume)
+        .max_by_key(|t| t.volume)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{flow::EPOCH_SECS, matcher::Role, orderbook::AskOrBid};
+    use rust_decimal::prelude::Zero;
+    use rust_decimal_macros::dec;
+
+    fn output(user_id: UserId, quote: Amount) -> Output {
+        Output {
+            event_id: 1,
+            order_id: 1,
+            user_id,
+            symbol: (1, 0),
+            state: OrderState::Filled,
+            role: Role::Maker,
+            ask_or_bid: AskOrBid::Ask,
+            price: dec!(10),
+            quote_charge: Amount::zero(),
+            quote_delta: quote,
+            quote_available: Amount::zero(),
+            quote_frozen: Amount::zero(),
+            base_charge: Amount::zero(),
+            base_delta: Amount::zero(),
+            base_available: Amount::zero(),
+            base_frozen: Amount::zero(),
+            timestamp: 0,
+            timestamp_us: 0,
+        }
+    }
+
+    #[test]
+    pub fn test_traded_volumes() {
+        let alice = UserId::from_low_u64_be(1);
+        let mut volumes = TradedVolumes::new();
+        volumes.record(&[output(alice, dec!(100))], 0);
+        volumes.record(&[output(alice, dec!(-50)), output(alice, dec!(0))], 1);
+        volumes.record(&[output(alice, dec!(30))], EPOCH_SECS * 10);
+        assert_eq!(dec!(180), volumes.get(&alice, 0, EPOCH_SECS * 10));
+        assert!(volumes.get(&alice, 1, EPOCH_SECS * 10).is_zero());
+        // the first epoch expired
+        assert_eq!(dec!(30), volumes.get(&alice, 0, EPOCH_SECS * 30));
+        volumes.record(&[output(alice, dec!(1))], EPOCH_SECS * 40);
+        assert_eq!(dec!(1), volumes.get(&alice, 0, EPOCH_SECS * 40));
+        assert_eq!(1, volumes.buckets[&(alice, 0)].len());
+
+        let mut shard = volumes.split_off(&[(alice, 0)]);
+        assert!(volumes.is_empty());
+        shard.record(&[output(alice, dec!(1))], EPOCH_SECS * 40);
+        volumes.extend(shard);
+        assert_eq!(dec!(2), volumes.get(&alice, 0, EPOCH_SECS * 40));
+
+        let tier = |volume, taker_fee| FeeTier {
+            currency: 0,
+            volume,
+            taker_fee,
+            maker_fee: dec!(0),
+        };
+        let tiers = vec![
+            tier(dec!(1000), dec!(0.0005)),
+            tier(dec!(100), dec!(0.0008)),
+        ];
+        assert!(tier_of(&tiers, 0, dec!(99)).is_none());
+        assert_eq!(
+            dec!(0.0008),
+            tier_of(&tiers, 0, dec!(100)).unwrap().taker_fee
+        );
+        assert_eq!(
+            dec!(0.0005),
+            tier_of(&tiers, 0, dec!(5000)).unwrap().taker_fee
+        );
+        assert!(tier_of(&tiers, 1, dec!(5000)).is_none());
+        assert!(validate(&tiers).is_ok());
+        let mut duplicated = tiers.clone();
+        duplicated.push(tier(dec!(100), dec!(0.0007)));
+        assert!(validate(&duplicated).is_err());
+        let mut rebate = tiers.clone();
+        rebate[0].maker_fee = dec!(-0.0006);
+        assert!(validate(&rebate).is_err());
+        rebate[0].maker_fee = dec!(-0.0002);
+        assert!(validate(&rebate).is_ok());
+    }
+}
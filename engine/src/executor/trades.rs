@@ -0,0 +1,156 @@
+// Copyright 2021-2023 UINB Technologies Pte. Ltd.
+
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// 	http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::{core::*, output::Trade};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{HashMap, VecDeque},
+    path::{Path, PathBuf},
+};
+
+/// trades kept for each symbol
+pub const MAX_TRADES: usize = 1000;
+
+pub const DEFAULT_TRADES: usize = 100;
+
+/// the latest trades of each symbol, persisted on checkpoints if `path` is set
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct RecentTrades {
+    #[serde(skip)]
+    path: Option<PathBuf>,
+    /// the events after the last snapshot would be replayed on restarting
+    last_event_id: u64,
+    trades: HashMap<Symbol, VecDeque<Trade>>,
+}
+
+impl RecentTrades {
+    pub fn open(path: impl AsRef<Path>) -> anyhow::Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let mut trades = if path.exists() {
+            // the trades before the stamps of matching are dropped, they are replaced soon
+            bincode::deserialize::<Self>(&std::fs::read(&path)?).unwrap_or_else(|e| {
+                log::warn!("discarding the recent trades of an older format, {:?}", e);
+                Self::default()
+            })
+        } else {
+            Self::default()
+        };
+        trades.path = Some(path);
+        Ok(trades)
+    }
+
+    /// the trades of one event
+    pub fn record(&mut self, trades: &[Trade]) {
+        let event_id = match trades.first() {
+            Some(t) if t.event_id > self.last_event_id => t.event_id,
+            _ => return,
+        };
+        for t in trades {
+            let ring = self.trades.entry(t.symbol).or_default();
+            ring.push_back(t.clone());
+            if ring.len() > MAX_TRADES {
+                ring.pop_front();
+            }
+        }
+        self.last_event_id = event_id;
+    }
+
+    /// move the trades of the symbols to a shard
+    pub fn fork(&mut self, symbols: &[Symbol]) -> Self {
+        Self {
+            path: None,
+            last_event_id: self.last_event_id,
+            trades: symbols
+                .iter()
+                .filter_map(|s| self.trades.remove_entry(s))
+                .collect(),
+        }
+    }
+
+    pub fn join(&mut self, shard: Self) {
+        self.last_event_id = self.last_event_id.max(shard.last_event_id);
+        self.trades.extend(shard.trades);
+    }
+
+    /// the latest `limit` trades, the oldest first
+    pub fn query(&self, symbol: &Symbol, limit: usize) -> Vec<Trade> {
+        self.trades
+            .get(symbol)
+            .map(|ring| {
+                ring.iter()
+                    .skip(ring.len().saturating_sub(limit))
+                    .cloned()
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    pub fn save(&self) -> anyhow::Result<()> {
+        if let Some(ref path) = self.path {
+            let tmp = path.with_extension("tmp");
+            std::fs::write(&tmp, bincode::serialize(self)?)?;
+            std::fs::rename(&tmp, path)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::orderbook::AskOrBid;
+    use rust_decimal_macros::dec;
+
+    fn trade(event_id: u64, symbol: Symbol, price: Price) -> Trade {
+        Trade {
+            event_id,
+            symbol,
+            price,
+            amount: dec!(1),
+            taker_side: AskOrBid::Bid,
+            timestamp: event_id,
+            timestamp_us: event_id * 1_000_000,
+        }
+    }
+
+    #[test]
+    pub fn test_recent_trades() {
+        let dir = tempdir::TempDir::new("galois-trades").unwrap();
+        let path = dir.path().join("trades.bin");
+        let mut trades = RecentTrades::open(&path).unwrap();
+        for i in 1..=MAX_TRADES as u64 {
+            trades.record(&[trade(i, (1, 0), dec!(10))]);
+        }
+        trades.record(&[
+            trade(MAX_TRADES as u64 + 1, (1, 0), dec!(11)),
+            trade(MAX_TRADES as u64 + 1, (1, 0), dec!(12)),
+        ]);
+        // replayed
+        trades.record(&[trade(MAX_TRADES as u64 + 1, (1, 0), dec!(12))]);
+        trades.record(&[trade(MAX_TRADES as u64 + 2, (2, 0), dec!(1))]);
+        let latest = trades.query(&(1, 0), 3);
+        assert_eq!(
+            vec![dec!(10), dec!(11), dec!(12)],
+            latest.iter().map(|t| t.price).collect::<Vec<_>>()
+        );
+        assert_eq!(MAX_TRADES, trades.query(&(1, 0), usize::MAX).len());
+        assert_eq!(3, trades.query(&(1, 0), usize::MAX)[0].event_id);
+        assert_eq!(1, trades.query(&(2, 0), 10).len());
+        assert!(trades.query(&(3, 0), 10).is_empty());
+        trades.save().unwrap();
+        let restored = RecentTrades::open(&path).unwrap();
+        assert_eq!(latest, restored.query(&(1, 0), 3));
+        assert_eq!(MAX_TRADES as u64 + 2, restored.last_event_id);
+    }
+}
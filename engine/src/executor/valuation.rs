@@ -0,0 +1,233 @@
+// Copyright 2021-2023 UINB Technologies Pte. Ltd.
+
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// 	http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::{
+    config::{PriceSource, ValuationConfig},
+    core::*,
+    fusotao::{self, TokenInfo},
+    orderbook::OrderBook,
+    output::canonical::{self, fixed, CURRENCY_SCALE},
+};
+use rust_decimal::{prelude::*, Decimal};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub struct ValuedBalance {
+    pub currency: Currency,
+    /// `None` if the token isn't registered on chain
+    pub token: Option<TokenInfo>,
+    pub available: Amount,
+    pub frozen: Amount,
+    /// in the quote currency, `None` if no market prices the currency
+    pub price: Option<Price>,
+    pub value: Option<Amount>,
+}
+
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub struct ValuedAccount {
+    /// `None` if the valuation isn't configured
+    pub quote: Option<Currency>,
+    pub balances: Vec<ValuedBalance>,
+    /// the sum of the valued balances
+    pub total: Amount,
+}
+
+fn price_of(
+    orderbooks: &HashMap<Symbol, CopyOnWrite<OrderBook>>,
+    last_prices: &HashMap<Symbol, LastPrice>,
+    source: PriceSource,
+    symbol: &Symbol,
+) -> Option<Price> {
+    match source {
+        PriceSource::LastPrice => last_prices.get(symbol).map(|p| p.price),
+        PriceSource::MidPrice => {
+            let orderbook = orderbooks.get(symbol)?;
+            match (orderbook.get_best_bid(), orderbook.get_best_ask()) {
+                (Some(bid), Some(ask)) => Some((bid + ask) / Decimal::TWO),
+                (bid, ask) => bid.or(ask),
+            }
+        }
+    }
+}
+
+/// the price of `currency` in `quote`, by the market `(currency, quote)` or `(quote, currency)`
+pub fn price(
+    orderbooks: &HashMap<Symbol, CopyOnWrite<OrderBook>>,
+    last_prices: &HashMap<Symbol, LastPrice>,
+    source: PriceSource,
+    currency: Currency,
+    quote: Currency,
+) -> Option<Price> {
+    if currency == quote {
+        return Some(Decimal::ONE);
+    }
+    price_of(orderbooks, last_prices, source, &(currency, quote)).or_else(|| {
+        price_of(orderbooks, last_prices, source, &(quote, currency))
+            .filter(|p| p.is_sign_positive() && !p.is_zero())
+            .and_then(|p| Decimal::ONE.checked_div(p))
+            .map(|p| p.round_dp(CURRENCY_SCALE))
+    })
+}
+
+/// join the balances with the tokens on chain and the prices in the quote currency
+pub fn value(
+    account: Account,
+    orderbooks: &HashMap<Symbol, CopyOnWrite<OrderBook>>,
+    last_prices: &HashMap<Symbol, LastPrice>,
+    valuation: Option<&ValuationConfig>,
+) -> ValuedAccount {
+    let mut balances = account
+        .into_iter()
+        .map(|(currency, b)| {
+            let price =
+                valuation.and_then(|v| price(orderbooks, last_prices, v.source, currency, v.quote));
+            let value = price.and_then(|p| {
+                b.available
+                    .checked_add(b.frozen)
+                    .and_then(|a| a.checked_mul(p))
+                    .map(|v| fixed(v.round_dp(CURRENCY_SCALE), CURRENCY_SCALE))
+            });
+            let b = canonical::balance(b);
+            ValuedBalance {
+                currency,
+                token: fusotao::token_info(currency),
+                available: b.available,
+                frozen: b.frozen,
+                price,
+                value,
+            }
+        })
+        .collect::<Vec<_>>();
+    balances.sort_by_key(|b| b.currency);
+    let total = balances
+        .iter()
+        .filter_map(|b| b.value)
+        .fold(Amount::zero(), |sum, v| sum.saturating_add(v));
+    ValuedAccount {
+        quote: valuation.map(|v| v.quote),
+        balances,
+        total: fixed(total, CURRENCY_SCALE),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{assets::Balance, fusotao::TOKENS, matcher};
+    use rust_decimal_macros::dec;
+
+    #[test]
+    pub fn test_value_account() {
+        let (btc, usdt, eth) = (901, 902, 903);
+        TOKENS.insert(
+            usdt,
+            TokenInfo {
+                symbol: "USDT".to_string(),
+                decimals: 6,
+                stable: true,
+            },
+        );
+        let mut orderbooks = HashMap::new();
+        let mut orderbook = OrderBook::new(
+            4,
+            2,
+            dec!(0.001),
+            dec!(0.001),
+            dec!(0.001),
+            dec!(0.001),
+            1,
+            dec!(0.1),
+            dec!(10),
+            true,
+            true,
+        );
+        let user_id = UserId::from_low_u64_be(1);
+        matcher::execute_limit(&mut orderbook, user_id, dec!(19990), dec!(1), AskOrBid::Bid);
+        matcher::execute_limit(&mut orderbook, user_id, dec!(20010), dec!(1), AskOrBid::Ask);
+        orderbooks.insert((btc, usdt), CopyOnWrite::new(orderbook));
+        let last_prices = HashMap::from([(
+            (usdt, eth),
+            LastPrice {
+                symbol: (usdt, eth),
+                price: dec!(0.0005),
+                event_id: 1,
+                timestamp: 0,
+            },
+        )]);
+        let account = Account::from([
+            (
+                btc,
+                Balance {
+                    available: dec!(1),
+                    frozen: dec!(1),
+                },
+            ),
+            (
+                usdt,
+                Balance {
+                    available: dec!(100),
+                    frozen: dec!(0),
+                },
+            ),
+            (
+                eth,
+                Balance {
+                    available: dec!(1),
+                    frozen: dec!(0),
+                },
+            ),
+        ]);
+        let last = ValuationConfig {
+            quote: usdt,
+            source: PriceSource::LastPrice,
+        };
+        let valued = value(account.clone(), &orderbooks, &last_prices, Some(&last));
+        assert_eq!(Some(usdt), valued.quote);
+        assert_eq!(
+            vec![btc, usdt, eth],
+            valued
+                .balances
+                .iter()
+                .map(|b| b.currency)
+                .collect::<Vec<_>>()
+        );
+        // no trade on `(btc, usdt)` yet
+        assert!(valued.balances[0].value.is_none());
+        assert_eq!(
+            Some("USDT"),
+            valued.balances[1].token.as_ref().map(|t| t.symbol.as_str())
+        );
+        assert_eq!(Some(dec!(100)), valued.balances[1].value);
+        // the inverse of `(usdt, eth)`
+        assert_eq!(Some(dec!(2000)), valued.balances[2].price);
+        assert_eq!(dec!(2100), valued.total);
+
+        let mid = ValuationConfig {
+            quote: usdt,
+            source: PriceSource::MidPrice,
+        };
+        let valued = value(account.clone(), &orderbooks, &last_prices, Some(&mid));
+        assert_eq!(Some(dec!(20000)), valued.balances[0].price);
+        assert_eq!(Some(dec!(40000)), valued.balances[0].value);
+        assert!(valued.balances[2].value.is_none());
+        assert_eq!(dec!(40100), valued.total);
+
+        let valued = value(account, &orderbooks, &last_prices, None);
+        assert!(valued.quote.is_none());
+        assert!(valued.balances.iter().all(|b| b.price.is_none()));
+        assert_eq!(dec!(1), valued.balances[0].frozen);
+        assert!(valued.total.is_zero());
+    }
+}
@@ -13,16 +13,82 @@
 // limitations under the License.
 
 use crate::{config::C, fusotao::*};
+use signer::Signer;
 use sp_core::Pair;
-use std::time::Duration;
+use std::time::{Duration, Instant};
+
+/// the proofs submitted but not finalized within it are submitted again
+const IN_FLIGHT_TIMEOUT: Duration = Duration::from_secs(60);
+
+lazy_static::lazy_static! {
+    static ref SUBMISSION: RwLock<SubmissionStats> = RwLock::new(Default::default());
+    static ref LAST_SUBMITTED: RwLock<Option<Instant>> = RwLock::new(None);
+}
+
+/// accumulated since the committer started
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct SubmissionStats {
+    pub batches: u64,
+    pub proofs: u64,
+    pub origin_bytes: u64,
+    pub compressed_bytes: u64,
+    /// the last proof submitted
+    pub last_event_id: u64,
+    /// the proofs saved but not submitted yet
+    pub backlog: u64,
+}
+
+pub fn stats(state: &FusoState) -> SubmissionStats {
+    let latest = prover::latest_root().map_or(0, |(id, _)| id);
+    SubmissionStats {
+        backlog: latest.saturating_sub(state.get_proving_progress()),
+        ..SUBMISSION.read().unwrap().clone()
+    }
+}
+
+/// a batch reaching either limit is submitted without waiting for the window
+fn is_full(batch: &[(u64, RawParameter)]) -> bool {
+    batch.len() >= C.fusotao.proof_batch_limit
+        || batch.iter().map(|(_, p)| p.0.len()).sum::<usize>() >= MAX_EXTRINSIC_SIZE
+}
 
 /// since we won't wait for the proofs to be `Finalized`, we must add a watchdog to revert `proved_event_id` in case of fork
-pub fn init(connector: FusoConnector, progress: Arc<FusoState>) {
+pub fn init(connector: FusoConnector, state: Arc<FusoState>) {
     if C.dry_run.is_some() {
         return;
     }
-    let progress = progress.proved_event_id.clone();
-    loop {
+    let progress = state.proved_event_id.clone();
+    // the proofs after the on-chain sequence are gone only if the dominator was reset
+    let proved_id = progress.load(Ordering::Relaxed);
+    if matches!(prover::fetch_raw_ge(proved_id + 1).first(), Some((id, _)) if *id > proved_id + 1) {
+        log::error!(
+            "dominator reset detected, proofs after {} not found, waiting for re-anchoring",
+            proved_id
+        );
+        let dominator = state.dominator.read().unwrap().clone();
+        *state.reset.write().unwrap() = Some(dominator);
+    }
+    // the proofs sent before restarting may be finalized later, don't submit them twice
+    let in_flight = prover::submitted();
+    if !state.is_reset() && in_flight > proved_id {
+        log::info!(
+            "proofs {}-{} in flight before restarting, waiting for them to be finalized",
+            proved_id + 1,
+            in_flight
+        );
+        let start = Instant::now();
+        loop {
+            if let Ok(n) = connector.sync_progress() {
+                progress.fetch_max(n, Ordering::Relaxed);
+            }
+            if progress.load(Ordering::Relaxed) >= in_flight || start.elapsed() >= IN_FLIGHT_TIMEOUT
+            {
+                break;
+            }
+            std::thread::sleep(Duration::from_millis(6000));
+        }
+    }
+    while !state.is_reset() {
         let proved_id = progress.load(Ordering::Relaxed);
         let v = prover::fetch_raw_ge(proved_id + 1);
         if v.is_empty() {
@@ -38,23 +104,40 @@ pub fn init(connector: FusoConnector, progress: Arc<FusoState>) {
             }
             Err(e) => {
                 log::error!("submitting proofs failed due to {}, retrying...", e);
+                connector.reconnect();
             }
         }
     }
-    let local = progress.clone();
+    let local = progress;
     let conn = connector.clone();
+    let st = state.clone();
     std::thread::spawn(move || -> anyhow::Result<()> {
+        let window = Duration::from_millis(C.fusotao.proof_batch_window);
+        let mut pending_since: Option<Instant> = None;
         loop {
+            if st.is_reset() {
+                std::thread::sleep(Duration::from_millis(3000));
+                continue;
+            }
             let id = local.load(Ordering::Relaxed);
             let v = prover::fetch_raw_ge(id + 1);
             if v.is_empty() {
                 std::thread::sleep(Duration::from_millis(3000));
                 continue;
             }
+            let since = *pending_since.get_or_insert_with(Instant::now);
+            if !is_full(&v) && since.elapsed() < window {
+                std::thread::sleep(Duration::from_millis(100).min(window));
+                continue;
+            }
             match submit(&conn, v, false) {
-                Ok(n) => local.store(n, Ordering::Relaxed),
+                Ok(n) => {
+                    pending_since = None;
+                    local.store(n, Ordering::Relaxed);
+                }
                 Err(e) => {
                     log::error!("submitting proofs failed due to {}, retrying...", e);
+                    conn.reconnect();
                 }
             }
         }
@@ -62,18 +145,158 @@ pub fn init(connector: FusoConnector, progress: Arc<FusoState>) {
     std::thread::spawn(move || -> anyhow::Result<()> {
         loop {
             std::thread::sleep(Duration::from_secs(60));
-            if let Ok(remote) = connector.sync_progress() {
-                let local = progress.load(Ordering::Relaxed);
-                if remote < local {
-                    progress.store(remote, Ordering::Relaxed);
-                }
-                let _ = prover::remove_before(remote);
+            if let Ok(remote) = connector.get_dominator() {
+                acknowledge(&state, remote);
             }
         }
     });
 }
 
-fn compress_proofs(raws: Vec<RawParameter>) -> Vec<u8> {
+/// the finalized dominator polled by the watchdog or read by the scanner, the proofs before its
+/// sequence are pruned and the ones in flight for too long are submitted again
+pub fn acknowledge(state: &FusoState, remote: Dominator) {
+    if state.is_reset() {
+        *state.reset.write().unwrap() = Some(remote);
+        return;
+    }
+    let anchor = state.dominator.read().unwrap().clone();
+    if is_reset(&anchor, &remote) {
+        log::error!(
+            "dominator reset detected, on-chain sequence {:?} -> {:?}, waiting for re-anchoring",
+            anchor.sequence,
+            remote.sequence
+        );
+        *state.reset.write().unwrap() = Some(remote);
+        return;
+    }
+    let local = state.proved_event_id.load(Ordering::Relaxed);
+    let expired = LAST_SUBMITTED
+        .read()
+        .unwrap()
+        .map_or(true, |t| t.elapsed() >= IN_FLIGHT_TIMEOUT);
+    if remote.sequence.0 < local && expired {
+        log::warn!(
+            "proofs {}-{} not finalized in time, submitting again",
+            remote.sequence.0 + 1,
+            local
+        );
+        state
+            .proved_event_id
+            .store(remote.sequence.0, Ordering::Relaxed);
+    }
+    let _ = prover::remove_before(remote.sequence.0);
+    *state.dominator.write().unwrap() = remote;
+}
+
+/// the proofs are dropped as if verified on chain in the standalone mode, the latest one is kept
+pub fn sink(state: Arc<FusoState>) {
+    if C.dry_run.is_some() {
+        return;
+    }
+    std::thread::spawn(move || loop {
+        if let Some((id, root)) = prover::latest_root() {
+            if id > state.get_proving_progress() {
+                state.proved_event_id.store(id, Ordering::Relaxed);
+                let _ = prover::remove_before(id);
+                let mut dominator = state.dominator.write().unwrap();
+                dominator.sequence.0 = id;
+                dominator.merkle_root = root;
+            }
+        }
+        std::thread::sleep(Duration::from_millis(3000));
+    });
+}
+
+/// wait for the committer to submit the saved proofs, `false` if `timeout` elapsed
+pub fn flush(state: &FusoState, timeout: Duration) -> bool {
+    if C.dry_run.is_some() {
+        return true;
+    }
+    let start = std::time::Instant::now();
+    loop {
+        let latest = prover::latest_root().map_or(0, |(id, _)| id);
+        let proved = state.get_proving_progress();
+        if proved >= latest {
+            return true;
+        }
+        if state.is_reset() || start.elapsed() >= timeout {
+            log::error!("proofs {}-{} are left unsubmitted", proved + 1, latest);
+            return false;
+        }
+        std::thread::sleep(Duration::from_millis(500));
+    }
+}
+
+/// the finalized sequence never goes backward and `start_from` never changes unless
+/// the dominator is re-registered or reset on chain
+pub fn is_reset(anchor: &Dominator, remote: &Dominator) -> bool {
+    remote.start_from != anchor.start_from || remote.sequence.0 < anchor.sequence.0
+}
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum ReanchorMode {
+    /// resume from the latest local proof matching the on-chain merkle root,
+    /// the proofs after it are submitted again
+    Replay,
+    /// drop all unproven proofs and continue from the latest one, the on-chain merkle root
+    /// must have been set to the current one
+    Discard,
+}
+
+impl std::str::FromStr for ReanchorMode {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        match s {
+            "replay" => Ok(Self::Replay),
+            "discard" => Ok(Self::Discard),
+            _ => Err(anyhow::anyhow!("unknown re-anchoring mode {}", s)),
+        }
+    }
+}
+
+/// confirmed by the operator through `confirm_reanchor` of the admin socket, returning the new proving progress
+pub fn reanchor(state: &FusoState, mode: ReanchorMode) -> anyhow::Result<u64> {
+    let remote = state
+        .reset
+        .read()
+        .unwrap()
+        .clone()
+        .ok_or(anyhow::anyhow!("no dominator reset detected"))?;
+    let anchor = match mode {
+        ReanchorMode::Replay => prover::find_by_root(&remote.merkle_root).ok_or(
+            anyhow::anyhow!("none of the local proofs matches the on-chain merkle root"),
+        )?,
+        ReanchorMode::Discard => {
+            let (latest, root) =
+                prover::latest_root().ok_or(anyhow::anyhow!("no local proofs to discard"))?;
+            anyhow::ensure!(
+                root == remote.merkle_root,
+                "the on-chain merkle root doesn't match the latest proof {}",
+                latest
+            );
+            prover::remove_before(latest + 1)?;
+            latest
+        }
+    };
+    state.proved_event_id.store(anchor, Ordering::Relaxed);
+    state
+        .scanning_progress
+        .fetch_max(remote.start_from, Ordering::Relaxed);
+    log::info!(
+        "re-anchored at event {} by {:?}, scanning from block {}",
+        anchor,
+        mode,
+        state.get_scanning_progress()
+    );
+    *state.dominator.write().unwrap() = remote;
+    *state.reset.write().unwrap() = None;
+    Ok(anchor)
+}
+
+/// the sibling hashes shared by the merkle proofs of a batch are deduplicated by compressing
+/// the batch as a whole, the chain still verifies the proofs one by one
+fn compress_proofs(raws: Vec<RawParameter>) -> (Vec<u8>, usize) {
     let r = raws.encode();
     let origin_size = r.len();
     let compressed_proofs = lz4_flex::compress_prepend_size(r.as_ref());
@@ -83,7 +306,20 @@ fn compress_proofs(raws: Vec<RawParameter>) -> Vec<u8> {
         origin_size,
         compressed_size
     );
-    compressed_proofs
+    (compressed_proofs, origin_size)
+}
+
+/// the extrinsic of the compressed proofs in hex, signed locally or by the remote signer
+pub fn compose(connector: &FusoConnector, compressed: Vec<u8>) -> anyhow::Result<String> {
+    match connector.signer {
+        Signer::Local(_) => {
+            let api = connector.api();
+            let payload: sub_api::UncheckedExtrinsicV4<_> =
+                sub_api::compose_extrinsic!(api, "Verifier", "verify_compress_v2", compressed);
+            Ok(payload.hex_encode())
+        }
+        Signer::Remote(ref remote) => remote.verify_proofs(&compressed),
+    }
 }
 
 fn submit(
@@ -93,22 +329,51 @@ fn submit(
 ) -> anyhow::Result<u64> {
     anyhow::ensure!(!batch.is_empty(), "empty batch is not allowed");
     let (id, proofs): (Vec<u64>, Vec<RawParameter>) = batch.into_iter().unzip();
-    log::debug!("submitting proofs at {}", chrono::Local::now());
-    let payload: sub_api::UncheckedExtrinsicV4<_> = sub_api::compose_extrinsic!(
-        connector.api,
-        "Verifier",
-        "verify_compress_v2",
-        compress_proofs(proofs)
-    );
-    if finalized {
-        connector
-            .api
-            .send_extrinsic(payload.hex_encode(), sub_api::XtStatus::Finalized)?;
-        connector.sync_progress()
+    let _span = tracing::debug_span!(
+        "commit",
+        from = id.first().copied().unwrap_or_default(),
+        to = id.last().copied().unwrap_or_default(),
+        finalized
+    )
+    .entered();
+    tracing::debug!("submitting proofs at {}", chrono::Local::now());
+    let (compressed, origin_size) = compress_proofs(proofs);
+    let compressed_size = compressed.len();
+    let xt = compose(connector, compressed)?;
+    let api = connector.api();
+    prover::mark_submitted(id.last().copied().unwrap_or_default())?;
+    *LAST_SUBMITTED.write().unwrap() = Some(Instant::now());
+    let n = if finalized {
+        api.send_extrinsic(xt, sub_api::XtStatus::Finalized)?;
+        connector.sync_progress()?
     } else {
-        connector
-            .api
-            .send_extrinsic(payload.hex_encode(), sub_api::XtStatus::InBlock)?;
-        Ok(id.last().copied().unwrap_or_default())
-    }
+        api.send_extrinsic(xt, sub_api::XtStatus::InBlock)?;
+        id.last().copied().unwrap_or_default()
+    };
+    let mut stats = SUBMISSION.write().unwrap();
+    stats.batches += 1;
+    stats.proofs += id.len() as u64;
+    stats.origin_bytes += origin_size as u64;
+    stats.compressed_bytes += compressed_size as u64;
+    stats.last_event_id = n;
+    Ok(n)
+}
+
+#[test]
+pub fn test_dominator_reset() {
+    let anchor = Dominator {
+        start_from: 100,
+        sequence: (1000, 200),
+        ..Default::default()
+    };
+    let mut remote = anchor.clone();
+    remote.sequence = (1200, 210);
+    assert!(!is_reset(&anchor, &remote));
+    remote.sequence = (0, 220);
+    assert!(is_reset(&anchor, &remote));
+    remote.sequence = (1200, 220);
+    remote.start_from = 215;
+    assert!(is_reset(&anchor, &remote));
+    assert_eq!(Ok(ReanchorMode::Replay), "replay".parse().map_err(|_| ()));
+    assert!("rewind".parse::<ReanchorMode>().is_err());
 }
@@ -17,62 +17,121 @@ use crate::config::C;
 use anyhow::anyhow;
 use node_api::decoder::{RuntimeDecoder, StorageHasher};
 use parity_scale_codec::{Decode, Error as CodecError};
-use sp_core::{sr25519::Public, Pair};
-use std::sync::atomic::Ordering;
+use signer::Signer;
+use sp_core::sr25519::Public;
+use std::{
+    sync::atomic::{AtomicUsize, Ordering},
+    time::Duration,
+};
 use sub_api::{rpc::WsRpcClient, Hash};
 
+const MAX_BACKOFF: Duration = Duration::from_secs(60);
+
 #[derive(Clone)]
 pub struct FusoConnector {
-    pub api: FusoApi,
-    pub signer: Sr25519Key,
+    api: Arc<RwLock<FusoApi>>,
+    // the index of the active endpoint in `fusotao.node_url` and `fusotao.fallback_urls`
+    active: Arc<AtomicUsize>,
+    pub signer: Signer,
 }
 
 impl FusoConnector {
     pub fn new() -> anyhow::Result<Self> {
-        let signer = Sr25519Key::from_string(&C.fusotao.key_seed, None)
-            .map_err(|e| anyhow!("invalid fusotao config: {:?}", e))?;
-        let client = WsRpcClient::new(&C.fusotao.node_url);
-        let api = FusoApi::new(client)
-            .map(|api| api.set_signer(signer.clone()))
-            .inspect_err(|e| log::error!("{:?}", e))
-            .map_err(|_| anyhow!("fusotao node not available or metadata check failed."))?;
-        Ok(Self { api, signer })
+        let signer = Signer::from_config()?;
+        let (active, api) = Self::connect(&signer, 0)?;
+        Ok(Self {
+            api: Arc::new(RwLock::new(api)),
+            active: Arc::new(AtomicUsize::new(active)),
+            signer,
+        })
+    }
+
+    /// try the endpoints in turn from `from`, returning the first available one
+    fn connect(signer: &Signer, from: usize) -> anyhow::Result<(usize, FusoApi)> {
+        let urls = C.fusotao.get_node_urls();
+        for i in (0..urls.len()).map(|i| (from + i) % urls.len()) {
+            match FusoApi::new(WsRpcClient::new(&urls[i])) {
+                Ok(api) => {
+                    log::info!("connected to fusotao node {}", urls[i]);
+                    // the extrinsics are signed by the remote signer without the key
+                    return match signer {
+                        Signer::Local(pair) => Ok((i, api.set_signer(pair.clone()))),
+                        Signer::Remote(_) => Ok((i, api)),
+                    };
+                }
+                Err(e) => log::error!("fusotao node {} not available, {:?}", urls[i], e),
+            }
+        }
+        Err(anyhow!(
+            "fusotao node not available or metadata check failed."
+        ))
+    }
+
+    pub fn api(&self) -> FusoApi {
+        self.api.read().unwrap().clone()
+    }
+
+    /// called on the rpc errors, keep the active endpoint if it recovers or switch to the next
+    /// available one, blocking with exponential backoff until either is reachable
+    pub fn reconnect(&self) {
+        let mut api = self.api.write().unwrap();
+        let mut backoff = Duration::from_secs(1);
+        loop {
+            if matches!(api.get_finalized_head(), Ok(Some(_))) {
+                return;
+            }
+            let active = self.active.load(Ordering::Relaxed);
+            if let Ok((i, reconnected)) = Self::connect(&self.signer, active + 1) {
+                self.active.store(i, Ordering::Relaxed);
+                *api = reconnected;
+                return;
+            }
+            log::error!("all fusotao nodes unavailable, retrying in {:?}", backoff);
+            std::thread::sleep(backoff);
+            backoff = (backoff * 2).min(MAX_BACKOFF);
+        }
     }
 
     pub fn get_pubkey(&self) -> Public {
-        self.signer.public().clone()
+        self.signer.public()
     }
 
     pub fn sync_progress(&self) -> anyhow::Result<u64> {
+        self.get_dominator().map(|d| d.sequence.0)
+    }
+
+    pub fn get_dominator(&self) -> anyhow::Result<Dominator> {
         let (_, hash) = self.get_finalized_block()?;
-        let key = self.api.metadata.storage_map_key::<FusoAccountId>(
+        self.get_dominator_at(hash)
+    }
+
+    pub fn get_dominator_at(&self, hash: Hash) -> anyhow::Result<Dominator> {
+        let api = self.api();
+        let key = api.metadata.storage_map_key::<FusoAccountId>(
             "Verifier",
             "Dominators",
             self.get_pubkey(),
         )?;
-        let payload = self
-            .api
+        let payload = api
             .get_opaque_storage_by_key_hash(key, Some(hash))?
             .ok_or(anyhow!("{} isn't the prover", self.get_pubkey()))?;
-        let result = Dominator::decode(&mut payload.as_slice())?;
-        Ok(result.sequence.0)
+        Ok(Dominator::decode(&mut payload.as_slice())?)
     }
 
     pub fn fully_sync_chain(&self, state: Arc<FusoState>) -> anyhow::Result<Vec<Command>> {
         let (block, hash) = self.get_finalized_block()?;
-        let decoder = RuntimeDecoder::new(self.api.metadata.clone());
+        let api = self.api();
+        let decoder = RuntimeDecoder::new(api.metadata.clone());
 
         // market list, double map AccountId, Symbol -> Market
-        let key = self
-            .api
+        let key = api
             .metadata
             .storage_double_map_partial_key::<FusoAccountId>(
                 "Market",
                 "Markets",
                 &self.get_pubkey(),
             )?;
-        let payload = self
-            .api
+        let payload = api
             .get_opaque_storage_pairs_by_key_hash(key, Some(hash))?
             .ok_or(anyhow!(""))?;
         for (k, v) in payload.into_iter() {
@@ -87,12 +146,8 @@ impl FusoConnector {
         }
 
         // token list, map TokenId -> Token
-        let key = self
-            .api
-            .metadata
-            .storage_map_key_prefix("Token", "Tokens")?;
-        let payload = self
-            .api
+        let key = api.metadata.storage_map_key_prefix("Token", "Tokens")?;
+        let payload = api
             .get_opaque_storage_pairs_by_key_hash(key, Some(hash))?
             .ok_or(anyhow!(""))?;
         for (k, v) in payload.into_iter() {
@@ -101,15 +156,12 @@ impl FusoConnector {
                 &mut k.as_slice(),
             )?;
             let token = OnchainToken::decode(&mut v.as_slice())?;
-            state.currencies.insert(token_id, token);
+            state.insert_currency(token_id, token);
         }
 
         // broker list, map AccountId -> Broker
-        let key = self
-            .api
-            .metadata
-            .storage_map_key_prefix("Market", "Brokers")?;
-        let payload = self.api.get_keys(key, Some(hash))?.ok_or(anyhow!(""))?;
+        let key = api.metadata.storage_map_key_prefix("Market", "Brokers")?;
+        let payload = api.get_keys(key, Some(hash))?.ok_or(anyhow!(""))?;
         for k in payload.into_iter() {
             let broker: FusoAccountId = RuntimeDecoder::extract_map_identifier(
                 StorageHasher::Blake2_128Concat,
@@ -119,19 +171,21 @@ impl FusoConnector {
         }
 
         // pending receipts, double map AccountId, AccountId -> Receipt
-        let key = self
-            .api
+        let key = api
             .metadata
             .storage_double_map_partial_key::<FusoAccountId>(
                 "Verifier",
                 "Receipts",
                 &self.get_pubkey(),
             )?;
-        let payload = self
-            .api
+        let payload = api
             .get_opaque_storage_pairs_by_key_hash(key, Some(hash))?
             .ok_or(anyhow!(""))?;
         let mut commands = vec![];
+        let now = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .expect("clock;qed")
+            .as_secs();
         for (k, v) in payload.into_iter() {
             let user = RuntimeDecoder::extract_double_map_identifier::<FusoAccountId, FusoAccountId>(
                 StorageHasher::Blake2_128Concat,
@@ -149,6 +203,7 @@ impl FusoConnector {
                     cmd.block_number = Some(u32::decode(stream)?);
                     // FIXME not a good idea to read the hash if the node isn't a full node
                     cmd.extrinsic_hash = Some(Default::default());
+                    cmd.timestamp = Some(now);
                     match i {
                         0 => {
                             cmd.cmd = crate::cmd::TRANSFER_IN;
@@ -175,12 +230,11 @@ impl FusoConnector {
     }
 
     pub fn get_finalized_block(&self) -> anyhow::Result<(u32, Hash)> {
-        let hash = self
-            .api
+        let api = self.api();
+        let hash = api
             .get_finalized_head()?
             .ok_or(anyhow!("finalized headers cant be found"))?;
-        let block_number = self
-            .api
+        let block_number = api
             .get_signed_block(Some(hash))?
             .ok_or(anyhow!("signed block {} can't be found", hash))
             .map(|b: sub_api::SignedBlock<FusoBlock>| b.block.header.number)?;
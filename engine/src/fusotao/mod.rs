@@ -15,7 +15,7 @@
 use crate::{config::C, core::*, input::*};
 use connector::FusoConnector;
 use dashmap::DashMap;
-use parity_scale_codec::{Compact, Decode, Encode, WrapperTypeDecode, WrapperTypeEncode};
+use parity_scale_codec::{Compact, Decode, Encode};
 use rust_decimal::{prelude::*, Decimal};
 use serde::{Deserialize, Serialize};
 use smt::{blake2b::Blake2bHasher, default_store::DefaultStore, SparseMerkleTree, H256};
@@ -23,7 +23,7 @@ use std::{
     convert::TryInto,
     sync::{
         atomic::{AtomicU32, AtomicU64, Ordering},
-        Arc,
+        Arc, RwLock,
     },
 };
 
@@ -31,6 +31,8 @@ pub mod committer;
 pub mod connector;
 pub mod prover;
 pub mod scanner;
+pub mod signer;
+pub mod store;
 
 pub type BlockNumber = u32;
 pub type GlobalStates = SparseMerkleTree<Blake2bHasher, H256, DefaultStore<H256>>;
@@ -47,19 +49,67 @@ const ONE_ONCHAIN: u128 = 1_000_000_000_000_000_000;
 const MILL: u32 = 1_000_000;
 const QUINTILL: u64 = 1_000_000_000_000_000_000;
 const MAX_EXTRINSIC_SIZE: usize = 3 * 1024 * 1024;
+// the decimals of the native token
+const NATIVE_DECIMALS: u8 = 18;
+
+lazy_static::lazy_static! {
+    // the tokens registered on chain, published for the queries served by the executor
+    pub static ref TOKENS: DashMap<Currency, TokenInfo> = DashMap::new();
+}
+
+/// the ticker of `currency`, the `[[token]]` aliases take precedence over the tokens on chain
+pub fn token_name(currency: Currency) -> Option<String> {
+    C.get_token(currency)
+        .map(|t| t.symbol.clone())
+        .or_else(|| TOKENS.get(&currency).map(|t| t.symbol.clone()))
+}
+
+/// the token registered on chain, renamed by the `[[token]]` aliases
+pub fn token_info(currency: Currency) -> Option<TokenInfo> {
+    let mut token = TOKENS.get(&currency)?.value().clone();
+    if let Some(alias) = C.get_token(currency) {
+        token.symbol = alias.symbol.clone();
+    }
+    Some(token)
+}
 
 /// AccountId of chain = MultiAddress<sp_runtime::AccountId32, ()>::Id = GenericAddress::Id
 /// 1. from_ss58check() or from_ss58check_with_version()
 /// 2. new or from public
 pub fn sync() -> anyhow::Result<(FusoConnector, Arc<FusoState>)> {
     let connector = FusoConnector::new()?;
-    let progress = connector.sync_progress()?;
+    let dominator = connector.get_dominator()?;
     let state = FusoState::default();
-    state.proved_event_id.store(progress, Ordering::Relaxed);
+    state
+        .proved_event_id
+        .store(dominator.sequence.0, Ordering::Relaxed);
+    *state.dominator.write().unwrap() = dominator;
     log::info!("proving progress synchronized");
     Ok((connector, Arc::new(state)))
 }
 
+/// the chain is simulated in the standalone mode, proven until the latest local proof and the
+/// block numbers of the fake transfers start from the current unix time to be unique
+pub fn standalone() -> Arc<FusoState> {
+    let state = FusoState::default();
+    let (proved, root) = prover::latest_root().unwrap_or_default();
+    state.proved_event_id.store(proved, Ordering::Relaxed);
+    state.chain_height.store(
+        std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .expect("clock;qed")
+            .as_secs() as u32,
+        Ordering::Relaxed,
+    );
+    {
+        let mut dominator = state.dominator.write().unwrap();
+        dominator.sequence.0 = proved;
+        dominator.merkle_root = root;
+    }
+    log::info!("running standalone, proven until {}", proved);
+    Arc::new(state)
+}
+
 /// tracking essential onchain states
 #[derive(Clone, Debug, Default)]
 pub struct FusoState {
@@ -69,6 +119,10 @@ pub struct FusoState {
     pub symbols: DashMap<Symbol, OnchainSymbol>,
     pub currencies: DashMap<Currency, OnchainToken>,
     pub brokers: DashMap<UserId, u32>,
+    /// the latest finalized state of the dominator
+    pub dominator: Arc<RwLock<Dominator>>,
+    /// the state of the dominator once it is reset on chain, waiting for re-anchoring
+    pub reset: Arc<RwLock<Option<Dominator>>>,
 }
 
 impl FusoState {
@@ -83,6 +137,20 @@ impl FusoState {
     pub fn get_chain_height(&self) -> u32 {
         self.chain_height.load(Ordering::Relaxed)
     }
+
+    /// the finalized blocks not scanned yet
+    pub fn get_blocks_behind(&self) -> u32 {
+        (self.get_chain_height() + 1).saturating_sub(self.get_scanning_progress())
+    }
+
+    pub fn insert_currency(&self, token_id: Currency, token: OnchainToken) {
+        TOKENS.insert(token_id, (&token).into());
+        self.currencies.insert(token_id, token);
+    }
+
+    pub fn is_reset(&self) -> bool {
+        self.reset.read().unwrap().is_some()
+    }
 }
 
 #[derive(Clone, Debug)]
@@ -101,7 +169,7 @@ pub enum Receipt {
     // RevokeWithCallback(u32, u128, u32, Callback),
 }
 
-#[derive(Clone, Encode, Decode, Eq, PartialEq, Debug, Serialize)]
+#[derive(Clone, Encode, Decode, Eq, PartialEq, Debug, Serialize, Deserialize)]
 pub enum MarketStatus {
     Registered,
     Open,
@@ -125,6 +193,9 @@ pub struct OffchainSymbol {
     pub min_base: Decimal,
     pub base_scale: u8,
     pub quote_scale: u8,
+    /// the registered or closed markets are listed too
+    #[serde(default)]
+    pub open: bool,
 }
 
 impl From<(Symbol, OnchainSymbol)> for OffchainSymbol {
@@ -134,6 +205,7 @@ impl From<(Symbol, OnchainSymbol)> for OffchainSymbol {
             min_base: to_decimal_represent(data.min_base).expect("far away from overflow;qed"),
             base_scale: data.base_scale,
             quote_scale: data.quote_scale,
+            open: data.status == MarketStatus::Open,
         }
     }
 }
@@ -148,6 +220,34 @@ pub enum OnchainToken {
     POLYGON(Vec<u8>, Vec<u8>, u128, bool, u8),
 }
 
+/// the metadata of the tokens joined with the balances
+#[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
+pub struct TokenInfo {
+    pub symbol: String,
+    pub decimals: u8,
+    pub stable: bool,
+}
+
+impl From<&OnchainToken> for TokenInfo {
+    fn from(token: &OnchainToken) -> Self {
+        match token {
+            OnchainToken::NEP141(symbol, _, _, stable, decimals)
+            | OnchainToken::ERC20(symbol, _, _, stable, decimals)
+            | OnchainToken::BEP20(symbol, _, _, stable, decimals)
+            | OnchainToken::POLYGON(symbol, _, _, stable, decimals) => Self {
+                symbol: String::from_utf8_lossy(symbol).into_owned(),
+                decimals: *decimals,
+                stable: *stable,
+            },
+            OnchainToken::FND10(symbol, _) => Self {
+                symbol: String::from_utf8_lossy(symbol).into_owned(),
+                decimals: NATIVE_DECIMALS,
+                stable: false,
+            },
+        }
+    }
+}
+
 #[derive(Clone, Decode, Debug, Default)]
 pub struct Dominator {
     pub name: Vec<u8>,
@@ -223,12 +323,6 @@ pub struct MarketClosedEvent {
     quote: u32,
 }
 
-impl WrapperTypeEncode for UserId {}
-
-impl WrapperTypeDecode for UserId {
-    type Wrapped = [u8; 32];
-}
-
 #[derive(Clone, Encode, Decode, Eq, PartialEq, Debug)]
 pub enum FusoCommand {
     AskLimit {
@@ -266,15 +360,104 @@ pub enum FusoCommand {
         amount: Compact<u128>,
     },
     RejectTransferIn,
+    /// `to_sub` moves the funds from the main account to the proxy account of `bot`
+    SubTransfer {
+        bot: FusoAccountId,
+        token: Compact<u32>,
+        currency: Compact<u32>,
+        amount: Compact<u128>,
+        to_sub: bool,
+    },
+    /// two legs proven in one event, the leaves of `first` come before the ones of `second`
+    /// and the maker deltas of the proof are the ones of `second`
+    Route {
+        first: Box<FusoCommand>,
+        first_leaves: Compact<u32>,
+        first_maker_page_delta: u8,
+        first_maker_account_delta: u8,
+        second: Box<FusoCommand>,
+    },
+    /// the fees moved from `SYSTEM`, which is never proven, to the treasury of the proof
+    WithdrawFees {
+        currency: Compact<u32>,
+        amount: Compact<u128>,
+    },
+    /// the taker trades with `counterparty` at `price` off the orderbook, the leaves of the
+    /// counterparty come before the ones of the taker
+    BlockAsk {
+        price: Compact<u128>,
+        amount: Compact<u128>,
+        maker_fee: Compact<u32>,
+        taker_fee: Compact<u32>,
+        base: Compact<u32>,
+        quote: Compact<u32>,
+        counterparty: FusoAccountId,
+    },
+    BlockBid {
+        price: Compact<u128>,
+        amount: Compact<u128>,
+        maker_fee: Compact<u32>,
+        taker_fee: Compact<u32>,
+        base: Compact<u32>,
+        quote: Compact<u32>,
+        counterparty: FusoAccountId,
+    },
+    /// the commands proven in one event in order, the leaves of each come before the ones of the
+    /// next and the maker deltas of the proof are the ones of `last`
+    Batch {
+        before: Vec<Batched>,
+        last: Box<FusoCommand>,
+    },
+    /// the makers charged other than the maker fee of `cmd` by the fee tiers, sorted by the
+    /// accounts, the others are charged the maker fee of `cmd`
+    MakerFees {
+        fees: Vec<(FusoAccountId, Compact<u32>)>,
+        cmd: Box<FusoCommand>,
+    },
+}
+
+impl FusoCommand {
+    /// wrap the command if any maker is charged other than `maker_fee`, the rebates encoded as
+    /// zero like the maker fee of the symbol
+    pub fn with_maker_fees(self, maker_fee: Fee, makers: &[crate::matcher::Maker]) -> Self {
+        let encode = |fee: Fee| fee.max(Fee::zero()).to_fee();
+        let fees = makers
+            .iter()
+            .filter_map(|m| m.fee.map(|fee| (m.user_id, encode(fee))))
+            .filter(|(_, fee)| *fee != encode(maker_fee))
+            .collect::<std::collections::BTreeMap<_, _>>();
+        if fees.is_empty() {
+            return self;
+        }
+        FusoCommand::MakerFees {
+            fees: fees
+                .into_iter()
+                .map(|(u, fee)| (FusoAccountId::from_raw(u.0), fee.into()))
+                .collect(),
+            cmd: Box::new(self),
+        }
+    }
 }
 
+/// a command proven before the last one of a batch, along with its leaves count and maker deltas
+#[derive(Clone, Encode, Decode, Eq, PartialEq, Debug)]
+pub struct Batched {
+    pub cmd: FusoCommand,
+    pub leaves: Compact<u32>,
+    pub maker_page_delta: u8,
+    pub maker_account_delta: u8,
+}
+
+/// the negative maker fees are rejected unless the verifier supports `maker_rebate`, which
+/// proves the rebates by the leaves of the makers with a zero maker fee
 impl Into<FusoCommand> for (LimitCmd, Fee, Fee) {
     fn into(self) -> FusoCommand {
+        let maker_fee = self.1.max(Fee::zero());
         match self.0.ask_or_bid {
             AskOrBid::Ask => FusoCommand::AskLimit {
                 price: self.0.price.to_amount().into(),
                 amount: self.0.amount.to_amount().into(),
-                maker_fee: self.1.to_fee().into(),
+                maker_fee: maker_fee.to_fee().into(),
                 taker_fee: self.2.to_fee().into(),
                 base: self.0.symbol.0.into(),
                 quote: self.0.symbol.1.into(),
@@ -283,7 +466,7 @@ impl Into<FusoCommand> for (LimitCmd, Fee, Fee) {
             AskOrBid::Bid => FusoCommand::BidLimit {
                 price: self.0.price.to_amount().into(),
                 amount: self.0.amount.to_amount().into(),
-                maker_fee: self.1.to_fee().into(),
+                maker_fee: maker_fee.to_fee().into(),
                 taker_fee: self.2.to_fee().into(),
                 base: self.0.symbol.0.into(),
                 quote: self.0.symbol.1.into(),
@@ -293,6 +476,33 @@ impl Into<FusoCommand> for (LimitCmd, Fee, Fee) {
     }
 }
 
+/// the counterparty pays the maker fee, encoded as the limit orders
+impl Into<FusoCommand> for (BlockTradeCmd, Fee, Fee) {
+    fn into(self) -> FusoCommand {
+        let maker_fee = self.1.max(Fee::zero());
+        match self.0.ask_or_bid {
+            AskOrBid::Ask => FusoCommand::BlockAsk {
+                price: self.0.price.to_amount().into(),
+                amount: self.0.amount.to_amount().into(),
+                maker_fee: maker_fee.to_fee().into(),
+                taker_fee: self.2.to_fee().into(),
+                base: self.0.symbol.0.into(),
+                quote: self.0.symbol.1.into(),
+                counterparty: FusoAccountId::from_raw(self.0.counterparty.0),
+            },
+            AskOrBid::Bid => FusoCommand::BlockBid {
+                price: self.0.price.to_amount().into(),
+                amount: self.0.amount.to_amount().into(),
+                maker_fee: maker_fee.to_fee().into(),
+                taker_fee: self.2.to_fee().into(),
+                base: self.0.symbol.0.into(),
+                quote: self.0.symbol.1.into(),
+                counterparty: FusoAccountId::from_raw(self.0.counterparty.0),
+            },
+        }
+    }
+}
+
 impl Into<FusoCommand> for CancelCmd {
     fn into(self) -> FusoCommand {
         FusoCommand::Cancel {
@@ -302,6 +512,18 @@ impl Into<FusoCommand> for CancelCmd {
     }
 }
 
+impl Into<FusoCommand> for SubTransferCmd {
+    fn into(self) -> FusoCommand {
+        FusoCommand::SubTransfer {
+            bot: FusoAccountId::from_raw(self.bot.0),
+            token: self.token.into(),
+            currency: self.currency.into(),
+            amount: self.amount.to_amount().into(),
+            to_sub: self.to_sub,
+        }
+    }
+}
+
 impl Into<FusoCommand> for (AssetsCmd, bool) {
     fn into(self) -> FusoCommand {
         match (self.0.in_or_out, self.1) {
@@ -390,4 +612,54 @@ pub mod test {
         let v = dec!(340282366920938463463);
         assert_eq!(v.to_amount(), 340282366920938463463000000000000000000);
     }
+
+    #[test]
+    pub fn test_blocks_behind() {
+        let state = FusoState::default();
+        state.chain_height.store(100, Ordering::Relaxed);
+        state.scanning_progress.store(95, Ordering::Relaxed);
+        assert_eq!(6, state.get_blocks_behind());
+        state.scanning_progress.store(101, Ordering::Relaxed);
+        assert_eq!(0, state.get_blocks_behind());
+    }
+
+    #[test]
+    pub fn test_maker_fees() {
+        let cmd = FusoCommand::Cancel {
+            base: 1.into(),
+            quote: 0.into(),
+        };
+        let maker = |id: u64, fee: Option<Fee>| crate::matcher::Maker {
+            fee,
+            ..crate::matcher::Maker::maker_filled(
+                UserId::from_low_u64_be(id),
+                id,
+                dec!(100),
+                dec!(1),
+            )
+        };
+        let makers = [
+            maker(3, Some(dec!(0.0002))),
+            maker(2, None),
+            maker(1, Some(dec!(-0.0001))),
+            maker(4, Some(dec!(0.001))),
+        ];
+        assert_eq!(
+            FusoCommand::MakerFees {
+                fees: vec![
+                    (
+                        FusoAccountId::from_raw(UserId::from_low_u64_be(1).0),
+                        0.into()
+                    ),
+                    (
+                        FusoAccountId::from_raw(UserId::from_low_u64_be(3).0),
+                        200.into()
+                    ),
+                ],
+                cmd: Box::new(cmd.clone()),
+            },
+            cmd.clone().with_maker_fees(dec!(0.001), &makers)
+        );
+        assert_eq!(cmd, cmd.clone().with_maker_fees(dec!(0.001), &makers[1..2]));
+    }
 }
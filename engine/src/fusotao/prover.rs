@@ -13,21 +13,49 @@
 // limitations under the License.
 
 use super::*;
-use crate::{assets::Balance, matcher::*, orderbook::AskOrBid, output::Output};
+use crate::{
+    assets::Balance,
+    clearing,
+    latency::{self, Stage},
+    matcher::*,
+    orderbook::AskOrBid,
+    output::{audit, Output},
+    ring, snapshot,
+    verify::{self, RootCheck, Violation},
+};
 use blake2::{Blake2b, Digest};
 use generic_array::typenum::U32;
 use rocksdb::{Direction, IteratorMode, WriteBatchWithTransaction};
-use std::collections::HashMap;
+use smt::traits::Store;
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        mpsc::TryRecvError,
+    },
+    thread::JoinHandle,
+    time::Instant,
+};
+use store::PersistentStates;
 
 pub type BlakeTwo256 = Blake2b<U32>;
+type MerkleTree<S> = SparseMerkleTree<Blake2bHasher, H256, S>;
 
 const ACCOUNT_KEY: u8 = 0x00;
 const ORDERBOOK_KEY: u8 = 0x01;
 const BESTPRICE_KEY: u8 = 0x02;
 const ORDERPAGE_KEY: u8 = 0x03;
 
+// sorted before all the proofs
+const SUBMITTED_KEY: &[u8] = b"ackproof";
+
+static TREE_BYTES: AtomicUsize = AtomicUsize::new(0);
+
+// the proofs searched for the on-chain root on re-anchoring, the older are submitted long before
+const MAX_ROOT_SEEK: usize = 100_000;
+
 pub fn prove_trade_cmd(
-    data: &mut Data,
+    data: &Data,
     _nonce: u32,
     _signature: Vec<u8>,
     encoded_cmd: FusoCommand,
@@ -39,7 +67,7 @@ pub fn prove_trade_cmd(
     taker_quote_before: &Balance,
     outputs: &[Output],
     matches: &Match,
-) -> Proof {
+) -> StateDelta {
     let mut leaves = vec![];
     let taker = outputs.last().unwrap();
     let symbol = taker.symbol.clone();
@@ -47,7 +75,7 @@ pub fn prove_trade_cmd(
     let user_id = taker.user_id;
     let orderbook = data.orderbooks.get(&symbol).unwrap();
     let size = orderbook.size();
-    log::debug!(
+    tracing::debug!(
         "generating merkle leaf of {:?}: orderbook = ({:?}, {:?}) -> ({:?}, {:?})",
         taker.event_id,
         ask_size_before,
@@ -88,24 +116,27 @@ pub fn prove_trade_cmd(
                 .or_insert_with(|| r.clone());
         });
     maker_accounts.values().for_each(|r| {
-        log::debug!("{:?}", r);
+        tracing::debug!("{:?}", r);
+        // the charges are negative fees or positive rebates
         let (ba, bf, qa, qf) = match r.ask_or_bid {
             // -base_frozen, +quote_available
             // base_frozen0 + r.base_delta = base_frozen
-            // qa - q0 + abs(r.quote_charge) = abs(quote_delta)
+            // qa - q0 - r.quote_charge = abs(quote_delta)
+            // ba - b0 = r.base_charge
             AskOrBid::Ask => (
-                r.base_available,
+                r.base_available - r.base_charge,
                 r.base_frozen + r.base_delta.abs(),
-                r.quote_available + r.quote_charge.abs() - r.quote_delta.abs(),
+                r.quote_available - r.quote_charge - r.quote_delta.abs(),
                 r.quote_frozen,
             ),
             // +base_available, -quote_frozen
             // quote_frozen0 + r.quote_delta = quote_frozen
-            // ba0 - ba + abs(r.base_charge) = abs(base_delta)
+            // ba - ba0 - r.base_charge = abs(base_delta)
+            // qa - q0 = r.quote_charge
             AskOrBid::Bid => (
-                r.base_available + r.base_charge.abs() - r.base_delta.abs(),
+                r.base_available - r.base_charge - r.base_delta.abs(),
                 r.base_frozen,
-                r.quote_available,
+                r.quote_available - r.quote_charge,
                 r.quote_frozen + r.quote_delta.abs(),
             ),
         };
@@ -134,7 +165,7 @@ pub fn prove_trade_cmd(
         taker_base_before.available.to_amount(),
         taker_base_before.frozen.to_amount(),
     );
-    log::debug!(
+    tracing::debug!(
             "generating merkle leaf of {:?}: taker base = [{:?}({:?}), {:?}({:?})] -> [{:?}({:?}), {:?}({:?})]",
             taker.event_id,
             old_taker_ba,
@@ -160,7 +191,7 @@ pub fn prove_trade_cmd(
         taker_quote_before.available.to_amount(),
         taker_quote_before.frozen.to_amount(),
     );
-    log::debug!(
+    tracing::debug!(
             "generating merkle leaf of {:?}: taker quote = [{:?}({:?}), {:?}({:?})] -> [{:?}({:?}), {:?}({:?})]",
             taker.event_id,
             old_taker_qa,
@@ -199,26 +230,137 @@ pub fn prove_trade_cmd(
         pages.reverse();
     }
     leaves.append(&mut pages);
-    let merkle_proof = gen_proofs(&mut data.merkle_tree, &leaves);
-    Proof {
+    let mut brokers = broker_leaves(data, symbol, outputs, matches);
+    leaves.append(&mut brokers);
+    StateDelta {
         event_id,
         user_id,
         cmd: encoded_cmd,
         leaves,
         maker_page_delta: matches.page_delta.len() as u8,
         maker_account_delta: maker_accounts.len() as u8 * 2,
-        merkle_proof,
-        root: data.merkle_tree.root().clone().into(),
+        omit_leaves: false,
+    }
+}
+
+/// block trades don't touch the orderbook, the counterparty is proven as the only maker
+pub fn prove_block_trade(
+    data: &Data,
+    encoded_cmd: FusoCommand,
+    maker_before: (&Balance, &Balance),
+    taker_before: (&Balance, &Balance),
+    outputs: &[Output],
+) -> StateDelta {
+    let maker = outputs.first().unwrap();
+    let taker = outputs.last().unwrap();
+    let symbol = taker.symbol;
+    let orderbook = data.orderbooks.get(&symbol).unwrap();
+    let (ask_size, bid_size) = orderbook.size();
+    let (best_ask, best_bid) = orderbook.get_size_of_best();
+    let best_ask = best_ask.map(|a| a.0).unwrap_or(Amount::zero()).to_amount();
+    let best_bid = best_bid.map(|b| b.0).unwrap_or(Amount::zero()).to_amount();
+    let mut leaves = vec![new_orderbook_merkle_leaf(
+        symbol,
+        ask_size.to_amount(),
+        bid_size.to_amount(),
+        ask_size.to_amount(),
+        bid_size.to_amount(),
+    )];
+    for (o, (base_before, quote_before)) in [(maker, maker_before), (taker, taker_before)] {
+        leaves.push(new_account_merkle_leaf(
+            &o.user_id,
+            symbol.0,
+            base_before.available.to_amount(),
+            base_before.frozen.to_amount(),
+            o.base_available.to_amount(),
+            o.base_frozen.to_amount(),
+        ));
+        leaves.push(new_account_merkle_leaf(
+            &o.user_id,
+            symbol.1,
+            quote_before.available.to_amount(),
+            quote_before.frozen.to_amount(),
+            o.quote_available.to_amount(),
+            o.quote_frozen.to_amount(),
+        ));
+    }
+    leaves.push(new_bestprice_merkle_leaf(
+        symbol, best_ask, best_bid, best_ask, best_bid,
+    ));
+    StateDelta {
+        event_id: taker.event_id,
+        user_id: taker.user_id,
+        cmd: encoded_cmd,
+        leaves,
+        maker_page_delta: 0,
+        maker_account_delta: 2,
+        omit_leaves: false,
+    }
+}
+
+/// the legs of a route are concatenated, the leaves touched by both are updated in order
+pub fn prove_route(first: StateDelta, second: StateDelta) -> StateDelta {
+    let mut leaves = first.leaves;
+    let first_leaves = leaves.len() as u32;
+    leaves.extend(second.leaves);
+    StateDelta {
+        event_id: second.event_id,
+        user_id: second.user_id,
+        cmd: FusoCommand::Route {
+            first: Box::new(first.cmd),
+            first_leaves: first_leaves.into(),
+            first_maker_page_delta: first.maker_page_delta,
+            first_maker_account_delta: first.maker_account_delta,
+            second: Box::new(second.cmd),
+        },
+        leaves,
+        maker_page_delta: second.maker_page_delta,
+        maker_account_delta: second.maker_account_delta,
+        omit_leaves: false,
+    }
+}
+
+/// the deltas of one event concatenated as a route, a single one is kept as is
+pub fn prove_batch(mut deltas: Vec<StateDelta>) -> StateDelta {
+    let last = deltas.pop().expect("at least one delta;qed");
+    if deltas.is_empty() {
+        return last;
+    }
+    let mut leaves = vec![];
+    let before = deltas
+        .into_iter()
+        .map(|delta| {
+            let batched = Batched {
+                cmd: delta.cmd,
+                leaves: (delta.leaves.len() as u32).into(),
+                maker_page_delta: delta.maker_page_delta,
+                maker_account_delta: delta.maker_account_delta,
+            };
+            leaves.extend(delta.leaves);
+            batched
+        })
+        .collect();
+    leaves.extend(last.leaves);
+    StateDelta {
+        event_id: last.event_id,
+        user_id: last.user_id,
+        cmd: FusoCommand::Batch {
+            before,
+            last: Box::new(last.cmd),
+        },
+        leaves,
+        maker_page_delta: last.maker_page_delta,
+        maker_account_delta: last.maker_account_delta,
+        omit_leaves: false,
     }
 }
 
 pub fn prove_assets_cmd(
-    merkle_tree: &mut GlobalStates,
     event_id: u64,
     cmd: AssetsCmd,
     account_before: &Balance,
     account_after: &Balance,
-) -> Proof {
+) -> StateDelta {
     let (new_available, new_frozen, old_available, old_frozen) = (
         account_after.available.to_amount(),
         account_after.frozen.to_amount(),
@@ -233,25 +375,80 @@ pub fn prove_assets_cmd(
         new_available,
         new_frozen,
     )];
-    let merkle_proof = gen_proofs(merkle_tree, &leaves);
-    Proof {
+    StateDelta {
         event_id,
         user_id: cmd.user_id,
         cmd: (cmd, true).into(),
         leaves,
         maker_page_delta: 0,
         maker_account_delta: 0,
-        merkle_proof,
-        root: merkle_tree.root().clone().into(),
+        omit_leaves: false,
     }
 }
 
-pub fn prove_cmd_rejected(
-    merkle_tree: &mut GlobalStates,
+/// both accounts are proven, the sending one first
+pub fn prove_sub_transfer(
     event_id: u64,
-    cmd: AssetsCmd,
-    account_before: &Balance,
-) -> Proof {
+    cmd: SubTransferCmd,
+    from: (&Balance, &Balance),
+    to: (&Balance, &Balance),
+) -> StateDelta {
+    let (from_id, to_id) = cmd.parties();
+    let leaves = [(from_id, from), (to_id, to)]
+        .into_iter()
+        .map(|(user_id, (before, after))| {
+            new_account_merkle_leaf(
+                &user_id,
+                cmd.currency,
+                before.available.to_amount(),
+                before.frozen.to_amount(),
+                after.available.to_amount(),
+                after.frozen.to_amount(),
+            )
+        })
+        .collect::<Vec<_>>();
+    StateDelta {
+        event_id,
+        user_id: cmd.user_id,
+        cmd: cmd.into(),
+        leaves,
+        maker_page_delta: 0,
+        maker_account_delta: 0,
+        omit_leaves: false,
+    }
+}
+
+/// only the treasury is proven since the fees of `SYSTEM` are never proven
+pub fn prove_fee_withdrawal(
+    event_id: u64,
+    cmd: &WithdrawFeesCmd,
+    amount: Amount,
+    before: &Balance,
+    after: &Balance,
+) -> StateDelta {
+    let leaves = vec![new_account_merkle_leaf(
+        &cmd.treasury,
+        cmd.currency,
+        before.available.to_amount(),
+        before.frozen.to_amount(),
+        after.available.to_amount(),
+        after.frozen.to_amount(),
+    )];
+    StateDelta {
+        event_id,
+        user_id: cmd.treasury,
+        cmd: FusoCommand::WithdrawFees {
+            currency: cmd.currency.into(),
+            amount: amount.to_amount().into(),
+        },
+        leaves,
+        maker_page_delta: 0,
+        maker_account_delta: 0,
+        omit_leaves: false,
+    }
+}
+
+pub fn prove_cmd_rejected(event_id: u64, cmd: AssetsCmd, account_before: &Balance) -> StateDelta {
     let (old_available, old_frozen) = (
         account_before.available.to_amount(),
         account_before.frozen.to_amount(),
@@ -264,25 +461,22 @@ pub fn prove_cmd_rejected(
         old_available,
         old_frozen,
     )];
-    let merkle_proof = gen_proofs(merkle_tree, &leaves);
-    Proof {
+    StateDelta {
         event_id,
         user_id: cmd.user_id,
         cmd: (cmd, false).into(),
         leaves,
         maker_page_delta: 0,
         maker_account_delta: 0,
-        merkle_proof,
-        root: merkle_tree.root().clone().into(),
+        omit_leaves: false,
     }
 }
 
 pub fn prove_rejecting_no_reason(
-    merkle_tree: &mut GlobalStates,
     event_id: u64,
     cmd: AssetsCmd,
     account_before: &Balance,
-) -> Proof {
+) -> StateDelta {
     let (old_available, old_frozen) = (
         account_before.available.to_amount(),
         account_before.frozen.to_amount(),
@@ -295,19 +489,263 @@ pub fn prove_rejecting_no_reason(
         old_available,
         old_frozen,
     )];
-    let merkle_proof = gen_proofs(merkle_tree, &leaves);
-    Proof {
+    StateDelta {
         event_id,
         user_id: cmd.user_id,
         cmd: (cmd, false).into(),
-        leaves: vec![],
+        leaves,
         maker_page_delta: 0,
         maker_account_delta: 0,
-        merkle_proof,
-        root: merkle_tree.root().clone().into(),
+        omit_leaves: true,
     }
 }
 
+/// the leaves changed by an event, proven on the merkle tree in the order of the events
+#[derive(Clone, Debug)]
+pub struct StateDelta {
+    pub event_id: u64,
+    pub user_id: UserId,
+    pub cmd: FusoCommand,
+    pub leaves: Vec<MerkleLeaf>,
+    pub maker_page_delta: u8,
+    pub maker_account_delta: u8,
+    /// the leaves are proven but left out of the proof, e.g. rejecting without a reason
+    pub omit_leaves: bool,
+}
+
+impl StateDelta {
+    pub fn prove(self, merkle_tree: &mut GlobalStates) -> Proof {
+        self.prove_on(merkle_tree)
+    }
+
+    pub fn prove_on<S: Store<H256>>(self, merkle_tree: &mut MerkleTree<S>) -> Proof {
+        let merkle_proof = gen_proofs(merkle_tree, &self.leaves);
+        Proof {
+            event_id: self.event_id,
+            user_id: self.user_id,
+            cmd: self.cmd,
+            leaves: match self.omit_leaves {
+                true => vec![],
+                false => self.leaves,
+            },
+            maker_page_delta: self.maker_page_delta,
+            maker_account_delta: self.maker_account_delta,
+            merkle_proof,
+            root: merkle_tree.root().clone().into(),
+        }
+    }
+}
+
+enum Task {
+    Prove(StateDelta),
+    /// dumped with the tree once the proofs before are generated
+    Dump(u64, Data),
+    /// `(check_id, event_id, leaves)` checked once the proofs before are generated
+    Check(u64, u64, Vec<(Violation, (H256, H256))>),
+}
+
+/// the tree owned by the prover, either in memory and dumped in the snapshots or persisted in
+/// rocksdb if `persist_tree`, which is applied to `applied` and skips the events until `replayed`
+enum Tree {
+    Memory(CopyOnWrite<GlobalStates>),
+    Persistent {
+        tree: Box<PersistentStates>,
+        applied: u64,
+        replayed: u64,
+    },
+}
+
+impl Tree {
+    fn prove(&mut self, delta: StateDelta) -> Option<Proof> {
+        match self {
+            Tree::Memory(tree) => Some(delta.prove(tree)),
+            Tree::Persistent { replayed, .. } if delta.event_id <= *replayed => None,
+            Tree::Persistent { tree, applied, .. } => {
+                *applied = delta.event_id;
+                Some(delta.prove_on(tree))
+            }
+        }
+    }
+
+    /// write the pending nodes in a batch
+    fn commit(&mut self, force: bool) -> anyhow::Result<()> {
+        match self {
+            Tree::Memory(_) => Ok(()),
+            Tree::Persistent { tree, applied, .. } if force || tree.store().pending() > 0 => {
+                let root = *tree.root();
+                tree.store_mut().commit(&root, *applied)
+            }
+            Tree::Persistent { .. } => Ok(()),
+        }
+    }
+
+    fn is_full(&self) -> bool {
+        match self {
+            Tree::Memory(_) => false,
+            Tree::Persistent { tree, .. } => tree.store().pending() >= C.server.tree_cache,
+        }
+    }
+
+    fn check(
+        &self,
+        event_id: u64,
+        leaves: Vec<(Violation, (H256, H256))>,
+    ) -> Result<RootCheck, String> {
+        match self {
+            Tree::Memory(tree) => Ok(verify::check_root(tree, event_id, leaves)),
+            Tree::Persistent { replayed, .. } if event_id < *replayed => Err(format!(
+                "the persisted tree is ahead of the event {} while replaying",
+                event_id
+            )),
+            Tree::Persistent { tree, .. } => Ok(verify::check_root(tree, event_id, leaves)),
+        }
+    }
+
+    /// the tree is applied to the event `id`, attached to `data` unless persisted
+    fn checkpoint(&mut self, id: u64, data: &mut Data) -> anyhow::Result<()> {
+        match self {
+            Tree::Memory(tree) => {
+                data.merkle_tree = tree.clone();
+                TREE_BYTES.store(
+                    bincode::serialized_size(tree).unwrap_or_default() as usize,
+                    Ordering::Relaxed,
+                );
+                Ok(())
+            }
+            Tree::Persistent { .. } => self.persist(id),
+        }
+    }
+
+    /// the tree is applied to the event `id`, the root is written even if unchanged
+    fn persist(&mut self, id: u64) -> anyhow::Result<()> {
+        if let Tree::Persistent { applied, .. } = self {
+            *applied = id.max(*applied);
+        }
+        self.commit(true)
+    }
+}
+
+/// the merkle tree is moved to a dedicated thread, which proves the deltas sent by the executor
+/// in order and dumps the checkpoints, so the matching never waits for the merkle updates
+pub struct Pipeline {
+    tx: ring::Producer<Task>,
+    handle: JoinHandle<anyhow::Result<Tree>>,
+}
+
+impl Pipeline {
+    /// `event_id` is the snapshot which `merkle_tree` is loaded from
+    pub fn start(
+        merkle_tree: CopyOnWrite<GlobalStates>,
+        event_id: u64,
+        capacity: usize,
+    ) -> anyhow::Result<Self> {
+        let tree = match C.server.persist_tree {
+            true => {
+                let (tree, persisted) = store::restore(
+                    C.server.get_tree_path(),
+                    C.server.tree_cache,
+                    &merkle_tree,
+                    event_id,
+                )?;
+                log::info!("merkle tree restored at {}", persisted);
+                Tree::Persistent {
+                    tree: Box::new(tree),
+                    applied: persisted,
+                    replayed: persisted,
+                }
+            }
+            false => Tree::Memory(merkle_tree),
+        };
+        let (tx, rx) = ring::named("executor-prover", capacity);
+        let handle = std::thread::spawn(move || run(rx, tree));
+        Ok(Self { tx, handle })
+    }
+
+    pub fn prove(&self, delta: StateDelta) -> anyhow::Result<()> {
+        self.tx
+            .send(Task::Prove(delta))
+            .map_err(|_| anyhow::anyhow!("the prover stopped"))
+    }
+
+    /// the state without the tree, e.g. `Data` of the executor
+    pub fn dump(&self, id: u64, data: Data) -> anyhow::Result<()> {
+        self.tx
+            .send(Task::Dump(id, data))
+            .map_err(|_| anyhow::anyhow!("the prover stopped"))
+    }
+
+    pub fn check(
+        &self,
+        check_id: u64,
+        event_id: u64,
+        leaves: Vec<(Violation, (H256, H256))>,
+    ) -> anyhow::Result<()> {
+        self.tx
+            .send(Task::Check(check_id, event_id, leaves))
+            .map_err(|_| anyhow::anyhow!("the prover stopped"))
+    }
+
+    /// wait for the pending deltas proven until the event `id`, return the tree unless persisted
+    pub fn finish(self, id: u64) -> anyhow::Result<CopyOnWrite<GlobalStates>> {
+        drop(self.tx);
+        let mut tree = self
+            .handle
+            .join()
+            .map_err(|_| anyhow::anyhow!("the prover panicked"))??;
+        tree.persist(id)?;
+        match tree {
+            Tree::Memory(tree) => Ok(tree),
+            Tree::Persistent { .. } => Ok(Default::default()),
+        }
+    }
+}
+
+fn run(rx: ring::Consumer<Task>, mut tree: Tree) -> anyhow::Result<Tree> {
+    loop {
+        let task = match rx.try_recv() {
+            Ok(task) => task,
+            Err(TryRecvError::Empty) => {
+                // the nodes are written in a batch once all the deltas received are proven
+                tree.commit(false)?;
+                match rx.recv() {
+                    Ok(task) => task,
+                    Err(_) => break,
+                }
+            }
+            Err(TryRecvError::Disconnected) => break,
+        };
+        match task {
+            Task::Prove(delta) => {
+                if let Some(proof) = tree.prove(delta) {
+                    tracing::debug!(
+                        "predicate root=0x{} after applying {}",
+                        hex::encode(proof.root),
+                        proof.event_id
+                    );
+                    save_proof(proof).inspect_err(|e| log::error!("{}", e))?;
+                }
+                if tree.is_full() {
+                    tree.commit(false)?;
+                }
+            }
+            Task::Dump(id, mut data) => {
+                tree.checkpoint(id, &mut data)?;
+                snapshot::dump(id, &data);
+            }
+            Task::Check(check_id, event_id, leaves) => {
+                verify::publish_root_check(check_id, tree.check(event_id, leaves));
+            }
+        }
+    }
+    tree.commit(false)?;
+    Ok(tree)
+}
+
+/// the serialized size of the tree owned by the pipeline, measured on the checkpoints
+pub fn pipelined_tree_bytes() -> usize {
+    TREE_BYTES.load(Ordering::Relaxed)
+}
+
 pub fn save_proof(proof: Proof) -> anyhow::Result<()> {
     match C.dry_run {
         Some(n) if n >= proof.event_id => {
@@ -319,7 +757,11 @@ pub fn save_proof(proof: Proof) -> anyhow::Result<()> {
         }
         _ => {}
     }
-    PROOF_STORE.put(id_to_key(proof.event_id), proof.encode())?;
+    // the proofs of the replayed events are never submitted
+    if C.dry_run.is_none() {
+        PROOF_STORE.put(id_to_key(proof.event_id), proof.encode())?;
+        audit::commit(proof.event_id, &proof.root);
+    }
     Ok(())
 }
 
@@ -330,6 +772,25 @@ pub fn remove_before(id: u64) -> anyhow::Result<()> {
     Ok(())
 }
 
+/// journaled before sending the extrinsic, so the proofs in flight are known after restarting
+pub fn mark_submitted(id: u64) -> anyhow::Result<()> {
+    let mut opts = rocksdb::WriteOptions::default();
+    opts.set_sync(true);
+    PROOF_STORE.put_opt(SUBMITTED_KEY, id.to_be_bytes(), &opts)?;
+    Ok(())
+}
+
+/// the last proof ever submitted, finalized or not
+pub fn submitted() -> u64 {
+    PROOF_STORE
+        .get(SUBMITTED_KEY)
+        .ok()
+        .flatten()
+        .and_then(|v| v.try_into().ok())
+        .map(u64::from_be_bytes)
+        .unwrap_or_default()
+}
+
 pub fn fetch_raw_ge(id: u64) -> Vec<(u64, RawParameter)> {
     if C.dry_run.is_some() {
         return vec![];
@@ -348,6 +809,46 @@ pub fn fetch_raw_ge(id: u64) -> Vec<(u64, RawParameter)> {
     ret
 }
 
+/// the root is the last field of an encoded proof
+fn root_of(raw: &[u8]) -> Option<[u8; 32]> {
+    raw.len()
+        .checked_sub(32)
+        .and_then(|i| raw[i..].try_into().ok())
+}
+
+/// the latest event whose post-state root equals `root`, seeking from the latest proof back to
+/// at most `limit` proofs
+fn find_root<R: AsRef<[u8]>>(
+    proofs: impl Iterator<Item = (u64, R)>,
+    root: &[u8; 32],
+    limit: usize,
+) -> Option<u64> {
+    proofs
+        .take(limit)
+        .find(|(_, raw)| root_of(raw.as_ref()).as_ref() == Some(root))
+        .map(|(id, _)| id)
+}
+
+pub fn find_by_root(root: &[u8; 32]) -> Option<u64> {
+    let proofs = PROOF_STORE
+        .iterator(IteratorMode::End)
+        .map_while(|item| item.ok())
+        .take_while(|(k, _)| k.starts_with(&id_to_key(0)[..8]))
+        .map(|(k, v)| (key_to_id(&k), v));
+    find_root(proofs, root, MAX_ROOT_SEEK)
+}
+
+/// the post-state root of the event, `None` if the proof is pruned or the event isn't proven
+pub fn root_at(id: u64) -> Option<[u8; 32]> {
+    root_of(&PROOF_STORE.get(id_to_key(id)).ok()??)
+}
+
+/// the latest proof and its root
+pub fn latest_root() -> Option<(u64, [u8; 32])> {
+    let (key, value) = PROOF_STORE.iterator(IteratorMode::End).next()?.ok()?;
+    Some((key_to_id(&key), root_of(&value)?))
+}
+
 fn id_to_key(id: u64) -> [u8; 16] {
     unsafe { std::mem::transmute::<[[u8; 8]; 2], [u8; 16]>([*b"rawproof", id.to_be_bytes()]) }
 }
@@ -358,23 +859,101 @@ fn key_to_id(key: &[u8]) -> u64 {
     u64::from_be_bytes(id)
 }
 
-fn gen_proofs(merkle_tree: &mut GlobalStates, leaves: &Vec<MerkleLeaf>) -> Vec<u8> {
-    let keys = leaves
-        .iter()
-        .map(|leaf| BlakeTwo256::digest(&leaf.key).into())
-        .collect::<Vec<_>>();
+fn gen_proofs<S: Store<H256>>(
+    merkle_tree: &mut MerkleTree<S>,
+    leaves: &Vec<MerkleLeaf>,
+) -> Vec<u8> {
+    let _span = tracing::debug_span!("prove", leaves = leaves.len()).entered();
+    let start = Instant::now();
+    // a leaf may be updated more than once in an event, e.g. the legs of a route
+    let mut keys: Vec<H256> = Vec::with_capacity(leaves.len());
+    for leaf in leaves.iter() {
+        let key = BlakeTwo256::digest(&leaf.key).into();
+        if !keys.contains(&key) {
+            keys.push(key);
+        }
+    }
     // TODO merge origin to support update all
     leaves.iter().for_each(|leaf| {
         merkle_tree
             .update(BlakeTwo256::digest(&leaf.key).into(), leaf.new_v.into())
             .unwrap();
     });
-    merkle_tree
+    let proof = merkle_tree
         .merkle_proof(keys.clone())
         .expect("generate merkle proof failed")
         .compile(keys)
         .expect("compile merkle proof failed")
-        .into()
+        .into();
+    latency::record_since(Stage::Prove, start);
+    proof
+}
+
+/// the fees shared to the brokers, the balances before are derived from the cuts; a positive
+/// `broker_share` requires `broker_share` of the proof extensions
+fn broker_leaves(
+    data: &Data,
+    symbol: Symbol,
+    outputs: &[Output],
+    matches: &Match,
+) -> Vec<MerkleLeaf> {
+    let broker_share = data
+        .orderbooks
+        .get(&symbol)
+        .map(|b| b.broker_share)
+        .unwrap_or_default();
+    if broker_share.is_zero() {
+        return vec![];
+    }
+    let mut cuts = BTreeMap::<(UserId, Currency), Amount>::new();
+    let broker_of = |o: &Output| match o.role {
+        Role::Taker => matches.taker.broker,
+        Role::Maker => matches
+            .maker
+            .iter()
+            .find(|m| m.user_id == o.user_id && m.order_id == o.order_id)
+            .and_then(|m| m.broker),
+    };
+    // the rebates of the makers are paid out of the taker fee before the cut
+    let rebates = |charge: fn(&Output) -> Amount| {
+        outputs
+            .iter()
+            .filter(|o| o.role == Role::Maker)
+            .map(|o| charge(o).max(Amount::zero()))
+            .sum::<Amount>()
+    };
+    let rebates = (rebates(|o| o.base_charge), rebates(|o| o.quote_charge));
+    for (o, broker) in outputs.iter().filter_map(|o| broker_of(o).map(|b| (o, b))) {
+        for (currency, charge, rebated) in [
+            (symbol.0, o.base_charge, rebates.0),
+            (symbol.1, o.quote_charge, rebates.1),
+        ] {
+            let fee = match o.role {
+                Role::Taker => -charge - rebated,
+                Role::Maker => -charge,
+            };
+            if fee <= Amount::zero() {
+                continue;
+            }
+            let cut = clearing::broker_cut(fee, broker_share);
+            if !cut.is_zero() {
+                *cuts.entry((broker_account(&broker), currency)).or_default() += cut;
+            }
+        }
+    }
+    cuts.into_iter()
+        .map(|((account, currency), cut)| {
+            let balance = crate::assets::get_balance_to_owned(&data.accounts, &account, currency);
+            new_account_merkle_leaf(
+                &account,
+                currency,
+                (balance.available - cut).to_amount(),
+                balance.frozen.to_amount(),
+                balance.available.to_amount(),
+                balance.frozen.to_amount(),
+            )
+        })
+        .collect()
 }
 
 fn new_account_merkle_leaf(
@@ -446,6 +1025,51 @@ fn new_orderpage_merkle_leaf(
     }
 }
 
+fn to_state(leaf: MerkleLeaf) -> (H256, H256) {
+    (BlakeTwo256::digest(&leaf.key).into(), leaf.new_v.into())
+}
+
+/// the `(key, value)` expected in the merkle tree of the balance
+pub fn account_state(user_id: &UserId, currency: Currency, balance: &Balance) -> (H256, H256) {
+    let (available, frozen) = (balance.available.to_amount(), balance.frozen.to_amount());
+    to_state(new_account_merkle_leaf(
+        user_id, currency, available, frozen, available, frozen,
+    ))
+}
+
+/// the `(key, value)`s expected in the merkle tree of the size, best prices and pages
+pub fn orderbook_states(symbol: Symbol, orderbook: &OrderBook) -> Vec<(H256, H256)> {
+    let (ask_size, bid_size) = orderbook.size();
+    let (ask_size, bid_size) = (ask_size.to_amount(), bid_size.to_amount());
+    let (best_ask, best_bid) = orderbook.get_size_of_best();
+    let (best_ask, best_bid) = (
+        best_ask.map(|a| a.0).unwrap_or(Amount::zero()).to_amount(),
+        best_bid.map(|b| b.0).unwrap_or(Amount::zero()).to_amount(),
+    );
+    let mut states = vec![
+        to_state(new_orderbook_merkle_leaf(
+            symbol, ask_size, bid_size, ask_size, bid_size,
+        )),
+        to_state(new_bestprice_merkle_leaf(
+            symbol, best_ask, best_bid, best_ask, best_bid,
+        )),
+    ];
+    orderbook
+        .asks
+        .iter()
+        .chain(orderbook.bids.iter())
+        .for_each(|(price, page)| {
+            let size = page.amount.to_amount();
+            states.push(to_state(new_orderpage_merkle_leaf(
+                symbol,
+                price.to_amount(),
+                size,
+                size,
+            )));
+        });
+    states
+}
+
 #[cfg(test)]
 mod test {
     use super::BlakeTwo256;
@@ -453,15 +1077,6 @@ mod test {
     use rust_decimal_macros::dec;
     use smt::{blake2b::Blake2bHasher, CompiledMerkleProof, H256};
 
-    impl UserId {
-        // adapt to legacy code
-        pub fn from_low_u64_be(x: u64) -> Self {
-            let mut s = [0u8; 32];
-            s[24..].copy_from_slice(&x.to_be_bytes());
-            Self::new(s)
-        }
-    }
-
     impl MerkleLeaf {
         const ACCOUNT_KEY: u8 = 0x00;
         const BESTPRICE_KEY: u8 = 0x02;
@@ -533,7 +1148,7 @@ mod test {
         }
     }
 
-    use crate::{assets, clearing, core::*, fusotao::*, matcher, orderbook::*};
+    use crate::{assets, clearing, core::*, fusotao::*, matcher};
 
     fn split_h256(v: &[u8; 32]) -> ([u8; 16], [u8; 16]) {
         (v[..16].try_into().unwrap(), v[16..].try_into().unwrap())
@@ -582,17 +1197,13 @@ mod test {
             amount: dec!(1.11111),
             block_number: 1,
             extrinsic_hash: vec![0],
+            timestamp: 0,
         };
         let after =
             assets::add_to_available(&mut all, &cmd0.user_id, cmd0.currency, cmd0.amount).unwrap();
         let cmd1 = cmd0.clone();
-        let proof = prover::prove_assets_cmd(
-            &mut merkle_tree,
-            1,
-            cmd0,
-            &assets::Balance::default(),
-            &after,
-        );
+        let proof = prover::prove_assets_cmd(1, cmd0, &assets::Balance::default(), &after)
+            .prove(&mut merkle_tree);
 
         let mp = CompiledMerkleProof(proof.merkle_proof.clone());
         let old = proof
@@ -617,7 +1228,8 @@ mod test {
         let new_root = proof.root.clone();
         let transfer_again =
             assets::add_to_available(&mut all, &cmd1.user_id, cmd1.currency, cmd1.amount).unwrap();
-        let proof = prover::prove_assets_cmd(&mut merkle_tree, 1, cmd1, &after, &transfer_again);
+        let proof =
+            prover::prove_assets_cmd(1, cmd1, &after, &transfer_again).prove(&mut merkle_tree);
         let mp = CompiledMerkleProof(proof.merkle_proof.clone());
         let old = proof
             .leaves
@@ -643,6 +1255,101 @@ mod test {
         );
     }
 
+    #[test]
+    pub fn test_prove_batch() {
+        let mut merkle_tree = GlobalStates::default();
+        let mut all = Accounts::new();
+        let cmd = AssetsCmd {
+            user_id: UserId::from_low_u64_be(1),
+            in_or_out: InOrOut::In,
+            currency: 1,
+            amount: dec!(1.11111),
+            block_number: 1,
+            extrinsic_hash: vec![0],
+            timestamp: 0,
+        };
+        let first =
+            assets::add_to_available(&mut all, &cmd.user_id, cmd.currency, cmd.amount).unwrap();
+        let second =
+            assets::add_to_available(&mut all, &cmd.user_id, cmd.currency, cmd.amount).unwrap();
+        let single = prover::prove_assets_cmd(1, cmd.clone(), &assets::Balance::default(), &first);
+        assert_eq!(single.cmd, prover::prove_batch(vec![single.clone()]).cmd);
+        let delta = prover::prove_batch(vec![
+            single,
+            prover::prove_assets_cmd(1, cmd, &first, &second),
+        ]);
+        assert!(matches!(&delta.cmd, FusoCommand::Batch { before, .. }
+            if before.len() == 1 && before[0].leaves.0 == 1));
+        let proof = delta.prove(&mut merkle_tree);
+        assert_eq!(2, proof.leaves.len());
+        assert_eq!(
+            split_h256_u128(&proof.leaves[1].old_v),
+            (1111110000000000000, 0)
+        );
+        assert_eq!(
+            split_h256_u128(&proof.leaves[1].new_v),
+            (2222220000000000000, 0)
+        );
+    }
+
+    #[test]
+    pub fn test_sub_transfer() {
+        let mut merkle_tree = GlobalStates::default();
+        let mut all = Accounts::new();
+        let alice =
+            UserId::from_str("0xd43593c715fdd31c61141abd04a99fd6822c8558854ccde39a5684e7a56da27d")
+                .unwrap();
+        let ferdie =
+            UserId::from_str("0x1cbd2d43530a44705ad088af313e18f80b53ef16b36177cd4b77b846f2a5f07c")
+                .unwrap();
+        let deposit = assets::add_to_available(&mut all, &alice, 1, dec!(10)).unwrap();
+        let cmd = SubTransferCmd {
+            user_id: alice,
+            bot: ferdie,
+            token: 1,
+            currency: 1,
+            amount: dec!(4),
+            to_sub: true,
+            nonce: 1,
+            signature: vec![],
+        };
+        // the same as the sidecar derives
+        let sub = cmd.sub_account();
+        assert_eq!(
+            UserId::from_str("0x768cff70bf523090fa1d09494cda1d4686361d1bc99129db3d67fe8b57649b7f")
+                .unwrap(),
+            sub
+        );
+        assert_eq!((alice, sub), cmd.parties());
+        let from = assets::deduct_available(&mut all, &alice, 1, cmd.amount).unwrap();
+        let to = assets::add_to_available(&mut all, &sub, 1, cmd.amount).unwrap();
+        let proof = prover::prove_sub_transfer(
+            2,
+            cmd,
+            (&deposit, &from),
+            (&assets::Balance::default(), &to),
+        )
+        .prove(&mut merkle_tree);
+        assert_eq!(2, proof.leaves.len());
+        let mp = CompiledMerkleProof(proof.merkle_proof.clone());
+        let new = proof
+            .leaves
+            .iter()
+            .map(|v| (BlakeTwo256::digest(&v.key).into(), v.new_v.into()))
+            .collect::<Vec<_>>();
+        let r = mp.verify::<Blake2bHasher>(&proof.root.into(), new).unwrap();
+        assert!(r);
+        assert_eq!(
+            split_h256_u128(&proof.leaves[0].new_v),
+            (6000000000000000000, 0)
+        );
+        assert_eq!(
+            split_h256_u128(&proof.leaves[1].new_v),
+            (4000000000000000000, 0)
+        );
+        assert_eq!(split_h256_u128(&proof.leaves[1].old_v), (0, 0));
+    }
+
     #[test]
     pub fn test_trade() {
         let merkle_tree = GlobalStates::default();
@@ -650,14 +1357,22 @@ mod test {
         let orderbook = construct_pair();
         let mut orderbooks = std::collections::HashMap::new();
         let (mf, tf) = (orderbook.maker_fee, orderbook.taker_fee);
-        orderbooks.insert((1, 0), orderbook);
+        orderbooks.insert((1, 0), orderbook.into());
         let mut data = Data {
             orderbooks,
-            accounts: all,
-            merkle_tree,
+            accounts: all.into(),
+            merkle_tree: merkle_tree.into(),
             current_event_id: 0,
             tvl: Amount::zero(),
             orders: Default::default(),
+            last_prices: Default::default(),
+            currencies: Default::default(),
+            volumes: Default::default(),
+            links: Default::default(),
+            breakers: Default::default(),
+            client_orders: Default::default(),
+            fee_tiers: Default::default(),
+            frozen_limits: Default::default(),
         };
         let cmd0 = AssetsCmd {
             user_id: UserId::from_low_u64_be(1),
@@ -666,6 +1381,7 @@ mod test {
             amount: dec!(1.11111),
             block_number: 1,
             extrinsic_hash: vec![0],
+            timestamp: 0,
         };
         let after = assets::add_to_available(
             &mut data.accounts,
@@ -674,13 +1390,8 @@ mod test {
             cmd0.amount,
         )
         .unwrap();
-        prover::prove_assets_cmd(
-            &mut data.merkle_tree,
-            1,
-            cmd0,
-            &assets::Balance::default(),
-            &after,
-        );
+        prover::prove_assets_cmd(1, cmd0, &assets::Balance::default(), &after)
+            .prove(&mut data.merkle_tree);
 
         let cmd1 = AssetsCmd {
             user_id: UserId::from_low_u64_be(2),
@@ -689,6 +1400,7 @@ mod test {
             amount: dec!(99.99),
             block_number: 1,
             extrinsic_hash: vec![0],
+            timestamp: 0,
         };
         let transfer_again = assets::add_to_available(
             &mut data.accounts,
@@ -698,7 +1410,7 @@ mod test {
         )
         .unwrap();
 
-        prover::prove_assets_cmd(&mut data.merkle_tree, 1, cmd1, &after, &transfer_again);
+        prover::prove_assets_cmd(1, cmd1, &after, &transfer_again).prove(&mut data.merkle_tree);
 
         let size = data.orderbooks.get(&(1, 0)).unwrap().size();
         let cmd2 = LimitCmd {
@@ -710,6 +1422,12 @@ mod test {
             nonce: 1,
             signature: vec![0],
             broker: None,
+            time_in_force: Default::default(),
+            self_trade_prevention: None,
+            vol: None,
+            display: None,
+            oco: None,
+            client_order_id: None,
         };
         let (best_ask_before, best_bid_before) =
             data.orderbooks.get(&(1, 0)).unwrap().get_size_of_best();
@@ -726,9 +1444,18 @@ mod test {
             cmd2.amount,
             cmd2.ask_or_bid,
         );
-        let cr = clearing::clear(&mut data.accounts, 3, &(1, 0), tf, mf, &mr, 0);
+        let cr = clearing::clear(
+            &mut data.accounts,
+            3,
+            &(1, 0),
+            tf,
+            mf,
+            Decimal::zero(),
+            &mr,
+            0,
+        );
         let proof = prover::prove_trade_cmd(
-            &mut data,
+            &data,
             cmd2.nonce,
             cmd2.signature.clone(),
             (cmd2, mf, tf).into(),
@@ -740,7 +1467,8 @@ mod test {
             &taker_quote_before,
             &cr,
             &mr,
-        );
+        )
+        .prove(&mut data.merkle_tree);
 
         // ask 0.11, 100
         {
@@ -787,6 +1515,12 @@ mod test {
             nonce: 1,
             signature: vec![0],
             broker: None,
+            time_in_force: Default::default(),
+            self_trade_prevention: None,
+            vol: None,
+            display: None,
+            oco: None,
+            client_order_id: None,
         };
         let (best_ask_before, best_bid_before) =
             data.orderbooks.get(&(1, 0)).unwrap().get_size_of_best();
@@ -803,9 +1537,18 @@ mod test {
             cmd2.amount,
             cmd2.ask_or_bid,
         );
-        let cr = clearing::clear(&mut data.accounts, 5, &(1, 0), tf, mf, &mr, 0);
+        let cr = clearing::clear(
+            &mut data.accounts,
+            5,
+            &(1, 0),
+            tf,
+            mf,
+            Decimal::zero(),
+            &mr,
+            0,
+        );
         let proof = prover::prove_trade_cmd(
-            &mut data,
+            &data,
             cmd2.nonce,
             cmd2.signature.clone(),
             (cmd2, mf, tf).into(),
@@ -817,7 +1560,8 @@ mod test {
             &taker_quote_before,
             &cr,
             &mr,
-        );
+        )
+        .prove(&mut data.merkle_tree);
         // bid 0.01, 90
         {
             // ask,bid
@@ -853,6 +1597,12 @@ mod test {
             nonce: 1,
             signature: vec![0],
             broker: None,
+            time_in_force: Default::default(),
+            self_trade_prevention: None,
+            vol: None,
+            display: None,
+            oco: None,
+            client_order_id: None,
         };
         let (best_ask_before, best_bid_before) =
             data.orderbooks.get(&(1, 0)).unwrap().get_size_of_best();
@@ -869,9 +1619,18 @@ mod test {
             cmd2.amount,
             cmd2.ask_or_bid,
         );
-        let cr = clearing::clear(&mut data.accounts, 6, &(1, 0), tf, mf, &mr, 0);
+        let cr = clearing::clear(
+            &mut data.accounts,
+            6,
+            &(1, 0),
+            tf,
+            mf,
+            Decimal::zero(),
+            &mr,
+            0,
+        );
         let proof = prover::prove_trade_cmd(
-            &mut data,
+            &data,
             cmd2.nonce,
             cmd2.signature.clone(),
             (cmd2, mf, tf).into(),
@@ -883,7 +1642,8 @@ mod test {
             &taker_quote_before,
             &cr,
             &mr,
-        );
+        )
+        .prove(&mut data.merkle_tree);
         // ask 0.11, 100
         {
             // ask,bid
@@ -919,6 +1679,12 @@ mod test {
             nonce: 1,
             signature: vec![0],
             broker: None,
+            time_in_force: Default::default(),
+            self_trade_prevention: None,
+            vol: None,
+            display: None,
+            oco: None,
+            client_order_id: None,
         };
         let (best_ask_before, best_bid_before) =
             data.orderbooks.get(&(1, 0)).unwrap().get_size_of_best();
@@ -935,9 +1701,18 @@ mod test {
             cmd2.amount,
             cmd2.ask_or_bid,
         );
-        let cr = clearing::clear(&mut data.accounts, 7, &(1, 0), tf, mf, &mr, 0);
+        let cr = clearing::clear(
+            &mut data.accounts,
+            7,
+            &(1, 0),
+            tf,
+            mf,
+            Decimal::zero(),
+            &mr,
+            0,
+        );
         let proof = prover::prove_trade_cmd(
-            &mut data,
+            &data,
             cmd2.nonce,
             cmd2.signature.clone(),
             (cmd2, mf, tf).into(),
@@ -949,7 +1724,8 @@ mod test {
             &taker_quote_before,
             &cr,
             &mr,
-        );
+        )
+        .prove(&mut data.merkle_tree);
         println!("{:?}", cr.last().unwrap());
         // bid 0.5, 110
         {
@@ -1002,6 +1778,12 @@ mod test {
             nonce: 1,
             signature: vec![0],
             broker: None,
+            time_in_force: Default::default(),
+            self_trade_prevention: None,
+            vol: None,
+            display: None,
+            oco: None,
+            client_order_id: None,
         };
         let (best_ask_before, best_bid_before) =
             data.orderbooks.get(&(1, 0)).unwrap().get_size_of_best();
@@ -1018,9 +1800,18 @@ mod test {
             cmd2.amount,
             cmd2.ask_or_bid,
         );
-        let cr = clearing::clear(&mut data.accounts, 8, &(1, 0), tf, mf, &mr, 0);
+        let cr = clearing::clear(
+            &mut data.accounts,
+            8,
+            &(1, 0),
+            tf,
+            mf,
+            Decimal::zero(),
+            &mr,
+            0,
+        );
         let proof = prover::prove_trade_cmd(
-            &mut data,
+            &data,
             cmd2.nonce,
             cmd2.signature.clone(),
             (cmd2, mf, tf).into(),
@@ -1032,7 +1823,8 @@ mod test {
             &taker_quote_before,
             &cr,
             &mr,
-        );
+        )
+        .prove(&mut data.merkle_tree);
         // ask 0.3, 88
         {
             // ask,bid
@@ -1070,6 +1862,116 @@ mod test {
         }
     }
 
+    #[test]
+    pub fn test_maker_rebate() {
+        let mut orderbook = construct_pair();
+        orderbook.maker_fee = dec!(-0.0005);
+        let (mf, tf) = (orderbook.maker_fee, orderbook.taker_fee);
+        let mut data = Data {
+            orderbooks: std::collections::HashMap::from([((1, 0), orderbook.into())]),
+            accounts: Accounts::new().into(),
+            merkle_tree: GlobalStates::default().into(),
+            current_event_id: 0,
+            tvl: Amount::zero(),
+            orders: Default::default(),
+            last_prices: Default::default(),
+            currencies: Default::default(),
+            volumes: Default::default(),
+            links: Default::default(),
+            breakers: Default::default(),
+            client_orders: Default::default(),
+            fee_tiers: Default::default(),
+            frozen_limits: Default::default(),
+        };
+        let (maker, taker) = (UserId::from_low_u64_be(1), UserId::from_low_u64_be(2));
+        assets::add_to_available(&mut data.accounts, &maker, 1, dec!(2)).unwrap();
+        assets::try_freeze(&mut data.accounts, &maker, 1, dec!(2)).unwrap();
+        assets::add_to_available(&mut data.accounts, &taker, 0, dec!(200)).unwrap();
+        matcher::execute_limit(
+            data.orderbooks.get_mut(&(1, 0)).unwrap(),
+            maker,
+            dec!(100),
+            dec!(2),
+            AskOrBid::Ask,
+        );
+        let size = data.orderbooks.get(&(1, 0)).unwrap().size();
+        let (best_ask_before, best_bid_before) =
+            data.orderbooks.get(&(1, 0)).unwrap().get_size_of_best();
+        let before = |user_id, currency| {
+            let b = assets::get_balance_to_owned(&data.accounts, &user_id, currency);
+            u128le_to_h256(b.available.to_amount(), b.frozen.to_amount())
+        };
+        let expected = [(maker, 1), (maker, 0), (taker, 1), (taker, 0)].map(|(u, c)| before(u, c));
+        let taker_base_before = assets::get_balance_to_owned(&data.accounts, &taker, 1);
+        let taker_quote_before = assets::get_balance_to_owned(&data.accounts, &taker, 0);
+        assets::try_freeze(&mut data.accounts, &taker, 0, dec!(100)).unwrap();
+        let cmd = LimitCmd {
+            symbol: (1, 0),
+            user_id: taker,
+            price: dec!(100),
+            amount: dec!(1),
+            ask_or_bid: AskOrBid::Bid,
+            nonce: 1,
+            signature: vec![0],
+            broker: None,
+            time_in_force: Default::default(),
+            self_trade_prevention: None,
+            vol: None,
+            display: None,
+            oco: None,
+            client_order_id: None,
+        };
+        let mr = matcher::execute_limit(
+            data.orderbooks.get_mut(&(1, 0)).unwrap(),
+            taker,
+            dec!(100),
+            dec!(1),
+            AskOrBid::Bid,
+        );
+        let cr = clearing::clear(
+            &mut data.accounts,
+            1,
+            &(1, 0),
+            tf,
+            mf,
+            Decimal::zero(),
+            &mr,
+            0,
+        );
+        // the maker is rebated in the base charged from the taker
+        assert_eq!(dec!(0.0005), cr[0].base_charge);
+        let proof = prover::prove_trade_cmd(
+            &data,
+            cmd.nonce,
+            cmd.signature.clone(),
+            (cmd, mf, tf).into(),
+            size.0,
+            size.1,
+            best_ask_before.unwrap_or((Decimal::zero(), Decimal::zero())),
+            best_bid_before.unwrap_or((Decimal::zero(), Decimal::zero())),
+            &taker_base_before,
+            &taker_quote_before,
+            &cr,
+            &mr,
+        )
+        .prove(&mut data.merkle_tree);
+        assert_eq!(
+            expected.to_vec(),
+            proof.leaves[1..5]
+                .iter()
+                .map(|l| l.old_v)
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(
+            split_h256_u128(&proof.leaves[1].new_v),
+            (500000000000000, 1000000000000000000)
+        );
+        assert!(matches!(
+            proof.cmd,
+            FusoCommand::BidLimit { maker_fee, .. } if maker_fee.0 == 0
+        ));
+    }
+
     #[test]
     pub fn test_price() {
         let mut merkle_tree = GlobalStates::default();
@@ -1082,16 +1984,12 @@ mod test {
             amount: dec!(100),
             block_number: 1,
             extrinsic_hash: vec![0],
+            timestamp: 0,
         };
         let after =
             assets::add_to_available(&mut all, &cmd0.user_id, cmd0.currency, cmd0.amount).unwrap();
-        prover::prove_assets_cmd(
-            &mut merkle_tree,
-            1,
-            cmd0,
-            &assets::Balance::default(),
-            &after,
-        );
+        prover::prove_assets_cmd(1, cmd0, &assets::Balance::default(), &after)
+            .prove(&mut merkle_tree);
         let cmd1 = AssetsCmd {
             user_id: UserId::from_low_u64_be(2),
             in_or_out: InOrOut::In,
@@ -1099,21 +1997,30 @@ mod test {
             amount: dec!(1000),
             block_number: 1,
             extrinsic_hash: vec![0],
+            timestamp: 0,
         };
         let transfer_again =
             assets::add_to_available(&mut all, &cmd1.user_id, cmd1.currency, cmd1.amount).unwrap();
-        prover::prove_assets_cmd(&mut merkle_tree, 1, cmd1, &after, &transfer_again);
+        prover::prove_assets_cmd(1, cmd1, &after, &transfer_again).prove(&mut merkle_tree);
 
         let mut orderbooks = std::collections::HashMap::new();
         let (mf, tf) = (orderbook.maker_fee, orderbook.taker_fee);
-        orderbooks.insert((0, 1), orderbook);
+        orderbooks.insert((0, 1), orderbook.into());
         let mut data = Data {
             orderbooks,
-            accounts: all,
-            merkle_tree,
+            accounts: all.into(),
+            merkle_tree: merkle_tree.into(),
             current_event_id: 0,
             tvl: Amount::zero(),
             orders: Default::default(),
+            last_prices: Default::default(),
+            currencies: Default::default(),
+            volumes: Default::default(),
+            links: Default::default(),
+            breakers: Default::default(),
+            client_orders: Default::default(),
+            fee_tiers: Default::default(),
+            frozen_limits: Default::default(),
         };
 
         // alice ask p=10, a=0.5
@@ -1128,6 +2035,12 @@ mod test {
                 nonce: 1,
                 signature: vec![0],
                 broker: None,
+                time_in_force: Default::default(),
+                self_trade_prevention: None,
+                vol: None,
+                display: None,
+                oco: None,
+                client_order_id: None,
             };
             let (best_ask_before, best_bid_before) =
                 data.orderbooks.get(&(0, 1)).unwrap().get_size_of_best();
@@ -1145,9 +2058,18 @@ mod test {
                 cmd2.amount,
                 cmd2.ask_or_bid,
             );
-            let cr = clearing::clear(&mut data.accounts, 3, &(0, 1), tf, mf, &mr, 0);
+            let cr = clearing::clear(
+                &mut data.accounts,
+                3,
+                &(0, 1),
+                tf,
+                mf,
+                Decimal::zero(),
+                &mr,
+                0,
+            );
             prover::prove_trade_cmd(
-                &mut data,
+                &data,
                 cmd2.nonce,
                 cmd2.signature.clone(),
                 (cmd2, mf, tf).into(),
@@ -1159,7 +2081,8 @@ mod test {
                 &taker_quote_before,
                 &cr,
                 &mr,
-            );
+            )
+            .prove(&mut data.merkle_tree);
         }
         // alice ask p=10, a=0.6
         {
@@ -1173,6 +2096,12 @@ mod test {
                 nonce: 1,
                 signature: vec![0],
                 broker: None,
+                time_in_force: Default::default(),
+                self_trade_prevention: None,
+                vol: None,
+                display: None,
+                oco: None,
+                client_order_id: None,
             };
             let (best_ask_before, best_bid_before) =
                 data.orderbooks.get(&(0, 1)).unwrap().get_size_of_best();
@@ -1190,9 +2119,18 @@ mod test {
                 cmd2.amount,
                 cmd2.ask_or_bid,
             );
-            let cr = clearing::clear(&mut data.accounts, 4, &(0, 1), tf, mf, &mr, 0);
+            let cr = clearing::clear(
+                &mut data.accounts,
+                4,
+                &(0, 1),
+                tf,
+                mf,
+                Decimal::zero(),
+                &mr,
+                0,
+            );
             prover::prove_trade_cmd(
-                &mut data,
+                &data,
                 cmd2.nonce,
                 cmd2.signature.clone(),
                 (cmd2, mf, tf).into(),
@@ -1204,7 +2142,8 @@ mod test {
                 &taker_quote_before,
                 &cr,
                 &mr,
-            );
+            )
+            .prove(&mut data.merkle_tree);
         }
         // alice ask p=10, a=0.6
         {
@@ -1218,6 +2157,12 @@ mod test {
                 nonce: 1,
                 signature: vec![0],
                 broker: None,
+                time_in_force: Default::default(),
+                self_trade_prevention: None,
+                vol: None,
+                display: None,
+                oco: None,
+                client_order_id: None,
             };
             let (best_ask_before, best_bid_before) =
                 data.orderbooks.get(&(0, 1)).unwrap().get_size_of_best();
@@ -1235,9 +2180,18 @@ mod test {
                 cmd2.amount,
                 cmd2.ask_or_bid,
             );
-            let cr = clearing::clear(&mut data.accounts, 5, &(0, 1), tf, mf, &mr, 0);
+            let cr = clearing::clear(
+                &mut data.accounts,
+                5,
+                &(0, 1),
+                tf,
+                mf,
+                Decimal::zero(),
+                &mr,
+                0,
+            );
             prover::prove_trade_cmd(
-                &mut data,
+                &data,
                 cmd2.nonce,
                 cmd2.signature.clone(),
                 (cmd2, mf, tf).into(),
@@ -1249,7 +2203,8 @@ mod test {
                 &taker_quote_before,
                 &cr,
                 &mr,
-            );
+            )
+            .prove(&mut data.merkle_tree);
         }
         // bob p=9.9, a=0.5
         {
@@ -1263,6 +2218,12 @@ mod test {
                 nonce: 1,
                 signature: vec![0],
                 broker: None,
+                time_in_force: Default::default(),
+                self_trade_prevention: None,
+                vol: None,
+                display: None,
+                oco: None,
+                client_order_id: None,
             };
             let (best_ask_before, best_bid_before) =
                 data.orderbooks.get(&(0, 1)).unwrap().get_size_of_best();
@@ -1280,9 +2241,18 @@ mod test {
                 cmd2.amount,
                 cmd2.ask_or_bid,
             );
-            let cr = clearing::clear(&mut data.accounts, 6, &(0, 1), tf, mf, &mr, 0);
+            let cr = clearing::clear(
+                &mut data.accounts,
+                6,
+                &(0, 1),
+                tf,
+                mf,
+                Decimal::zero(),
+                &mr,
+                0,
+            );
             let proof = prover::prove_trade_cmd(
-                &mut data,
+                &data,
                 cmd2.nonce,
                 cmd2.signature.clone(),
                 (cmd2, mf, tf).into(),
@@ -1294,7 +2264,8 @@ mod test {
                 &taker_quote_before,
                 &cr,
                 &mr,
-            );
+            )
+            .prove(&mut data.merkle_tree);
             // bid p=9.9, a=0.5
             {
                 assert_eq!(proof.leaves.len(), 7);
@@ -1421,16 +2392,12 @@ mod test {
             amount: dec!(100),
             block_number: 1,
             extrinsic_hash: vec![0],
+            timestamp: 0,
         };
         let after =
             assets::add_to_available(&mut all, &cmd0.user_id, cmd0.currency, cmd0.amount).unwrap();
-        prover::prove_assets_cmd(
-            &mut merkle_tree,
-            1,
-            cmd0,
-            &assets::Balance::default(),
-            &after,
-        );
+        prover::prove_assets_cmd(1, cmd0, &assets::Balance::default(), &after)
+            .prove(&mut merkle_tree);
         let cmd1 = AssetsCmd {
             user_id: UserId::from_low_u64_be(2),
             in_or_out: InOrOut::In,
@@ -1438,21 +2405,30 @@ mod test {
             amount: dec!(1000),
             block_number: 1,
             extrinsic_hash: vec![0],
+            timestamp: 0,
         };
         let transfer_again =
             assets::add_to_available(&mut all, &cmd1.user_id, cmd1.currency, cmd1.amount).unwrap();
-        prover::prove_assets_cmd(&mut merkle_tree, 1, cmd1, &after, &transfer_again);
+        prover::prove_assets_cmd(1, cmd1, &after, &transfer_again).prove(&mut merkle_tree);
 
         let mut orderbooks = std::collections::HashMap::new();
         let (mf, tf) = (orderbook.maker_fee, orderbook.taker_fee);
-        orderbooks.insert((0, 1), orderbook);
+        orderbooks.insert((0, 1), orderbook.into());
         let mut data = Data {
             orderbooks,
-            accounts: all,
-            merkle_tree,
+            accounts: all.into(),
+            merkle_tree: merkle_tree.into(),
             current_event_id: 0,
             tvl: Amount::zero(),
             orders: Default::default(),
+            last_prices: Default::default(),
+            currencies: Default::default(),
+            volumes: Default::default(),
+            links: Default::default(),
+            breakers: Default::default(),
+            client_orders: Default::default(),
+            fee_tiers: Default::default(),
+            frozen_limits: Default::default(),
         };
 
         // alice ask p=10, a=1.1
@@ -1467,6 +2443,12 @@ mod test {
                 nonce: 1,
                 signature: vec![0],
                 broker: None,
+                time_in_force: Default::default(),
+                self_trade_prevention: None,
+                vol: None,
+                display: None,
+                oco: None,
+                client_order_id: None,
             };
             let (best_ask_before, best_bid_before) =
                 data.orderbooks.get(&(0, 1)).unwrap().get_size_of_best();
@@ -1484,9 +2466,18 @@ mod test {
                 cmd2.amount,
                 cmd2.ask_or_bid,
             );
-            let cr = clearing::clear(&mut data.accounts, 3, &(0, 1), tf, mf, &mr, 0);
+            let cr = clearing::clear(
+                &mut data.accounts,
+                3,
+                &(0, 1),
+                tf,
+                mf,
+                Decimal::zero(),
+                &mr,
+                0,
+            );
             prover::prove_trade_cmd(
-                &mut data,
+                &data,
                 cmd2.nonce,
                 cmd2.signature.clone(),
                 (cmd2, mf, tf).into(),
@@ -1498,7 +2489,8 @@ mod test {
                 &taker_quote_before,
                 &cr,
                 &mr,
-            );
+            )
+            .prove(&mut data.merkle_tree);
         }
         // alice ask p=5, a=7.6
         {
@@ -1512,6 +2504,12 @@ mod test {
                 nonce: 1,
                 signature: vec![0],
                 broker: None,
+                time_in_force: Default::default(),
+                self_trade_prevention: None,
+                vol: None,
+                display: None,
+                oco: None,
+                client_order_id: None,
             };
             let (best_ask_before, best_bid_before) =
                 data.orderbooks.get(&(0, 1)).unwrap().get_size_of_best();
@@ -1529,9 +2527,18 @@ mod test {
                 cmd2.amount,
                 cmd2.ask_or_bid,
             );
-            let cr = clearing::clear(&mut data.accounts, 4, &(0, 1), tf, mf, &mr, 0);
+            let cr = clearing::clear(
+                &mut data.accounts,
+                4,
+                &(0, 1),
+                tf,
+                mf,
+                Decimal::zero(),
+                &mr,
+                0,
+            );
             prover::prove_trade_cmd(
-                &mut data,
+                &data,
                 cmd2.nonce,
                 cmd2.signature.clone(),
                 (cmd2, mf, tf).into(),
@@ -1543,7 +2550,8 @@ mod test {
                 &taker_quote_before,
                 &cr,
                 &mr,
-            );
+            )
+            .prove(&mut data.merkle_tree);
         }
         // alice cancel 2
         {
@@ -1563,9 +2571,18 @@ mod test {
                 assets::get_balance_to_owned(&data.accounts, &cmd2.user_id, cmd2.symbol.1);
             let mr =
                 matcher::cancel(data.orderbooks.get_mut(&(0, 1)).unwrap(), cmd2.order_id).unwrap();
-            let cr = clearing::clear(&mut data.accounts, 5, &(0, 1), tf, mf, &mr, 0);
+            let cr = clearing::clear(
+                &mut data.accounts,
+                5,
+                &(0, 1),
+                tf,
+                mf,
+                Decimal::zero(),
+                &mr,
+                0,
+            );
             let proof = prover::prove_trade_cmd(
-                &mut data,
+                &data,
                 cmd2.nonce,
                 cmd2.signature.clone(),
                 cmd2.into(),
@@ -1577,7 +2594,8 @@ mod test {
                 &taker_quote_before,
                 &cr,
                 &mr,
-            );
+            )
+            .prove(&mut data.merkle_tree);
             // cancel p=5, a=7.6
             {
                 let (base, quote) = (0, 1);
@@ -2046,4 +3064,35 @@ mod test {
             assert!(bid_delta == before_cancel - after_cancel,);
         }
     }
+
+    #[test]
+    pub fn test_find_root() {
+        use super::{find_root, root_of};
+        use crate::{core::*, fusotao::*};
+        let proof = Proof {
+            event_id: 1,
+            user_id: UserId::from_low_u64_be(1),
+            cmd: FusoCommand::RejectTransferIn,
+            leaves: vec![],
+            maker_page_delta: 0,
+            maker_account_delta: 0,
+            merkle_proof: vec![1, 2, 3],
+            root: [7u8; 32],
+        };
+        assert_eq!(Some([7u8; 32]), root_of(&proof.encode()));
+        assert_eq!(None, root_of(&[0u8; 31]));
+        let raws = vec![
+            (1u64, [[0u8; 8].to_vec(), [1u8; 32].to_vec()].concat()),
+            (2u64, [[0u8; 8].to_vec(), [2u8; 32].to_vec()].concat()),
+            // rejected events don't change the root
+            (3u64, [[0u8; 8].to_vec(), [2u8; 32].to_vec()].concat()),
+            (4u64, [[0u8; 8].to_vec(), [4u8; 32].to_vec()].concat()),
+        ];
+        let proofs = || raws.iter().rev().map(|(id, raw)| (*id, raw.as_slice()));
+        assert_eq!(Some(1), find_root(proofs(), &[1u8; 32], 10));
+        assert_eq!(Some(3), find_root(proofs(), &[2u8; 32], 10));
+        assert_eq!(None, find_root(proofs(), &[3u8; 32], 10));
+        assert_eq!(None, find_root(proofs(), &[1u8; 32], 3));
+        assert!(super::SUBMITTED_KEY < &super::id_to_key(0)[..]);
+    }
 }
@@ -17,63 +17,119 @@ use crate::input::Command;
 use anyhow::anyhow;
 use node_api::decoder::{Raw, RuntimeDecoder};
 use parity_scale_codec::Decode;
+use rayon::prelude::*;
 use std::{sync::atomic::Ordering, sync::mpsc::Sender, thread, time::Duration};
+use sub_api::Hash;
+
+// stored along with the proofs, sorted before them
+const PROGRESS_KEY: &[u8] = b"ackblock";
+
+// the blocks fetched in parallel while catching up
+const CATCH_UP_BATCH: u32 = 32;
 
 pub fn init(tx: Sender<Input>, connector: FusoConnector, state: Arc<FusoState>) {
     if C.dry_run.is_some() {
         return;
     }
-    let decoder = RuntimeDecoder::new(connector.api.metadata.clone());
+    let decoder = RuntimeDecoder::new(connector.api().metadata.clone());
     let receipts = connector.fully_sync_chain(state.clone()).unwrap();
     for cmd in receipts.into_iter() {
         tx.send(Input::new(cmd)).unwrap();
     }
+    // catch up the blocks finalized during the downtime
+    if let Some(at) = load_progress() {
+        let from = state.scanning_progress.fetch_min(at, Ordering::Relaxed);
+        if at < from {
+            log::info!("catching up blocks {}-{}", at, from - 1);
+        }
+    }
     thread::spawn(move || loop {
+        // the unscanned blocks are synchronized again on restarting
+        if sequencer::is_stopping() {
+            log::info!("scanner stopped at block {}", state.get_scanning_progress());
+            break;
+        }
         let at = state.scanning_progress.load(Ordering::Relaxed);
         if let Ok((finalized, _)) = connector.get_finalized_block() {
             log::info!("block {} finalized, ours {}", finalized, at);
             state.chain_height.store(finalized, Ordering::Relaxed);
             if finalized >= at {
-                match handle_finalized_block(&connector, at, &decoder, &state, &tx) {
-                    Ok(()) => {
-                        state.scanning_progress.fetch_add(1, Ordering::Relaxed);
-                        log::info!("block {} finalized", at);
+                let to = finalized.min(at + CATCH_UP_BATCH - 1);
+                let fetched = (at..=to)
+                    .into_par_iter()
+                    .map(|b| fetch_events(&connector, b))
+                    .collect::<Vec<_>>();
+                for (b, events) in (at..=to).zip(fetched) {
+                    if sequencer::is_stopping() {
+                        break;
+                    }
+                    match events.and_then(|(hash, payload)| {
+                        handle_finalized_block(&connector, b, hash, &payload, &decoder, &state, &tx)
+                    }) {
+                        Ok(()) => {
+                            state.scanning_progress.fetch_add(1, Ordering::Relaxed);
+                            save_progress(b + 1);
+                            log::info!("block {} finalized", b);
+                        }
+                        Err(e) => {
+                            log::error!("{:?}", e);
+                            connector.reconnect();
+                            break;
+                        }
                     }
-                    Err(e) => log::error!("{:?}", e),
                 }
             } else {
                 thread::sleep(Duration::from_millis(6000));
             }
         } else {
-            log::error!("scanning connection temporarily lost, retrying...");
-            thread::sleep(Duration::from_millis(1000));
+            log::error!("scanning connection temporarily lost, reconnecting...");
+            connector.reconnect();
         }
     });
 }
 
+/// the next block to scan, persisted after each block
+fn load_progress() -> Option<u32> {
+    PROOF_STORE
+        .get(PROGRESS_KEY)
+        .ok()
+        .flatten()
+        .and_then(|v| v.try_into().ok())
+        .map(u32::from_be_bytes)
+}
+
+fn save_progress(at: u32) {
+    if let Err(e) = PROOF_STORE.put(PROGRESS_KEY, at.to_be_bytes()) {
+        log::error!("saving the scanning progress failed, {:?}", e);
+    }
+}
+
+/// the hash and encoded events of the block
+fn fetch_events(connector: &FusoConnector, at: u32) -> anyhow::Result<(Hash, Vec<u8>)> {
+    let api = connector.api();
+    let hash = api
+        .get_block_hash(Some(at))?
+        .ok_or(anyhow!("block {} not ready", at))?;
+    let key = api
+        .metadata
+        .storage_value_key("System", "Events")
+        .map_err(|e| anyhow!("Read storage failed: {:?}", e))?;
+    let payload = api.get_opaque_storage_by_key_hash(key, Some(hash))?;
+    Ok((hash, payload.unwrap_or_default()))
+}
+
 fn handle_finalized_block(
     connector: &FusoConnector,
     at: u32,
+    hash: Hash,
+    payload: &[u8],
     decoder: &RuntimeDecoder,
     state: &Arc<FusoState>,
     to_seq: &Sender<Input>,
 ) -> anyhow::Result<()> {
     use hex::ToHex;
-    let hash = connector
-        .api
-        .get_block_hash(Some(at))?
-        .ok_or(anyhow!("block {} not ready", at))?;
-    let key = connector
-        .api
-        .metadata
-        .storage_value_key("System", "Events")
-        .map_err(|e| anyhow!("Read storage failed: {:?}", e))?;
-    let payload = connector
-        .api
-        .get_opaque_storage_by_key_hash(key, Some(hash))?;
-    let events = decoder
-        .decode_events(&mut payload.unwrap_or(vec![]).as_slice())
-        .unwrap_or(vec![]);
+    let api = connector.api();
+    let events = decoder.decode_events(&mut &payload[..]).unwrap_or(vec![]);
     for (_, event) in events.into_iter() {
         if let Raw::Event(raw) = event {
             match (raw.pallet.as_ref(), raw.variant.as_ref()) {
@@ -87,6 +143,7 @@ fn handle_finalized_block(
                         cmd.user_id = Some(format!("{}", decoded.fund_owner));
                         cmd.block_number = Some(at);
                         cmd.extrinsic_hash = Some(hash.encode_hex());
+                        cmd.timestamp = Some(now());
                         to_seq.send(Input::new(cmd))?;
                     }
                 }
@@ -100,22 +157,21 @@ fn handle_finalized_block(
                         cmd.user_id = Some(format!("{}", decoded.fund_owner));
                         cmd.block_number = Some(at);
                         cmd.extrinsic_hash = Some(hash.encode_hex());
+                        cmd.timestamp = Some(now());
                         to_seq.send(Input::new(cmd))?;
                     }
                 }
                 ("Token", "TokenIssued") => {
                     let decoded = TokenIssuedEvent::decode(&mut &raw.data[..])?;
-                    let key = connector
-                        .api
+                    let key = api
                         .metadata
                         .storage_map_key::<u32>("Token", "Tokens", decoded.token_id)
                         .map_err(|e| anyhow!("Read storage failed: {:?}", e))?;
-                    let payload = connector
-                        .api
+                    let payload = api
                         .get_opaque_storage_by_key_hash(key, Some(hash))?
                         .ok_or(anyhow::anyhow!(""))?;
                     let token = OnchainToken::decode(&mut payload.as_slice())?;
-                    state.currencies.insert(decoded.token_id, token);
+                    state.insert_currency(decoded.token_id, token);
                 }
                 ("Market", "BrokerRegistered") => {
                     let decoded = BrokerRegisteredEvent::decode(&mut &raw.data[..])?;
@@ -143,6 +199,8 @@ fn handle_finalized_block(
                         // useless
                         cmd.min_vol = Some(Decimal::from_str("10").unwrap());
                         cmd.enable_market_order = Some(false);
+                        cmd.block_number = Some(at);
+                        cmd.timestamp = Some(now());
                         to_seq.send(Input::new(cmd))?;
                         state.symbols.insert(
                             (decoded.base, decoded.quote),
@@ -161,34 +219,124 @@ fn handle_finalized_block(
                 ("Market", "MarketClosed") => {
                     let decoded = MarketClosedEvent::decode(&mut &raw.data[..])?;
                     if decoded.dominator == connector.get_pubkey() {
-                        let market = state.symbols.remove(&(decoded.base, decoded.quote));
-                        let mut cmd = Command::default();
-                        let milli = Decimal::from_str("0.001").unwrap();
-                        cmd.cmd = crate::cmd::UPDATE_SYMBOL;
-                        cmd.base = Some(decoded.base);
-                        cmd.quote = Some(decoded.quote);
-                        cmd.open = Some(false);
-                        cmd.taker_fee = Some(milli);
-                        cmd.maker_fee = Some(milli);
-                        let (base_scale, quote_scale, min_amount) = market
-                            .map(|(_, m)| (m.base_scale, m.quote_scale, m.min_base))
-                            .ok_or(anyhow!(""))?;
-                        cmd.base_scale = Some(base_scale.into());
-                        cmd.quote_scale = Some(quote_scale.into());
-                        cmd.min_amount = to_decimal_represent(min_amount);
-                        // DEPRECATED
-                        cmd.base_maker_fee = Some(milli);
-                        cmd.base_taker_fee = Some(milli);
-                        cmd.fee_times = Some(1);
-                        // useless
-                        cmd.min_vol = Some(Decimal::from_str("10").unwrap());
-                        cmd.enable_market_order = Some(false);
-                        to_seq.send(Input::new(cmd))?;
+                        let symbol = (decoded.base, decoded.quote);
+                        let (_, market) = state.symbols.remove(&symbol).ok_or(anyhow!(""))?;
+                        close_symbol(symbol, &market, at, to_seq)?;
                     }
                 }
                 _ => {}
             }
         }
     }
+    // acknowledge the proofs in flight without waiting for the watchdog
+    if prover::submitted() > state.dominator.read().unwrap().sequence.0 {
+        if let Ok(remote) = connector.get_dominator_at(hash) {
+            committer::acknowledge(state, remote);
+        }
+    }
+    expire_symbols(at, state, to_seq)
+}
+
+fn close_symbol_cmd(symbol: Symbol, market: &OnchainSymbol) -> Command {
+    let mut cmd = Command::default();
+    let milli = Decimal::from_str("0.001").unwrap();
+    cmd.cmd = crate::cmd::UPDATE_SYMBOL;
+    cmd.base = Some(symbol.0);
+    cmd.quote = Some(symbol.1);
+    cmd.open = Some(false);
+    cmd.taker_fee = Some(milli);
+    cmd.maker_fee = Some(milli);
+    cmd.base_scale = Some(market.base_scale.into());
+    cmd.quote_scale = Some(market.quote_scale.into());
+    cmd.min_amount = to_decimal_represent(market.min_base);
+    // DEPRECATED
+    cmd.base_maker_fee = Some(milli);
+    cmd.base_taker_fee = Some(milli);
+    cmd.fee_times = Some(1);
+    // useless
+    cmd.min_vol = Some(Decimal::from_str("10").unwrap());
+    cmd.enable_market_order = Some(false);
+    cmd.timestamp = Some(now());
+    cmd
+}
+
+fn now() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap()
+        .as_secs()
+}
+
+/// close the symbols whose `unavailable_after` has been passed and cancel all their resting orders,
+/// the symbols would be loaded again after restarting so this must be idempotent
+fn expire_symbols(at: u32, state: &Arc<FusoState>, to_seq: &Sender<Input>) -> anyhow::Result<()> {
+    let expired = state
+        .symbols
+        .iter()
+        .filter(|s| s.value().unavailable_after.map_or(false, |h| h < at))
+        .map(|s| *s.key())
+        .collect::<Vec<_>>();
+    for symbol in expired {
+        if let Some((_, market)) = state.symbols.remove(&symbol) {
+            log::info!("symbol {:?} expired at block {}", symbol, at);
+            close_symbol(symbol, &market, at, to_seq)?;
+        }
+    }
     Ok(())
 }
+
+/// the resting orders of the closed symbol are cancelled in one event proven as a batch, so the
+/// frozen balances are released rather than kept on the book forever; without the `batch`
+/// extension they are left to their owners to cancel
+fn close_symbol(
+    symbol: Symbol,
+    market: &OnchainSymbol,
+    at: u32,
+    to_seq: &Sender<Input>,
+) -> anyhow::Result<()> {
+    let mut cmd = close_symbol_cmd(symbol, market);
+    cmd.block_number = Some(at);
+    to_seq.send(Input::new(cmd))?;
+    if !C.fusotao.supports(crate::config::ProofExtension::Batch) {
+        log::warn!("resting orders of the closed symbol {:?} are kept", symbol);
+        return Ok(());
+    }
+    let mut cmd = Command::default();
+    cmd.cmd = crate::cmd::CANCEL_ALL;
+    cmd.base = Some(symbol.0);
+    cmd.quote = Some(symbol.1);
+    cmd.timestamp = Some(now());
+    to_seq.send(Input::new(cmd))?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    pub fn test_close_symbol() {
+        let market = OnchainSymbol {
+            min_base: 1_000_000_000_000_000_000,
+            base_scale: 4,
+            quote_scale: 2,
+            status: MarketStatus::Closed,
+            trading_rewards: true,
+            liquidity_rewards: true,
+            unavailable_after: None,
+        };
+        let (tx, rx) = std::sync::mpsc::channel();
+        close_symbol((1, 0), &market, 100, &tx).unwrap();
+        let events = rx
+            .try_iter()
+            .map(|input| input.try_into().unwrap())
+            .collect::<Vec<Event>>();
+        let batch = C.fusotao.supports(crate::config::ProofExtension::Batch);
+        assert_eq!(1 + batch as usize, events.len());
+        assert!(matches!(
+            &events[0],
+            Event::UpdateSymbol(_, cmd) if cmd.symbol == (1, 0) && !cmd.open && cmd.actor == "chain@100"
+        ));
+        assert!(!batch || matches!(events[1], Event::CancelAll(_, (1, 0), None, _, 0, 0)));
+    }
+}
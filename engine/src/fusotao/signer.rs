@@ -0,0 +1,258 @@
+// Copyright 2021-2023 UINB Technologies Pte. Ltd.
+
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// 	http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::{
+    config::{SignerCmd, C},
+    core::*,
+    flow::{BrokerFlow, BrokerSettlement},
+    fusotao::{committer, connector::FusoConnector, FusoAccountId, Sr25519Key},
+};
+use anyhow::anyhow;
+use serde::{Deserialize, Serialize};
+use sp_core::{crypto::Ss58Codec, Pair};
+use std::{
+    io::{BufRead, BufReader, Write},
+    os::unix::{
+        fs::PermissionsExt,
+        net::{UnixListener, UnixStream},
+    },
+    time::Duration,
+};
+
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// the prover key signing the proofs and the broker settlements
+#[derive(Clone)]
+pub enum Signer {
+    Local(Sr25519Key),
+    /// the key is kept by `galois signer` listening on `fusotao.remote_signer`
+    Remote(RemoteSigner),
+}
+
+impl Signer {
+    pub fn from_config() -> anyhow::Result<Self> {
+        match C.fusotao.remote_signer {
+            Some(ref path) => Ok(Self::Remote(RemoteSigner::connect(path)?)),
+            None => Sr25519Key::from_string(&C.fusotao.key_seed, None)
+                .map(Self::Local)
+                .map_err(|e| anyhow!("invalid fusotao config: {:?}", e)),
+        }
+    }
+
+    pub fn public(&self) -> FusoAccountId {
+        match self {
+            Self::Local(pair) => pair.public(),
+            Self::Remote(remote) => remote.public,
+        }
+    }
+
+    pub fn settle(
+        &self,
+        broker: &UserId,
+        epoch: u64,
+        flows: Vec<BrokerFlow>,
+    ) -> anyhow::Result<BrokerSettlement> {
+        match self {
+            Self::Local(pair) => Ok(BrokerSettlement::sign(broker, epoch, flows, pair)),
+            Self::Remote(remote) => remote.request(&Request::BrokerSettlement {
+                broker: broker.to_string(),
+                epoch,
+                flows,
+            }),
+        }
+    }
+}
+
+/// one json line per request and reply over the unix socket, the other signing services, e.g. a
+/// vault, can be adapted to it
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "method", rename_all = "snake_case")]
+pub enum Request {
+    Public,
+    /// the hex of the compressed proofs, replied with the hex of `Verifier::verify_compress_v2`
+    /// signed by the prover, which is submitted by the requester
+    VerifyProofs {
+        proofs: String,
+    },
+    BrokerSettlement {
+        broker: String,
+        epoch: u64,
+        flows: Vec<BrokerFlow>,
+    },
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum Reply {
+    Ok(serde_json::Value),
+    Error(String),
+}
+
+#[derive(Clone, Debug)]
+pub struct RemoteSigner {
+    path: String,
+    public: FusoAccountId,
+}
+
+impl RemoteSigner {
+    pub fn connect(path: &str) -> anyhow::Result<Self> {
+        let public = request::<String>(path, &Request::Public)?;
+        let signer = Self {
+            path: path.to_string(),
+            public: FusoAccountId::from_ss58check(&public)
+                .map_err(|_| anyhow!("invalid public key {} of the remote signer", public))?,
+        };
+        log::info!("remote signer {} connected at {}", public, path);
+        Ok(signer)
+    }
+
+    pub fn request<T: serde::de::DeserializeOwned>(&self, req: &Request) -> anyhow::Result<T> {
+        request(&self.path, req)
+    }
+
+    pub fn verify_proofs(&self, compressed: &[u8]) -> anyhow::Result<String> {
+        self.request(&Request::VerifyProofs {
+            proofs: hex::encode(compressed),
+        })
+    }
+}
+
+/// a new connection for each request, so the signer is free to restart
+fn request<T: serde::de::DeserializeOwned>(path: &str, req: &Request) -> anyhow::Result<T> {
+    let mut stream = UnixStream::connect(path)?;
+    stream.set_read_timeout(Some(REQUEST_TIMEOUT))?;
+    stream.write_all(&[serde_json::to_vec(req)?, b"\n".to_vec()].concat())?;
+    let mut line = String::new();
+    BufReader::new(stream).read_line(&mut line)?;
+    match serde_json::from_str(&line)? {
+        Reply::Ok(v) => Ok(serde_json::from_value(v)?),
+        Reply::Error(e) => Err(anyhow!("remote signer: {}", e)),
+    }
+}
+
+/// the proofs are composed by `connector`, only the settlements are signed without it
+fn handle(
+    pair: &Sr25519Key,
+    connector: Option<&FusoConnector>,
+    req: Request,
+) -> anyhow::Result<serde_json::Value> {
+    match req {
+        Request::Public => Ok(serde_json::to_value(pair.public().to_ss58check())?),
+        Request::VerifyProofs { proofs } => {
+            let connector = connector.ok_or_else(|| anyhow!("not connected to fusotao"))?;
+            log::info!("signing {} bytes of proofs", proofs.len() / 2);
+            let xt = committer::compose(connector, hex::decode(proofs.trim_start_matches("0x"))?)?;
+            Ok(serde_json::to_value(xt)?)
+        }
+        Request::BrokerSettlement {
+            broker,
+            epoch,
+            flows,
+        } => {
+            let broker = broker.parse::<UserId>()?;
+            let settlement = BrokerSettlement::sign(&broker, epoch, flows, pair);
+            Ok(serde_json::to_value(settlement)?)
+        }
+    }
+}
+
+fn serve(stream: UnixStream, pair: &Sr25519Key, connector: Option<&FusoConnector>) {
+    let mut writer = match stream.try_clone() {
+        Ok(writer) => writer,
+        Err(e) => return log::error!("remote signer connection broken, {}", e),
+    };
+    for line in BufReader::new(stream).lines() {
+        let Ok(line) = line else { return };
+        let reply = match serde_json::from_str::<Request>(&line) {
+            Ok(req) => handle(pair, connector, req)
+                .map(Reply::Ok)
+                .unwrap_or_else(|e| Reply::Error(e.to_string())),
+            Err(e) => Reply::Error(e.to_string()),
+        };
+        let reply = serde_json::to_vec(&reply).expect("jsonser;qed");
+        if writer.write_all(&[reply, b"\n".to_vec()].concat()).is_err() {
+            return;
+        }
+    }
+}
+
+fn listen(listener: UnixListener, pair: Sr25519Key, connector: Option<FusoConnector>) {
+    for stream in listener.incoming() {
+        match stream {
+            Ok(stream) => {
+                let (pair, connector) = (pair.clone(), connector.clone());
+                std::thread::spawn(move || serve(stream, &pair, connector.as_ref()));
+            }
+            Err(e) => log::error!("remote signer accepting failed, {}", e),
+        }
+    }
+}
+
+/// the key is read from `fusotao.key_seed` on the signer host, which connects to the fusotao node
+/// to compose the extrinsics as well
+pub fn run(c: SignerCmd) -> anyhow::Result<()> {
+    anyhow::ensure!(
+        C.fusotao.remote_signer.is_none(),
+        "the signer must hold the key, remove `fusotao.remote_signer`"
+    );
+    let connector = FusoConnector::new()?;
+    let Signer::Local(pair) = connector.signer.clone() else {
+        unreachable!("local key;qed")
+    };
+    let _ = std::fs::remove_file(&c.listen);
+    let listener = UnixListener::bind(&c.listen)?;
+    std::fs::set_permissions(&c.listen, std::fs::Permissions::from_mode(0o600))?;
+    log::info!(
+        "signing by {} on {}",
+        pair.public().to_ss58check(),
+        c.listen
+    );
+    listen(listener, pair, Some(connector));
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use rust_decimal_macros::dec;
+
+    #[test]
+    pub fn test_remote_signer() {
+        let path = std::env::temp_dir().join(format!("galois-signer-{}.sock", std::process::id()));
+        let path = path.to_str().unwrap().to_string();
+        let _ = std::fs::remove_file(&path);
+        let listener = UnixListener::bind(&path).unwrap();
+        let pair = Sr25519Key::from_string("//Alice", None).unwrap();
+        let public = pair.public();
+        std::thread::spawn(move || listen(listener, pair, None));
+        let signer = Signer::Remote(RemoteSigner::connect(&path).unwrap());
+        assert_eq!(public, signer.public());
+        let broker = UserId::from_low_u64_be(1);
+        let mut flow = BrokerFlow::new((1, 0));
+        flow.orders = 2;
+        flow.base_volume = dec!(1.5);
+        flow.quote_volume = dec!(150);
+        flow.quote_fee = dec!(0.15);
+        let flows = vec![flow];
+        let settlement = signer.settle(&broker, 7, flows).unwrap();
+        assert_eq!(public.to_ss58check(), settlement.signer);
+        assert!(settlement.verify().unwrap());
+        // the proofs can't be composed without the fusotao node
+        let Signer::Remote(remote) = signer else {
+            unreachable!()
+        };
+        assert!(remote.verify_proofs(&[1, 2, 3]).is_err());
+        let _ = std::fs::remove_file(&path);
+    }
+}
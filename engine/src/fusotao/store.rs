@@ -0,0 +1,299 @@
+// Copyright 2021-2023 UINB Technologies Pte. Ltd.
+
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// 	http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use super::GlobalStates;
+use rocksdb::{WriteBatch, DB};
+use serde::{Deserialize, Serialize};
+use smt::{
+    blake2b::Blake2bHasher,
+    default_store::DefaultStore,
+    error::Error,
+    traits::Store,
+    tree::{BranchNode, LeafNode},
+    SparseMerkleTree, H256,
+};
+use std::{
+    cell::RefCell,
+    collections::{HashMap, VecDeque},
+    path::Path,
+};
+
+pub type PersistentStates = SparseMerkleTree<Blake2bHasher, H256, RocksStore>;
+
+const BRANCH_KEY: u8 = 0x00;
+const LEAF_KEY: u8 = 0x01;
+// the root and the last event applied, sorted after all the nodes
+const ROOT_KEY: &[u8] = b"root";
+
+type NodeKey = [u8; 33];
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+enum Node {
+    Branch(BranchNode),
+    Leaf(LeafNode<H256>),
+}
+
+fn node_key(prefix: u8, hash: &H256) -> NodeKey {
+    let mut key = [prefix; 33];
+    key[1..].copy_from_slice(hash.as_slice());
+    key
+}
+
+/// the recently read or committed nodes, the oldest are evicted first
+struct Cache {
+    capacity: usize,
+    nodes: HashMap<NodeKey, Node>,
+    order: VecDeque<NodeKey>,
+}
+
+impl Cache {
+    fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            nodes: HashMap::new(),
+            order: VecDeque::new(),
+        }
+    }
+
+    fn insert(&mut self, key: NodeKey, node: Node) {
+        if self.nodes.insert(key, node).is_none() {
+            self.order.push_back(key);
+        }
+        while self.nodes.len() > self.capacity {
+            match self.order.pop_front() {
+                Some(evicted) => self.nodes.remove(&evicted),
+                None => break,
+            };
+        }
+    }
+}
+
+/// the nodes of the merkle tree persisted in rocksdb, the updates are kept in memory until
+/// committed in a single batch along with the root so the tree on disk is always consistent
+pub struct RocksStore {
+    db: DB,
+    // `None` if removed since the latest commit
+    pending: HashMap<NodeKey, Option<Node>>,
+    cache: RefCell<Cache>,
+}
+
+impl RocksStore {
+    pub fn open(path: impl AsRef<Path>, cache: usize) -> anyhow::Result<Self> {
+        Ok(Self {
+            db: DB::open_default(path)?,
+            pending: HashMap::new(),
+            cache: RefCell::new(Cache::new(cache)),
+        })
+    }
+
+    /// the root and the last event of the latest commit
+    pub fn committed(&self) -> anyhow::Result<Option<(H256, u64)>> {
+        match self.db.get(ROOT_KEY)? {
+            Some(v) if v.len() == 40 => {
+                let mut root = [0u8; 32];
+                root.copy_from_slice(&v[..32]);
+                let event_id = u64::from_be_bytes(v[32..].try_into()?);
+                Ok(Some((root.into(), event_id)))
+            }
+            Some(_) => Err(anyhow::anyhow!(
+                "the persisted root of merkle tree is corrupted"
+            )),
+            None => Ok(None),
+        }
+    }
+
+    pub fn pending(&self) -> usize {
+        self.pending.len()
+    }
+
+    pub fn commit(&mut self, root: &H256, event_id: u64) -> anyhow::Result<()> {
+        let mut batch = WriteBatch::default();
+        for (key, node) in self.pending.iter() {
+            match node {
+                Some(node) => batch.put(key, bincode::serialize(node)?),
+                None => batch.delete(key),
+            }
+        }
+        let mut v = root.as_slice().to_vec();
+        v.extend_from_slice(&event_id.to_be_bytes());
+        batch.put(ROOT_KEY, v);
+        self.db.write(batch)?;
+        let mut cache = self.cache.borrow_mut();
+        for (key, node) in self.pending.drain() {
+            match node {
+                Some(node) => cache.insert(key, node),
+                None => {
+                    cache.nodes.remove(&key);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn get(&self, key: NodeKey) -> Result<Option<Node>, Error> {
+        if let Some(node) = self.pending.get(&key) {
+            return Ok(node.clone());
+        }
+        if let Some(node) = self.cache.borrow().nodes.get(&key) {
+            return Ok(Some(node.clone()));
+        }
+        let node = self
+            .db
+            .get(key)
+            .map_err(|e| Error::Store(e.to_string()))?
+            .map(|v| bincode::deserialize::<Node>(&v))
+            .transpose()
+            .map_err(|e| Error::Store(e.to_string()))?;
+        if let Some(ref node) = node {
+            self.cache.borrow_mut().insert(key, node.clone());
+        }
+        Ok(node)
+    }
+}
+
+impl Store<H256> for RocksStore {
+    fn get_branch(&self, node: &H256) -> Result<Option<BranchNode>, Error> {
+        match self.get(node_key(BRANCH_KEY, node))? {
+            Some(Node::Branch(branch)) => Ok(Some(branch)),
+            _ => Ok(None),
+        }
+    }
+
+    fn get_leaf(&self, leaf_hash: &H256) -> Result<Option<LeafNode<H256>>, Error> {
+        match self.get(node_key(LEAF_KEY, leaf_hash))? {
+            Some(Node::Leaf(leaf)) => Ok(Some(leaf)),
+            _ => Ok(None),
+        }
+    }
+
+    fn insert_branch(&mut self, node: H256, branch: BranchNode) -> Result<(), Error> {
+        self.pending
+            .insert(node_key(BRANCH_KEY, &node), Some(Node::Branch(branch)));
+        Ok(())
+    }
+
+    fn insert_leaf(&mut self, leaf_hash: H256, leaf: LeafNode<H256>) -> Result<(), Error> {
+        self.pending
+            .insert(node_key(LEAF_KEY, &leaf_hash), Some(Node::Leaf(leaf)));
+        Ok(())
+    }
+
+    fn remove_branch(&mut self, node: &H256) -> Result<(), Error> {
+        self.pending.insert(node_key(BRANCH_KEY, node), None);
+        Ok(())
+    }
+
+    fn remove_leaf(&mut self, leaf_hash: &H256) -> Result<(), Error> {
+        self.pending.insert(node_key(LEAF_KEY, leaf_hash), None);
+        Ok(())
+    }
+}
+
+/// open the tree persisted in `path`, which must be applied at least to the snapshot of
+/// `event_id`, or migrated from `merkle_tree` of the snapshot if never persisted
+pub fn restore(
+    path: impl AsRef<Path>,
+    cache: usize,
+    merkle_tree: &GlobalStates,
+    event_id: u64,
+) -> anyhow::Result<(PersistentStates, u64)> {
+    let mut store = RocksStore::open(path, cache)?;
+    match store.committed()? {
+        Some((root, persisted)) => {
+            anyhow::ensure!(
+                persisted >= event_id,
+                "the merkle tree persisted at {} is behind the snapshot {}",
+                persisted,
+                event_id
+            );
+            anyhow::ensure!(
+                persisted > event_id || merkle_tree.is_empty() || root == *merkle_tree.root(),
+                "the merkle tree persisted at {} mismatches the snapshot",
+                persisted
+            );
+            Ok((SparseMerkleTree::new(root, store), persisted))
+        }
+        None => {
+            migrate(merkle_tree.store(), &mut store)?;
+            store.commit(merkle_tree.root(), event_id)?;
+            log::info!("merkle tree of snapshot {} persisted", event_id);
+            Ok((SparseMerkleTree::new(*merkle_tree.root(), store), event_id))
+        }
+    }
+}
+
+fn migrate(from: &DefaultStore<H256>, to: &mut RocksStore) -> Result<(), Error> {
+    for (node, branch) in from.branches_map().iter() {
+        to.insert_branch(*node, branch.clone())?;
+    }
+    for (leaf_hash, leaf) in from.leaves_map().iter() {
+        to.insert_leaf(*leaf_hash, leaf.clone())?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn leaf(i: u8) -> (H256, H256) {
+        ([i; 32].into(), [i.wrapping_add(1); 32].into())
+    }
+
+    #[test]
+    pub fn test_persistent_tree() {
+        let dir = tempdir::TempDir::new("galois-merkle").unwrap();
+        let path = dir.path().join("merkle");
+        let mut snapshot = GlobalStates::default();
+        (0..10).map(leaf).for_each(|(k, v)| {
+            snapshot.update(k, v).unwrap();
+        });
+        let (mut tree, persisted) = restore(&path, 4, &snapshot, 10).unwrap();
+        assert_eq!(persisted, 10);
+        assert_eq!(tree.root(), snapshot.root());
+        assert!(tree.store().pending() == 0);
+        // the cached nodes are bounded
+        (0..10).map(leaf).for_each(|(k, v)| {
+            assert_eq!(tree.get(&k).unwrap(), v);
+        });
+        assert!(tree.store().cache.borrow().nodes.len() <= 4);
+
+        (10..20).map(leaf).for_each(|(k, v)| {
+            tree.update(k, v).unwrap();
+            snapshot.update(k, v).unwrap();
+        });
+        assert!(tree.store().pending() > 0);
+        assert_eq!(tree.root(), snapshot.root());
+        let root = *tree.root();
+        tree.store_mut().commit(&root, 20).unwrap();
+        drop(tree);
+
+        // the uncommitted updates are lost
+        let (mut tree, persisted) = restore(&path, 4, &GlobalStates::default(), 15).unwrap();
+        assert_eq!(persisted, 20);
+        assert_eq!(tree.root(), snapshot.root());
+        let (k, v) = leaf(20);
+        tree.update(k, v).unwrap();
+        drop(tree);
+        let (tree, _) = restore(&path, 4, &GlobalStates::default(), 20).unwrap();
+        assert_eq!(tree.root(), snapshot.root());
+        assert_eq!(tree.get(&k).unwrap(), H256::zero());
+        (0..20).map(leaf).for_each(|(k, v)| {
+            assert_eq!(tree.get(&k).unwrap(), v);
+        });
+        drop(tree);
+
+        assert!(restore(&path, 4, &snapshot, 21).is_err());
+    }
+}
@@ -0,0 +1,315 @@
+// Copyright 2021-2023 UINB Technologies Pte. Ltd.
+
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// 	http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::{
+    assets,
+    config::{GenesisCmd, GenesisConfig},
+    core::*,
+    executor,
+    fusotao::prover,
+    input::SymbolCmd,
+};
+use anyhow::{anyhow, ensure};
+use serde::{Deserialize, Serialize};
+use sp_core::{crypto::Ss58Codec, sr25519, Pair};
+use std::collections::HashSet;
+
+/// the initial balances and markets, the markets can't be imported from a csv
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Genesis {
+    #[serde(default)]
+    pub markets: Vec<SymbolCmd>,
+    #[serde(default)]
+    pub balances: Vec<GenesisBalance>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct GenesisBalance {
+    pub user_id: String,
+    pub currency: Currency,
+    pub amount: Amount,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Summary {
+    pub signer: String,
+    pub verified: bool,
+    pub markets: usize,
+    pub balances: usize,
+    pub tvl: Amount,
+    /// must be set on chain before submitting the first proof
+    pub root: String,
+}
+
+impl Genesis {
+    /// the csv is in `user_id,currency,amount`, the header is optional
+    pub fn parse(raw: &[u8], csv: bool) -> anyhow::Result<Self> {
+        if !csv {
+            return Ok(serde_json::from_slice(raw)?);
+        }
+        let balances = std::str::from_utf8(raw)?
+            .lines()
+            .enumerate()
+            .map(|(i, line)| (i, line.trim()))
+            .filter(|(i, line)| !(line.is_empty() || *i == 0 && line.starts_with("user_id")))
+            .map(|(i, line)| {
+                let row = line.split(',').map(|c| c.trim()).collect::<Vec<_>>();
+                match row[..] {
+                    [user_id, currency, amount] => Ok(GenesisBalance {
+                        user_id: user_id.to_string(),
+                        currency: currency.parse()?,
+                        amount: amount.parse()?,
+                    }),
+                    _ => Err(anyhow!(
+                        "line {}: expecting `user_id,currency,amount`",
+                        i + 1
+                    )),
+                }
+            })
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        Ok(Self {
+            markets: vec![],
+            balances,
+        })
+    }
+
+    /// the balances and the orderbooks with the merkle tree of them, nothing is proven
+    pub fn build(&self) -> anyhow::Result<Data> {
+        let mut data = Data::new();
+        for cmd in &self.markets {
+            check_market(cmd).map_err(|e| anyhow!("market {:?}: {}", cmd.symbol, e))?;
+            ensure!(
+                !data.orderbooks.contains_key(&cmd.symbol),
+                "market {:?}: duplicated",
+                cmd.symbol
+            );
+            data.orderbooks
+                .insert(cmd.symbol, executor::new_orderbook(cmd).into());
+        }
+        let mut imported = HashSet::new();
+        for b in &self.balances {
+            let user_id: UserId = b
+                .user_id
+                .parse()
+                .map_err(|e| anyhow!("balance of {}: {}", b.user_id, e))?;
+            ensure!(
+                b.amount.is_sign_positive() && !b.amount.is_zero(),
+                "balance of {}: the amount must be positive",
+                b.user_id
+            );
+            ensure!(
+                imported.insert((user_id, b.currency)),
+                "balance of {}: currency {} duplicated",
+                b.user_id,
+                b.currency
+            );
+            ensure!(
+                data.tvl + b.amount < max_number(),
+                "balance of {}: the tvl overflows",
+                b.user_id
+            );
+            assets::add_to_available(&mut data.accounts, &user_id, b.currency, b.amount)?;
+            data.tvl += b.amount;
+        }
+        let mut states = vec![];
+        for (user_id, account) in data.accounts.iter() {
+            for (currency, balance) in account.iter() {
+                states.push(prover::account_state(user_id, *currency, balance));
+            }
+        }
+        for (symbol, orderbook) in data.orderbooks.iter() {
+            states.extend(prover::orderbook_states(*symbol, orderbook));
+        }
+        for (key, value) in states {
+            data.merkle_tree
+                .update(key, value)
+                .map_err(|e| anyhow!("unable to update the merkle tree, {:?}", e))?;
+        }
+        Ok(data)
+    }
+}
+
+/// the same as the checks of `UPDATE_SYMBOL`
+fn check_market(cmd: &SymbolCmd) -> anyhow::Result<()> {
+    ensure!(
+        cmd.base_scale <= 7 && cmd.quote_scale <= 7,
+        "the scales must not exceed 7"
+    );
+    ensure!(
+        cmd.taker_fee.is_sign_positive() && cmd.base_taker_fee.is_sign_positive(),
+        "the taker fees must not be negative"
+    );
+    ensure!(
+        -cmd.maker_fee <= cmd.taker_fee && cmd.base_maker_fee.is_sign_positive(),
+        "the maker fees must not be negative but rebated within the taker fee"
+    );
+    ensure!(
+        cmd.min_amount.is_sign_positive() && cmd.min_vol.is_sign_positive(),
+        "the minimums must not be negative"
+    );
+    ensure!(
+        !cmd.broker_share
+            .is_some_and(|f| f.is_sign_negative() || f > Fee::ONE),
+        "the broker share must be within [0, 1]"
+    );
+    Ok(())
+}
+
+fn payload(raw: &[u8]) -> Vec<u8> {
+    [&b"galoisgenesis"[..], raw].concat()
+}
+
+pub fn sign(raw: &[u8], signer: &sr25519::Pair) -> String {
+    format!("0x{}", hex::encode(signer.sign(&payload(raw)).0))
+}
+
+pub fn verify(raw: &[u8], signature: &str, signer: &str) -> anyhow::Result<bool> {
+    let signer = sr25519::Public::from_ss58check(signer).map_err(|_| anyhow!("invalid signer"))?;
+    let mut sig = [0u8; 64];
+    hex::decode_to_slice(signature.trim().trim_start_matches("0x"), &mut sig)?;
+    Ok(sr25519::Pair::verify(
+        &sr25519::Signature::from_raw(sig),
+        payload(raw),
+        &signer,
+    ))
+}
+
+fn signature_path(path: &str) -> String {
+    format!("{}.sig", path)
+}
+
+fn is_csv(path: &str) -> bool {
+    path.to_lowercase().ends_with(".csv")
+}
+
+/// import the genesis file on starting from genesis, refused unless signed by the signer
+pub fn load(config: &GenesisConfig) -> anyhow::Result<Data> {
+    let raw = std::fs::read(&config.path)?;
+    let signature = std::fs::read_to_string(signature_path(&config.path))?;
+    ensure!(
+        verify(&raw, &signature, &config.signer)?,
+        "the genesis file {} isn't signed by {}",
+        config.path,
+        config.signer
+    );
+    let data = Genesis::parse(&raw, is_csv(&config.path))?.build()?;
+    log::info!(
+        "genesis {} imported, {} markets, {} accounts, merkle root 0x{}",
+        config.path,
+        data.orderbooks.len(),
+        data.accounts.len(),
+        hex::encode(data.merkle_tree.root().as_slice())
+    );
+    Ok(data)
+}
+
+pub fn run(c: GenesisCmd) -> anyhow::Result<bool> {
+    let raw = std::fs::read(&c.input_path)?;
+    let genesis = Genesis::parse(&raw, is_csv(&c.input_path))?;
+    let data = genesis.build()?;
+    let mut signer = c.signer;
+    if let Some(seed) = c.sign {
+        let pair = sr25519::Pair::from_string(&seed, None)
+            .map_err(|e| anyhow!("invalid seed, {:?}", e))?;
+        std::fs::write(signature_path(&c.input_path), sign(&raw, &pair))?;
+        signer.get_or_insert(pair.public().to_ss58check());
+    }
+    let signer = signer.ok_or(anyhow!("either --sign or --signer is required"))?;
+    let signature = std::fs::read_to_string(signature_path(&c.input_path))?;
+    let summary = Summary {
+        verified: verify(&raw, &signature, &signer)?,
+        signer,
+        markets: data.orderbooks.len(),
+        balances: genesis.balances.len(),
+        tvl: data.tvl,
+        root: format!("0x{}", hex::encode(data.merkle_tree.root().as_slice())),
+    };
+    println!("{}", serde_json::to_string_pretty(&summary)?);
+    Ok(summary.verified)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use rust_decimal_macros::dec;
+
+    #[test]
+    pub fn test_genesis() {
+        let alice = UserId::from_low_u64_be(1);
+        let user = format!("0x{}", hex::encode(alice.0));
+        let json = serde_json::json!({
+            "markets": [{
+                "symbol": [1, 0],
+                "open": true,
+                "base_scale": 4,
+                "quote_scale": 2,
+                "taker_fee": "0.001",
+                "maker_fee": "-0.0002",
+                "base_maker_fee": "0",
+                "base_taker_fee": "0.001",
+                "fee_times": 1,
+                "min_amount": "0.1",
+                "min_vol": "10",
+                "enable_market_order": true,
+                "tick_size": "0.01"
+            }],
+            "balances": [
+                {"user_id": user, "currency": 0, "amount": "100.5"},
+                {"user_id": user, "currency": 1, "amount": "2"}
+            ]
+        })
+        .to_string();
+        let data = Genesis::parse(json.as_bytes(), false)
+            .unwrap()
+            .build()
+            .unwrap();
+        assert_eq!(data.tvl, dec!(102.5));
+        assert_eq!(data.orderbooks[&(1, 0)].tick_size, dec!(0.01));
+        let balance = assets::get_balance_to_owned(&data.accounts, &alice, 0);
+        assert_eq!(balance.available, dec!(100.5));
+        let (key, value) = prover::account_state(&alice, 0, &balance);
+        assert_eq!(data.merkle_tree.get(&key).unwrap(), value);
+
+        let csv = format!("user_id,currency,amount\n{},0,100.5\n{},1,2\n", user, user);
+        let from_csv = Genesis::parse(csv.as_bytes(), true)
+            .unwrap()
+            .build()
+            .unwrap();
+        assert_eq!(from_csv.tvl, dec!(102.5));
+        assert!(from_csv.orderbooks.is_empty());
+        // the markets are left out of the tree since the books are empty
+        assert_eq!(from_csv.merkle_tree.root(), data.merkle_tree.root());
+
+        let duplicated = format!("{},0,1\n{},0,2\n", user, user);
+        assert!(Genesis::parse(duplicated.as_bytes(), true)
+            .unwrap()
+            .build()
+            .is_err());
+        let negative = format!("{},0,-1\n", user);
+        assert!(Genesis::parse(negative.as_bytes(), true)
+            .unwrap()
+            .build()
+            .is_err());
+
+        let pair = sr25519::Pair::from_string("//Alice", None).unwrap();
+        let signer = pair.public().to_ss58check();
+        let signature = sign(csv.as_bytes(), &pair);
+        assert!(verify(csv.as_bytes(), &signature, &signer).unwrap());
+        assert!(!verify(duplicated.as_bytes(), &signature, &signer).unwrap());
+        let bob = sr25519::Pair::from_string("//Bob", None).unwrap();
+        assert!(!verify(csv.as_bytes(), &signature, &bob.public().to_ss58check()).unwrap());
+    }
+}
@@ -0,0 +1,495 @@
+// Copyright 2021-2023 UINB Technologies Pte. Ltd.
+
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// 	http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::{
+    config::{self, AdminConfig, C},
+    core::*,
+    fusotao::committer,
+    input::{
+        cmd::*,
+        ratelimit::LIMITER,
+        sequencer,
+        server::{self, Sessions, ToBackend},
+        Command, Input,
+    },
+    latency, logger,
+    shared::Shared,
+    stats, verify,
+};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::{
+    io::{BufRead, BufReader, Write},
+    net::{TcpListener, TcpStream},
+    sync::atomic::{AtomicU64, Ordering},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+const DEFAULT_DRAIN_TIMEOUT: u64 = 10;
+const DEFAULT_CHECK_TIMEOUT: u64 = 30;
+
+// the checks are replied by the id, never 0
+static CHECK_ID: AtomicU64 = AtomicU64::new(1);
+
+/// the commands of the operators, one json per line with the `token`, e.g.
+/// `{"token": "..", "cmd": "pause", "symbol": [1, 0]}`
+#[derive(Debug, Clone, Eq, PartialEq, Deserialize)]
+#[serde(tag = "cmd", rename_all = "snake_case", deny_unknown_fields)]
+pub enum AdminCmd {
+    Pause {
+        symbol: Symbol,
+    },
+    Resume {
+        symbol: Symbol,
+    },
+    /// reject the takers of the symbol until lifted, the cancels and the makers are accepted
+    Halt {
+        symbol: Symbol,
+    },
+    /// lift the halt, by the operators or the circuit breaker
+    Lift {
+        symbol: Symbol,
+    },
+    /// dump the state after the events sequenced
+    Dump,
+    /// the x25519 key of the sessions, in hex
+    RotateKey {
+        x25519_priv: String,
+    },
+    SetLogLevel {
+        module: Option<String>,
+        level: Option<String>,
+        event_id: Option<u64>,
+    },
+    /// close the sessions after replying the pending requests in `timeout` seconds
+    Drain {
+        timeout: Option<u64>,
+    },
+    /// only in the standalone mode, fake a transfer of the chain
+    FakeTransfer {
+        user_id: String,
+        currency: Currency,
+        amount: Amount,
+        #[serde(default)]
+        out: bool,
+    },
+    /// recompute the leaves of the user and the symbol, all if neither, and check them against
+    /// the merkle tree after the events sequenced, waiting at most `timeout` seconds
+    CheckMerkle {
+        user_id: Option<String>,
+        symbol: Option<Symbol>,
+        timeout: Option<u64>,
+    },
+    /// read the config file again, see `reload_config`
+    ReloadConfig,
+    /// the latency histograms of the pipeline stages, cleared after replying if `reset`
+    Latency {
+        #[serde(default)]
+        reset: bool,
+    },
+    /// the events missing from the primary while following it
+    SequenceGaps,
+    /// the limit orders checked against the reference matcher and the recent divergences
+    ShadowStats,
+    /// the memory accounting measured on the last checkpoint, i.e. `QUERY_MEMORY_STATS`
+    MemoryStats,
+    /// the dominator states and the pending reset if any, i.e. `QUERY_DOMINATOR`
+    Dominator,
+    /// resume proving after a dominator reset, `replay` or `discard`, i.e. `CONFIRM_REANCHOR`
+    ConfirmReanchor {
+        mode: String,
+    },
+    /// move the fees accrued by `SYSTEM` to the treasury, all the available if no `amount`
+    WithdrawFees {
+        treasury: String,
+        currency: Currency,
+        amount: Option<Amount>,
+    },
+    /// the other admin commands of the engine, e.g. `UPDATE_CURRENCY`
+    Engine {
+        command: Box<Command>,
+    },
+}
+
+struct Context {
+    token: String,
+    to_backend: ToBackend,
+    shared: Shared,
+    sessions: Sessions,
+}
+
+pub fn init(config: &AdminConfig, to_backend: ToBackend, shared: Shared, sessions: Sessions) {
+    let listener = TcpListener::bind(&config.bind_addr).unwrap();
+    let token = config.token.clone();
+    std::thread::spawn(move || {
+        for stream in listener.incoming().flatten() {
+            let ctx = Context {
+                token: token.clone(),
+                to_backend: to_backend.clone(),
+                shared: shared.clone(),
+                sessions: sessions.clone(),
+            };
+            std::thread::spawn(move || {
+                let peer = stream.peer_addr();
+                if let Err(e) = serve(stream, ctx) {
+                    log::info!("admin connection {:?} closed, {:?}", peer, e);
+                }
+            });
+        }
+    });
+    log::info!("admin socket initialized");
+}
+
+fn serve(stream: TcpStream, ctx: Context) -> anyhow::Result<()> {
+    let mut writer = stream.try_clone()?;
+    for line in BufReader::new(stream).lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let reply = match parse(&line, &ctx.token) {
+            Ok(cmd) => {
+                log::info!("admin command: {:?}", cmd);
+                execute(cmd, &ctx).unwrap_or_else(|e| json!({"error": e.to_string()}))
+            }
+            Err(AuthError::Unauthorized) => {
+                log::warn!("unauthorized admin command from {:?}", writer.peer_addr());
+                writer.write_all(b"{\"error\":\"unauthorized\"}\n")?;
+                return Ok(());
+            }
+            Err(AuthError::Invalid(e)) => json!({ "error": e }),
+        };
+        writer.write_all(format!("{}\n", reply).as_bytes())?;
+    }
+    Ok(())
+}
+
+#[derive(Debug, Eq, PartialEq)]
+enum AuthError {
+    Unauthorized,
+    Invalid(String),
+}
+
+fn parse(line: &str, token: &str) -> Result<AdminCmd, AuthError> {
+    let mut v: Value = serde_json::from_str(line).map_err(|e| AuthError::Invalid(e.to_string()))?;
+    let given = v
+        .as_object_mut()
+        .and_then(|o| o.remove("token"))
+        .and_then(|t| t.as_str().map(|t| t.to_string()))
+        .ok_or(AuthError::Unauthorized)?;
+    if !constant_time_eq(given.as_bytes(), token.as_bytes()) {
+        return Err(AuthError::Unauthorized);
+    }
+    serde_json::from_value(v).map_err(|e| AuthError::Invalid(e.to_string()))
+}
+
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0u8, |r, (x, y)| r | (x ^ y)) == 0
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("clock;qed")
+        .as_secs()
+}
+
+fn sequence(ctx: &Context, cmd: Command) -> anyhow::Result<Value> {
+    // the rejections of the sequencer aren't replied to the admin socket
+    anyhow::ensure!(
+        C.fusotao.is_provable(&cmd),
+        crate::executor::RejectReason::Unprovable
+    );
+    ctx.to_backend.send(Input::new(cmd))?;
+    Ok(json!({"sequenced": true}))
+}
+
+fn execute(cmd: AdminCmd, ctx: &Context) -> anyhow::Result<Value> {
+    match cmd {
+        AdminCmd::Pause { symbol } | AdminCmd::Resume { symbol } => {
+            let open = matches!(cmd, AdminCmd::Resume { .. });
+            let cmd = Command {
+                cmd: SET_SYMBOL_OPEN,
+                base: Some(symbol.0),
+                quote: Some(symbol.1),
+                open: Some(open),
+                timestamp: Some(now()),
+                ..Default::default()
+            };
+            sequence(ctx, cmd)
+        }
+        AdminCmd::Halt { symbol } | AdminCmd::Lift { symbol } => {
+            let halted = matches!(cmd, AdminCmd::Halt { .. });
+            let cmd = Command {
+                cmd: SET_TRADING_HALT,
+                base: Some(symbol.0),
+                quote: Some(symbol.1),
+                halted: Some(halted),
+                ..Default::default()
+            };
+            sequence(ctx, cmd)
+        }
+        AdminCmd::Dump => sequence(
+            ctx,
+            Command {
+                cmd: DUMP,
+                ..Default::default()
+            },
+        ),
+        AdminCmd::RotateKey { x25519_priv } => {
+            let key = x25519_priv.trim_start_matches("0x");
+            anyhow::ensure!(
+                key.len() == 64 && hex::decode(key).is_ok(),
+                "x25519_priv must be 32 bytes in hex"
+            );
+            ctx.shared.rotate_x25519(x25519_priv);
+            log::warn!("x25519 key rotated, update the config before restarting");
+            Ok(json!({"rotated": true}))
+        }
+        AdminCmd::SetLogLevel {
+            module,
+            level,
+            event_id,
+        } => {
+            if let Some(level) = level {
+                logger::set_level(module.as_deref(), &level)?;
+            }
+            if let Some(id) = event_id {
+                logger::trace_event(id);
+            }
+            Ok(serde_json::to_value(logger::get_levels())?)
+        }
+        AdminCmd::Drain { timeout } => {
+            let timeout = Duration::from_secs(timeout.unwrap_or(DEFAULT_DRAIN_TIMEOUT));
+            Ok(json!({ "drained": server::drain(&ctx.sessions, timeout) }))
+        }
+        AdminCmd::FakeTransfer {
+            user_id,
+            currency,
+            amount,
+            out,
+        } => {
+            anyhow::ensure!(C.fusotao.standalone, "only in the standalone mode");
+            let block_number = ctx
+                .shared
+                .fuso_state
+                .chain_height
+                .fetch_add(1, std::sync::atomic::Ordering::Relaxed)
+                + 1;
+            let cmd = Command {
+                cmd: if out { TRANSFER_OUT } else { TRANSFER_IN },
+                user_id: Some(user_id),
+                currency: Some(currency),
+                amount: Some(amount),
+                block_number: Some(block_number),
+                extrinsic_hash: Some(Default::default()),
+                timestamp: Some(now()),
+                ..Default::default()
+            };
+            sequence(ctx, cmd).map(|_| json!({ "block_number": block_number }))
+        }
+        AdminCmd::CheckMerkle {
+            user_id,
+            symbol,
+            timeout,
+        } => {
+            let check_id = CHECK_ID.fetch_add(1, Ordering::Relaxed);
+            let cmd = Command {
+                cmd: CHECK_MERKLE,
+                user_id,
+                base: symbol.map(|s| s.0),
+                quote: symbol.map(|s| s.1),
+                ..Default::default()
+            };
+            ctx.to_backend.send(Input::new_with_req(cmd, 0, check_id))?;
+            let timeout = Duration::from_secs(timeout.unwrap_or(DEFAULT_CHECK_TIMEOUT));
+            match verify::wait_root_check(check_id, timeout) {
+                Some(Ok(check)) => Ok(serde_json::to_value(check)?),
+                Some(Err(e)) => Err(anyhow::anyhow!(e)),
+                None => Err(anyhow::anyhow!("the check is pending, see the logs later")),
+            }
+        }
+        AdminCmd::ReloadConfig => Ok(json!({ "reloaded": reload_config()? })),
+        AdminCmd::Latency { reset } => {
+            let stages = latency::snapshot();
+            if reset {
+                latency::reset();
+            }
+            Ok(serde_json::to_value(stages)?)
+        }
+        AdminCmd::SequenceGaps => Ok(serde_json::to_value(sequencer::gaps())?),
+        AdminCmd::ShadowStats => Ok(serde_json::to_value(crate::shadow::stats())?),
+        AdminCmd::MemoryStats => {
+            let (id, stats) =
+                stats::measured().ok_or_else(|| anyhow::anyhow!("not measured yet"))?;
+            Ok(json!({ "event_id": id, "stats": stats }))
+        }
+        AdminCmd::Dominator => Ok(ctx.shared.query_dominator()),
+        AdminCmd::ConfirmReanchor { mode } => {
+            let anchor = committer::reanchor(&ctx.shared.fuso_state, mode.parse()?)?;
+            Ok(json!({ "proving_progress": anchor }))
+        }
+        AdminCmd::WithdrawFees {
+            treasury,
+            currency,
+            amount,
+        } => {
+            let cmd = Command {
+                cmd: WITHDRAW_FEES,
+                user_id: Some(treasury),
+                currency: Some(currency),
+                amount,
+                timestamp: Some(now()),
+                ..Default::default()
+            };
+            sequence(ctx, cmd)
+        }
+        AdminCmd::Engine { mut command } => {
+            anyhow::ensure!(
+                crate::input::usage::CmdClass::of(command.cmd)
+                    == crate::input::usage::CmdClass::Admin,
+                "not an admin command"
+            );
+            anyhow::ensure!(
+                !matches!(
+                    command.cmd,
+                    SET_LOG_LEVEL | QUERY_MEMORY_STATS | QUERY_DOMINATOR | CONFIRM_REANCHOR
+                ),
+                "not sequenced, see `set_log_level`, `memory_stats`, `dominator` and `confirm_reanchor`"
+            );
+            command.timestamp = Some(now());
+            sequence(ctx, *command)
+        }
+    }
+}
+
+/// swap the log levels, rate limits and fusotao endpoints of the config file, the
+/// other changes are rejected so the config is either reloaded entirely or untouched, also on
+/// SIGHUP
+pub fn reload_config() -> anyhow::Result<Vec<&'static str>> {
+    let changed = config::reload()?;
+    if changed.iter().any(|c| c.starts_with("log.")) {
+        logger::reload(&C.log)?;
+    }
+    if changed.contains(&"rate_limit") {
+        LIMITER.reload(C.rate_limit.clone().unwrap_or_default());
+    }
+    log::info!("config reloaded, changed: {:?}", changed);
+    Ok(changed)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    pub fn test_parse_admin_cmd() {
+        let token = "0123456789abcdef";
+        assert_eq!(
+            parse(
+                r#"{"token": "0123456789abcdef", "cmd": "pause", "symbol": [1, 0]}"#,
+                token
+            ),
+            Ok(AdminCmd::Pause { symbol: (1, 0) })
+        );
+        assert_eq!(
+            parse(r#"{"token": "0123456789abcdef", "cmd": "dump"}"#, token),
+            Ok(AdminCmd::Dump)
+        );
+        assert_eq!(
+            parse(
+                r#"{"token": "0123456789abcdef", "cmd": "set_log_level", "event_id": 5}"#,
+                token
+            ),
+            Ok(AdminCmd::SetLogLevel {
+                module: None,
+                level: None,
+                event_id: Some(5)
+            })
+        );
+        assert_eq!(
+            parse(
+                r#"{"token": "0123456789abcdef", "cmd": "reload_config"}"#,
+                token
+            ),
+            Ok(AdminCmd::ReloadConfig)
+        );
+        assert_eq!(
+            parse(r#"{"token": "0123456789abcdeF", "cmd": "dump"}"#, token),
+            Err(AuthError::Unauthorized)
+        );
+        assert_eq!(
+            parse(r#"{"cmd": "dump"}"#, token),
+            Err(AuthError::Unauthorized)
+        );
+        assert!(matches!(
+            parse(r#"{"token": "0123456789abcdef", "cmd": "reboot"}"#, token),
+            Err(AuthError::Invalid(_))
+        ));
+        assert_eq!(
+            parse(
+                r#"{"token": "0123456789abcdef", "cmd": "fake_transfer", "user_id": "5DaYdJ1fXoFetSCaA44PrK6iQeTwg9AtjzLrxaQXooRrx9RK", "currency": 1, "amount": "100.5"}"#,
+                token
+            ),
+            Ok(AdminCmd::FakeTransfer {
+                user_id: "5DaYdJ1fXoFetSCaA44PrK6iQeTwg9AtjzLrxaQXooRrx9RK".to_string(),
+                currency: 1,
+                amount: rust_decimal_macros::dec!(100.5),
+                out: false,
+            })
+        );
+        assert_eq!(
+            parse(
+                r#"{"token": "0123456789abcdef", "cmd": "memory_stats"}"#,
+                token
+            ),
+            Ok(AdminCmd::MemoryStats)
+        );
+        assert_eq!(
+            parse(
+                r#"{"token": "0123456789abcdef", "cmd": "confirm_reanchor", "mode": "replay"}"#,
+                token
+            ),
+            Ok(AdminCmd::ConfirmReanchor {
+                mode: "replay".to_string()
+            })
+        );
+        assert_eq!(
+            parse(r#"{"token": "0123456789abcdef", "cmd": "latency"}"#, token),
+            Ok(AdminCmd::Latency { reset: false })
+        );
+        assert_eq!(
+            parse(
+                r#"{"token": "0123456789abcdef", "cmd": "check_merkle", "symbol": [1, 0]}"#,
+                token
+            ),
+            Ok(AdminCmd::CheckMerkle {
+                user_id: None,
+                symbol: Some((1, 0)),
+                timeout: None,
+            })
+        );
+        assert_eq!(
+            parse(
+                r#"{"token": "0123456789abcdef", "cmd": "withdraw_fees", "treasury": "5DaYdJ1fXoFetSCaA44PrK6iQeTwg9AtjzLrxaQXooRrx9RK", "currency": 1}"#,
+                token
+            ),
+            Ok(AdminCmd::WithdrawFees {
+                treasury: "5DaYdJ1fXoFetSCaA44PrK6iQeTwg9AtjzLrxaQXooRrx9RK".to_string(),
+                currency: 1,
+                amount: None,
+            })
+        );
+    }
+}
@@ -0,0 +1,204 @@
+// Copyright 2021-2023 UINB Technologies Pte. Ltd.
+
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// 	http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use blake2::{Blake2b, Digest};
+use chacha20poly1305::{
+    aead::{Aead, KeyInit},
+    ChaCha20Poly1305, Nonce,
+};
+use generic_array::typenum::U32;
+use std::sync::atomic::{AtomicU64, Ordering};
+use x25519_dalek::{PublicKey, StaticSecret};
+
+const NONCE_LEN: usize = 12;
+
+const KDF_CONTEXT: &[u8] = b"galois-session";
+
+// the nonces of the payloads from the client and from the server never collide
+const FROM_CLIENT: u32 = 0;
+const FROM_SERVER: u32 = 1;
+
+/// the payloads of a session are sealed once the handshake completed, the nonce of each direction
+/// is the count of the payloads sealed before, so a payload replayed, dropped or reordered can't
+/// be opened
+pub struct SessionCipher {
+    aead: ChaCha20Poly1305,
+    sealing: u32,
+    sealed: AtomicU64,
+    opened: AtomicU64,
+}
+
+impl std::fmt::Debug for SessionCipher {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "SessionCipher")
+    }
+}
+
+impl SessionCipher {
+    fn derive(
+        secret: &StaticSecret,
+        peer: &PublicKey,
+        client: &PublicKey,
+        server: &PublicKey,
+        sealing: u32,
+    ) -> Self {
+        let shared = secret.diffie_hellman(peer);
+        let mut hasher = Blake2b::<U32>::new();
+        hasher.update(KDF_CONTEXT);
+        hasher.update(shared.as_bytes());
+        hasher.update(client.as_bytes());
+        hasher.update(server.as_bytes());
+        Self {
+            aead: ChaCha20Poly1305::new(&hasher.finalize()),
+            sealing,
+            sealed: AtomicU64::new(0),
+            opened: AtomicU64::new(0),
+        }
+    }
+
+    fn nonce(direction: u32, counter: u64) -> [u8; NONCE_LEN] {
+        let mut nonce = [0u8; NONCE_LEN];
+        nonce[..4].copy_from_slice(&direction.to_be_bytes());
+        nonce[4..].copy_from_slice(&counter.to_be_bytes());
+        nonce
+    }
+
+    /// the payloads must be sealed in the order they are sent
+    pub fn seal(&self, plain: &[u8]) -> Vec<u8> {
+        let counter = self.sealed.fetch_add(1, Ordering::AcqRel);
+        let nonce = Self::nonce(self.sealing, counter);
+        self.aead
+            .encrypt(Nonce::from_slice(&nonce), plain)
+            .expect("the payload is limited;qed")
+    }
+
+    /// the payloads must be opened in the order they are received
+    pub fn open(&self, sealed: &[u8]) -> anyhow::Result<Vec<u8>> {
+        let counter = self.opened.load(Ordering::Acquire);
+        let nonce = Self::nonce(self.sealing ^ 1, counter);
+        let plain = self
+            .aead
+            .decrypt(Nonce::from_slice(&nonce), sealed)
+            .map_err(|_| anyhow::anyhow!("the sealed payload is corrupted or replayed"))?;
+        self.opened.store(counter + 1, Ordering::Release);
+        Ok(plain)
+    }
+}
+
+/// the server side of the handshake, replying the public key of `x25519_priv`
+pub fn respond(x25519_priv: &str, client: &str) -> anyhow::Result<(String, SessionCipher)> {
+    let secret = StaticSecret::from(decode_key(x25519_priv)?);
+    let server = PublicKey::from(&secret);
+    let client = PublicKey::from(decode_key(client)?);
+    let cipher = SessionCipher::derive(&secret, &client, &client, &server, FROM_SERVER);
+    Ok((hex::encode(server.as_bytes()), cipher))
+}
+
+/// the public key of `x25519_priv` in hex, pinned by the clients
+pub fn public_of(x25519_priv: &str) -> anyhow::Result<String> {
+    let secret = StaticSecret::from(decode_key(x25519_priv)?);
+    Ok(hex::encode(PublicKey::from(&secret).as_bytes()))
+}
+
+/// the client side of the handshake with an ephemeral key
+pub struct Initiator {
+    secret: StaticSecret,
+    public: PublicKey,
+}
+
+impl Initiator {
+    pub fn new() -> Self {
+        let secret = StaticSecret::from(rand::random::<[u8; 32]>());
+        let public = PublicKey::from(&secret);
+        Self { secret, public }
+    }
+
+    pub fn public(&self) -> String {
+        hex::encode(self.public.as_bytes())
+    }
+
+    /// the server must reply the `pinned` public key, otherwise it could be anyone in the middle
+    pub fn complete(self, server: &str, pinned: &str) -> anyhow::Result<SessionCipher> {
+        let server = decode_key(server)?;
+        anyhow::ensure!(
+            server == decode_key(pinned)?,
+            "the x25519 key of the server isn't the pinned"
+        );
+        let server = PublicKey::from(server);
+        Ok(SessionCipher::derive(
+            &self.secret,
+            &server,
+            &self.public,
+            &server,
+            FROM_CLIENT,
+        ))
+    }
+}
+
+impl Default for Initiator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn decode_key(key: &str) -> anyhow::Result<[u8; 32]> {
+    hex::decode(key.trim_start_matches("0x"))?
+        .try_into()
+        .map_err(|_| anyhow::anyhow!("x25519 key must be 32 bytes"))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    pub fn test_handshake() {
+        let x25519_priv = hex::encode(rand::random::<[u8; 32]>());
+        let pinned = public_of(&x25519_priv).unwrap();
+        let initiator = Initiator::new();
+        let (server, responder) = respond(&x25519_priv, &initiator.public()).unwrap();
+        assert_eq!(pinned, server);
+        let initiator = initiator.complete(&server, &pinned).unwrap();
+        let sealed = initiator.seal(br#"{"cmd":24}"#);
+        assert_eq!(br#"{"cmd":24}"#.to_vec(), responder.open(&sealed).unwrap());
+        // replayed
+        assert!(responder.open(&sealed).is_err());
+        let next = initiator.seal(br#"{"cmd":24}"#);
+        assert_ne!(sealed, next);
+        assert_eq!(br#"{"cmd":24}"#.to_vec(), responder.open(&next).unwrap());
+        // the directions don't share the nonces
+        let sealed = responder.seal(b"");
+        assert!(responder.open(&sealed).is_err());
+        let mut tampered = sealed.clone();
+        tampered[0] ^= 1;
+        assert!(initiator.open(&tampered).is_err());
+        assert!(initiator.open(&sealed).unwrap().is_empty());
+        // reordered
+        let (first, second) = (responder.seal(b"1"), responder.seal(b"2"));
+        assert!(initiator.open(&second).is_err());
+        assert_eq!(b"1".to_vec(), initiator.open(&first).unwrap());
+        assert_eq!(b"2".to_vec(), initiator.open(&second).unwrap());
+        let (_, other) = respond(&x25519_priv, &Initiator::new().public()).unwrap();
+        assert!(other.open(&first).is_err());
+        assert!(respond(&x25519_priv, "0x01").is_err());
+        // someone in the middle
+        let initiator = Initiator::new();
+        let (server, _) = respond(
+            &hex::encode(rand::random::<[u8; 32]>()),
+            &initiator.public(),
+        )
+        .unwrap();
+        assert!(initiator.complete(&server, &pinned).is_err());
+    }
+}
@@ -0,0 +1,806 @@
+// Copyright 2021-2023 UINB Technologies Pte. Ltd.
+
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// 	http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::input::Message;
+use dashmap::DashMap;
+use parity_scale_codec::{Compact, Decode, Encode};
+use serde::{ser, Deserialize, Serialize};
+
+lazy_static::lazy_static! {
+    /// the binary encodings requested by the commands waiting for the replies
+    static ref REQUESTED: DashMap<(u64, u64), Encoding> = DashMap::new();
+}
+
+/// the encoding of the replies requested by `Command::encoding`, the broadcasts are always json
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Encoding {
+    #[default]
+    Json,
+    Cbor,
+    Scale,
+}
+
+/// the self-describing value tree of SCALE encoded replies, the decimals are kept as strings
+/// exactly as the json ones
+#[derive(Debug, Clone, PartialEq, Encode, Decode)]
+pub enum ScaleValue {
+    Null,
+    Bool(bool),
+    U64(u64),
+    I64(i64),
+    /// bits of f64
+    F64(u64),
+    String(String),
+    Array(Vec<ScaleValue>),
+    Object(Vec<(String, ScaleValue)>),
+}
+
+#[derive(Debug, thiserror::Error)]
+#[error("{0}")]
+pub struct EncodeError(String);
+
+impl ser::Error for EncodeError {
+    fn custom<T: std::fmt::Display>(msg: T) -> Self {
+        Self(msg.to_string())
+    }
+}
+
+fn cbor_head(major: u8, n: u64, buf: &mut Vec<u8>) {
+    let major = major << 5;
+    match n {
+        0..=23 => buf.push(major | n as u8),
+        24..=0xff => buf.extend_from_slice(&[major | 24, n as u8]),
+        0x100..=0xffff => {
+            buf.push(major | 25);
+            buf.extend_from_slice(&(n as u16).to_be_bytes());
+        }
+        0x10000..=0xffff_ffff => {
+            buf.push(major | 26);
+            buf.extend_from_slice(&(n as u32).to_be_bytes());
+        }
+        _ => {
+            buf.push(major | 27);
+            buf.extend_from_slice(&n.to_be_bytes());
+        }
+    }
+}
+
+/// the heads of the arrays and maps
+fn head(encoding: Encoding, map: bool, len: usize) -> Vec<u8> {
+    let mut head = vec![];
+    match encoding {
+        Encoding::Cbor => cbor_head(if map { 5 } else { 4 }, len as u64, &mut head),
+        _ => {
+            head.push(if map { 7 } else { 6 });
+            Compact(len as u32).encode_to(&mut head);
+        }
+    }
+    head
+}
+
+/// writes the binary encodings straight from the serde data model, the map keys are strings
+/// as the json ones, CBOR in RFC 8949 with definite lengths only
+struct Writer<'a> {
+    encoding: Encoding,
+    out: &'a mut Vec<u8>,
+}
+
+impl<'a> Writer<'a> {
+    fn null(&mut self) {
+        match self.encoding {
+            Encoding::Cbor => self.out.push(0xf6),
+            _ => self.out.push(0),
+        }
+    }
+
+    fn bool(&mut self, v: bool) {
+        match self.encoding {
+            Encoding::Cbor => self.out.push(if v { 0xf5 } else { 0xf4 }),
+            _ => self.out.extend_from_slice(&[1, v as u8]),
+        }
+    }
+
+    fn u64(&mut self, v: u64) {
+        match self.encoding {
+            Encoding::Cbor => cbor_head(0, v, self.out),
+            _ => {
+                self.out.push(2);
+                v.encode_to(self.out);
+            }
+        }
+    }
+
+    fn i64(&mut self, v: i64) {
+        if v >= 0 {
+            return self.u64(v as u64);
+        }
+        match self.encoding {
+            Encoding::Cbor => cbor_head(1, !v as u64, self.out),
+            _ => {
+                self.out.push(3);
+                v.encode_to(self.out);
+            }
+        }
+    }
+
+    fn f64(&mut self, v: f64) {
+        match self.encoding {
+            Encoding::Cbor => {
+                self.out.push(0xfb);
+                self.out.extend_from_slice(&v.to_be_bytes());
+            }
+            _ => {
+                self.out.push(4);
+                v.to_bits().encode_to(self.out);
+            }
+        }
+    }
+
+    fn str(&mut self, v: &str) {
+        if self.encoding == Encoding::Scale {
+            self.out.push(5);
+        }
+        self.key(v);
+    }
+
+    /// the keys of `ScaleValue::Object` are untagged
+    fn key(&mut self, v: &str) {
+        match self.encoding {
+            Encoding::Cbor => {
+                cbor_head(3, v.len() as u64, self.out);
+                self.out.extend_from_slice(v.as_bytes());
+            }
+            _ => v.encode_to(self.out),
+        }
+    }
+
+    fn head(&mut self, map: bool, len: usize) {
+        let head = head(self.encoding, map, len);
+        self.out.extend_from_slice(&head);
+    }
+
+    fn begin(mut self, map: bool, len: Option<usize>) -> Compound<'a> {
+        let at = self.out.len();
+        if let Some(len) = len {
+            self.head(map, len);
+        }
+        let start = self.out.len();
+        Compound {
+            encoding: self.encoding,
+            out: self.out,
+            map,
+            len,
+            at,
+            start,
+            count: 0,
+        }
+    }
+}
+
+/// the arrays and maps, the head is written ahead if the length is known or fixed in the end
+struct Compound<'a> {
+    encoding: Encoding,
+    out: &'a mut Vec<u8>,
+    map: bool,
+    len: Option<usize>,
+    at: usize,
+    start: usize,
+    count: usize,
+}
+
+impl Compound<'_> {
+    fn writer(&mut self) -> Writer<'_> {
+        Writer {
+            encoding: self.encoding,
+            out: self.out,
+        }
+    }
+
+    fn element<T: Serialize + ?Sized>(&mut self, v: &T) -> Result<(), EncodeError> {
+        self.count += 1;
+        v.serialize(self.writer())
+    }
+
+    fn field<T: Serialize + ?Sized>(&mut self, k: &str, v: &T) -> Result<(), EncodeError> {
+        self.writer().key(k);
+        self.element(v)
+    }
+
+    fn finish(self) -> Result<(), EncodeError> {
+        if self.len != Some(self.count) {
+            let head = head(self.encoding, self.map, self.count);
+            self.out.splice(self.at..self.start, head);
+        }
+        Ok(())
+    }
+}
+
+macro_rules! widen {
+    ($($f:ident: $t:ty => $w:ident),* $(,)?) => {
+        $(fn $f(mut self, v: $t) -> Result<(), EncodeError> {
+            self.$w(v.into());
+            Ok(())
+        })*
+    };
+}
+
+impl<'a> ser::Serializer for Writer<'a> {
+    type Error = EncodeError;
+    type Ok = ();
+    type SerializeMap = Compound<'a>;
+    type SerializeSeq = Compound<'a>;
+    type SerializeStruct = Compound<'a>;
+    type SerializeStructVariant = Compound<'a>;
+    type SerializeTuple = Compound<'a>;
+    type SerializeTupleStruct = Compound<'a>;
+    type SerializeTupleVariant = Compound<'a>;
+
+    widen! {
+        serialize_u8: u8 => u64,
+        serialize_u16: u16 => u64,
+        serialize_u32: u32 => u64,
+        serialize_i8: i8 => i64,
+        serialize_i16: i16 => i64,
+        serialize_i32: i32 => i64,
+        serialize_f32: f32 => f64,
+    }
+
+    fn serialize_u64(mut self, v: u64) -> Result<(), EncodeError> {
+        self.u64(v);
+        Ok(())
+    }
+
+    fn serialize_i64(mut self, v: i64) -> Result<(), EncodeError> {
+        self.i64(v);
+        Ok(())
+    }
+
+    fn serialize_f64(mut self, v: f64) -> Result<(), EncodeError> {
+        self.f64(v);
+        Ok(())
+    }
+
+    fn serialize_bool(mut self, v: bool) -> Result<(), EncodeError> {
+        self.bool(v);
+        Ok(())
+    }
+
+    fn serialize_char(mut self, v: char) -> Result<(), EncodeError> {
+        self.str(v.encode_utf8(&mut [0; 4]));
+        Ok(())
+    }
+
+    fn serialize_str(mut self, v: &str) -> Result<(), EncodeError> {
+        self.str(v);
+        Ok(())
+    }
+
+    fn serialize_bytes(self, v: &[u8]) -> Result<(), EncodeError> {
+        if self.encoding == Encoding::Cbor {
+            cbor_head(2, v.len() as u64, self.out);
+            self.out.extend_from_slice(v);
+            return Ok(());
+        }
+        let mut seq = self.begin(false, Some(v.len()));
+        v.iter().try_for_each(|b| seq.element(b))?;
+        seq.finish()
+    }
+
+    fn serialize_none(mut self) -> Result<(), EncodeError> {
+        self.null();
+        Ok(())
+    }
+
+    fn serialize_some<T: Serialize + ?Sized>(self, v: &T) -> Result<(), EncodeError> {
+        v.serialize(self)
+    }
+
+    fn serialize_unit(mut self) -> Result<(), EncodeError> {
+        self.null();
+        Ok(())
+    }
+
+    fn serialize_unit_struct(self, _: &'static str) -> Result<(), EncodeError> {
+        self.serialize_unit()
+    }
+
+    fn serialize_unit_variant(
+        mut self,
+        _: &'static str,
+        _: u32,
+        variant: &'static str,
+    ) -> Result<(), EncodeError> {
+        self.str(variant);
+        Ok(())
+    }
+
+    fn serialize_newtype_struct<T: Serialize + ?Sized>(
+        self,
+        _: &'static str,
+        v: &T,
+    ) -> Result<(), EncodeError> {
+        v.serialize(self)
+    }
+
+    fn serialize_newtype_variant<T: Serialize + ?Sized>(
+        self,
+        _: &'static str,
+        _: u32,
+        variant: &'static str,
+        v: &T,
+    ) -> Result<(), EncodeError> {
+        let mut map = self.begin(true, Some(1));
+        map.field(variant, v)?;
+        map.finish()
+    }
+
+    fn serialize_seq(self, len: Option<usize>) -> Result<Compound<'a>, EncodeError> {
+        Ok(self.begin(false, len))
+    }
+
+    fn serialize_tuple(self, len: usize) -> Result<Compound<'a>, EncodeError> {
+        Ok(self.begin(false, Some(len)))
+    }
+
+    fn serialize_tuple_struct(
+        self,
+        _: &'static str,
+        len: usize,
+    ) -> Result<Compound<'a>, EncodeError> {
+        Ok(self.begin(false, Some(len)))
+    }
+
+    fn serialize_tuple_variant(
+        mut self,
+        _: &'static str,
+        _: u32,
+        variant: &'static str,
+        len: usize,
+    ) -> Result<Compound<'a>, EncodeError> {
+        self.head(true, 1);
+        self.key(variant);
+        Ok(self.begin(false, Some(len)))
+    }
+
+    fn serialize_map(self, len: Option<usize>) -> Result<Compound<'a>, EncodeError> {
+        Ok(self.begin(true, len))
+    }
+
+    fn serialize_struct(self, _: &'static str, len: usize) -> Result<Compound<'a>, EncodeError> {
+        Ok(self.begin(true, Some(len)))
+    }
+
+    fn serialize_struct_variant(
+        mut self,
+        _: &'static str,
+        _: u32,
+        variant: &'static str,
+        len: usize,
+    ) -> Result<Compound<'a>, EncodeError> {
+        self.head(true, 1);
+        self.key(variant);
+        Ok(self.begin(true, Some(len)))
+    }
+}
+
+impl ser::SerializeSeq for Compound<'_> {
+    type Error = EncodeError;
+    type Ok = ();
+
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, v: &T) -> Result<(), EncodeError> {
+        self.element(v)
+    }
+
+    fn end(self) -> Result<(), EncodeError> {
+        self.finish()
+    }
+}
+
+impl ser::SerializeTuple for Compound<'_> {
+    type Error = EncodeError;
+    type Ok = ();
+
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, v: &T) -> Result<(), EncodeError> {
+        self.element(v)
+    }
+
+    fn end(self) -> Result<(), EncodeError> {
+        self.finish()
+    }
+}
+
+impl ser::SerializeTupleStruct for Compound<'_> {
+    type Error = EncodeError;
+    type Ok = ();
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, v: &T) -> Result<(), EncodeError> {
+        self.element(v)
+    }
+
+    fn end(self) -> Result<(), EncodeError> {
+        self.finish()
+    }
+}
+
+impl ser::SerializeTupleVariant for Compound<'_> {
+    type Error = EncodeError;
+    type Ok = ();
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, v: &T) -> Result<(), EncodeError> {
+        self.element(v)
+    }
+
+    fn end(self) -> Result<(), EncodeError> {
+        self.finish()
+    }
+}
+
+impl ser::SerializeMap for Compound<'_> {
+    type Error = EncodeError;
+    type Ok = ();
+
+    fn serialize_key<T: Serialize + ?Sized>(&mut self, k: &T) -> Result<(), EncodeError> {
+        let k = k.serialize(MapKey)?;
+        self.writer().key(&k);
+        Ok(())
+    }
+
+    fn serialize_value<T: Serialize + ?Sized>(&mut self, v: &T) -> Result<(), EncodeError> {
+        self.element(v)
+    }
+
+    fn end(self) -> Result<(), EncodeError> {
+        self.finish()
+    }
+}
+
+impl ser::SerializeStruct for Compound<'_> {
+    type Error = EncodeError;
+    type Ok = ();
+
+    fn serialize_field<T: Serialize + ?Sized>(
+        &mut self,
+        k: &'static str,
+        v: &T,
+    ) -> Result<(), EncodeError> {
+        self.field(k, v)
+    }
+
+    fn end(self) -> Result<(), EncodeError> {
+        self.finish()
+    }
+}
+
+impl ser::SerializeStructVariant for Compound<'_> {
+    type Error = EncodeError;
+    type Ok = ();
+
+    fn serialize_field<T: Serialize + ?Sized>(
+        &mut self,
+        k: &'static str,
+        v: &T,
+    ) -> Result<(), EncodeError> {
+        self.field(k, v)
+    }
+
+    fn end(self) -> Result<(), EncodeError> {
+        self.finish()
+    }
+}
+
+/// the map keys are stringified as `serde_json` does
+struct MapKey;
+
+macro_rules! stringify_key {
+    ($($f:ident: $t:ty),* $(,)?) => {
+        $(fn $f(self, v: $t) -> Result<String, EncodeError> {
+            Ok(v.to_string())
+        })*
+    };
+}
+
+fn unsupported_key<T>() -> Result<T, EncodeError> {
+    Err(EncodeError("the map keys must be strings".to_string()))
+}
+
+impl ser::Serializer for MapKey {
+    type Error = EncodeError;
+    type Ok = String;
+    type SerializeMap = ser::Impossible<String, EncodeError>;
+    type SerializeSeq = ser::Impossible<String, EncodeError>;
+    type SerializeStruct = ser::Impossible<String, EncodeError>;
+    type SerializeStructVariant = ser::Impossible<String, EncodeError>;
+    type SerializeTuple = ser::Impossible<String, EncodeError>;
+    type SerializeTupleStruct = ser::Impossible<String, EncodeError>;
+    type SerializeTupleVariant = ser::Impossible<String, EncodeError>;
+
+    stringify_key! {
+        serialize_bool: bool,
+        serialize_i8: i8,
+        serialize_i16: i16,
+        serialize_i32: i32,
+        serialize_i64: i64,
+        serialize_u8: u8,
+        serialize_u16: u16,
+        serialize_u32: u32,
+        serialize_u64: u64,
+        serialize_char: char,
+        serialize_str: &str,
+    }
+
+    fn serialize_f32(self, _: f32) -> Result<String, EncodeError> {
+        unsupported_key()
+    }
+
+    fn serialize_f64(self, _: f64) -> Result<String, EncodeError> {
+        unsupported_key()
+    }
+
+    fn serialize_bytes(self, _: &[u8]) -> Result<String, EncodeError> {
+        unsupported_key()
+    }
+
+    fn serialize_none(self) -> Result<String, EncodeError> {
+        unsupported_key()
+    }
+
+    fn serialize_some<T: Serialize + ?Sized>(self, _: &T) -> Result<String, EncodeError> {
+        unsupported_key()
+    }
+
+    fn serialize_unit(self) -> Result<String, EncodeError> {
+        unsupported_key()
+    }
+
+    fn serialize_unit_struct(self, _: &'static str) -> Result<String, EncodeError> {
+        unsupported_key()
+    }
+
+    fn serialize_unit_variant(
+        self,
+        _: &'static str,
+        _: u32,
+        variant: &'static str,
+    ) -> Result<String, EncodeError> {
+        Ok(variant.to_string())
+    }
+
+    fn serialize_newtype_struct<T: Serialize + ?Sized>(
+        self,
+        _: &'static str,
+        v: &T,
+    ) -> Result<String, EncodeError> {
+        v.serialize(self)
+    }
+
+    fn serialize_newtype_variant<T: Serialize + ?Sized>(
+        self,
+        _: &'static str,
+        _: u32,
+        _: &'static str,
+        _: &T,
+    ) -> Result<String, EncodeError> {
+        unsupported_key()
+    }
+
+    fn serialize_seq(self, _: Option<usize>) -> Result<Self::SerializeSeq, EncodeError> {
+        unsupported_key()
+    }
+
+    fn serialize_tuple(self, _: usize) -> Result<Self::SerializeTuple, EncodeError> {
+        unsupported_key()
+    }
+
+    fn serialize_tuple_struct(
+        self,
+        _: &'static str,
+        _: usize,
+    ) -> Result<Self::SerializeTupleStruct, EncodeError> {
+        unsupported_key()
+    }
+
+    fn serialize_tuple_variant(
+        self,
+        _: &'static str,
+        _: u32,
+        _: &'static str,
+        _: usize,
+    ) -> Result<Self::SerializeTupleVariant, EncodeError> {
+        unsupported_key()
+    }
+
+    fn serialize_map(self, _: Option<usize>) -> Result<Self::SerializeMap, EncodeError> {
+        unsupported_key()
+    }
+
+    fn serialize_struct(
+        self,
+        _: &'static str,
+        _: usize,
+    ) -> Result<Self::SerializeStruct, EncodeError> {
+        unsupported_key()
+    }
+
+    fn serialize_struct_variant(
+        self,
+        _: &'static str,
+        _: u32,
+        _: &'static str,
+        _: usize,
+    ) -> Result<Self::SerializeStructVariant, EncodeError> {
+        unsupported_key()
+    }
+}
+
+impl Encoding {
+    /// serialize the reply straight in this encoding
+    pub fn to_vec<T: Serialize + ?Sized>(&self, v: &T) -> anyhow::Result<Vec<u8>> {
+        if *self == Encoding::Json {
+            return Ok(serde_json::to_vec(v)?);
+        }
+        let mut out = Vec::with_capacity(128);
+        v.serialize(Writer {
+            encoding: *self,
+            out: &mut out,
+        })?;
+        Ok(out)
+    }
+
+    /// the reply of `req_id`, an error frame if unable to encode it
+    pub fn reply<T: Serialize + ?Sized>(&self, req_id: u64, v: &T) -> Message {
+        match self.to_vec(v) {
+            Ok(payload) => Message::new_req(req_id, payload),
+            Err(e) => {
+                log::error!("unable to encode reply to {:?}, {:?}", self, e);
+                self.error(
+                    req_id,
+                    &serde_json::json!({"error": "unable to encode the reply"}),
+                )
+            }
+        }
+    }
+
+    /// the rejection of `req_id` in an error frame
+    pub fn error<T: Serialize + ?Sized>(&self, req_id: u64, v: &T) -> Message {
+        Message::new_err(req_id, self.to_vec(v).unwrap_or_default())
+    }
+}
+
+/// the replies of the request are encoded as `encoding`, json if never requested
+pub fn request(session: u64, req_id: u64, encoding: Encoding) {
+    if encoding != Encoding::Json {
+        REQUESTED.insert((session, req_id), encoding);
+    }
+}
+
+/// drop the requests of a closed session which would never be replied
+pub fn close_session(session: u64) {
+    REQUESTED.retain(|k, _| k.0 != session);
+}
+
+fn requested(session: u64, req_id: u64) -> Encoding {
+    REQUESTED
+        .remove(&(session, req_id))
+        .map(|(_, e)| e)
+        .unwrap_or_default()
+}
+
+/// encode the reply in the encoding requested by `(session, req_id)`
+pub fn reply<T: Serialize + ?Sized>(session: u64, req_id: u64, v: &T) -> Message {
+    requested(session, req_id).reply(req_id, v)
+}
+
+/// nothing found, replied as an empty payload in any encoding
+pub fn empty(session: u64, req_id: u64) -> Message {
+    REQUESTED.remove(&(session, req_id));
+    Message::new_req(req_id, vec![])
+}
+
+/// encode the rejection in the encoding requested by `(session, req_id)`
+pub fn reject<T: Serialize + ?Sized>(session: u64, req_id: u64, v: &T) -> Message {
+    requested(session, req_id).error(req_id, v)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::collections::BTreeMap;
+
+    #[derive(Serialize)]
+    struct Reply {
+        a: (u32, i64, &'static str),
+        b: Option<u8>,
+        c: bool,
+    }
+
+    #[derive(Serialize)]
+    struct Flattened {
+        #[serde(flatten)]
+        inner: BTreeMap<u32, f64>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        absent: Option<u8>,
+    }
+
+    #[test]
+    pub fn test_encode() {
+        let typed = Reply {
+            a: (1, -2, "0.10"),
+            b: None,
+            c: true,
+        };
+        assert_eq!(
+            br#"{"a":[1,-2,"0.10"],"b":null,"c":true}"#.to_vec(),
+            Encoding::Json.to_vec(&typed).unwrap()
+        );
+        assert_eq!(
+            vec![
+                0xa3, 0x61, b'a', 0x83, 0x01, 0x21, 0x64, b'0', b'.', b'1', b'0', 0x61, b'b', 0xf6,
+                0x61, b'c', 0xf5
+            ],
+            Encoding::Cbor.to_vec(&typed).unwrap()
+        );
+        let scale = Encoding::Scale.to_vec(&typed).unwrap();
+        assert_eq!(
+            ScaleValue::Object(vec![
+                (
+                    "a".to_string(),
+                    ScaleValue::Array(vec![
+                        ScaleValue::U64(1),
+                        ScaleValue::I64(-2),
+                        ScaleValue::String("0.10".to_string())
+                    ])
+                ),
+                ("b".to_string(), ScaleValue::Null),
+                ("c".to_string(), ScaleValue::Bool(true)),
+            ]),
+            ScaleValue::decode(&mut scale.as_slice()).unwrap()
+        );
+        // the heads of the maps of unknown lengths are written in the end
+        let flattened = Flattened {
+            inner: BTreeMap::from([(1, 0.5), (2, 1.0)]),
+            absent: None,
+        };
+        let scale = Encoding::Scale.to_vec(&flattened).unwrap();
+        assert_eq!(
+            ScaleValue::Object(vec![
+                ("1".to_string(), ScaleValue::F64(0.5_f64.to_bits())),
+                ("2".to_string(), ScaleValue::F64(1.0_f64.to_bits())),
+            ]),
+            ScaleValue::decode(&mut scale.as_slice()).unwrap()
+        );
+        let mut cbor = vec![0xa2, 0x61, b'1', 0xfb];
+        cbor.extend_from_slice(&0.5_f64.to_be_bytes());
+        cbor.extend_from_slice(&[0x61, b'2', 0xfb]);
+        cbor.extend_from_slice(&1.0_f64.to_be_bytes());
+        assert_eq!(cbor, Encoding::Cbor.to_vec(&flattened).unwrap());
+        let mut buf = vec![];
+        cbor_head(0, 500, &mut buf);
+        assert_eq!(vec![0x19, 0x01, 0xf4], buf);
+
+        let unsupported = BTreeMap::from([((1, 2), 3)]);
+        assert!(Encoding::Cbor.to_vec(&unsupported).is_err());
+        request(1, 1, Encoding::Cbor);
+        let rejected = reply(1, 1, &unsupported);
+        assert!(rejected.error);
+        assert_eq!(
+            Encoding::Cbor
+                .to_vec(&serde_json::json!({"error": "unable to encode the reply"}))
+                .unwrap(),
+            rejected.payload
+        );
+        // json once replied
+        assert_eq!(b"3".to_vec(), reply(1, 1, &3).payload);
+    }
+}
@@ -0,0 +1,1105 @@
+// Copyright 2021-2023 UINB Technologies Pte. Ltd.
+
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// 	http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::{
+    config::{FixConfig, C},
+    core::*,
+    input::{
+        cmd::*,
+        server::{self, FromSession, Sessions, ToBackend, ToSession},
+        Command, Message, MAX_FRAME_SIZE, ORDER_MATCHED,
+    },
+    matcher::TimeInForce,
+    orders::FillReport,
+    output::DepthSnapshot,
+    shared::Shared,
+};
+use anyhow::{anyhow, ensure};
+use async_std::{
+    io,
+    net::{TcpListener, TcpStream},
+    prelude::*,
+    task,
+};
+use futures::{
+    channel::mpsc::{self, UnboundedReceiver, UnboundedSender},
+    stream,
+};
+use rust_decimal::{prelude::Zero, Decimal};
+use serde_json::Value;
+use std::{
+    collections::HashMap,
+    net::Shutdown,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+const SOH: u8 = 0x01;
+const BEGIN_STRING: &str = "FIX.4.4";
+const DEFAULT_HEARTBEAT: u64 = 30;
+
+pub mod msg_type {
+    pub const HEARTBEAT: &str = "0";
+    pub const TEST_REQUEST: &str = "1";
+    pub const RESEND_REQUEST: &str = "2";
+    pub const REJECT: &str = "3";
+    pub const SEQUENCE_RESET: &str = "4";
+    pub const LOGOUT: &str = "5";
+    pub const EXECUTION_REPORT: &str = "8";
+    pub const ORDER_CANCEL_REJECT: &str = "9";
+    pub const LOGON: &str = "A";
+    pub const NEW_ORDER_SINGLE: &str = "D";
+    pub const ORDER_CANCEL_REQUEST: &str = "F";
+    pub const MARKET_DATA_REQUEST: &str = "V";
+    pub const MARKET_DATA_SNAPSHOT: &str = "W";
+    pub const MARKET_DATA_REQUEST_REJECT: &str = "Y";
+}
+
+/// the tags above 7000 are user defined, carrying the signatures of the commands
+pub mod tag {
+    pub const ACCOUNT: u32 = 1;
+    pub const AVG_PX: u32 = 6;
+    pub const CL_ORD_ID: u32 = 11;
+    pub const CUM_QTY: u32 = 14;
+    pub const EXEC_ID: u32 = 17;
+    pub const LAST_PX: u32 = 31;
+    pub const LAST_QTY: u32 = 32;
+    pub const MSG_SEQ_NUM: u32 = 34;
+    pub const MSG_TYPE: u32 = 35;
+    pub const NEW_SEQ_NO: u32 = 36;
+    pub const ORDER_ID: u32 = 37;
+    pub const ORDER_QTY: u32 = 38;
+    pub const ORD_STATUS: u32 = 39;
+    pub const ORD_TYPE: u32 = 40;
+    pub const ORIG_CL_ORD_ID: u32 = 41;
+    pub const POSS_DUP_FLAG: u32 = 43;
+    pub const PRICE: u32 = 44;
+    pub const REF_SEQ_NUM: u32 = 45;
+    pub const SENDER_COMP_ID: u32 = 49;
+    pub const SENDING_TIME: u32 = 52;
+    pub const SIDE: u32 = 54;
+    pub const SYMBOL: u32 = 55;
+    pub const TARGET_COMP_ID: u32 = 56;
+    pub const TEXT: u32 = 58;
+    pub const TIME_IN_FORCE: u32 = 59;
+    pub const ENCRYPT_METHOD: u32 = 98;
+    pub const CXL_REJ_REASON: u32 = 102;
+    pub const ORD_REJ_REASON: u32 = 103;
+    pub const HEART_BT_INT: u32 = 108;
+    pub const TEST_REQ_ID: u32 = 112;
+    pub const GAP_FILL_FLAG: u32 = 123;
+    pub const RESET_SEQ_NUM_FLAG: u32 = 141;
+    pub const NO_RELATED_SYM: u32 = 146;
+    pub const EXEC_TYPE: u32 = 150;
+    pub const LEAVES_QTY: u32 = 151;
+    pub const CASH_ORDER_QTY: u32 = 152;
+    pub const MD_REQ_ID: u32 = 262;
+    pub const SUBSCRIPTION_REQUEST_TYPE: u32 = 263;
+    pub const MARKET_DEPTH: u32 = 264;
+    pub const NO_MD_ENTRIES: u32 = 268;
+    pub const MD_ENTRY_TYPE: u32 = 269;
+    pub const MD_ENTRY_PX: u32 = 270;
+    pub const MD_ENTRY_SIZE: u32 = 271;
+    pub const MD_REQ_REJ_REASON: u32 = 281;
+    pub const REF_MSG_TYPE: u32 = 372;
+    pub const SESSION_REJECT_REASON: u32 = 373;
+    pub const CXL_REJ_RESPONSE_TO: u32 = 434;
+    pub const PASSWORD: u32 = 554;
+    pub const NONCE: u32 = 7001;
+    pub const SIGNATURE: u32 = 7002;
+    pub const BROKER: u32 = 7003;
+}
+
+use msg_type::*;
+use tag::*;
+
+/// the fields in order without the header and trailer, the repeating groups are kept flat
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct FixMessage {
+    pub msg_type: String,
+    pub fields: Vec<(u32, String)>,
+}
+
+impl FixMessage {
+    pub fn new(msg_type: &str) -> Self {
+        Self {
+            msg_type: msg_type.to_string(),
+            fields: vec![],
+        }
+    }
+
+    pub fn with(mut self, tag: u32, v: impl ToString) -> Self {
+        self.fields.push((tag, v.to_string()));
+        self
+    }
+
+    pub fn get(&self, tag: u32) -> Option<&str> {
+        self.fields
+            .iter()
+            .find(|(t, _)| *t == tag)
+            .map(|(_, v)| v.as_str())
+    }
+
+    pub fn get_all(&self, tag: u32) -> impl Iterator<Item = &str> {
+        self.fields
+            .iter()
+            .filter(move |(t, _)| *t == tag)
+            .map(|(_, v)| v.as_str())
+    }
+
+    fn require(&self, tag: u32) -> anyhow::Result<&str> {
+        self.get(tag)
+            .filter(|v| !v.is_empty())
+            .ok_or_else(|| anyhow!("tag {} is required", tag))
+    }
+
+    pub fn encode(&self, sender: &str, target: &str, seq: u64, sending_time: &str) -> Vec<u8> {
+        let mut body = format!(
+            "{}={}\x01{}={}\x01{}={}\x01{}={}\x01{}={}\x01",
+            MSG_TYPE,
+            self.msg_type,
+            SENDER_COMP_ID,
+            sender,
+            TARGET_COMP_ID,
+            target,
+            MSG_SEQ_NUM,
+            seq,
+            SENDING_TIME,
+            sending_time
+        );
+        for (tag, v) in self.fields.iter() {
+            body.push_str(&format!("{}={}\x01", tag, v));
+        }
+        let mut all = format!("8={}\x019={}\x01{}", BEGIN_STRING, body.len(), body).into_bytes();
+        let checksum = all.iter().fold(0_u8, |s, b| s.wrapping_add(*b));
+        all.extend_from_slice(format!("10={:03}\x01", checksum).as_bytes());
+        all
+    }
+
+    /// `None` if the message isn't complete yet, the bytes decoded are drained from `buf`
+    pub fn decode(buf: &mut Vec<u8>) -> anyhow::Result<Option<Self>> {
+        let prefix = format!("8={}\x019=", BEGIN_STRING).into_bytes();
+        if buf.len() < prefix.len() {
+            ensure!(prefix.starts_with(buf), "invalid BeginString");
+            return Ok(None);
+        }
+        ensure!(buf.starts_with(&prefix), "invalid BeginString");
+        let Some(n) = buf[prefix.len()..].iter().position(|b| *b == SOH) else {
+            ensure!(buf.len() < prefix.len() + 8, "invalid BodyLength");
+            return Ok(None);
+        };
+        let len: usize = std::str::from_utf8(&buf[prefix.len()..prefix.len() + n])?.parse()?;
+        ensure!(len <= MAX_FRAME_SIZE, "BodyLength {} is too large", len);
+        let body = prefix.len() + n + 1;
+        // 10=nnn<SOH>
+        let total = body + len + 7;
+        if buf.len() < total {
+            return Ok(None);
+        }
+        let trailer = &buf[body + len..total];
+        ensure!(
+            trailer.starts_with(b"10=") && trailer[6] == SOH,
+            "invalid CheckSum"
+        );
+        let checksum: u8 = std::str::from_utf8(&trailer[3..6])?.parse()?;
+        let sum = buf[..body + len]
+            .iter()
+            .fold(0_u8, |s, b| s.wrapping_add(*b));
+        ensure!(sum == checksum, "CheckSum mismatched");
+        let mut fields = std::str::from_utf8(&buf[body..body + len])?
+            .split('\x01')
+            .filter(|f| !f.is_empty())
+            .map(|f| {
+                let (tag, v) = f.split_once('=').ok_or(anyhow!("invalid field {}", f))?;
+                Ok((tag.parse::<u32>()?, v.to_string()))
+            })
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        buf.drain(..total);
+        ensure!(
+            fields.first().filter(|(t, _)| *t == MSG_TYPE).is_some(),
+            "MsgType must be the first field of the body"
+        );
+        let (_, msg_type) = fields.remove(0);
+        Ok(Some(Self { msg_type, fields }))
+    }
+}
+
+fn sending_time() -> String {
+    chrono::Utc::now().format("%Y%m%d-%H:%M:%S%.3f").to_string()
+}
+
+fn symbol_of(v: &str) -> anyhow::Result<Symbol> {
+    let (base, quote) = v
+        .split_once('/')
+        .ok_or_else(|| anyhow!("Symbol must be `base/quote`"))?;
+    Ok((base.parse()?, quote.parse()?))
+}
+
+fn symbol_str(symbol: &Symbol) -> String {
+    format!("{}/{}", symbol.0, symbol.1)
+}
+
+fn decimal_of(msg: &FixMessage, tag: u32) -> anyhow::Result<Decimal> {
+    msg.require(tag)?
+        .parse()
+        .map_err(|_| anyhow!("tag {} isn't a decimal", tag))
+}
+
+fn signed(msg: &FixMessage) -> anyhow::Result<(u32, String)> {
+    Ok((
+        msg.require(NONCE)?.parse()?,
+        msg.require(SIGNATURE)?.to_string(),
+    ))
+}
+
+/// the orders of the session, reported until done
+#[derive(Clone, Debug)]
+struct Tracked {
+    cl_ord_id: String,
+    account: String,
+    symbol: Symbol,
+    side: String,
+    // `None` if the market order is by the quote
+    qty: Option<Decimal>,
+    cum: Decimal,
+    cum_quote: Decimal,
+}
+
+#[derive(Clone, Debug)]
+enum Pending {
+    Order(FixMessage, Tracked),
+    Cancel(FixMessage, (Symbol, OrderId)),
+    MarketData(FixMessage, Symbol),
+}
+
+fn to_place_cmd(msg: &FixMessage) -> anyhow::Result<(Command, Tracked)> {
+    let cl_ord_id = msg.require(CL_ORD_ID)?.to_string();
+    let account = msg.require(ACCOUNT)?.to_string();
+    let symbol = symbol_of(msg.require(SYMBOL)?)?;
+    let side = msg.require(SIDE)?.to_string();
+    let limit = match msg.require(ORD_TYPE)? {
+        "1" => false,
+        "2" => true,
+        t => return Err(anyhow!("OrdType {} isn't supported", t)),
+    };
+    let time_in_force = match msg.get(TIME_IN_FORCE) {
+        None | Some("1") => TimeInForce::GoodTillCancel,
+        Some("3") => TimeInForce::ImmediateOrCancel,
+        Some("4") => TimeInForce::FillOrKill,
+        Some(t) => return Err(anyhow!("TimeInForce {} isn't supported", t)),
+    };
+    let (vol, amount) = match msg.get(CASH_ORDER_QTY) {
+        Some(_) if !limit => (Some(decimal_of(msg, CASH_ORDER_QTY)?), None),
+        _ => (None, Some(decimal_of(msg, ORDER_QTY)?)),
+    };
+    let (nonce, signature) = signed(msg)?;
+    let cmd = Command {
+        cmd: match (limit, side.as_str()) {
+            (true, "1") => BID_LIMIT,
+            (true, "2") => ASK_LIMIT,
+            (false, "1") => MARKET_BID,
+            (false, "2") => MARKET_ASK,
+            (_, s) => return Err(anyhow!("Side {} isn't supported", s)),
+        },
+        base: Some(symbol.0),
+        quote: Some(symbol.1),
+        user_id: Some(account.clone()),
+        amount,
+        vol,
+        price: limit.then(|| decimal_of(msg, PRICE)).transpose()?,
+        time_in_force: limit.then_some(time_in_force),
+        nonce: Some(nonce),
+        signature: Some(signature),
+        broker: msg.get(BROKER).map(|b| b.to_string()),
+        client_order_id: limit.then(|| cl_ord_id.clone()),
+        ..Default::default()
+    };
+    let order = Tracked {
+        cl_ord_id,
+        account,
+        symbol,
+        side,
+        qty: amount,
+        cum: Decimal::zero(),
+        cum_quote: Decimal::zero(),
+    };
+    Ok((cmd, order))
+}
+
+/// the replies are rejected as `{"error": ..}` or empty if the command is invalid
+fn parse_reply(payload: &[u8]) -> Result<Value, String> {
+    if payload.is_empty() {
+        return Err("invalid command".to_string());
+    }
+    let v: Value = serde_json::from_slice(payload).map_err(|_| "unexpected reply".to_string())?;
+    match v.get("error") {
+        Some(e) => Err(e.as_str().unwrap_or_default().to_string()),
+        None => Ok(v),
+    }
+}
+
+fn order_rejected(request: &FixMessage, text: &str) -> FixMessage {
+    let field = |tag| request.get(tag).unwrap_or_default();
+    FixMessage::new(EXECUTION_REPORT)
+        .with(ORDER_ID, "NONE")
+        .with(CL_ORD_ID, field(CL_ORD_ID))
+        .with(EXEC_ID, "NONE")
+        .with(EXEC_TYPE, '8')
+        .with(ORD_STATUS, '8')
+        .with(ACCOUNT, field(ACCOUNT))
+        .with(SYMBOL, field(SYMBOL))
+        .with(SIDE, field(SIDE))
+        .with(LEAVES_QTY, 0)
+        .with(CUM_QTY, 0)
+        .with(AVG_PX, 0)
+        .with(ORD_REJ_REASON, 99)
+        .with(TEXT, text)
+}
+
+fn cancel_rejected(request: &FixMessage, order_id: Option<OrderId>, text: &str) -> FixMessage {
+    let field = |tag| request.get(tag).unwrap_or_default();
+    FixMessage::new(ORDER_CANCEL_REJECT)
+        .with(
+            ORDER_ID,
+            order_id.map_or("NONE".to_string(), |id| id.to_string()),
+        )
+        .with(CL_ORD_ID, field(CL_ORD_ID))
+        .with(ORIG_CL_ORD_ID, field(ORIG_CL_ORD_ID))
+        .with(ORD_STATUS, '8')
+        .with(CXL_REJ_RESPONSE_TO, 1)
+        // unknown order
+        .with(CXL_REJ_REASON, if order_id.is_some() { 99 } else { 1 })
+        .with(TEXT, text)
+}
+
+fn session_rejected(request: &FixMessage, reason: u32, text: &str) -> FixMessage {
+    FixMessage::new(REJECT)
+        .with(REF_SEQ_NUM, request.get(MSG_SEQ_NUM).unwrap_or_default())
+        .with(REF_MSG_TYPE, &request.msg_type)
+        .with(SESSION_REJECT_REASON, reason)
+        .with(TEXT, text)
+}
+
+#[derive(Debug)]
+struct State {
+    // the SenderCompID of the client once logged on
+    counterparty: Option<String>,
+    heartbeat: Duration,
+    in_seq: u64,
+    out_seq: u64,
+    next_req: u64,
+    next_exec: u64,
+    pending: HashMap<u64, Pending>,
+    orders: HashMap<(Symbol, OrderId), Tracked>,
+    cl_ord_ids: HashMap<String, (Symbol, OrderId)>,
+}
+
+impl Default for State {
+    fn default() -> Self {
+        Self {
+            counterparty: None,
+            heartbeat: Duration::from_secs(DEFAULT_HEARTBEAT),
+            in_seq: 1,
+            out_seq: 0,
+            next_req: 0,
+            next_exec: 0,
+            pending: HashMap::new(),
+            orders: HashMap::new(),
+            cl_ord_ids: HashMap::new(),
+        }
+    }
+}
+
+impl State {
+    fn seal(&mut self, msg: FixMessage, comp_id: &str) -> Vec<u8> {
+        self.out_seq += 1;
+        let msg = match msg.msg_type.as_str() {
+            // the messages sent aren't kept, so the resend requests are replied by resetting
+            SEQUENCE_RESET => msg.with(NEW_SEQ_NO, self.out_seq + 1),
+            _ => msg,
+        };
+        let target = self.counterparty.as_deref().unwrap_or_default();
+        msg.encode(comp_id, target, self.out_seq, &sending_time())
+    }
+
+    fn submit(&mut self, pending: Pending) -> u64 {
+        self.next_req += 1;
+        self.pending.insert(self.next_req, pending);
+        self.next_req
+    }
+
+    fn execution_report(
+        &mut self,
+        order_id: OrderId,
+        order: &Tracked,
+        exec_type: char,
+        ord_status: char,
+    ) -> FixMessage {
+        self.next_exec += 1;
+        let leaves = match (ord_status, order.qty) {
+            ('0' | '1', Some(qty)) => qty - order.cum,
+            _ => Decimal::zero(),
+        };
+        let avg = match order.cum.is_zero() {
+            true => Decimal::zero(),
+            false => order.cum_quote / order.cum,
+        };
+        FixMessage::new(EXECUTION_REPORT)
+            .with(ORDER_ID, order_id)
+            .with(CL_ORD_ID, &order.cl_ord_id)
+            .with(EXEC_ID, self.next_exec)
+            .with(EXEC_TYPE, exec_type)
+            .with(ORD_STATUS, ord_status)
+            .with(ACCOUNT, &order.account)
+            .with(SYMBOL, symbol_str(&order.symbol))
+            .with(SIDE, &order.side)
+            .with(LEAVES_QTY, leaves.normalize())
+            .with(CUM_QTY, order.cum.normalize())
+            .with(AVG_PX, avg.normalize())
+    }
+
+    /// the replies of the requests and the fills of the orders of the session
+    fn translate(&mut self, msg: Message) -> Vec<FixMessage> {
+        match msg.broadcast_type {
+            0 => match self.pending.remove(&msg.req_id) {
+                Some(pending) => self.reply(pending, &msg.payload),
+                None => vec![],
+            },
+            ORDER_MATCHED => serde_json::from_slice::<FillReport>(&msg.payload)
+                .map(|r| self.fill(r))
+                .unwrap_or_default(),
+            _ => vec![],
+        }
+    }
+
+    fn reply(&mut self, pending: Pending, payload: &[u8]) -> Vec<FixMessage> {
+        match pending {
+            Pending::Order(request, order) => {
+                let accepted = parse_reply(payload).and_then(|v| {
+                    v.get("id")
+                        .and_then(|id| id.as_u64())
+                        .ok_or_else(|| "unexpected reply".to_string())
+                });
+                match accepted {
+                    Ok(order_id) => {
+                        let r = self.execution_report(order_id, &order, '0', '0');
+                        let key = (order.symbol, order_id);
+                        self.cl_ord_ids.insert(order.cl_ord_id.clone(), key);
+                        self.orders.insert(key, order);
+                        vec![r]
+                    }
+                    Err(e) => vec![order_rejected(&request, &e)],
+                }
+            }
+            Pending::Cancel(request, key) => match parse_reply(payload) {
+                Ok(_) => {
+                    let mut order = match self.orders.remove(&key) {
+                        Some(order) => order,
+                        // done already
+                        None => return vec![],
+                    };
+                    self.cl_ord_ids.remove(&order.cl_ord_id);
+                    order.cl_ord_id = request.get(CL_ORD_ID).unwrap_or_default().to_string();
+                    let r = self.execution_report(key.1, &order, '4', '4');
+                    vec![r.with(
+                        ORIG_CL_ORD_ID,
+                        request.get(ORIG_CL_ORD_ID).unwrap_or_default(),
+                    )]
+                }
+                Err(e) => vec![cancel_rejected(&request, Some(key.1), &e)],
+            },
+            Pending::MarketData(request, symbol) => {
+                let depth = parse_reply(payload).and_then(|v| {
+                    serde_json::from_value::<Option<DepthSnapshot>>(v).map_err(|e| e.to_string())
+                });
+                let md_req_id = request.get(MD_REQ_ID).unwrap_or_default();
+                match depth {
+                    Ok(Some(snapshot)) => {
+                        let depth = snapshot.depth;
+                        let mut w = FixMessage::new(MARKET_DATA_SNAPSHOT)
+                            .with(MD_REQ_ID, md_req_id)
+                            .with(SYMBOL, symbol_str(&symbol))
+                            .with(NO_MD_ENTRIES, depth.bids.len() + depth.asks.len());
+                        let levels = depth
+                            .bids
+                            .iter()
+                            .map(|l| ('0', l))
+                            .chain(depth.asks.iter().map(|l| ('1', l)));
+                        for (entry_type, (price, amount, _)) in levels {
+                            w = w
+                                .with(MD_ENTRY_TYPE, entry_type)
+                                .with(MD_ENTRY_PX, price)
+                                .with(MD_ENTRY_SIZE, amount);
+                        }
+                        vec![w]
+                    }
+                    // unknown symbol
+                    Ok(None) => vec![FixMessage::new(MARKET_DATA_REQUEST_REJECT)
+                        .with(MD_REQ_ID, md_req_id)
+                        .with(MD_REQ_REJ_REASON, '0')],
+                    Err(e) => vec![FixMessage::new(MARKET_DATA_REQUEST_REJECT)
+                        .with(MD_REQ_ID, md_req_id)
+                        .with(TEXT, e)],
+                }
+            }
+        }
+    }
+
+    fn fill(&mut self, report: FillReport) -> Vec<FixMessage> {
+        let o = report.order;
+        let key = (o.symbol, o.order_id);
+        let Some(mut order) = self.orders.remove(&key) else {
+            return vec![];
+        };
+        let last_qty = o.matched_base_amount - order.cum;
+        let last_px = match last_qty.is_zero() {
+            true => Decimal::zero(),
+            false => (o.matched_quote_amount - order.cum_quote) / last_qty,
+        };
+        order.cum = o.matched_base_amount;
+        order.cum_quote = o.matched_quote_amount;
+        let mut reports = vec![];
+        if last_qty > Decimal::zero() {
+            let ord_status = if o.status == 2 { '2' } else { '1' };
+            let r = self.execution_report(o.order_id, &order, 'F', ord_status);
+            reports.push(
+                r.with(LAST_QTY, last_qty.normalize())
+                    .with(LAST_PX, last_px.normalize()),
+            );
+        }
+        match o.status {
+            // the remaining of the IOC or market orders are canceled
+            1 | 4 => reports.push(self.execution_report(o.order_id, &order, '4', '4')),
+            2 => {}
+            _ => {
+                self.orders.insert(key, order);
+                return reports;
+            }
+        }
+        self.cl_ord_ids.remove(&order.cl_ord_id);
+        reports
+    }
+}
+
+struct Connection {
+    session_id: u64,
+    config: Arc<FixConfig>,
+    to_backend: ToBackend,
+    to_session: ToSession,
+    to_writer: UnboundedSender<FixMessage>,
+    shared: Shared,
+    state: Arc<Mutex<State>>,
+}
+
+impl Connection {
+    fn send(&self, msg: FixMessage) -> anyhow::Result<()> {
+        self.to_writer
+            .unbounded_send(msg)
+            .map_err(|_| anyhow!("the writer of session {} is closed", self.session_id))
+    }
+
+    async fn submit(&mut self, cmd: Command, pending: Pending) -> anyhow::Result<()> {
+        let req_id = self.state.lock().expect("lock;qed").submit(pending);
+        let body = serde_json::to_string(&cmd)?;
+        server::handle_req(
+            &mut self.to_backend,
+            &mut self.to_session,
+            &self.shared,
+            self.session_id,
+            req_id,
+            body,
+        )
+        .await
+        .map_err(|e| anyhow!("{:?}", e))
+    }
+
+    fn logon(&self, msg: &FixMessage) -> anyhow::Result<bool> {
+        ensure!(msg.msg_type == LOGON, "the first message must be Logon");
+        ensure!(
+            msg.get(TARGET_COMP_ID) == Some(self.config.comp_id.as_str()),
+            "TargetCompID mismatched"
+        );
+        let counterparty = msg.require(SENDER_COMP_ID)?.to_string();
+        let heartbeat = msg
+            .get(HEART_BT_INT)
+            .and_then(|h| h.parse::<u64>().ok())
+            .filter(|h| *h > 0)
+            .unwrap_or(DEFAULT_HEARTBEAT);
+        {
+            let mut state = self.state.lock().expect("lock;qed");
+            state.counterparty.replace(counterparty);
+            state.heartbeat = Duration::from_secs(heartbeat);
+        }
+        if self.config.password.is_some() && msg.get(PASSWORD) != self.config.password.as_deref() {
+            self.send(FixMessage::new(LOGOUT).with(TEXT, "invalid password"))?;
+            return Ok(false);
+        }
+        let mut reply = FixMessage::new(LOGON)
+            .with(ENCRYPT_METHOD, 0)
+            .with(HEART_BT_INT, heartbeat);
+        if msg.get(RESET_SEQ_NUM_FLAG) == Some("Y") {
+            reply = reply.with(RESET_SEQ_NUM_FLAG, 'Y');
+        }
+        self.send(reply)?;
+        Ok(true)
+    }
+
+    /// `false` if the session is logged out
+    async fn handle(&mut self, msg: FixMessage) -> anyhow::Result<bool> {
+        let seq: u64 = msg.require(MSG_SEQ_NUM)?.parse()?;
+        let logged_on = self.state.lock().expect("lock;qed").counterparty.is_some();
+        if !logged_on && !self.logon(&msg)? {
+            return Ok(false);
+        }
+        {
+            let mut state = self.state.lock().expect("lock;qed");
+            if seq < state.in_seq && msg.get(POSS_DUP_FLAG) != Some("Y") {
+                drop(state);
+                self.send(FixMessage::new(LOGOUT).with(TEXT, "MsgSeqNum too low"))?;
+                return Ok(false);
+            }
+            if seq > state.in_seq {
+                log::warn!(
+                    "fix session {} expected MsgSeqNum {} but received {}",
+                    self.session_id,
+                    state.in_seq,
+                    seq
+                );
+            }
+            state.in_seq = state.in_seq.max(seq + 1);
+        }
+        match msg.msg_type.as_str() {
+            LOGON if logged_on => {
+                self.send(session_rejected(&msg, 11, "logged on already"))?;
+            }
+            LOGON | HEARTBEAT => {}
+            TEST_REQUEST => {
+                let id = msg.get(TEST_REQ_ID).unwrap_or_default();
+                self.send(FixMessage::new(HEARTBEAT).with(TEST_REQ_ID, id))?;
+            }
+            RESEND_REQUEST => {
+                self.send(FixMessage::new(SEQUENCE_RESET).with(GAP_FILL_FLAG, 'N'))?;
+            }
+            SEQUENCE_RESET => {
+                let next: u64 = msg.require(NEW_SEQ_NO)?.parse()?;
+                self.state.lock().expect("lock;qed").in_seq = next;
+            }
+            LOGOUT => {
+                self.send(FixMessage::new(LOGOUT))?;
+                return Ok(false);
+            }
+            NEW_ORDER_SINGLE => match to_place_cmd(&msg) {
+                Ok((cmd, order)) => self.submit(cmd, Pending::Order(msg, order)).await?,
+                Err(e) => self.send(order_rejected(&msg, &e.to_string()))?,
+            },
+            ORDER_CANCEL_REQUEST => self.cancel(msg).await?,
+            MARKET_DATA_REQUEST => self.market_data(msg).await?,
+            _ => self.send(session_rejected(&msg, 11, "MsgType isn't supported"))?,
+        }
+        Ok(true)
+    }
+
+    async fn cancel(&mut self, msg: FixMessage) -> anyhow::Result<()> {
+        let translated = || -> anyhow::Result<(Command, (Symbol, OrderId))> {
+            msg.require(CL_ORD_ID)?;
+            let symbol = symbol_of(msg.require(SYMBOL)?)?;
+            let order_id = match msg.get(ORDER_ID) {
+                Some(id) => id.parse()?,
+                None => {
+                    let orig = msg.require(ORIG_CL_ORD_ID)?;
+                    let state = self.state.lock().expect("lock;qed");
+                    state
+                        .cl_ord_ids
+                        .get(orig)
+                        .filter(|k| k.0 == symbol)
+                        .map(|k| k.1)
+                        .ok_or_else(|| anyhow!("unknown order"))?
+                }
+            };
+            let (nonce, signature) = signed(&msg)?;
+            let cmd = Command {
+                cmd: CANCEL,
+                base: Some(symbol.0),
+                quote: Some(symbol.1),
+                user_id: Some(msg.require(ACCOUNT)?.to_string()),
+                order_id: Some(order_id),
+                nonce: Some(nonce),
+                signature: Some(signature),
+                ..Default::default()
+            };
+            Ok((cmd, (symbol, order_id)))
+        };
+        match translated() {
+            Ok((cmd, key)) => self.submit(cmd, Pending::Cancel(msg, key)).await,
+            Err(e) => self.send(cancel_rejected(&msg, None, &e.to_string())),
+        }
+    }
+
+    /// only the snapshots are supported, one for each symbol requested
+    async fn market_data(&mut self, msg: FixMessage) -> anyhow::Result<()> {
+        let md_req_id = msg.get(MD_REQ_ID).unwrap_or_default();
+        if msg.get(SUBSCRIPTION_REQUEST_TYPE) != Some("0") {
+            // unsupported subscription type
+            return self.send(
+                FixMessage::new(MARKET_DATA_REQUEST_REJECT)
+                    .with(MD_REQ_ID, md_req_id)
+                    .with(MD_REQ_REJ_REASON, '4'),
+            );
+        }
+        let limit = msg
+            .get(MARKET_DEPTH)
+            .and_then(|d| d.parse::<u32>().ok())
+            .filter(|d| *d > 0);
+        let symbols = msg
+            .get_all(SYMBOL)
+            .map(symbol_of)
+            .collect::<anyhow::Result<Vec<_>>>();
+        let symbols = match symbols {
+            Ok(symbols) if !symbols.is_empty() => symbols,
+            _ => {
+                return self.send(
+                    FixMessage::new(MARKET_DATA_REQUEST_REJECT)
+                        .with(MD_REQ_ID, md_req_id)
+                        .with(MD_REQ_REJ_REASON, '0'),
+                )
+            }
+        };
+        for symbol in symbols {
+            let cmd = Command {
+                cmd: QUERY_DEPTH,
+                base: Some(symbol.0),
+                quote: Some(symbol.1),
+                limit,
+                ..Default::default()
+            };
+            self.submit(cmd, Pending::MarketData(msg.clone(), symbol))
+                .await?;
+        }
+        Ok(())
+    }
+}
+
+/// the FIX 4.4 sessions are translated to the json commands and replied through the same
+/// relayer as the tcp sessions, the orders are signed with the user defined tags
+/// `Nonce`(7001) and `Signature`(7002) the same as the tcp commands
+pub fn init(to_backend: ToBackend, shared: Shared, sessions: Sessions) {
+    let config = match C.fix {
+        Some(ref fix) => Arc::new(fix.clone()),
+        None => return,
+    };
+    let listener = task::block_on(async { TcpListener::bind(&config.bind_addr).await }).unwrap();
+    std::thread::spawn(move || {
+        log::info!("fix gateway initialized");
+        let r = task::block_on(accept(listener, config, to_backend, shared, sessions));
+        log::error!("fix gateway interrupted, {:?}", r);
+    });
+}
+
+async fn accept(
+    listener: TcpListener,
+    config: Arc<FixConfig>,
+    to_backend: ToBackend,
+    shared: Shared,
+    sessions: Sessions,
+) -> anyhow::Result<()> {
+    let mut incoming = listener.incoming();
+    while let Some(stream) = incoming.next().await {
+        let stream = Arc::new(stream?);
+        if stream.set_nodelay(true).is_err() {
+            continue;
+        }
+        let session_id = server::next_session();
+        let (to_session, from_engine) = mpsc::unbounded();
+        let (to_writer, from_reader) = mpsc::unbounded();
+        sessions.insert(session_id, to_session.clone());
+        let state = Arc::new(Mutex::new(State::default()));
+        task::spawn(write_loop(
+            from_engine,
+            from_reader,
+            stream.clone(),
+            state.clone(),
+            config.clone(),
+        ));
+        let conn = Connection {
+            session_id,
+            config: config.clone(),
+            to_backend: to_backend.clone(),
+            to_session,
+            to_writer,
+            shared: shared.clone(),
+            state,
+        };
+        let sessions = sessions.clone();
+        task::spawn(async move {
+            if let Err(e) = read_loop(conn, stream).await {
+                log::info!("fix session {} closed, {:?}", session_id, e);
+            }
+            server::close_session(&sessions, session_id);
+        });
+    }
+    Ok(())
+}
+
+/// the session is closed if nothing received in 2 heartbeats
+async fn read_loop(mut conn: Connection, stream: Arc<TcpStream>) -> anyhow::Result<()> {
+    let mut stream = &*stream;
+    let mut buf = Vec::<u8>::with_capacity(4096);
+    let mut tmp = [0_u8; 4096];
+    loop {
+        while let Some(msg) = FixMessage::decode(&mut buf)? {
+            log::debug!("fix session {} received {:?}", conn.session_id, msg);
+            if !conn.handle(msg).await? {
+                return Ok(());
+            }
+        }
+        let heartbeat = conn.state.lock().expect("lock;qed").heartbeat;
+        let n = io::timeout(heartbeat * 2, stream.read(&mut tmp)).await?;
+        if n == 0 {
+            return Ok(());
+        }
+        buf.extend_from_slice(&tmp[..n]);
+    }
+}
+
+enum Outgoing {
+    Engine(Message),
+    Session(FixMessage),
+}
+
+/// the heartbeats are sent if nothing sent in an interval
+async fn write_loop(
+    from_engine: FromSession,
+    from_reader: UnboundedReceiver<FixMessage>,
+    stream: Arc<TcpStream>,
+    state: Arc<Mutex<State>>,
+    config: Arc<FixConfig>,
+) {
+    let mut outgoing = stream::select(
+        from_engine.map(Outgoing::Engine),
+        from_reader.map(Outgoing::Session),
+    );
+    let mut writer = &*stream;
+    loop {
+        let heartbeat = state.lock().expect("lock;qed").heartbeat;
+        let next = async_std::future::timeout(heartbeat, outgoing.next()).await;
+        let bytes = {
+            let mut state = state.lock().expect("lock;qed");
+            let msgs = match next {
+                Ok(Some(Outgoing::Engine(msg))) => state.translate(msg),
+                Ok(Some(Outgoing::Session(msg))) => vec![msg],
+                Ok(None) => break,
+                Err(_) if state.counterparty.is_some() => vec![FixMessage::new(HEARTBEAT)],
+                Err(_) => vec![],
+            };
+            msgs.into_iter()
+                .flat_map(|msg| state.seal(msg, &config.comp_id))
+                .collect::<Vec<_>>()
+        };
+        if !bytes.is_empty() && writer.write_all(&bytes).await.is_err() {
+            break;
+        }
+    }
+    let _ = stream.shutdown(Shutdown::Both);
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn checksum(raw: &[u8]) -> u8 {
+        raw.iter().fold(0_u8, |s, b| s.wrapping_add(*b))
+    }
+
+    #[test]
+    pub fn test_codec() {
+        let msg = FixMessage::new(NEW_ORDER_SINGLE)
+            .with(CL_ORD_ID, "c-1")
+            .with(SYMBOL, "1/0")
+            .with(NO_RELATED_SYM, 2)
+            .with(SYMBOL, "2/0");
+        let raw = msg.encode("CLIENT", "GALOIS", 7, "20230101-00:00:00.000");
+        let text = String::from_utf8(raw.clone()).unwrap();
+        assert!(text.starts_with("8=FIX.4.4\x019="));
+        assert!(text.contains("\x0135=D\x0149=CLIENT\x0156=GALOIS\x0134=7\x01"));
+        let trailer = raw.len() - 7;
+        assert_eq!(
+            format!("10={:03}\x01", checksum(&raw[..trailer])),
+            text[trailer..]
+        );
+        let mut buf = raw.clone();
+        buf.extend_from_slice(&raw[..10]);
+        let decoded = FixMessage::decode(&mut buf).unwrap().unwrap();
+        assert_eq!(NEW_ORDER_SINGLE, decoded.msg_type);
+        assert_eq!(Some("7"), decoded.get(MSG_SEQ_NUM));
+        assert_eq!(Some("c-1"), decoded.get(CL_ORD_ID));
+        assert_eq!(
+            vec!["1/0", "2/0"],
+            decoded.get_all(SYMBOL).collect::<Vec<_>>()
+        );
+        // the next one isn't complete
+        assert_eq!(10, buf.len());
+        assert!(FixMessage::decode(&mut buf).unwrap().is_none());
+        buf.extend_from_slice(&raw[10..]);
+        assert!(FixMessage::decode(&mut buf).unwrap().is_some());
+        assert!(buf.is_empty());
+
+        let mut corrupted = raw.clone();
+        let i = corrupted.len() - 9;
+        corrupted[i] = b'X';
+        assert!(FixMessage::decode(&mut corrupted).is_err());
+        let mut other = b"8=FIX.4.2\x019=5\x01".to_vec();
+        assert!(FixMessage::decode(&mut other).is_err());
+    }
+
+    #[test]
+    pub fn test_session() {
+        let account = "0x0ae466861e8397f1e3beadac1a49dc111beea3b62d34a6eb4b5be370f5aada30";
+        let order = FixMessage::new(NEW_ORDER_SINGLE)
+            .with(CL_ORD_ID, "c-1")
+            .with(ACCOUNT, account)
+            .with(SYMBOL, "1/0")
+            .with(SIDE, '1')
+            .with(ORD_TYPE, '2')
+            .with(ORDER_QTY, "2")
+            .with(PRICE, "10.5")
+            .with(TIME_IN_FORCE, '3')
+            .with(NONCE, 1)
+            .with(SIGNATURE, "00");
+        let (cmd, tracked) = to_place_cmd(&order).unwrap();
+        assert_eq!(BID_LIMIT, cmd.cmd);
+        assert_eq!(Some((1, 0)), cmd.symbol());
+        assert_eq!(Some(TimeInForce::ImmediateOrCancel), cmd.time_in_force);
+        assert_eq!(Some("c-1".to_string()), cmd.client_order_id);
+        assert_eq!(Some(Decimal::from(2)), tracked.qty);
+        let without = |tags: &[u32]| FixMessage {
+            fields: order
+                .fields
+                .iter()
+                .filter(|(t, _)| !tags.contains(t))
+                .cloned()
+                .collect(),
+            ..order.clone()
+        };
+        let market = without(&[ORD_TYPE, ORDER_QTY, PRICE])
+            .with(ORD_TYPE, '1')
+            .with(CASH_ORDER_QTY, 100);
+        let (cmd, tracked) = to_place_cmd(&market).unwrap();
+        assert_eq!(MARKET_BID, cmd.cmd);
+        assert_eq!(Some(Decimal::from(100)), cmd.vol);
+        assert!(cmd.price.is_none() && cmd.client_order_id.is_none() && tracked.qty.is_none());
+        assert!(to_place_cmd(&without(&[SIGNATURE])).is_err());
+        assert!(to_place_cmd(&without(&[ORD_TYPE]).with(ORD_TYPE, '3')).is_err());
+
+        let mut state = State::default();
+        let (_, tracked) = to_place_cmd(&order).unwrap();
+        let req_id = state.submit(Pending::Order(order.clone(), tracked));
+        let r = state.translate(Message::new_req(
+            req_id,
+            br#"{"id":7,"event_id":12,"client_order_id":"c-1"}"#.to_vec(),
+        ));
+        assert_eq!(1, r.len());
+        assert_eq!(Some("0"), r[0].get(EXEC_TYPE));
+        assert_eq!(Some("7"), r[0].get(ORDER_ID));
+        assert_eq!(Some("2"), r[0].get(LEAVES_QTY));
+        assert_eq!(Some(&((1, 0), 7)), state.cl_ord_ids.get("c-1"));
+        let fill = |status: u8, base: &str, quote: &str| {
+            let report = FillReport {
+                order: crate::orders::PendingOrder {
+                    order_id: 7,
+                    user_id: account.parse().unwrap(),
+                    symbol: (1, 0),
+                    direction: 1,
+                    create_timestamp: 0,
+                    amount: Decimal::from(2),
+                    price: "10.5".parse().unwrap(),
+                    status,
+                    matched_quote_amount: quote.parse().unwrap(),
+                    matched_base_amount: base.parse().unwrap(),
+                    base_fee: Decimal::zero(),
+                    quote_fee: Decimal::zero(),
+                },
+                execution: None,
+                timestamp_us: 0,
+            };
+            Message::new_broadcast(ORDER_MATCHED, serde_json::to_vec(&report).unwrap())
+        };
+        let r = state.translate(fill(3, "0.5", "5"));
+        assert_eq!(1, r.len());
+        assert_eq!(Some("F"), r[0].get(EXEC_TYPE));
+        assert_eq!(Some("1"), r[0].get(ORD_STATUS));
+        assert_eq!(Some("0.5"), r[0].get(LAST_QTY));
+        assert_eq!(Some("10"), r[0].get(LAST_PX));
+        assert_eq!(Some("1.5"), r[0].get(LEAVES_QTY));
+        // IOC, the remaining is canceled
+        let r = state.translate(fill(4, "1.5", "15.5"));
+        assert_eq!(2, r.len());
+        assert_eq!(Some("1"), r[0].get(LAST_QTY));
+        assert_eq!(Some("10.5"), r[0].get(LAST_PX));
+        assert_eq!(Some("4"), r[1].get(EXEC_TYPE));
+        assert_eq!(Some("1.5"), r[1].get(CUM_QTY));
+        assert_eq!(Some("0"), r[1].get(LEAVES_QTY));
+        assert!(state.orders.is_empty() && state.cl_ord_ids.is_empty());
+        // not of the session
+        assert!(state.translate(fill(2, "2", "21")).is_empty());
+
+        let (_, tracked) = to_place_cmd(&order).unwrap();
+        let req_id = state.submit(Pending::Order(order.clone(), tracked));
+        let r = state.translate(Message::new_req(
+            req_id,
+            br#"{"error":"duplicated client order id","code":11,"order_id":7}"#.to_vec(),
+        ));
+        assert_eq!(Some("8"), r[0].get(EXEC_TYPE));
+        assert_eq!(Some("duplicated client order id"), r[0].get(TEXT));
+        let (_, tracked) = to_place_cmd(&order).unwrap();
+        let req_id = state.submit(Pending::Order(order.clone(), tracked));
+        state.translate(Message::new_req(
+            req_id,
+            br#"{"id":8,"event_id":13}"#.to_vec(),
+        ));
+        let cancel = FixMessage::new(ORDER_CANCEL_REQUEST)
+            .with(CL_ORD_ID, "c-2")
+            .with(ORIG_CL_ORD_ID, "c-1");
+        let req_id = state.submit(Pending::Cancel(cancel.clone(), ((1, 0), 8)));
+        let r = state.translate(Message::new_req(req_id, b"".to_vec()));
+        assert_eq!(ORDER_CANCEL_REJECT, r[0].msg_type);
+        assert_eq!(Some("invalid command"), r[0].get(TEXT));
+        let req_id = state.submit(Pending::Cancel(cancel, ((1, 0), 8)));
+        let r = state.translate(Message::new_req(
+            req_id,
+            br#"{"id":8,"event_id":14}"#.to_vec(),
+        ));
+        assert_eq!(Some("4"), r[0].get(EXEC_TYPE));
+        assert_eq!(Some("c-2"), r[0].get(CL_ORD_ID));
+        assert_eq!(Some("c-1"), r[0].get(ORIG_CL_ORD_ID));
+        assert!(state.orders.is_empty());
+
+        let md = FixMessage::new(MARKET_DATA_REQUEST).with(MD_REQ_ID, "m-1");
+        let req_id = state.submit(Pending::MarketData(md.clone(), (1, 0)));
+        let snapshot = br#"{"update_id":5,"asks":[["10.50","1.000","0"]],"bids":[["10.00","2.000","0"],["9.00","1.000","0"]],"symbol":[1,0]}"#;
+        let r = state.translate(Message::new_req(req_id, snapshot.to_vec()));
+        assert_eq!(MARKET_DATA_SNAPSHOT, r[0].msg_type);
+        assert_eq!(Some("3"), r[0].get(NO_MD_ENTRIES));
+        assert_eq!(
+            vec!["0", "0", "1"],
+            r[0].get_all(MD_ENTRY_TYPE).collect::<Vec<_>>()
+        );
+        assert_eq!(
+            vec!["10.00", "9.00", "10.50"],
+            r[0].get_all(MD_ENTRY_PX).collect::<Vec<_>>()
+        );
+        let req_id = state.submit(Pending::MarketData(md, (2, 0)));
+        let r = state.translate(Message::new_req(req_id, b"null".to_vec()));
+        assert_eq!(MARKET_DATA_REQUEST_REJECT, r[0].msg_type);
+    }
+}
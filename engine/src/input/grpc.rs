@@ -0,0 +1,470 @@
+// Copyright 2021-2023 UINB Technologies Pte. Ltd.
+
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// 	http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::{
+    config::C,
+    input::{
+        cmd::*,
+        server::{self, FromSession, Sessions, ToBackend},
+        Command, DEPTH_DELTA, TRADE_EXECUTED,
+    },
+    matcher::{SelfTradePrevention, TimeInForce},
+    orderbook::{AskOrBid, Order},
+    output,
+    shared::Shared,
+};
+use futures::{channel::mpsc, stream, Stream, StreamExt};
+use proto::{
+    galois_server::{Galois, GaloisServer},
+    *,
+};
+use serde::de::DeserializeOwned;
+use std::{pin::Pin, sync::Mutex};
+use tonic::{transport::Server, Request, Response, Status};
+
+pub mod proto {
+    tonic::include_proto!("galois");
+}
+
+/// the calls are translated to the json commands and replied through the same relayer as
+/// the tcp sessions, so they are sequenced, rejected and accounted exactly the same
+pub fn init(to_backend: ToBackend, shared: Shared, sessions: Sessions) {
+    let addr = match C.grpc {
+        Some(ref grpc) => grpc.bind_addr.parse().expect("validated;qed"),
+        None => return,
+    };
+    let service = GaloisService {
+        to_backend: Mutex::new(to_backend),
+        shared,
+        sessions,
+    };
+    std::thread::spawn(move || {
+        let rt = tokio::runtime::Runtime::new().expect("tokio runtime;qed");
+        log::info!("grpc server initialized");
+        let r = rt.block_on(
+            Server::builder()
+                .add_service(GaloisServer::new(service))
+                .serve(addr),
+        );
+        log::error!("grpc server interrupted, {:?}", r);
+    });
+}
+
+/// the session is closed with the call or the stream, e.g. the client goes away
+struct Session {
+    id: u64,
+    sessions: Sessions,
+}
+
+impl Session {
+    fn open(sessions: &Sessions) -> (Self, FromSession) {
+        let id = server::next_session();
+        let (tx, rx) = mpsc::unbounded();
+        sessions.insert(id, tx);
+        let session = Self {
+            id,
+            sessions: sessions.clone(),
+        };
+        (session, rx)
+    }
+}
+
+impl Drop for Session {
+    fn drop(&mut self) {
+        server::close_session(&self.sessions, self.id);
+    }
+}
+
+struct GaloisService {
+    // `Sender` isn't `Sync`
+    to_backend: Mutex<ToBackend>,
+    shared: Shared,
+    sessions: Sessions,
+}
+
+// each call takes a session of its own, so the only request of it is always 1
+const REQ_ID: u64 = 1;
+
+impl GaloisService {
+    async fn call(&self, cmd: Command) -> Result<Vec<u8>, Status> {
+        let (session, mut rx) = Session::open(&self.sessions);
+        let mut to_back = self.to_backend.lock().expect("lock;qed").clone();
+        let mut to_session = self
+            .sessions
+            .get(&session.id)
+            .map(|s| s.clone())
+            .ok_or_else(|| Status::internal("session not found"))?;
+        let body = serde_json::to_string(&cmd).expect("qed;");
+        server::handle_req(
+            &mut to_back,
+            &mut to_session,
+            &self.shared,
+            session.id,
+            REQ_ID,
+            body,
+        )
+        .await
+        .map_err(|e| Status::internal(e.to_string()))?;
+        while let Some(msg) = rx.next().await {
+            if msg.broadcast_type == 0 && msg.req_id == REQ_ID {
+                return Ok(msg.payload);
+            }
+        }
+        Err(Status::unavailable("the engine is shutting down"))
+    }
+
+    fn subscribe<T, F>(&self, broadcast_type: u8, f: F) -> BroadcastStream<T>
+    where
+        T: Send + 'static,
+        F: Fn(Vec<u8>) -> Vec<T> + Send + 'static,
+    {
+        let (session, rx) = Session::open(&self.sessions);
+        Box::pin(
+            rx.filter(move |msg| futures::future::ready(msg.broadcast_type == broadcast_type))
+                .flat_map(move |msg| stream::iter(f(msg.payload)))
+                .map(move |v| {
+                    // moved into the stream so it is closed once the stream is dropped
+                    let _ = &session;
+                    Ok(v)
+                }),
+        )
+    }
+}
+
+type BroadcastStream<T> = Pin<Box<dyn Stream<Item = Result<T, Status>> + Send>>;
+
+fn symbol_of(symbol: Option<Symbol>) -> Result<crate::core::Symbol, Status> {
+    symbol
+        .map(|s| (s.base, s.quote))
+        .ok_or_else(|| Status::invalid_argument("symbol is required"))
+}
+
+fn decimal_of(v: &str, field: &str) -> Result<rust_decimal::Decimal, Status> {
+    v.parse()
+        .map_err(|_| Status::invalid_argument(format!("invalid {}", field)))
+}
+
+/// the replies are rejected as `{"error": ..}` or empty if the command is invalid or nothing found
+fn parse_reply<T: DeserializeOwned>(payload: &[u8]) -> Result<T, Status> {
+    if payload.is_empty() {
+        return Err(Status::invalid_argument("invalid command"));
+    }
+    let v: serde_json::Value = serde_json::from_slice(payload)
+        .map_err(|e| Status::internal(format!("unexpected reply, {:?}", e)))?;
+    if let Some(e) = v.get("error") {
+        return Err(Status::failed_precondition(
+            e.as_str().unwrap_or_default().to_string(),
+        ));
+    }
+    serde_json::from_value(v).map_err(|e| Status::internal(format!("unexpected reply, {:?}", e)))
+}
+
+/// `(order_id, event_id)` of the writes
+fn reply_id(payload: &[u8]) -> Result<(u64, u64), Status> {
+    let v: serde_json::Value = parse_reply(payload)?;
+    let field = |k: &str| {
+        v.get(k)
+            .and_then(|id| id.as_u64())
+            .ok_or_else(|| Status::internal("unexpected reply"))
+    };
+    Ok((field("id")?, field("event_id")?))
+}
+
+fn to_place_cmd(req: PlaceOrderRequest) -> Result<Command, Status> {
+    let side = req.side();
+    let order_type = req.order_type();
+    let time_in_force = match req.time_in_force() {
+        proto::TimeInForce::Gtc => TimeInForce::GoodTillCancel,
+        proto::TimeInForce::Ioc => TimeInForce::ImmediateOrCancel,
+        proto::TimeInForce::Fok => TimeInForce::FillOrKill,
+    };
+    let self_trade_prevention =
+        req.self_trade_prevention
+            .map(|_| match req.self_trade_prevention() {
+                proto::SelfTradePrevention::CancelNewest => SelfTradePrevention::CancelNewest,
+                proto::SelfTradePrevention::CancelOldest => SelfTradePrevention::CancelOldest,
+                proto::SelfTradePrevention::CancelBoth => SelfTradePrevention::CancelBoth,
+            });
+    let symbol = symbol_of(req.symbol)?;
+    let limit = order_type == OrderType::Limit;
+    Ok(Command {
+        cmd: match (order_type, side) {
+            (OrderType::Limit, Side::Ask) => ASK_LIMIT,
+            (OrderType::Limit, Side::Bid) => BID_LIMIT,
+            (OrderType::Market, Side::Ask) => MARKET_ASK,
+            (OrderType::Market, Side::Bid) => MARKET_BID,
+        },
+        base: Some(symbol.0),
+        quote: Some(symbol.1),
+        user_id: Some(req.user_id),
+        amount: req
+            .vol
+            .is_none()
+            .then(|| decimal_of(&req.amount, "amount"))
+            .transpose()?,
+        vol: req
+            .vol
+            .as_deref()
+            .map(|v| decimal_of(v, "vol"))
+            .transpose()?,
+        price: limit.then(|| decimal_of(&req.price, "price")).transpose()?,
+        time_in_force: limit.then_some(time_in_force),
+        nonce: Some(req.nonce),
+        signature: Some(req.signature),
+        broker: req.broker,
+        self_trade_prevention,
+        ..Default::default()
+    })
+}
+
+fn side_of(side: AskOrBid) -> i32 {
+    match side {
+        AskOrBid::Ask => Side::Ask as i32,
+        AskOrBid::Bid => Side::Bid as i32,
+    }
+}
+
+fn filter_symbols(symbols: &[Symbol]) -> Vec<crate::core::Symbol> {
+    symbols.iter().map(|s| (s.base, s.quote)).collect()
+}
+
+fn to_trades(payload: &[u8], symbols: &[crate::core::Symbol]) -> Vec<proto::Trade> {
+    serde_json::from_slice::<Vec<output::Trade>>(payload)
+        .unwrap_or_default()
+        .into_iter()
+        .filter(|t| symbols.is_empty() || symbols.contains(&t.symbol))
+        .map(|t| proto::Trade {
+            event_id: t.event_id,
+            symbol: Some(Symbol {
+                base: t.symbol.0,
+                quote: t.symbol.1,
+            }),
+            price: t.price.to_string(),
+            amount: t.amount.to_string(),
+            taker_side: side_of(t.taker_side),
+            timestamp: t.timestamp,
+            timestamp_us: t.timestamp_us,
+        })
+        .collect()
+}
+
+fn to_depth(payload: &[u8], symbols: &[crate::core::Symbol]) -> Option<proto::DepthDelta> {
+    let levels = |l: Vec<(crate::core::Price, crate::core::Amount)>| {
+        l.into_iter()
+            .map(|(price, amount)| Level {
+                price: price.to_string(),
+                amount: amount.to_string(),
+            })
+            .collect()
+    };
+    serde_json::from_slice::<output::DepthDelta>(payload)
+        .ok()
+        .filter(|d| symbols.is_empty() || symbols.contains(&d.symbol))
+        .map(|d| proto::DepthDelta {
+            symbol: Some(Symbol {
+                base: d.symbol.0,
+                quote: d.symbol.1,
+            }),
+            update_id: d.update_id,
+            prev_update_id: d.prev_update_id,
+            asks: levels(d.asks),
+            bids: levels(d.bids),
+        })
+}
+
+#[tonic::async_trait]
+impl Galois for GaloisService {
+    type SubscribeDepthStream = BroadcastStream<proto::DepthDelta>;
+    type SubscribeTradesStream = BroadcastStream<proto::Trade>;
+
+    async fn place_order(
+        &self,
+        request: Request<PlaceOrderRequest>,
+    ) -> Result<Response<PlaceOrderReply>, Status> {
+        let cmd = to_place_cmd(request.into_inner())?;
+        let payload = self.call(cmd).await?;
+        let (order_id, event_id) = reply_id(&payload)?;
+        Ok(Response::new(PlaceOrderReply { order_id, event_id }))
+    }
+
+    async fn cancel_order(
+        &self,
+        request: Request<CancelOrderRequest>,
+    ) -> Result<Response<CancelOrderReply>, Status> {
+        let req = request.into_inner();
+        let symbol = symbol_of(req.symbol)?;
+        let cmd = Command {
+            cmd: CANCEL,
+            base: Some(symbol.0),
+            quote: Some(symbol.1),
+            user_id: Some(req.user_id),
+            order_id: Some(req.order_id),
+            nonce: Some(req.nonce),
+            signature: Some(req.signature),
+            ..Default::default()
+        };
+        let payload = self.call(cmd).await?;
+        let (order_id, event_id) = reply_id(&payload)?;
+        Ok(Response::new(CancelOrderReply { order_id, event_id }))
+    }
+
+    async fn query_balance(
+        &self,
+        request: Request<QueryBalanceRequest>,
+    ) -> Result<Response<Balance>, Status> {
+        let req = request.into_inner();
+        let cmd = Command {
+            cmd: QUERY_BALANCE,
+            user_id: Some(req.user_id),
+            currency: Some(req.currency),
+            ..Default::default()
+        };
+        let payload = self.call(cmd).await?;
+        let balance: crate::assets::Balance = parse_reply(&payload)?;
+        Ok(Response::new(Balance {
+            available: balance.available.to_string(),
+            frozen: balance.frozen.to_string(),
+        }))
+    }
+
+    async fn query_order(
+        &self,
+        request: Request<QueryOrderRequest>,
+    ) -> Result<Response<proto::Order>, Status> {
+        let req = request.into_inner();
+        let symbol = symbol_of(req.symbol)?;
+        let cmd = Command {
+            cmd: QUERY_ORDER,
+            base: Some(symbol.0),
+            quote: Some(symbol.1),
+            order_id: Some(req.order_id),
+            ..Default::default()
+        };
+        let payload = self.call(cmd).await?;
+        if payload.is_empty() {
+            return Err(Status::not_found("order not found"));
+        }
+        let order: Order = parse_reply(&payload)?;
+        Ok(Response::new(proto::Order {
+            order_id: order.id,
+            user_id: order.user.to_string(),
+            price: order.price.to_string(),
+            unfilled: order.unfilled.to_string(),
+        }))
+    }
+
+    async fn subscribe_trades(
+        &self,
+        request: Request<SubscribeRequest>,
+    ) -> Result<Response<Self::SubscribeTradesStream>, Status> {
+        let symbols = filter_symbols(&request.into_inner().symbols);
+        Ok(Response::new(self.subscribe(TRADE_EXECUTED, move |p| {
+            to_trades(&p, &symbols)
+        })))
+    }
+
+    async fn subscribe_depth(
+        &self,
+        request: Request<SubscribeRequest>,
+    ) -> Result<Response<Self::SubscribeDepthStream>, Status> {
+        let symbols = filter_symbols(&request.into_inner().symbols);
+        Ok(Response::new(self.subscribe(DEPTH_DELTA, move |p| {
+            to_depth(&p, &symbols).into_iter().collect()
+        })))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    pub fn test_translate() {
+        let req = PlaceOrderRequest {
+            user_id: "0x0ae466861e8397f1e3beadac1a49dc111beea3b62d34a6eb4b5be370f5aada30"
+                .to_string(),
+            symbol: Some(Symbol { base: 1, quote: 0 }),
+            side: Side::Bid as i32,
+            order_type: OrderType::Limit as i32,
+            price: "10.5".to_string(),
+            amount: "2".to_string(),
+            nonce: 1,
+            signature: "00".to_string(),
+            broker: None,
+            time_in_force: proto::TimeInForce::Ioc as i32,
+            self_trade_prevention: Some(proto::SelfTradePrevention::CancelBoth as i32),
+            vol: None,
+        };
+        let cmd = to_place_cmd(req.clone()).unwrap();
+        assert_eq!(BID_LIMIT, cmd.cmd);
+        assert_eq!(Some((1, 0)), cmd.symbol());
+        assert_eq!(Some(TimeInForce::ImmediateOrCancel), cmd.time_in_force);
+        assert_eq!(
+            Some(SelfTradePrevention::CancelBoth),
+            cmd.self_trade_prevention
+        );
+        let market = to_place_cmd(PlaceOrderRequest {
+            order_type: OrderType::Market as i32,
+            price: String::new(),
+            self_trade_prevention: None,
+            ..req.clone()
+        })
+        .unwrap();
+        assert_eq!(MARKET_BID, market.cmd);
+        assert!(market.price.is_none());
+        assert!(market.self_trade_prevention.is_none());
+        let by_quote = to_place_cmd(PlaceOrderRequest {
+            order_type: OrderType::Market as i32,
+            amount: String::new(),
+            vol: Some("100".to_string()),
+            ..req.clone()
+        })
+        .unwrap();
+        assert!(by_quote.amount.is_none());
+        assert_eq!(Some(rust_decimal::Decimal::from(100)), by_quote.vol);
+        assert_eq!(
+            tonic::Code::InvalidArgument,
+            to_place_cmd(PlaceOrderRequest {
+                amount: "x".to_string(),
+                ..req
+            })
+            .unwrap_err()
+            .code()
+        );
+
+        assert_eq!((7, 12), reply_id(br#"{"id":7,"event_id":12}"#).unwrap());
+        assert_eq!(
+            tonic::Code::FailedPrecondition,
+            reply_id(br#"{"error":"no liquidity","code":1}"#)
+                .unwrap_err()
+                .code()
+        );
+        assert_eq!(
+            tonic::Code::InvalidArgument,
+            reply_id(b"").unwrap_err().code()
+        );
+
+        let trades = br#"[{"event_id":3,"symbol":[1,0],"price":"10.50","amount":"2.000","taker_side":"Bid","timestamp":100},{"event_id":3,"symbol":[2,0],"price":"1.0","amount":"1.0","taker_side":"Bid","timestamp":100}]"#;
+        let t = to_trades(trades, &[(1, 0)]);
+        assert_eq!(1, t.len());
+        assert_eq!("10.50", t[0].price);
+        assert_eq!(Side::Bid as i32, t[0].taker_side);
+        assert_eq!(2, to_trades(trades, &[]).len());
+        let delta = br#"{"symbol":[1,0],"update_id":5,"prev_update_id":4,"asks":[["10.50","0.000"]],"bids":[]}"#;
+        let d = to_depth(delta, &[]).unwrap();
+        assert_eq!(Some(4), d.prev_update_id);
+        assert_eq!("0.000", d.asks[0].amount);
+        assert!(to_depth(delta, &[(2, 0)]).is_none());
+    }
+}
@@ -0,0 +1,312 @@
+// Copyright 2021-2023 UINB Technologies Pte. Ltd.
+
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// 	http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::config::JournalCmd;
+use std::{
+    collections::VecDeque,
+    fs::{File, OpenOptions},
+    io::{BufReader, ErrorKind, Read, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+};
+use thiserror::Error;
+
+const SEGMENT_EXT: &str = "journal";
+// `len(u32) | crc32(u32) | id(u64)`, the checksum covers the id and the command
+const HEADER_SIZE: usize = 16;
+
+#[derive(Error, Debug)]
+pub enum JournalError {
+    #[error("the record at {1} of {0} is torn")]
+    Torn(PathBuf, u64),
+    #[error("the checksum of the record at {1} of {0} mismatches")]
+    Corrupted(PathBuf, u64),
+}
+
+/// the saved commands appended in the order of the events, segmented into files named by the
+/// first event id of each
+pub struct Journal {
+    dir: PathBuf,
+    segment_size: u64,
+    retain: usize,
+    segment: Option<File>,
+    written: u64,
+    last_id: u64,
+}
+
+impl Journal {
+    /// the torn or corrupted records at the tail of the last segment are truncated
+    pub fn open(dir: impl AsRef<Path>, segment_size: u64, retain: usize) -> anyhow::Result<Self> {
+        let dir = dir.as_ref().to_path_buf();
+        std::fs::create_dir_all(&dir)?;
+        let mut journal = Self {
+            dir,
+            segment_size,
+            retain,
+            segment: None,
+            written: 0,
+            last_id: 0,
+        };
+        if let Some((first_id, path)) = segments(&journal.dir)?.pop_back() {
+            let mut file = OpenOptions::new().read(true).write(true).open(&path)?;
+            let (mut valid, mut last_id) = (0, first_id.saturating_sub(1));
+            let mut reader = BufReader::new(&mut file);
+            loop {
+                match read_record(&mut reader, &path, valid) {
+                    Ok(Some((id, cmd))) => {
+                        valid += (HEADER_SIZE + cmd.len()) as u64;
+                        last_id = id;
+                    }
+                    Ok(None) => break,
+                    Err(e) => {
+                        log::warn!("{}, truncated", e);
+                        break;
+                    }
+                }
+            }
+            file.set_len(valid)?;
+            file.seek(SeekFrom::End(0))?;
+            journal.segment = Some(file);
+            journal.written = valid;
+            journal.last_id = last_id;
+        }
+        Ok(journal)
+    }
+
+    pub fn last_id(&self) -> u64 {
+        self.last_id
+    }
+
+    /// the events appended already are skipped, e.g. replaying on restarting
+    pub fn append(&mut self, id: u64, cmd: &[u8]) -> anyhow::Result<()> {
+        if id <= self.last_id {
+            return Ok(());
+        }
+        if self.segment.is_none() || self.written >= self.segment_size {
+            self.rotate(id)?;
+        }
+        let mut record = Vec::with_capacity(HEADER_SIZE + cmd.len());
+        record.extend_from_slice(&(cmd.len() as u32).to_be_bytes());
+        record.extend_from_slice(&checksum(id, cmd).to_be_bytes());
+        record.extend_from_slice(&id.to_be_bytes());
+        record.extend_from_slice(cmd);
+        self.segment
+            .as_mut()
+            .expect("segment opened;qed")
+            .write_all(&record)?;
+        self.written += record.len() as u64;
+        self.last_id = id;
+        Ok(())
+    }
+
+    fn rotate(&mut self, first_id: u64) -> anyhow::Result<()> {
+        if let Some(segment) = self.segment.take() {
+            segment.sync_all()?;
+        }
+        let path = self.dir.join(format!("{:020}.{}", first_id, SEGMENT_EXT));
+        self.segment = Some(OpenOptions::new().create(true).append(true).open(path)?);
+        self.written = 0;
+        if self.retain > 0 {
+            let mut segments = segments(&self.dir)?;
+            while segments.len() > self.retain {
+                let (_, path) = segments.pop_front().expect("more than retained;qed");
+                std::fs::remove_file(&path)?;
+                log::info!("journal segment {} removed", path.display());
+            }
+        }
+        Ok(())
+    }
+}
+
+fn checksum(id: u64, cmd: &[u8]) -> u32 {
+    let mut hasher = crc32fast::Hasher::new();
+    hasher.update(&id.to_be_bytes());
+    hasher.update(cmd);
+    hasher.finalize()
+}
+
+/// the segments sorted by their first event ids
+fn segments(dir: &Path) -> anyhow::Result<VecDeque<(u64, PathBuf)>> {
+    let mut segments = std::fs::read_dir(dir)?
+        .filter_map(|entry| entry.ok().map(|e| e.path()))
+        .filter(|path| path.extension().is_some_and(|ext| ext == SEGMENT_EXT))
+        .filter_map(|path| {
+            path.file_stem()
+                .and_then(|s| s.to_str())
+                .and_then(|s| s.parse::<u64>().ok())
+                .map(|id| (id, path))
+        })
+        .collect::<Vec<_>>();
+    segments.sort();
+    Ok(segments.into())
+}
+
+/// `None` at the end of the segment
+fn read_record(
+    r: &mut impl Read,
+    path: &Path,
+    offset: u64,
+) -> Result<Option<(u64, Vec<u8>)>, JournalError> {
+    let torn = || JournalError::Torn(path.to_path_buf(), offset);
+    let mut header = [0u8; HEADER_SIZE];
+    let mut read = 0;
+    while read < HEADER_SIZE {
+        match r.read(&mut header[read..]) {
+            Ok(0) if read == 0 => return Ok(None),
+            Ok(0) => return Err(torn()),
+            Ok(n) => read += n,
+            Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+            Err(_) => return Err(torn()),
+        }
+    }
+    let len = u32::from_be_bytes(header[..4].try_into().expect("4 bytes;qed"));
+    let crc = u32::from_be_bytes(header[4..8].try_into().expect("4 bytes;qed"));
+    let id = u64::from_be_bytes(header[8..].try_into().expect("8 bytes;qed"));
+    let mut cmd = vec![0u8; len as usize];
+    r.read_exact(&mut cmd).map_err(|_| torn())?;
+    if checksum(id, &cmd) != crc {
+        return Err(JournalError::Corrupted(path.to_path_buf(), offset));
+    }
+    Ok(Some((id, cmd)))
+}
+
+/// the records from the event `from` across the segments, stopped after an error
+pub fn read_from(
+    dir: impl AsRef<Path>,
+    from: u64,
+) -> anyhow::Result<impl Iterator<Item = anyhow::Result<(u64, Vec<u8>)>>> {
+    let mut segments = segments(dir.as_ref())?;
+    // the segments before the one containing `from`
+    while segments.len() > 1 && segments[1].0 <= from {
+        segments.pop_front();
+    }
+    let mut current: Option<(PathBuf, BufReader<File>, u64)> = None;
+    let mut failed = false;
+    Ok(std::iter::from_fn(move || loop {
+        if failed {
+            return None;
+        }
+        let (path, reader, offset) = match current.as_mut() {
+            Some(c) => c,
+            None => {
+                let (_, path) = segments.pop_front()?;
+                match File::open(&path) {
+                    Ok(file) => current = Some((path, BufReader::new(file), 0)),
+                    Err(e) => {
+                        failed = true;
+                        return Some(Err(e.into()));
+                    }
+                }
+                continue;
+            }
+        };
+        match read_record(reader, path, *offset) {
+            Ok(Some((id, cmd))) => {
+                *offset += (HEADER_SIZE + cmd.len()) as u64;
+                if id >= from {
+                    return Some(Ok((id, cmd)));
+                }
+            }
+            Ok(None) => current = None,
+            Err(e) => {
+                failed = true;
+                return Some(Err(e.into()));
+            }
+        }
+    }))
+}
+
+/// print the events as json lines, returns false if any record is broken
+pub fn export(c: JournalCmd) -> anyhow::Result<bool> {
+    let stdout = std::io::stdout();
+    let mut out = stdout.lock();
+    for record in read_from(&c.input_path, c.from)? {
+        let (id, cmd) = match record {
+            Ok(r) => r,
+            Err(e) => {
+                log::error!("{}", e);
+                return Ok(false);
+            }
+        };
+        if c.to.is_some_and(|to| id > to) {
+            break;
+        }
+        let cmd = serde_json::from_slice::<serde_json::Value>(&cmd)?;
+        writeln!(out, "{}", serde_json::json!({"id": id, "cmd": cmd}))?;
+    }
+    Ok(true)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn cmd(id: u64) -> Vec<u8> {
+        format!("{{\"cmd\":17,\"event_id\":{}}}", id).into_bytes()
+    }
+
+    #[test]
+    pub fn test_journal() {
+        let dir = tempdir::TempDir::new("galois-journal").unwrap();
+        let mut journal = Journal::open(dir.path(), 100, 0).unwrap();
+        for id in 1..=20 {
+            journal.append(id, &cmd(id)).unwrap();
+        }
+        // replayed
+        journal.append(10, b"ignored").unwrap();
+        assert_eq!(20, journal.last_id());
+        drop(journal);
+        let all = segments(dir.path()).unwrap();
+        assert!(all.len() > 1);
+        assert_eq!(1, all[0].0);
+        let read = read_from(dir.path(), 7)
+            .unwrap()
+            .collect::<anyhow::Result<Vec<_>>>()
+            .unwrap();
+        assert_eq!((7..=20).map(|id| (id, cmd(id))).collect::<Vec<_>>(), read);
+
+        // the torn tail is truncated on reopening
+        let (_, last) = all.back().unwrap();
+        let mut file = OpenOptions::new().append(true).open(last).unwrap();
+        file.write_all(&[0, 0, 0, 9, 1, 2]).unwrap();
+        drop(file);
+        assert!(read_from(dir.path(), 1).unwrap().any(|r| r.is_err()));
+        let mut journal = Journal::open(dir.path(), 100, 2).unwrap();
+        assert_eq!(20, journal.last_id());
+        for id in 21..=40 {
+            journal.append(id, &cmd(id)).unwrap();
+        }
+        drop(journal);
+        let kept = segments(dir.path()).unwrap();
+        assert_eq!(2, kept.len());
+        let read = read_from(dir.path(), 0)
+            .unwrap()
+            .collect::<anyhow::Result<Vec<_>>>()
+            .unwrap();
+        assert_eq!(kept[0].0, read[0].0);
+        assert_eq!(40, read.last().unwrap().0);
+
+        // a flipped byte is detected
+        let (_, first) = kept.front().unwrap();
+        let mut bytes = std::fs::read(first).unwrap();
+        bytes[HEADER_SIZE + 2] ^= 0xff;
+        std::fs::write(first, bytes).unwrap();
+        let read = read_from(dir.path(), 0).unwrap().collect::<Vec<_>>();
+        assert_eq!(1, read.len());
+        assert!(read[0]
+            .as_ref()
+            .unwrap_err()
+            .downcast_ref::<JournalError>()
+            .is_some_and(|e| matches!(e, JournalError::Corrupted(_, 0))));
+    }
+}
@@ -12,14 +12,35 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use crate::{core::*, fusotao::ToBlockChainNumeric};
+use crate::{
+    client_orders::MAX_CLIENT_ORDER_ID_LEN,
+    core::*,
+    fusotao::ToBlockChainNumeric,
+    matcher::{SelfTradePrevention, TimeInForce},
+    orders::MAX_USER_ORDERS,
+    trades::{DEFAULT_TRADES, MAX_TRADES},
+};
 use anyhow::{anyhow, ensure};
-use rust_decimal::Decimal;
+use parity_scale_codec::Encode;
+use rust_decimal::{prelude::Zero, Decimal};
 use serde::{Deserialize, Serialize};
 use std::str::FromStr;
 
+pub mod admin;
+pub mod cipher;
+pub mod encoding;
+#[cfg(feature = "fix")]
+pub mod fix;
+#[cfg(feature = "grpc")]
+#[allow(clippy::result_large_err)]
+pub mod grpc;
+pub mod journal;
+pub mod nonce;
+pub mod ratelimit;
 pub mod sequencer;
 pub mod server;
+pub mod tls;
+pub mod usage;
 
 #[derive(Debug, Serialize, Deserialize, Eq, PartialEq, Clone)]
 pub struct Input {
@@ -55,24 +76,69 @@ impl TryInto<Event> for Input {
     fn try_into(self) -> anyhow::Result<Event> {
         match self.cmd.cmd {
             ASK_LIMIT | BID_LIMIT => {
-                let amount = self.cmd.amount.ok_or(anyhow!(""))?;
                 let price = self.cmd.price.ok_or(anyhow!(""))?;
                 ensure!(
                     price.is_sign_positive() && price.scale() <= 7,
                     "invalid price numeric"
                 );
+                let time_in_force = self.cmd.time_in_force.unwrap_or_default();
+                let vol = self.cmd.vol;
                 ensure!(
-                    amount.is_sign_positive() && amount.scale() <= 7,
-                    "invalid amount numeric"
+                    vol.is_none()
+                        || (self.cmd.cmd == BID_LIMIT
+                            && time_in_force == TimeInForce::ImmediateOrCancel),
+                    "vol only applies to the IOC or market bids"
+                );
+                // the amount of a bid by quote is derived on executing
+                let amount = match vol {
+                    Some(vol) => {
+                        ensure!(
+                            vol.is_sign_positive() && vol.scale() <= 7,
+                            "invalid vol numeric"
+                        );
+                        Amount::zero()
+                    }
+                    None => {
+                        let amount = self.cmd.amount.ok_or(anyhow!("amount required"))?;
+                        ensure!(
+                            amount.is_sign_positive() && amount.scale() <= 7,
+                            "invalid amount numeric"
+                        );
+                        let vol = amount
+                            .checked_mul(price)
+                            .ok_or(anyhow!("volume overflow"))?;
+                        ensure!(vol.validate(), "overflow");
+                        amount
+                    }
+                };
+                let display = self.cmd.display;
+                if let Some(display) = display {
+                    ensure!(
+                        display.is_sign_positive()
+                            && display.scale() <= 7
+                            && display < amount
+                            && time_in_force == TimeInForce::GoodTillCancel,
+                        "display only applies to the GTC orders and must be less than the amount"
+                    );
+                }
+                ensure!(
+                    self.cmd.oco.is_none() || time_in_force == TimeInForce::GoodTillCancel,
+                    "oco only applies to the GTC orders"
+                );
+                ensure!(
+                    !self
+                        .cmd
+                        .client_order_id
+                        .as_ref()
+                        .is_some_and(|c| c.is_empty() || c.len() > MAX_CLIENT_ORDER_ID_LEN),
+                    "invalid client order id"
                 );
-                let vol = amount.checked_mul(price).ok_or(anyhow!(""))?;
-                ensure!(vol.validate(), "overflow");
                 let cmd = LimitCmd {
                     symbol: self.cmd.symbol().ok_or(anyhow!(""))?,
                     user_id: UserId::from_str(self.cmd.user_id.as_ref().ok_or(anyhow!(""))?)?,
                     price,
                     amount,
-                    ask_or_bid: AskOrBid::try_from(self.cmd.cmd)?,
+                    ask_or_bid: ask_or_bid_of(self.cmd.cmd)?,
                     nonce: self.cmd.nonce.ok_or(anyhow!(""))?,
                     signature: hex::decode(self.cmd.signature.ok_or(anyhow!(""))?)?,
                     broker: self
@@ -80,6 +146,12 @@ impl TryInto<Event> for Input {
                         .broker
                         .map(|b| UserId::from_str(b.as_ref()))
                         .transpose()?,
+                    time_in_force,
+                    self_trade_prevention: self.cmd.self_trade_prevention,
+                    vol,
+                    display,
+                    oco: self.cmd.oco,
+                    client_order_id: self.cmd.client_order_id,
                 };
                 Ok(Event::Limit(
                     self.sequence,
@@ -90,6 +162,166 @@ impl TryInto<Event> for Input {
                     self.req_id,
                 ))
             }
+            MARKET_ASK | MARKET_BID => {
+                let vol = self.cmd.vol;
+                ensure!(
+                    vol.is_none() || self.cmd.cmd == MARKET_BID,
+                    "vol only applies to the IOC or market bids"
+                );
+                let amount = match vol {
+                    Some(vol) => {
+                        ensure!(
+                            vol.is_sign_positive() && vol.scale() <= 7,
+                            "invalid vol numeric"
+                        );
+                        Amount::zero()
+                    }
+                    None => {
+                        let amount = self.cmd.amount.ok_or(anyhow!("amount required"))?;
+                        ensure!(
+                            amount.is_sign_positive() && amount.scale() <= 7,
+                            "invalid amount numeric"
+                        );
+                        amount
+                    }
+                };
+                let cmd = MarketCmd {
+                    symbol: self.cmd.symbol().ok_or(anyhow!("symbol required"))?,
+                    user_id: UserId::from_str(
+                        self.cmd
+                            .user_id
+                            .as_ref()
+                            .ok_or(anyhow!("user id required"))?,
+                    )?,
+                    amount,
+                    ask_or_bid: ask_or_bid_of(self.cmd.cmd)?,
+                    nonce: self.cmd.nonce.ok_or(anyhow!("nonce required"))?,
+                    signature: hex::decode(
+                        self.cmd.signature.ok_or(anyhow!("signature required"))?,
+                    )?,
+                    broker: self
+                        .cmd
+                        .broker
+                        .map(|b| UserId::from_str(b.as_ref()))
+                        .transpose()?,
+                    self_trade_prevention: self.cmd.self_trade_prevention,
+                    vol,
+                };
+                Ok(Event::Market(
+                    self.sequence,
+                    cmd,
+                    self.cmd.timestamp.unwrap_or_default(),
+                    self.session,
+                    self.req_id,
+                ))
+            }
+            BLOCK_ASK | BLOCK_BID => {
+                let amount = self.cmd.amount.ok_or(anyhow!("amount required"))?;
+                let price = self.cmd.price.ok_or(anyhow!("price required"))?;
+                ensure!(
+                    price.is_sign_positive() && price.scale() <= 7,
+                    "invalid price numeric"
+                );
+                ensure!(
+                    amount.is_sign_positive() && amount.scale() <= 7,
+                    "invalid amount numeric"
+                );
+                let vol = amount
+                    .checked_mul(price)
+                    .ok_or(anyhow!("volume overflow"))?;
+                ensure!(vol.validate(), "overflow");
+                let cmd = BlockTradeCmd {
+                    symbol: self.cmd.symbol().ok_or(anyhow!("symbol required"))?,
+                    user_id: UserId::from_str(
+                        self.cmd
+                            .user_id
+                            .as_ref()
+                            .ok_or(anyhow!("user id required"))?,
+                    )?,
+                    counterparty: UserId::from_str(
+                        self.cmd
+                            .counterparty
+                            .as_ref()
+                            .ok_or(anyhow!("counterparty required"))?,
+                    )?,
+                    price,
+                    amount,
+                    ask_or_bid: if self.cmd.cmd == BLOCK_ASK {
+                        AskOrBid::Ask
+                    } else {
+                        AskOrBid::Bid
+                    },
+                    nonce: self.cmd.nonce.ok_or(anyhow!("nonce required"))?,
+                    signature: hex::decode(
+                        self.cmd.signature.ok_or(anyhow!("signature required"))?,
+                    )?,
+                    counterparty_nonce: self
+                        .cmd
+                        .counterparty_nonce
+                        .ok_or(anyhow!("counterparty nonce required"))?,
+                    counterparty_signature: hex::decode(
+                        self.cmd
+                            .counterparty_signature
+                            .ok_or(anyhow!("counterparty signature required"))?,
+                    )?,
+                };
+                Ok(Event::BlockTrade(
+                    self.sequence,
+                    cmd,
+                    self.cmd.timestamp.unwrap_or_default(),
+                    self.session,
+                    self.req_id,
+                ))
+            }
+            ROUTE_ASK | ROUTE_BID => {
+                let amount = self.cmd.amount.ok_or(anyhow!("amount required"))?;
+                let price = self.cmd.price.ok_or(anyhow!("price required"))?;
+                ensure!(
+                    price.is_sign_positive() && price.scale() <= 7,
+                    "invalid price numeric"
+                );
+                ensure!(
+                    amount.is_sign_positive() && amount.scale() <= 7,
+                    "invalid amount numeric"
+                );
+                let vol = amount
+                    .checked_mul(price)
+                    .ok_or(anyhow!("volume overflow"))?;
+                ensure!(vol.validate(), "overflow");
+                let symbol = self.cmd.symbol().ok_or(anyhow!("symbol required"))?;
+                let via = self.cmd.via.ok_or(anyhow!("via required"))?;
+                ensure!(
+                    via != symbol.0 && via != symbol.1,
+                    "the intermediate currency must differ from the symbol"
+                );
+                Ok(Event::Route(
+                    self.sequence,
+                    RouteCmd {
+                        symbol,
+                        via,
+                        user_id: UserId::from_str(
+                            self.cmd
+                                .user_id
+                                .as_ref()
+                                .ok_or(anyhow!("user id required"))?,
+                        )?,
+                        price,
+                        amount,
+                        ask_or_bid: if self.cmd.cmd == ROUTE_ASK {
+                            AskOrBid::Ask
+                        } else {
+                            AskOrBid::Bid
+                        },
+                        nonce: self.cmd.nonce.ok_or(anyhow!("nonce required"))?,
+                        signature: hex::decode(
+                            self.cmd.signature.ok_or(anyhow!("signature required"))?,
+                        )?,
+                    },
+                    self.cmd.timestamp.unwrap_or_default(),
+                    self.session,
+                    self.req_id,
+                ))
+            }
             CANCEL => Ok(Event::Cancel(
                 self.sequence,
                 CancelCmd {
@@ -104,6 +336,56 @@ impl TryInto<Event> for Input {
                 self.session,
                 self.req_id,
             )),
+            SUB_TRANSFER => {
+                let amount = self.cmd.amount.ok_or(anyhow!("amount required"))?;
+                ensure!(
+                    amount.is_sign_positive() && !amount.is_zero() && amount.validate(),
+                    "invalid amount numeric"
+                );
+                Ok(Event::SubTransfer(
+                    self.sequence,
+                    SubTransferCmd {
+                        user_id: UserId::from_str(
+                            self.cmd
+                                .user_id
+                                .as_ref()
+                                .ok_or(anyhow!("user id required"))?,
+                        )?,
+                        bot: UserId::from_str(
+                            self.cmd.bot.as_ref().ok_or(anyhow!("bot required"))?,
+                        )?,
+                        token: self.cmd.token.ok_or(anyhow!("token required"))?,
+                        currency: self.cmd.currency.ok_or(anyhow!("currency required"))?,
+                        amount,
+                        to_sub: self.cmd.to_sub.ok_or(anyhow!("to sub required"))?,
+                        nonce: self.cmd.nonce.ok_or(anyhow!("nonce required"))?,
+                        signature: hex::decode(
+                            self.cmd.signature.ok_or(anyhow!("signature required"))?,
+                        )?,
+                    },
+                    self.cmd.timestamp.unwrap_or_default(),
+                    self.session,
+                    self.req_id,
+                ))
+            }
+            CANCEL_ALL => {
+                // only the system is allowed to cancel the orders of all users
+                ensure!(
+                    self.session == 0 || self.cmd.user_id.is_some(),
+                    "user_id is required"
+                );
+                Ok(Event::CancelAll(
+                    self.sequence,
+                    self.cmd.symbol().ok_or(anyhow!("symbol required"))?,
+                    self.cmd
+                        .user_id
+                        .map(|u| UserId::from_str(u.as_ref()))
+                        .transpose()?,
+                    self.cmd.timestamp.unwrap_or_default(),
+                    self.session,
+                    self.req_id,
+                ))
+            }
             TRANSFER_OUT => Ok(Event::TransferOut(
                 self.sequence,
                 AssetsCmd {
@@ -117,6 +399,7 @@ impl TryInto<Event> for Input {
                         .ok_or(anyhow!(""))?,
                     block_number: self.cmd.block_number.ok_or(anyhow!(""))?,
                     extrinsic_hash: hex::decode(self.cmd.extrinsic_hash.ok_or(anyhow!(""))?)?,
+                    timestamp: self.cmd.timestamp.unwrap_or_default(),
                 },
             )),
             TRANSFER_IN => Ok(Event::TransferIn(
@@ -132,6 +415,7 @@ impl TryInto<Event> for Input {
                         .ok_or(anyhow!(""))?,
                     block_number: self.cmd.block_number.ok_or(anyhow!(""))?,
                     extrinsic_hash: hex::decode(self.cmd.extrinsic_hash.ok_or(anyhow!(""))?)?,
+                    timestamp: self.cmd.timestamp.unwrap_or_default(),
                 },
             )),
             UPDATE_SYMBOL => Ok(Event::UpdateSymbol(
@@ -150,17 +434,18 @@ impl TryInto<Event> for Input {
                         .taker_fee
                         .filter(|f| f.is_sign_positive())
                         .ok_or(anyhow!(""))?,
+                    // a negative maker fee is rebated out of the taker fee
                     maker_fee: self
                         .cmd
                         .maker_fee
-                        .filter(|f| f.is_sign_positive())
+                        .filter(|f| self.cmd.taker_fee.is_some_and(|t| -*f <= t))
                         .ok_or(anyhow!(""))?,
                     base_maker_fee: self
                         .cmd
                         .base_maker_fee
                         .filter(|f| f.is_sign_positive())
                         .or(self.cmd.maker_fee)
-                        .filter(|f| f.is_sign_positive())
+                        .map(|f| f.max(Fee::zero()))
                         .ok_or(anyhow!(""))?,
                     base_taker_fee: self
                         .cmd
@@ -181,6 +466,91 @@ impl TryInto<Event> for Input {
                         .filter(|f| f.is_sign_positive())
                         .ok_or(anyhow!(""))?,
                     enable_market_order: self.cmd.enable_market_order.ok_or(anyhow!(""))?,
+                    broker_share: self
+                        .cmd
+                        .broker_share
+                        .map(|f| {
+                            (f.is_sign_positive() && f <= Fee::ONE)
+                                .then_some(f)
+                                .ok_or(anyhow!("broker share must be in [0, 1]"))
+                        })
+                        .transpose()?,
+                    tick_size: self
+                        .cmd
+                        .tick_size
+                        .map(|t| {
+                            t.is_sign_positive()
+                                .then_some(t)
+                                .ok_or(anyhow!("tick size must be positive"))
+                        })
+                        .transpose()?,
+                    lot_size: self
+                        .cmd
+                        .lot_size
+                        .map(|l| {
+                            l.is_sign_positive()
+                                .then_some(l)
+                                .ok_or(anyhow!("lot size must be positive"))
+                        })
+                        .transpose()?,
+                    price_band: self
+                        .cmd
+                        .price_band
+                        .map(|b| {
+                            b.is_sign_positive()
+                                .then_some(b)
+                                .ok_or(anyhow!("price band must be positive"))
+                        })
+                        .transpose()?,
+                    max_open_notional: self
+                        .cmd
+                        .max_open_notional
+                        .map(|n| {
+                            n.is_sign_positive()
+                                .then_some(n)
+                                .ok_or(anyhow!("max open notional must be positive"))
+                        })
+                        .transpose()?,
+                    max_open_orders: self.cmd.max_open_orders,
+                    trading_hours: self
+                        .cmd
+                        .trading_hours
+                        .map(|hours| {
+                            hours
+                                .iter()
+                                .all(|w| crate::breaker::parse_window(w).is_some())
+                                .then_some(hours)
+                                .ok_or(anyhow!("trading hours must be in `HH:MM-HH:MM`"))
+                        })
+                        .transpose()?,
+                    circuit_breaker: self
+                        .cmd
+                        .circuit_breaker
+                        .map(|cb| {
+                            (cb == CircuitBreaker::default()
+                                || cb.max_move > Decimal::zero() && cb.window > 0 && cb.halt > 0)
+                                .then_some(cb)
+                                .ok_or(anyhow!(
+                                    "max move, window and halt of the circuit breaker must be positive"
+                                ))
+                        })
+                        .transpose()?,
+                    block_trade_min_amount: self
+                        .cmd
+                        .block_trade_min_amount
+                        .map(|m| {
+                            m.is_sign_positive()
+                                .then_some(m)
+                                .ok_or(anyhow!("block trade min amount must be positive"))
+                        })
+                        .transpose()?,
+                    block_trade_report_delay: self.cmd.block_trade_report_delay,
+                    self_trade_prevention: self.cmd.self_trade_prevention,
+                    timestamp: self.cmd.timestamp.unwrap_or_default(),
+                    actor: match self.cmd.block_number {
+                        Some(block) => format!("chain@{}", block),
+                        None => self.cmd.user_id.unwrap_or_else(|| "operator".to_string()),
+                    },
                 },
             )),
             QUERY_ORDER => Ok(Event::QueryOrder(
@@ -192,6 +562,12 @@ impl TryInto<Event> for Input {
             QUERY_USER_ORDERS => Ok(Event::QueryUserOrders(
                 self.cmd.symbol().ok_or(anyhow!(""))?,
                 UserId::from_str(self.cmd.user_id.as_ref().ok_or(anyhow!(""))?)?,
+                self.cmd.from,
+                self.cmd
+                    .limit
+                    .map(|l| l as usize)
+                    .unwrap_or(MAX_USER_ORDERS)
+                    .min(MAX_USER_ORDERS),
                 self.session,
                 self.req_id,
             )),
@@ -203,6 +579,7 @@ impl TryInto<Event> for Input {
             )),
             QUERY_ACCOUNTS => Ok(Event::QueryAccounts(
                 UserId::from_str(self.cmd.user_id.as_ref().ok_or(anyhow!(""))?)?,
+                self.cmd.valued.unwrap_or_default(),
                 self.session,
                 self.req_id,
             )),
@@ -212,7 +589,139 @@ impl TryInto<Event> for Input {
                 self.req_id,
             )),
             QUERY_ALL_ORDERBOOKS => Ok(Event::QueryAllOrderbooks(self.session, self.req_id)),
-            DUMP => Ok(Event::Dump(self.cmd.event_id.ok_or(anyhow!(""))?)),
+            QUERY_BROKER_EXECUTION => Ok(Event::QueryBrokerExecution(
+                UserId::from_str(
+                    self.cmd
+                        .user_id
+                        .as_ref()
+                        .ok_or(anyhow!("user id required"))?,
+                )?,
+                self.session,
+                self.req_id,
+            )),
+            QUERY_BROKER_REVENUE => Ok(Event::QueryBrokerRevenue(
+                UserId::from_str(
+                    self.cmd
+                        .user_id
+                        .as_ref()
+                        .ok_or(anyhow!("user id required"))?,
+                )?,
+                self.session,
+                self.req_id,
+            )),
+            QUERY_SYSTEM_FEES => Ok(Event::QuerySystemFees(self.session, self.req_id)),
+            WITHDRAW_FEES => Ok(Event::WithdrawFees(
+                self.sequence,
+                WithdrawFeesCmd {
+                    treasury: UserId::from_str(
+                        self.cmd
+                            .user_id
+                            .as_ref()
+                            .ok_or(anyhow!("user id required"))?,
+                    )?,
+                    currency: self.cmd.currency.ok_or(anyhow!("currency required"))?,
+                    amount: match self.cmd.amount {
+                        Some(a) if !a.is_sign_positive() || a.is_zero() => {
+                            return Err(anyhow!("amount must be positive"))
+                        }
+                        a => a,
+                    },
+                    timestamp: self.cmd.timestamp.unwrap_or_default(),
+                },
+            )),
+            UPDATE_FEE_TIERS => {
+                let tiers = self.cmd.fee_tiers.ok_or(anyhow!("fee tiers required"))?;
+                crate::tiers::validate(&tiers)?;
+                Ok(Event::UpdateFeeTiers(self.sequence, tiers))
+            }
+            QUERY_DEPTH => Ok(Event::QueryDepth(
+                self.cmd.symbol().ok_or(anyhow!("symbol required"))?,
+                self.cmd.limit.map(|l| l as usize),
+                match self.cmd.tick {
+                    Some(tick) if tick <= Decimal::zero() => {
+                        return Err(anyhow!("tick must be positive"))
+                    }
+                    tick => tick,
+                },
+                self.session,
+                self.req_id,
+            )),
+            QUERY_TRADES => Ok(Event::QueryTrades(
+                self.cmd.symbol().ok_or(anyhow!("symbol required"))?,
+                self.cmd
+                    .limit
+                    .map(|l| l as usize)
+                    .unwrap_or(DEFAULT_TRADES)
+                    .min(MAX_TRADES),
+                self.session,
+                self.req_id,
+            )),
+            QUERY_CONFIG_HISTORY => Ok(Event::QueryConfigHistory(
+                self.cmd.symbol(),
+                self.session,
+                self.req_id,
+            )),
+            QUERY_LAST_PRICE => Ok(Event::QueryLastPrice(
+                self.cmd.symbol(),
+                self.session,
+                self.req_id,
+            )),
+            QUERY_SESSION_PROGRESS => Ok(Event::QuerySessionProgress(
+                self.cmd.session.unwrap_or(self.session),
+                self.session,
+                self.req_id,
+            )),
+            UPDATE_CURRENCY => Ok(Event::UpdateCurrency(
+                self.sequence,
+                CurrencyCmd {
+                    currency: self.cmd.currency.ok_or(anyhow!("currency required"))?,
+                    mode: self
+                        .cmd
+                        .mode
+                        .as_deref()
+                        .ok_or(anyhow!("mode required"))?
+                        .parse()?,
+                    max_frozen: self
+                        .cmd
+                        .max_frozen
+                        .map(|m| {
+                            m.is_sign_positive()
+                                .then_some(m)
+                                .ok_or(anyhow!("max frozen must not be negative"))
+                        })
+                        .transpose()?,
+                },
+            )),
+            QUERY_CURRENCIES => Ok(Event::QueryCurrencies(self.session, self.req_id)),
+            QUERY_BOOK_STATS => Ok(Event::QueryBookStats(
+                self.cmd.symbol(),
+                self.session,
+                self.req_id,
+            )),
+            SET_SYMBOL_OPEN => Ok(Event::SetSymbolOpen(
+                self.sequence,
+                self.cmd.symbol().ok_or(anyhow!("symbol required"))?,
+                self.cmd.open.ok_or(anyhow!("open required"))?,
+                self.cmd.timestamp.unwrap_or_default(),
+            )),
+            SET_TRADING_HALT => Ok(Event::SetTradingHalt(
+                self.sequence,
+                self.cmd.symbol().ok_or(anyhow!("symbol required"))?,
+                self.cmd.halted.ok_or(anyhow!("halted required"))?,
+            )),
+            CHECK_MERKLE => Ok(Event::CheckMerkle(
+                self.cmd
+                    .user_id
+                    .as_deref()
+                    .map(UserId::from_str)
+                    .transpose()?,
+                self.cmd.symbol(),
+                self.req_id,
+            )),
+            // the last one sequenced by default
+            DUMP => Ok(Event::Dump(
+                self.cmd.event_id.unwrap_or(self.sequence.saturating_sub(1)),
+            )),
             _ => Err(anyhow!("Unsupported Command")),
         }
     }
@@ -224,19 +733,55 @@ unsafe impl Send for Input {}
 pub enum Event {
     // write
     Limit(EventId, LimitCmd, Timestamp, u64, u64),
+    Market(EventId, MarketCmd, Timestamp, u64, u64),
     Cancel(EventId, CancelCmd, Timestamp, u64, u64),
     TransferOut(EventId, AssetsCmd),
     TransferIn(EventId, AssetsCmd),
     UpdateSymbol(EventId, SymbolCmd),
+    BlockTrade(EventId, BlockTradeCmd, Timestamp, u64, u64),
+    // two legs through an intermediate currency cleared and proven in one event
+    Route(EventId, RouteCmd, Timestamp, u64, u64),
+    // between the main account and a sub-account derived from it
+    SubTransfer(EventId, SubTransferCmd, Timestamp, u64, u64),
+    UpdateCurrency(EventId, CurrencyCmd),
+    // the symbol paused or resumed by the operators, the other configs are kept
+    SetSymbolOpen(EventId, Symbol, bool, Timestamp),
+    // the takers of the symbol halted or resumed by the operators, overriding the circuit breaker
+    SetTradingHalt(EventId, Symbol, bool),
+    // the fees accrued by `SYSTEM` moved to the treasury by the operators
+    WithdrawFees(EventId, WithdrawFeesCmd),
+    // the resting orders of the user, or of all users if absent, cancelled and proven in one event
+    CancelAll(EventId, Symbol, Option<UserId>, Timestamp, u64, u64),
+    // the fee tiers replaced entirely by the operators, empty to charge the fees of the symbols
+    UpdateFeeTiers(EventId, Vec<FeeTier>),
     // read
     QueryOrder(Symbol, OrderId, u64, u64),
     QueryBalance(UserId, Currency, u64, u64),
-    QueryAccounts(UserId, u64, u64),
+    /// valued if `true`
+    QueryAccounts(UserId, bool, u64, u64),
     QueryExchangeFee(Symbol, u64, u64),
-    QueryUserOrders(Symbol, UserId, u64, u64),
+    // the orders from the cursor, at most `limit`
+    QueryUserOrders(Symbol, UserId, Option<OrderId>, usize, u64, u64),
     QueryAllOrderbooks(u64, u64),
+    QueryBrokerExecution(UserId, u64, u64),
+    // the fees shared to the broker
+    QueryBrokerRevenue(UserId, u64, u64),
+    QuerySystemFees(u64, u64),
+    QueryConfigHistory(Option<Symbol>, u64, u64),
+    // at most `limit` levels aggregated by the tick if given
+    QueryDepth(Symbol, Option<usize>, Option<Price>, u64, u64),
+    QueryTrades(Symbol, usize, u64, u64),
+    QueryLastPrice(Option<Symbol>, u64, u64),
+    // the session queried, defaults to the requesting one
+    QuerySessionProgress(u64, u64, u64),
+    QueryCurrencies(u64, u64),
+    // all symbols if absent
+    QueryBookStats(Option<Symbol>, u64, u64),
     // the `EventId` has been executed
     Dump(EventId),
+    // the leaves of the user and the symbol checked against the merkle tree, all if neither,
+    // replied to the operator by the check id
+    CheckMerkle(Option<UserId>, Option<Symbol>, u64),
 }
 
 impl Event {
@@ -244,12 +789,60 @@ impl Event {
         matches!(
             self,
             Self::Limit(..)
+                | Self::Market(..)
                 | Self::Cancel(..)
                 | Self::TransferOut(..)
                 | Self::TransferIn(..)
                 | Self::UpdateSymbol(..)
+                | Self::BlockTrade(..)
+                | Self::Route(..)
+                | Self::SubTransfer(..)
+                | Self::UpdateCurrency(..)
+                | Self::SetSymbolOpen(..)
+                | Self::SetTradingHalt(..)
+                | Self::WithdrawFees(..)
+                | Self::CancelAll(..)
+                | Self::UpdateFeeTiers(..)
         )
     }
+
+    /// `(session, event_id)` of the writes requested by clients
+    pub fn sequenced(&self) -> Option<(u64, EventId)> {
+        let (id, session) = match self {
+            Self::Limit(id, _, _, session, _)
+            | Self::Market(id, _, _, session, _)
+            | Self::Cancel(id, _, _, session, _)
+            | Self::BlockTrade(id, _, _, session, _)
+            | Self::Route(id, _, _, session, _)
+            | Self::SubTransfer(id, _, _, session, _)
+            | Self::CancelAll(id, _, _, _, session, _) => (*id, *session),
+            _ => return None,
+        };
+        (session != 0).then_some((session, id))
+    }
+
+    /// the id of the saved events and the checkpoints
+    pub fn id(&self) -> Option<EventId> {
+        match self {
+            Self::Limit(id, ..)
+            | Self::Market(id, ..)
+            | Self::Cancel(id, ..)
+            | Self::TransferOut(id, ..)
+            | Self::TransferIn(id, ..)
+            | Self::UpdateSymbol(id, ..)
+            | Self::BlockTrade(id, ..)
+            | Self::Route(id, ..)
+            | Self::SubTransfer(id, ..)
+            | Self::UpdateCurrency(id, ..)
+            | Self::SetSymbolOpen(id, ..)
+            | Self::SetTradingHalt(id, ..)
+            | Self::WithdrawFees(id, ..)
+            | Self::CancelAll(id, ..)
+            | Self::UpdateFeeTiers(id, ..)
+            | Self::Dump(id) => Some(*id),
+            _ => None,
+        }
+    }
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
@@ -262,6 +855,151 @@ pub struct LimitCmd {
     pub nonce: u32,
     pub signature: Vec<u8>,
     pub broker: Option<UserId>,
+    #[serde(default)]
+    pub time_in_force: TimeInForce,
+    /// the policy of the symbol if absent
+    #[serde(default)]
+    pub self_trade_prevention: Option<SelfTradePrevention>,
+    /// the quote to spend of an IOC bid, `amount` is derived from it on executing
+    #[serde(default)]
+    pub vol: Option<Vol>,
+    /// an iceberg shows only `display` on the book, the rest is replenished slice by slice
+    #[serde(default)]
+    pub display: Option<Amount>,
+    /// linked with the resting order `oco` of the same user, once either is filled or cancelled
+    /// the other is cancelled
+    #[serde(default)]
+    pub oco: Option<OrderId>,
+    /// the duplicated ones of the same user recently used are rejected along with the order id
+    /// accepted before
+    #[serde(default)]
+    pub client_order_id: Option<String>,
+}
+
+/// taking the opposite side at the best available prices without resting on the book
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct MarketCmd {
+    pub symbol: Symbol,
+    pub user_id: UserId,
+    pub amount: Amount,
+    pub ask_or_bid: AskOrBid,
+    pub nonce: u32,
+    pub signature: Vec<u8>,
+    pub broker: Option<UserId>,
+    #[serde(default)]
+    pub self_trade_prevention: Option<SelfTradePrevention>,
+    /// the quote to spend of a bid, `amount` is zero if present
+    #[serde(default)]
+    pub vol: Option<Vol>,
+}
+
+/// a negotiated cross between `user_id` and `counterparty` off the public book,
+/// `ask_or_bid` is the side of `user_id`
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct BlockTradeCmd {
+    pub symbol: Symbol,
+    pub user_id: UserId,
+    pub counterparty: UserId,
+    pub price: Price,
+    pub amount: Amount,
+    pub ask_or_bid: AskOrBid,
+    pub nonce: u32,
+    pub signature: Vec<u8>,
+    pub counterparty_nonce: u32,
+    pub counterparty_signature: Vec<u8>,
+}
+
+// the block trades are forced on the counterparties unless they sign the terms
+const BLOCK_TRADE_DOMAIN: &[u8] = b"galois/block-trade";
+
+impl BlockTradeCmd {
+    /// signed by the counterparty, `galois/block-trade` followed by the SCALE encoded `(base,
+    /// quote, user_id, counterparty, price, amount, side of the counterparty, counterparty_nonce)`
+    /// with the decimals as the strings
+    pub fn terms(&self) -> Vec<u8> {
+        let side: u8 = (!self.ask_or_bid).into();
+        let terms = (
+            self.symbol.0,
+            self.symbol.1,
+            self.user_id.0,
+            self.counterparty.0,
+            self.price.to_string(),
+            self.amount.to_string(),
+            side,
+            self.counterparty_nonce,
+        );
+        [BLOCK_TRADE_DOMAIN, &terms.encode()].concat()
+    }
+
+    pub fn is_countersigned(&self) -> bool {
+        let Ok(signature) = <[u8; 64]>::try_from(&self.counterparty_signature[..]) else {
+            return false;
+        };
+        <sp_core::sr25519::Pair as sp_core::Pair>::verify(
+            &sp_core::sr25519::Signature::from_raw(signature),
+            self.terms(),
+            &sp_core::sr25519::Public::from_raw(self.counterparty.0),
+        )
+    }
+}
+
+/// trading `symbol` through the books of `(base, via)` and `(via, quote)` atomically, `amount`
+/// of the base is sold or bought at the average `price` in the quote or better
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct RouteCmd {
+    pub symbol: Symbol,
+    pub via: Currency,
+    pub user_id: UserId,
+    pub price: Price,
+    pub amount: Amount,
+    pub ask_or_bid: AskOrBid,
+    pub nonce: u32,
+    pub signature: Vec<u8>,
+}
+
+/// all the available of `SYSTEM` in the currency if `amount` is absent
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct WithdrawFeesCmd {
+    pub treasury: UserId,
+    pub currency: Currency,
+    pub amount: Option<Amount>,
+    pub timestamp: Timestamp,
+}
+
+/// signed by the main account, `to_sub` moves the funds from the main account to the sub-account
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct SubTransferCmd {
+    pub user_id: UserId,
+    pub bot: UserId,
+    pub token: u32,
+    pub currency: Currency,
+    pub amount: Amount,
+    pub to_sub: bool,
+    pub nonce: u32,
+    pub signature: Vec<u8>,
+}
+
+impl SubTransferCmd {
+    /// the same as the proxy account of `bot` on chain
+    pub fn sub_account(&self) -> UserId {
+        let h = (
+            b"-*-#fusotao-proxy#-*-",
+            self.token,
+            self.user_id.0,
+            self.bot.0,
+        )
+            .using_encoded(sp_core::hashing::blake2_256);
+        UserId::new(h)
+    }
+
+    /// `(from, to)` of the funds
+    pub fn parties(&self) -> (UserId, UserId) {
+        if self.to_sub {
+            (self.user_id, self.sub_account())
+        } else {
+            (self.sub_account(), self.user_id)
+        }
+    }
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
@@ -291,6 +1029,14 @@ impl std::convert::TryFrom<u32> for InOrOut {
     }
 }
 
+fn ask_or_bid_of(x: u32) -> anyhow::Result<AskOrBid> {
+    match x {
+        cmd::ASK_LIMIT | cmd::MARKET_ASK => Ok(AskOrBid::Ask),
+        cmd::BID_LIMIT | cmd::MARKET_BID => Ok(AskOrBid::Bid),
+        _ => Err(anyhow::anyhow!("invalid order command")),
+    }
+}
+
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct AssetsCmd {
     pub user_id: UserId,
@@ -299,6 +1045,17 @@ pub struct AssetsCmd {
     pub amount: Amount,
     pub block_number: u32,
     pub extrinsic_hash: Vec<u8>,
+    /// when the transfer was scanned, 0 for the ones sequenced before it was tracked
+    pub timestamp: Timestamp,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct CurrencyCmd {
+    pub currency: Currency,
+    pub mode: CurrencyMode,
+    /// the frozen balance of each user, `None` to keep the current, zero for no limit
+    #[serde(default)]
+    pub max_frozen: Option<Amount>,
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
@@ -315,13 +1072,53 @@ pub struct SymbolCmd {
     pub min_amount: Amount,
     pub min_vol: Vol,
     pub enable_market_order: bool,
+    /// the part of the fees accrued to the brokers, `None` to keep the current
+    #[serde(default)]
+    pub broker_share: Option<Fee>,
+    /// `None` to keep the current, zero to check the scale only
+    #[serde(default)]
+    pub tick_size: Option<Price>,
+    /// `None` to keep the current, zero to check the scale only
+    #[serde(default)]
+    pub lot_size: Option<Amount>,
+    /// `None` to keep the current, zero to accept any limit price
+    #[serde(default)]
+    pub price_band: Option<Fee>,
+    /// `None` to keep the current, zero to rest any passive order
+    #[serde(default)]
+    pub max_open_notional: Option<Vol>,
+    /// `None` to keep the current, zero to rest any passive order
+    #[serde(default)]
+    pub max_open_orders: Option<u32>,
+    /// `None` to keep the current, empty to accept the orders all day
+    #[serde(default)]
+    pub trading_hours: Option<Vec<String>>,
+    /// `None` to keep the current, all zero to never halt
+    #[serde(default)]
+    pub circuit_breaker: Option<CircuitBreaker>,
+    /// `None` to keep the current, zero to reject any block trade
+    #[serde(default)]
+    pub block_trade_min_amount: Option<Amount>,
+    /// `None` to keep the current, zero to report the block trades immediately
+    #[serde(default)]
+    pub block_trade_report_delay: Option<u64>,
+    /// `None` to keep the current, overridden by the orders specifying one
+    #[serde(default)]
+    pub self_trade_prevention: Option<SelfTradePrevention>,
+    #[serde(default)]
+    pub timestamp: Timestamp,
+    /// `chain@<block>` for the market events, otherwise the issuer
+    #[serde(default)]
+    pub actor: String,
 }
 
 pub mod cmd {
     pub const ASK_LIMIT: u32 = 0;
     pub const BID_LIMIT: u32 = 1;
+    pub const MARKET_ASK: u32 = 2;
+    pub const MARKET_BID: u32 = 3;
     pub const CANCEL: u32 = 4;
-    pub const CANCEL_ALL: u32 = 5; /* DEPRECATED */
+    pub const CANCEL_ALL: u32 = 5;
     pub const TRANSFER_OUT: u32 = 10;
     pub const TRANSFER_IN: u32 = 11;
     pub const UPDATE_SYMBOL: u32 = 13;
@@ -343,6 +1140,46 @@ pub mod cmd {
     pub const QUERY_FUSOTAO_PROGRESS: u32 = 27;
     pub const QUERY_USER_ORDERS: u32 = 28;
     pub const QUERY_ALL_ORDERBOOKS: u32 = 29;
+    pub const BLOCK_ASK: u32 = 30;
+    pub const BLOCK_BID: u32 = 31;
+    pub const QUERY_BROKER_EXECUTION: u32 = 32;
+    /// reserved, `set_log_level` of the admin socket
+    pub const SET_LOG_LEVEL: u32 = 33;
+    /// reserved, `memory_stats` of the admin socket
+    pub const QUERY_MEMORY_STATS: u32 = 34;
+    pub const QUERY_API_USAGE: u32 = 35;
+    /// reserved, `dominator` of the admin socket
+    pub const QUERY_DOMINATOR: u32 = 36;
+    /// reserved, `confirm_reanchor` of the admin socket
+    pub const CONFIRM_REANCHOR: u32 = 37;
+    pub const QUERY_KLINES: u32 = 38;
+    pub const QUERY_CONFIG_HISTORY: u32 = 39;
+    pub const QUERY_DEPTH: u32 = 40;
+    pub const QUERY_TRADES: u32 = 41;
+    pub const QUERY_BROKER_FLOW: u32 = 42;
+    pub const QUERY_LAST_PRICE: u32 = 43;
+    pub const QUERY_SESSION_PROGRESS: u32 = 44;
+    pub const QUERY_ORDER_HISTORY: u32 = 45;
+    pub const UPDATE_CURRENCY: u32 = 46;
+    pub const QUERY_CURRENCIES: u32 = 47;
+    pub const SET_SYMBOL_OPEN: u32 = 48;
+    pub const GET_NONCE: u32 = 49;
+    pub const X25519_HANDSHAKE: u32 = 50;
+    pub const QUERY_BROKER_REVENUE: u32 = 51;
+    pub const QUERY_BOOK_STATS: u32 = 52;
+    pub const QUERY_TICKER: u32 = 53;
+    pub const QUERY_STATEMENT: u32 = 54;
+    pub const SUB_TRANSFER: u32 = 55;
+    pub const SET_TRADING_HALT: u32 = 56;
+    pub const CHECK_MERKLE: u32 = 57;
+    pub const PING: u32 = 58;
+    pub const RESUME_SESSION: u32 = 59;
+    pub const ROUTE_ASK: u32 = 60;
+    pub const ROUTE_BID: u32 = 61;
+    pub const QUERY_SYSTEM_FEES: u32 = 62;
+    pub const WITHDRAW_FEES: u32 = 63;
+    pub const QUERY_BOOK_IMBALANCE: u32 = 64;
+    pub const UPDATE_FEE_TIERS: u32 = 65;
 }
 
 #[derive(Debug, Serialize, Deserialize, Eq, PartialEq, Clone, Default)]
@@ -387,6 +1224,28 @@ pub struct Command {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub fee_times: Option<u32>,
     #[serde(skip_serializing_if = "Option::is_none")]
+    pub broker_share: Option<Fee>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tick_size: Option<Price>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub lot_size: Option<Amount>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub price_band: Option<Fee>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_open_notional: Option<Vol>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_open_orders: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_frozen: Option<Amount>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub trading_hours: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub circuit_breaker: Option<CircuitBreaker>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub block_trade_min_amount: Option<Amount>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub block_trade_report_delay: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub min_amount: Option<Amount>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub min_vol: Option<Amount>,
@@ -404,6 +1263,69 @@ pub struct Command {
     pub timestamp: Option<u64>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub broker: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub counterparty: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub counterparty_nonce: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub counterparty_signature: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub module: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub level: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub time_in_force: Option<TimeInForce>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub mode: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub interval: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub to: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub limit: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tick: Option<Price>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub encoding: Option<encoding::Encoding>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub epoch: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub self_trade_prevention: Option<SelfTradePrevention>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub session: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cursor: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub display: Option<Amount>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub x25519: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub bot: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub token: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub to_sub: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub valued: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub oco: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub halted: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub client_order_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub resume_token: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub via: Option<Currency>,
+    /// in milliseconds, the signature v2 expires after
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub expiry: Option<u64>,
+    /// the signature v2 is bound to, see `sequence.domain`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub domain: Option<String>,
+    /// the whole schedule of `UPDATE_FEE_TIERS`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub fee_tiers: Option<Vec<FeeTier>>,
 }
 
 unsafe impl Send for Command {}
@@ -423,18 +1345,41 @@ impl Command {
                 | QUERY_ORDER
                 | QUERY_EXCHANGE_FEE
                 | QUERY_ALL_ORDERBOOKS
+                | QUERY_BROKER_EXECUTION
+                | QUERY_BROKER_REVENUE
+                | QUERY_SYSTEM_FEES
+                | QUERY_CONFIG_HISTORY
+                | QUERY_DEPTH
+                | QUERY_TRADES
+                | QUERY_LAST_PRICE
+                | QUERY_SESSION_PROGRESS
+                | QUERY_CURRENCIES
+                | QUERY_BOOK_STATS
         )
     }
 
+    /// served by the replica if published, otherwise by the executor
+    pub const fn is_querying_replica(&self) -> bool {
+        matches!(self.cmd, QUERY_BALANCE | QUERY_ACCOUNTS | QUERY_ORDER)
+    }
+
     pub const fn is_querying_share_data(&self) -> bool {
         matches!(
             self.cmd,
             GET_NONCE_FOR_BROKER
+                | GET_NONCE
                 | GET_X25519_KEY
                 | QUERY_OPEN_MARKETS
                 | QUERY_FUSOTAO_PROGRESS
                 | QUERY_PROVING_PERF_INDEX
                 | QUERY_SCAN_HEIGHT
+                | QUERY_API_USAGE
+                | QUERY_KLINES
+                | QUERY_BROKER_FLOW
+                | QUERY_ORDER_HISTORY
+                | QUERY_TICKER
+                | QUERY_STATEMENT
+                | QUERY_BOOK_IMBALANCE
         )
     }
 }
@@ -444,6 +1389,8 @@ pub struct Message {
     pub req_id: u64,
     pub broadcast_type: u8,
     pub payload: Vec<u8>,
+    /// the rejections are flagged in the headers
+    pub error: bool,
 }
 
 const _MAGIC_N_MASK: u64 = 0x0316_0000_0000_0000;
@@ -454,7 +1401,14 @@ const _NXT_FRM_MASK: u64 = 0x0000_0000_0000_0002;
 const _BRD_TYP_MASK: u64 = 0x0000_0000_0000_ff00;
 
 pub const ORDER_MATCHED: u8 = 0x01;
+/// not broadcasted any more, the depth is maintained by the `DEPTH_DELTA`s
 pub const DEPTH_UPDATED: u8 = 0x02;
+pub const BLOCK_TRADE_REPORTED: u8 = 0x03;
+pub const TRADE_EXECUTED: u8 = 0x04;
+pub const DEPTH_DELTA: u8 = 0x05;
+pub const TICKER_UPDATED: u8 = 0x06;
+pub const TRANSFER_EXECUTED: u8 = 0x07;
+pub const BOOK_IMBALANCE_UPDATED: u8 = 0x08;
 
 /// header = 0x0316<2bytes payload len><2bytes cheskcum><2bytes flag>
 pub const MAX_FRAME_SIZE: usize = 64 * 1024;
@@ -465,6 +1419,16 @@ impl Message {
             req_id,
             broadcast_type: 0,
             payload,
+            error: false,
+        }
+    }
+
+    pub fn new_err(req_id: u64, payload: Vec<u8>) -> Self {
+        Self {
+            req_id,
+            broadcast_type: 0,
+            payload,
+            error: true,
         }
     }
 
@@ -473,18 +1437,20 @@ impl Message {
             req_id: 0,
             broadcast_type,
             payload,
+            error: false,
         }
     }
 
     pub fn encode(self) -> Vec<u8> {
         let frame_count = self.payload.len() / MAX_FRAME_SIZE + 1;
         let mut payload_len = self.payload.len();
+        let flag = if self.error { _ERR_RSP_MASK } else { 0 };
         let mut all = Vec::<u8>::with_capacity(payload_len + 16 * frame_count);
         for i in 0..frame_count - 1 {
             let mut header = _MAGIC_N_MASK;
             header |= (MAX_FRAME_SIZE as u64) << 32;
             header |= (self.broadcast_type as u64) << 16;
-            header |= 1;
+            header |= _NXT_FRM_MASK | flag;
             payload_len -= MAX_FRAME_SIZE;
             all.extend_from_slice(&header.to_be_bytes());
             all.extend_from_slice(&self.req_id.to_be_bytes());
@@ -493,6 +1459,7 @@ impl Message {
         let mut header = _MAGIC_N_MASK;
         header |= (payload_len as u64) << 32;
         header |= (self.broadcast_type as u64) << 16;
+        header |= flag;
         all.extend_from_slice(&header.to_be_bytes());
         all.extend_from_slice(&self.req_id.to_be_bytes());
         all.extend_from_slice(&self.payload[(frame_count - 1) * MAX_FRAME_SIZE..]);
@@ -511,6 +1478,10 @@ impl Message {
         (header & _NXT_FRM_MASK) == _NXT_FRM_MASK
     }
 
+    pub const fn is_error(header: u64) -> bool {
+        (header & _ERR_RSP_MASK) == _ERR_RSP_MASK
+    }
+
     pub const fn get_broadcast_type(header: u64) -> u8 {
         ((header & _BRD_TYP_MASK) >> 16) as u8
     }
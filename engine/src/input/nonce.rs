@@ -0,0 +1,133 @@
+// Copyright 2021-2023 UINB Technologies Pte. Ltd.
+
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// 	http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::{core::*, input::Event};
+use std::collections::BTreeSet;
+
+// the nonce keys are ordered before the `sequence` keys in the same store
+const NONCE_PREFIX: &[u8; 5] = b"nonce";
+
+/// the nonces kept besides the lowest one, the same as `Session::try_occupy_nonce` of the sidecar
+pub const NONCE_WINDOW: usize = 100;
+
+/// the nonces recently accepted from a user, the ones below the window are expired
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct Window(BTreeSet<u32>);
+
+impl Window {
+    /// the nonces in big endian, ascending
+    pub fn decode(v: &[u8]) -> Self {
+        Self(
+            v.chunks_exact(4)
+                .map(|n| u32::from_be_bytes(n.try_into().expect("4 bytes;qed")))
+                .collect(),
+        )
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        self.0.iter().flat_map(|n| n.to_be_bytes()).collect()
+    }
+
+    pub fn last(&self) -> Option<u32> {
+        self.0.last().copied()
+    }
+
+    /// `false` if the nonce has been used or expired
+    pub fn occupy(&mut self, nonce: u32) -> bool {
+        match self.0.first() {
+            Some(min) if *min > nonce || self.0.contains(&nonce) => return false,
+            _ => {}
+        }
+        if self.0.len() > NONCE_WINDOW {
+            self.0.pop_first();
+        }
+        self.0.insert(nonce)
+    }
+}
+
+/// the nonces signed by the users, i.e. both sides of a block trade
+pub fn signed_by(event: &Event) -> Vec<(UserId, u32)> {
+    match event {
+        Event::Limit(_, cmd, ..) => vec![(cmd.user_id, cmd.nonce)],
+        Event::Market(_, cmd, ..) => vec![(cmd.user_id, cmd.nonce)],
+        Event::Cancel(_, cmd, ..) => vec![(cmd.user_id, cmd.nonce)],
+        Event::SubTransfer(_, cmd, ..) => vec![(cmd.user_id, cmd.nonce)],
+        Event::Route(_, cmd, ..) => vec![(cmd.user_id, cmd.nonce)],
+        Event::BlockTrade(_, cmd, ..) => vec![
+            (cmd.user_id, cmd.nonce),
+            (cmd.counterparty, cmd.counterparty_nonce),
+        ],
+        _ => vec![],
+    }
+}
+
+/// the windows to save along with the event, `None` if any of the nonces has been used or expired
+pub fn advance(event: &Event) -> anyhow::Result<Option<Vec<(UserId, Window)>>> {
+    let mut windows: Vec<(UserId, Window)> = vec![];
+    for (user_id, nonce) in signed_by(event) {
+        let idx = match windows.iter().position(|(u, _)| *u == user_id) {
+            Some(idx) => idx,
+            None => {
+                windows.push((user_id, window_of(&user_id)?));
+                windows.len() - 1
+            }
+        };
+        if !windows[idx].1.occupy(nonce) {
+            return Ok(None);
+        }
+    }
+    Ok(Some(windows))
+}
+
+/// the last nonce accepted from the user across the sessions
+pub fn get(user_id: &UserId) -> anyhow::Result<Option<u32>> {
+    Ok(window_of(user_id)?.last())
+}
+
+/// the single nonces saved by the older versions are read as the windows of one
+pub fn window_of(user_id: &UserId) -> anyhow::Result<Window> {
+    let v = SEQ_STORE.get(key_of(user_id))?;
+    Ok(v.map(|v| Window::decode(&v)).unwrap_or_default())
+}
+
+pub fn key_of(user_id: &UserId) -> Vec<u8> {
+    [&NONCE_PREFIX[..], user_id.as_ref()].concat()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    pub fn test_nonce() {
+        let mut window = Window::default();
+        assert!(window.occupy(5));
+        // out of order within the window
+        assert!(!window.occupy(3));
+        assert!(window.occupy(9));
+        assert!(window.occupy(7));
+        assert!(!window.occupy(7));
+        assert_eq!(Some(9), window.last());
+        for n in 10..10 + NONCE_WINDOW as u32 {
+            assert!(window.occupy(n));
+        }
+        // 5 and 7 are evicted
+        assert!(!window.occupy(8));
+        assert!(window.occupy(200));
+        assert_eq!(NONCE_WINDOW + 1, window.0.len());
+        assert_eq!(window, Window::decode(&window.encode()));
+        assert_eq!(Some(7), Window::decode(&7u32.to_be_bytes()).last());
+        assert!(key_of(&UserId::new([0xff; 32])).as_slice() < &b"sequence"[..]);
+    }
+}
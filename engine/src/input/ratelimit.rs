@@ -0,0 +1,181 @@
+// Copyright 2021-2023 UINB Technologies Pte. Ltd.
+
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// 	http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::{
+    config::{RateLimit, RateLimitConfig, C},
+    input::{cmd::*, usage::CmdClass, Command},
+};
+use dashmap::DashMap;
+use std::{
+    sync::RwLock,
+    time::{Duration, Instant},
+};
+
+lazy_static::lazy_static! {
+    pub static ref LIMITER: RateLimiter = RateLimiter::new(C.rate_limit.clone().unwrap_or_default());
+}
+
+const EVICT_INTERVAL: Duration = Duration::from_secs(60);
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
+pub enum LimitClass {
+    Order,
+    Cancel,
+    Query,
+}
+
+impl LimitClass {
+    /// the admin commands are never limited
+    pub fn of(cmd: u32) -> Option<Self> {
+        match (cmd, CmdClass::of(cmd)) {
+            (CANCEL | CANCEL_ALL, _) => Some(LimitClass::Cancel),
+            (_, CmdClass::Trade) => Some(LimitClass::Order),
+            (_, CmdClass::Query) => Some(LimitClass::Query),
+            (_, CmdClass::Admin) => None,
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+/// token buckets of each broker, or the user if not via a broker, or the session if anonymous
+#[derive(Debug, Default)]
+pub struct RateLimiter {
+    config: RwLock<RateLimitConfig>,
+    buckets: DashMap<(String, LimitClass), Bucket>,
+}
+
+impl RateLimiter {
+    pub fn new(config: RateLimitConfig) -> Self {
+        Self {
+            config: RwLock::new(config),
+            buckets: DashMap::new(),
+        }
+    }
+
+    /// the buckets are refilled by the new rates and capped by the new bursts on acquiring
+    pub fn reload(&self, config: RateLimitConfig) {
+        *self.config.write().unwrap() = config;
+    }
+
+    fn limit_of(&self, class: LimitClass) -> Option<RateLimit> {
+        let config = self.config.read().unwrap();
+        match class {
+            LimitClass::Order => config.orders,
+            LimitClass::Cancel => config.cancels,
+            LimitClass::Query => config.queries,
+        }
+    }
+
+    /// `false` if the request exceeds the limit of its class
+    pub fn acquire(&self, session: u64, cmd: &Command) -> bool {
+        let class = match LimitClass::of(cmd.cmd) {
+            Some(class) => class,
+            None => return true,
+        };
+        let key = match cmd.broker.as_ref().or(cmd.user_id.as_ref()) {
+            Some(key) => key.clone(),
+            None => format!("session:{}", session),
+        };
+        self.try_acquire(key, class, Instant::now())
+    }
+
+    fn try_acquire(&self, key: String, class: LimitClass, now: Instant) -> bool {
+        let limit = match self.limit_of(class) {
+            Some(limit) => limit,
+            None => return true,
+        };
+        let mut bucket = self.buckets.entry((key, class)).or_insert(Bucket {
+            tokens: limit.burst as f64,
+            updated: now,
+        });
+        let elapsed = now.saturating_duration_since(bucket.updated).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * limit.rate as f64).min(limit.burst as f64);
+        bucket.updated = now;
+        if bucket.tokens < 1.0 {
+            return false;
+        }
+        bucket.tokens -= 1.0;
+        true
+    }
+
+    /// drop the buckets refilled entirely, which are the same as the absent ones
+    pub fn evict(&self, now: Instant) {
+        self.buckets.retain(|(_, class), bucket| {
+            self.limit_of(*class).map_or(false, |limit| {
+                let elapsed = now.saturating_duration_since(bucket.updated).as_secs_f64();
+                bucket.tokens + elapsed * (limit.rate as f64) < limit.burst as f64
+            })
+        });
+    }
+}
+
+/// the limits could be enabled by reloading the config
+pub fn init() {
+    std::thread::spawn(|| loop {
+        std::thread::sleep(EVICT_INTERVAL);
+        LIMITER.evict(Instant::now());
+    });
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    pub fn test_rate_limit() {
+        let limiter = RateLimiter::new(RateLimitConfig {
+            orders: Some(RateLimit { rate: 2, burst: 3 }),
+            cancels: None,
+            queries: Some(RateLimit { rate: 1, burst: 1 }),
+        });
+        let now = Instant::now();
+        let alice = || "alice".to_string();
+        for _ in 0..3 {
+            assert!(limiter.try_acquire(alice(), LimitClass::Order, now));
+        }
+        assert!(!limiter.try_acquire(alice(), LimitClass::Order, now));
+        // the others and the other classes are limited separately
+        assert!(limiter.try_acquire("bob".to_string(), LimitClass::Order, now));
+        assert!(limiter.try_acquire(alice(), LimitClass::Query, now));
+        assert!(!limiter.try_acquire(alice(), LimitClass::Query, now));
+        for _ in 0..10 {
+            assert!(limiter.try_acquire(alice(), LimitClass::Cancel, now));
+        }
+        // refilled by `rate` per second up to `burst`
+        let later = now + Duration::from_millis(500);
+        assert!(limiter.try_acquire(alice(), LimitClass::Order, later));
+        assert!(!limiter.try_acquire(alice(), LimitClass::Order, later));
+        limiter.evict(later);
+        assert_eq!(2, limiter.buckets.len());
+        limiter.evict(later + Duration::from_secs(10));
+        assert!(limiter.buckets.is_empty());
+
+        let mut cmd = Command::default();
+        cmd.cmd = CANCEL;
+        assert_eq!(Some(LimitClass::Cancel), LimitClass::of(cmd.cmd));
+        assert_eq!(Some(LimitClass::Order), LimitClass::of(BID_LIMIT));
+        assert_eq!(None, LimitClass::of(DUMP));
+        assert_eq!(None, LimitClass::of(CONFIRM_REANCHOR));
+        assert_eq!(None, LimitClass::of(QUERY_MEMORY_STATS));
+        cmd.cmd = QUERY_BALANCE;
+        assert!(limiter.acquire(1, &cmd));
+        assert!(!limiter.acquire(1, &cmd));
+        assert!(limiter.acquire(2, &cmd));
+    }
+}
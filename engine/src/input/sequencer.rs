@@ -12,17 +12,44 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use crate::{config::C, input::*};
+use crate::{
+    config::C,
+    executor::RejectReason,
+    input::*,
+    latency::{self, Stage, Stamps},
+    ring,
+};
 use rocksdb::{Direction, IteratorMode, WriteBatchWithTransaction};
-use std::{convert::TryInto, sync::mpsc::*};
+use serde::Serialize;
+use std::{
+    collections::BTreeMap,
+    convert::TryInto,
+    sync::atomic::{AtomicBool, Ordering},
+    sync::{mpsc::*, Mutex},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+use thiserror::Error;
 
+/// the events sequenced along with the stamps of their stages
+pub type ToExecutor = ring::Producer<(Event, Option<Stamps>)>;
+
+// the inputs keep being sequenced until idle for `STOPPING_IDLE` once stopping
+static STOPPING: AtomicBool = AtomicBool::new(false);
+
+const STOPPING_IDLE: Duration = Duration::from_millis(100);
+
+lazy_static::lazy_static! {
+    static ref GAPS: Mutex<GapStats> = Mutex::new(GapStats::default());
+}
+
+/// the sequencer thread exits once stopped, the executor stops after the sequenced events executed
 pub fn init(
     rx: Receiver<Input>,
-    to_executor: Sender<Event>,
+    to_executor: ToExecutor,
     to_server: Sender<(u64, Message)>,
     init_at: u64,
 ) {
-    let recovery = ensure_fully_loaded(init_at, to_executor.clone()).unwrap();
+    let recovery = ensure_fully_loaded(init_at, &to_executor).unwrap();
     log::info!(
         "historic events {}-{} have been executed",
         init_at,
@@ -36,54 +63,337 @@ pub fn init(
     }
     std::thread::spawn(move || -> anyhow::Result<()> {
         let mut current_id = recovery;
+        let mut journal = C
+            .sequence
+            .journal
+            .as_ref()
+            .map(|j| journal::Journal::open(C.server.get_journal_path(), j.segment_size, j.retain))
+            .transpose()?;
+        if let Some(ref mut journal) = journal {
+            // the events saved before the journal enabled or lost from its tail
+            let mut expected = journal.last_id() + 1;
+            for item in fetch_raw_from(expected) {
+                let (id, cmd) = item?;
+                if id != expected && expected > 1 {
+                    log::warn!("events {}-{} are missing in the journal", expected, id - 1);
+                }
+                journal.append(id, &cmd)?;
+                expected = id + 1;
+            }
+        }
         loop {
-            let mut input = rx.recv()?;
+            let mut input = match rx.recv_timeout(STOPPING_IDLE) {
+                Ok(input) => input,
+                Err(RecvTimeoutError::Timeout) if !is_stopping() => continue,
+                Err(RecvTimeoutError::Timeout) => break,
+                Err(RecvTimeoutError::Disconnected) => return Err(RecvError.into()),
+            };
+            let received = Instant::now();
+            let _span = tracing::debug_span!("sequence", event_id = current_id).entered();
             let (session, req_id) = (input.session, input.req_id);
+            if !C.fusotao.is_provable(&input.cmd) {
+                to_server.send((
+                    session,
+                    rejection(session, req_id, RejectReason::Unprovable),
+                ))?;
+                continue;
+            }
+            if session != 0 {
+                if let Some(reason) = check_signed(&input.cmd, C.sequence.domain.as_ref(), now()) {
+                    to_server.send((session, rejection(session, req_id, reason)))?;
+                    continue;
+                }
+            }
             input.sequence = current_id;
             let cmd = serde_json::to_vec(&input.cmd)?;
-            if let Ok(event) = <Input as TryInto<Event>>::try_into(input) {
-                if event.should_save() {
-                    save(current_id, cmd)?;
-                    to_executor.send(event)?;
-                    if current_id % C.sequence.checkpoint == 0 {
-                        to_executor.send(Event::Dump(current_id))?;
+            match <Input as TryInto<Event>>::try_into(input) {
+                Ok(event) => {
+                    if event.should_save() {
+                        // the nonce of the counterparty isn't consumed without its consent
+                        if matches!(event, Event::BlockTrade(_, ref cmd, ..) if !cmd.is_countersigned())
+                        {
+                            let msg = rejection(session, req_id, RejectReason::InvalidSignature);
+                            to_server.send((session, msg))?;
+                            continue;
+                        }
+                        let nonces = if session == 0 {
+                            Some(vec![])
+                        } else {
+                            nonce::advance(&event)?
+                        };
+                        let Some(nonces) = nonces else {
+                            let msg = rejection(session, req_id, RejectReason::InvalidNonce);
+                            to_server.send((session, msg))?;
+                            continue;
+                        };
+                        if let Some(ref mut journal) = journal {
+                            journal.append(current_id, &cmd)?;
+                        }
+                        save_signed(current_id, cmd, &nonces)?;
+                        to_executor.send((event, Some(stamp(received))))?;
+                        if current_id % C.sequence.checkpoint == 0 {
+                            to_executor.send((Event::Dump(current_id), None))?;
+                        }
+                    } else {
+                        to_executor.send((event, Some(stamp(received))))?;
                     }
-                } else {
-                    to_executor.send(event)?;
+                    current_id += 1;
+                }
+                Err(e) => {
+                    let msg = serde_json::json!({"error": e.to_string()});
+                    to_server.send((session, encoding::reject(session, req_id, &msg)))?;
                 }
-                current_id += 1;
-            } else {
-                to_server.send((session, Message::new_req(req_id, vec![])))?;
             }
         }
+        log::info!("sequencer stopped at {}", current_id - 1);
+        Ok(())
     });
 }
 
+fn rejection(session: u64, req_id: u64, reason: RejectReason) -> Message {
+    let msg = serde_json::json!({"error": reason.to_string(), "code": reason.code()});
+    encoding::reject(session, req_id, &msg)
+}
+
+/// the signatures v2 carry the expiry and the domain, checked against the wall clock
+fn check_signed(cmd: &Command, domain: Option<&String>, now: u64) -> Option<RejectReason> {
+    if cmd.domain.is_some() && cmd.domain.as_ref() != domain {
+        Some(RejectReason::DomainMismatch)
+    } else {
+        cmd.expiry
+            .filter(|expiry| *expiry < now)
+            .map(|_| RejectReason::SignatureExpired)
+    }
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("after epoch;qed")
+        .as_millis() as u64
+}
+
+fn stamp(received: Instant) -> Stamps {
+    let sequenced = Instant::now();
+    latency::record(Stage::Sequence, sequenced - received);
+    Stamps {
+        received,
+        sequenced,
+    }
+}
+
+/// stop sequencing once the pending inputs are drained
+pub fn stop() {
+    STOPPING.store(true, Ordering::Relaxed);
+}
+
+pub fn is_stopping() -> bool {
+    STOPPING.load(Ordering::Relaxed)
+}
+
 pub fn save(id: u64, cmd: Vec<u8>) -> anyhow::Result<()> {
-    SEQ_STORE.put(id_to_key(id), cmd)?;
+    save_signed(id, cmd, &[])
+}
+
+/// save the event along with the nonces it advanced atomically
+pub fn save_signed(
+    id: u64,
+    cmd: Vec<u8>,
+    nonces: &[(UserId, nonce::Window)],
+) -> anyhow::Result<()> {
+    let mut batch = WriteBatchWithTransaction::<false>::default();
+    batch.put(id_to_key(id), cmd);
+    for (user_id, window) in nonces {
+        batch.put(nonce::key_of(user_id), window.encode());
+    }
+    SEQ_STORE.write(batch)?;
     Ok(())
 }
 
-fn ensure_fully_loaded(init_at: u64, tx: Sender<Event>) -> anyhow::Result<u64> {
+/// the saved commands from `from`
+pub fn fetch_raw_from(from: u64) -> impl Iterator<Item = anyhow::Result<(u64, Vec<u8>)>> {
+    SEQ_STORE
+        .iterator(IteratorMode::From(&id_to_key(from), Direction::Forward))
+        .map(|item| {
+            let (key, value) = item?;
+            Ok((key_to_id(&key), value.to_vec()))
+        })
+}
+
+/// LIMIT|CANCEL(session=0, req_id=0) represent historic events, shouldn't reply
+pub fn to_event(id: u64, value: &[u8]) -> anyhow::Result<Event> {
+    let input = Input {
+        session: 0,
+        req_id: 0,
+        sequence: id,
+        cmd: value_to_cmd(value).map_err(|_| anyhow::anyhow!("id {} is invalid", id))?,
+    };
+    <Input as TryInto<Event>>::try_into(input).map_err(|_| anyhow::anyhow!("id {} is invalid", id))
+}
+
+/// the saved events from `from`
+pub fn fetch_from(from: u64) -> impl Iterator<Item = anyhow::Result<(u64, Event)>> {
+    fetch_raw_from(from).map(|item| {
+        let (id, value) = item?;
+        Ok((id, to_event(id, &value)?))
+    })
+}
+
+#[derive(Error, Debug, Eq, PartialEq)]
+pub enum GapError {
+    #[error("events {0}-{1} are missing from the upstream for {2:?}")]
+    Persisted(u64, u64, Duration),
+    #[error("more than {0} events are buffered after the missing ones from {1}")]
+    Overflowed(usize, u64),
+}
+
+/// the alert of the missing events from the upstream, replied by the admin command
+/// `sequence_gaps`
+#[derive(Serialize, Debug, Default, Clone, Eq, PartialEq)]
+pub struct GapStats {
+    pub detected: u64,
+    pub reordered: u64,
+    pub duplicated: u64,
+    /// the ids missing right now
+    pub missing: Option<(u64, u64)>,
+    /// unix seconds when the missing ones were detected
+    pub since: Option<u64>,
+}
+
+pub fn gaps() -> GapStats {
+    GAPS.lock().expect("gaps lock poisoned;qed").clone()
+}
+
+/// the saved events from the upstream along with the ids before them, i.e. nothing is saved
+/// between `prev` and `id`, are released in order; the ones after a gap are buffered until it's
+/// filled, which shouldn't exceed `window` or last longer than `timeout`
+pub struct Reorder {
+    next: u64,
+    window: usize,
+    timeout: Duration,
+    // keyed by `prev`
+    pending: BTreeMap<u64, (u64, Vec<u8>)>,
+    // the last id known missing and when it's detected
+    gap: Option<(u64, Instant)>,
+}
+
+impl Reorder {
+    pub fn new(next: u64, window: usize, timeout: Duration) -> Self {
+        Self {
+            next,
+            window,
+            timeout,
+            pending: BTreeMap::new(),
+            gap: None,
+        }
+    }
+
+    /// the id expected next
+    pub fn next(&self) -> u64 {
+        self.next
+    }
+
+    /// the events ready to apply, the duplicated ones are dropped
+    pub fn push(
+        &mut self,
+        prev: u64,
+        id: u64,
+        cmd: Vec<u8>,
+    ) -> Result<Vec<(u64, Vec<u8>)>, GapError> {
+        if id < self.next {
+            GAPS.lock().expect("gaps lock poisoned;qed").duplicated += 1;
+            return Ok(vec![]);
+        }
+        if prev >= self.next {
+            self.pending.insert(prev, (id, cmd));
+            self.open(prev);
+            if self.pending.len() > self.window {
+                return Err(GapError::Overflowed(self.window, self.next));
+            }
+            return self.check().map(|_| vec![]);
+        }
+        let mut ready = vec![(id, cmd)];
+        self.next = id + 1;
+        while let Some(entry) = self.pending.first_entry() {
+            if *entry.key() >= self.next {
+                break;
+            }
+            let (id, cmd) = entry.remove();
+            let mut stats = GAPS.lock().expect("gaps lock poisoned;qed");
+            if id >= self.next {
+                stats.reordered += 1;
+                ready.push((id, cmd));
+                self.next = id + 1;
+            } else {
+                stats.duplicated += 1;
+            }
+        }
+        self.close();
+        self.check().map(|_| ready)
+    }
+
+    /// the upstream has sent the events till `head`
+    pub fn heartbeat(&mut self, head: u64) -> Result<(), GapError> {
+        if head >= self.next {
+            self.open(head);
+        } else {
+            self.close();
+        }
+        self.check()
+    }
+
+    fn open(&mut self, to: u64) {
+        match self.gap.as_mut() {
+            Some((last, _)) => *last = (*last).max(to),
+            None => {
+                log::error!("events from {} to {} are missing", self.next, to);
+                self.gap = Some((to, Instant::now()));
+                let mut stats = GAPS.lock().expect("gaps lock poisoned;qed");
+                stats.detected += 1;
+                stats.since = SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .ok()
+                    .map(|d| d.as_secs());
+            }
+        }
+        let mut stats = GAPS.lock().expect("gaps lock poisoned;qed");
+        stats.missing = self.gap.map(|(last, _)| (self.next, last));
+    }
+
+    fn close(&mut self) {
+        match self.gap {
+            Some((last, _)) if self.next > last => {
+                log::info!("events to {} are filled", last);
+                self.gap = None;
+                let mut stats = GAPS.lock().expect("gaps lock poisoned;qed");
+                stats.missing = None;
+                stats.since = None;
+            }
+            Some((last, _)) => {
+                GAPS.lock().expect("gaps lock poisoned;qed").missing = Some((self.next, last))
+            }
+            None => {}
+        }
+    }
+
+    fn check(&self) -> Result<(), GapError> {
+        match self.gap {
+            Some((last, since)) if since.elapsed() >= self.timeout => {
+                Err(GapError::Persisted(self.next, last, since.elapsed()))
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+fn ensure_fully_loaded(init_at: u64, tx: &ToExecutor) -> anyhow::Result<u64> {
     let mut current_id = init_at;
-    let iter = SEQ_STORE.iterator(IteratorMode::From(&id_to_key(init_at), Direction::Forward));
-    for item in iter {
-        let (key, value) = item?;
-        current_id = key_to_id(&key);
-        let input = Input {
-            session: 0,
-            req_id: 0,
-            sequence: current_id,
-            cmd: value_to_cmd(&value)
-                .map_err(|_| anyhow::anyhow!("id {} is invalid", current_id))?,
-        };
-        let event = input
-            .try_into()
-            .map_err(|_| anyhow::anyhow!("id {} is invalid", current_id))?;
-        // LIMIT|CANCEL(session=0, req_id=0) represent historic events, shouldn't reply
+    for item in fetch_from(init_at) {
+        let (id, event) = item?;
+        current_id = id;
         match C.dry_run {
-            Some(n) if n >= current_id => tx.send(event)?,
-            None => tx.send(event)?,
+            Some(n) if n >= current_id => tx.send((event, None))?,
+            None => tx.send((event, None))?,
             _ => break,
         }
     }
@@ -155,7 +465,7 @@ mod test {
             true,
             true,
         );
-        data.orderbooks.insert((0, 1), orderbook);
+        data.orderbooks.insert((0, 1), orderbook.into());
     }
 
     #[test]
@@ -180,5 +490,126 @@ mod test {
         }
         .try_into();
         assert!(s.is_ok());
+        let block_bid = r#"{"quote":100, "base":101, "cmd":31, "price":"10.0", "amount":"5000", "user_id":"5Ccr8Qcp6NBMCvdUHSoqDaQMJHnA5PAC879NbWkzaiUwBdMm", "counterparty":"0x0000000000000000000000000000000000000000000000000000000000000001","nonce":1,"signature":"","counterparty_nonce":1,"counterparty_signature":""}"#;
+        let e = serde_json::from_str::<Command>(block_bid).unwrap();
+        let s: anyhow::Result<Event> = Input {
+            cmd: e,
+            sequence: 0,
+            session: 1,
+            req_id: 0,
+        }
+        .try_into();
+        let mut cmd = match s {
+            Ok(Event::BlockTrade(_, cmd, ..)) => cmd,
+            _ => panic!("block trade expected"),
+        };
+        assert_eq!(AskOrBid::Bid, cmd.ask_or_bid);
+        assert!(!cmd.is_countersigned());
+        let counterparty =
+            <sp_core::sr25519::Pair as sp_core::Pair>::from_string("//Bob", None).unwrap();
+        cmd.counterparty = UserId::new(sp_core::Pair::public(&counterparty).0);
+        cmd.counterparty_signature = sp_core::Pair::sign(&counterparty, &cmd.terms()).0.to_vec();
+        assert!(cmd.is_countersigned());
+        // the terms changed
+        cmd.amount = dec!(5001);
+        assert!(!cmd.is_countersigned());
+        let market_ask = r#"{"quote":100, "base":101, "cmd":2, "amount":"0.5", "user_id":"5Ccr8Qcp6NBMCvdUHSoqDaQMJHnA5PAC879NbWkzaiUwBdMm","nonce":1,"signature":""}"#;
+        let e = serde_json::from_str::<Command>(market_ask).unwrap();
+        let s: anyhow::Result<Event> = Input {
+            cmd: e,
+            sequence: 0,
+            session: 1,
+            req_id: 0,
+        }
+        .try_into();
+        assert!(matches!(s, Ok(Event::Market(_, cmd, ..)) if cmd.ask_or_bid == AskOrBid::Ask));
+        let ioc = r#"{"quote":100, "base":101, "cmd":1, "price":"0.1", "amount":"1", "user_id":"5Ccr8Qcp6NBMCvdUHSoqDaQMJHnA5PAC879NbWkzaiUwBdMm","nonce":1,"signature":"","time_in_force":"IOC"}"#;
+        let e = serde_json::from_str::<Command>(ioc).unwrap();
+        let s: anyhow::Result<Event> = Input::new(e).try_into();
+        assert!(
+            matches!(s, Ok(Event::Limit(_, cmd, ..)) if cmd.time_in_force == TimeInForce::ImmediateOrCancel)
+        );
+        let stp = r#"{"quote":100, "base":101, "cmd":1, "price":"0.1", "amount":"1", "user_id":"5Ccr8Qcp6NBMCvdUHSoqDaQMJHnA5PAC879NbWkzaiUwBdMm","nonce":1,"signature":"01ff","broker":"5Ccr8Qcp6NBMCvdUHSoqDaQMJHnA5PAC879NbWkzaiUwBdMm","self_trade_prevention":"cancel_oldest"}"#;
+        let e = serde_json::from_str::<Command>(stp).unwrap();
+        let s: anyhow::Result<Event> = Input::new(e).try_into();
+        let cmd = match s {
+            Ok(Event::Limit(_, cmd, ..)) => cmd,
+            _ => panic!("limit expected"),
+        };
+        assert_eq!(
+            Some(crate::matcher::SelfTradePrevention::CancelOldest),
+            cmd.self_trade_prevention
+        );
+        let cancel_all = r#"{"quote":100, "base":101, "cmd":5}"#;
+        let e = serde_json::from_str::<Command>(cancel_all).unwrap();
+        let s: anyhow::Result<Event> = Input {
+            cmd: e.clone(),
+            sequence: 0,
+            session: 1,
+            req_id: 0,
+        }
+        .try_into();
+        assert!(s.is_err());
+        let s: anyhow::Result<Event> = Input::new(e).try_into();
+        assert!(matches!(s, Ok(Event::CancelAll(_, (101, 100), None, ..))));
+    }
+
+    #[test]
+    pub fn test_check_signed() {
+        let limit = r#"{"quote":100, "base":101, "cmd":1, "price":"10.0", "amount":"0.5", "user_id":"5Ccr8Qcp6NBMCvdUHSoqDaQMJHnA5PAC879NbWkzaiUwBdMm","nonce":1,"signature":"","expiry":1000,"domain":"fusotao"}"#;
+        let cmd = serde_json::from_str::<Command>(limit).unwrap();
+        let domain = Some("fusotao".to_string());
+        assert_eq!(None, check_signed(&cmd, domain.as_ref(), 1000));
+        assert_eq!(
+            Some(RejectReason::SignatureExpired),
+            check_signed(&cmd, domain.as_ref(), 1001)
+        );
+        let testnet = Some("fusotao-testnet".to_string());
+        assert_eq!(
+            Some(RejectReason::DomainMismatch),
+            check_signed(&cmd, testnet.as_ref(), 0)
+        );
+        assert_eq!(
+            Some(RejectReason::DomainMismatch),
+            check_signed(&cmd, None, 0)
+        );
+        // v1 without expiry nor domain
+        let cmd = Command::default();
+        assert_eq!(None, check_signed(&cmd, testnet.as_ref(), u64::MAX));
+    }
+
+    #[test]
+    pub fn test_reorder() {
+        let ids =
+            |ready: Vec<(u64, Vec<u8>)>| ready.into_iter().map(|(id, _)| id).collect::<Vec<_>>();
+        let mut reorder = Reorder::new(10, 2, Duration::from_millis(50));
+        // the unsaved ids are skipped
+        assert_eq!(vec![12], ids(reorder.push(9, 12, vec![]).unwrap()));
+        assert_eq!(vec![13], ids(reorder.push(12, 13, vec![]).unwrap()));
+        // 14-15 are missing
+        assert!(reorder.push(15, 16, vec![]).unwrap().is_empty());
+        assert!(reorder.push(16, 18, vec![]).unwrap().is_empty());
+        assert_eq!(Some((14, 16)), gaps().missing);
+        assert_eq!(vec![15, 16, 18], ids(reorder.push(13, 15, vec![]).unwrap()));
+        assert_eq!(None, gaps().missing);
+        assert_eq!(19, reorder.next());
+        assert!(reorder.push(13, 15, vec![]).unwrap().is_empty());
+        assert!(reorder.heartbeat(18).is_ok());
+
+        // stalled
+        assert!(reorder.heartbeat(20).is_ok());
+        std::thread::sleep(Duration::from_millis(60));
+        assert!(matches!(
+            reorder.heartbeat(20),
+            Err(GapError::Persisted(19, 20, _))
+        ));
+        let mut reorder = Reorder::new(19, 2, Duration::from_secs(60));
+        for (prev, id) in [(20, 21), (21, 22)] {
+            assert!(reorder.push(prev, id, vec![]).unwrap().is_empty());
+        }
+        assert_eq!(
+            Err(GapError::Overflowed(2, 19)),
+            reorder.push(22, 23, vec![])
+        );
     }
 }
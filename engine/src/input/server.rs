@@ -14,7 +14,16 @@
 
 use crate::{
     config::C,
-    input::{Command, Input, Message},
+    executor::{replica, RejectReason},
+    input::{
+        cipher::{self, SessionCipher},
+        cmd::{PING, RESUME_SESSION, X25519_HANDSHAKE},
+        encoding,
+        ratelimit::LIMITER,
+        tls::TlsCerts,
+        usage::{CmdClass, USAGE},
+        Command, Input, Message,
+    },
     shared::Shared,
 };
 use async_std::{
@@ -25,40 +34,92 @@ use async_std::{
 use dashmap::DashMap;
 use futures::{
     channel::mpsc::{self, UnboundedReceiver, UnboundedSender},
+    io::{AsyncRead, AsyncWrite},
     sink::SinkExt,
 };
+use futures_rustls::TlsAcceptor;
 use std::{
     net::Shutdown,
     sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
         mpsc::{Receiver, Sender},
         Arc,
     },
+    time::{Duration, Instant},
 };
 
+lazy_static::lazy_static! {
+    // the tcp sessions, shut down on draining
+    static ref STREAMS: DashMap<u64, Arc<TcpStream>> = DashMap::new();
+    // all the sessions, drained on shutting down
+    static ref SESSIONS: Sessions = Arc::new(DashMap::new());
+    // (session, req_id) -> the cipher sealing the replies after the handshake replied
+    static ref HANDSHAKES: DashMap<(u64, u64), Arc<SessionCipher>> = DashMap::new();
+    // resumption token -> session, issued by `RESUME_SESSION`
+    static ref TOKENS: DashMap<String, u64> = DashMap::new();
+    // the disconnected sessions with tokens, buffering the replies until resumed or expired
+    static ref DETACHED: DashMap<u64, (FromSession, Instant)> = DashMap::new();
+}
+
+// NOTICE: session id must be started from 1
+static NEXT_SESSION: AtomicU64 = AtomicU64::new(1);
+
+// the connections not handshaked in time are dropped
+const TLS_HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+// no more connections or requests are accepted once draining
+static DRAINING: AtomicBool = AtomicBool::new(false);
+
 type Result<T> = std::result::Result<T, Box<dyn std::error::Error + Send + Sync>>;
-type ToSession = UnboundedSender<Message>;
-type FromSession = UnboundedReceiver<Message>;
-type ToBackend = Sender<Input>;
+pub(crate) type ToSession = UnboundedSender<Message>;
+pub(crate) type FromSession = UnboundedReceiver<Message>;
+pub(crate) type ToBackend = Sender<Input>;
 type FromBackend = Receiver<(u64, Message)>;
+pub(crate) type Sessions = Arc<DashMap<u64, ToSession>>;
+
+/// the sessions of tcp connections and grpc calls share the same id space
+pub(crate) fn next_session() -> u64 {
+    NEXT_SESSION.fetch_add(1, Ordering::Relaxed)
+}
 
 pub fn init(receiver: FromBackend, sender: ToBackend, shared: Shared) {
     if C.dry_run.is_some() {
         return;
     }
     let listener = task::block_on(async { TcpListener::bind(&C.server.bind_addr).await }).unwrap();
-    let sessions = Arc::new(DashMap::<u64, ToSession>::new());
+    let tls = C.server.tls.as_ref().map(|tls| {
+        Arc::new(TlsCerts::open(&tls.cert_path, &tls.key_path).expect("checked by config;qed"))
+    });
+    crate::usage::init(C.server.get_usage_path());
+    crate::input::ratelimit::init();
+    let sessions = SESSIONS.clone();
     let sx = sessions.clone();
     std::thread::spawn(move || {
         log::error!("session relayer interrupted, {:?}", relay(receiver, sx));
     });
+    if C.server.resume_timeout > 0 {
+        let sx = sessions.clone();
+        let timeout = Duration::from_secs(C.server.resume_timeout);
+        std::thread::spawn(move || loop {
+            std::thread::sleep(Duration::from_secs(1));
+            expire_detached(&sx, timeout);
+        });
+    }
+    #[cfg(feature = "grpc")]
+    crate::input::grpc::init(sender.clone(), shared.clone(), sessions.clone());
+    #[cfg(feature = "fix")]
+    crate::input::fix::init(sender.clone(), shared.clone(), sessions.clone());
+    if let Some(ref admin) = C.admin {
+        crate::input::admin::init(admin, sender.clone(), shared.clone(), sessions.clone());
+    }
     log::info!("server initialized");
-    let future = accept(listener, sender, shared, sessions);
+    let future = accept(listener, tls, sender, shared, sessions);
     let _ = task::block_on(future);
     log::info!("bye!");
 }
 
 /// relay the messages from backend to session, using block_on to switch to async
-fn relay(receiver: FromBackend, sessions: Arc<DashMap<u64, ToSession>>) -> Result<()> {
+fn relay(receiver: FromBackend, sessions: Sessions) -> Result<()> {
     loop {
         let (session_id, msg) = receiver.recv()?;
         if session_id == 0 {
@@ -67,6 +128,7 @@ fn relay(receiver: FromBackend, sessions: Arc<DashMap<u64, ToSession>>) -> Resul
             });
         } else {
             log::debug!("session relayer received msg: {:?}", msg);
+            USAGE.record_reply(session_id, &msg);
             if let Some(mut session) = sessions.get_mut(&session_id) {
                 let _ = task::block_on(session.send(msg));
             } else {
@@ -78,54 +140,108 @@ fn relay(receiver: FromBackend, sessions: Arc<DashMap<u64, ToSession>>) -> Resul
 
 async fn accept(
     listener: TcpListener,
+    tls: Option<Arc<TlsCerts>>,
     to_backend: ToBackend,
     shared: Shared,
-    sessions: Arc<DashMap<u64, ToSession>>,
+    sessions: Sessions,
 ) -> Result<()> {
     let mut incoming = listener.incoming();
-    // NOTICE: session id must be started from 1
-    let mut session_id = 1_u64;
     while let Some(stream) = incoming.next().await {
         let stream = stream?;
-        register(
-            session_id,
-            stream,
-            to_backend.clone(),
-            shared.clone(),
-            sessions.clone(),
-        );
-        session_id += 1;
+        if DRAINING.load(Ordering::Relaxed) {
+            let _ = stream.shutdown(Shutdown::Both);
+            continue;
+        }
+        if stream.set_nodelay(true).is_err() {
+            continue;
+        }
+        let (to_backend, shared, sessions) = (to_backend.clone(), shared.clone(), sessions.clone());
+        match tls {
+            Some(ref certs) => {
+                // the handshakes mustn't block accepting
+                let acceptor = TlsAcceptor::from(certs.server_config());
+                task::spawn(async move {
+                    let tcp = stream.clone();
+                    match async_std::future::timeout(TLS_HANDSHAKE_TIMEOUT, acceptor.accept(stream))
+                        .await
+                    {
+                        Ok(Ok(tls)) => {
+                            let (reader, writer) = futures::io::AsyncReadExt::split(tls);
+                            register(
+                                next_session(),
+                                tcp,
+                                reader,
+                                writer,
+                                to_backend,
+                                shared,
+                                sessions,
+                            );
+                        }
+                        Ok(Err(e)) => log::debug!("TLS handshake with {:?} failed, {:?}", tcp, e),
+                        Err(_) => {
+                            log::debug!("TLS handshake with {:?} timeout", tcp);
+                            let _ = tcp.shutdown(Shutdown::Both);
+                        }
+                    }
+                });
+            }
+            None => register(
+                next_session(),
+                stream.clone(),
+                stream.clone(),
+                stream,
+                to_backend,
+                shared,
+                sessions,
+            ),
+        }
     }
     Ok(())
 }
 
-fn register(
+/// `reader` and `writer` are the halves of `stream`, or of the TLS session over it
+fn register<R, W>(
     session_id: u64,
     stream: TcpStream,
+    reader: R,
+    writer: W,
     to_backend: ToBackend,
     shared: Shared,
-    sessions: Arc<DashMap<u64, ToSession>>,
-) {
-    match stream.set_nodelay(true) {
-        Ok(_) => {}
-        Err(_) => return,
-    }
+    sessions: Sessions,
+) where
+    R: AsyncRead + Unpin + Send + 'static,
+    W: AsyncWrite + Unpin + Send + 'static,
+{
     let (tx, rx) = mpsc::unbounded();
     sessions.insert(session_id, tx);
     let stream = Arc::new(stream);
-    task::spawn(write_loop(rx, stream.clone()));
+    STREAMS.insert(session_id, stream.clone());
+    task::spawn(write_loop(rx, writer, session_id));
     task::spawn(read_loop(
         to_backend.clone(),
         shared,
         session_id,
         stream,
+        reader,
         sessions,
     ));
 }
 
-async fn write_loop(mut recv: FromSession, stream: Arc<TcpStream>) -> Result<()> {
-    let mut stream = &*stream;
+async fn write_loop<W: AsyncWrite + Unpin>(
+    mut recv: FromSession,
+    mut stream: W,
+    session_id: u64,
+) -> Result<()> {
+    let mut cipher: Option<Arc<SessionCipher>> = None;
     while let Some(output) = recv.next().await {
+        let req_id = output.req_id;
+        let output = match cipher {
+            Some(ref c) => Message {
+                payload: c.seal(&output.payload),
+                ..output
+            },
+            None => output,
+        };
         match stream.write_all(&output.encode()).await {
             Ok(_) => log::debug!("replying to sidecar -> OK"),
             Err(e) => {
@@ -133,28 +249,47 @@ async fn write_loop(mut recv: FromSession, stream: Arc<TcpStream>) -> Result<()>
                 break;
             }
         }
+        if cipher.is_none() && req_id != 0 {
+            cipher = HANDSHAKES.remove(&(session_id, req_id)).map(|(_, c)| c);
+        }
     }
-    log::info!("bye! {:?}", stream);
+    log::info!("bye! session {}", session_id);
     Ok(())
 }
 
-async fn read_loop(
+async fn read_loop<R: AsyncRead + Unpin>(
     mut to_back: ToBackend,
     shared: Shared,
-    session_id: u64,
-    stream: Arc<TcpStream>,
-    sessions: Arc<DashMap<u64, ToSession>>,
+    mut session_id: u64,
+    tcp: Arc<TcpStream>,
+    mut stream: R,
+    sessions: Sessions,
 ) -> Result<()> {
-    let mut stream = &*stream;
     let mut buf = Vec::<u8>::with_capacity(4096);
     let mut to_session = sessions
         .get(&session_id)
         .ok_or(anyhow::anyhow!("session not found;qed?"))?
         .clone();
+    // the handshake is required as the first request, then the payloads are sealed
+    let (mut first, mut cipher) = (true, None::<Arc<SessionCipher>>);
+    // the resumption is only accepted before the other requests except the handshake
+    let mut opening = true;
+    let idle = Duration::from_secs(C.server.idle_timeout);
     loop {
         let mut header = [0_u8; 8];
         let mut req_id = [0_u8; 8];
-        if stream.read_exact(&mut header).await.is_err() {
+        let read = stream.read_exact(&mut header);
+        let read = match idle.is_zero() {
+            true => read.await,
+            false => match async_std::future::timeout(idle, read).await {
+                Ok(read) => read,
+                Err(_) => {
+                    log::info!("session {} idle for {:?}, closing", session_id, idle);
+                    break;
+                }
+            },
+        };
+        if read.is_err() {
             break;
         }
         if stream.read_exact(&mut req_id).await.is_err() {
@@ -170,11 +305,68 @@ async fn read_loop(
             break;
         }
         buf.extend_from_slice(&tmp[..]);
+        if DRAINING.load(Ordering::Relaxed) {
+            break;
+        }
         if !Message::has_next_frame(header) {
-            let json = match std::str::from_utf8(&buf[..]) {
+            let plain = match cipher {
+                Some(ref c) => match c.open(&buf) {
+                    Ok(plain) => Some(plain),
+                    Err(_) => break,
+                },
+                None => None,
+            };
+            let json = match std::str::from_utf8(plain.as_deref().unwrap_or(&buf[..])) {
                 Ok(json) => json.to_string(),
                 Err(_) => break,
             };
+            buf.clear();
+            if std::mem::take(&mut first) {
+                match handshake(&shared, &json) {
+                    Some(Ok((reply, c))) => {
+                        let c = Arc::new(c);
+                        HANDSHAKES.insert((session_id, req_id), c.clone());
+                        if to_session
+                            .send(Message::new_req(req_id, reply))
+                            .await
+                            .is_err()
+                        {
+                            break;
+                        }
+                        cipher.replace(c);
+                        continue;
+                    }
+                    Some(Err(e)) => {
+                        log::error!("{:?}, will close session {}", e, session_id);
+                        break;
+                    }
+                    None => {
+                        log::warn!("session {} not handshaked, closing", session_id);
+                        break;
+                    }
+                }
+            }
+            if std::mem::take(&mut opening) {
+                if let Some(token) = resume_request(&json) {
+                    let (resumed, reply) = resume(
+                        session_id,
+                        token,
+                        C.server.resume_timeout > 0,
+                        &to_session,
+                        &sessions,
+                    );
+                    session_id = resumed;
+                    let reply = serde_json::to_vec(&reply).unwrap_or_default();
+                    if to_session
+                        .send(Message::new_req(req_id, reply))
+                        .await
+                        .is_err()
+                    {
+                        break;
+                    }
+                    continue;
+                }
+            }
             if let Err(e) = handle_req(
                 &mut to_back,
                 &mut to_session,
@@ -188,15 +380,176 @@ async fn read_loop(
                 log::error!("{:?}, will close session {}", e, session_id);
                 break;
             }
-            buf.clear();
         }
     }
-    let _ = stream.shutdown(Shutdown::Both);
+    let _ = tcp.shutdown(Shutdown::Both);
+    detach_or_close(
+        &sessions,
+        session_id,
+        C.server.resume_timeout > 0 && !DRAINING.load(Ordering::Relaxed),
+    );
+    Ok(())
+}
+
+/// `Some(token)` if the request is a `RESUME_SESSION`, the token is absent to issue one
+fn resume_request(json: &str) -> Option<Option<String>> {
+    let cmd = serde_json::from_str::<Command>(json).ok()?;
+    (cmd.cmd == RESUME_SESSION).then_some(cmd.resume_token)
+}
+
+/// issue a token of `session_id` if `token` is absent, or take over the detached session of
+/// `token` and flush the replies buffered, returning the session to continue with and the reply
+fn resume(
+    session_id: u64,
+    token: Option<String>,
+    enabled: bool,
+    to_session: &ToSession,
+    sessions: &Sessions,
+) -> (u64, serde_json::Value) {
+    if !enabled {
+        return (
+            session_id,
+            serde_json::json!({"error": "session resumption disabled"}),
+        );
+    }
+    let token = match token {
+        Some(token) => token,
+        None => {
+            let token = hex::encode(rand::random::<[u8; 16]>());
+            TOKENS.insert(token.clone(), session_id);
+            return (
+                session_id,
+                serde_json::json!({ "session": session_id, "token": token }),
+            );
+        }
+    };
+    let detached = TOKENS.get(&token).and_then(|id| DETACHED.remove(&*id));
+    let (resumed, (mut buffered, _)) = match detached {
+        Some(detached) => detached,
+        None => {
+            return (
+                session_id,
+                serde_json::json!({"error": "session not resumable"}),
+            )
+        }
+    };
+    // the replies are routed to this connection before flushing, nothing is buffered after
+    sessions.insert(resumed, to_session.clone());
+    sessions.remove(&session_id);
+    if let Some((_, stream)) = STREAMS.remove(&session_id) {
+        STREAMS.insert(resumed, stream);
+    }
+    USAGE.close_session(session_id);
+    let mut flushed = 0;
+    while let Some(Some(msg)) = futures::FutureExt::now_or_never(buffered.next()) {
+        let _ = to_session.unbounded_send(msg);
+        flushed += 1;
+    }
+    log::info!(
+        "session {} resumed on the connection of {}, {} replies flushed",
+        resumed,
+        session_id,
+        flushed
+    );
+    (
+        resumed,
+        serde_json::json!({ "session": resumed, "token": token, "flushed": flushed }),
+    )
+}
+
+/// buffer the replies of the disconnected session with a token until resumed or expired
+fn detach_or_close(sessions: &Sessions, session_id: u64, resumable: bool) {
+    if !resumable || !TOKENS.iter().any(|t| *t.value() == session_id) {
+        close_session(sessions, session_id);
+        return;
+    }
+    let (tx, rx) = mpsc::unbounded();
+    sessions.insert(session_id, tx);
+    STREAMS.remove(&session_id);
+    DETACHED.insert(session_id, (rx, Instant::now()));
+    log::info!("session {} detached", session_id);
+}
+
+fn expire_detached(sessions: &Sessions, timeout: Duration) {
+    let expired = DETACHED
+        .iter()
+        .filter(|d| d.value().1.elapsed() > timeout)
+        .map(|d| *d.key())
+        .collect::<Vec<_>>();
+    for id in expired {
+        if DETACHED
+            .remove_if(&id, |_, d| d.1.elapsed() > timeout)
+            .is_some()
+        {
+            log::info!("detached session {} expired", id);
+            close_session(sessions, id);
+        }
+    }
+}
+
+/// ECDH between the x25519 key of the server and the ephemeral key of the client,
+/// `None` if the request isn't a handshake
+fn handshake(shared: &Shared, json: &str) -> Option<anyhow::Result<(Vec<u8>, SessionCipher)>> {
+    let cmd = serde_json::from_str::<Command>(json).ok()?;
+    if cmd.cmd != X25519_HANDSHAKE {
+        return None;
+    }
+    let handshaked = || {
+        let client = cmd
+            .x25519
+            .as_ref()
+            .ok_or(anyhow::anyhow!("x25519 is required"))?;
+        let (server, cipher) = cipher::respond(&shared.x25519_priv.read().unwrap(), client)?;
+        let reply = serde_json::to_vec(&serde_json::json!({ "x25519": server }))?;
+        Ok((reply, cipher))
+    };
+    Some(handshaked())
+}
+
+pub(crate) fn close_session(sessions: &Sessions, session_id: u64) {
     sessions.remove(&session_id);
+    STREAMS.remove(&session_id);
+    USAGE.close_session(session_id);
+    encoding::close_session(session_id);
+    HANDSHAKES.retain(|k, _| k.0 != session_id);
+    DETACHED.remove(&session_id);
+    TOKENS.retain(|_, s| *s != session_id);
+}
+
+/// stop accepting connections and requests, close the sessions once the pending requests are
+/// replied or `timeout` elapsed, return the number of sessions closed
+pub(crate) fn drain(sessions: &Sessions, timeout: Duration) -> usize {
+    DRAINING.store(true, Ordering::Relaxed);
+    let start = Instant::now();
+    while USAGE.pending_replies() > 0 && start.elapsed() < timeout {
+        std::thread::sleep(Duration::from_millis(10));
+    }
+    let ids = sessions.iter().map(|s| *s.key()).collect::<Vec<_>>();
+    for id in ids.iter() {
+        if let Some((_, stream)) = STREAMS.remove(id) {
+            let _ = stream.shutdown(Shutdown::Both);
+        }
+        close_session(sessions, *id);
+    }
+    log::info!("{} sessions drained", ids.len());
+    ids.len()
+}
+
+/// drain all the sessions before shutting down
+pub fn shutdown(timeout: Duration) -> usize {
+    drain(&SESSIONS, timeout)
+}
+
+async fn reply(to_session: &mut ToSession, session: u64, w: Message) -> Result<()> {
+    USAGE.record_reply(session, &w);
+    to_session
+        .send(w)
+        .await
+        .map_err(|e| anyhow::anyhow!("read loop -> write loop -> {:?}", e))?;
     Ok(())
 }
 
-async fn handle_req(
+pub(crate) async fn handle_req(
     to_back: &mut ToBackend,
     to_session: &mut ToSession,
     shared: &Shared,
@@ -211,14 +564,41 @@ async fn handle_req(
         .unwrap()
         .as_secs();
     cmd.timestamp = Some(timestamp);
-    if cmd.is_querying_share_data() {
-        let w = shared.handle_req(&cmd)?;
-        to_session
-            .send(Message::new_req(req_id, w))
-            .await
-            .map_err(|e| anyhow::anyhow!("read loop -> write loop -> {:?}", e))?;
-        Ok(())
+    USAGE.record_request(session, req_id, &cmd, body.len());
+    let encoding = cmd.encoding.unwrap_or_default();
+    match cmd.cmd {
+        PING => {
+            let msg = serde_json::json!({ "pong": timestamp });
+            return reply(to_session, session, encoding.reply(req_id, &msg)).await;
+        }
+        RESUME_SESSION => {
+            let msg = serde_json::json!({
+                "error": "the resumption is only accepted before the other requests"
+            });
+            return reply(to_session, session, encoding.error(req_id, &msg)).await;
+        }
+        _ => {}
+    }
+    if CmdClass::of(cmd.cmd) == CmdClass::Admin {
+        let msg = serde_json::json!({
+            "error": "the admin commands are only accepted on the admin socket"
+        });
+        return reply(to_session, session, encoding.error(req_id, &msg)).await;
+    }
+    // rejected before sequencing
+    if !LIMITER.acquire(session, &cmd) {
+        let reason = RejectReason::RateLimited;
+        let msg = serde_json::json!({"error": reason.to_string(), "code": reason.code()});
+        return reply(to_session, session, encoding.error(req_id, &msg)).await;
+    }
+    if cmd.is_querying_share_data() || (cmd.is_querying_replica() && replica::is_published()) {
+        let w = match shared.handle_req(&cmd, encoding) {
+            Ok(payload) => Message::new_req(req_id, payload),
+            Err(e) => encoding.error(req_id, &serde_json::json!({"error": e.to_string()})),
+        };
+        reply(to_session, session, w).await
     } else {
+        encoding::request(session, req_id, encoding);
         let input = Input::new_with_req(cmd, session, req_id);
         to_back
             .send(input)
@@ -226,3 +606,66 @@ async fn handle_req(
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    pub fn test_resume_session() {
+        let sessions: Sessions = Arc::new(DashMap::new());
+        let (tx, _rx) = mpsc::unbounded();
+        sessions.insert(100, tx.clone());
+        let (id, issued) = resume(100, None, true, &tx, &sessions);
+        assert_eq!(id, 100);
+        let token = issued["token"].as_str().unwrap().to_string();
+        let (_, r) = resume(101, Some(token.clone()), false, &tx, &sessions);
+        assert_eq!(r["error"], "session resumption disabled");
+        // still connected
+        let (id, r) = resume(101, Some(token.clone()), true, &tx, &sessions);
+        assert_eq!(id, 101);
+        assert_eq!(r["error"], "session not resumable");
+        detach_or_close(&sessions, 100, true);
+        assert!(DETACHED.contains_key(&100));
+        sessions
+            .get(&100)
+            .unwrap()
+            .unbounded_send(Message::new_req(7, vec![]))
+            .unwrap();
+        let (new_tx, mut new_rx) = mpsc::unbounded();
+        sessions.insert(101, new_tx.clone());
+        let (id, r) = resume(101, Some(token.clone()), true, &new_tx, &sessions);
+        assert_eq!(id, 100);
+        assert_eq!(r["flushed"], 1);
+        assert_eq!(
+            futures::FutureExt::now_or_never(new_rx.next())
+                .flatten()
+                .unwrap()
+                .req_id,
+            7
+        );
+        assert!(!sessions.contains_key(&101));
+        sessions
+            .get(&100)
+            .unwrap()
+            .unbounded_send(Message::new_req(8, vec![]))
+            .unwrap();
+        assert_eq!(
+            futures::FutureExt::now_or_never(new_rx.next())
+                .flatten()
+                .unwrap()
+                .req_id,
+            8
+        );
+        detach_or_close(&sessions, 100, true);
+        std::thread::sleep(Duration::from_millis(2));
+        expire_detached(&sessions, Duration::from_millis(1));
+        assert!(!sessions.contains_key(&100));
+        assert!(!TOKENS.contains_key(&token));
+        // closed at once without tokens
+        sessions.insert(102, tx);
+        detach_or_close(&sessions, 102, true);
+        assert!(!sessions.contains_key(&102));
+        assert!(!DETACHED.contains_key(&102));
+    }
+}
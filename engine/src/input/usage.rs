@@ -0,0 +1,306 @@
+// Copyright 2021-2023 UINB Technologies Pte. Ltd.
+
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// 	http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::{
+    core::UserId,
+    input::{cmd::*, Command, Message},
+};
+use dashmap::DashMap;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashSet,
+    path::Path,
+    str::FromStr,
+    sync::atomic::{AtomicU64, Ordering},
+    time::Duration,
+};
+
+lazy_static::lazy_static! {
+    pub static ref USAGE: ApiUsage = ApiUsage::default();
+}
+
+const FLUSH_INTERVAL: Duration = Duration::from_secs(60);
+
+/// requests without a valid `user_id`, e.g. querying the open markets
+const ANONYMOUS: &str = "-";
+
+/// the accounts tracked at most, the least recently active ones are evicted beyond
+const MAX_ACCOUNTS: usize = 100_000;
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash, Ord, PartialOrd, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CmdClass {
+    Trade,
+    Query,
+    /// only accepted on the admin socket, rejected on the data path
+    Admin,
+}
+
+impl CmdClass {
+    pub fn of(cmd: u32) -> Self {
+        match cmd {
+            ASK_LIMIT | BID_LIMIT | MARKET_ASK | MARKET_BID | CANCEL | CANCEL_ALL | BLOCK_ASK
+            | BLOCK_BID | SUB_TRANSFER | ROUTE_ASK | ROUTE_BID => CmdClass::Trade,
+            TRANSFER_OUT | TRANSFER_IN | UPDATE_SYMBOL | DUMP | UPDATE_DEPTH | CONFIRM_ALL
+            | SET_LOG_LEVEL | UPDATE_CURRENCY | SET_SYMBOL_OPEN | SET_TRADING_HALT
+            | CHECK_MERKLE | WITHDRAW_FEES | QUERY_MEMORY_STATS | QUERY_DOMINATOR
+            | CONFIRM_REANCHOR | UPDATE_FEE_TIERS => CmdClass::Admin,
+            _ => CmdClass::Query,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Eq, PartialEq, Serialize, Deserialize)]
+pub struct UsageCounter {
+    pub requests: u64,
+    pub rejected: u64,
+    pub bytes_in: u64,
+    pub bytes_out: u64,
+}
+
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub struct UsageEntry {
+    pub user_id: String,
+    pub class: CmdClass,
+    #[serde(flatten)]
+    pub counter: UsageCounter,
+}
+
+/// request counts and bandwidth per account and command class, the replies are attributed to
+/// the requests by `(session, req_id)`
+#[derive(Debug)]
+pub struct ApiUsage {
+    counters: DashMap<(String, CmdClass), UsageCounter>,
+    pending: DashMap<(u64, u64), (String, CmdClass)>,
+    // account -> the tick of its latest request
+    active: DashMap<String, u64>,
+    ticks: AtomicU64,
+    capacity: usize,
+}
+
+impl Default for ApiUsage {
+    fn default() -> Self {
+        Self::with_capacity(MAX_ACCOUNTS)
+    }
+}
+
+/// the accounts in the canonical form, so the malformed ids are never kept
+fn account_of(user_id: Option<&str>) -> String {
+    user_id
+        .and_then(|u| UserId::from_str(u).ok())
+        .map(|u| u.to_string())
+        .unwrap_or_else(|| ANONYMOUS.to_string())
+}
+
+impl ApiUsage {
+    pub fn with_capacity(capacity: usize) -> Self {
+        Self {
+            counters: DashMap::new(),
+            pending: DashMap::new(),
+            active: DashMap::new(),
+            ticks: AtomicU64::new(0),
+            capacity,
+        }
+    }
+
+    pub fn record_request(&self, session: u64, req_id: u64, cmd: &Command, bytes: usize) {
+        let account = account_of(cmd.user_id.as_deref());
+        let class = CmdClass::of(cmd.cmd);
+        self.touch(&account);
+        let mut counter = self.counters.entry((account.clone(), class)).or_default();
+        counter.requests += 1;
+        counter.bytes_in += bytes as u64;
+        drop(counter);
+        self.pending.insert((session, req_id), (account, class));
+    }
+
+    /// the rejections are replied in error frames by both executor and shared data
+    pub fn record_reply(&self, session: u64, msg: &Message) {
+        if let Some((_, key)) = self.pending.remove(&(session, msg.req_id)) {
+            if let Some(mut counter) = self.counters.get_mut(&key) {
+                counter.bytes_out += msg.payload.len() as u64;
+                if msg.error {
+                    counter.rejected += 1;
+                }
+            }
+        }
+    }
+
+    fn touch(&self, account: &str) {
+        let tick = self.ticks.fetch_add(1, Ordering::Relaxed);
+        self.active.insert(account.to_string(), tick);
+        if self.active.len() > self.capacity {
+            self.evict();
+        }
+    }
+
+    /// evict a tenth of the least recently active accounts at once so it's amortized
+    fn evict(&self) {
+        let mut active = self
+            .active
+            .iter()
+            .filter(|a| a.key() != ANONYMOUS)
+            .map(|a| (*a.value(), a.key().clone()))
+            .collect::<Vec<_>>();
+        active.sort_unstable();
+        let n = active
+            .len()
+            .saturating_sub(self.capacity - self.capacity / 10);
+        let evicted = active
+            .into_iter()
+            .take(n)
+            .map(|(_, account)| account)
+            .collect::<HashSet<_>>();
+        for account in evicted.iter() {
+            self.active.remove(account);
+        }
+        self.counters.retain(|k, _| !evicted.contains(&k.0));
+    }
+
+    /// drop the requests of a closed session which would never be replied
+    pub fn close_session(&self, session: u64) {
+        self.pending.retain(|k, _| k.0 != session);
+    }
+
+    /// the requests waiting for the replies, some writes are never replied on success
+    pub fn pending_replies(&self) -> usize {
+        self.pending.len()
+    }
+
+    pub fn query(&self, user_id: Option<&str>) -> Vec<UsageEntry> {
+        let account = user_id.map(|u| account_of(Some(u)));
+        let mut r = self
+            .counters
+            .iter()
+            .filter(|e| account.as_ref().map(|a| *a == e.key().0).unwrap_or(true))
+            .map(|e| UsageEntry {
+                user_id: e.key().0.clone(),
+                class: e.key().1,
+                counter: e.value().clone(),
+            })
+            .collect::<Vec<_>>();
+        r.sort_by(|a, b| (&a.user_id, a.class).cmp(&(&b.user_id, b.class)));
+        r
+    }
+
+    pub fn save(&self, path: &Path) -> anyhow::Result<()> {
+        let tmp = path.with_extension("tmp");
+        std::fs::write(&tmp, serde_json::to_vec(&self.query(None))?)?;
+        std::fs::rename(&tmp, path)?;
+        Ok(())
+    }
+
+    pub fn load(&self, path: &Path) -> anyhow::Result<()> {
+        if !path.exists() {
+            return Ok(());
+        }
+        let entries: Vec<UsageEntry> = serde_json::from_slice(&std::fs::read(path)?)?;
+        // the entries keyed by the malformed ids before are dropped
+        for e in entries
+            .into_iter()
+            .filter(|e| account_of(Some(&e.user_id)) == e.user_id)
+        {
+            self.counters
+                .insert((e.user_id.clone(), e.class), e.counter);
+            self.touch(&e.user_id);
+        }
+        Ok(())
+    }
+}
+
+/// restore the statistics and persist them periodically
+pub fn init(path: impl AsRef<Path>) {
+    let path = path.as_ref().to_path_buf();
+    if let Err(e) = USAGE.load(&path) {
+        log::error!("unable to load api usage from {:?}, {:?}", path, e);
+    }
+    std::thread::spawn(move || loop {
+        std::thread::sleep(FLUSH_INTERVAL);
+        if let Err(e) = USAGE.save(&path) {
+            log::error!("unable to save api usage to {:?}, {:?}", path, e);
+        }
+    });
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    pub fn test_api_usage() {
+        let alice = "5GrwvaEF5zXb26Fz9rcQpDWS57CtERHpNehXCPcNoHGKutQY";
+        let usage = ApiUsage::default();
+        let mut cmd = Command::default();
+        cmd.cmd = BID_LIMIT;
+        cmd.user_id = Some(alice.to_string());
+        usage.record_request(1, 1, &cmd, 100);
+        usage.record_request(1, 2, &cmd, 100);
+        cmd.cmd = QUERY_BALANCE;
+        usage.record_request(1, 3, &cmd, 50);
+        cmd.user_id = None;
+        cmd.cmd = QUERY_OPEN_MARKETS;
+        usage.record_request(2, 1, &cmd, 20);
+        // counted as anonymous
+        cmd.user_id = Some("mallory".to_string());
+        usage.record_request(2, 2, &cmd, 20);
+        usage.record_reply(1, &Message::new_req(1, br#"{"id":1}"#.to_vec()));
+        usage.record_reply(
+            1,
+            &Message::new_err(2, br#"{"code":1,"error":"rejected"}"#.to_vec()),
+        );
+        // unknown request
+        usage.record_reply(1, &Message::new_req(9, b"{}".to_vec()));
+        usage.close_session(1);
+        usage.record_reply(1, &Message::new_req(3, b"{}".to_vec()));
+        let alice = usage.query(Some(alice));
+        assert_eq!(2, alice.len());
+        assert_eq!(CmdClass::Trade, alice[0].class);
+        assert_eq!(
+            UsageCounter {
+                requests: 2,
+                rejected: 1,
+                bytes_in: 200,
+                bytes_out: 37,
+            },
+            alice[0].counter
+        );
+        assert_eq!(CmdClass::Query, alice[1].class);
+        assert_eq!(0, alice[1].counter.bytes_out);
+        assert_eq!(3, usage.query(None).len());
+        assert_eq!(2, usage.query(Some(ANONYMOUS))[0].counter.requests);
+
+        let dir = tempdir::TempDir::new("galois-usage").unwrap();
+        let path = dir.path().join("usage.json");
+        usage.save(&path).unwrap();
+        let restored = ApiUsage::default();
+        restored.load(&path).unwrap();
+        assert_eq!(usage.query(None), restored.query(None));
+
+        let usage = ApiUsage::with_capacity(10);
+        cmd.cmd = QUERY_BALANCE;
+        for i in 0..11_u8 {
+            cmd.user_id = Some(UserId::new([i; 32]).to_string());
+            usage.record_request(3, i as u64, &cmd, 1);
+        }
+        // the least recently active are evicted
+        assert_eq!(9, usage.query(None).len());
+        assert!(usage
+            .query(Some(&UserId::new([0; 32]).to_string()))
+            .is_empty());
+        assert_eq!(
+            1,
+            usage.query(Some(&UserId::new([10; 32]).to_string())).len()
+        );
+    }
+}
@@ -0,0 +1,213 @@
+// Copyright 2021-2023 UINB Technologies Pte. Ltd.
+
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// 	http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use serde::Serialize;
+use std::{
+    sync::atomic::{AtomicU64, Ordering},
+    time::{Duration, Instant},
+};
+
+// the values are bucketed by the power of 2 and then linearly into 32 sub-buckets,
+// so the quantiles are within about 3% of the recorded nanoseconds
+const SUB_BUCKET_BITS: u32 = 5;
+const SUB_BUCKETS: usize = 1 << SUB_BUCKET_BITS;
+const BUCKETS: usize = (64 - SUB_BUCKET_BITS as usize + 1) * SUB_BUCKETS;
+
+lazy_static::lazy_static! {
+    static ref HISTOGRAMS: Vec<Histogram> = Stage::ALL.iter().map(|_| Histogram::new()).collect();
+}
+
+/// the stages of an event through the pipeline, `execute` includes `prove`
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Stage {
+    /// received by the sequencer until sent to the executor, including the persisting
+    Sequence,
+    /// waiting in the ring until taken by the executor
+    Queue,
+    /// executing by the executor
+    Execute,
+    /// generating the merkle proofs
+    Prove,
+    /// the outputs waiting in the ring until taken by the market
+    Publish,
+    /// received by the sequencer until executed
+    Total,
+}
+
+impl Stage {
+    pub const ALL: [Stage; 6] = [
+        Stage::Sequence,
+        Stage::Queue,
+        Stage::Execute,
+        Stage::Prove,
+        Stage::Publish,
+        Stage::Total,
+    ];
+}
+
+/// the instants carried along with the sequenced events, the historic events have none
+#[derive(Debug, Clone, Copy)]
+pub struct Stamps {
+    pub received: Instant,
+    pub sequenced: Instant,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct StageLatency {
+    pub stage: Stage,
+    pub count: u64,
+    pub mean_us: f64,
+    pub p50_us: f64,
+    pub p90_us: f64,
+    pub p99_us: f64,
+    pub p999_us: f64,
+    pub max_us: f64,
+}
+
+/// recorded concurrently without locking
+struct Histogram {
+    buckets: Vec<AtomicU64>,
+    count: AtomicU64,
+    sum: AtomicU64,
+    max: AtomicU64,
+}
+
+fn index_of(nanos: u64) -> usize {
+    if nanos < SUB_BUCKETS as u64 {
+        return nanos as usize;
+    }
+    let exp = 63 - nanos.leading_zeros();
+    let sub = (nanos >> (exp - SUB_BUCKET_BITS)) as usize & (SUB_BUCKETS - 1);
+    (exp - SUB_BUCKET_BITS + 1) as usize * SUB_BUCKETS + sub
+}
+
+fn lowest_of(index: usize) -> u64 {
+    if index < SUB_BUCKETS {
+        return index as u64;
+    }
+    let shift = (index / SUB_BUCKETS - 1) as u32;
+    ((SUB_BUCKETS + index % SUB_BUCKETS) as u64) << shift
+}
+
+impl Histogram {
+    fn new() -> Self {
+        Self {
+            buckets: (0..BUCKETS).map(|_| AtomicU64::new(0)).collect(),
+            count: AtomicU64::new(0),
+            sum: AtomicU64::new(0),
+            max: AtomicU64::new(0),
+        }
+    }
+
+    fn record(&self, nanos: u64) {
+        self.buckets[index_of(nanos)].fetch_add(1, Ordering::Relaxed);
+        self.count.fetch_add(1, Ordering::Relaxed);
+        self.sum.fetch_add(nanos, Ordering::Relaxed);
+        self.max.fetch_max(nanos, Ordering::Relaxed);
+    }
+
+    fn reset(&self) {
+        self.buckets
+            .iter()
+            .for_each(|b| b.store(0, Ordering::Relaxed));
+        self.count.store(0, Ordering::Relaxed);
+        self.sum.store(0, Ordering::Relaxed);
+        self.max.store(0, Ordering::Relaxed);
+    }
+
+    fn snapshot(&self, stage: Stage) -> StageLatency {
+        let counts = self
+            .buckets
+            .iter()
+            .map(|b| b.load(Ordering::Relaxed))
+            .collect::<Vec<_>>();
+        // the buckets may be updated while reading
+        let count = counts.iter().sum::<u64>();
+        let max = self.max.load(Ordering::Relaxed);
+        let quantile = |q: f64| {
+            let rank = ((count as f64 * q).ceil() as u64).max(1);
+            let mut seen = 0;
+            for (index, n) in counts.iter().enumerate() {
+                seen += n;
+                if seen >= rank {
+                    return lowest_of(index).min(max);
+                }
+            }
+            max
+        };
+        let to_us = |nanos: u64| nanos as f64 / 1000.0;
+        StageLatency {
+            stage,
+            count,
+            mean_us: to_us(self.sum.load(Ordering::Relaxed) / count.max(1)),
+            p50_us: to_us(quantile(0.5)),
+            p90_us: to_us(quantile(0.9)),
+            p99_us: to_us(quantile(0.99)),
+            p999_us: to_us(quantile(0.999)),
+            max_us: to_us(max),
+        }
+    }
+}
+
+fn histogram(stage: Stage) -> &'static Histogram {
+    &HISTOGRAMS[stage as usize]
+}
+
+pub fn record(stage: Stage, elapsed: Duration) {
+    histogram(stage).record(elapsed.as_nanos().min(u64::MAX as u128) as u64);
+}
+
+pub fn record_since(stage: Stage, since: Instant) {
+    record(stage, since.elapsed());
+}
+
+/// the latencies of all stages since started or reset
+pub fn snapshot() -> Vec<StageLatency> {
+    Stage::ALL
+        .iter()
+        .map(|stage| histogram(*stage).snapshot(*stage))
+        .collect()
+}
+
+pub fn reset() {
+    HISTOGRAMS.iter().for_each(Histogram::reset);
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    pub fn test_histogram() {
+        for nanos in [0, 1, 31, 32, 33, 63, 64, 1000, 123_456_789, u64::MAX] {
+            let index = index_of(nanos);
+            assert!(index < BUCKETS);
+            assert!(lowest_of(index) <= nanos);
+            assert!(index + 1 == BUCKETS || lowest_of(index + 1) > nanos);
+        }
+        let h = Histogram::new();
+        (1..=1000u64).for_each(|us| h.record(us * 1000));
+        let s = h.snapshot(Stage::Total);
+        assert_eq!(1000, s.count);
+        assert_eq!(500.5, s.mean_us);
+        assert_eq!(1000.0, s.max_us);
+        for (q, v) in [(0.5, s.p50_us), (0.99, s.p99_us), (0.999, s.p999_us)] {
+            let expected = 1000.0 * q;
+            assert!(v <= expected && v >= expected * 0.96, "{} {}", q, v);
+        }
+        h.reset();
+        assert_eq!(0, h.snapshot(Stage::Total).count);
+    }
+}
@@ -20,15 +20,23 @@
 #![allow(clippy::wrong_self_convention)]
 #![allow(clippy::map_entry)]
 
+pub mod bench;
 pub mod config;
 pub mod core;
 pub mod executor;
 pub mod fusotao;
+pub mod genesis;
 pub mod input;
+pub mod latency;
+pub mod logger;
 pub mod migration;
 pub mod output;
+pub mod replay;
+pub mod replication;
+pub mod ring;
 pub mod shared;
 pub mod snapshot;
+pub mod verify;
 
 pub use ::core::*;
 pub use config::C;
@@ -0,0 +1,588 @@
+// Copyright 2021-2023 UINB Technologies Pte. Ltd.
+
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// 	http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::config::{LogConfig, LogFormat};
+use log::{Level, LevelFilter, Log, Metadata, Record};
+use std::{
+    cell::RefCell,
+    collections::{BTreeMap, HashMap},
+    fmt::{Debug, Display, Write as _},
+    fs::{File, OpenOptions},
+    io::Write,
+    path::PathBuf,
+    str::FromStr,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex, RwLock,
+    },
+    time::Instant,
+};
+use tracing::{
+    field::{Field, Visit},
+    span::{Attributes, Id, Record as Values},
+    subscriber::Interest,
+    Subscriber,
+};
+
+lazy_static::lazy_static! {
+    static ref LOGGER: Logger = Logger::new();
+}
+
+thread_local! {
+    // the entered spans of the current thread
+    static STACK: RefCell<Vec<u64>> = const { RefCell::new(vec![]) };
+}
+
+/// short names of the modules that operators usually care about
+const ALIASES: [(&str, &str); 4] = [
+    ("matcher", "galois_engine::executor::matcher"),
+    ("prover", "galois_engine::fusotao::prover"),
+    ("scanner", "galois_engine::fusotao::scanner"),
+    ("server", "galois_engine::input::server"),
+];
+
+struct Levels {
+    default: LevelFilter,
+    // target prefix -> level
+    modules: BTreeMap<String, LevelFilter>,
+    // the spans of the event and everything inside them are logged at all levels
+    traced: Option<u64>,
+}
+
+impl Levels {
+    fn get(&self, target: &str) -> LevelFilter {
+        self.modules
+            .iter()
+            .filter(|(prefix, _)| target.starts_with(prefix.as_str()))
+            .max_by_key(|(prefix, _)| prefix.len())
+            .map(|(_, level)| *level)
+            .unwrap_or(self.default)
+    }
+
+    fn max(&self) -> LevelFilter {
+        self.modules
+            .values()
+            .copied()
+            .fold(self.default, std::cmp::max)
+    }
+}
+
+struct RollingFile {
+    path: PathBuf,
+    max_size: u64,
+    max_files: usize,
+    file: File,
+    written: u64,
+}
+
+impl RollingFile {
+    fn open(path: PathBuf, max_size: u64, max_files: usize) -> std::io::Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(&path)?;
+        let written = file.metadata()?.len();
+        Ok(Self {
+            path,
+            max_size,
+            max_files,
+            file,
+            written,
+        })
+    }
+
+    fn rotated(&self, n: usize) -> PathBuf {
+        let mut p = self.path.clone().into_os_string();
+        p.push(format!(".{}", n));
+        p.into()
+    }
+
+    fn rotate(&mut self) -> std::io::Result<()> {
+        self.file.flush()?;
+        for n in (1..self.max_files).rev() {
+            let from = self.rotated(n);
+            if from.exists() {
+                std::fs::rename(&from, self.rotated(n + 1))?;
+            }
+        }
+        if self.max_files > 0 {
+            std::fs::rename(&self.path, self.rotated(1))?;
+        }
+        self.file = OpenOptions::new()
+            .create(true)
+            .write(true)
+            .truncate(true)
+            .open(&self.path)?;
+        self.written = 0;
+        Ok(())
+    }
+
+    fn write_line(&mut self, line: &[u8]) -> std::io::Result<()> {
+        if self.max_size > 0 && self.written + line.len() as u64 > self.max_size {
+            self.rotate()?;
+        }
+        self.file.write_all(line)?;
+        self.written += line.len() as u64;
+        Ok(())
+    }
+}
+
+struct Logger {
+    levels: RwLock<Levels>,
+    format: RwLock<LogFormat>,
+    file: Mutex<Option<RollingFile>>,
+}
+
+impl Logger {
+    fn new() -> Self {
+        Self {
+            levels: RwLock::new(Levels {
+                default: LevelFilter::Info,
+                modules: BTreeMap::new(),
+                traced: None,
+            }),
+            format: RwLock::new(LogFormat::Text),
+            file: Mutex::new(None),
+        }
+    }
+
+    fn format(&self, level: Level, target: &str, message: &dyn Display) -> String {
+        let now = chrono::Local::now().format("%Y-%m-%dT%H:%M:%S%.3f%:z");
+        match *self.format.read().unwrap() {
+            LogFormat::Text => format!("[{} {:<5} {}] {}\n", now, level, target, message),
+            LogFormat::Json => {
+                let mut line = serde_json::json!({
+                    "time": now.to_string(),
+                    "level": level.as_str(),
+                    "target": target,
+                    "message": message.to_string(),
+                })
+                .to_string();
+                line.push('\n');
+                line
+            }
+        }
+    }
+
+    fn write(&self, line: String) {
+        let mut file = self.file.lock().unwrap();
+        match file.as_mut() {
+            Some(f) => {
+                if let Err(e) = f.write_line(line.as_bytes()) {
+                    eprintln!("unable to write log file, {:?}", e);
+                    eprint!("{}", line);
+                }
+            }
+            None => eprint!("{}", line),
+        }
+    }
+}
+
+impl Log for Logger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= self.levels.read().unwrap().get(metadata.target())
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        self.write(self.format(record.level(), record.target(), record.args()));
+    }
+
+    fn flush(&self) {
+        if let Some(f) = self.file.lock().unwrap().as_mut() {
+            let _ = f.file.flush();
+        }
+    }
+}
+
+fn to_level(level: &tracing::Level) -> Level {
+    match *level {
+        tracing::Level::ERROR => Level::Error,
+        tracing::Level::WARN => Level::Warn,
+        tracing::Level::INFO => Level::Info,
+        tracing::Level::DEBUG => Level::Debug,
+        tracing::Level::TRACE => Level::Trace,
+    }
+}
+
+/// the fields as `k=v`, `event_id` or `from..=to` of the span is picked up for tracing
+#[derive(Default)]
+struct Fields {
+    message: String,
+    fields: String,
+    event_id: Option<u64>,
+    from: Option<u64>,
+    to: Option<u64>,
+}
+
+impl Fields {
+    fn covers(&self, event_id: u64) -> bool {
+        self.event_id == Some(event_id)
+            || matches!((self.from, self.to), (Some(from), Some(to)) if (from..=to).contains(&event_id))
+    }
+}
+
+impl Visit for Fields {
+    fn record_u64(&mut self, field: &Field, value: u64) {
+        match field.name() {
+            "event_id" => self.event_id = Some(value),
+            "from" => self.from = Some(value),
+            "to" => self.to = Some(value),
+            _ => {}
+        }
+        self.record_debug(field, &value);
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn Debug) {
+        if field.name() == "message" {
+            let _ = write!(self.message, "{:?}", value);
+        } else {
+            if !self.fields.is_empty() {
+                self.fields.push(' ');
+            }
+            let _ = write!(self.fields, "{}={:?}", field.name(), value);
+        }
+    }
+}
+
+struct Span {
+    name: &'static str,
+    target: &'static str,
+    level: Level,
+    fields: Fields,
+    parent: Option<u64>,
+    created: Instant,
+    traced: bool,
+    refs: usize,
+}
+
+/// the `tracing` spans are logged with the elapsed time on closing, and the events are logged
+/// with the spans entered, e.g. `sequence{event_id=1}:execute{event_id=1}:prove: ...`
+struct Tracer {
+    spans: Mutex<HashMap<u64, Span>>,
+    next_id: AtomicU64,
+}
+
+impl Tracer {
+    fn new() -> Self {
+        Self {
+            spans: Mutex::new(HashMap::new()),
+            next_id: AtomicU64::new(1),
+        }
+    }
+
+    fn current() -> Option<u64> {
+        STACK.with(|s| s.borrow().last().copied())
+    }
+
+    fn context(spans: &HashMap<u64, Span>, mut id: Option<u64>) -> (String, bool) {
+        let (mut chain, mut traced) = (vec![], false);
+        while let Some(span) = id.and_then(|i| spans.get(&i)) {
+            traced |= span.traced;
+            chain.push(if span.fields.fields.is_empty() {
+                span.name.to_string()
+            } else {
+                format!("{}{{{}}}", span.name, span.fields.fields)
+            });
+            id = span.parent;
+        }
+        chain.reverse();
+        (chain.join(":"), traced)
+    }
+}
+
+impl Subscriber for Tracer {
+    // the levels are changed at runtime, so never cached by the callsites
+    fn register_callsite(&self, _: &'static tracing::Metadata<'static>) -> Interest {
+        Interest::sometimes()
+    }
+
+    fn enabled(&self, metadata: &tracing::Metadata) -> bool {
+        let levels = LOGGER.levels.read().unwrap();
+        levels.traced.is_some() || to_level(metadata.level()) <= levels.get(metadata.target())
+    }
+
+    fn new_span(&self, attrs: &Attributes) -> Id {
+        let mut fields = Fields::default();
+        attrs.record(&mut fields);
+        let parent = if attrs.is_contextual() {
+            Self::current()
+        } else {
+            attrs.parent().map(|p| p.into_u64())
+        };
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let traced = LOGGER
+            .levels
+            .read()
+            .unwrap()
+            .traced
+            .is_some_and(|t| fields.covers(t));
+        let mut spans = self.spans.lock().unwrap();
+        let traced = traced || parent.and_then(|p| spans.get(&p)).is_some_and(|p| p.traced);
+        spans.insert(
+            id,
+            Span {
+                name: attrs.metadata().name(),
+                target: attrs.metadata().target(),
+                level: to_level(attrs.metadata().level()),
+                fields,
+                parent,
+                created: Instant::now(),
+                traced,
+                refs: 1,
+            },
+        );
+        Id::from_u64(id)
+    }
+
+    fn record(&self, span: &Id, values: &Values) {
+        let traced = LOGGER.levels.read().unwrap().traced;
+        if let Some(span) = self.spans.lock().unwrap().get_mut(&span.into_u64()) {
+            values.record(&mut span.fields);
+            span.traced |= traced.is_some_and(|t| span.fields.covers(t));
+        }
+    }
+
+    fn record_follows_from(&self, _: &Id, _: &Id) {}
+
+    fn event(&self, event: &tracing::Event) {
+        let mut fields = Fields::default();
+        event.record(&mut fields);
+        let parent = if event.is_contextual() {
+            Self::current()
+        } else {
+            event.parent().map(|p| p.into_u64())
+        };
+        let (context, traced) = Self::context(&self.spans.lock().unwrap(), parent);
+        let (level, target) = (
+            to_level(event.metadata().level()),
+            event.metadata().target(),
+        );
+        if !traced && level > LOGGER.levels.read().unwrap().get(target) {
+            return;
+        }
+        let mut message = context;
+        if !message.is_empty() {
+            message.push_str(": ");
+        }
+        message.push_str(&fields.message);
+        if !fields.fields.is_empty() {
+            message.push(' ');
+            message.push_str(&fields.fields);
+        }
+        LOGGER.write(LOGGER.format(level, target, &message));
+    }
+
+    fn enter(&self, span: &Id) {
+        STACK.with(|s| s.borrow_mut().push(span.into_u64()));
+    }
+
+    fn exit(&self, span: &Id) {
+        STACK.with(|s| {
+            let mut s = s.borrow_mut();
+            if let Some(i) = s.iter().rposition(|id| *id == span.into_u64()) {
+                s.remove(i);
+            }
+        });
+    }
+
+    fn clone_span(&self, span: &Id) -> Id {
+        if let Some(span) = self.spans.lock().unwrap().get_mut(&span.into_u64()) {
+            span.refs += 1;
+        }
+        span.clone()
+    }
+
+    fn try_close(&self, span: Id) -> bool {
+        let mut spans = self.spans.lock().unwrap();
+        let id = span.into_u64();
+        match spans.get_mut(&id) {
+            Some(s) if s.refs > 1 => {
+                s.refs -= 1;
+                return false;
+            }
+            None => return false,
+            _ => {}
+        }
+        let (context, traced) = Self::context(&spans, Some(id));
+        let span = spans.remove(&id).expect("checked above;qed");
+        drop(spans);
+        if traced || span.level <= LOGGER.levels.read().unwrap().get(span.target) {
+            let message = format!("{}: closed, elapsed={:?}", context, span.created.elapsed());
+            LOGGER.write(LOGGER.format(span.level, span.target, &message));
+        }
+        true
+    }
+}
+
+fn resolve(module: &str) -> String {
+    ALIASES
+        .iter()
+        .find(|(alias, _)| *alias == module)
+        .map(|(_, target)| target.to_string())
+        .unwrap_or_else(|| module.to_string())
+}
+
+/// install the logger, could only be called once
+pub fn init(config: &LogConfig) -> anyhow::Result<()> {
+    {
+        let mut levels = LOGGER.levels.write().unwrap();
+        levels.default = LevelFilter::from_str(&config.level)?;
+        for (module, level) in config.modules.iter() {
+            levels
+                .modules
+                .insert(resolve(module), LevelFilter::from_str(level)?);
+        }
+    }
+    *LOGGER.format.write().unwrap() = config.format;
+    if let Some(ref path) = config.file {
+        let file = RollingFile::open(path.into(), config.max_size, config.max_files)?;
+        *LOGGER.file.lock().unwrap() = Some(file);
+    }
+    log::set_logger(&*LOGGER).map_err(|e| anyhow::anyhow!("{}", e))?;
+    log::set_max_level(LOGGER.levels.read().unwrap().max());
+    tracing::subscriber::set_global_default(Tracer::new())?;
+    Ok(())
+}
+
+/// change the level of `module` at runtime, `module` is either an alias or a target prefix,
+/// `None` or `*` changes the default level
+pub fn set_level(module: Option<&str>, level: &str) -> anyhow::Result<()> {
+    let level = LevelFilter::from_str(level)?;
+    let mut levels = LOGGER.levels.write().unwrap();
+    match module.filter(|m| *m != "*") {
+        Some(m) => {
+            levels.modules.insert(resolve(m), level);
+        }
+        None => levels.default = level,
+    }
+    log::set_max_level(levels.max());
+    Ok(())
+}
+
+/// replace the levels and the format of the config reloaded, the traced event is kept
+pub fn reload(config: &LogConfig) -> anyhow::Result<()> {
+    let mut modules = BTreeMap::new();
+    for (module, level) in config.modules.iter() {
+        modules.insert(resolve(module), LevelFilter::from_str(level)?);
+    }
+    let mut levels = LOGGER.levels.write().unwrap();
+    levels.default = LevelFilter::from_str(&config.level)?;
+    levels.modules = modules;
+    log::set_max_level(levels.max());
+    *LOGGER.format.write().unwrap() = config.format;
+    Ok(())
+}
+
+/// follow the event through the pipeline at all levels, `0` stops tracing
+pub fn trace_event(event_id: u64) {
+    LOGGER.levels.write().unwrap().traced = Some(event_id).filter(|id| *id != 0);
+}
+
+/// current levels, keyed by target prefix and `*` for the default one, and the traced
+/// `event_id` if any
+pub fn get_levels() -> BTreeMap<String, String> {
+    let levels = LOGGER.levels.read().unwrap();
+    let mut r = levels
+        .modules
+        .iter()
+        .map(|(k, v)| (k.clone(), v.to_string()))
+        .collect::<BTreeMap<_, _>>();
+    r.insert("*".to_string(), levels.default.to_string());
+    if let Some(id) = levels.traced {
+        r.insert("event_id".to_string(), id.to_string());
+    }
+    r
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    pub fn test_module_levels() {
+        let mut levels = Levels {
+            default: LevelFilter::Info,
+            modules: BTreeMap::new(),
+            traced: None,
+        };
+        levels.modules.insert(resolve("prover"), LevelFilter::Debug);
+        levels
+            .modules
+            .insert("galois_engine::fusotao".to_string(), LevelFilter::Warn);
+        assert_eq!(
+            levels.get("galois_engine::fusotao::prover"),
+            LevelFilter::Debug
+        );
+        assert_eq!(
+            levels.get("galois_engine::fusotao::scanner"),
+            LevelFilter::Warn
+        );
+        assert_eq!(
+            levels.get("galois_engine::executor::matcher"),
+            LevelFilter::Info
+        );
+        assert_eq!(levels.max(), LevelFilter::Debug);
+    }
+
+    #[test]
+    pub fn test_span_context() {
+        let span = |name, fields: Fields, parent, traced| Span {
+            name,
+            target: "galois_engine",
+            level: Level::Debug,
+            fields,
+            parent,
+            created: Instant::now(),
+            traced,
+            refs: 1,
+        };
+        let commit = Fields {
+            fields: "from=1 to=3".to_string(),
+            from: Some(1),
+            to: Some(3),
+            ..Default::default()
+        };
+        assert!(commit.covers(2));
+        assert!(!commit.covers(4));
+        let execute = Fields {
+            fields: "event_id=2".to_string(),
+            event_id: Some(2),
+            ..Default::default()
+        };
+        assert!(execute.covers(2));
+        let mut spans = HashMap::new();
+        spans.insert(1, span("execute", execute, None, true));
+        spans.insert(2, span("prove", Fields::default(), Some(1), false));
+        assert_eq!(
+            Tracer::context(&spans, Some(2)),
+            ("execute{event_id=2}:prove".to_string(), true)
+        );
+        assert_eq!(Tracer::context(&spans, None), (String::new(), false));
+    }
+
+    #[test]
+    pub fn test_rolling_file() {
+        let dir = tempdir::TempDir::new("galois-log").unwrap();
+        let path = dir.path().join("galois.log");
+        let mut file = RollingFile::open(path.clone(), 10, 2).unwrap();
+        for _ in 0..4 {
+            file.write_line(b"12345678\n").unwrap();
+        }
+        assert!(path.exists());
+        assert!(file.rotated(1).exists());
+        assert!(file.rotated(2).exists());
+        assert!(!file.rotated(3).exists());
+        assert_eq!(std::fs::metadata(&path).unwrap().len(), 9);
+    }
+}
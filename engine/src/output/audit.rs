@@ -0,0 +1,496 @@
+// Copyright 2021-2023 UINB Technologies Pte. Ltd.
+
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// 	http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::{
+    config::{AuditCmd, C},
+    core::*,
+    matcher::{Match, Role},
+    orderbook::AskOrBid,
+    output::Output,
+};
+use blake2::{Blake2b, Digest};
+use generic_array::typenum::U32;
+use rust_decimal::prelude::Zero;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{hash_map::Entry as MapEntry, BTreeMap, HashMap, VecDeque},
+    fs::{File, OpenOptions},
+    io::{BufRead, BufReader, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+use thiserror::Error;
+
+const SEGMENT_EXT: &str = "audit";
+
+#[derive(Error, Debug)]
+pub enum AuditError {
+    #[error("the record at line {1} of {0} is malformed")]
+    Malformed(PathBuf, usize),
+    #[error("the hash of the record at line {1} of {0} mismatches")]
+    Tampered(PathBuf, usize),
+    #[error("the record at line {1} of {0} isn't chained to the previous one")]
+    Unchained(PathBuf, usize),
+}
+
+lazy_static::lazy_static! {
+    // the matches waiting for the merkle roots, more than one of an event on different symbols
+    static ref STAGED: Mutex<BTreeMap<u64, Vec<AuditRecord>>> = Mutex::new(BTreeMap::new());
+    static ref LOGS: Mutex<HashMap<Symbol, AuditLog>> = Mutex::new(HashMap::new());
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Eq, PartialEq)]
+pub struct AuditFill {
+    pub maker: String,
+    pub maker_order_id: u64,
+    pub price: Price,
+    pub amount: Amount,
+    pub base_fee: Amount,
+    pub quote_fee: Amount,
+}
+
+/// one for each match, chained to the previous record of the symbol by `prev`
+#[derive(Serialize, Deserialize, Debug, Clone, Eq, PartialEq)]
+pub struct AuditRecord {
+    pub event_id: u64,
+    pub symbol: Symbol,
+    pub timestamp: Timestamp,
+    pub taker: String,
+    pub taker_order_id: u64,
+    pub taker_side: AskOrBid,
+    pub taker_base_fee: Amount,
+    pub taker_quote_fee: Amount,
+    pub fills: Vec<AuditFill>,
+    /// the merkle root after the event
+    pub root: String,
+    pub prev: String,
+}
+
+/// the `hash` covers the json of the record
+#[derive(Serialize, Deserialize, Debug)]
+struct Entry {
+    #[serde(flatten)]
+    record: AuditRecord,
+    hash: String,
+}
+
+impl AuditRecord {
+    pub fn new(
+        event_id: u64,
+        symbol: Symbol,
+        timestamp: Timestamp,
+        mr: &Match,
+        out: &[Output],
+    ) -> Self {
+        // the charges are negative
+        let fee = |order_id: u64, role: Role| {
+            out.iter()
+                .find(|o| o.order_id == order_id && o.role == role)
+                .map_or((Amount::zero(), Amount::zero()), |o| {
+                    (-o.base_charge, -o.quote_charge)
+                })
+        };
+        let (taker_base_fee, taker_quote_fee) = fee(mr.taker.order_id, Role::Taker);
+        Self {
+            event_id,
+            symbol,
+            timestamp,
+            taker: to_hex(mr.taker.user_id),
+            taker_order_id: mr.taker.order_id,
+            taker_side: mr.taker.ask_or_bid,
+            taker_base_fee,
+            taker_quote_fee,
+            fills: mr
+                .maker
+                .iter()
+                .map(|m| {
+                    let (base_fee, quote_fee) = fee(m.order_id, Role::Maker);
+                    AuditFill {
+                        maker: to_hex(m.user_id),
+                        maker_order_id: m.order_id,
+                        price: m.price,
+                        amount: m.filled,
+                        base_fee,
+                        quote_fee,
+                    }
+                })
+                .collect(),
+            root: String::new(),
+            prev: String::new(),
+        }
+    }
+
+    fn hash(&self) -> [u8; 32] {
+        let mut hasher = Blake2b::<U32>::new();
+        hasher.update(serde_json::to_vec(self).expect("jsonify record;qed"));
+        hasher.finalize().into()
+    }
+}
+
+fn to_hex(bytes: impl AsRef<[u8]>) -> String {
+    format!("0x{}", hex::encode(bytes))
+}
+
+fn from_hex(s: &str) -> Option<[u8; 32]> {
+    let mut bytes = [0u8; 32];
+    hex::decode_to_slice(s.trim_start_matches("0x"), &mut bytes).ok()?;
+    Some(bytes)
+}
+
+/// keep the match until its merkle root is known, the replayed events are skipped
+pub fn stage(event_id: u64, symbol: Symbol, timestamp: Timestamp, mr: &Match, out: &[Output]) {
+    if C.audit.is_none() || C.dry_run.is_some() || mr.maker.is_empty() {
+        return;
+    }
+    let record = AuditRecord::new(event_id, symbol, timestamp, mr, out);
+    STAGED
+        .lock()
+        .expect("audit lock poisoned;qed")
+        .entry(event_id)
+        .or_default()
+        .push(record);
+}
+
+/// append the staged match along with the root once the event is proven, the audit shouldn't
+/// block the matching
+pub fn commit(event_id: u64, root: &[u8; 32]) {
+    let config = match C.audit.as_ref() {
+        Some(config) => config,
+        None => return,
+    };
+    let records = {
+        let mut staged = STAGED.lock().expect("audit lock poisoned;qed");
+        // the proofs are saved in the order of the events
+        let mut pending = staged.split_off(&event_id);
+        std::mem::swap(&mut *staged, &mut pending);
+        if !pending.is_empty() {
+            log::warn!(
+                "the audit records of {:?} are dropped without proofs",
+                pending.keys().collect::<Vec<_>>()
+            );
+        }
+        staged.remove(&event_id).unwrap_or_default()
+    };
+    let mut logs = LOGS.lock().expect("audit lock poisoned;qed");
+    for mut record in records {
+        record.root = to_hex(root);
+        let log = match logs.entry(record.symbol) {
+            MapEntry::Occupied(e) => e.into_mut(),
+            MapEntry::Vacant(e) => {
+                let dir = Path::new(&C.server.get_audit_path())
+                    .join(format!("{}-{}", record.symbol.0, record.symbol.1));
+                match AuditLog::open(dir, config.segment_size) {
+                    Ok(log) => e.insert(log),
+                    Err(err) => {
+                        log::error!("unable to open the audit log of {:?}, {:?}", e.key(), err);
+                        continue;
+                    }
+                }
+            }
+        };
+        if let Err(e) = log.append(record) {
+            log::error!("unable to append the audit record of {}, {:?}", event_id, e);
+        }
+    }
+}
+
+/// the matches of a symbol chained by the hashes, segmented into files named by the first event
+/// id of each and never removed
+pub struct AuditLog {
+    dir: PathBuf,
+    segment_size: u64,
+    segment: Option<File>,
+    written: u64,
+    last_id: u64,
+    head: [u8; 32],
+}
+
+impl AuditLog {
+    /// the torn record at the tail of the last segment is truncated
+    pub fn open(dir: impl AsRef<Path>, segment_size: u64) -> anyhow::Result<Self> {
+        let dir = dir.as_ref().to_path_buf();
+        std::fs::create_dir_all(&dir)?;
+        let mut log = Self {
+            dir,
+            segment_size,
+            segment: None,
+            written: 0,
+            last_id: 0,
+            head: [0; 32],
+        };
+        let mut segments = segments(&log.dir)?;
+        if let Some((_, path)) = segments.pop_back() {
+            let mut file = OpenOptions::new().read(true).write(true).open(&path)?;
+            let (valid, mut last) = last_entry(&mut file, &path)?;
+            file.set_len(valid)?;
+            file.seek(SeekFrom::End(0))?;
+            // rotated right before crashing
+            if last.is_none() {
+                if let Some((_, path)) = segments.pop_back() {
+                    last = last_entry(&mut File::open(&path)?, &path)?.1;
+                }
+            }
+            if let Some(entry) = last {
+                log.last_id = entry.record.event_id;
+                log.head = from_hex(&entry.hash)
+                    .ok_or_else(|| anyhow::anyhow!("invalid hash of {}", entry.record.event_id))?;
+            }
+            log.segment = Some(file);
+            log.written = valid;
+        }
+        Ok(log)
+    }
+
+    pub fn last_id(&self) -> u64 {
+        self.last_id
+    }
+
+    /// the events appended already are skipped, e.g. replaying on restarting
+    pub fn append(&mut self, mut record: AuditRecord) -> anyhow::Result<()> {
+        if record.event_id <= self.last_id {
+            return Ok(());
+        }
+        if self.segment.is_none() || self.written >= self.segment_size {
+            self.rotate(record.event_id)?;
+        }
+        record.prev = to_hex(self.head);
+        let hash = record.hash();
+        let (id, entry) = (
+            record.event_id,
+            Entry {
+                record,
+                hash: to_hex(hash),
+            },
+        );
+        let mut line = serde_json::to_vec(&entry)?;
+        line.push(b'\n');
+        self.segment
+            .as_mut()
+            .expect("segment opened;qed")
+            .write_all(&line)?;
+        self.written += line.len() as u64;
+        self.last_id = id;
+        self.head = hash;
+        Ok(())
+    }
+
+    fn rotate(&mut self, first_id: u64) -> anyhow::Result<()> {
+        if let Some(segment) = self.segment.take() {
+            segment.sync_all()?;
+        }
+        let path = self.dir.join(format!("{:020}.{}", first_id, SEGMENT_EXT));
+        self.segment = Some(OpenOptions::new().create(true).append(true).open(path)?);
+        self.written = 0;
+        Ok(())
+    }
+}
+
+/// the segments sorted by their first event ids
+fn segments(dir: &Path) -> anyhow::Result<VecDeque<(u64, PathBuf)>> {
+    let mut segments = std::fs::read_dir(dir)?
+        .filter_map(|entry| entry.ok().map(|e| e.path()))
+        .filter(|path| path.extension().is_some_and(|ext| ext == SEGMENT_EXT))
+        .filter_map(|path| {
+            path.file_stem()
+                .and_then(|s| s.to_str())
+                .and_then(|s| s.parse::<u64>().ok())
+                .map(|id| (id, path))
+        })
+        .collect::<Vec<_>>();
+    segments.sort();
+    Ok(segments.into())
+}
+
+/// the length of the complete lines and the last of them
+fn last_entry(file: &mut File, path: &Path) -> anyhow::Result<(u64, Option<Entry>)> {
+    let mut reader = BufReader::new(file);
+    let (mut valid, mut last, mut n) = (0, None, 0);
+    let mut line = vec![];
+    loop {
+        line.clear();
+        let read = reader.read_until(b'\n', &mut line)?;
+        if read == 0 || line.last() != Some(&b'\n') {
+            break;
+        }
+        n += 1;
+        valid += read as u64;
+        last = Some(line.clone());
+    }
+    let entry = match last {
+        Some(line) => Some(
+            serde_json::from_slice::<Entry>(&line)
+                .map_err(|_| AuditError::Malformed(path.to_path_buf(), n))?,
+        ),
+        None => None,
+    };
+    Ok((valid, entry))
+}
+
+/// the number of the records and the last one, stopped at the first broken record
+pub fn verify_log(dir: impl AsRef<Path>) -> anyhow::Result<(usize, Option<AuditRecord>)> {
+    let (mut count, mut last, mut head) = (0, None::<AuditRecord>, [0u8; 32]);
+    for (_, path) in segments(dir.as_ref())? {
+        let mut reader = BufReader::new(File::open(&path)?);
+        let mut line = vec![];
+        let mut n = 0;
+        loop {
+            line.clear();
+            if reader.read_until(b'\n', &mut line)? == 0 {
+                break;
+            }
+            n += 1;
+            let malformed = || AuditError::Malformed(path.clone(), n);
+            if line.last() != Some(&b'\n') {
+                return Err(malformed().into());
+            }
+            let entry = serde_json::from_slice::<Entry>(&line).map_err(|_| malformed())?;
+            let hash = entry.record.hash();
+            if from_hex(&entry.hash) != Some(hash) {
+                return Err(AuditError::Tampered(path.clone(), n).into());
+            }
+            if from_hex(&entry.record.prev) != Some(head)
+                || last
+                    .as_ref()
+                    .is_some_and(|r| r.event_id >= entry.record.event_id)
+            {
+                return Err(AuditError::Unchained(path.clone(), n).into());
+            }
+            head = hash;
+            count += 1;
+            last = Some(entry.record);
+        }
+    }
+    Ok((count, last))
+}
+
+/// verify the logs of the symbols under the directory and print the summaries as json lines,
+/// returns false if any log is broken
+pub fn check(c: AuditCmd) -> anyhow::Result<bool> {
+    let root = Path::new(&c.input_path);
+    let mut dirs = std::fs::read_dir(root)?
+        .filter_map(|entry| entry.ok().map(|e| e.path()))
+        .filter(|path| path.is_dir())
+        .collect::<Vec<_>>();
+    dirs.sort();
+    // a single log
+    if !segments(root)?.is_empty() {
+        dirs = vec![root.to_path_buf()];
+    }
+    let stdout = std::io::stdout();
+    let mut out = stdout.lock();
+    let mut intact = true;
+    for dir in dirs {
+        let name = dir
+            .file_name()
+            .map(|s| s.to_string_lossy().to_string())
+            .unwrap_or_default();
+        match verify_log(&dir) {
+            Ok((records, last)) => writeln!(
+                out,
+                "{}",
+                serde_json::json!({
+                    "symbol": name,
+                    "records": records,
+                    "last_event_id": last.as_ref().map(|r| r.event_id),
+                    "root": last.as_ref().map(|r| r.root.clone()),
+                })
+            )?,
+            Err(e) => {
+                log::error!("{}", e);
+                intact = false;
+            }
+        }
+    }
+    Ok(intact)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use rust_decimal_macros::dec;
+
+    fn record(id: u64) -> AuditRecord {
+        AuditRecord {
+            event_id: id,
+            symbol: (1, 0),
+            timestamp: 1_700_000_000_000 + id,
+            taker: to_hex(UserId::from_low_u64_be(1)),
+            taker_order_id: id,
+            taker_side: AskOrBid::Bid,
+            taker_base_fee: dec!(0.001),
+            taker_quote_fee: Amount::zero(),
+            fills: vec![AuditFill {
+                maker: to_hex(UserId::from_low_u64_be(2)),
+                maker_order_id: id + 1000,
+                price: dec!(10.50),
+                amount: dec!(1),
+                base_fee: Amount::zero(),
+                quote_fee: dec!(0.0105),
+            }],
+            root: to_hex([id as u8; 32]),
+            prev: String::new(),
+        }
+    }
+
+    #[test]
+    pub fn test_audit_log() {
+        let dir = tempdir::TempDir::new("galois-audit").unwrap();
+        let mut log = AuditLog::open(dir.path(), 1000).unwrap();
+        for id in 1..=10 {
+            log.append(record(id * 2)).unwrap();
+        }
+        // replayed
+        log.append(record(4)).unwrap();
+        assert_eq!(20, log.last_id());
+        drop(log);
+        let all = segments(dir.path()).unwrap();
+        assert!(all.len() > 1);
+        let (count, last) = verify_log(dir.path()).unwrap();
+        assert_eq!(10, count);
+        assert_eq!(20, last.unwrap().event_id);
+
+        // the torn tail is truncated and the chain continues on reopening
+        let (_, tail) = all.back().unwrap();
+        let mut file = OpenOptions::new().append(true).open(tail).unwrap();
+        file.write_all(b"{\"event_id\":2").unwrap();
+        drop(file);
+        assert!(verify_log(dir.path()).is_err());
+        let mut log = AuditLog::open(dir.path(), 1000).unwrap();
+        assert_eq!(20, log.last_id());
+        for id in 21..=25 {
+            log.append(record(id)).unwrap();
+        }
+        drop(log);
+        assert_eq!(15, verify_log(dir.path()).unwrap().0);
+
+        // a modified amount is detected
+        let (_, first) = segments(dir.path()).unwrap().pop_front().unwrap();
+        let origin = std::fs::read_to_string(&first).unwrap();
+        std::fs::write(&first, origin.replacen("\"1\"", "\"2\"", 1)).unwrap();
+        let e = verify_log(dir.path()).unwrap_err();
+        assert!(matches!(
+            e.downcast_ref::<AuditError>(),
+            Some(AuditError::Tampered(_, 1))
+        ));
+
+        // so is a removed record
+        let lines = origin.lines().collect::<Vec<_>>();
+        std::fs::write(&first, format!("{}\n", lines[1])).unwrap();
+        let e = verify_log(dir.path()).unwrap_err();
+        assert!(matches!(
+            e.downcast_ref::<AuditError>(),
+            Some(AuditError::Unchained(_, 1))
+        ));
+    }
+}
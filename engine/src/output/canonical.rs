@@ -0,0 +1,230 @@
+// Copyright 2021-2023 UINB Technologies Pte. Ltd.
+
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// 	http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::{
+    archive::{ClosedOrder, OrderHistoryPage},
+    core::*,
+    matcher::Execution,
+    orderbook::Order,
+    orders::{FillReport, PendingOrder, UserOrdersPage},
+    output::{kline::Kline, Trade},
+};
+use rust_decimal::Decimal;
+
+/// balances and fees are kept in the precision of the chain
+pub const CURRENCY_SCALE: u32 = 18;
+
+/// pad `d` with trailing zeros to exactly `scale` digits, so the same value is always
+/// serialized as the same string; the significant digits beyond `scale`(e.g. the average
+/// prices) are kept since rounding them would make the outputs disagree with the proofs
+pub fn fixed(d: Decimal, scale: u32) -> Decimal {
+    let scale = scale.min(28);
+    let mut d = if d.scale() > scale { d.normalize() } else { d };
+    if d.scale() < scale {
+        d.rescale(scale);
+    }
+    d
+}
+
+/// the formatting of a symbol, prices in quote scale and amounts in base scale
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct Scales {
+    pub base: Scale,
+    pub quote: Scale,
+}
+
+impl Scales {
+    pub const fn new(base: Scale, quote: Scale) -> Self {
+        Self { base, quote }
+    }
+
+    pub fn price(&self, d: Price) -> Price {
+        fixed(d, self.quote)
+    }
+
+    pub fn amount(&self, d: Amount) -> Amount {
+        fixed(d, self.base)
+    }
+
+    /// the quote amounts, i.e. price * amount
+    pub fn vol(&self, d: Amount) -> Amount {
+        fixed(d, self.base + self.quote)
+    }
+}
+
+impl From<&OrderBook> for Scales {
+    fn from(book: &OrderBook) -> Self {
+        Self::new(book.base_scale, book.quote_scale)
+    }
+}
+
+/// applied to every decimal of the json outputs, queries and broadcasts
+pub trait Canonical {
+    fn canonical(self, scales: &Scales) -> Self;
+}
+
+impl Canonical for PendingOrder {
+    fn canonical(mut self, scales: &Scales) -> Self {
+        self.amount = scales.amount(self.amount);
+        self.price = scales.price(self.price);
+        self.matched_base_amount = scales.amount(self.matched_base_amount);
+        self.matched_quote_amount = scales.vol(self.matched_quote_amount);
+        self.base_fee = fixed(self.base_fee, CURRENCY_SCALE);
+        self.quote_fee = fixed(self.quote_fee, CURRENCY_SCALE);
+        self
+    }
+}
+
+impl Canonical for UserOrdersPage {
+    fn canonical(mut self, scales: &Scales) -> Self {
+        self.orders = self.orders.canonical(scales);
+        self
+    }
+}
+
+impl Canonical for ClosedOrder {
+    fn canonical(mut self, scales: &Scales) -> Self {
+        self.order = self.order.canonical(scales);
+        self
+    }
+}
+
+impl Canonical for OrderHistoryPage {
+    fn canonical(mut self, scales: &Scales) -> Self {
+        self.orders = self.orders.canonical(scales);
+        self
+    }
+}
+
+impl Canonical for Execution {
+    fn canonical(mut self, scales: &Scales) -> Self {
+        self.best_price = scales.price(self.best_price);
+        self.average_price = scales.price(self.average_price);
+        self.filled = scales.amount(self.filled);
+        self
+    }
+}
+
+impl Canonical for FillReport {
+    fn canonical(self, scales: &Scales) -> Self {
+        Self {
+            order: self.order.canonical(scales),
+            execution: self.execution.map(|e| e.canonical(scales)),
+            timestamp_us: self.timestamp_us,
+        }
+    }
+}
+
+impl Canonical for Order {
+    fn canonical(mut self, scales: &Scales) -> Self {
+        self.price = scales.price(self.price);
+        self.unfilled = scales.amount(self.unfilled);
+        self.display = scales.amount(self.display);
+        self.hidden = scales.amount(self.hidden);
+        self
+    }
+}
+
+impl Canonical for Trade {
+    fn canonical(mut self, scales: &Scales) -> Self {
+        self.price = scales.price(self.price);
+        self.amount = scales.amount(self.amount);
+        self
+    }
+}
+
+impl Canonical for LastPrice {
+    fn canonical(mut self, scales: &Scales) -> Self {
+        self.price = scales.price(self.price);
+        self
+    }
+}
+
+impl Canonical for Kline {
+    fn canonical(mut self, scales: &Scales) -> Self {
+        self.open = scales.price(self.open);
+        self.high = scales.price(self.high);
+        self.low = scales.price(self.low);
+        self.close = scales.price(self.close);
+        self.volume = scales.amount(self.volume);
+        self.quote_volume = scales.vol(self.quote_volume);
+        self
+    }
+}
+
+impl<T: Canonical> Canonical for Vec<T> {
+    fn canonical(self, scales: &Scales) -> Self {
+        self.into_iter().map(|t| t.canonical(scales)).collect()
+    }
+}
+
+impl<T: Canonical> Canonical for Option<T> {
+    fn canonical(self, scales: &Scales) -> Self {
+        self.map(|t| t.canonical(scales))
+    }
+}
+
+/// in the precision of the chain
+pub fn balance(b: Balance) -> Balance {
+    Balance {
+        available: fixed(b.available, CURRENCY_SCALE),
+        frozen: fixed(b.frozen, CURRENCY_SCALE),
+    }
+}
+
+impl BrokerExecution {
+    /// aggregated across the symbols, so in the precision of the chain
+    pub fn canonical(mut self) -> Self {
+        self.base_filled = fixed(self.base_filled, CURRENCY_SCALE);
+        self.quote_filled = fixed(self.quote_filled, CURRENCY_SCALE);
+        self.price_improvement = fixed(self.price_improvement, CURRENCY_SCALE);
+        self
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use rust_decimal_macros::dec;
+
+    #[test]
+    pub fn test_canonical() {
+        assert_eq!("1.500", fixed(dec!(1.5), 3).to_string());
+        assert_eq!("1.500", fixed(dec!(1.50000), 3).to_string());
+        assert_eq!("1.500", fixed(dec!(1.500), 3).to_string());
+        // never rounded
+        assert_eq!("1.5001", fixed(dec!(1.50010), 3).to_string());
+        assert_eq!("0.00", fixed(dec!(0), 2).to_string());
+        let scales = Scales::new(3, 2);
+        assert_eq!("2.00000", scales.vol(dec!(2)).to_string());
+        let trade = Trade {
+            event_id: 1,
+            symbol: (1, 0),
+            price: dec!(10.1),
+            amount: dec!(2),
+            taker_side: crate::orderbook::AskOrBid::Bid,
+            timestamp: 0,
+            timestamp_us: 0,
+        };
+        let json = serde_json::to_value(trade.canonical(&scales)).unwrap();
+        assert_eq!("10.10", json["price"]);
+        assert_eq!("2.000", json["amount"]);
+        let balance = balance(Balance {
+            available: dec!(1),
+            frozen: dec!(0.000000000000000001),
+        });
+        assert_eq!(balance.available.to_string(), "1.000000000000000000");
+        assert_eq!(balance.frozen.to_string(), "0.000000000000000001");
+    }
+}
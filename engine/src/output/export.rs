@@ -0,0 +1,594 @@
+// Copyright 2021-2023 UINB Technologies Pte. Ltd.
+
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// 	http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! export trades, order updates and ledger entries as parquet files, partitioned like
+//! `{path}/{trades|orders|ledger}/date=YYYY-MM-DD/symbol={base}-{quote}/{from}-{to}.parquet`.
+//! the `path` could be a local directory or an object store bucket mounted by fuse.
+
+use crate::{config::ExportConfig, core::*, matcher::Role, orderbook::AskOrBid, output::Output};
+use parquet::{
+    basic::Compression,
+    data_type::{ByteArray, ByteArrayType, Int64Type},
+    file::{properties::WriterProperties, writer::SerializedFileWriter},
+    schema::{parser::parse_message_type, types::Type},
+};
+use std::{
+    collections::HashMap,
+    path::PathBuf,
+    sync::{
+        mpsc::{RecvTimeoutError, Sender},
+        Arc,
+    },
+    thread::JoinHandle,
+    time::{Duration, Instant},
+};
+
+// the failed partitions are retried no sooner than this
+const MIN_FLUSH_WAIT: Duration = Duration::from_millis(100);
+
+const TRADES_SCHEMA: &str = "
+message trade {
+    REQUIRED INT64 event_id;
+    REQUIRED INT64 base;
+    REQUIRED INT64 quote;
+    REQUIRED BINARY taker_side (UTF8);
+    REQUIRED BINARY price (UTF8);
+    REQUIRED BINARY amount (UTF8);
+    REQUIRED BINARY vol (UTF8);
+    REQUIRED INT64 maker_order_id;
+    REQUIRED BINARY maker (UTF8);
+    REQUIRED BINARY maker_fee (UTF8);
+    REQUIRED INT64 taker_order_id;
+    REQUIRED BINARY taker (UTF8);
+    REQUIRED INT64 timestamp (TIMESTAMP_MILLIS);
+}";
+
+const ORDERS_SCHEMA: &str = "
+message order {
+    REQUIRED INT64 event_id;
+    REQUIRED INT64 order_id;
+    REQUIRED BINARY user_id (UTF8);
+    REQUIRED INT64 base;
+    REQUIRED INT64 quote;
+    REQUIRED BINARY side (UTF8);
+    REQUIRED BINARY role (UTF8);
+    REQUIRED BINARY state (UTF8);
+    REQUIRED BINARY price (UTF8);
+    REQUIRED BINARY base_delta (UTF8);
+    REQUIRED BINARY quote_delta (UTF8);
+    REQUIRED INT64 timestamp (TIMESTAMP_MILLIS);
+}";
+
+const LEDGER_SCHEMA: &str = "
+message ledger {
+    REQUIRED INT64 event_id;
+    REQUIRED INT64 order_id;
+    REQUIRED BINARY user_id (UTF8);
+    REQUIRED INT64 currency;
+    REQUIRED BINARY delta (UTF8);
+    REQUIRED BINARY charge (UTF8);
+    REQUIRED BINARY available (UTF8);
+    REQUIRED BINARY frozen (UTF8);
+    REQUIRED INT64 timestamp (TIMESTAMP_MILLIS);
+}";
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
+pub enum Dataset {
+    Trades,
+    Orders,
+    Ledger,
+}
+
+impl Dataset {
+    fn name(&self) -> &'static str {
+        match self {
+            Dataset::Trades => "trades",
+            Dataset::Orders => "orders",
+            Dataset::Ledger => "ledger",
+        }
+    }
+
+    fn schema(&self) -> &'static str {
+        match self {
+            Dataset::Trades => TRADES_SCHEMA,
+            Dataset::Orders => ORDERS_SCHEMA,
+            Dataset::Ledger => LEDGER_SCHEMA,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+enum Value {
+    Int(i64),
+    Str(String),
+}
+
+impl From<u64> for Value {
+    fn from(v: u64) -> Self {
+        Value::Int(v as i64)
+    }
+}
+
+impl From<u32> for Value {
+    fn from(v: u32) -> Self {
+        Value::Int(v as i64)
+    }
+}
+
+impl From<String> for Value {
+    fn from(v: String) -> Self {
+        Value::Str(v)
+    }
+}
+
+impl From<&str> for Value {
+    fn from(v: &str) -> Self {
+        Value::Str(v.to_string())
+    }
+}
+
+impl From<Amount> for Value {
+    fn from(v: Amount) -> Self {
+        Value::Str(v.normalize().to_string())
+    }
+}
+
+#[derive(Debug, Eq, PartialEq, Hash, Clone)]
+struct Partition {
+    dataset: Dataset,
+    date: String,
+    symbol: Symbol,
+}
+
+struct Rows {
+    since: Instant,
+    first_event: u64,
+    last_event: u64,
+    rows: Vec<Vec<Value>>,
+}
+
+pub struct Exporter {
+    root: PathBuf,
+    batch_size: usize,
+    flush_interval: Duration,
+    schemas: HashMap<Dataset, Arc<Type>>,
+    pending: HashMap<Partition, Rows>,
+}
+
+impl Exporter {
+    pub fn new(cfg: &ExportConfig) -> anyhow::Result<Self> {
+        let mut schemas = HashMap::new();
+        for d in [Dataset::Trades, Dataset::Orders, Dataset::Ledger] {
+            schemas.insert(d, Arc::new(parse_message_type(d.schema())?));
+        }
+        Ok(Self {
+            root: PathBuf::from(&cfg.path),
+            batch_size: cfg.batch_size.max(1),
+            flush_interval: Duration::from_secs(cfg.flush_interval),
+            schemas,
+            pending: HashMap::new(),
+        })
+    }
+
+    /// append the outputs of one event, the makers always come before the taker
+    pub fn append(&mut self, outputs: &[Output]) -> anyhow::Result<()> {
+        self.stage(outputs);
+        self.flush_ready()
+    }
+
+    /// append the outputs of the events in order, the partitions are checked once
+    pub fn append_batch(&mut self, events: &[Vec<Output>]) -> anyhow::Result<()> {
+        events.iter().for_each(|outputs| self.stage(outputs));
+        self.flush_ready()
+    }
+
+    fn stage(&mut self, outputs: &[Output]) {
+        let taker = outputs.iter().rev().find(|o| o.role == Role::Taker);
+        for o in outputs {
+            let date = to_date(o.timestamp_us / 1_000_000);
+            self.push(Dataset::Orders, &date, o, order_row(o));
+            self.push(Dataset::Ledger, &date, o, ledger_row(o, o.symbol.0));
+            self.push(Dataset::Ledger, &date, o, ledger_row(o, o.symbol.1));
+            if let Some(taker) = taker.filter(|_| o.role == Role::Maker) {
+                self.push(Dataset::Trades, &date, o, trade_row(o, taker));
+            }
+        }
+    }
+
+    fn flush_ready(&mut self) -> anyhow::Result<()> {
+        self.flush_if(|rows, batch_size, interval| {
+            rows.rows.len() >= batch_size || rows.since.elapsed() >= interval
+        })
+    }
+
+    pub fn flush(&mut self) -> anyhow::Result<()> {
+        self.flush_if(|rows, _, _| !rows.rows.is_empty())
+    }
+
+    /// the time until the oldest pending partition is due, `None` if nothing pending
+    fn next_flush(&self) -> Option<Duration> {
+        self.pending
+            .values()
+            .map(|rows| self.flush_interval.saturating_sub(rows.since.elapsed()))
+            .min()
+            .map(|d| d.max(MIN_FLUSH_WAIT))
+    }
+
+    fn push(&mut self, dataset: Dataset, date: &str, o: &Output, row: Vec<Value>) {
+        let partition = Partition {
+            dataset,
+            date: date.to_string(),
+            symbol: o.symbol,
+        };
+        let rows = self.pending.entry(partition).or_insert_with(|| Rows {
+            since: Instant::now(),
+            first_event: o.event_id,
+            last_event: o.event_id,
+            rows: vec![],
+        });
+        rows.last_event = o.event_id;
+        rows.rows.push(row);
+    }
+
+    fn flush_if<F>(&mut self, f: F) -> anyhow::Result<()>
+    where
+        F: Fn(&Rows, usize, Duration) -> bool,
+    {
+        let ready = self
+            .pending
+            .iter()
+            .filter(|(_, rows)| f(rows, self.batch_size, self.flush_interval))
+            .map(|(p, _)| p.clone())
+            .collect::<Vec<_>>();
+        for p in ready {
+            let rows = self.pending.remove(&p).expect("exists;qed");
+            if let Err(e) = self.write(&p, &rows) {
+                // keep the rows and retry next time
+                self.pending.insert(p, rows);
+                return Err(e);
+            }
+        }
+        Ok(())
+    }
+
+    fn write(&self, p: &Partition, rows: &Rows) -> anyhow::Result<()> {
+        let dir = self
+            .root
+            .join(p.dataset.name())
+            .join(format!("date={}", p.date))
+            .join(format!("symbol={}-{}", p.symbol.0, p.symbol.1));
+        std::fs::create_dir_all(&dir)?;
+        let file = dir.join(format!("{}-{}.parquet", rows.first_event, rows.last_event));
+        let tmp = file.with_extension("parquet.tmp");
+        let schema = self.schemas.get(&p.dataset).expect("schema;qed").clone();
+        let props = Arc::new(
+            WriterProperties::builder()
+                .set_compression(Compression::SNAPPY)
+                .build(),
+        );
+        let mut writer = SerializedFileWriter::new(std::fs::File::create(&tmp)?, schema, props)?;
+        let mut group = writer.next_row_group()?;
+        let mut idx = 0;
+        while let Some(mut col) = group.next_column()? {
+            match rows.rows.first().map(|r| &r[idx]) {
+                Some(Value::Int(_)) => {
+                    let values = rows
+                        .rows
+                        .iter()
+                        .map(|r| match r[idx] {
+                            Value::Int(v) => v,
+                            Value::Str(_) => unreachable!("schema mismatch"),
+                        })
+                        .collect::<Vec<_>>();
+                    col.typed::<Int64Type>().write_batch(&values, None, None)?;
+                }
+                _ => {
+                    let values = rows
+                        .rows
+                        .iter()
+                        .map(|r| match &r[idx] {
+                            Value::Str(v) => ByteArray::from(v.as_str()),
+                            Value::Int(_) => unreachable!("schema mismatch"),
+                        })
+                        .collect::<Vec<_>>();
+                    col.typed::<ByteArrayType>()
+                        .write_batch(&values, None, None)?;
+                }
+            }
+            col.close()?;
+            idx += 1;
+        }
+        group.close()?;
+        writer.close()?;
+        std::fs::rename(&tmp, &file)?;
+        log::debug!("{} rows exported to {:?}", rows.rows.len(), file);
+        Ok(())
+    }
+}
+
+/// the exporters running in `export.writers` threads, each owns the symbols routed to it so the
+/// outputs of a symbol are exported in order while a slow partition doesn't hold the others
+pub struct Writers {
+    senders: Vec<Sender<Vec<Output>>>,
+    handles: Vec<JoinHandle<anyhow::Result<()>>>,
+}
+
+impl Writers {
+    pub fn start(cfg: &ExportConfig) -> anyhow::Result<Self> {
+        let mut senders = vec![];
+        let mut handles = vec![];
+        for i in 0..cfg.writers.max(1) {
+            let mut exporter = Exporter::new(cfg)?;
+            // unbounded, the bursts are queued rather than blocking the market thread
+            let (tx, rx) = std::sync::mpsc::channel::<Vec<Output>>();
+            let handle = std::thread::Builder::new()
+                .name(format!("export-{}", i))
+                .spawn(move || -> anyhow::Result<()> {
+                    loop {
+                        // wake up on the pending partitions due even if no more outputs arrive
+                        let first = match exporter.next_flush() {
+                            Some(wait) => match rx.recv_timeout(wait) {
+                                Ok(first) => first,
+                                Err(RecvTimeoutError::Timeout) => {
+                                    if let Err(e) = exporter.flush_ready() {
+                                        log::error!("exporting outputs failed, {}", e);
+                                    }
+                                    continue;
+                                }
+                                Err(RecvTimeoutError::Disconnected) => break,
+                            },
+                            None => match rx.recv() {
+                                Ok(first) => first,
+                                Err(_) => break,
+                            },
+                        };
+                        // the events queued while writing are appended as one batch
+                        let mut batch = vec![first];
+                        batch.extend(rx.try_iter());
+                        if let Err(e) = exporter.append_batch(&batch) {
+                            log::error!("exporting outputs failed, {}", e);
+                        }
+                    }
+                    exporter.flush()
+                })?;
+            senders.push(tx);
+            handles.push(handle);
+        }
+        Ok(Self { senders, handles })
+    }
+
+    /// the outputs of one event
+    pub fn send(&self, outputs: Vec<Output>) -> anyhow::Result<()> {
+        let symbol = match outputs.first() {
+            Some(o) => o.symbol,
+            None => return Ok(()),
+        };
+        self.senders[route(&symbol, self.senders.len())]
+            .send(outputs)
+            .map_err(|_| anyhow::anyhow!("the writer of {:?} stopped", symbol))
+    }
+
+    /// flush all the pending rows after the queued are exported
+    pub fn stop(self) -> anyhow::Result<()> {
+        drop(self.senders);
+        let mut r = Ok(());
+        for handle in self.handles {
+            let e = handle
+                .join()
+                .unwrap_or_else(|_| Err(anyhow::anyhow!("the writer panicked")));
+            r = r.and(e);
+        }
+        r
+    }
+}
+
+/// the writer of the symbol
+fn route(symbol: &Symbol, writers: usize) -> usize {
+    (symbol.0 as usize)
+        .wrapping_mul(31)
+        .wrapping_add(symbol.1 as usize)
+        % writers
+}
+
+fn to_date(timestamp: u64) -> String {
+    chrono::NaiveDateTime::from_timestamp_opt(timestamp as i64, 0)
+        .unwrap_or_default()
+        .format("%Y-%m-%d")
+        .to_string()
+}
+
+fn side(ask_or_bid: AskOrBid) -> &'static str {
+    match ask_or_bid {
+        AskOrBid::Ask => "ask",
+        AskOrBid::Bid => "bid",
+    }
+}
+
+fn trade_row(maker: &Output, taker: &Output) -> Vec<Value> {
+    vec![
+        maker.event_id.into(),
+        maker.symbol.0.into(),
+        maker.symbol.1.into(),
+        side(taker.ask_or_bid).into(),
+        maker.price.into(),
+        maker.base_delta.abs().into(),
+        maker.quote_delta.abs().into(),
+        maker.order_id.into(),
+        maker.user_id.to_string().into(),
+        // negative for the rebates
+        (-maker.base_charge - maker.quote_charge).into(),
+        taker.order_id.into(),
+        taker.user_id.to_string().into(),
+        (maker.timestamp_us / 1000).into(),
+    ]
+}
+
+fn order_row(o: &Output) -> Vec<Value> {
+    vec![
+        o.event_id.into(),
+        o.order_id.into(),
+        o.user_id.to_string().into(),
+        o.symbol.0.into(),
+        o.symbol.1.into(),
+        side(o.ask_or_bid).into(),
+        format!("{:?}", o.role).into(),
+        format!("{:?}", o.state).into(),
+        o.price.into(),
+        o.base_delta.into(),
+        o.quote_delta.into(),
+        (o.timestamp_us / 1000).into(),
+    ]
+}
+
+fn ledger_row(o: &Output, currency: Currency) -> Vec<Value> {
+    let (delta, charge, available, frozen) = if currency == o.symbol.0 {
+        (o.base_delta, o.base_charge, o.base_available, o.base_frozen)
+    } else {
+        (
+            o.quote_delta,
+            o.quote_charge,
+            o.quote_available,
+            o.quote_frozen,
+        )
+    };
+    vec![
+        o.event_id.into(),
+        o.order_id.into(),
+        o.user_id.to_string().into(),
+        currency.into(),
+        delta.into(),
+        charge.into(),
+        available.into(),
+        frozen.into(),
+        (o.timestamp_us / 1000).into(),
+    ]
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::matcher::State;
+    use parquet::file::reader::{FileReader, SerializedFileReader};
+    use rust_decimal_macros::dec;
+
+    fn output(event_id: u64, role: Role, ask_or_bid: AskOrBid) -> Output {
+        Output {
+            event_id,
+            order_id: event_id,
+            user_id: UserId::from_low_u64_be(event_id),
+            symbol: (1, 0),
+            state: State::Filled,
+            role,
+            ask_or_bid,
+            price: dec!(10),
+            quote_charge: dec!(0),
+            quote_delta: dec!(10),
+            quote_available: dec!(10),
+            quote_frozen: dec!(0),
+            base_charge: dec!(0),
+            base_delta: dec!(-1),
+            base_available: dec!(0),
+            base_frozen: dec!(0),
+            timestamp: 1672531200,
+            timestamp_us: 1672531200 * 1_000_000,
+        }
+    }
+
+    fn count_rows(path: PathBuf) -> i64 {
+        let reader = SerializedFileReader::new(std::fs::File::open(path).unwrap()).unwrap();
+        reader.metadata().file_metadata().num_rows()
+    }
+
+    #[test]
+    pub fn test_export_partitioned_by_date_and_symbol() {
+        let dir = tempdir::TempDir::new("export").unwrap();
+        let cfg = ExportConfig {
+            path: dir.path().to_str().unwrap().to_string(),
+            batch_size: 1000,
+            flush_interval: 3600,
+            writers: 1,
+        };
+        let mut exporter = Exporter::new(&cfg).unwrap();
+        exporter
+            .append(&[
+                output(1, Role::Maker, AskOrBid::Ask),
+                output(1, Role::Taker, AskOrBid::Bid),
+            ])
+            .unwrap();
+        assert!(!dir.path().join("trades").exists());
+        exporter.flush().unwrap();
+        let partition = "date=2023-01-01/symbol=1-0/1-1.parquet";
+        assert_eq!(1, count_rows(dir.path().join("trades").join(partition)));
+        assert_eq!(2, count_rows(dir.path().join("orders").join(partition)));
+        assert_eq!(4, count_rows(dir.path().join("ledger").join(partition)));
+    }
+
+    #[test]
+    pub fn test_export_by_writers() {
+        let dir = tempdir::TempDir::new("export").unwrap();
+        let cfg = ExportConfig {
+            path: dir.path().to_str().unwrap().to_string(),
+            batch_size: 1000,
+            flush_interval: 3600,
+            writers: 3,
+        };
+        let writers = Writers::start(&cfg).unwrap();
+        for symbol in [(1, 0), (2, 0), (3, 0)] {
+            for id in 1..=10 {
+                let mut maker = output(id, Role::Maker, AskOrBid::Ask);
+                let mut taker = output(id, Role::Taker, AskOrBid::Bid);
+                maker.symbol = symbol;
+                taker.symbol = symbol;
+                writers.send(vec![maker, taker]).unwrap();
+            }
+        }
+        writers.send(vec![]).unwrap();
+        writers.stop().unwrap();
+        for symbol in ["1-0", "2-0", "3-0"] {
+            let path = dir
+                .path()
+                .join("trades")
+                .join(format!("date=2023-01-01/symbol={}/1-10.parquet", symbol));
+            assert_eq!(10, count_rows(path));
+        }
+        assert_eq!(route(&(2, 0), 3), route(&(2, 0), 3));
+        assert_ne!(route(&(1, 0), 3), route(&(2, 0), 3));
+    }
+
+    #[test]
+    pub fn test_flush_idle_writers() {
+        let dir = tempdir::TempDir::new("export").unwrap();
+        let cfg = ExportConfig {
+            path: dir.path().to_str().unwrap().to_string(),
+            batch_size: 1000,
+            flush_interval: 1,
+            writers: 1,
+        };
+        let writers = Writers::start(&cfg).unwrap();
+        writers
+            .send(vec![
+                output(1, Role::Maker, AskOrBid::Ask),
+                output(1, Role::Taker, AskOrBid::Bid),
+            ])
+            .unwrap();
+        let path = dir
+            .path()
+            .join("trades")
+            .join("date=2023-01-01/symbol=1-0/1-1.parquet");
+        // flushed by the timer rather than the next outputs or stopping
+        std::thread::sleep(Duration::from_millis(2000));
+        assert_eq!(1, count_rows(path));
+        writers.stop().unwrap();
+    }
+}
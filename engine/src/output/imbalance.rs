@@ -0,0 +1,195 @@
+// Copyright 2021-2023 UINB Technologies Pte. Ltd.
+
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// 	http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::{
+    config::C,
+    core::*,
+    input::{self, Message},
+    orderbook::Level,
+    output::Depth,
+};
+use dashmap::DashMap;
+use rust_decimal::{prelude::Zero, Decimal};
+use serde::{Deserialize, Serialize};
+use std::{collections::HashMap, sync::mpsc::Sender, time::Duration};
+
+/// the levels of each side summed into `imbalance`
+pub const LEVELS: usize = 5;
+
+/// finer than the ticks, it's an estimation of the fair price
+const MICROPRICE_SCALE: u32 = 8;
+
+const BROADCAST_INTERVAL: Duration = Duration::from_secs(5);
+
+lazy_static::lazy_static! {
+    // updated by the executor along with the depth
+    pub static ref IMBALANCES: DashMap<Symbol, BookImbalance> = DashMap::new();
+}
+
+/// the top of the book of a symbol as of `update_id`, the sides missing leave the prices `None`
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub struct BookImbalance {
+    pub symbol: Symbol,
+    pub update_id: u64,
+    pub best_ask: Option<Price>,
+    pub best_bid: Option<Price>,
+    pub spread: Option<Price>,
+    pub mid_price: Option<Price>,
+    /// the mid weighted by the amounts of the opposite best levels, leaning to the thinner side,
+    /// rounded to 8 decimals
+    pub microprice: Option<Price>,
+    /// `(bid_depth - ask_depth) / (bid_depth + ask_depth)` in `[-1, 1]`, rounded to 4 decimals
+    pub imbalance: Decimal,
+    /// the amounts of the best `LEVELS` levels
+    pub ask_depth: Amount,
+    pub bid_depth: Amount,
+}
+
+impl BookImbalance {
+    pub fn new(update_id: u64, depth: &Depth) -> Self {
+        // the totals are accumulated from the best
+        let depth_of = |levels: &[Level]| {
+            levels[..LEVELS.min(levels.len())]
+                .last()
+                .map_or(Amount::zero(), |l| l.2)
+        };
+        let (ask_depth, bid_depth) = (depth_of(&depth.asks), depth_of(&depth.bids));
+        let (ask, bid) = (depth.asks.first(), depth.bids.first());
+        let top = ask.zip(bid);
+        let microprice = top.and_then(|(a, b)| {
+            let size = a.1.checked_add(b.1).filter(|s| !s.is_zero())?;
+            let weighted = a.0.checked_mul(b.1)?.checked_add(b.0.checked_mul(a.1)?)?;
+            Some(weighted.checked_div(size)?.round_dp(MICROPRICE_SCALE))
+        });
+        let total = ask_depth + bid_depth;
+        Self {
+            symbol: depth.symbol,
+            update_id,
+            best_ask: ask.map(|a| a.0),
+            best_bid: bid.map(|b| b.0),
+            spread: top.map(|(a, b)| a.0 - b.0),
+            mid_price: top.map(|(a, b)| (a.0 + b.0) / Decimal::TWO),
+            microprice,
+            imbalance: if total.is_zero() {
+                Decimal::zero()
+            } else {
+                ((bid_depth - ask_depth) / total).round_dp(4)
+            },
+            ask_depth,
+            bid_depth,
+        }
+    }
+}
+
+pub fn update(update_id: u64, depth: &Depth) {
+    IMBALANCES.insert(depth.symbol, BookImbalance::new(update_id, depth));
+}
+
+/// all symbols sorted if `symbol` absent
+pub fn query(symbol: Option<Symbol>) -> Vec<BookImbalance> {
+    let mut r = match symbol {
+        Some(symbol) => IMBALANCES
+            .get(&symbol)
+            .map(|i| vec![i.value().clone()])
+            .unwrap_or_default(),
+        None => IMBALANCES.iter().map(|i| i.value().clone()).collect(),
+    };
+    r.sort_by_key(|i| i.symbol);
+    r
+}
+
+/// broadcast the symbols updated since the last round as `BOOK_IMBALANCE_UPDATED` periodically
+pub fn init(tx: Sender<(u64, Message)>) {
+    if C.dry_run.is_some() {
+        return;
+    }
+    std::thread::spawn(move || {
+        let mut broadcasted = HashMap::<Symbol, u64>::new();
+        loop {
+            std::thread::sleep(BROADCAST_INTERVAL);
+            let updated = query(None)
+                .into_iter()
+                .filter(|i| broadcasted.get(&i.symbol) != Some(&i.update_id))
+                .collect::<Vec<_>>();
+            if updated.is_empty() {
+                continue;
+            }
+            broadcasted.extend(updated.iter().map(|i| (i.symbol, i.update_id)));
+            let msg = Message::new_broadcast(
+                input::BOOK_IMBALANCE_UPDATED,
+                serde_json::to_vec(&updated).unwrap_or_default(),
+            );
+            if tx.send((0, msg)).is_err() {
+                break;
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use rust_decimal_macros::dec;
+
+    #[test]
+    pub fn test_book_imbalance() {
+        let depth = Depth {
+            asks: vec![
+                (dec!(10.2), dec!(1), dec!(1)),
+                (dec!(10.3), dec!(3), dec!(4)),
+            ],
+            bids: vec![
+                (dec!(10.0), dec!(3), dec!(3)),
+                (dec!(9.9), dec!(3), dec!(6)),
+            ],
+            symbol: (1, 0),
+        };
+        let i = BookImbalance::new(7, &depth);
+        assert_eq!(Some(dec!(0.2)), i.spread);
+        assert_eq!(Some(dec!(10.1)), i.mid_price);
+        // (10.2 * 3 + 10.0 * 1) / 4, closer to the thin ask
+        assert_eq!(Some(dec!(10.15)), i.microprice);
+        assert_eq!((dec!(4), dec!(6)), (i.ask_depth, i.bid_depth));
+        assert_eq!(dec!(0.2), i.imbalance);
+
+        let one_sided = Depth {
+            asks: vec![],
+            bids: depth.bids.clone(),
+            symbol: (1, 0),
+        };
+        let i = BookImbalance::new(8, &one_sided);
+        assert!(i.best_ask.is_none() && i.microprice.is_none() && i.spread.is_none());
+        assert_eq!(dec!(1), i.imbalance);
+        let empty = BookImbalance::new(
+            9,
+            &Depth {
+                asks: vec![],
+                bids: vec![],
+                symbol: (1, 0),
+            },
+        );
+        assert_eq!(Decimal::zero(), empty.imbalance);
+
+        // the executors of the other tests may update theirs too
+        update(
+            8,
+            &Depth {
+                symbol: (906, 0),
+                ..one_sided
+            },
+        );
+        assert_eq!(8, query(Some((906, 0)))[0].update_id);
+        assert!(query(Some((907, 0))).is_empty());
+    }
+}
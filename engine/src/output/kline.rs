@@ -0,0 +1,338 @@
+// Copyright 2021-2023 UINB Technologies Pte. Ltd.
+
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// 	http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::{core::*, matcher::Role, output::Output};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{HashMap, VecDeque},
+    path::Path,
+    str::FromStr,
+    sync::RwLock,
+};
+
+lazy_static::lazy_static! {
+    pub static ref KLINES: RwLock<Klines> = RwLock::new(Klines::default());
+}
+
+/// bars kept for each symbol and interval
+pub const MAX_BARS: usize = 1440;
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash, Serialize, Deserialize)]
+pub enum Interval {
+    #[serde(rename = "1m")]
+    M1,
+    #[serde(rename = "5m")]
+    M5,
+    #[serde(rename = "15m")]
+    M15,
+    #[serde(rename = "1h")]
+    H1,
+    #[serde(rename = "1d")]
+    D1,
+}
+
+impl Interval {
+    pub const ALL: [Interval; 5] = [
+        Interval::M1,
+        Interval::M5,
+        Interval::M15,
+        Interval::H1,
+        Interval::D1,
+    ];
+
+    pub const fn secs(&self) -> u64 {
+        match self {
+            Interval::M1 => 60,
+            Interval::M5 => 300,
+            Interval::M15 => 900,
+            Interval::H1 => 3600,
+            Interval::D1 => 86400,
+        }
+    }
+}
+
+impl FromStr for Interval {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "1m" => Ok(Interval::M1),
+            "5m" => Ok(Interval::M5),
+            "15m" => Ok(Interval::M15),
+            "1h" => Ok(Interval::H1),
+            "1d" => Ok(Interval::D1),
+            _ => Err(anyhow::anyhow!("unknown interval {}", s)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Eq, PartialEq, Serialize, Deserialize)]
+pub struct Kline {
+    pub open_time: u64,
+    pub open: Price,
+    pub high: Price,
+    pub low: Price,
+    pub close: Price,
+    /// base amount
+    pub volume: Amount,
+    pub quote_volume: Amount,
+    pub trades: u64,
+}
+
+impl Kline {
+    fn new(open_time: u64, price: Price, base: Amount, quote: Amount) -> Self {
+        Self {
+            open_time,
+            open: price,
+            high: price,
+            low: price,
+            close: price,
+            volume: base,
+            quote_volume: quote,
+            trades: 1,
+        }
+    }
+
+    fn merge(&mut self, price: Price, base: Amount, quote: Amount) {
+        self.high = self.high.max(price);
+        self.low = self.low.min(price);
+        self.close = price;
+        self.volume += base;
+        self.quote_volume += quote;
+        self.trades += 1;
+    }
+}
+
+/// OHLCV bars built from the fills of makers, the periods without trades have no bars
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Klines {
+    /// the outputs would be replayed from the last snapshot after restarting
+    last_event_id: u64,
+    bars: HashMap<(Symbol, Interval), VecDeque<Kline>>,
+}
+
+impl Klines {
+    pub fn update(&mut self, outputs: &[Output]) {
+        let replayed = self.last_event_id;
+        let mut last_event_id = replayed;
+        for o in outputs.iter().filter(|o| o.event_id > replayed) {
+            last_event_id = last_event_id.max(o.event_id);
+            if o.role != Role::Maker || o.base_delta.is_zero() {
+                continue;
+            }
+            for interval in Interval::ALL {
+                self.append(
+                    o.symbol,
+                    interval,
+                    o.timestamp_us / 1_000_000,
+                    o.price,
+                    o.base_delta.abs(),
+                    o.quote_delta.abs(),
+                );
+            }
+        }
+        self.last_event_id = last_event_id;
+    }
+
+    fn append(
+        &mut self,
+        symbol: Symbol,
+        interval: Interval,
+        timestamp: u64,
+        price: Price,
+        base: Amount,
+        quote: Amount,
+    ) {
+        let bars = self.bars.entry((symbol, interval)).or_default();
+        let open_time = timestamp - timestamp % interval.secs();
+        match bars.back_mut() {
+            // the stamps of matching are monotonic, an earlier one only comes from the older versions
+            Some(bar) if bar.open_time >= open_time => bar.merge(price, base, quote),
+            _ => {
+                bars.push_back(Kline::new(open_time, price, base, quote));
+                if bars.len() > MAX_BARS {
+                    bars.pop_front();
+                }
+            }
+        }
+    }
+
+    /// the latest `limit` bars opened in `[from, to]`
+    pub fn query(
+        &self,
+        symbol: Symbol,
+        interval: Interval,
+        from: Option<u64>,
+        to: Option<u64>,
+        limit: usize,
+    ) -> Vec<Kline> {
+        let bars = match self.bars.get(&(symbol, interval)) {
+            Some(bars) => bars,
+            None => return vec![],
+        };
+        let mut r = bars
+            .iter()
+            .rev()
+            .filter(|b| from.map(|f| b.open_time >= f).unwrap_or(true))
+            .filter(|b| to.map(|t| b.open_time <= t).unwrap_or(true))
+            .take(limit)
+            .cloned()
+            .collect::<Vec<_>>();
+        r.reverse();
+        r
+    }
+
+    pub fn symbols(&self) -> Vec<Symbol> {
+        let mut symbols = self
+            .bars
+            .keys()
+            .filter(|(_, interval)| *interval == Interval::M1)
+            .map(|(symbol, _)| *symbol)
+            .collect::<Vec<_>>();
+        symbols.sort();
+        symbols
+    }
+
+    /// the 1m bars opened since `from` merged into one, `None` if no trades
+    pub fn rolling(&self, symbol: Symbol, from: u64) -> Option<Kline> {
+        self.bars
+            .get(&(symbol, Interval::M1))?
+            .iter()
+            .filter(|b| b.open_time >= from)
+            .fold(None, |merged: Option<Kline>, b| match merged {
+                Some(mut k) => {
+                    k.high = k.high.max(b.high);
+                    k.low = k.low.min(b.low);
+                    k.close = b.close;
+                    k.volume += b.volume;
+                    k.quote_volume += b.quote_volume;
+                    k.trades += b.trades;
+                    Some(k)
+                }
+                None => Some(b.clone()),
+            })
+    }
+
+    pub fn save(&self, path: &Path) -> anyhow::Result<()> {
+        let tmp = path.with_extension("tmp");
+        std::fs::write(&tmp, bincode::serialize(self)?)?;
+        std::fs::rename(&tmp, path)?;
+        Ok(())
+    }
+
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+        Ok(bincode::deserialize(&std::fs::read(path)?)?)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::orderbook::AskOrBid;
+    use rust_decimal::prelude::Zero;
+    use rust_decimal_macros::dec;
+
+    fn fill(event_id: u64, timestamp: u64, price: Price, filled: Amount) -> Output {
+        Output {
+            event_id,
+            order_id: 1,
+            user_id: UserId::zero(),
+            symbol: (1, 0),
+            state: OrderState::Filled,
+            role: Role::Maker,
+            ask_or_bid: AskOrBid::Ask,
+            price,
+            quote_charge: Amount::zero(),
+            quote_delta: price * filled,
+            quote_available: Amount::zero(),
+            quote_frozen: Amount::zero(),
+            base_charge: Amount::zero(),
+            base_delta: -filled,
+            base_available: Amount::zero(),
+            base_frozen: Amount::zero(),
+            timestamp,
+            timestamp_us: timestamp * 1_000_000,
+        }
+    }
+
+    #[test]
+    pub fn test_klines() {
+        let mut klines = Klines::default();
+        klines.update(&[
+            fill(1, 60, dec!(10), dec!(1)),
+            fill(1, 61, dec!(12), dec!(1)),
+        ]);
+        klines.update(&[fill(2, 119, dec!(9), dec!(2))]);
+        klines.update(&[fill(3, 120, dec!(11), dec!(1))]);
+        // replayed
+        klines.update(&[fill(3, 120, dec!(11), dec!(1))]);
+        // taker
+        let mut taker = fill(4, 121, dec!(100), dec!(1));
+        taker.role = Role::Taker;
+        klines.update(&[taker]);
+        let m1 = klines.query((1, 0), Interval::M1, None, None, 10);
+        assert_eq!(2, m1.len());
+        assert_eq!(
+            Kline {
+                open_time: 60,
+                open: dec!(10),
+                high: dec!(12),
+                low: dec!(9),
+                close: dec!(9),
+                volume: dec!(4),
+                quote_volume: dec!(40),
+                trades: 3,
+            },
+            m1[0]
+        );
+        assert_eq!(dec!(11), m1[1].open);
+        assert_eq!(1, m1[1].trades);
+        let m5 = klines.query((1, 0), Interval::M5, None, None, 10);
+        assert_eq!(1, m5.len());
+        assert_eq!(4, m5[0].trades);
+        assert_eq!(1, klines.query((1, 0), Interval::M1, None, None, 1).len());
+        assert_eq!(
+            120,
+            klines.query((1, 0), Interval::M1, Some(61), None, 10)[0].open_time
+        );
+        assert!(klines
+            .query((2, 0), Interval::M1, None, None, 10)
+            .is_empty());
+        assert_eq!(4, klines.last_event_id);
+        let rolling = klines.rolling((1, 0), 0).unwrap();
+        assert_eq!((dec!(10), dec!(11)), (rolling.open, rolling.close));
+        assert_eq!((dec!(12), dec!(9)), (rolling.high, rolling.low));
+        assert_eq!((dec!(5), 4), (rolling.volume, rolling.trades));
+        assert_eq!(dec!(11), klines.rolling((1, 0), 61).unwrap().high);
+        assert!(klines.rolling((1, 0), 121).is_none());
+
+        let dir = tempdir::TempDir::new("galois-klines").unwrap();
+        let path = dir.path().join("klines.bin");
+        klines.save(&path).unwrap();
+        let restored = Klines::load(&path).unwrap();
+        assert_eq!(m1, restored.query((1, 0), Interval::M1, None, None, 10));
+
+        // bucketed by the stamp of matching rather than the sequenced second
+        let mut late = fill(5, 179, dec!(8), dec!(1));
+        late.timestamp_us = 180 * 1_000_000 + 1;
+        klines.update(&[late]);
+        assert_eq!(
+            180,
+            klines.query((1, 0), Interval::M1, None, None, 1)[0].open_time
+        );
+    }
+}
@@ -12,19 +12,60 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use crate::{config::C, input::*, output::*};
-use std::sync::mpsc::{Receiver, Sender};
+use crate::{
+    config::C,
+    input::*,
+    latency::{self, Stage},
+    output::*,
+    ring,
+};
+use std::{
+    sync::mpsc::Sender,
+    time::{Duration, Instant},
+};
 
-type MarketChannel = Receiver<Vec<Output>>;
+type MarketChannel = ring::Consumer<(Vec<Output>, Instant)>;
 type ResponseChannel = Sender<(u64, Message)>;
 
-// TODO build kline
-pub fn init(rx: MarketChannel, _tx: ResponseChannel) {
-    std::thread::spawn(move || -> anyhow::Result<()> {
-        loop {
-            let _crs = rx.recv()?;
-            if C.dry_run.is_none() {}
+const FLUSH_INTERVAL: Duration = Duration::from_secs(60);
+
+/// the market thread returns once the executor stopped
+pub fn init(rx: MarketChannel, tx: ResponseChannel) -> std::thread::JoinHandle<anyhow::Result<()>> {
+    let path = std::path::PathBuf::from(C.server.get_klines_path());
+    match kline::Klines::load(&path) {
+        Ok(klines) => *kline::KLINES.write().unwrap() = klines,
+        Err(e) => log::error!("unable to load klines from {:?}, {:?}", path, e),
+    }
+    let handle = std::thread::spawn(move || -> anyhow::Result<()> {
+        #[cfg(feature = "parquet-export")]
+        let writers = export::Writers::start(&C.export)?;
+        let mut flushed = Instant::now();
+        while let Ok((crs, sent)) = rx.recv() {
+            latency::record_since(Stage::Publish, sent);
+            kline::KLINES.write().unwrap().update(&crs);
+            if C.dry_run.is_none() {
+                #[cfg(feature = "parquet-export")]
+                if let Err(e) = writers.send(crs) {
+                    log::error!("exporting outputs failed, {}", e);
+                }
+                if flushed.elapsed() >= FLUSH_INTERVAL {
+                    if let Err(e) = kline::KLINES.read().unwrap().save(&path) {
+                        log::error!("unable to save klines to {:?}, {:?}", path, e);
+                    }
+                    flushed = Instant::now();
+                }
+            }
+        }
+        #[cfg(feature = "parquet-export")]
+        writers.stop()?;
+        if C.dry_run.is_none() {
+            kline::KLINES.read().unwrap().save(&path)?;
         }
+        log::info!("market stopped");
+        Ok(())
     });
+    ticker::init(tx.clone());
+    imbalance::init(tx);
     log::info!("market initialized");
+    handle
 }
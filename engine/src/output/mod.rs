@@ -13,32 +13,21 @@
 // limitations under the License.
 
 use crate::core::*;
-use crate::executor::orderbook::Level;
+use crate::orderbook::{Level, Tape};
 use rust_decimal::{prelude::Zero, Decimal};
 use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
 
+pub mod audit;
+pub mod canonical;
+#[cfg(feature = "parquet-export")]
+pub mod export;
+pub mod imbalance;
+pub mod kline;
 pub mod market;
+pub mod ticker;
 
-#[derive(Debug, Clone)]
-pub struct Output {
-    pub event_id: u64,
-    pub order_id: u64,
-    pub user_id: UserId,
-    pub symbol: Symbol,
-    pub state: OrderState,
-    pub role: Role,
-    pub ask_or_bid: AskOrBid,
-    pub price: Price,
-    pub quote_charge: Amount,
-    pub quote_delta: Amount,
-    pub quote_available: Amount,
-    pub quote_frozen: Amount,
-    pub base_charge: Amount,
-    pub base_delta: Amount,
-    pub base_available: Amount,
-    pub base_frozen: Amount,
-    pub timestamp: u64,
-}
+pub use galois_core::clearing::Output;
 
 #[derive(Serialize, Deserialize, Debug, Clone, Eq, PartialEq)]
 pub struct Depth {
@@ -47,6 +36,20 @@ pub struct Depth {
     pub symbol: Symbol,
 }
 
+/// the public trade tick broadcasted as `TRADE_EXECUTED`, one for each maker filled
+#[derive(Serialize, Deserialize, Debug, Clone, Eq, PartialEq)]
+pub struct Trade {
+    pub event_id: u64,
+    pub symbol: Symbol,
+    pub price: Price,
+    pub amount: Amount,
+    pub taker_side: AskOrBid,
+    pub timestamp: u64,
+    /// the microseconds of matching, strictly increasing across the events
+    #[serde(default)]
+    pub timestamp_us: u64,
+}
+
 impl From<(Symbol, &OrderBook)> for Depth {
     fn from(orderbook: (Symbol, &OrderBook)) -> Self {
         let mut asks = Vec::<Level>::new();
@@ -70,3 +73,302 @@ impl From<(Symbol, &OrderBook)> for Depth {
         }
     }
 }
+
+impl Depth {
+    /// the best `limit` levels grouped at the multiples of `tick`, the asks are rounded up and the
+    /// bids down so a group never shows a better price than its orders
+    pub fn aggregate(&self, limit: Option<usize>, tick: Option<Price>) -> Self {
+        Self {
+            asks: group(&self.asks, limit, tick, Decimal::ceil),
+            bids: group(&self.bids, limit, tick, Decimal::floor),
+            symbol: self.symbol,
+        }
+    }
+}
+
+fn group(
+    levels: &[Level],
+    limit: Option<usize>,
+    tick: Option<Price>,
+    round: fn(&Decimal) -> Decimal,
+) -> Vec<Level> {
+    let limit = limit.unwrap_or(usize::MAX);
+    let mut grouped = Vec::<Level>::new();
+    for l in levels {
+        let price = tick.map_or(l.0, |t| round(&(l.0 / t)) * t);
+        let len = grouped.len();
+        match grouped.last_mut() {
+            // the levels are sorted from the best so the rounded are contiguous
+            Some(g) if g.0 == price => {
+                g.1 += l.1;
+                g.2 = l.2;
+            }
+            _ if len == limit => break,
+            _ => grouped.push((price, l.1, l.2)),
+        }
+    }
+    grouped
+}
+
+/// the full depth of a symbol as of `update_id`, the deltas following it are chained by
+/// `prev_update_id`
+#[derive(Serialize, Deserialize, Debug, Clone, Eq, PartialEq)]
+pub struct DepthSnapshot {
+    pub update_id: u64,
+    #[serde(flatten)]
+    pub depth: Depth,
+}
+
+/// the changed levels broadcasted as `DEPTH_DELTA`, a removed level has zero amount,
+/// `prev_update_id` is `None` if the levels are a full snapshot
+#[derive(Serialize, Deserialize, Debug, Clone, Eq, PartialEq)]
+pub struct DepthDelta {
+    pub symbol: Symbol,
+    pub update_id: u64,
+    pub prev_update_id: Option<u64>,
+    pub asks: Vec<(Price, Amount)>,
+    pub bids: Vec<(Price, Amount)>,
+}
+
+impl DepthDelta {
+    pub fn snapshot(snapshot: &DepthSnapshot) -> Self {
+        Self {
+            symbol: snapshot.depth.symbol,
+            update_id: snapshot.update_id,
+            prev_update_id: None,
+            asks: snapshot.depth.asks.iter().map(|l| (l.0, l.1)).collect(),
+            bids: snapshot.depth.bids.iter().map(|l| (l.0, l.1)).collect(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.prev_update_id.is_some() && self.asks.is_empty() && self.bids.is_empty()
+    }
+}
+
+impl Depth {
+    /// replace the levels by a snapshot or patch them by a delta, the totals are accumulated again
+    pub fn apply(&mut self, delta: &DepthDelta) {
+        let patch = |levels: &[Level], changed: &[(Price, Amount)], reversed: bool| {
+            let mut merged = match delta.prev_update_id {
+                Some(_) => levels.iter().map(|l| (l.0, l.1)).collect(),
+                None => BTreeMap::new(),
+            };
+            for (price, amount) in changed {
+                if amount.is_zero() {
+                    merged.remove(price);
+                } else {
+                    merged.insert(*price, *amount);
+                }
+            }
+            accumulate(merged, reversed)
+        };
+        self.asks = patch(&self.asks, &delta.asks, false);
+        self.bids = patch(&self.bids, &delta.bids, true);
+    }
+}
+
+fn accumulate(levels: BTreeMap<Price, Amount>, reversed: bool) -> Vec<Level> {
+    let mut total = Amount::zero();
+    let mut accumulate = |(price, amount): (Price, Amount)| {
+        total += amount;
+        (price, amount, total)
+    };
+    match reversed {
+        true => levels.into_iter().rev().map(&mut accumulate).collect(),
+        false => levels.into_iter().map(&mut accumulate).collect(),
+    }
+}
+
+/// the published levels of a symbol, patched at the changed prices only so publishing an order
+/// doesn't walk the whole book
+#[derive(Debug, Clone)]
+pub struct DepthBook {
+    pub update_id: u64,
+    asks: BTreeMap<Price, Amount>,
+    bids: BTreeMap<Price, Amount>,
+}
+
+impl DepthBook {
+    pub fn new(update_id: u64, depth: &Depth) -> Self {
+        let levels = |levels: &[Level]| {
+            levels
+                .iter()
+                .filter(|l| !l.1.is_zero())
+                .map(|l| (l.0, l.1))
+                .collect()
+        };
+        Self {
+            update_id,
+            asks: levels(&depth.asks),
+            bids: levels(&depth.bids),
+        }
+    }
+
+    /// read the levels at `prices` from the orderbook, the changed are returned as a delta
+    pub fn patch(
+        &mut self,
+        update_id: u64,
+        symbol: Symbol,
+        orderbook: &OrderBook,
+        prices: &BTreeSet<Price>,
+    ) -> DepthDelta {
+        let (base_scale, quote_scale) = (orderbook.base_scale, orderbook.quote_scale);
+        let patch = |levels: &mut BTreeMap<Price, Amount>, tape: &Tape| {
+            let mut changed = vec![];
+            for price in prices {
+                let level = tape
+                    .get(price)
+                    .map(|page| page.merge(base_scale, quote_scale, Amount::zero()))
+                    .filter(|l| !l.1.is_zero());
+                match (level, levels.get(price)) {
+                    (Some(l), Some(prev)) if l.1 == *prev => {}
+                    (Some(l), _) => {
+                        levels.insert(l.0, l.1);
+                        changed.push((l.0, l.1));
+                    }
+                    // keep the scale of the removed levels
+                    (None, Some(prev)) => {
+                        changed.push((*price, Amount::new(0, prev.scale())));
+                        levels.remove(price);
+                    }
+                    (None, None) => {}
+                }
+            }
+            changed
+        };
+        let delta = DepthDelta {
+            symbol,
+            update_id,
+            prev_update_id: Some(self.update_id),
+            asks: patch(&mut self.asks, &orderbook.asks),
+            bids: patch(&mut self.bids, &orderbook.bids),
+        };
+        if !delta.is_empty() {
+            self.update_id = update_id;
+        }
+        delta
+    }
+
+    /// the best `limit` levels of each side with the totals
+    pub fn snapshot(&self, symbol: Symbol, limit: Option<usize>) -> DepthSnapshot {
+        let limit = limit.unwrap_or(usize::MAX);
+        let take = |levels: Box<dyn Iterator<Item = (&Price, &Amount)> + '_>| {
+            let mut total = Amount::zero();
+            levels
+                .take(limit)
+                .map(|(p, a)| {
+                    total += a;
+                    (*p, *a, total)
+                })
+                .collect()
+        };
+        DepthSnapshot {
+            update_id: self.update_id,
+            depth: Depth {
+                asks: take(Box::new(self.asks.iter())),
+                bids: take(Box::new(self.bids.iter().rev())),
+                symbol,
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use rust_decimal_macros::dec;
+
+    #[test]
+    pub fn test_depth_delta() {
+        use crate::{matcher, orderbook::AskOrBid};
+        let mut orderbook = OrderBook::new(
+            2,
+            2,
+            dec!(0.001),
+            dec!(0.001),
+            dec!(0.001),
+            dec!(0.001),
+            1,
+            dec!(0.1),
+            dec!(0.1),
+            true,
+            true,
+        );
+        let (maker, taker) = (UserId::from_low_u64_be(1), UserId::from_low_u64_be(2));
+        matcher::execute_limit(&mut orderbook, maker, dec!(10), dec!(1), AskOrBid::Ask);
+        matcher::execute_limit(&mut orderbook, maker, dec!(11), dec!(2), AskOrBid::Ask);
+        matcher::execute_limit(&mut orderbook, maker, dec!(9), dec!(1), AskOrBid::Bid);
+        let mut depth = Depth::from(((1, 0), &orderbook));
+        let mut book = DepthBook::new(1, &depth);
+        let mut published = depth.clone();
+        published.apply(&DepthDelta::snapshot(&book.snapshot((1, 0), None)));
+        assert_eq!(depth, published);
+
+        let mr = matcher::execute_limit(&mut orderbook, taker, dec!(11), dec!(2), AskOrBid::Bid);
+        matcher::execute_limit(&mut orderbook, maker, dec!(12), dec!(2), AskOrBid::Ask);
+        let mut prices = mr.page_delta.keys().copied().collect::<BTreeSet<_>>();
+        prices.insert(dec!(12));
+        let delta = book.patch(3, (1, 0), &orderbook, &prices);
+        assert_eq!(Some(1), delta.prev_update_id);
+        assert_eq!(3, delta.update_id);
+        assert_eq!(
+            vec![
+                (dec!(10), dec!(0)),
+                (dec!(11), dec!(1)),
+                (dec!(12), dec!(2))
+            ],
+            delta.asks
+        );
+        assert!(delta.bids.is_empty());
+        assert_eq!(3, book.update_id);
+        assert!(book.patch(4, (1, 0), &orderbook, &prices).is_empty());
+        assert_eq!(3, book.update_id);
+
+        depth = Depth::from(((1, 0), &orderbook));
+        published.apply(&delta);
+        assert_eq!(depth, published);
+        assert_eq!(depth, book.snapshot((1, 0), None).depth);
+        let top = book.snapshot((1, 0), Some(1)).depth;
+        assert_eq!(vec![(dec!(11), dec!(1), dec!(1))], top.asks);
+        assert_eq!(vec![(dec!(9), dec!(1), dec!(1))], top.bids);
+    }
+
+    #[test]
+    pub fn test_depth_aggregate() {
+        let depth = Depth {
+            asks: vec![
+                (dec!(10.1), dec!(1), dec!(1)),
+                (dec!(10.5), dec!(2), dec!(3)),
+                (dec!(10.6), dec!(1), dec!(4)),
+                (dec!(12.0), dec!(3), dec!(7)),
+            ],
+            bids: vec![
+                (dec!(9.9), dec!(1), dec!(1)),
+                (dec!(9.5), dec!(2), dec!(3)),
+                (dec!(9.4), dec!(1), dec!(4)),
+            ],
+            symbol: (1, 0),
+        };
+        assert_eq!(depth, depth.aggregate(None, None));
+        let limited = depth.aggregate(Some(2), None);
+        assert_eq!(2, limited.asks.len());
+        assert_eq!(dec!(3), limited.asks[1].2);
+        let grouped = depth.aggregate(None, Some(dec!(0.5)));
+        assert_eq!(
+            vec![
+                (dec!(10.5), dec!(3), dec!(3)),
+                (dec!(11.0), dec!(1), dec!(4)),
+                (dec!(12.0), dec!(3), dec!(7)),
+            ],
+            grouped.asks
+        );
+        assert_eq!(
+            vec![(dec!(9.5), dec!(3), dec!(3)), (dec!(9.0), dec!(1), dec!(4))],
+            grouped.bids
+        );
+        let grouped = depth.aggregate(Some(1), Some(dec!(1)));
+        assert_eq!(vec![(dec!(11), dec!(4), dec!(4))], grouped.asks);
+        assert_eq!(vec![(dec!(9), dec!(4), dec!(4))], grouped.bids);
+    }
+}
@@ -0,0 +1,194 @@
+// Copyright 2021-2023 UINB Technologies Pte. Ltd.
+
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// 	http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::{
+    config::C,
+    core::*,
+    executor::MARKETS,
+    input::{self, Message},
+    output::{
+        canonical::{Canonical, Scales},
+        kline::{Kline, Klines, KLINES},
+    },
+};
+use rust_decimal::{prelude::Zero, Decimal};
+use serde::{Deserialize, Serialize};
+use std::{
+    sync::mpsc::Sender,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+/// the tickers are rolled over the 1m klines of the last 24 hours
+pub const WINDOW: u64 = 86400;
+
+const BROADCAST_INTERVAL: Duration = Duration::from_secs(3);
+
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub struct Ticker {
+    pub symbol: Symbol,
+    /// the first bar in the window
+    pub open_time: u64,
+    pub open: Price,
+    pub high: Price,
+    pub low: Price,
+    pub close: Price,
+    pub volume: Amount,
+    pub quote_volume: Amount,
+    pub trades: u64,
+    pub price_change: Price,
+    /// in percent, rounded to 2 decimals
+    pub price_change_percent: Decimal,
+}
+
+impl Ticker {
+    fn new(symbol: Symbol, k: Kline) -> Self {
+        let price_change = k.close - k.open;
+        let price_change_percent = if k.open.is_zero() {
+            Decimal::zero()
+        } else {
+            (price_change * Decimal::ONE_HUNDRED / k.open).round_dp(2)
+        };
+        Self {
+            symbol,
+            open_time: k.open_time,
+            open: k.open,
+            high: k.high,
+            low: k.low,
+            close: k.close,
+            volume: k.volume,
+            quote_volume: k.quote_volume,
+            trades: k.trades,
+            price_change,
+            price_change_percent,
+        }
+    }
+}
+
+impl Canonical for Ticker {
+    fn canonical(mut self, scales: &Scales) -> Self {
+        self.open = scales.price(self.open);
+        self.high = scales.price(self.high);
+        self.low = scales.price(self.low);
+        self.close = scales.price(self.close);
+        self.volume = scales.amount(self.volume);
+        self.quote_volume = scales.vol(self.quote_volume);
+        self.price_change = scales.price(self.price_change);
+        self
+    }
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+/// the symbols without trades in the window have no tickers
+pub fn rolling(klines: &Klines, symbol: Option<Symbol>, now: u64) -> Vec<Ticker> {
+    let from = now.saturating_sub(WINDOW);
+    let symbols = match symbol {
+        Some(symbol) => vec![symbol],
+        None => klines.symbols(),
+    };
+    symbols
+        .into_iter()
+        .filter_map(|s| klines.rolling(s, from).map(|k| Ticker::new(s, k)))
+        .map(|t| match MARKETS.get(&t.symbol) {
+            Some(m) => t.canonical(&Scales::new(m.base_scale, m.quote_scale)),
+            None => t,
+        })
+        .collect()
+}
+
+pub fn query(symbol: Option<Symbol>) -> Vec<Ticker> {
+    rolling(&KLINES.read().unwrap(), symbol, now())
+}
+
+/// broadcast the tickers of all symbols as `TICKER_UPDATED` periodically
+pub fn init(tx: Sender<(u64, Message)>) {
+    if C.dry_run.is_some() {
+        return;
+    }
+    std::thread::spawn(move || loop {
+        std::thread::sleep(BROADCAST_INTERVAL);
+        let tickers = query(None);
+        if tickers.is_empty() {
+            continue;
+        }
+        let msg = Message::new_broadcast(
+            input::TICKER_UPDATED,
+            serde_json::to_vec(&tickers).unwrap_or_default(),
+        );
+        if tx.send((0, msg)).is_err() {
+            break;
+        }
+    });
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{matcher::Role, orderbook::AskOrBid, output::Output};
+    use rust_decimal_macros::dec;
+
+    fn fill(event_id: u64, timestamp: u64, price: Price, filled: Amount) -> Output {
+        Output {
+            event_id,
+            order_id: 1,
+            user_id: UserId::zero(),
+            symbol: (1, 0),
+            state: OrderState::Filled,
+            role: Role::Maker,
+            ask_or_bid: AskOrBid::Ask,
+            price,
+            quote_charge: Amount::zero(),
+            quote_delta: price * filled,
+            quote_available: Amount::zero(),
+            quote_frozen: Amount::zero(),
+            base_charge: Amount::zero(),
+            base_delta: -filled,
+            base_available: Amount::zero(),
+            base_frozen: Amount::zero(),
+            timestamp,
+            timestamp_us: timestamp * 1_000_000,
+        }
+    }
+
+    #[test]
+    pub fn test_rolling_ticker() {
+        let mut klines = Klines::default();
+        klines.update(&[fill(1, 60, dec!(8), dec!(1))]);
+        klines.update(&[fill(2, 3600, dec!(10), dec!(1))]);
+        klines.update(&[fill(3, 7200, dec!(12), dec!(2))]);
+        klines.update(&[fill(4, 7260, dec!(11), dec!(1))]);
+        // the first trade is out of the window
+        let tickers = rolling(&klines, None, WINDOW + 120);
+        assert_eq!(1, tickers.len());
+        let t = &tickers[0];
+        assert_eq!(3600, t.open_time);
+        assert_eq!(
+            (dec!(10), dec!(12), dec!(10), dec!(11)),
+            (t.open, t.high, t.low, t.close)
+        );
+        assert_eq!((dec!(4), dec!(45), 3), (t.volume, t.quote_volume, t.trades));
+        assert_eq!(
+            (dec!(1), dec!(10)),
+            (t.price_change, t.price_change_percent)
+        );
+        assert_eq!(tickers, rolling(&klines, Some((1, 0)), WINDOW + 120));
+        assert!(rolling(&klines, Some((2, 0)), WINDOW + 120).is_empty());
+        assert!(rolling(&klines, None, WINDOW * 2 + 7200).is_empty());
+    }
+}
@@ -0,0 +1,95 @@
+// Copyright 2021-2023 UINB Technologies Pte. Ltd.
+
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// 	http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::{
+    config::ReplayCmd,
+    core::*,
+    executor::Replayer,
+    fusotao::{connector::FusoConnector, prover},
+    journal, sequencer, snapshot,
+};
+use anyhow::anyhow;
+use serde::Serialize;
+use std::{fs::File, path::Path};
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Report {
+    pub from: u64,
+    pub to: u64,
+    /// the last event replayed
+    pub executed: Option<u64>,
+    pub root: String,
+    pub onchain_sequence: u64,
+    pub onchain_root: String,
+    /// `None` unless replayed to the on-chain proving progress
+    pub matched: Option<bool>,
+    /// the first event whose root differs from the local proof
+    pub diverged_at: Option<u64>,
+}
+
+impl Report {
+    pub fn passed(&self) -> bool {
+        self.diverged_at.is_none() && self.matched != Some(false)
+    }
+}
+
+/// re-execute the saved events in `[from, to]` against the coredump, returns whether all roots matched
+pub fn run(c: ReplayCmd) -> anyhow::Result<bool> {
+    let mut data = Data::from_raw(File::open(&c.input_path)?)?;
+    let from = match c.from {
+        Some(from) => from,
+        None => {
+            snapshot::id_of(Path::new(&c.input_path))
+                .ok_or(anyhow!("unknown id of {}, specify `--from`", c.input_path))?
+                + 1
+        }
+    };
+    let dominator = FusoConnector::new()?.get_dominator()?;
+    let to = c.to.unwrap_or(dominator.sequence.0);
+    log::info!("replaying events {}-{} against {}", from, to, c.input_path);
+    let mut replayer = Replayer::new();
+    let (mut executed, mut diverged_at) = (None, None);
+    // the journal keeps the events removed from the sequence storage after the checkpoints
+    let events: Box<dyn Iterator<Item = anyhow::Result<(u64, Vec<u8>)>>> = match c.journal {
+        Some(ref dir) => Box::new(journal::read_from(dir, from)?),
+        None => Box::new(sequencer::fetch_raw_from(from)),
+    };
+    for item in events {
+        let (id, cmd) = item?;
+        let event = sequencer::to_event(id, &cmd)?;
+        if id > to {
+            break;
+        }
+        replayer.execute(event, &mut data)?;
+        executed = Some(id);
+        let root: [u8; 32] = data.merkle_tree.root().clone().into();
+        if diverged_at.is_none() && prover::root_at(id).map_or(false, |r| r != root) {
+            log::warn!("the root of event {} differs from the local proof", id);
+            diverged_at = Some(id);
+        }
+    }
+    let root: [u8; 32] = data.merkle_tree.root().clone().into();
+    let report = Report {
+        from,
+        to,
+        executed,
+        root: format!("0x{}", hex::encode(root)),
+        onchain_sequence: dominator.sequence.0,
+        onchain_root: format!("0x{}", hex::encode(dominator.merkle_root)),
+        matched: (to == dominator.sequence.0).then_some(root == dominator.merkle_root),
+        diverged_at,
+    };
+    println!("{}", serde_json::to_string_pretty(&report)?);
+    Ok(report.passed())
+}
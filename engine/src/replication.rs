@@ -0,0 +1,214 @@
+// Copyright 2021-2023 UINB Technologies Pte. Ltd.
+
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// 	http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::{config::C, core::*, executor::Replayer, nonce, sequencer, snapshot};
+use std::{
+    io::{BufReader, BufWriter, Read, Write},
+    net::{TcpListener, TcpStream, ToSocketAddrs},
+    time::{Duration, Instant},
+};
+
+const POLL_INTERVAL: Duration = Duration::from_millis(5);
+
+const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(1);
+
+/// the sequence starts from 1, so the frames of id 0 are heartbeats
+const HEARTBEAT: u64 = 0;
+
+/// `<id: u64be><prev: u64be><len: u32be><the saved command>`, nothing is saved between `prev`
+/// and `id`; `prev` of the heartbeats is the last id sent
+fn write_frame(w: &mut impl Write, id: u64, prev: u64, cmd: &[u8]) -> std::io::Result<()> {
+    w.write_all(&id.to_be_bytes())?;
+    w.write_all(&prev.to_be_bytes())?;
+    w.write_all(&(cmd.len() as u32).to_be_bytes())?;
+    w.write_all(cmd)
+}
+
+fn read_frame(r: &mut impl Read) -> std::io::Result<(u64, u64, Vec<u8>)> {
+    let mut head = [0u8; 20];
+    r.read_exact(&mut head)?;
+    let id = u64::from_be_bytes(head[..8].try_into().expect("8 bytes;qed"));
+    let prev = u64::from_be_bytes(head[8..16].try_into().expect("8 bytes;qed"));
+    let len = u32::from_be_bytes(head[16..].try_into().expect("4 bytes;qed"));
+    let mut cmd = vec![0u8; len as usize];
+    r.read_exact(&mut cmd)?;
+    Ok((id, prev, cmd))
+}
+
+/// serve the standbys if `replication.bind_addr` is set
+pub fn init() {
+    let addr = match C.replication.as_ref().and_then(|r| r.bind_addr.clone()) {
+        Some(addr) if C.dry_run.is_none() => addr,
+        _ => return,
+    };
+    let listener = TcpListener::bind(&addr).unwrap();
+    log::info!("serving standbys on {}", addr);
+    std::thread::spawn(move || {
+        for stream in listener.incoming() {
+            match stream {
+                Ok(stream) => {
+                    std::thread::spawn(move || {
+                        let peer = stream.peer_addr();
+                        log::info!("standby {:?} disconnected, {:?}", peer, serve(stream));
+                    });
+                }
+                Err(e) => log::error!("accepting standby failed, {:?}", e),
+            }
+        }
+    });
+}
+
+/// stream the saved events from the id requested by the standby, which must not be pruned yet
+fn serve(mut stream: TcpStream) -> anyhow::Result<()> {
+    stream.set_nodelay(true)?;
+    let mut head = [0u8; 8];
+    stream.read_exact(&mut head)?;
+    let mut next = u64::from_be_bytes(head);
+    if let Some(pruned) = snapshot::latest_id().filter(|id| *id > next) {
+        // the standby should be restarted from a copy of the snapshot
+        let _ = write_frame(&mut stream, HEARTBEAT, 0, b"pruned");
+        anyhow::bail!(
+            "events before {} are pruned, the standby requests {}",
+            pruned,
+            next
+        );
+    }
+    log::info!("standby {} following from {}", stream.peer_addr()?, next);
+    let mut writer = BufWriter::new(stream);
+    let mut last_sent = Instant::now();
+    // the standby has the events before the requested one
+    let mut prev = next.saturating_sub(1);
+    loop {
+        let mut idle = true;
+        for item in sequencer::fetch_raw_from(next) {
+            let (id, cmd) = item?;
+            write_frame(&mut writer, id, prev, &cmd)?;
+            next = id + 1;
+            prev = id;
+            idle = false;
+        }
+        if !idle {
+            writer.flush()?;
+            last_sent = Instant::now();
+        } else if last_sent.elapsed() >= HEARTBEAT_INTERVAL {
+            write_frame(&mut writer, HEARTBEAT, prev, &[])?;
+            writer.flush()?;
+            last_sent = Instant::now();
+        } else {
+            std::thread::sleep(POLL_INTERVAL);
+        }
+    }
+}
+
+/// apply the events from the primary if `replication.primary_addr` is set, returns the id(not
+/// executed yet) and the state after the primary is unreachable for `takeover_timeout`
+pub fn follow(mut next: u64, mut data: Data) -> (u64, Data) {
+    let (addr, timeout) = match C.replication.as_ref() {
+        Some(r) if r.primary_addr.is_some() && C.dry_run.is_none() => (
+            r.primary_addr.clone().expect("checked;qed"),
+            Duration::from_secs(r.takeover_timeout),
+        ),
+        _ => return (next, data),
+    };
+    let mut replayer = Replayer::new();
+    let mut last_seen = Instant::now();
+    loop {
+        let r = replicate(
+            &addr,
+            timeout,
+            &mut next,
+            &mut data,
+            &mut replayer,
+            &mut last_seen,
+        );
+        log::warn!("replication from {} interrupted at {}, {:?}", addr, next, r);
+        if last_seen.elapsed() >= timeout {
+            log::warn!("primary {} is unreachable, taking over from {}", addr, next);
+            return (next, data);
+        }
+        std::thread::sleep(POLL_INTERVAL.max(timeout / 10));
+    }
+}
+
+fn replicate(
+    addr: &str,
+    timeout: Duration,
+    next: &mut u64,
+    data: &mut Data,
+    replayer: &mut Replayer,
+    last_seen: &mut Instant,
+) -> anyhow::Result<()> {
+    let addr = addr
+        .to_socket_addrs()?
+        .next()
+        .ok_or(anyhow::anyhow!("unresolved address {}", addr))?;
+    let mut stream = TcpStream::connect_timeout(&addr, timeout)?;
+    stream.set_read_timeout(Some(timeout))?;
+    stream.write_all(&next.to_be_bytes())?;
+    *last_seen = Instant::now();
+    log::info!("following primary {} from {}", addr, next);
+    let mut reader = BufReader::new(stream);
+    let config = C.replication.as_ref().expect("following;qed");
+    // the missing events are requested again by reconnecting
+    let mut reorder = sequencer::Reorder::new(
+        *next,
+        config.reorder_window,
+        Duration::from_secs(config.gap_timeout),
+    );
+    loop {
+        let (id, prev, cmd) = read_frame(&mut reader)?;
+        *last_seen = Instant::now();
+        if id == HEARTBEAT {
+            // never take over with the stale state
+            assert!(
+                cmd.is_empty(),
+                "rejected by the primary {}, {}",
+                addr,
+                String::from_utf8_lossy(&cmd)
+            );
+            reorder.heartbeat(prev)?;
+            continue;
+        }
+        for (id, cmd) in reorder.push(prev, id, cmd)? {
+            let event = sequencer::to_event(id, &cmd)?;
+            let nonces = nonce::advance(&event)?.unwrap_or_default();
+            sequencer::save_signed(id, cmd, &nonces)?;
+            replayer.execute(event, data)?;
+            *next = id + 1;
+            if id % C.sequence.checkpoint == 0 {
+                snapshot::dump(id, data);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    pub fn test_frame() {
+        let mut buf = vec![];
+        write_frame(&mut buf, 7, 5, br#"{"cmd":12}"#).unwrap();
+        write_frame(&mut buf, HEARTBEAT, 7, &[]).unwrap();
+        assert_eq!(20 + 10 + 20, buf.len());
+        let mut r = buf.as_slice();
+        assert_eq!(
+            (7, 5, br#"{"cmd":12}"#.to_vec()),
+            read_frame(&mut r).unwrap()
+        );
+        assert_eq!((HEARTBEAT, 7, vec![]), read_frame(&mut r).unwrap());
+        assert!(read_frame(&mut r).is_err());
+    }
+}
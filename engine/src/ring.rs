@@ -0,0 +1,333 @@
+// Copyright 2021-2023 UINB Technologies Pte. Ltd.
+
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// 	http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use serde::Serialize;
+use std::{
+    cell::UnsafeCell,
+    mem::MaybeUninit,
+    sync::{
+        atomic::{fence, AtomicBool, AtomicU64, AtomicUsize, Ordering},
+        mpsc::{RecvError, RecvTimeoutError, SendError, TryRecvError},
+        Arc, Mutex, OnceLock, Weak,
+    },
+    thread::{self, Thread},
+    time::{Duration, Instant},
+};
+
+lazy_static::lazy_static! {
+    static ref RINGS: Mutex<Vec<Weak<Cursors>>> = Mutex::new(vec![]);
+}
+
+const SPIN_LIMIT: u32 = 64;
+const YIELD_LIMIT: u32 = 128;
+const PARK_TIMEOUT: Duration = Duration::from_millis(1);
+
+/// avoid the false sharing between the producer and the consumer
+#[repr(align(64))]
+struct Padded<T>(T);
+
+struct Cursors {
+    name: &'static str,
+    capacity: usize,
+    // the next slot to read, written by the consumer only
+    head: Padded<AtomicUsize>,
+    // the next slot to write, written by the producer only
+    tail: Padded<AtomicUsize>,
+    high_watermark: AtomicUsize,
+    stalls: AtomicU64,
+    stalled_micros: AtomicU64,
+}
+
+struct Ring<T> {
+    slots: Box<[UnsafeCell<MaybeUninit<T>>]>,
+    cursors: Arc<Cursors>,
+    // either side is dropped
+    closed: AtomicBool,
+    parked: AtomicBool,
+    consumer: OnceLock<Thread>,
+}
+
+impl<T> Ring<T> {
+    fn len(&self) -> usize {
+        self.cursors.tail.0.load(Ordering::Acquire) - self.cursors.head.0.load(Ordering::Acquire)
+    }
+
+    fn wake(&self) {
+        fence(Ordering::SeqCst);
+        if self.parked.load(Ordering::SeqCst) {
+            if let Some(t) = self.consumer.get() {
+                t.unpark();
+            }
+        }
+    }
+}
+
+impl<T> Drop for Ring<T> {
+    fn drop(&mut self) {
+        let head = self.cursors.head.0.load(Ordering::Acquire);
+        let tail = self.cursors.tail.0.load(Ordering::Acquire);
+        for i in head..tail {
+            unsafe {
+                self.slots[i & (self.slots.len() - 1)]
+                    .get_mut()
+                    .assume_init_drop()
+            };
+        }
+    }
+}
+
+/// the sending half, blocks while the ring is full
+pub struct Producer<T>(Arc<Ring<T>>);
+
+/// the receiving half
+pub struct Consumer<T>(Arc<Ring<T>>);
+
+// the slots are only written by the producer before publishing and read by the consumer after
+unsafe impl<T: Send> Send for Producer<T> {}
+unsafe impl<T: Send> Send for Consumer<T> {}
+
+/// the backpressure of a named ring, `stalls` counts the sends waiting on the full ring
+#[derive(Clone, Debug, Serialize)]
+pub struct RingStats {
+    pub name: &'static str,
+    pub capacity: usize,
+    pub len: usize,
+    pub high_watermark: usize,
+    pub stalls: u64,
+    pub stalled_micros: u64,
+}
+
+/// a bounded single-producer single-consumer channel, the capacity is rounded up to a power of 2
+pub fn channel<T>(capacity: usize) -> (Producer<T>, Consumer<T>) {
+    let (producer, consumer, _) = new(capacity, "");
+    (producer, consumer)
+}
+
+/// the channel reported by `stats`
+pub fn named<T>(name: &'static str, capacity: usize) -> (Producer<T>, Consumer<T>) {
+    let (producer, consumer, cursors) = new(capacity, name);
+    RINGS.lock().unwrap().push(Arc::downgrade(&cursors));
+    (producer, consumer)
+}
+
+fn new<T>(capacity: usize, name: &'static str) -> (Producer<T>, Consumer<T>, Arc<Cursors>) {
+    let capacity = capacity.max(1).next_power_of_two();
+    let cursors = Arc::new(Cursors {
+        name,
+        capacity,
+        head: Padded(AtomicUsize::new(0)),
+        tail: Padded(AtomicUsize::new(0)),
+        high_watermark: AtomicUsize::new(0),
+        stalls: AtomicU64::new(0),
+        stalled_micros: AtomicU64::new(0),
+    });
+    let ring = Arc::new(Ring {
+        slots: (0..capacity)
+            .map(|_| UnsafeCell::new(MaybeUninit::uninit()))
+            .collect(),
+        cursors: cursors.clone(),
+        closed: AtomicBool::new(false),
+        parked: AtomicBool::new(false),
+        consumer: OnceLock::new(),
+    });
+    (Producer(ring.clone()), Consumer(ring), cursors)
+}
+
+/// the living named rings
+pub fn stats() -> Vec<RingStats> {
+    let mut rings = RINGS.lock().unwrap();
+    rings.retain(|r| r.strong_count() > 0);
+    rings
+        .iter()
+        .filter_map(|r| r.upgrade())
+        .map(|c| RingStats {
+            name: c.name,
+            capacity: c.capacity,
+            len: c.tail.0.load(Ordering::Relaxed) - c.head.0.load(Ordering::Relaxed),
+            high_watermark: c.high_watermark.load(Ordering::Relaxed),
+            stalls: c.stalls.load(Ordering::Relaxed),
+            stalled_micros: c.stalled_micros.load(Ordering::Relaxed),
+        })
+        .collect()
+}
+
+fn backoff(step: &mut u32) {
+    if *step < SPIN_LIMIT {
+        std::hint::spin_loop();
+    } else if *step < YIELD_LIMIT {
+        thread::yield_now();
+    } else {
+        thread::sleep(Duration::from_micros(50));
+    }
+    *step = step.saturating_add(1);
+}
+
+impl<T> Producer<T> {
+    pub fn send(&self, v: T) -> Result<(), SendError<T>> {
+        let ring = &self.0;
+        let cursors = &ring.cursors;
+        let tail = cursors.tail.0.load(Ordering::Relaxed);
+        let (mut step, mut stalled) = (0, None);
+        while tail - cursors.head.0.load(Ordering::Acquire) == cursors.capacity {
+            if ring.closed.load(Ordering::Acquire) {
+                return Err(SendError(v));
+            }
+            stalled.get_or_insert_with(Instant::now);
+            ring.wake();
+            backoff(&mut step);
+        }
+        if ring.closed.load(Ordering::Acquire) {
+            return Err(SendError(v));
+        }
+        unsafe { (*ring.slots[tail & (cursors.capacity - 1)].get()).write(v) };
+        cursors.tail.0.store(tail + 1, Ordering::Release);
+        if let Some(t) = stalled {
+            cursors.stalls.fetch_add(1, Ordering::Relaxed);
+            cursors
+                .stalled_micros
+                .fetch_add(t.elapsed().as_micros() as u64, Ordering::Relaxed);
+        }
+        let len = tail + 1 - cursors.head.0.load(Ordering::Relaxed);
+        if len > cursors.high_watermark.load(Ordering::Relaxed) {
+            cursors.high_watermark.store(len, Ordering::Relaxed);
+        }
+        ring.wake();
+        Ok(())
+    }
+}
+
+impl<T> Drop for Producer<T> {
+    fn drop(&mut self) {
+        self.0.closed.store(true, Ordering::Release);
+        self.0.wake();
+    }
+}
+
+impl<T> Consumer<T> {
+    pub fn try_recv(&self) -> Result<T, TryRecvError> {
+        let ring = &self.0;
+        let cursors = &ring.cursors;
+        let head = cursors.head.0.load(Ordering::Relaxed);
+        if head == cursors.tail.0.load(Ordering::Acquire) {
+            // the producer publishes the last one before closing
+            return if ring.closed.load(Ordering::Acquire)
+                && head == cursors.tail.0.load(Ordering::Acquire)
+            {
+                Err(TryRecvError::Disconnected)
+            } else {
+                Err(TryRecvError::Empty)
+            };
+        }
+        let v = unsafe { (*ring.slots[head & (cursors.capacity - 1)].get()).assume_init_read() };
+        cursors.head.0.store(head + 1, Ordering::Release);
+        Ok(v)
+    }
+
+    /// spin, yield and park in turn while the ring is empty
+    pub fn recv(&self) -> Result<T, RecvError> {
+        self.recv_until(None).map_err(|_| RecvError)
+    }
+
+    pub fn recv_timeout(&self, timeout: Duration) -> Result<T, RecvTimeoutError> {
+        self.recv_until(Some(Instant::now() + timeout))
+    }
+
+    fn recv_until(&self, deadline: Option<Instant>) -> Result<T, RecvTimeoutError> {
+        let ring = &self.0;
+        let mut step = 0;
+        loop {
+            match self.try_recv() {
+                Ok(v) => return Ok(v),
+                Err(TryRecvError::Disconnected) => return Err(RecvTimeoutError::Disconnected),
+                Err(TryRecvError::Empty) if deadline.is_some_and(|d| Instant::now() >= d) => {
+                    return Err(RecvTimeoutError::Timeout)
+                }
+                Err(TryRecvError::Empty) if step < YIELD_LIMIT => backoff(&mut step),
+                Err(TryRecvError::Empty) => {
+                    ring.consumer.get_or_init(thread::current);
+                    ring.parked.store(true, Ordering::SeqCst);
+                    fence(Ordering::SeqCst);
+                    if ring.len() == 0 && !ring.closed.load(Ordering::SeqCst) {
+                        thread::park_timeout(PARK_TIMEOUT);
+                    }
+                    ring.parked.store(false, Ordering::SeqCst);
+                }
+            }
+        }
+    }
+
+    pub fn try_iter(&self) -> impl Iterator<Item = T> + '_ {
+        std::iter::from_fn(|| self.try_recv().ok())
+    }
+}
+
+impl<T> Drop for Consumer<T> {
+    fn drop(&mut self) {
+        self.0.closed.store(true, Ordering::Release);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    pub fn test_ring() {
+        let (tx, rx) = named::<Arc<u64>>("test", 3);
+        let s = stats().into_iter().find(|s| s.name == "test").unwrap();
+        assert_eq!(4, s.capacity);
+        let handle = thread::spawn(move || {
+            for i in 0..10000u64 {
+                tx.send(Arc::new(i)).unwrap();
+            }
+        });
+        for i in 0..10000u64 {
+            assert_eq!(i, *rx.recv().unwrap());
+        }
+        handle.join().unwrap();
+        assert!(matches!(rx.try_recv(), Err(TryRecvError::Disconnected)));
+        assert!(rx.recv().is_err());
+        let s = stats().into_iter().find(|s| s.name == "test").unwrap();
+        assert_eq!(0, s.len);
+        assert!(s.high_watermark <= 4);
+        drop(rx);
+        assert!(stats().iter().all(|s| s.name != "test"));
+
+        // the remaining are dropped with the ring
+        let v = Arc::new(0);
+        let (tx, rx) = channel(2);
+        tx.send(v.clone()).unwrap();
+        tx.send(v.clone()).unwrap();
+        drop(rx.try_recv().unwrap());
+        assert_eq!(2, Arc::strong_count(&v));
+        drop(rx);
+        assert!(tx.send(v.clone()).is_err());
+        drop(tx);
+        assert_eq!(1, Arc::strong_count(&v));
+
+        let (tx, rx) = channel(2);
+        let timeout = Duration::from_millis(5);
+        assert!(matches!(
+            rx.recv_timeout(timeout),
+            Err(RecvTimeoutError::Timeout)
+        ));
+        tx.send(1u64).unwrap();
+        assert_eq!(1, rx.recv_timeout(timeout).unwrap());
+        drop(tx);
+        assert!(matches!(
+            rx.recv_timeout(timeout),
+            Err(RecvTimeoutError::Disconnected)
+        ));
+    }
+}
@@ -12,16 +12,74 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use crate::{cmd::*, core::*, fusotao::*, Command};
-use serde_json::{json, to_vec};
+use crate::{
+    archive,
+    cmd::*,
+    config::C,
+    core::*,
+    flow,
+    fusotao::*,
+    history,
+    input::encoding::Encoding,
+    output::{
+        canonical::{Canonical, Scales},
+        imbalance, kline, ticker,
+    },
+    replica, statement, Command, MARKETS,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
 use std::str::FromStr;
-use std::sync::Arc;
+use std::sync::{Arc, RwLock};
+
+const DEFAULT_KLINES: usize = 500;
+
+/// the markets listed for bootstrapping the clients, compatible with `OffchainSymbol`
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct MarketInfo {
+    #[serde(flatten)]
+    pub symbol: OffchainSymbol,
+    /// `None` if the market isn't registered on chain
+    pub onchain: Option<MarketStatus>,
+    /// `None` if the orderbook hasn't been created yet
+    pub orderbook: Option<history::SymbolConfig>,
+    /// e.g. `"BTC/USDT"`, `None` unless both the currencies are named
+    #[serde(default)]
+    pub name: Option<String>,
+    #[serde(default)]
+    pub base_name: Option<String>,
+    #[serde(default)]
+    pub quote_name: Option<String>,
+}
+
+impl MarketInfo {
+    fn new(
+        symbol: OffchainSymbol,
+        onchain: Option<MarketStatus>,
+        orderbook: Option<history::SymbolConfig>,
+    ) -> Self {
+        let base_name = token_name(symbol.symbol.0);
+        let quote_name = token_name(symbol.symbol.1);
+        Self {
+            symbol,
+            onchain,
+            orderbook,
+            name: base_name
+                .as_ref()
+                .zip(quote_name.as_ref())
+                .map(|(b, q)| format!("{}/{}", b, q)),
+            base_name,
+            quote_name,
+        }
+    }
+}
 
 /// Serve the sidechar, for some requests needn't to be put into the executor
 #[derive(Clone, Debug)]
 pub struct Shared {
     pub fuso_state: Arc<FusoState>,
-    pub x25519_priv: String,
+    // rotated through the admin socket
+    pub x25519_priv: Arc<RwLock<String>>,
 }
 
 unsafe impl Send for Shared {}
@@ -29,65 +87,252 @@ unsafe impl Sync for Shared {}
 
 impl Shared {
     pub fn new(fuso_state: Arc<FusoState>, x25519_priv: String) -> Self {
+        if let Ok(public) = crate::input::cipher::public_of(&x25519_priv) {
+            log::info!("x25519 public key of the sessions: {}", public);
+        }
         Self {
             fuso_state,
-            x25519_priv,
+            x25519_priv: Arc::new(RwLock::new(x25519_priv)),
         }
     }
 
+    /// the sessions established before keep the old key until reconnecting
+    pub fn rotate_x25519(&self, x25519_priv: String) {
+        *self.x25519_priv.write().unwrap() = x25519_priv;
+    }
+
     /// query scanning and proving progress
-    fn query_progress(&self) -> Vec<u8> {
-        let ans = json!({
+    fn query_progress(&self) -> serde_json::Value {
+        json!({
             "proving_progress": self.fuso_state.get_proving_progress(),
             "scanning_progress": self.fuso_state.get_scanning_progress(),
             "chain_height": self.fuso_state.get_chain_height(),
-        });
-        to_vec(&ans).unwrap()
+            "blocks_behind": self.fuso_state.get_blocks_behind(),
+            "submission": committer::stats(&self.fuso_state),
+        })
     }
 
-    /// this is for helping to reject invalid orders, not for providing human-readable information
-    /// NOTE: this is a heavy operation because we have to clone the map to avoid potential deadlock
-    fn query_open_markets(&self) -> Vec<u8> {
+    /// the markets either registered on chain or created by the operators
+    /// NOTE: this is a heavy operation because we have to clone the maps to avoid potential deadlock
+    fn query_open_markets(&self, standalone: bool) -> Vec<MarketInfo> {
         let symbols = self.fuso_state.symbols.clone();
-        let open = symbols
+        let orderbooks = MARKETS.clone();
+        let mut markets = symbols
             .iter()
-            .map(|r| (r.key().clone(), r.value().clone()).into())
-            .collect::<Vec<OffchainSymbol>>();
-        to_vec(&open).expect("jsonser;qed")
+            .map(|r| {
+                MarketInfo::new(
+                    (*r.key(), r.value().clone()).into(),
+                    Some(r.value().status.clone()),
+                    orderbooks.get(r.key()).map(|c| c.value().clone()),
+                )
+            })
+            .collect::<Vec<_>>();
+        markets.extend(
+            orderbooks
+                .iter()
+                .filter(|r| !symbols.contains_key(r.key()))
+                .map(|r| {
+                    MarketInfo::new(
+                        OffchainSymbol {
+                            symbol: *r.key(),
+                            min_base: r.min_amount,
+                            base_scale: r.base_scale as u8,
+                            quote_scale: r.quote_scale as u8,
+                            // nothing is on chain in the standalone mode
+                            open: standalone && r.open,
+                        },
+                        None,
+                        Some(r.value().clone()),
+                    )
+                }),
+        );
+        markets.sort_by_key(|m| m.symbol.symbol);
+        markets
     }
 
-    /// retrieve the x25519 private key
-    fn get_x25519_key(&self) -> Vec<u8> {
-        to_vec(&json!({ "x25519": self.x25519_priv })).expect("jsonser;qed")
+    /// the x25519 public key pinned by the clients, the private key never leaves the engine
+    fn get_x25519_key(&self) -> anyhow::Result<serde_json::Value> {
+        let public = crate::input::cipher::public_of(&self.x25519_priv.read().unwrap())?;
+        Ok(json!({ "x25519_pub": public }))
     }
 
     /// get the broker nonce
-    fn get_nonce_for_broker(&self, broker: &UserId) -> Vec<u8> {
-        let p = if self.fuso_state.brokers.contains_key(broker) {
+    fn get_nonce_for_broker(&self, broker: &UserId) -> serde_json::Value {
+        if self.fuso_state.brokers.contains_key(broker) {
             json!({"nonce": self.fuso_state.get_chain_height()})
         } else {
             json!({"nonce": -1})
-        };
-        to_vec(&p).expect("jsonser;qed")
+        }
+    }
+
+    fn dominator_to_json(d: &Dominator) -> serde_json::Value {
+        json!({
+            "start_from": d.start_from,
+            "sequence": d.sequence.0,
+            "sequence_block": d.sequence.1,
+            "merkle_root": format!("0x{}", hex::encode(d.merkle_root)),
+            "status": d.status,
+        })
+    }
+
+    /// the dominator states and the pending reset if any, only through the admin socket
+    pub fn query_dominator(&self) -> serde_json::Value {
+        let reset = self.fuso_state.reset.read().unwrap().clone();
+        json!({
+            "dominator": Self::dominator_to_json(&self.fuso_state.dominator.read().unwrap()),
+            "reset": reset.as_ref().map(Self::dominator_to_json),
+            "proving_progress": self.fuso_state.get_proving_progress(),
+        })
+    }
+
+    fn query_klines(&self, cmd: &Command) -> anyhow::Result<Vec<kline::Kline>> {
+        let symbol = cmd.symbol().ok_or(anyhow::anyhow!("symbol is required"))?;
+        let interval = cmd
+            .interval
+            .as_deref()
+            .ok_or(anyhow::anyhow!("interval is required"))?
+            .parse()?;
+        let limit = cmd
+            .limit
+            .map(|l| l as usize)
+            .unwrap_or(DEFAULT_KLINES)
+            .min(kline::MAX_BARS);
+        let klines = kline::KLINES
+            .read()
+            .unwrap()
+            .query(symbol, interval, cmd.from, cmd.to, limit);
+        Ok(match self.fuso_state.symbols.get(&symbol) {
+            Some(s) => {
+                klines.canonical(&Scales::new(s.base_scale as Scale, s.quote_scale as Scale))
+            }
+            None => klines,
+        })
+    }
+
+    /// the signed flow of a broker during an epoch, the current one if absent
+    fn query_broker_flow(&self, cmd: &Command) -> anyhow::Result<flow::BrokerSettlement> {
+        let broker = cmd
+            .user_id
+            .as_ref()
+            .ok_or(anyhow::anyhow!("broker is required"))
+            .and_then(|b| UserId::from_str(b))?;
+        let epoch = cmd.epoch.ok_or(anyhow::anyhow!("epoch is required"))?;
+        let signature = cmd
+            .signature
+            .as_ref()
+            .ok_or(anyhow::anyhow!("signature of the broker is required"))?;
+        flow::verify_query(&broker, epoch, signature)?;
+        let flows = flow::query(&broker, epoch)?;
+        signer::Signer::from_config()?.settle(&broker, epoch, flows)
+    }
+
+    /// the closed orders of a user in `[from, to]`, resumed from `cursor`
+    fn query_order_history(&self, cmd: &Command) -> anyhow::Result<archive::OrderHistoryPage> {
+        let symbol = cmd.symbol().ok_or(anyhow::anyhow!("symbol is required"))?;
+        let user_id = cmd
+            .user_id
+            .as_ref()
+            .ok_or(anyhow::anyhow!("user_id is required"))
+            .and_then(|u| UserId::from_str(u))?;
+        let limit = cmd
+            .limit
+            .map(|l| l as usize)
+            .unwrap_or(archive::DEFAULT_ORDER_HISTORY)
+            .min(archive::MAX_ORDER_HISTORY);
+        let page = archive::query(
+            &OUTPUT_STORE,
+            &user_id,
+            &symbol,
+            cmd.from,
+            cmd.to,
+            cmd.cursor.as_deref(),
+            limit,
+        )?;
+        Ok(match self.fuso_state.symbols.get(&symbol) {
+            Some(s) => page.canonical(&Scales::new(s.base_scale as Scale, s.quote_scale as Scale)),
+            None => page,
+        })
     }
 
-    pub fn handle_req(&self, cmd: &Command) -> anyhow::Result<Vec<u8>> {
+    /// the balance changes of a user in `[from, to]`, resumed from `cursor`
+    fn query_statement(&self, cmd: &Command) -> anyhow::Result<statement::StatementPage> {
+        let user_id = cmd
+            .user_id
+            .as_ref()
+            .ok_or(anyhow::anyhow!("user_id is required"))
+            .and_then(|u| UserId::from_str(u))?;
+        let limit = cmd
+            .limit
+            .map(|l| l as usize)
+            .unwrap_or(statement::DEFAULT_STATEMENT_ENTRIES)
+            .min(statement::MAX_STATEMENT_ENTRIES);
+        let page = statement::query(
+            &OUTPUT_STORE,
+            &user_id,
+            cmd.from,
+            cmd.to,
+            cmd.cursor.as_deref(),
+            limit,
+        )?;
+        Ok(page.canonical())
+    }
+
+    /// the balances and orders in the replica, lagging behind the executor
+    fn query_replica(&self, cmd: &Command, encoding: Encoding) -> anyhow::Result<Vec<u8>> {
+        let replica = replica::get().ok_or(anyhow::anyhow!("the replica is disabled"))?;
+        match cmd.cmd {
+            QUERY_BALANCE => {
+                let user_id = UserId::from_str(cmd.user_id.as_ref().ok_or(anyhow::anyhow!(""))?)?;
+                let currency = cmd.currency.ok_or(anyhow::anyhow!(""))?;
+                encoding.to_vec(&replica.query_balance(&user_id, currency))
+            }
+            QUERY_ACCOUNTS => {
+                let user_id = UserId::from_str(cmd.user_id.as_ref().ok_or(anyhow::anyhow!(""))?)?;
+                if cmd.valued.unwrap_or_default() {
+                    encoding.to_vec(&replica.query_valued_account(&user_id))
+                } else {
+                    encoding.to_vec(&replica.query_account(&user_id))
+                }
+            }
+            QUERY_ORDER => {
+                let symbol = cmd.symbol().ok_or(anyhow::anyhow!(""))?;
+                // empty if not found
+                match replica.query_order(&symbol, cmd.order_id.ok_or(anyhow::anyhow!(""))?) {
+                    Some(order) => encoding.to_vec(&order),
+                    None => Ok(vec![]),
+                }
+            }
+            _ => Err(anyhow::anyhow!("")),
+        }
+    }
+
+    /// the replies are serialized in `encoding`, the errors are replied in error frames
+    pub fn handle_req(&self, cmd: &Command, encoding: Encoding) -> anyhow::Result<Vec<u8>> {
         match cmd.cmd {
-            QUERY_OPEN_MARKETS => Ok(self.query_open_markets()),
-            GET_X25519_KEY => Ok(self.get_x25519_key()),
-            QUERY_FUSOTAO_PROGRESS => Ok(self.query_progress()),
+            QUERY_OPEN_MARKETS => encoding.to_vec(&self.query_open_markets(C.fusotao.standalone)),
+            GET_X25519_KEY => encoding.to_vec(&self.get_x25519_key()?),
+            QUERY_FUSOTAO_PROGRESS => encoding.to_vec(&self.query_progress()),
+            GET_NONCE => {
+                let user_id = UserId::from_str(cmd.user_id.as_ref().ok_or(anyhow::anyhow!(""))?)?;
+                encoding.to_vec(&json!({"nonce": crate::input::nonce::get(&user_id)?}))
+            }
             GET_NONCE_FOR_BROKER => {
                 let broker = UserId::from_str(cmd.user_id.as_ref().ok_or(anyhow::anyhow!(""))?)?;
-                Ok(self.get_nonce_for_broker(&broker))
-            }
-            QUERY_PROVING_PERF_INDEX => {
-                to_vec(&json!({"proving_perf_index": 0})).map_err(|e| e.into())
+                encoding.to_vec(&self.get_nonce_for_broker(&broker))
             }
-            QUERY_SCAN_HEIGHT => to_vec(&json!({
+            QUERY_PROVING_PERF_INDEX => encoding.to_vec(&json!({"proving_perf_index": 0})),
+            QUERY_SCAN_HEIGHT => encoding.to_vec(&json!({
                 "scaned_height": self.fuso_state.get_scanning_progress(),
                 "chain_height": self.fuso_state.get_chain_height(),
-            }))
-            .map_err(|e| e.into()),
+            })),
+            QUERY_API_USAGE => encoding.to_vec(&crate::usage::USAGE.query(cmd.user_id.as_deref())),
+            QUERY_KLINES => encoding.to_vec(&self.query_klines(cmd)?),
+            QUERY_BROKER_FLOW => encoding.to_vec(&self.query_broker_flow(cmd)?),
+            QUERY_ORDER_HISTORY => encoding.to_vec(&self.query_order_history(cmd)?),
+            QUERY_TICKER => encoding.to_vec(&ticker::query(cmd.symbol())),
+            QUERY_BOOK_IMBALANCE => encoding.to_vec(&imbalance::query(cmd.symbol())),
+            QUERY_STATEMENT => encoding.to_vec(&self.query_statement(cmd)?),
+            QUERY_BALANCE | QUERY_ACCOUNTS | QUERY_ORDER => self.query_replica(cmd, encoding),
             _ => Err(anyhow::anyhow!("")),
         }
     }
@@ -112,14 +357,92 @@ mod test {
             .store(1000, std::sync::atomic::Ordering::Relaxed);
         assert_eq!(
             serde_json::json!({"nonce": 1000}),
-            serde_json::from_slice::<serde_json::Value>(&shared.get_nonce_for_broker(&broker))
-                .unwrap()
+            shared.get_nonce_for_broker(&broker)
         );
         let broker = UserId::from_str("5FhfEqhp2Dt9e1FgL9EmnE6kRT6NJgSUPCTPMCCNqxrm3MQX").unwrap();
         assert_eq!(
             serde_json::json!({"nonce": -1}),
-            serde_json::from_slice::<serde_json::Value>(&shared.get_nonce_for_broker(&broker))
-                .unwrap()
+            shared.get_nonce_for_broker(&broker)
+        );
+    }
+
+    #[test]
+    pub fn test_query_open_markets() {
+        use rust_decimal_macros::dec;
+        let shared = Shared::new(
+            Arc::new(Default::default()),
+            "0xedcff0c69e4c0fa7e9a36e2e6d07f2cc355c8d25907a0ad2ab7e03b24f8e90f3".to_string(),
+        );
+        // the executors of the other tests may publish their orderbooks too
+        let (listed, unlisted, offchain) = ((1001, 1000), (1002, 1000), (1003, 1000));
+        for (currency, symbol) in [(1000, "USDX"), (1001, "AAA")] {
+            TOKENS.insert(
+                currency,
+                TokenInfo {
+                    symbol: symbol.to_string(),
+                    decimals: 18,
+                    stable: false,
+                },
+            );
+        }
+        for (symbol, status) in [
+            (listed, MarketStatus::Open),
+            (unlisted, MarketStatus::Registered),
+        ] {
+            shared.fuso_state.symbols.insert(
+                symbol,
+                OnchainSymbol {
+                    min_base: 100_000_000_000_000_000,
+                    base_scale: 4,
+                    quote_scale: 2,
+                    status,
+                    trading_rewards: false,
+                    liquidity_rewards: false,
+                    unavailable_after: None,
+                },
+            );
+        }
+        for symbol in [listed, offchain] {
+            let orderbook = OrderBook::new(
+                4,
+                2,
+                dec!(0.001),
+                dec!(0.001),
+                dec!(0.001),
+                dec!(0.001),
+                1,
+                dec!(0.1),
+                dec!(10),
+                true,
+                true,
+            );
+            MARKETS.insert(symbol, (&orderbook).into());
+        }
+        let markets = shared.query_open_markets(false);
+        let markets = markets
+            .into_iter()
+            .filter(|m| [listed, unlisted, offchain].contains(&m.symbol.symbol))
+            .collect::<Vec<_>>();
+        assert_eq!(3, markets.len());
+        assert!(markets[0].symbol.open);
+        assert_eq!(Some(MarketStatus::Open), markets[0].onchain);
+        assert_eq!(Some("AAA/USDX"), markets[0].name.as_deref());
+        assert!(markets[1].name.is_none());
+        assert_eq!(Some("USDX"), markets[1].quote_name.as_deref());
+        assert_eq!(
+            Some(dec!(0.001)),
+            markets[0].orderbook.as_ref().map(|c| c.taker_fee)
         );
+        assert!(!markets[1].symbol.open);
+        assert_eq!(Some(MarketStatus::Registered), markets[1].onchain);
+        assert!(markets[1].orderbook.is_none());
+        assert!(!markets[2].symbol.open);
+        assert!(markets[2].onchain.is_none());
+        assert_eq!(dec!(0.1), markets[2].symbol.min_base);
+        // compatible with the former replies
+        let markets = serde_json::to_vec(&shared.query_open_markets(true)).unwrap();
+        let symbols = serde_json::from_slice::<Vec<OffchainSymbol>>(&markets).unwrap();
+        assert!(symbols.iter().any(|s| s.symbol == listed && s.open));
+        assert!(symbols.iter().any(|s| s.symbol == offchain && s.open));
     }
 }
@@ -13,47 +13,160 @@
 // limitations under the License.
 
 use crate::{config, core, sequencer};
+use anyhow::{anyhow, ensure};
+use flate2::read::ZlibDecoder;
+use std::{
+    io::{BufReader, Read, Write},
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+// the snapshots being written in the background
+static DUMPING: AtomicUsize = AtomicUsize::new(0);
+
+/// prefixing the versioned snapshots
+const MAGIC: [u8; 4] = *b"GLXS";
+
+/// bumped on changing the layout of `Data`, the former layout must be kept in `Data::from_version`
+pub const VERSION: u32 = 18;
+
+/// the earliest layout migrated on loading, `v1` is migrated by the `migrate` command
+pub const MIN_VERSION: u32 = 2;
+
+/// the snapshots before the header are detected by trying the layouts from the latest
+const LAST_HEADERLESS: u32 = 7;
+
+/// the header followed by the bincode of `data`
+pub fn encode<W: Write>(data: &core::Data, mut w: W) -> anyhow::Result<()> {
+    w.write_all(&MAGIC)?;
+    w.write_all(&VERSION.to_le_bytes())?;
+    bincode::serialize_into(w, data)?;
+    Ok(())
+}
+
+/// return the version of the layout and the data migrated to the current one
+pub fn decode(raw: &[u8]) -> anyhow::Result<(u32, core::Data)> {
+    match raw.strip_prefix(&MAGIC).filter(|r| r.len() >= 4) {
+        Some(r) => {
+            let version = u32::from_le_bytes(r[..4].try_into().expect("4 bytes;qed"));
+            ensure!(
+                version <= VERSION,
+                "snapshot v{} is newer than v{}, upgrade galois to load it",
+                version,
+                VERSION
+            );
+            ensure!(
+                version >= MIN_VERSION,
+                "snapshot v{} is too old to be loaded",
+                version
+            );
+            Ok((version, core::Data::from_version(version, &r[4..])?))
+        }
+        None => (MIN_VERSION..=LAST_HEADERLESS)
+            .rev()
+            .find_map(|v| core::Data::from_version(v, raw).ok().map(|d| (v, d)))
+            .ok_or_else(|| anyhow!("unrecognized layout of snapshot")),
+    }
+}
+
+/// decompressed only once, and a misaligned length from the legacy layouts fails on the slice
+/// rather than allocating
+pub fn read(file: std::fs::File) -> anyhow::Result<(u32, core::Data)> {
+    let mut raw = vec![];
+    ZlibDecoder::new(BufReader::new(file)).read_to_end(&mut raw)?;
+    decode(&raw)
+}
+
+/// rewrite the coredump of a former layout in the current one
+pub fn upgrade(c: config::UpgradeCmd) -> anyhow::Result<()> {
+    let (version, data) = read(std::fs::File::open(&c.input_path)?)?;
+    let file = std::fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(&c.output_path)?;
+    data.into_raw(file)?;
+    log::info!(
+        "coredump {} upgraded from v{} to v{}",
+        c.input_path,
+        version,
+        VERSION
+    );
+    Ok(())
+}
 
 /// dump snapshot at id(executed)
 pub fn dump(id: u64, data: &core::Data) {
     if config::C.dry_run.is_some() {
         return;
     }
+    // the state is shared rather than copied, the executor clones what it mutates afterwards
     let data = data.clone();
+    DUMPING.fetch_add(1, Ordering::SeqCst);
     std::thread::spawn(move || -> anyhow::Result<()> {
-        let f = std::path::Path::new(&config::C.server.get_checkpoint_path())
-            .join(id.to_string())
-            .with_extension("gz");
-        let file = std::fs::OpenOptions::new()
-            .write(true)
-            .create_new(true)
-            .open(f)?;
-        data.into_raw(file)?;
-        sequencer::remove_before(id)?;
-        log::info!("snapshot dumped at sequence {}", id);
-        Ok(())
+        let r = write(id, data);
+        DUMPING.fetch_sub(1, Ordering::SeqCst);
+        r
     });
 }
 
+/// dump in the caller thread after the ongoing dumps finished, skipped if already dumped at `id`
+pub fn dump_final(id: u64, data: &core::Data) -> anyhow::Result<()> {
+    if config::C.dry_run.is_some() {
+        return Ok(());
+    }
+    while DUMPING.load(Ordering::SeqCst) > 0 {
+        std::thread::sleep(std::time::Duration::from_millis(10));
+    }
+    if latest_id().map_or(false, |latest| latest >= id) {
+        return Ok(());
+    }
+    write(id, data.clone())
+}
+
+fn write(id: u64, data: core::Data) -> anyhow::Result<()> {
+    let f = std::path::Path::new(&config::C.server.get_checkpoint_path())
+        .join(id.to_string())
+        .with_extension("gz");
+    let file = std::fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(f)?;
+    data.into_raw(file)?;
+    sequencer::remove_before(id)?;
+    log::info!("snapshot dumped at sequence {}", id);
+    Ok(())
+}
+
+/// the id of snapshot `<id>.gz`
+pub fn id_of(path: &std::path::Path) -> Option<u64> {
+    std::path::Path::new(path.file_stem()?)
+        .file_stem()?
+        .to_str()?
+        .parse::<u64>()
+        .ok()
+}
+
 fn get_id(path: &std::path::Path) -> u64 {
-    let file_stem = std::path::Path::new(path.file_stem().unwrap())
-        .file_stem()
-        .unwrap()
-        .to_str()
-        .unwrap();
-    file_stem.parse::<u64>().unwrap()
+    id_of(path).unwrap()
 }
 
-/// return the id(not executed yet), and the snapshot
-pub fn load() -> anyhow::Result<(u64, core::Data)> {
+fn latest() -> anyhow::Result<Option<std::path::PathBuf>> {
     let dir = std::fs::read_dir(&config::C.server.get_checkpoint_path())?;
-    let file_path = dir
+    Ok(dir
         .map(|e| e.unwrap())
         .filter(|f| f.file_type().unwrap().is_file())
         .map(|e| e.path())
         .filter(|p| p.extension().map_or(false, |s| s == "gz"))
-        .max_by(|x, y| get_id(x).cmp(&get_id(y)));
-    match file_path {
+        .max_by(|x, y| get_id(x).cmp(&get_id(y))))
+}
+
+/// the id of the latest snapshot, the saved events before it are removed
+pub fn latest_id() -> Option<u64> {
+    latest().ok().flatten().map(|f| get_id(&f))
+}
+
+/// return the id(not executed yet), and the snapshot
+pub fn load() -> anyhow::Result<(u64, core::Data)> {
+    match latest()? {
         Some(f) => {
             let event_id = get_id(&f);
             log::info!(
@@ -61,12 +174,18 @@ pub fn load() -> anyhow::Result<(u64, core::Data)> {
                 event_id,
                 event_id + 1
             );
-            let data = core::Data::from_raw(std::fs::File::open(f)?)?;
+            let (version, data) = read(std::fs::File::open(f)?)?;
+            if version < VERSION {
+                log::info!("snapshot migrated from v{} to v{}", version, VERSION);
+            }
             print_symbols(&data);
             Ok((event_id + 1, data))
         }
         None => match config::C.sequence.enable_from_genesis {
-            true => Ok((1, core::Data::new())),
+            true => match config::C.sequence.genesis {
+                Some(ref genesis) => Ok((1, crate::genesis::load(genesis)?)),
+                None => Ok((1, core::Data::new())),
+            },
             false => Err(anyhow::anyhow!(
                 "missing snapshot, add `enable_from_genesis` to force to start"
             )),
@@ -90,8 +209,28 @@ fn print_symbols(data: &core::Data) {
 
 #[cfg(test)]
 mod test {
+    use crate::core::Data;
     use std::path::Path;
 
+    #[test]
+    pub fn test_versioned() {
+        let mut data = Data::new();
+        data.current_event_id = 9;
+        let mut raw = vec![];
+        super::encode(&data, &mut raw).unwrap();
+        assert_eq!(b"GLXS", &raw[..4]);
+        let (version, de) = super::decode(&raw).unwrap();
+        assert_eq!(super::VERSION, version);
+        assert_eq!(9, de.current_event_id);
+        // dumped before the header
+        let (version, de) = super::decode(&raw[8..]).unwrap();
+        assert_eq!(super::LAST_HEADERLESS, version);
+        assert_eq!(9, de.current_event_id);
+        raw[4..8].copy_from_slice(&(super::VERSION + 1).to_le_bytes());
+        assert!(super::decode(&raw).is_err());
+        assert!(super::decode(&[0u8; 8]).is_err());
+    }
+
     #[test]
     pub fn test_syspath() {
         let f = Path::new("/tmp/snapshot/")
@@ -113,5 +252,6 @@ mod test {
             std::cmp::Ordering::Greater,
             super::get_id(&f0).cmp(&super::get_id(&f1))
         );
+        assert_eq!(None, super::id_of(Path::new("/tmp/snapshot/coredump.gz")));
     }
 }
@@ -0,0 +1,564 @@
+// Copyright 2021-2023 UINB Technologies Pte. Ltd.
+
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// 	http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::{assets, config::VerifyCmd, core::*, fusotao::prover};
+use dashmap::DashMap;
+use rayon::prelude::*;
+use rust_decimal::prelude::Zero;
+use serde::Serialize;
+use smt::{blake2b::Blake2bHasher, traits::Store, SparseMerkleTree, H256};
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs::File,
+    time::{Duration, Instant},
+};
+
+lazy_static::lazy_static! {
+    // the checks of the merkle root requested by the operators, taken once replied
+    static ref ROOT_CHECKS: DashMap<u64, Result<RootCheck, String>> = DashMap::new();
+}
+
+/// the inconsistency found in a coredump
+#[derive(Debug, Clone, Eq, PartialEq, Serialize)]
+#[serde(tag = "check", rename_all = "snake_case")]
+pub enum Violation {
+    /// the frozen balance differs from the sum required by the resting orders
+    Frozen {
+        user_id: String,
+        currency: Currency,
+        frozen: Amount,
+        expected: Amount,
+    },
+    /// the page amount differs from the sum of its orders
+    OrderPage {
+        symbol: Symbol,
+        price: Price,
+        amount: Amount,
+        expected: Amount,
+    },
+    /// the hidden of the page differs from the sum of its icebergs
+    HiddenAmount {
+        symbol: Symbol,
+        price: Price,
+        hidden: Amount,
+        expected: Amount,
+    },
+    /// the resting order isn't indexed by its price or the index is dangling
+    OrderIndex { symbol: Symbol, order_id: OrderId },
+    /// the resting order differs from the pending one of the user
+    PendingOrder { symbol: Symbol, order_id: OrderId },
+    /// the balance differs from the leaf
+    AccountLeaf { user_id: String, currency: Currency },
+    /// the size, best prices or pages differ from the leaves
+    OrderBookLeaf { symbol: Symbol },
+    /// the sum of all balances differs from the tvl
+    Tvl { tvl: Amount, total: Amount },
+    /// the available or frozen is negative
+    NegativeBalance {
+        user_id: String,
+        currency: Currency,
+        available: Amount,
+        frozen: Amount,
+    },
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Report {
+    pub event_id: u64,
+    pub passed: bool,
+    pub orderbooks: usize,
+    pub accounts: usize,
+    /// the sum of available and frozen per currency, including the fees of `SYSTEM`
+    pub tvl: BTreeMap<Currency, Amount>,
+    pub violations: Vec<Violation>,
+}
+
+/// the checks are independent and run on all cores, the data is never mutated
+pub fn verify(data: &Data) -> Report {
+    let ((frozen, orderbooks), (leaves, tvl)) = rayon::join(
+        || rayon::join(|| check_frozen(data), || check_orderbooks(data)),
+        || rayon::join(|| check_leaves(data), || sum_balances(data)),
+    );
+    let mut violations = [frozen, orderbooks, leaves, check_negative(data)].concat();
+    let total = tvl.values().fold(Amount::zero(), |x, a| x + a);
+    if total != data.tvl {
+        violations.push(Violation::Tvl {
+            tvl: data.tvl,
+            total,
+        });
+    }
+    Report {
+        event_id: data.current_event_id,
+        passed: violations.is_empty(),
+        orderbooks: data.orderbooks.len(),
+        accounts: data.accounts.len(),
+        tvl,
+        violations,
+    }
+}
+
+/// the leaves recomputed from the state checked against the merkle tree of the same event
+#[derive(Debug, Clone, Serialize)]
+pub struct RootCheck {
+    pub event_id: u64,
+    pub root: String,
+    pub leaves: usize,
+    pub passed: bool,
+    pub violations: Vec<Violation>,
+}
+
+/// the leaves of the user's balances and the orderbook, all of them if neither is given,
+/// along with the violation reported if the leaf differs
+pub fn select_leaves(
+    data: &Data,
+    user_id: Option<UserId>,
+    symbol: Option<Symbol>,
+) -> Vec<(Violation, (H256, H256))> {
+    let all = user_id.is_none() && symbol.is_none();
+    let accounts = match user_id {
+        Some(user_id) => data.accounts.get_key_value(&user_id).into_iter().collect(),
+        None if all => data.accounts.iter().collect(),
+        None => vec![],
+    };
+    let orderbooks = match symbol {
+        Some(symbol) => data.orderbooks.get_key_value(&symbol).into_iter().collect(),
+        None if all => data.orderbooks.iter().collect(),
+        None => vec![],
+    };
+    let mut leaves = vec![];
+    for (user_id, account) in accounts.into_iter().filter(|(u, _)| **u != SYSTEM) {
+        for (currency, balance) in account.iter() {
+            leaves.push((
+                Violation::AccountLeaf {
+                    user_id: format!("{:?}", user_id),
+                    currency: *currency,
+                },
+                prover::account_state(user_id, *currency, balance),
+            ));
+        }
+    }
+    for (symbol, orderbook) in orderbooks {
+        for state in prover::orderbook_states(*symbol, orderbook) {
+            leaves.push((Violation::OrderBookLeaf { symbol: *symbol }, state));
+        }
+    }
+    leaves
+}
+
+pub fn check_root<S: Store<H256>>(
+    merkle_tree: &SparseMerkleTree<Blake2bHasher, H256, S>,
+    event_id: u64,
+    leaves: Vec<(Violation, (H256, H256))>,
+) -> RootCheck {
+    let count = leaves.len();
+    let mut violations = leaves
+        .into_iter()
+        .filter(|(_, (key, value))| merkle_tree.get(key).ok().as_ref() != Some(value))
+        .map(|(violation, _)| violation)
+        .collect::<Vec<_>>();
+    // the leaves of an orderbook are adjacent
+    violations.dedup();
+    RootCheck {
+        event_id,
+        root: format!("0x{}", hex::encode(merkle_tree.root().as_slice())),
+        leaves: count,
+        passed: violations.is_empty(),
+        violations,
+    }
+}
+
+/// the check is always logged, and replied to the operator unless `check_id` is 0
+pub fn publish_root_check(check_id: u64, check: Result<RootCheck, String>) {
+    match check {
+        Ok(ref c) if c.passed => {
+            log::info!(
+                "{} leaves checked against root {} at {}",
+                c.leaves,
+                c.root,
+                c.event_id
+            )
+        }
+        Ok(ref c) => log::error!(
+            "the merkle root {} diverged from the state at {}: {}",
+            c.root,
+            c.event_id,
+            serde_json::to_string(&c.violations).unwrap_or_default()
+        ),
+        Err(ref e) => log::warn!("unable to check the merkle root, {}", e),
+    }
+    if check_id != 0 {
+        ROOT_CHECKS.insert(check_id, check);
+    }
+}
+
+pub fn wait_root_check(check_id: u64, timeout: Duration) -> Option<Result<RootCheck, String>> {
+    let deadline = Instant::now() + timeout;
+    while Instant::now() < deadline {
+        if let Some((_, check)) = ROOT_CHECKS.remove(&check_id) {
+            return Some(check);
+        }
+        std::thread::sleep(Duration::from_millis(10));
+    }
+    ROOT_CHECKS.remove(&check_id).map(|(_, check)| check)
+}
+
+/// load the coredump and write the report, returns whether all checks passed
+pub fn run(c: VerifyCmd) -> anyhow::Result<bool> {
+    let data = Data::from_raw(File::open(&c.input_path)?)?;
+    log::info!("coredump {} loaded, verifying", c.input_path);
+    let report = verify(&data);
+    let json = serde_json::to_vec_pretty(&report)?;
+    match c.output_path {
+        Some(path) => std::fs::write(path, json)?,
+        None => println!("{}", String::from_utf8_lossy(&json)),
+    }
+    Ok(report.passed)
+}
+
+fn resting_frozen(symbol: &Symbol, orderbook: &OrderBook) -> HashMap<(UserId, Currency), Amount> {
+    let mut frozen = HashMap::<(UserId, Currency), Amount>::new();
+    for (tape, ask_or_bid) in [
+        (&orderbook.asks, AskOrBid::Ask),
+        (&orderbook.bids, AskOrBid::Bid),
+    ] {
+        for order in tape.values().flat_map(|page| page.orders.values()) {
+            let (currency, amount) =
+                assets::freeze_if(symbol, ask_or_bid, order.price, order.unfilled);
+            *frozen.entry((order.user, currency)).or_default() += amount;
+        }
+    }
+    frozen
+}
+
+fn check_frozen(data: &Data) -> Vec<Violation> {
+    let mut expected = HashMap::<(UserId, Currency), Amount>::new();
+    data.orderbooks
+        .par_iter()
+        .map(|(symbol, orderbook)| resting_frozen(symbol, orderbook))
+        .collect::<Vec<_>>()
+        .into_iter()
+        .flatten()
+        .for_each(|(k, amount)| *expected.entry(k).or_default() += amount);
+    let expected = &expected;
+    let mut violations = data
+        .accounts
+        .par_iter()
+        .flat_map_iter(|(user_id, account)| {
+            account.iter().filter_map(move |(currency, balance)| {
+                let required = expected
+                    .get(&(*user_id, *currency))
+                    .copied()
+                    .unwrap_or_default();
+                (balance.frozen != required).then(|| Violation::Frozen {
+                    user_id: format!("{:?}", user_id),
+                    currency: *currency,
+                    frozen: balance.frozen,
+                    expected: required,
+                })
+            })
+        })
+        .collect::<Vec<_>>();
+    // the orders of users without balances
+    violations.extend(
+        expected
+            .iter()
+            .filter(|((user_id, currency), _)| {
+                data.accounts
+                    .get(user_id)
+                    .map_or(true, |a| !a.contains_key(currency))
+            })
+            .map(|((user_id, currency), required)| Violation::Frozen {
+                user_id: format!("{:?}", user_id),
+                currency: *currency,
+                frozen: Amount::zero(),
+                expected: *required,
+            }),
+    );
+    violations
+}
+
+fn check_orderbook(data: &Data, symbol: &Symbol, orderbook: &OrderBook) -> Vec<Violation> {
+    let mut violations = vec![];
+    for (price, page) in orderbook.asks.iter().chain(orderbook.bids.iter()) {
+        let expected = page
+            .orders
+            .values()
+            .fold(Amount::zero(), |x, o| x + o.unfilled);
+        if expected != page.amount {
+            violations.push(Violation::OrderPage {
+                symbol: *symbol,
+                price: *price,
+                amount: page.amount,
+                expected,
+            });
+        }
+        let expected = page
+            .orders
+            .values()
+            .fold(Amount::zero(), |x, o| x + o.hidden);
+        if expected != page.hidden {
+            violations.push(Violation::HiddenAmount {
+                symbol: *symbol,
+                price: *price,
+                hidden: page.hidden,
+                expected,
+            });
+        }
+        for order in page.orders.values() {
+            if orderbook.indices.get(&order.id) != Some(price) {
+                violations.push(Violation::OrderIndex {
+                    symbol: *symbol,
+                    order_id: order.id,
+                });
+            }
+            let pending = data
+                .orders
+                .orders
+                .get(&(order.user, *symbol))
+                .and_then(|orders| orders.get(&order.id));
+            if pending.map_or(true, |p| p.amount - p.matched_base_amount != order.unfilled) {
+                violations.push(Violation::PendingOrder {
+                    symbol: *symbol,
+                    order_id: order.id,
+                });
+            }
+        }
+    }
+    violations.extend(
+        orderbook
+            .indices
+            .keys()
+            .filter(|id| orderbook.find_order(**id).is_none())
+            .map(|id| Violation::OrderIndex {
+                symbol: *symbol,
+                order_id: *id,
+            }),
+    );
+    violations
+}
+
+fn check_orderbooks(data: &Data) -> Vec<Violation> {
+    let mut violations = data
+        .orderbooks
+        .par_iter()
+        .flat_map_iter(|(symbol, orderbook)| check_orderbook(data, symbol, orderbook))
+        .collect::<Vec<_>>();
+    // the pending orders not resting on the books
+    violations.extend(
+        data.orders
+            .orders
+            .par_iter()
+            .flat_map_iter(|((user_id, symbol), orders)| {
+                let orderbook = data.orderbooks.get(symbol);
+                orders
+                    .keys()
+                    .filter(move |id| {
+                        orderbook
+                            .and_then(|b| b.find_order(**id))
+                            .map_or(true, |o| o.user != *user_id)
+                    })
+                    .map(move |id| Violation::PendingOrder {
+                        symbol: *symbol,
+                        order_id: *id,
+                    })
+            })
+            .collect::<Vec<_>>(),
+    );
+    violations
+}
+
+/// the fees of `SYSTEM` are never proven
+fn check_leaves(data: &Data) -> Vec<Violation> {
+    let merkle_tree = &data.merkle_tree;
+    let mut violations = data
+        .accounts
+        .par_iter()
+        .filter(|(user_id, _)| **user_id != SYSTEM)
+        .flat_map_iter(|(user_id, account)| {
+            account.iter().filter_map(move |(currency, balance)| {
+                let (key, value) = prover::account_state(user_id, *currency, balance);
+                (merkle_tree.get(&key).ok() != Some(value)).then(|| Violation::AccountLeaf {
+                    user_id: format!("{:?}", user_id),
+                    currency: *currency,
+                })
+            })
+        })
+        .collect::<Vec<_>>();
+    violations.extend(
+        data.orderbooks
+            .par_iter()
+            .filter(|(symbol, orderbook)| {
+                prover::orderbook_states(**symbol, orderbook)
+                    .iter()
+                    .any(|(key, value)| merkle_tree.get(key).ok().as_ref() != Some(value))
+            })
+            .map(|(symbol, _)| Violation::OrderBookLeaf { symbol: *symbol })
+            .collect::<Vec<_>>(),
+    );
+    violations
+}
+
+fn check_negative(data: &Data) -> Vec<Violation> {
+    data.accounts
+        .par_iter()
+        .flat_map_iter(|(user_id, account)| {
+            account
+                .iter()
+                .filter(|(_, b)| b.available < Amount::zero() || b.frozen < Amount::zero())
+                .map(move |(currency, balance)| Violation::NegativeBalance {
+                    user_id: format!("{:?}", user_id),
+                    currency: *currency,
+                    available: balance.available,
+                    frozen: balance.frozen,
+                })
+        })
+        .collect()
+}
+
+fn sum_balances(data: &Data) -> BTreeMap<Currency, Amount> {
+    let mut tvl = BTreeMap::<Currency, Amount>::new();
+    data.accounts
+        .par_iter()
+        .flat_map_iter(|(_, account)| {
+            account
+                .iter()
+                .map(|(currency, balance)| (*currency, balance.available + balance.frozen))
+        })
+        .collect::<Vec<_>>()
+        .into_iter()
+        .for_each(|(currency, amount)| *tvl.entry(currency).or_default() += amount);
+    tvl
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::orderbook::Order;
+    use rust_decimal_macros::dec;
+
+    fn pending(order_id: OrderId, user_id: UserId, price: Price, amount: Amount) -> PendingOrder {
+        PendingOrder {
+            order_id,
+            user_id,
+            symbol: (1, 0),
+            direction: 0,
+            create_timestamp: 0,
+            amount,
+            price,
+            status: 0,
+            matched_quote_amount: Amount::zero(),
+            matched_base_amount: Amount::zero(),
+            base_fee: Amount::zero(),
+            quote_fee: Amount::zero(),
+        }
+    }
+
+    fn commit(data: &mut Data) {
+        let mut states = vec![];
+        for (user_id, account) in data.accounts.iter() {
+            for (currency, balance) in account.iter() {
+                states.push(prover::account_state(user_id, *currency, balance));
+            }
+        }
+        for (symbol, orderbook) in data.orderbooks.iter() {
+            states.extend(prover::orderbook_states(*symbol, orderbook));
+        }
+        for (key, value) in states {
+            data.merkle_tree.update(key, value).unwrap();
+        }
+    }
+
+    #[test]
+    pub fn test_verify() {
+        let mut data = Data::new();
+        let (alice, bob) = (UserId::from_low_u64_be(1), UserId::from_low_u64_be(2));
+        let mut orderbook = OrderBook::new(
+            5,
+            1,
+            dec!(0.001),
+            dec!(0.001),
+            dec!(0.001),
+            dec!(0.001),
+            1,
+            dec!(0.1),
+            dec!(10),
+            false,
+            true,
+        );
+        orderbook.insert(Order::new(1, alice, dec!(10), dec!(2)), AskOrBid::Ask);
+        orderbook.insert(Order::new(2, bob, dec!(9), dec!(3)), AskOrBid::Bid);
+        data.orderbooks.insert((1, 0), orderbook.into());
+        data.orders.insert(pending(1, alice, dec!(10), dec!(2)));
+        data.orders.insert(pending(2, bob, dec!(9), dec!(3)));
+        assets::add_to_available(&mut data.accounts, &alice, 1, dec!(5)).unwrap();
+        assets::try_freeze(&mut data.accounts, &alice, 1, dec!(2)).unwrap();
+        assets::add_to_available(&mut data.accounts, &bob, 0, dec!(100)).unwrap();
+        assets::try_freeze(&mut data.accounts, &bob, 0, dec!(27)).unwrap();
+        assets::add_to_available(&mut data.accounts, &SYSTEM, 0, dec!(0.1)).unwrap();
+        data.tvl = dec!(105.1);
+        commit(&mut data);
+        let report = verify(&data);
+        assert!(report.passed, "{:?}", report.violations);
+        assert_eq!(BTreeMap::from([(0, dec!(100.1)), (1, dec!(5))]), report.tvl);
+        let check = check_root(&data.merkle_tree, 0, select_leaves(&data, None, None));
+        assert!(check.passed);
+        let states = prover::orderbook_states((1, 0), &data.orderbooks[&(1, 0)]);
+        assert_eq!(2 + states.len(), check.leaves);
+
+        data.accounts
+            .get_mut(&bob)
+            .unwrap()
+            .get_mut(&0)
+            .unwrap()
+            .frozen = dec!(20);
+        data.orders.remove(alice, (1, 0), 1);
+        let report = verify(&data);
+        assert!(!report.passed);
+        assert!(report.violations.contains(&Violation::Frozen {
+            user_id: format!("{:?}", bob),
+            currency: 0,
+            frozen: dec!(20),
+            expected: dec!(27),
+        }));
+        assert!(report.violations.contains(&Violation::AccountLeaf {
+            user_id: format!("{:?}", bob),
+            currency: 0,
+        }));
+        assert!(report.violations.contains(&Violation::PendingOrder {
+            symbol: (1, 0),
+            order_id: 1,
+        }));
+        assert!(report.violations.contains(&Violation::Tvl {
+            tvl: dec!(105.1),
+            total: dec!(98.1),
+        }));
+        assert_eq!(4, report.violations.len());
+        let check = check_root(&data.merkle_tree, 0, select_leaves(&data, Some(bob), None));
+        assert!(!check.passed);
+        assert_eq!(1, check.leaves);
+        assert_eq!(
+            vec![Violation::AccountLeaf {
+                user_id: format!("{:?}", bob),
+                currency: 0,
+            }],
+            check.violations
+        );
+        let check = check_root(
+            &data.merkle_tree,
+            0,
+            select_leaves(&data, None, Some((1, 0))),
+        );
+        assert!(check.passed);
+    }
+}
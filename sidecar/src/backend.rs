@@ -12,15 +12,25 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use crate::endpoint::{PendingOrderWrapper, TradingCommand};
+use crate::{
+    endpoint::{PendingOrderWrapper, TradingCommand},
+    errors::CustomRpcError,
+};
 use dashmap::DashMap;
 use galois_engine::{
     core::*,
     fusotao::OffchainSymbol,
-    input::{cmd::*, Command, Message},
+    input::{
+        cipher::{Initiator, SessionCipher},
+        cmd::*,
+        Command, Message,
+    },
     orderbook::Order,
-    orders::PendingOrder,
-    output::Depth,
+    orders::{PendingOrder, UserOrdersPage},
+    output::{imbalance::BookImbalance, kline::Kline, ticker::Ticker, Depth, DepthSnapshot, Trade},
+    shared::MarketInfo,
+    statement::{Entry, StatementPage, MAX_STATEMENT_ENTRIES},
+    valuation::ValuedAccount,
 };
 use rust_decimal::Decimal;
 use serde_json::{json, to_vec, Value as JsonValue};
@@ -33,13 +43,15 @@ use tokio::net::{
     TcpStream, ToSocketAddrs,
 };
 use tokio::sync::mpsc::{self, Receiver, Sender, UnboundedSender};
-use x25519_dalek::StaticSecret;
 
 type ToBackend = Sender<Option<Req>>;
 type FromFrontend = Receiver<Option<Req>>;
 type Notifier = Sender<JsonValue>;
 type Broadcast = UnboundedSender<(u8, JsonValue)>;
 
+// the write loop starts from 2
+const HANDSHAKE_REQ_ID: u64 = 1;
+
 #[derive(Clone, Debug)]
 pub struct BackendConnection {
     to_backend: ToBackend,
@@ -52,12 +64,14 @@ struct Req {
 }
 
 impl BackendConnection {
+    /// `pinned` is the x25519 public key of galois in hex
     pub fn new(
         addr: impl ToSocketAddrs + Send + Sync + Clone + 'static,
+        pinned: String,
         broadcast: Broadcast,
     ) -> Self {
         let (to_backend, from_frontend) = mpsc::channel(3000);
-        Self::start_inner(to_backend.clone(), from_frontend, broadcast, addr);
+        Self::start_inner(to_backend.clone(), from_frontend, broadcast, addr, pinned);
         Self { to_backend }
     }
 
@@ -66,15 +80,29 @@ impl BackendConnection {
         from_front: FromFrontend,
         broadcast: Broadcast,
         addr: impl ToSocketAddrs + Send + Sync + Clone + 'static,
+        pinned: String,
     ) {
         tokio::spawn(async move {
             let sink = Arc::new(DashMap::<u64, Notifier>::new());
             let mut from_front = from_front;
             loop {
                 if let Ok(stream) = TcpStream::connect(addr.clone()).await {
-                    let (r, w) = stream.into_split();
-                    let join = tokio::spawn(Self::write_loop(w, sink.clone(), from_front));
-                    Self::read_loop(r, sink.clone(), broadcast.clone()).await;
+                    let (mut r, mut w) = stream.into_split();
+                    let cipher = match Self::handshake(&mut r, &mut w, &pinned).await {
+                        Ok(cipher) => Arc::new(cipher),
+                        Err(e) => {
+                            log::error!("handshake with galois failed, {:?}, will retry in 1s.", e);
+                            tokio::time::sleep(std::time::Duration::from_millis(1000)).await;
+                            continue;
+                        }
+                    };
+                    let join = tokio::spawn(Self::write_loop(
+                        w,
+                        sink.clone(),
+                        from_front,
+                        cipher.clone(),
+                    ));
+                    Self::read_loop(r, sink.clone(), broadcast.clone(), cipher).await;
                     let _ = to_back.send(None).await;
                     from_front = join.await.unwrap();
                 } else {
@@ -85,33 +113,66 @@ impl BackendConnection {
         });
     }
 
+    /// exchange the x25519 keys before any other request, all the payloads are sealed after
+    async fn handshake(
+        r: &mut OwnedReadHalf,
+        w: &mut OwnedWriteHalf,
+        pinned: &str,
+    ) -> anyhow::Result<SessionCipher> {
+        let initiator = Initiator::new();
+        let payload = to_vec(&json!({
+            "cmd": X25519_HANDSHAKE,
+            "x25519": initiator.public(),
+        }))?;
+        w.write_all(&Message::new_req(HANDSHAKE_REQ_ID, payload).encode())
+            .await?;
+        // the broadcasts before the handshake replied are dropped
+        loop {
+            let (_, req_id, payload) = Self::read_frame(r).await?;
+            if req_id != HANDSHAKE_REQ_ID {
+                continue;
+            }
+            let r = serde_json::from_slice::<JsonValue>(&payload)?;
+            let server = r
+                .get("x25519")
+                .and_then(|v| v.as_str())
+                .ok_or(anyhow::anyhow!("handshake rejected by galois"))?;
+            return initiator.complete(server, pinned);
+        }
+    }
+
+    async fn read_frame(stream: &mut OwnedReadHalf) -> anyhow::Result<(u64, u64, Vec<u8>)> {
+        let mut header = [0_u8; 8];
+        let mut req_id = [0_u8; 8];
+        stream.read_exact(&mut header).await?;
+        stream.read_exact(&mut req_id).await?;
+        let header = u64::from_be_bytes(header);
+        anyhow::ensure!(Message::check_magic(header), "invalid magic number");
+        let req_id = u64::from_be_bytes(req_id);
+        let mut payload = vec![0_u8; Message::get_len(header)];
+        stream.read_exact(&mut payload).await?;
+        Ok((header, req_id, payload))
+    }
+
     async fn read_loop(
         mut stream: OwnedReadHalf,
         req: Arc<DashMap<u64, Notifier>>,
         broadcast: Broadcast,
+        cipher: Arc<SessionCipher>,
     ) {
         log::debug!("starting background read loop.");
         let mut buf = Vec::<u8>::with_capacity(4096);
         loop {
-            let mut header = [0_u8; 8];
-            let mut req_id = [0_u8; 8];
-            if stream.read_exact(&mut header).await.is_err() {
-                break;
-            }
-            if stream.read_exact(&mut req_id).await.is_err() {
-                break;
-            }
-            let header = u64::from_be_bytes(header);
-            if !Message::check_magic(header) {
-                break;
-            }
-            let req_id = u64::from_be_bytes(req_id);
-            let mut tmp = vec![0_u8; Message::get_len(header)];
-            if stream.read_exact(&mut tmp).await.is_err() {
-                break;
-            }
+            let (header, req_id, tmp) = match Self::read_frame(&mut stream).await {
+                Ok(frame) => frame,
+                Err(_) => break,
+            };
             buf.extend_from_slice(&tmp[..]);
             if !Message::has_next_frame(header) {
+                let buf = match cipher.open(&std::mem::take(&mut buf)) {
+                    Ok(plain) => plain,
+                    Err(_) => break,
+                };
                 log::debug!("receiving data from galois: {:?}", buf);
                 let json = if buf.is_empty() {
                     serde_json::Value::Null
@@ -127,7 +188,6 @@ impl BackendConnection {
                 } else if let Some((_, noti)) = req.remove(&req_id) {
                     let _ = noti.send(json).await;
                 }
-                buf.clear();
             }
         }
         req.clear();
@@ -138,6 +198,7 @@ impl BackendConnection {
         mut stream: OwnedWriteHalf,
         sink: Arc<DashMap<u64, Notifier>>,
         mut from_front: FromFrontend,
+        cipher: Arc<SessionCipher>,
     ) -> FromFrontend {
         log::debug!("starting background write loop.");
         let mut req_id = 1u64;
@@ -147,7 +208,7 @@ impl BackendConnection {
                     req_id += 1;
                     let Req { payload, notifier } = req;
                     sink.insert(req_id, notifier);
-                    let msg = Message::new_req(req_id, payload);
+                    let msg = Message::new_req(req_id, cipher.seal(&payload));
                     match stream.write_all(&msg.encode()).await {
                         Ok(_) => log::debug!("write to galois -> OK"),
                         Err(e) => log::debug!("write to galois -> {:?}", e),
@@ -221,17 +282,114 @@ impl BackendConnection {
                 place.broker = Some(relayer.to_string());
                 place
             }
+            TradingCommand::SubTransfer {
+                bot,
+                token,
+                currency,
+                amount,
+                to_sub,
+            } => {
+                let mut transfer = Command::default();
+                transfer.cmd = SUB_TRANSFER;
+                transfer.user_id = Some(user_id.to_string());
+                transfer.bot = Some(crate::try_into_ss58(bot)?);
+                transfer.token = Some(token);
+                transfer.currency = Some(currency);
+                transfer.amount = Decimal::from_str(&amount).ok();
+                transfer.to_sub = Some(to_sub);
+                transfer.signature = Some(fix_cmd_signature.to_string());
+                transfer.nonce = Some(fix_cmd_nonce);
+                transfer
+            }
+            TradingCommand::CancelAll { .. } => {
+                return Err(anyhow::anyhow!("Invalid command"));
+            }
         };
         let r = self
             .request(to_vec(&payload)?)
             .await
             .inspect_err(|e| log::debug!("{:?}", e))?;
+        if let (Some(code), Some(e)) = (
+            r.get("code").and_then(|c| c.as_i64()),
+            r.get("error").and_then(|e| e.as_str()),
+        ) {
+            return Err(CustomRpcError::rejected_by_galois(code, e).into());
+        }
         r.get("id")
             .ok_or(anyhow::anyhow!("error while placing orders"))?
             .as_u64()
             .ok_or(anyhow::anyhow!("error while placing orders"))
     }
 
+    /// cancel all resting orders of the user on the symbol, returning the canceled order ids
+    pub async fn cancel_all(
+        &self,
+        user_id: impl ToString,
+        symbol: Symbol,
+    ) -> anyhow::Result<Vec<u64>> {
+        let payload = json!({
+            "cmd": CANCEL_ALL,
+            "base": symbol.0,
+            "quote": symbol.1,
+            "user_id": user_id.to_string(),
+        });
+        let r = self
+            .request(to_vec(&payload)?)
+            .await
+            .inspect_err(|e| log::debug!("{:?}", e))?;
+        if let (Some(code), Some(e)) = (
+            r.get("code").and_then(|c| c.as_i64()),
+            r.get("error").and_then(|e| e.as_str()),
+        ) {
+            return Err(CustomRpcError::rejected_by_galois(code, e).into());
+        }
+        r.get("ids")
+            .and_then(|ids| serde_json::from_value::<Vec<u64>>(ids.clone()).ok())
+            .ok_or(anyhow::anyhow!("error while canceling orders"))
+    }
+
+    /// reject the trivially invalid orders without burning a sequence id, the balance is only
+    /// likely sufficient since the account may change before the order is sequenced
+    pub async fn pre_validate(
+        &self,
+        user_id: &str,
+        market: &OffchainSymbol,
+        cmd: &TradingCommand,
+    ) -> anyhow::Result<()> {
+        let (currency, required) = match cmd {
+            TradingCommand::Ask {
+                base,
+                amount,
+                price,
+                ..
+            } => {
+                let (amount, _) = check_order(market, amount, price)?;
+                (*base, amount)
+            }
+            TradingCommand::Bid {
+                quote,
+                amount,
+                price,
+                ..
+            } => {
+                let (amount, price) = check_order(market, amount, price)?;
+                let vol = amount
+                    .checked_mul(price)
+                    .ok_or(CustomRpcError::invalid_order("overflow"))?;
+                (*quote, vol)
+            }
+            TradingCommand::Cancel { .. }
+            | TradingCommand::CancelAll { .. }
+            | TradingCommand::SubTransfer { .. } => return Ok(()),
+        };
+        let mut account = self.get_account(user_id).await?;
+        anyhow::ensure!(
+            account.remove(&currency).unwrap_or_default().available >= required,
+            CustomRpcError::invalid_order("insufficient balance")
+        );
+        Ok(())
+    }
+
     pub async fn get_nonce(&self, broker: &str) -> Option<u32> {
         let r = self
             .request(to_vec(&json!({ "cmd": GET_NONCE_FOR_BROKER, "user_id": broker })).ok()?)
@@ -244,6 +402,21 @@ impl BackendConnection {
             .flatten()
     }
 
+    /// the last nonce of the user accepted by galois, `None` if never signed
+    pub async fn get_last_nonce(&self, user_id: impl AsRef<str>) -> anyhow::Result<Option<u32>> {
+        let r = self
+            .request(
+                to_vec(&json!({"cmd": GET_NONCE, "user_id": user_id.as_ref()}))
+                    .expect("jsonser;qed"),
+            )
+            .await
+            .inspect_err(|e| log::debug!("{:?}", e))
+            .map_err(|_| anyhow::anyhow!("Galois not available"))?;
+        Ok(r.get("nonce")
+            .and_then(|n| n.as_u64())
+            .and_then(|n| n.try_into().ok()))
+    }
+
     pub async fn get_account(
         &self,
         user_id: impl AsRef<str>,
@@ -259,6 +432,24 @@ impl BackendConnection {
         serde_json::from_value::<BTreeMap<u32, Balance>>(r).map_err(|_| anyhow::anyhow!("galois?"))
     }
 
+    /// the balances joined with the tokens and valued by galois
+    pub async fn get_valued_account(
+        &self,
+        user_id: impl AsRef<str>,
+    ) -> anyhow::Result<ValuedAccount> {
+        let r = self
+            .request(
+                to_vec(
+                    &json!({"cmd": QUERY_ACCOUNTS, "user_id": user_id.as_ref(), "valued": true}),
+                )
+                .expect("jsonser;qed"),
+            )
+            .await
+            .inspect_err(|e| log::debug!("{:?}", e))
+            .map_err(|_| anyhow::anyhow!("Galois not available"))?;
+        serde_json::from_value::<ValuedAccount>(r).map_err(|_| anyhow::anyhow!("galois?"))
+    }
+
     // this should be deprected
     pub async fn get_order(&self, symbol: Symbol, order_id: u64) -> anyhow::Result<Option<Order>> {
         let r = self
@@ -282,22 +473,184 @@ impl BackendConnection {
         symbol: Symbol,
         user_id: impl AsRef<str>,
     ) -> anyhow::Result<Vec<PendingOrderWrapper>> {
+        let mut orders = Vec::<PendingOrder>::new();
+        let mut from = None;
+        // paged by galois to keep the replies small
+        loop {
+            let r = self
+                .request(
+                    to_vec(&json!({
+                        "cmd": QUERY_USER_ORDERS,
+                        "base": symbol.0,
+                        "quote": symbol.1,
+                        "user_id": user_id.as_ref(),
+                        "from": from,
+                    }))
+                    .expect("jsonser;qed"),
+                )
+                .await
+                .inspect_err(|e| log::debug!("fetching order failed: {:?}", e))
+                .map_err(|_| anyhow::anyhow!("Galois not available"))?;
+            let page = serde_json::from_value::<UserOrdersPage>(r)
+                .map_err(|_| anyhow::anyhow!("galois?"))?;
+            orders.extend(page.orders);
+            match page.next {
+                Some(next) if page.truncated => from = Some(next),
+                _ => break,
+            }
+        }
+        Ok(orders.into_iter().map(|o| o.into()).collect())
+    }
+
+    /// the balance changes of the user in `[from, to]`
+    pub async fn query_statement(
+        &self,
+        user_id: impl AsRef<str>,
+        from: u64,
+        to: u64,
+    ) -> anyhow::Result<Vec<Entry>> {
+        let mut entries = Vec::<Entry>::new();
+        let mut cursor = None;
+        // paged by galois to keep the replies small
+        loop {
+            let r = self
+                .request(
+                    to_vec(&json!({
+                        "cmd": QUERY_STATEMENT,
+                        "user_id": user_id.as_ref(),
+                        "from": from,
+                        "to": to,
+                        "cursor": cursor,
+                        "limit": MAX_STATEMENT_ENTRIES,
+                    }))
+                    .expect("jsonser;qed"),
+                )
+                .await
+                .inspect_err(|e| log::debug!("fetching statement failed: {:?}", e))
+                .map_err(|_| anyhow::anyhow!("Galois not available"))?;
+            let page = serde_json::from_value::<StatementPage>(r)
+                .map_err(|_| anyhow::anyhow!("galois?"))?;
+            entries.extend(page.entries);
+            match page.next {
+                Some(next) if page.truncated => cursor = Some(next),
+                _ => break,
+            }
+        }
+        Ok(entries)
+    }
+
+    /// the full depth if neither `limit` nor `tick` given
+    pub async fn query_depth(
+        &self,
+        symbol: Symbol,
+        limit: Option<u32>,
+        tick: Option<Decimal>,
+    ) -> anyhow::Result<DepthSnapshot> {
         let r = self
             .request(
                 to_vec(&json!({
-                    "cmd": QUERY_USER_ORDERS,
+                    "cmd": QUERY_DEPTH,
                     "base": symbol.0,
                     "quote": symbol.1,
-                    "user_id": user_id.as_ref(),
+                    "limit": limit,
+                    "tick": tick,
                 }))
                 .expect("jsonser;qed"),
             )
             .await
-            .inspect_err(|e| log::debug!("fetching order failed: {:?}", e))
+            .inspect_err(|e| log::debug!("fetching depth failed: {:?}", e))
+            .map_err(|_| anyhow::anyhow!("Galois not available"))?;
+        serde_json::from_value::<Option<DepthSnapshot>>(r)
+            .map_err(|_| anyhow::anyhow!("galois?"))?
+            .ok_or(anyhow::anyhow!("symbol not exists"))
+    }
+
+    pub async fn query_trades(
+        &self,
+        symbol: Symbol,
+        limit: Option<u32>,
+    ) -> anyhow::Result<Vec<Trade>> {
+        let r = self
+            .request(
+                to_vec(&json!({
+                    "cmd": QUERY_TRADES,
+                    "base": symbol.0,
+                    "quote": symbol.1,
+                    "limit": limit,
+                }))
+                .expect("jsonser;qed"),
+            )
+            .await
+            .inspect_err(|e| log::debug!("fetching trades failed: {:?}", e))
+            .map_err(|_| anyhow::anyhow!("Galois not available"))?;
+        serde_json::from_value::<Vec<Trade>>(r).map_err(|_| anyhow::anyhow!("galois?"))
+    }
+
+    pub async fn query_klines(
+        &self,
+        symbol: Symbol,
+        interval: &str,
+        from: Option<u64>,
+        to: Option<u64>,
+        limit: Option<u32>,
+    ) -> anyhow::Result<Vec<Kline>> {
+        let r = self
+            .request(
+                to_vec(&json!({
+                    "cmd": QUERY_KLINES,
+                    "base": symbol.0,
+                    "quote": symbol.1,
+                    "interval": interval,
+                    "from": from,
+                    "to": to,
+                    "limit": limit,
+                }))
+                .expect("jsonser;qed"),
+            )
+            .await
+            .inspect_err(|e| log::debug!("fetching klines failed: {:?}", e))
             .map_err(|_| anyhow::anyhow!("Galois not available"))?;
-        serde_json::from_value::<Vec<PendingOrder>>(r)
-            .map_err(|_| anyhow::anyhow!("galois?"))
-            .map(|v| v.into_iter().map(|o| o.into()).collect())
+        if let Some(e) = r.get("error").and_then(|e| e.as_str()) {
+            return Err(anyhow::anyhow!("{}", e));
+        }
+        serde_json::from_value::<Vec<Kline>>(r).map_err(|_| anyhow::anyhow!("galois?"))
+    }
+
+    /// the rolling 24h tickers of all symbols traded if `symbol` absent
+    pub async fn query_ticker(&self, symbol: Option<Symbol>) -> anyhow::Result<Vec<Ticker>> {
+        let r = self
+            .request(
+                to_vec(&json!({
+                    "cmd": QUERY_TICKER,
+                    "base": symbol.map(|s| s.0),
+                    "quote": symbol.map(|s| s.1),
+                }))
+                .expect("jsonser;qed"),
+            )
+            .await
+            .inspect_err(|e| log::debug!("fetching ticker failed: {:?}", e))
+            .map_err(|_| anyhow::anyhow!("Galois not available"))?;
+        serde_json::from_value::<Vec<Ticker>>(r).map_err(|_| anyhow::anyhow!("galois?"))
+    }
+
+    /// the imbalances of all symbols with orderbooks if `symbol` absent
+    pub async fn query_book_imbalance(
+        &self,
+        symbol: Option<Symbol>,
+    ) -> anyhow::Result<Vec<BookImbalance>> {
+        let r = self
+            .request(
+                to_vec(&json!({
+                    "cmd": QUERY_BOOK_IMBALANCE,
+                    "base": symbol.map(|s| s.0),
+                    "quote": symbol.map(|s| s.1),
+                }))
+                .expect("jsonser;qed"),
+            )
+            .await
+            .inspect_err(|e| log::debug!("fetching book imbalance failed: {:?}", e))
+            .map_err(|_| anyhow::anyhow!("Galois not available"))?;
+        serde_json::from_value::<Vec<BookImbalance>>(r).map_err(|_| anyhow::anyhow!("galois?"))
     }
 
     pub async fn get_markets(&self) -> anyhow::Result<Vec<OffchainSymbol>> {
@@ -309,31 +662,84 @@ impl BackendConnection {
         serde_json::from_value::<Vec<OffchainSymbol>>(r).map_err(|_| anyhow::anyhow!("galois?"))
     }
 
-    pub async fn get_orderbooks(&self) -> anyhow::Result<Vec<Depth>> {
+    pub async fn list_markets(&self) -> anyhow::Result<Vec<MarketInfo>> {
         let r = self
-            .request(to_vec(&json!({ "cmd": QUERY_ALL_ORDERBOOKS })).expect("jsonser;qed"))
+            .request(to_vec(&json!({ "cmd": QUERY_OPEN_MARKETS })).expect("jsonser;qed"))
             .await
             .inspect_err(|e| log::debug!("{:?}", e))
             .map_err(|_| anyhow::anyhow!("Galois not available"))?;
-        serde_json::from_value::<Vec<Depth>>(r).map_err(|_| anyhow::anyhow!("galois?"))
+        serde_json::from_value::<Vec<MarketInfo>>(r).map_err(|_| anyhow::anyhow!("galois?"))
     }
 
-    pub async fn get_x25519(&self) -> anyhow::Result<StaticSecret> {
+    pub async fn get_orderbooks(&self) -> anyhow::Result<Vec<Depth>> {
         let r = self
-            .request(to_vec(&json!({ "cmd": GET_X25519_KEY })).expect("jsonser;qed"))
+            .request(to_vec(&json!({ "cmd": QUERY_ALL_ORDERBOOKS })).expect("jsonser;qed"))
             .await
             .inspect_err(|e| log::debug!("{:?}", e))
             .map_err(|_| anyhow::anyhow!("Galois not available"))?;
-        let b = r
-            .get("x25519")
-            .map(|v| v.as_str())
-            .flatten()
-            .map(|hex| crate::hexstr_to_vec(&hex))
-            .ok_or(anyhow::anyhow!("retrieving x25519 private key failed"))
-            .flatten()?;
-        let key: [u8; 32] = b
-            .try_into()
-            .map_err(|_| anyhow::anyhow!("x25519 config error"))?;
-        Ok(StaticSecret::from(key))
+        serde_json::from_value::<Vec<Depth>>(r).map_err(|_| anyhow::anyhow!("galois?"))
+    }
+}
+
+/// the order must respect the scales and the minimum of the open market
+fn check_order(
+    market: &OffchainSymbol,
+    amount: &str,
+    price: &str,
+) -> anyhow::Result<(Amount, Price)> {
+    anyhow::ensure!(
+        market.open,
+        CustomRpcError::invalid_order("symbol is not open")
+    );
+    let amount = Decimal::from_str(amount)
+        .map_err(|_| CustomRpcError::invalid_order("invalid amount numeric"))?;
+    let price = Decimal::from_str(price)
+        .map_err(|_| CustomRpcError::invalid_order("invalid price numeric"))?;
+    anyhow::ensure!(
+        price.is_sign_positive() && !price.is_zero() && price.scale() <= market.quote_scale.into(),
+        CustomRpcError::invalid_order(format!(
+            "price must be positive with at most {} decimals",
+            market.quote_scale
+        ))
+    );
+    anyhow::ensure!(
+        amount.is_sign_positive() && amount.scale() <= market.base_scale.into(),
+        CustomRpcError::invalid_order(format!(
+            "amount must be positive with at most {} decimals",
+            market.base_scale
+        ))
+    );
+    anyhow::ensure!(
+        amount >= market.min_base && !amount.is_zero(),
+        CustomRpcError::invalid_order(format!("amount must be at least {}", market.min_base))
+    );
+    Ok((amount, price))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    pub fn test_check_order() {
+        let mut market = OffchainSymbol {
+            symbol: (1, 0),
+            min_base: Decimal::new(1, 1),
+            base_scale: 2,
+            quote_scale: 4,
+            open: true,
+        };
+        assert_eq!(
+            (Decimal::new(1, 1), Decimal::new(100001, 4)),
+            check_order(&market, "0.1", "10.0001").unwrap()
+        );
+        assert!(check_order(&market, "0.09", "10").is_err());
+        assert!(check_order(&market, "0.101", "10").is_err());
+        assert!(check_order(&market, "1", "10.00001").is_err());
+        assert!(check_order(&market, "1", "0").is_err());
+        assert!(check_order(&market, "1", "-1").is_err());
+        assert!(check_order(&market, "one", "10").is_err());
+        market.open = false;
+        assert!(check_order(&market, "1", "10").is_err());
     }
 }
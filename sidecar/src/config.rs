@@ -15,11 +15,143 @@
 use clap::Parser;
 use serde::{Deserialize, Serialize};
 
+/// where the trading keys are kept
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Storage {
+    /// embedded in `db_dir`
+    #[default]
+    Rocksdb,
+    /// nothing is written to the disk, the users register the keys again after restarting
+    Memory,
+}
+
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct Config {
     pub prover: String,
+    /// the same as `fusotao.x25519_priv` of galois, deriving the keys of the users and pinning the
+    /// public key of galois on handshaking
+    pub x25519_priv: String,
+    /// only required by `rocksdb`
+    #[serde(default)]
     pub db_dir: String,
+    #[serde(default)]
+    pub storage: Storage,
+    pub bind_addr: String,
+    /// terminate TLS on `bind_addr`, the websocket endpoint is plain if absent
+    #[serde(default)]
+    pub tls: Option<TlsConfig>,
+    /// the REST gateway is disabled if absent
+    #[serde(default)]
+    pub rest_addr: Option<String>,
+    /// the webhooks are disabled if absent
+    #[serde(default)]
+    pub webhook: Option<WebhookConfig>,
+    /// the Binance compatible gateway is disabled if absent
+    #[serde(default)]
+    pub binance: Option<BinanceConfig>,
+    #[serde(default)]
+    pub signature: SignatureConfig,
+}
+
+/// the signatures v2 expire and are bound to `domain`, so they can't be replayed across the
+/// environments, e.g. from the testnet to the mainnet
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct SignatureConfig {
+    #[serde(default = "default_domain")]
+    pub domain: String,
+    /// reject the signatures v1 without expiry once the clients upgraded
+    #[serde(default = "default_accept_v1")]
+    pub accept_v1: bool,
+    /// in seconds, the expiries later than that from now are rejected as well
+    #[serde(default = "default_max_expiry")]
+    pub max_expiry: u64,
+}
+
+impl Default for SignatureConfig {
+    fn default() -> Self {
+        Self {
+            domain: default_domain(),
+            accept_v1: default_accept_v1(),
+            max_expiry: default_max_expiry(),
+        }
+    }
+}
+
+/// the orders placed through the Binance compatible gateway are relayed by `broker`
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct BinanceConfig {
     pub bind_addr: String,
+    pub broker: String,
+    /// in seconds, kept alive by `PUT /api/v3/userDataStream`
+    #[serde(default = "default_listen_key_ttl")]
+    pub listen_key_ttl: u64,
+}
+
+/// PEM files, reloaded for the new connections once modified
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct TlsConfig {
+    pub cert_path: String,
+    pub key_path: String,
+}
+
+/// the failed deliveries are retried after `backoff` doubled each time up to `max_backoff`, all in
+/// milliseconds
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct WebhookConfig {
+    #[serde(default = "default_max_attempts")]
+    pub max_attempts: u32,
+    #[serde(default = "default_backoff")]
+    pub backoff: u64,
+    #[serde(default = "default_max_backoff")]
+    pub max_backoff: u64,
+    #[serde(default = "default_timeout")]
+    pub timeout: u64,
+    /// of each user
+    #[serde(default = "default_max_webhooks")]
+    pub max_webhooks: usize,
+}
+
+fn default_max_attempts() -> u32 {
+    8
+}
+
+fn default_backoff() -> u64 {
+    1000
+}
+
+fn default_max_backoff() -> u64 {
+    60_000
+}
+
+fn default_timeout() -> u64 {
+    5000
+}
+
+fn default_max_webhooks() -> usize {
+    5
+}
+
+fn default_listen_key_ttl() -> u64 {
+    3600
+}
+
+#[cfg(feature = "testenv")]
+fn default_domain() -> String {
+    "fusotao-testnet".to_string()
+}
+
+#[cfg(not(feature = "testenv"))]
+fn default_domain() -> String {
+    "fusotao".to_string()
+}
+
+fn default_accept_v1() -> bool {
+    true
+}
+
+fn default_max_expiry() -> u64 {
+    300
 }
 
 #[derive(Debug, Parser)]
@@ -36,5 +168,84 @@ pub fn init_config_file() -> anyhow::Result<Config> {
 
 fn init_config(toml: &str) -> anyhow::Result<Config> {
     let cfg: Config = toml::from_str(toml)?;
+    galois_engine::input::cipher::public_of(&cfg.x25519_priv)
+        .map_err(|_| anyhow::anyhow!("x25519_priv: must be 32 bytes in hex"))?;
     Ok(cfg)
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    pub fn test_storage() {
+        let cfg = init_config(
+            r#"
+prover = "127.0.0.1:8097"
+x25519_priv = "0xedcff0c69e4c0fa7e9a36e2e6d07f2cc355c8d25907a0ad2ab7e03b24f8e90f3"
+db_dir = "/tmp/sidecar"
+bind_addr = "127.0.0.1:8098"
+"#,
+        )
+        .unwrap();
+        assert_eq!(Storage::Rocksdb, cfg.storage);
+        let cfg = init_config(
+            r#"
+prover = "127.0.0.1:8097"
+x25519_priv = "0xedcff0c69e4c0fa7e9a36e2e6d07f2cc355c8d25907a0ad2ab7e03b24f8e90f3"
+bind_addr = "127.0.0.1:8098"
+storage = "memory"
+"#,
+        )
+        .unwrap();
+        assert_eq!(Storage::Memory, cfg.storage);
+        assert!(cfg.webhook.is_none());
+        assert!(cfg.tls.is_none());
+        let cfg = init_config(
+            r#"
+prover = "127.0.0.1:8097"
+x25519_priv = "0xedcff0c69e4c0fa7e9a36e2e6d07f2cc355c8d25907a0ad2ab7e03b24f8e90f3"
+bind_addr = "127.0.0.1:8098"
+
+[webhook]
+max_attempts = 3
+"#,
+        )
+        .unwrap();
+        let webhook = cfg.webhook.unwrap();
+        assert_eq!(3, webhook.max_attempts);
+        assert_eq!(60_000, webhook.max_backoff);
+        assert!(cfg.binance.is_none());
+        let cfg = init_config(
+            r#"
+prover = "127.0.0.1:8097"
+x25519_priv = "0xedcff0c69e4c0fa7e9a36e2e6d07f2cc355c8d25907a0ad2ab7e03b24f8e90f3"
+bind_addr = "127.0.0.1:8098"
+
+[binance]
+bind_addr = "127.0.0.1:8094"
+broker = "5GrwvaEF5zXb26Fz9rcQpDWS57CtERHpNehXCPcNoHGKutQY"
+"#,
+        )
+        .unwrap();
+        assert_eq!(3600, cfg.binance.unwrap().listen_key_ttl);
+        assert!(cfg.signature.accept_v1);
+        assert_eq!(300, cfg.signature.max_expiry);
+        let cfg = init_config(
+            r#"
+prover = "127.0.0.1:8097"
+x25519_priv = "0xedcff0c69e4c0fa7e9a36e2e6d07f2cc355c8d25907a0ad2ab7e03b24f8e90f3"
+bind_addr = "127.0.0.1:8098"
+
+[signature]
+domain = "local"
+accept_v1 = false
+"#,
+        )
+        .unwrap();
+        assert_eq!("local", cfg.signature.domain);
+        assert!(!cfg.signature.accept_v1);
+        assert!(init_config("prover = \"\"\nbind_addr = \"\"\nstorage = \"mysql\"").is_err());
+        assert!(init_config("prover = \"\"\nbind_addr = \"\"\nx25519_priv = \"0x01\"").is_err());
+    }
+}
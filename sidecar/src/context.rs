@@ -14,30 +14,35 @@
 
 use crate::{
     backend::BackendConnection,
-    config::Config,
+    config::{Config, SignatureConfig},
     db,
     endpoint::{PendingOrderWrapper, TradingCommand},
     errors::CustomRpcError,
-    AccountId32, Sr25519Pair, Sr25519Public, Sr25519Signature,
+    webhook::{WebhookEvent, Webhooks},
+    AccountId32, SignedNonce,
 };
 use dashmap::DashMap;
-use galois_engine::{core::*, fusotao::OffchainSymbol, input, orders::PendingOrder, output::Depth};
+use galois_engine::{
+    core::*,
+    fusotao::OffchainSymbol,
+    input,
+    output::{Depth, DepthDelta, Trade},
+    statement::TransferReport,
+};
 use hyper::{Body, Request, Response};
-use parity_scale_codec::{Decode, Encode};
-use rocksdb::DB;
-use rust_decimal::Decimal;
-use sp_core::crypto::{Pair as Crypto, Ss58Codec};
+use parity_scale_codec::Encode;
+use sp_core::crypto::Ss58Codec;
 use std::{
     collections::BTreeSet,
     error::Error,
     future::Future,
     pin::Pin,
-    str::FromStr,
     sync::atomic::AtomicBool,
     sync::Arc,
     task::{Context as TaskCtx, Poll},
 };
 use tokio::sync::{
+    broadcast,
     mpsc::{self, UnboundedSender},
     Mutex,
 };
@@ -47,7 +52,7 @@ use x25519_dalek::StaticSecret;
 pub struct Context {
     pub backend: BackendConnection,
     pub x25519: StaticSecret,
-    pub db: DB,
+    pub db: Arc<dyn db::KeyStore>,
     pub subscribers: Arc<DashMap<String, UnboundedSender<(String, PendingOrderWrapper)>>>,
     // broker -> channel<symbol> map to notify the active brokers
     pub active_brokers: Arc<DashMap<String, UnboundedSender<Symbol>>>,
@@ -55,15 +60,34 @@ pub struct Context {
     pub orderbooks: Arc<DashMap<Symbol, Depth>>,
     pub markets: Arc<DashMap<Symbol, (Arc<AtomicBool>, OffchainSymbol)>>,
     pub session_nonce: Arc<DashMap<String, Session>>,
+    // market data streams, the subscribers filter them by symbol or user
+    pub depth_updates: broadcast::Sender<DepthDelta>,
+    pub trades: broadcast::Sender<(Symbol, Vec<Trade>)>,
+    pub order_updates: broadcast::Sender<(String, PendingOrderWrapper)>,
+    pub webhooks: Option<Arc<Webhooks>>,
+    pub signature: SignatureConfig,
 }
 
+const MARKET_DATA_CAPACITY: usize = 4096;
+
+// the markets opened or closed on chain are picked up by the pre-validation
+const MARKETS_REFRESH_INTERVAL: std::time::Duration = std::time::Duration::from_secs(30);
+
 impl Context {
     pub fn new(config: Config) -> Self {
+        let db = db::open(&config).unwrap();
+        let webhooks = config
+            .webhook
+            .clone()
+            .map(|c| Arc::new(Webhooks::new(c, db.clone())));
         let (broadcast, mut dispatcher) = mpsc::unbounded_channel();
-        let backend = BackendConnection::new(config.prover, broadcast);
-        let conn = backend.clone();
-        let x25519 = futures::executor::block_on(async move { conn.get_x25519().await }).unwrap();
-        let db = DB::open_default(&config.db_dir).unwrap();
+        let key: [u8; 32] = crate::hexstr_to_vec(&config.x25519_priv)
+            .ok()
+            .and_then(|k| k.try_into().ok())
+            .expect("checked;qed");
+        let x25519 = StaticSecret::from(key);
+        let pinned = input::cipher::public_of(&config.x25519_priv).expect("checked;qed");
+        let backend = BackendConnection::new(config.prover, pinned, broadcast);
         let subscribers = Arc::new(DashMap::<
             String,
             UnboundedSender<(String, PendingOrderWrapper)>,
@@ -82,6 +106,21 @@ impl Context {
         })
         .unwrap();
         log::debug!("Loading marketings from backend: {:?}", markets);
+        let (conn, refreshing) = (backend.clone(), markets.clone());
+        tokio::spawn(async move {
+            loop {
+                tokio::time::sleep(MARKETS_REFRESH_INTERVAL).await;
+                match conn.get_markets().await {
+                    Ok(markets) => markets.into_iter().for_each(|m| {
+                        refreshing
+                            .entry(m.symbol)
+                            .and_modify(|e| e.1 = m.clone())
+                            .or_insert_with(|| (Arc::new(AtomicBool::new(false)), m));
+                    }),
+                    Err(e) => log::debug!("refreshing markets failed: {:?}", e),
+                }
+            }
+        });
         let conn = backend.clone();
         let orderbooks = futures::executor::block_on(async move {
             conn.get_orderbooks().await.map(|orderbooks| {
@@ -90,8 +129,14 @@ impl Context {
             })
         })
         .unwrap();
+        let (depth_updates, _) = broadcast::channel(MARKET_DATA_CAPACITY);
+        let (trades, _) = broadcast::channel(MARKET_DATA_CAPACITY);
+        let (order_updates, _) = broadcast::channel(MARKET_DATA_CAPACITY);
         let sub = subscribers.clone();
         let depth = orderbooks.clone();
+        let (depth_tx, trades_tx, orders_tx) =
+            (depth_updates.clone(), trades.clone(), order_updates.clone());
+        let hooks = webhooks.clone();
         // let notify_when_depth_updated = active_brokers.clone();
         tokio::spawn(async move {
             loop {
@@ -102,8 +147,15 @@ impl Context {
                 let (typ, payload) = v.unwrap();
                 match typ {
                     input::ORDER_MATCHED => {
-                        if let Ok(o) = serde_json::from_value::<PendingOrder>(payload) {
-                            let user_id = o.user_id.to_string();
+                        if let Ok(o) = serde_json::from_value::<FillReport>(payload.clone()) {
+                            if let Some((hooks, event)) =
+                                hooks.as_ref().zip(WebhookEvent::of_order(o.order.status))
+                            {
+                                hooks.notify(&AccountId32::new(o.order.user_id.0), event, &payload);
+                            }
+                            let user_id = o.order.user_id.to_string();
+                            // no receivers is fine
+                            let _ = orders_tx.send((user_id.clone(), o.clone().into()));
                             let r = if let Some(u) = sub.get(&user_id) {
                                 u.value().send((user_id.clone(), o.into()))
                             } else {
@@ -118,9 +170,34 @@ impl Context {
                             }
                         }
                     }
-                    input::DEPTH_UPDATED => {
-                        if let Ok(d) = serde_json::from_value::<Depth>(payload) {
-                            depth.insert(d.symbol.clone(), d);
+                    input::DEPTH_DELTA => {
+                        if let Ok(d) = serde_json::from_value::<DepthDelta>(payload) {
+                            depth
+                                .entry(d.symbol)
+                                .or_insert_with(|| Depth {
+                                    asks: vec![],
+                                    bids: vec![],
+                                    symbol: d.symbol,
+                                })
+                                .apply(&d);
+                            let _ = depth_tx.send(d);
+                        }
+                    }
+                    input::TRANSFER_EXECUTED => {
+                        let reports = serde_json::from_value::<Vec<TransferReport>>(payload);
+                        if let Some((hooks, reports)) = hooks.as_ref().zip(reports.ok()) {
+                            for r in reports {
+                                let data = serde_json::to_value(&r).unwrap_or_default();
+                                let user_id = AccountId32::new(r.user_id.0);
+                                hooks.notify(&user_id, WebhookEvent::Transfer, &data);
+                            }
+                        }
+                    }
+                    input::TRADE_EXECUTED => {
+                        if let Ok(t) = serde_json::from_value::<Vec<Trade>>(payload) {
+                            if let Some(symbol) = t.first().map(|t| t.symbol) {
+                                let _ = trades_tx.send((symbol, t));
+                            }
                         }
                     }
                     _ => {}
@@ -136,15 +213,51 @@ impl Context {
             subscribers,
             markets,
             session_nonce: Arc::new(DashMap::default()),
+            depth_updates,
+            trades,
+            order_updates,
+            webhooks,
+            signature: config.signature,
         }
     }
 
-    pub async fn get_user_nonce(&self, user_id: &String) -> anyhow::Result<u32> {
-        let session = self
+    pub async fn get_user_nonce(&self, user_id: &AccountId32) -> anyhow::Result<u32> {
+        Ok(self.session(user_id)?.get_nonce().await)
+    }
+
+    /// the signatures v1 without expiry are rejected unless `accept_v1`
+    pub fn check_expiry(&self, expiry: Option<u64>) -> anyhow::Result<()> {
+        anyhow::ensure!(
+            expiry.is_some() || self.signature.accept_v1,
+            CustomRpcError::invalid_signature()
+        );
+        crate::check_expiry(expiry, crate::now_millis(), self.signature.max_expiry)
+    }
+
+    /// the nonces of the user start over from `init_nonce` after registering the trading key
+    pub fn open_session(&self, user_id: &AccountId32, init_nonce: u32) -> anyhow::Result<()> {
+        self.db.put_nonce(user_id, init_nonce)?;
+        self.session_nonce
+            .insert(user_id.to_ss58check(), Session::new(init_nonce));
+        Ok(())
+    }
+
+    /// the sessions are restored from the last nonces persisted after rebooting
+    fn session(&self, user_id: &AccountId32) -> anyhow::Result<Session> {
+        let ss58 = user_id.to_ss58check();
+        if let Some(session) = self.session_nonce.get(&ss58) {
+            return Ok(session.value().clone());
+        }
+        let nonce = self
+            .db
+            .get_nonce(user_id)?
+            .ok_or(CustomRpcError::user_not_found())?;
+        Ok(self
             .session_nonce
-            .get(user_id)
-            .ok_or_else(|| CustomRpcError::user_not_found())?;
-        Ok(session.value().get_nonce().await)
+            .entry(ss58)
+            .or_insert_with(|| Session::new(nonce))
+            .value()
+            .clone())
     }
 
     // FIXME maybe we could calculate the shared secret on each request
@@ -156,19 +269,14 @@ impl Context {
         sig: &[u8],
         nonce: &[u8],
     ) -> anyhow::Result<()> {
-        let mut decode = nonce.clone();
-        let n = u32::decode(&mut decode)?;
-        let key = db::query_trading_key(&self.db, user_id)?;
-        // FIXME when sidecar reboot, the session_nonce will be empty
-        let session = self
-            .session_nonce
-            .get(&user_id.to_ss58check())
-            .ok_or(CustomRpcError::user_not_found())?;
-        session.value().try_occupy_nonce(n).await?;
-        let mut to_be_signed = vec![];
-        to_be_signed.extend_from_slice(data);
-        to_be_signed.extend_from_slice(key.as_slice());
-        to_be_signed.extend_from_slice(nonce);
+        let signed = SignedNonce::decode(nonce)?;
+        self.check_expiry(signed.expiry)?;
+        let key = db::query_trading_key(&*self.db, user_id)?;
+        let session = self.session(user_id)?;
+        session.try_occupy_nonce(signed.nonce).await?;
+        self.db.put_nonce(user_id, session.last_nonce().await)?;
+        let to_be_signed =
+            crate::trading_payload(&self.signature.domain, data, key.as_slice(), nonce);
         log::debug!("sig content: {}", hex::encode(&to_be_signed));
         let hash = sp_core::blake2_256(&to_be_signed);
         log::debug!("user sign content: {}", hex::encode(&sig));
@@ -195,60 +303,32 @@ impl Context {
                     Ok(())
                 }
             }
-            TradingCommand::Ask {
-                base,
-                quote,
-                amount,
-                price,
-            } => {
-                let open = self
-                    .markets
-                    .get(&(*base, *quote))
-                    .ok_or(anyhow::anyhow!("symbol not exists"))?;
-                let market = open.value();
-                let amount = Decimal::from_str(amount)?;
-                let price = Decimal::from_str(price)?;
+            TradingCommand::CancelAll { base, quote } => {
                 anyhow::ensure!(
-                    amount >= market.1.min_base
-                        && price.is_sign_positive()
-                        && price.scale() <= market.1.quote_scale.into()
-                        && amount.is_sign_positive()
-                        && amount.scale() <= market.1.base_scale.into(),
-                    "invalid numeric"
+                    self.markets.contains_key(&(*base, *quote)),
+                    "symbol not exists"
                 );
-                let mut account = self.backend.get_account(user_id).await?;
+                Ok(())
+            }
+            TradingCommand::SubTransfer { bot, amount, .. } => {
+                crate::try_into_account(bot.clone())?;
                 anyhow::ensure!(
-                    account.remove(base).unwrap_or_default().available >= amount,
-                    "insufficient balance"
+                    amount
+                        .parse::<Amount>()
+                        .ok()
+                        .filter(|a| *a > Amount::ZERO)
+                        .is_some(),
+                    "invalid amount"
                 );
                 Ok(())
             }
-            TradingCommand::Bid {
-                base,
-                quote,
-                amount,
-                price,
-            } => {
+            TradingCommand::Ask { base, quote, .. } | TradingCommand::Bid { base, quote, .. } => {
                 let market = self
                     .markets
                     .get(&(*base, *quote))
+                    .map(|m| m.value().1.clone())
                     .ok_or(anyhow::anyhow!("symbol not exists"))?;
-                let amount = Decimal::from_str(amount)?;
-                let price = Decimal::from_str(price)?;
-                anyhow::ensure!(
-                    amount >= market.1.min_base
-                        && price.is_sign_positive()
-                        && price.scale() <= market.1.quote_scale.into()
-                        && amount.is_sign_positive()
-                        && amount.scale() <= market.1.base_scale.into(),
-                    "invalid numeric"
-                );
-                let mut account = self.backend.get_account(user_id).await?;
-                anyhow::ensure!(
-                    account.remove(quote).unwrap_or_default().available >= amount * price,
-                    "insufficient balance"
-                );
-                Ok(())
+                self.backend.pre_validate(user_id, &market, cmd).await
             }
         }
     }
@@ -329,16 +409,14 @@ where
             }
             let sig_hex =
                 hex::decode(signature.trim_start_matches("0x")).map_err(|_| anyhow::anyhow!(""))?;
-            let raw_signature =
-                Sr25519Signature::decode(&mut &sig_hex[..]).map_err(|_| anyhow::anyhow!(""))?;
-            let public = AccountId32::from_ss58check(ss58)
-                .map_err(|_| anyhow::anyhow!(""))
-                .map(|a| Sr25519Public::from_raw(*a.as_ref()))?;
+            let account = AccountId32::from_ss58check(ss58).map_err(|_| anyhow::anyhow!(""))?;
             let to_be_signed = nonce.encode();
-            log::debug!("sr25519 pubkey: 0x{}", hex::encode(&public));
+            log::debug!("broker pubkey: 0x{}", hex::encode(&account));
             log::debug!("to be signed: 0x{}", hex::encode(&to_be_signed));
-            log::debug!("signature: 0x{}", hex::encode(&raw_signature));
-            let verified = Sr25519Pair::verify(&raw_signature, to_be_signed, &public);
+            log::debug!("signature: 0x{}", hex::encode(&sig_hex));
+            // the brokers sign by sr25519 or ed25519
+            let verified =
+                crate::verify_sr25519_or_ed25519(sig_hex, &to_be_signed, &account).is_ok();
             log::debug!("verified: {}", verified);
             if verified {
                 inner.call(req).await.map_err(|e| e.into())
@@ -378,13 +456,20 @@ impl Session {
     }
 
     pub async fn get_nonce(&self) -> u32 {
+        self.last_nonce().await + 1
+    }
+
+    /// persisted, the nonces up to it are rejected after rebooting
+    pub async fn last_nonce(&self) -> u32 {
         let occupied_nonce = self.occupied_nonce.lock().await;
-        *occupied_nonce.last().expect("at least max;qed") + 1
+        *occupied_nonce.last().expect("at least max;qed")
     }
 }
 
 #[test]
 pub fn validate_signature_should_work() {
+    use crate::Sr25519Pair;
+    use sp_core::Pair;
     let nonce = 83143.encode();
     let seed = "e5be9a5092b81bca64be81d212e7f2f9eba183bb7a90954f7b76361f6edb5c0a";
     let key: [u8; 32] = hex::decode(seed).unwrap().try_into().unwrap();
@@ -395,6 +480,9 @@ pub fn validate_signature_should_work() {
 
 #[test]
 pub fn validate_deser_signature_should_work() {
+    use crate::{Sr25519Pair, Sr25519Public, Sr25519Signature};
+    use parity_scale_codec::Decode;
+    use sp_core::Pair;
     let nonce = 83143.encode();
     let signature = "0x8a44a5e17f9bfa67330d9dbf28afee1e81ea86678beb240b4259cfcaa6c2753a3e2df60afd52171360372d3460b041fc3596ab41b6c7fc30142b091139ba5f89";
     let ss58 = "5GrwvaEF5zXb26Fz9rcQpDWS57CtERHpNehXCPcNoHGKutQY";
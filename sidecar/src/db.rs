@@ -12,17 +12,175 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use crate::AccountId32;
+use crate::{
+    config::{Config, Storage},
+    webhook::Webhook,
+    AccountId32,
+};
+use dashmap::DashMap;
+use std::sync::Arc;
 
-pub fn query_trading_key(db: &rocksdb::DB, user_id: &AccountId32) -> anyhow::Result<Vec<u8>> {
+/// the trading keys negotiated with the users, the last nonces they signed and the webhooks
+/// registered by them
+pub trait KeyStore: Send + Sync {
+    fn get(&self, user_id: &AccountId32) -> anyhow::Result<Option<Vec<u8>>>;
+
+    fn put(&self, user_id: &AccountId32, key: [u8; 32]) -> anyhow::Result<()>;
+
+    fn get_nonce(&self, user_id: &AccountId32) -> anyhow::Result<Option<u32>>;
+
+    fn put_nonce(&self, user_id: &AccountId32, nonce: u32) -> anyhow::Result<()>;
+
+    fn get_webhooks(&self, user_id: &AccountId32) -> anyhow::Result<Vec<Webhook>>;
+
+    fn put_webhooks(&self, user_id: &AccountId32, webhooks: &[Webhook]) -> anyhow::Result<()>;
+}
+
+fn webhooks_key(user_id: &AccountId32) -> Vec<u8> {
+    [&b"webhooks"[..], user_id.as_ref()].concat()
+}
+
+fn nonce_key(user_id: &AccountId32) -> Vec<u8> {
+    [&b"nonce"[..], user_id.as_ref()].concat()
+}
+
+impl KeyStore for rocksdb::DB {
+    fn get(&self, user_id: &AccountId32) -> anyhow::Result<Option<Vec<u8>>> {
+        Ok(rocksdb::DB::get(self, user_id)?)
+    }
+
+    fn put(&self, user_id: &AccountId32, key: [u8; 32]) -> anyhow::Result<()> {
+        Ok(rocksdb::DB::put(self, user_id, key)?)
+    }
+
+    fn get_nonce(&self, user_id: &AccountId32) -> anyhow::Result<Option<u32>> {
+        match rocksdb::DB::get(self, nonce_key(user_id))? {
+            Some(v) => Ok(Some(u32::from_be_bytes(
+                v.as_slice()
+                    .try_into()
+                    .map_err(|_| anyhow::anyhow!("invalid nonce"))?,
+            ))),
+            None => Ok(None),
+        }
+    }
+
+    fn put_nonce(&self, user_id: &AccountId32, nonce: u32) -> anyhow::Result<()> {
+        Ok(rocksdb::DB::put(
+            self,
+            nonce_key(user_id),
+            nonce.to_be_bytes(),
+        )?)
+    }
+
+    fn get_webhooks(&self, user_id: &AccountId32) -> anyhow::Result<Vec<Webhook>> {
+        match rocksdb::DB::get(self, webhooks_key(user_id))? {
+            Some(v) => Ok(serde_json::from_slice(&v)?),
+            None => Ok(vec![]),
+        }
+    }
+
+    fn put_webhooks(&self, user_id: &AccountId32, webhooks: &[Webhook]) -> anyhow::Result<()> {
+        match webhooks.is_empty() {
+            true => Ok(rocksdb::DB::delete(self, webhooks_key(user_id))?),
+            false => Ok(rocksdb::DB::put(
+                self,
+                webhooks_key(user_id),
+                serde_json::to_vec(webhooks)?,
+            )?),
+        }
+    }
+}
+
+/// the keys are lost on restarting, the users have to register them again
+#[derive(Default)]
+pub struct MemoryStore {
+    keys: DashMap<AccountId32, [u8; 32]>,
+    nonces: DashMap<AccountId32, u32>,
+    webhooks: DashMap<AccountId32, Vec<Webhook>>,
+}
+
+impl KeyStore for MemoryStore {
+    fn get(&self, user_id: &AccountId32) -> anyhow::Result<Option<Vec<u8>>> {
+        Ok(self.keys.get(user_id).map(|k| k.to_vec()))
+    }
+
+    fn put(&self, user_id: &AccountId32, key: [u8; 32]) -> anyhow::Result<()> {
+        self.keys.insert(user_id.clone(), key);
+        Ok(())
+    }
+
+    fn get_nonce(&self, user_id: &AccountId32) -> anyhow::Result<Option<u32>> {
+        Ok(self.nonces.get(user_id).map(|n| *n.value()))
+    }
+
+    fn put_nonce(&self, user_id: &AccountId32, nonce: u32) -> anyhow::Result<()> {
+        self.nonces.insert(user_id.clone(), nonce);
+        Ok(())
+    }
+
+    fn get_webhooks(&self, user_id: &AccountId32) -> anyhow::Result<Vec<Webhook>> {
+        Ok(self
+            .webhooks
+            .get(user_id)
+            .map(|w| w.value().clone())
+            .unwrap_or_default())
+    }
+
+    fn put_webhooks(&self, user_id: &AccountId32, webhooks: &[Webhook]) -> anyhow::Result<()> {
+        if webhooks.is_empty() {
+            self.webhooks.remove(user_id);
+        } else {
+            self.webhooks.insert(user_id.clone(), webhooks.to_vec());
+        }
+        Ok(())
+    }
+}
+
+pub fn open(config: &Config) -> anyhow::Result<Arc<dyn KeyStore>> {
+    match config.storage {
+        Storage::Rocksdb => {
+            anyhow::ensure!(!config.db_dir.is_empty(), "db_dir is required by rocksdb");
+            Ok(Arc::new(rocksdb::DB::open_default(&config.db_dir)?))
+        }
+        Storage::Memory => Ok(Arc::new(MemoryStore::default())),
+    }
+}
+
+pub fn query_trading_key(db: &dyn KeyStore, user_id: &AccountId32) -> anyhow::Result<Vec<u8>> {
     db.get(user_id)?.ok_or(anyhow::anyhow!("Key expired"))
 }
 
 pub fn save_trading_key(
-    db: &rocksdb::DB,
+    db: &dyn KeyStore,
     user_id: &AccountId32,
     key: [u8; 32],
 ) -> anyhow::Result<()> {
-    db.put(user_id, key)?;
-    Ok(())
+    db.put(user_id, key)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    pub fn test_memory_store() {
+        let store = MemoryStore::default();
+        let user_id = AccountId32::new([1; 32]);
+        assert!(query_trading_key(&store, &user_id).is_err());
+        save_trading_key(&store, &user_id, [2; 32]).unwrap();
+        assert_eq!(vec![2; 32], query_trading_key(&store, &user_id).unwrap());
+        assert!(query_trading_key(&store, &AccountId32::new([3; 32])).is_err());
+        assert_eq!(None, store.get_nonce(&user_id).unwrap());
+        store.put_nonce(&user_id, 83143).unwrap();
+        assert_eq!(Some(83143), store.get_nonce(&user_id).unwrap());
+        let hooks = vec![Webhook {
+            url: "https://a.com/".to_string(),
+            secret: "s".to_string(),
+            events: vec![crate::webhook::WebhookEvent::Transfer],
+        }];
+        store.put_webhooks(&user_id, &hooks).unwrap();
+        assert_eq!(hooks, store.get_webhooks(&user_id).unwrap());
+        store.put_webhooks(&user_id, &[]).unwrap();
+        assert!(store.get_webhooks(&user_id).unwrap().is_empty());
+    }
 }
@@ -0,0 +1,833 @@
+// Copyright 2021-2023 UINB Technologies Pte. Ltd.
+
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// 	http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::{
+    config::BinanceConfig,
+    context::Context,
+    db,
+    endpoint::{PendingOrderWrapper, TradingCommand},
+    now_millis, AccountId32,
+};
+use dashmap::DashMap;
+use futures::{SinkExt, StreamExt};
+use galois_engine::{core::*, orderbook::Level};
+use hmac::{Hmac, Mac};
+use hyper::{
+    header::CONTENT_TYPE,
+    service::{make_service_fn, service_fn},
+    Body, Method, Request, Response, Server, StatusCode,
+};
+use hyper_tungstenite::tungstenite::Message as WsMessage;
+use rand::Rng;
+use rust_decimal::Decimal;
+use serde_json::{json, Value as JsonValue};
+use sp_core::crypto::Ss58Codec;
+use std::{
+    collections::HashMap,
+    convert::Infallible,
+    net::SocketAddr,
+    str::FromStr,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+use tokio::sync::broadcast;
+
+pub const DEFAULT_RECV_WINDOW: u64 = 5000;
+pub const MAX_RECV_WINDOW: u64 = 60000;
+pub const DEFAULT_DEPTH_LIMIT: u32 = 100;
+pub const MAX_DEPTH_LIMIT: u32 = 5000;
+
+/// the spot api v3 of Binance mapped onto galois, so the existing bots could connect by changing
+/// the base url, the symbols are named `{base}_{quote}` of the currency ids, the api key is the
+/// address of the user and the secret is the hex of the trading key
+pub async fn serve(
+    addr: SocketAddr,
+    config: BinanceConfig,
+    context: Arc<Context>,
+) -> anyhow::Result<()> {
+    let (canceled, _) = broadcast::channel(1024);
+    let gateway = Arc::new(Gateway {
+        ctx: context,
+        config,
+        listen_keys: DashMap::new(),
+        canceled,
+    });
+    let make = make_service_fn(move |_| {
+        let gateway = gateway.clone();
+        async move {
+            Ok::<_, Infallible>(service_fn(move |req| {
+                let gateway = gateway.clone();
+                async move { Ok::<_, Infallible>(gateway.route(req).await) }
+            }))
+        }
+    });
+    log::info!("Binance compatible gateway listening on {}", addr);
+    Server::bind(&addr).serve(make).await?;
+    Ok(())
+}
+
+struct Gateway {
+    ctx: Arc<Context>,
+    config: BinanceConfig,
+    // listen key -> (ss58 of the user, expiring at)
+    listen_keys: DashMap<String, (String, Instant)>,
+    // the orders canceled by the requests aren't broadcast by galois
+    canceled: broadcast::Sender<(String, PendingOrderWrapper)>,
+}
+
+/// replied as `{"code": .., "msg": ..}` the same as Binance
+#[derive(Debug, Clone, Eq, PartialEq)]
+struct BinanceError {
+    status: StatusCode,
+    code: i32,
+    msg: String,
+}
+
+impl BinanceError {
+    fn new(code: i32, msg: impl ToString) -> Self {
+        let status = match code {
+            -2014 | -2015 | -1022 => StatusCode::UNAUTHORIZED,
+            _ => StatusCode::BAD_REQUEST,
+        };
+        Self {
+            status,
+            code,
+            msg: msg.to_string(),
+        }
+    }
+
+    fn missing(param: &str) -> Self {
+        Self::new(
+            -1102,
+            format!(
+                "Mandatory parameter '{}' was not sent, was empty/null, or malformed.",
+                param
+            ),
+        )
+    }
+
+    fn invalid_symbol() -> Self {
+        Self::new(-1121, "Invalid symbol.")
+    }
+
+    fn unknown_order() -> Self {
+        Self::new(-2013, "Order does not exist.")
+    }
+
+    fn unknown(e: impl ToString) -> Self {
+        Self::new(-1000, e)
+    }
+}
+
+impl From<BinanceError> for Response<Body> {
+    fn from(e: BinanceError) -> Self {
+        reply(e.status, &json!({ "code": e.code, "msg": e.msg }))
+    }
+}
+
+type BinanceResult = Result<Response<Body>, BinanceError>;
+
+fn reply(status: StatusCode, body: &JsonValue) -> Response<Body> {
+    Response::builder()
+        .status(status)
+        .header(CONTENT_TYPE, "application/json")
+        .body(Body::from(serde_json::to_vec(body).unwrap_or_default()))
+        .expect("valid response;qed")
+}
+
+fn ok(body: JsonValue) -> BinanceResult {
+    Ok(reply(StatusCode::OK, &body))
+}
+
+/// the query string and the form body, kept raw for the signatures
+#[derive(Debug, Clone, Default)]
+struct Params {
+    query: String,
+    body: String,
+    values: HashMap<String, String>,
+}
+
+impl Params {
+    fn new(query: &str, body: &str) -> Self {
+        let values = query
+            .split('&')
+            .chain(body.split('&'))
+            .filter_map(|kv| kv.split_once('='))
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect();
+        Self {
+            query: query.to_string(),
+            body: body.to_string(),
+            values,
+        }
+    }
+
+    fn get(&self, key: &str) -> Option<&str> {
+        self.values
+            .get(key)
+            .map(|v| v.as_str())
+            .filter(|v| !v.is_empty())
+    }
+
+    fn require<T: FromStr>(&self, key: &str) -> Result<T, BinanceError> {
+        self.get(key)
+            .and_then(|v| v.parse().ok())
+            .ok_or_else(|| BinanceError::missing(key))
+    }
+
+    fn optional<T: FromStr>(&self, key: &str) -> Result<Option<T>, BinanceError> {
+        self.get(key).map(|_| self.require(key)).transpose()
+    }
+
+    /// the query string concatenated with the body, without the `signature`
+    fn total_params(&self) -> String {
+        let unsigned = |raw: &str| {
+            raw.split('&')
+                .filter(|kv| !kv.starts_with("signature="))
+                .collect::<Vec<_>>()
+                .join("&")
+        };
+        format!("{}{}", unsigned(&self.query), unsigned(&self.body))
+    }
+
+    fn symbol(&self) -> Result<Symbol, BinanceError> {
+        let symbol = self
+            .get("symbol")
+            .ok_or_else(|| BinanceError::missing("symbol"))?;
+        symbol_of(symbol).ok_or_else(BinanceError::invalid_symbol)
+    }
+}
+
+pub fn symbol_of(name: &str) -> Option<Symbol> {
+    let (base, quote) = name.split_once('_')?;
+    Some((base.parse().ok()?, quote.parse().ok()?))
+}
+
+pub fn symbol_name(symbol: &Symbol) -> String {
+    format!("{}_{}", symbol.0, symbol.1)
+}
+
+/// hex of HMAC-SHA256 over the total params keyed by the secret
+pub fn sign(secret: &[u8], total_params: &str) -> String {
+    let mut mac = Hmac::<sha2::Sha256>::new_from_slice(secret).expect("any size of key;qed");
+    mac.update(total_params.as_bytes());
+    hex::encode(mac.finalize().into_bytes())
+}
+
+fn side_of(direction: u8) -> &'static str {
+    match direction {
+        0 => "SELL",
+        _ => "BUY",
+    }
+}
+
+fn status_of(status: u16) -> &'static str {
+    match status {
+        0 => "NEW",
+        1 => "CANCELED",
+        2 => "FILLED",
+        3 => "PARTIALLY_FILLED",
+        _ => "EXPIRED",
+    }
+}
+
+fn decimal(v: &str) -> Decimal {
+    Decimal::from_str(v).unwrap_or_default()
+}
+
+/// the order of `GET /api/v3/order`, galois keeps no client order ids and the timestamps are in
+/// seconds
+fn to_order(o: &PendingOrderWrapper) -> JsonValue {
+    json!({
+        "symbol": symbol_name(&o.symbol),
+        "orderId": o.order_id,
+        "orderListId": -1,
+        "clientOrderId": "",
+        "price": o.price,
+        "origQty": o.amount,
+        "executedQty": o.matched_base_amount,
+        "cummulativeQuoteQty": o.matched_quote_amount,
+        "status": status_of(o.status),
+        "timeInForce": "GTC",
+        "type": "LIMIT",
+        "side": side_of(o.direction),
+        "stopPrice": "0",
+        "icebergQty": "0",
+        "time": o.create_timestamp * 1000,
+        "updateTime": o.create_timestamp * 1000,
+        "isWorking": true,
+        "origQuoteOrderQty": "0",
+    })
+}
+
+/// the `executionReport` of the user data stream, `last` is the filled base, quote and fee
+/// reported before
+fn to_execution_report(o: &PendingOrderWrapper, last: (Decimal, Decimal, Decimal)) -> JsonValue {
+    let (base, quote) = (
+        decimal(&o.matched_base_amount),
+        decimal(&o.matched_quote_amount),
+    );
+    // the bids are charged in base and the asks in quote
+    let (fee, fee_asset) = match o.direction {
+        0 => (decimal(&o.quote_fee), o.symbol.1),
+        _ => (decimal(&o.base_fee), o.symbol.0),
+    };
+    let last_qty = base - last.0;
+    let last_price = match last_qty.is_zero() {
+        true => Decimal::ZERO,
+        false => (quote - last.1) / last_qty,
+    };
+    let exec_type = match o.status {
+        _ if last_qty > Decimal::ZERO => "TRADE",
+        0 => "NEW",
+        1 => "CANCELED",
+        _ => "EXPIRED",
+    };
+    let now = now_millis();
+    json!({
+        "e": "executionReport",
+        "E": now,
+        "s": symbol_name(&o.symbol),
+        "c": "",
+        "S": side_of(o.direction),
+        "o": "LIMIT",
+        "f": "GTC",
+        "q": o.amount,
+        "p": o.price,
+        "P": "0",
+        "F": "0",
+        "g": -1,
+        "C": "",
+        "x": exec_type,
+        "X": status_of(o.status),
+        "r": "NONE",
+        "i": o.order_id,
+        "l": last_qty.normalize().to_string(),
+        "z": o.matched_base_amount,
+        "L": last_price.normalize().to_string(),
+        "n": (fee - last.2).normalize().to_string(),
+        "N": fee_asset.to_string(),
+        "T": now,
+        "t": -1,
+        "I": 0,
+        "w": o.status == 0 || o.status == 3,
+        "m": false,
+        "M": false,
+        "O": o.create_timestamp * 1000,
+        "Z": o.matched_quote_amount,
+        "Y": last_price * last_qty,
+        "Q": "0",
+    })
+}
+
+impl Gateway {
+    async fn route(self: Arc<Self>, req: Request<Body>) -> Response<Body> {
+        let path = req.uri().path().to_string();
+        if let Some(listen_key) = path.strip_prefix("/ws/") {
+            let listen_key = listen_key.to_string();
+            return self
+                .user_data_stream(req, listen_key)
+                .unwrap_or_else(|e| e.into());
+        }
+        let method = req.method().clone();
+        let api_key = req
+            .headers()
+            .get("X-MBX-APIKEY")
+            .and_then(|v| v.to_str().ok())
+            .map(|v| v.to_string());
+        let query = req.uri().query().unwrap_or_default().to_string();
+        let body = match hyper::body::to_bytes(req.into_body()).await {
+            Ok(body) => String::from_utf8_lossy(&body).to_string(),
+            Err(e) => return BinanceError::unknown(e).into(),
+        };
+        let params = Params::new(&query, &body);
+        let r = match (method, path.as_str()) {
+            (Method::GET, "/api/v3/ping") => ok(json!({})),
+            (Method::GET, "/api/v3/time") => ok(json!({ "serverTime": now_millis() })),
+            (Method::GET, "/api/v3/exchangeInfo") => self.exchange_info(),
+            (Method::GET, "/api/v3/depth") => self.depth(&params).await,
+            (method, "/api/v3/userDataStream") => {
+                self.listen_key(method, api_key.as_deref(), &params)
+            }
+            (method, path) => match self.authenticate(api_key.as_deref(), &params) {
+                Ok(user_id) => match (method, path) {
+                    (Method::POST, "/api/v3/order") => self.place_order(&user_id, &params).await,
+                    (Method::DELETE, "/api/v3/order") => self.cancel_order(&user_id, &params).await,
+                    (Method::GET, "/api/v3/order") => self.query_order(&user_id, &params).await,
+                    (Method::GET, "/api/v3/openOrders") => {
+                        self.open_orders(&user_id, &params).await
+                    }
+                    (Method::GET, "/api/v3/account") => self.account(&user_id).await,
+                    _ => Err(BinanceError {
+                        status: StatusCode::NOT_FOUND,
+                        code: -1000,
+                        msg: "not found".to_string(),
+                    }),
+                },
+                Err(e) => Err(e),
+            },
+        };
+        r.unwrap_or_else(|e| e.into())
+    }
+
+    fn account_of(api_key: Option<&str>) -> Result<AccountId32, BinanceError> {
+        api_key
+            .and_then(|k| crate::try_into_account(k.to_string()).ok())
+            .ok_or_else(|| BinanceError::new(-2014, "API-key format invalid."))
+    }
+
+    /// the `SIGNED` endpoints, returning the ss58 address of the user
+    fn authenticate(&self, api_key: Option<&str>, params: &Params) -> Result<String, BinanceError> {
+        let user_id = Self::account_of(api_key)?;
+        let timestamp: u64 = params.require("timestamp")?;
+        let recv_window = params
+            .optional::<u64>("recvWindow")?
+            .unwrap_or(DEFAULT_RECV_WINDOW);
+        if recv_window > MAX_RECV_WINDOW {
+            return Err(BinanceError::new(
+                -1131,
+                "recvWindow must be less than 60000",
+            ));
+        }
+        let now = now_millis();
+        if timestamp > now + 1000 || now.saturating_sub(timestamp) > recv_window {
+            return Err(BinanceError::new(
+                -1021,
+                "Timestamp for this request is outside of the recvWindow.",
+            ));
+        }
+        let signature = params
+            .get("signature")
+            .ok_or_else(|| BinanceError::missing("signature"))?;
+        let key = db::query_trading_key(&*self.ctx.db, &user_id).map_err(|_| {
+            BinanceError::new(-2015, "Invalid API-key, IP, or permissions for action.")
+        })?;
+        let expected = sign(hex::encode(key).as_bytes(), &params.total_params());
+        if !expected.eq_ignore_ascii_case(signature) {
+            return Err(BinanceError::new(
+                -1022,
+                "Signature for this request is not valid.",
+            ));
+        }
+        Ok(user_id.to_ss58check())
+    }
+
+    fn exchange_info(&self) -> BinanceResult {
+        let step = |scale: u8| Decimal::new(1, scale as u32).to_string();
+        let symbols = self
+            .ctx
+            .markets
+            .iter()
+            .map(|m| {
+                let market = &m.value().1;
+                json!({
+                    "symbol": symbol_name(&market.symbol),
+                    "status": if market.open { "TRADING" } else { "BREAK" },
+                    "baseAsset": market.symbol.0.to_string(),
+                    "baseAssetPrecision": market.base_scale,
+                    "quoteAsset": market.symbol.1.to_string(),
+                    "quotePrecision": market.quote_scale,
+                    "quoteAssetPrecision": market.quote_scale,
+                    "orderTypes": ["LIMIT"],
+                    "icebergAllowed": false,
+                    "ocoAllowed": false,
+                    "isSpotTradingAllowed": true,
+                    "isMarginTradingAllowed": false,
+                    "permissions": ["SPOT"],
+                    "filters": [
+                        {
+                            "filterType": "PRICE_FILTER",
+                            "minPrice": "0",
+                            "maxPrice": "0",
+                            "tickSize": step(market.quote_scale),
+                        },
+                        {
+                            "filterType": "LOT_SIZE",
+                            "minQty": market.min_base.to_string(),
+                            "maxQty": "0",
+                            "stepSize": step(market.base_scale),
+                        },
+                    ],
+                })
+            })
+            .collect::<Vec<_>>();
+        ok(json!({
+            "timezone": "UTC",
+            "serverTime": now_millis(),
+            "rateLimits": [],
+            "exchangeFilters": [],
+            "symbols": symbols,
+        }))
+    }
+
+    async fn depth(&self, params: &Params) -> BinanceResult {
+        let symbol = params.symbol()?;
+        let limit = params
+            .optional::<u32>("limit")?
+            .unwrap_or(DEFAULT_DEPTH_LIMIT)
+            .min(MAX_DEPTH_LIMIT);
+        let snapshot = self
+            .ctx
+            .backend
+            .query_depth(symbol, Some(limit), None)
+            .await
+            .map_err(|_| BinanceError::invalid_symbol())?;
+        let levels = |levels: &[Level]| {
+            levels
+                .iter()
+                .map(|(price, amount, _)| json!([price.to_string(), amount.to_string()]))
+                .collect::<Vec<_>>()
+        };
+        ok(json!({
+            "lastUpdateId": snapshot.update_id,
+            "bids": levels(&snapshot.depth.bids),
+            "asks": levels(&snapshot.depth.asks),
+        }))
+    }
+
+    async fn place_order(&self, user_id: &str, params: &Params) -> BinanceResult {
+        let (base, quote) = params.symbol()?;
+        if params.get("type") != Some("LIMIT") {
+            return Err(BinanceError::new(-1116, "Invalid orderType."));
+        }
+        if params.get("timeInForce").is_some_and(|t| t != "GTC") {
+            return Err(BinanceError::new(-1115, "Invalid timeInForce."));
+        }
+        let amount = params.require::<Decimal>("quantity")?.to_string();
+        let price = params.require::<Decimal>("price")?.to_string();
+        let cmd = match params.get("side") {
+            Some("BUY") => TradingCommand::Bid {
+                base,
+                quote,
+                amount,
+                price,
+            },
+            Some("SELL") => TradingCommand::Ask {
+                base,
+                quote,
+                amount,
+                price,
+            },
+            _ => return Err(BinanceError::new(-1117, "Invalid side.")),
+        };
+        let rejected = |e: anyhow::Error| BinanceError::new(-2010, e);
+        self.ctx
+            .validate_cmd(user_id, &cmd)
+            .await
+            .map_err(rejected)?;
+        let order_id = self
+            .ctx
+            .backend
+            .submit_trading_command(user_id, cmd, &self.config.broker)
+            .await
+            .map_err(rejected)?;
+        // the client order ids are only replied
+        ok(json!({
+            "symbol": symbol_name(&(base, quote)),
+            "orderId": order_id,
+            "orderListId": -1,
+            "clientOrderId": params.get("newClientOrderId").unwrap_or_default(),
+            "transactTime": now_millis(),
+        }))
+    }
+
+    async fn find_order(
+        &self,
+        user_id: &str,
+        symbol: Symbol,
+        order_id: u64,
+    ) -> Result<PendingOrderWrapper, BinanceError> {
+        self.ctx
+            .backend
+            .query_pending_orders(symbol, user_id)
+            .await
+            .map_err(BinanceError::unknown)?
+            .into_iter()
+            .find(|o| o.order_id == order_id)
+            .ok_or_else(BinanceError::unknown_order)
+    }
+
+    async fn cancel_order(&self, user_id: &str, params: &Params) -> BinanceResult {
+        let symbol = params.symbol()?;
+        let order_id = params.require::<u64>("orderId")?;
+        let mut order = self.find_order(user_id, symbol, order_id).await?;
+        let cmd = TradingCommand::Cancel {
+            base: symbol.0,
+            quote: symbol.1,
+            order_id,
+        };
+        let rejected = |e: anyhow::Error| BinanceError::new(-2011, e);
+        self.ctx
+            .validate_cmd(user_id, &cmd)
+            .await
+            .map_err(rejected)?;
+        self.ctx
+            .backend
+            .submit_trading_command(user_id, cmd, &self.config.broker)
+            .await
+            .map_err(rejected)?;
+        order.status = 1;
+        // no receivers is fine
+        let _ = self.canceled.send((user_id.to_string(), order.clone()));
+        ok(to_order(&order))
+    }
+
+    async fn query_order(&self, user_id: &str, params: &Params) -> BinanceResult {
+        let symbol = params.symbol()?;
+        let order_id = params.require::<u64>("orderId")?;
+        ok(to_order(&self.find_order(user_id, symbol, order_id).await?))
+    }
+
+    /// of all the markets if the symbol is absent
+    async fn open_orders(&self, user_id: &str, params: &Params) -> BinanceResult {
+        let symbols = match params.get("symbol") {
+            Some(_) => vec![params.symbol()?],
+            None => self.ctx.markets.iter().map(|m| *m.key()).collect(),
+        };
+        let mut orders = vec![];
+        for symbol in symbols {
+            let pending = self
+                .ctx
+                .backend
+                .query_pending_orders(symbol, user_id)
+                .await
+                .map_err(BinanceError::unknown)?;
+            orders.extend(pending.iter().map(to_order));
+        }
+        ok(JsonValue::Array(orders))
+    }
+
+    async fn account(&self, user_id: &str) -> BinanceResult {
+        let balances = self
+            .ctx
+            .backend
+            .get_account(user_id)
+            .await
+            .map_err(BinanceError::unknown)?
+            .into_iter()
+            .map(|(currency, b)| {
+                json!({
+                    "asset": currency.to_string(),
+                    "free": b.available.to_string(),
+                    "locked": b.frozen.to_string(),
+                })
+            })
+            .collect::<Vec<_>>();
+        ok(json!({
+            "makerCommission": 0,
+            "takerCommission": 0,
+            "buyerCommission": 0,
+            "sellerCommission": 0,
+            "canTrade": true,
+            "canWithdraw": false,
+            "canDeposit": false,
+            "updateTime": now_millis(),
+            "accountType": "SPOT",
+            "balances": balances,
+            "permissions": ["SPOT"],
+        }))
+    }
+
+    /// the listen keys are created, kept alive and closed with the api key only
+    fn listen_key(&self, method: Method, api_key: Option<&str>, params: &Params) -> BinanceResult {
+        let user_id = Self::account_of(api_key)?.to_ss58check();
+        let ttl = Duration::from_secs(self.config.listen_key_ttl);
+        self.listen_keys
+            .retain(|_, (_, expiring)| *expiring > Instant::now());
+        if method == Method::POST {
+            let key = hex::encode(rand::thread_rng().gen::<[u8; 32]>());
+            self.listen_keys
+                .insert(key.clone(), (user_id, Instant::now() + ttl));
+            return ok(json!({ "listenKey": key }));
+        }
+        let key = params
+            .get("listenKey")
+            .ok_or_else(|| BinanceError::missing("listenKey"))?;
+        let owned = self
+            .listen_keys
+            .get(key)
+            .filter(|v| v.0 == user_id)
+            .is_some();
+        if !owned {
+            return Err(BinanceError::new(-1125, "This listenKey does not exist."));
+        }
+        match method {
+            Method::PUT => {
+                if let Some(mut v) = self.listen_keys.get_mut(key) {
+                    v.1 = Instant::now() + ttl;
+                }
+            }
+            Method::DELETE => {
+                self.listen_keys.remove(key);
+            }
+            _ => return Err(BinanceError::unknown("not found")),
+        }
+        ok(json!({}))
+    }
+
+    /// `wss://../ws/<listenKey>` streaming the `executionReport` of the user until the key expired
+    fn user_data_stream(self: Arc<Self>, req: Request<Body>, listen_key: String) -> BinanceResult {
+        let user_id = self
+            .listen_keys
+            .get(&listen_key)
+            .filter(|v| v.1 > Instant::now())
+            .map(|v| v.0.clone())
+            .ok_or_else(|| BinanceError::new(-1125, "This listenKey does not exist."))?;
+        if !hyper_tungstenite::is_upgrade_request(&req) {
+            return Err(BinanceError::unknown("websocket upgrade required"));
+        }
+        let (response, websocket) =
+            hyper_tungstenite::upgrade(req, None).map_err(BinanceError::unknown)?;
+        let (mut updates, mut canceled) = (
+            self.ctx.order_updates.subscribe(),
+            self.canceled.subscribe(),
+        );
+        tokio::spawn(async move {
+            let mut ws = match websocket.await {
+                Ok(ws) => ws,
+                Err(e) => {
+                    log::debug!("user data stream upgrade failed, {:?}", e);
+                    return;
+                }
+            };
+            // (filled base, filled quote, fee) reported of the orders
+            let mut reported = HashMap::<(Symbol, u64), (Decimal, Decimal, Decimal)>::new();
+            loop {
+                let update = tokio::select! {
+                    u = updates.recv() => u,
+                    u = canceled.recv() => u,
+                    msg = ws.next() => match msg {
+                        Some(Ok(_)) => continue,
+                        _ => break,
+                    },
+                };
+                let alive = self
+                    .listen_keys
+                    .get(&listen_key)
+                    .filter(|v| v.1 > Instant::now())
+                    .is_some();
+                if !alive {
+                    break;
+                }
+                let order = match update {
+                    Ok((user, order)) if user == user_id => order,
+                    Ok(_) => continue,
+                    Err(broadcast::error::RecvError::Lagged(n)) => {
+                        log::debug!("user data stream lagged {} updates", n);
+                        continue;
+                    }
+                    Err(broadcast::error::RecvError::Closed) => break,
+                };
+                let key = (order.symbol, order.order_id);
+                let last = reported.get(&key).copied().unwrap_or_default();
+                let report = to_execution_report(&order, last);
+                if matches!(order.status, 1 | 2 | 4) {
+                    reported.remove(&key);
+                } else {
+                    let fee = match order.direction {
+                        0 => decimal(&order.quote_fee),
+                        _ => decimal(&order.base_fee),
+                    };
+                    let filled = (
+                        decimal(&order.matched_base_amount),
+                        decimal(&order.matched_quote_amount),
+                        fee,
+                    );
+                    reported.insert(key, filled);
+                }
+                if ws.send(WsMessage::Text(report.to_string())).await.is_err() {
+                    break;
+                }
+            }
+            let _ = ws.close(None).await;
+        });
+        Ok(response)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    pub fn test_binance_requests() {
+        // the example of the Binance docs
+        let params = Params::new(
+            "symbol=LTCBTC&side=BUY&type=LIMIT&timeInForce=GTC",
+            "quantity=1&price=0.1&recvWindow=5000&timestamp=1499827319559&signature=c8db56825ae71d6d79447849e617115f4a920fa2acdcab2b053c4b2838bd6b71",
+        );
+        assert_eq!(
+            "symbol=LTCBTC&side=BUY&type=LIMIT&timeInForce=GTCquantity=1&price=0.1&recvWindow=5000&timestamp=1499827319559",
+            params.total_params()
+        );
+        let secret = b"NhqPtmdSJYdKjVHjA7PZj4Mge3R5YNiP1e3UZjInClVN65XAbvqqM6A7H5fATj0j";
+        let payload = "symbol=LTCBTC&side=BUY&type=LIMIT&timeInForce=GTC&quantity=1&price=0.1&recvWindow=5000&timestamp=1499827319559";
+        assert_eq!(
+            "c8db56825ae71d6d79447849e617115f4a920fa2acdcab2b053c4b2838bd6b71",
+            sign(secret, payload)
+        );
+        assert_eq!(Some(5000), params.optional::<u64>("recvWindow").unwrap());
+        assert_eq!(Err(BinanceError::invalid_symbol()), params.symbol());
+        assert_eq!(-1102, params.require::<u64>("orderId").unwrap_err().code);
+        assert_eq!(Some((1, 0)), symbol_of("1_0"));
+        assert_eq!(None, symbol_of("1-0"));
+        assert_eq!("1_0", symbol_name(&(1, 0)));
+        assert_eq!(
+            StatusCode::UNAUTHORIZED,
+            Gateway::account_of(Some("5Grw")).unwrap_err().status
+        );
+    }
+
+    #[test]
+    pub fn test_binance_reports() {
+        let user_id = UserId::from_low_u64_be(1);
+        let mut order: PendingOrderWrapper = galois_engine::orders::PendingOrder {
+            order_id: 7,
+            user_id,
+            symbol: (1, 0),
+            direction: 1,
+            create_timestamp: 100,
+            amount: Decimal::from(2),
+            price: Decimal::new(105, 1),
+            status: 3,
+            matched_quote_amount: Decimal::new(5, 0),
+            matched_base_amount: Decimal::new(5, 1),
+            base_fee: Decimal::new(1, 3),
+            quote_fee: Decimal::ZERO,
+        }
+        .into();
+        let o = to_order(&order);
+        assert_eq!("1_0", o["symbol"]);
+        assert_eq!("BUY", o["side"]);
+        assert_eq!("PARTIALLY_FILLED", o["status"]);
+        assert_eq!(100000, o["time"]);
+        let r = to_execution_report(&order, Default::default());
+        assert_eq!("TRADE", r["x"]);
+        assert_eq!("0.5", r["l"]);
+        assert_eq!("10", r["L"]);
+        assert_eq!("0.001", r["n"]);
+        assert_eq!("1", r["N"]);
+        assert_eq!(true, r["w"]);
+        order.status = 1;
+        let r = to_execution_report(
+            &order,
+            (Decimal::new(5, 1), Decimal::new(5, 0), Decimal::new(1, 3)),
+        );
+        assert_eq!("CANCELED", r["x"]);
+        assert_eq!("CANCELED", r["X"]);
+        assert_eq!("0", r["l"]);
+        assert_eq!("0", r["n"]);
+        assert_eq!(false, r["w"]);
+    }
+}
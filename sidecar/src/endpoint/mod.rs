@@ -0,0 +1,609 @@
+// Copyright 2021-2023 UINB Technologies Pte. Ltd.
+
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// 	http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::{context::Context, db, errors::CustomRpcError, webhook::WebhookCommand};
+use galois_engine::{core::*, output::DepthDelta};
+use jsonrpsee::{RpcModule, SubscriptionSink};
+use parity_scale_codec::{Decode, Encode};
+use serde::{Deserialize, Serialize};
+use sp_core::crypto::Ss58Codec;
+use std::sync::Arc;
+use tokio::sync::broadcast::error::RecvError;
+
+pub mod binance;
+pub mod rest;
+
+pub fn export_rpc(context: Arc<Context>) -> RpcModule<Arc<Context>> {
+    let mut module = RpcModule::new(context);
+    module
+        .register_async_method("query_pending_orders", |p, ctx| async move {
+            let (symbol, user_id, signature, nonce) =
+                p.parse::<(String, String, String, String)>()?;
+            let user_id = crate::try_into_account(user_id)?;
+            let symbol = crate::hexstr_to_vec(&symbol)?;
+            let signature = crate::hexstr_to_vec(&signature)?;
+            let nonce = crate::hexstr_to_vec(&nonce)?;
+            ctx.verify_trading_signature(&symbol, &user_id, &signature, &nonce)
+                .await
+                .map_err(handle_error)?;
+            let symbol = Symbol::decode(&mut symbol.as_slice())
+                .map_err(|_| anyhow::anyhow!("invalid symbol"))?;
+            ctx.backend
+                .query_pending_orders(symbol, &user_id.to_ss58check())
+                .await
+                .map(|r| {
+                    r.into_iter()
+                        .map(|o| crate::to_hexstr(o))
+                        .collect::<Vec<_>>()
+                })
+                .map_err(handle_error)
+        })
+        .unwrap();
+    module
+        .register_async_method("query_klines", |p, ctx| async move {
+            let (symbol, interval, from, to, limit) =
+                p.parse::<(String, String, Option<u64>, Option<u64>, Option<u32>)>()?;
+            let symbol = decode_symbol(&symbol)?;
+            ctx.backend
+                .query_klines(symbol, &interval, from, to, limit)
+                .await
+                .map_err(handle_error)
+        })
+        .unwrap();
+    module
+        .register_async_method("query_trades", |p, ctx| async move {
+            let (symbol, limit) = p.parse::<(String, Option<u32>)>()?;
+            let symbol = decode_symbol(&symbol)?;
+            ctx.backend
+                .query_trades(symbol, limit)
+                .await
+                .map_err(handle_error)
+        })
+        .unwrap();
+    module
+        .register_async_method("query_ticker", |p, ctx| async move {
+            let symbol = p
+                .sequence()
+                .optional_next::<String>()?
+                .map(|s| decode_symbol(&s))
+                .transpose()?;
+            ctx.backend.query_ticker(symbol).await.map_err(handle_error)
+        })
+        .unwrap();
+    module
+        .register_async_method("query_book_imbalance", |p, ctx| async move {
+            let symbol = p
+                .sequence()
+                .optional_next::<String>()?
+                .map(|s| decode_symbol(&s))
+                .transpose()?;
+            ctx.backend
+                .query_book_imbalance(symbol)
+                .await
+                .map_err(handle_error)
+        })
+        .unwrap();
+    module
+        .register_async_method("list_markets", |_, ctx| async move {
+            ctx.backend.list_markets().await.map_err(handle_error)
+        })
+        .unwrap();
+    module
+        .register_async_method("query_account", |p, ctx| async move {
+            let (user_id, signature, nonce) = p.parse::<(String, String, String)>()?;
+            let user_id = crate::try_into_account(user_id)?;
+            let signature = crate::hexstr_to_vec(&signature)?;
+            let nonce = crate::hexstr_to_vec(&nonce)?;
+            ctx.verify_trading_signature(&[], &user_id, &signature, &nonce)
+                .await
+                .map_err(handle_error)?;
+            ctx.backend
+                .get_account(&user_id.to_ss58check())
+                .await
+                .map(|r| {
+                    r.into_iter()
+                        .map(|(k, v)| crate::to_hexstr((k, v)))
+                        .collect::<Vec<_>>()
+                })
+                .map_err(handle_error)
+        })
+        .unwrap();
+    module
+        .register_async_method("query_valued_account", |p, ctx| async move {
+            let (user_id, signature, nonce) = p.parse::<(String, String, String)>()?;
+            let user_id = crate::try_into_account(user_id)?;
+            let signature = crate::hexstr_to_vec(&signature)?;
+            let nonce = crate::hexstr_to_vec(&nonce)?;
+            ctx.verify_trading_signature(&[], &user_id, &signature, &nonce)
+                .await
+                .map_err(handle_error)?;
+            ctx.backend
+                .get_valued_account(&user_id.to_ss58check())
+                .await
+                .map_err(handle_error)
+        })
+        .unwrap();
+    module
+        .register_async_method("trade", |p, ctx| async move {
+            let (user_id, cmd, signature, nonce, relayer) =
+                p.parse::<(String, String, String, String, String)>()?;
+            let user_id = crate::try_into_account(user_id)?;
+            let ss58 = user_id.to_ss58check();
+            let signature = crate::hexstr_to_vec(&signature)?;
+            let nonce = crate::hexstr_to_vec(&nonce)?;
+            let hex = crate::hexstr_to_vec(&cmd)?;
+            let cmd = TradingCommand::decode(&mut hex.clone().as_slice())
+                .map_err(|_| anyhow::anyhow!("Invalid command"))?;
+            ctx.verify_trading_signature(&hex, &user_id, &signature, &nonce)
+                .await
+                .map_err(handle_error)?;
+            ctx.validate_cmd(&ss58, &cmd).await.map_err(handle_error)?;
+            match cmd {
+                TradingCommand::CancelAll { base, quote } => ctx
+                    .backend
+                    .cancel_all(ss58, (base, quote))
+                    .await
+                    .map(|ids| crate::to_hexstr(ids))
+                    .map_err(handle_error),
+                _ => ctx
+                    .backend
+                    .submit_trading_command(ss58, cmd, relayer)
+                    .await
+                    .map(|id| crate::to_hexstr(id))
+                    .map_err(handle_error),
+            }
+        })
+        .unwrap();
+    module
+        .register_async_method("register_trading_key", |p, ctx| async move {
+            let mut params = p.sequence();
+            let user_id = params.next::<String>()?;
+            let user_x25519_pub = params.next::<String>()?;
+            let sig = params.next::<String>()?;
+            // the signature v2 is followed by its expiry
+            let expiry = params.optional_next::<u64>()?;
+            log::debug!(
+                "user = {}, x25519 = {}, sign = {} ",
+                &user_id,
+                &user_x25519_pub,
+                &sig
+            );
+            let user_id = crate::try_into_account(user_id)?;
+            let user_x25519_pub_vec = crate::hexstr_to_vec(&user_x25519_pub)?;
+            let raw_sig = crate::hexstr_to_vec(&sig)?;
+            ctx.check_expiry(expiry).map_err(handle_error)?;
+            let v2 = expiry
+                .map(|e| crate::registration_text(&ctx.signature.domain, e, &user_x25519_pub_vec));
+            if raw_sig.len() == 64 {
+                let message = format!("<Bytes>{}</Bytes>", v2.unwrap_or(user_x25519_pub));
+                crate::verify_sr25519_or_ed25519(raw_sig, message.into_bytes().as_ref(), &user_id)
+                    .map_err(handle_error)?;
+            } else {
+                crate::verify_ecdsa(
+                    raw_sig,
+                    &v2.unwrap_or_else(|| hex::encode(&user_x25519_pub_vec)),
+                    &user_id.to_ss58check(),
+                )
+                .map_err(handle_error)?;
+            }
+            let user_x25519_pub: [u8; 32] = user_x25519_pub_vec
+                .try_into()
+                .map_err(|_| anyhow::anyhow!("Invalid public key"))?;
+            let user_x25519_pub = x25519_dalek::PublicKey::from(user_x25519_pub);
+            let key = ctx.x25519.diffie_hellman(&user_x25519_pub).to_bytes();
+            // the nonces used before registering again are still rejected by galois
+            let init_nonce = ctx
+                .backend
+                .get_last_nonce(user_id.to_ss58check())
+                .await?
+                .unwrap_or_default();
+            db::save_trading_key(&*ctx.db, &user_id, key)?;
+            ctx.open_session(&user_id, init_nonce)?;
+            Ok(crate::to_hexstr(init_nonce + 1))
+        })
+        .unwrap();
+    module
+        .register_async_method("register_trading_key_for_subaccount", |p, ctx| async move {
+            let mut params = p.sequence();
+            let user_id = params.next::<String>()?;
+            let bot_id = params.next::<String>()?;
+            let token = params.next::<u32>()?;
+            let bot_x25519_pub = params.next::<String>()?;
+            let sig = params.next::<String>()?;
+            let expiry = params.optional_next::<u64>()?;
+            log::debug!(
+                "user = {}, bot = {}, x25519 = {}, sign = {} ",
+                &user_id,
+                &bot_id,
+                &bot_x25519_pub,
+                &sig
+            );
+            let user_id = crate::try_into_account(user_id)?;
+            let bot_id = crate::try_into_account(bot_id)?;
+            let sub_id = crate::derive_sub_account(&user_id, &bot_id, token);
+            let bot_x25519_pub_vec = crate::hexstr_to_vec(&bot_x25519_pub)?;
+            let raw_sig = crate::hexstr_to_vec(&sig)?;
+            ctx.check_expiry(expiry).map_err(handle_error)?;
+            let message = format!(
+                "<Bytes>{}</Bytes>",
+                expiry
+                    .map(|e| crate::registration_text(
+                        &ctx.signature.domain,
+                        e,
+                        &bot_x25519_pub_vec
+                    ))
+                    .unwrap_or(bot_x25519_pub)
+            );
+            // the bot account must be sr25519 or ed25519
+            crate::verify_sr25519_or_ed25519(raw_sig, message.into_bytes().as_ref(), &bot_id)
+                .map_err(handle_error)?;
+            let bot_x25519_pub: [u8; 32] = bot_x25519_pub_vec
+                .try_into()
+                .map_err(|_| anyhow::anyhow!("Invalid public key"))?;
+            let bot_x25519_pub = x25519_dalek::PublicKey::from(bot_x25519_pub);
+            let key = ctx.x25519.diffie_hellman(&bot_x25519_pub).to_bytes();
+            let init_nonce = ctx
+                .backend
+                .get_last_nonce(sub_id.to_ss58check())
+                .await?
+                .unwrap_or_default();
+            db::save_trading_key(&*ctx.db, &sub_id, key)?;
+            ctx.open_session(&sub_id, init_nonce)?;
+            Ok(crate::to_hexstr(init_nonce + 1))
+        })
+        .unwrap();
+    module
+        .register_async_method("get_nonce", |p, ctx| async move {
+            let user_id = p.parse::<(String,)>()?;
+            let user_id = crate::try_into_account(user_id.0)?;
+            ctx.get_user_nonce(&user_id)
+                .await
+                .map(|n| crate::to_hexstr(n))
+                .map_err(handle_error)
+        })
+        .unwrap();
+    module
+        .register_async_method("append_user", |p, ctx| async move {
+            let (user_id, signature, nonce, relayer) =
+                p.parse::<(String, String, String, String)>()?;
+            let user_id = crate::try_into_account(user_id)?;
+            let signature = crate::hexstr_to_vec(&signature)?;
+            let nonce = crate::hexstr_to_vec(&nonce)?;
+            ctx.verify_trading_signature(&[], &user_id, &signature, &nonce)
+                .await
+                .map_err(handle_error)?;
+            let tx = ctx
+                .subscribers
+                .get(&format!("broker:{}", relayer))
+                .map(|b| b.value().clone())
+                .ok_or_else(|| anyhow::anyhow!("Broker not initialized."))?;
+            ctx.subscribers.insert(user_id.to_ss58check(), tx);
+            Ok(())
+        })
+        .unwrap();
+    module
+        .register_async_method("webhook", |p, ctx| async move {
+            let (user_id, cmd, signature, nonce) = p.parse::<(String, String, String, String)>()?;
+            let user_id = crate::try_into_account(user_id)?;
+            let signature = crate::hexstr_to_vec(&signature)?;
+            let nonce = crate::hexstr_to_vec(&nonce)?;
+            let hex = crate::hexstr_to_vec(&cmd)?;
+            let cmd = WebhookCommand::decode(&mut hex.as_slice())
+                .map_err(|_| anyhow::anyhow!("Invalid command"))?;
+            ctx.verify_trading_signature(&hex, &user_id, &signature, &nonce)
+                .await
+                .map_err(handle_error)?;
+            let webhooks = ctx
+                .webhooks
+                .as_ref()
+                .ok_or(CustomRpcError::invalid_webhook("webhooks disabled"))?;
+            webhooks.execute(&user_id, cmd).map_err(handle_error)
+        })
+        .unwrap();
+    module
+        .register_async_method("list_webhooks", |p, ctx| async move {
+            let (user_id, signature, nonce) = p.parse::<(String, String, String)>()?;
+            let user_id = crate::try_into_account(user_id)?;
+            let signature = crate::hexstr_to_vec(&signature)?;
+            let nonce = crate::hexstr_to_vec(&nonce)?;
+            ctx.verify_trading_signature(&[], &user_id, &signature, &nonce)
+                .await
+                .map_err(handle_error)?;
+            let webhooks = ctx
+                .webhooks
+                .as_ref()
+                .ok_or(CustomRpcError::invalid_webhook("webhooks disabled"))?;
+            // the secrets are never replied
+            webhooks
+                .list(&user_id)
+                .map(|hooks| {
+                    hooks
+                        .iter()
+                        .map(|h| serde_json::json!({ "url": h.url, "events": h.events }))
+                        .collect::<Vec<_>>()
+                })
+                .map_err(handle_error)
+        })
+        .unwrap();
+    module
+        .register_subscription("sub_trading", "", "unsub_trading", |p, mut sink, ctx| {
+            let (broker,) = p.parse::<(String,)>()?;
+            let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+            sink.accept()?;
+            tokio::spawn(async move {
+                loop {
+                    if let Some((user_id, order)) = rx.recv().await {
+                        let v = serde_json::json!({
+                            "user_id": user_id,
+                            "order": crate::to_hexstr(order),
+                        });
+                        match sink.send(&v) {
+                            Ok(true) => {}
+                            Ok(false) => break,
+                            Err(e) => log::error!("Unable to serialize msg, {:?}", e),
+                        }
+                    } else {
+                        break;
+                    }
+                }
+            });
+            ctx.subscribers.insert(format!("broker:{}", broker), tx);
+            Ok(())
+        })
+        .unwrap();
+    module
+        .register_subscription("sub_depth", "depth", "unsub_depth", |p, mut sink, ctx| {
+            let (symbol,) = p.parse::<(String,)>()?;
+            let symbol = decode_symbol(&symbol)?;
+            let mut rx = ctx.depth_updates.subscribe();
+            sink.accept()?;
+            tokio::spawn(async move {
+                // the deltas are chained by `prev_update_id`, resync from a snapshot on any gap
+                let mut last = None;
+                loop {
+                    if last.is_none() {
+                        let snapshot = match ctx.backend.query_depth(symbol, None, None).await {
+                            Ok(snapshot) => snapshot,
+                            Err(e) => {
+                                log::error!("unable to fetch depth of {:?}, {:?}", symbol, e);
+                                break;
+                            }
+                        };
+                        last = Some(snapshot.update_id);
+                        if !forward(&mut sink, &DepthDelta::snapshot(&snapshot)) {
+                            break;
+                        }
+                    }
+                    match rx.recv().await {
+                        Ok(delta) if delta.symbol == symbol => {
+                            if last.map_or(false, |id| delta.update_id <= id) {
+                                continue;
+                            }
+                            if delta.prev_update_id != last {
+                                last = None;
+                                continue;
+                            }
+                            last = Some(delta.update_id);
+                            if !forward(&mut sink, &delta) {
+                                break;
+                            }
+                        }
+                        Ok(_) => {}
+                        Err(RecvError::Lagged(n)) => {
+                            log::warn!("depth subscriber lagged {} updates, resyncing", n);
+                            last = None;
+                        }
+                        Err(RecvError::Closed) => break,
+                    }
+                }
+            });
+            Ok(())
+        })
+        .unwrap();
+    module
+        .register_subscription(
+            "sub_trades",
+            "trades",
+            "unsub_trades",
+            |p, mut sink, ctx| {
+                let (symbol,) = p.parse::<(String,)>()?;
+                let symbol = decode_symbol(&symbol)?;
+                let mut rx = ctx.trades.subscribe();
+                sink.accept()?;
+                tokio::spawn(async move {
+                    loop {
+                        match rx.recv().await {
+                            Ok((s, trades)) if s == symbol => {
+                                if !forward(&mut sink, &trades) {
+                                    break;
+                                }
+                            }
+                            Ok(_) => {}
+                            Err(RecvError::Lagged(n)) => {
+                                log::warn!("trades subscriber lagged, {} ticks dropped", n)
+                            }
+                            Err(RecvError::Closed) => break,
+                        }
+                    }
+                });
+                Ok(())
+            },
+        )
+        .unwrap();
+    module
+        .register_subscription(
+            "sub_orders",
+            "orders",
+            "unsub_orders",
+            |p, mut sink, ctx| {
+                let (user_id, signature, nonce) = p.parse::<(String, String, String)>()?;
+                let user_id = crate::try_into_account(user_id)?;
+                let signature = crate::hexstr_to_vec(&signature)?;
+                let nonce = crate::hexstr_to_vec(&nonce)?;
+                let mut rx = ctx.order_updates.subscribe();
+                tokio::spawn(async move {
+                    if let Err(e) = ctx
+                        .verify_trading_signature(&[], &user_id, &signature, &nonce)
+                        .await
+                    {
+                        let _ = sink.reject(handle_error(e));
+                        return;
+                    }
+                    if sink.accept().is_err() {
+                        return;
+                    }
+                    let user_id = user_id.to_ss58check();
+                    loop {
+                        match rx.recv().await {
+                            Ok((u, order)) if u == user_id => {
+                                if !forward(&mut sink, &crate::to_hexstr(order)) {
+                                    break;
+                                }
+                            }
+                            Ok(_) => {}
+                            Err(RecvError::Lagged(n)) => {
+                                log::warn!("orders subscriber lagged, {} updates dropped", n)
+                            }
+                            Err(RecvError::Closed) => break,
+                        }
+                    }
+                });
+                Ok(())
+            },
+        )
+        .unwrap();
+    module
+}
+
+fn decode_symbol(symbol: &str) -> anyhow::Result<Symbol> {
+    let symbol = crate::hexstr_to_vec(symbol)?;
+    Symbol::decode(&mut symbol.as_slice()).map_err(|_| anyhow::anyhow!("invalid symbol"))
+}
+
+// returns false if the subscription is closed
+fn forward<T: Serialize>(sink: &mut SubscriptionSink, msg: &T) -> bool {
+    match sink.send(msg) {
+        Ok(alive) => alive,
+        Err(e) => {
+            log::error!("Unable to serialize msg, {:?}", e);
+            true
+        }
+    }
+}
+
+#[derive(Clone, Serialize, Deserialize, Debug, Eq, PartialEq, Encode)]
+pub struct PendingOrderWrapper {
+    order_id: u64,
+    symbol: Symbol,
+    direction: u8,
+    create_timestamp: u64,
+    amount: String,
+    price: String,
+    status: u16,
+    matched_quote_amount: String,
+    matched_base_amount: String,
+    base_fee: String,
+    quote_fee: String,
+    execution: Option<ExecutionWrapper>,
+}
+
+#[derive(Clone, Serialize, Deserialize, Debug, Eq, PartialEq, Encode)]
+pub struct ExecutionWrapper {
+    best_price: String,
+    average_price: String,
+    levels: u32,
+}
+
+impl From<FillReport> for PendingOrderWrapper {
+    fn from(report: FillReport) -> Self {
+        let mut order: Self = report.order.into();
+        order.execution = report.execution.map(|e| ExecutionWrapper {
+            best_price: e.best_price.to_string(),
+            average_price: e.average_price.to_string(),
+            levels: e.levels,
+        });
+        order
+    }
+}
+
+impl From<PendingOrder> for PendingOrderWrapper {
+    fn from(order: PendingOrder) -> Self {
+        Self {
+            order_id: order.order_id,
+            symbol: order.symbol,
+            direction: order.direction,
+            create_timestamp: order.create_timestamp,
+            amount: order.amount.to_string(),
+            price: order.price.to_string(),
+            status: order.status.into(),
+            matched_quote_amount: order.matched_quote_amount.to_string(),
+            matched_base_amount: order.matched_base_amount.to_string(),
+            base_fee: order.base_fee.to_string(),
+            quote_fee: order.quote_fee.to_string(),
+            execution: None,
+        }
+    }
+}
+
+#[derive(Eq, PartialEq, Clone, Encode, Decode, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum TradingCommand {
+    Ask {
+        base: u32,
+        quote: u32,
+        amount: String,
+        price: String,
+    },
+    Bid {
+        base: u32,
+        quote: u32,
+        amount: String,
+        price: String,
+    },
+    Cancel {
+        base: u32,
+        quote: u32,
+        order_id: u64,
+    },
+    CancelAll {
+        base: u32,
+        quote: u32,
+    },
+    /// between the signer and its sub-account of `bot` and `token`, `bot` in ss58 or hex
+    SubTransfer {
+        bot: String,
+        token: u32,
+        currency: u32,
+        amount: String,
+        to_sub: bool,
+    },
+}
+
+impl TradingCommand {
+    pub fn get_direction_if_trade(&self) -> Option<u8> {
+        match self {
+            TradingCommand::Ask { .. } => Some(AskOrBid::Ask.into()),
+            TradingCommand::Bid { .. } => Some(AskOrBid::Bid.into()),
+            _ => None,
+        }
+    }
+}
+
+fn handle_error(e: anyhow::Error) -> jsonrpsee::core::Error {
+    let error = e.downcast::<jsonrpsee::core::Error>();
+    match error {
+        Ok(e) => e,
+        Err(e) => e.into(),
+    }
+}
@@ -0,0 +1,607 @@
+// Copyright 2021-2023 UINB Technologies Pte. Ltd.
+
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// 	http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::{
+    context::{BrokerSignatureVerifier, Context},
+    endpoint::TradingCommand,
+};
+use galois_engine::{core::*, statement};
+use hyper::{
+    header::{CONTENT_DISPOSITION, CONTENT_TYPE},
+    service::{make_service_fn, service_fn},
+    Body, Method, Request, Response, Server, StatusCode,
+};
+use jsonrpsee::types::error::CallError;
+use parity_scale_codec::Encode;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value as JsonValue};
+use sp_core::crypto::Ss58Codec;
+use std::{collections::HashMap, convert::Infallible, net::SocketAddr, sync::Arc};
+use tower::ServiceExt;
+
+/// the plain HTTP/JSON facade of the jsonrpc methods, the orders are signed by the users exactly
+/// the same as `trade` and the requests are signed by the brokers with the `X-Broker-*` headers
+pub async fn serve(addr: SocketAddr, context: Arc<Context>) -> anyhow::Result<()> {
+    let make = make_service_fn(move |_| {
+        let ctx = context.clone();
+        async move { Ok::<_, Infallible>(service_fn(move |req| route(ctx.clone(), req))) }
+    });
+    log::info!("REST gateway listening on {}", addr);
+    Server::bind(&addr).serve(make).await?;
+    Ok(())
+}
+
+async fn route(ctx: Arc<Context>, req: Request<Body>) -> Result<Response<Body>, Infallible> {
+    let r = match (req.method(), req.uri().path()) {
+        (&Method::GET, "/openapi.json") => Ok(reply(StatusCode::OK, &openapi())),
+        (&Method::GET, "/depth") => query_depth(&ctx, &req).await,
+        (&Method::GET, "/statement") => export_statement(&ctx, &req).await,
+        (_, path) if path == "/orders" || path.starts_with("/orders/") => {
+            let verifier = BrokerSignatureVerifier {
+                backend: ctx.backend.clone(),
+                inner: tower::service_fn(move |req| orders(ctx.clone(), req)),
+            };
+            return Ok(verifier.oneshot(req).await.unwrap_or_else(|e| {
+                log::debug!("broker verification failed, {:?}", e);
+                error(StatusCode::UNAUTHORIZED, None, "invalid broker signature")
+            }));
+        }
+        _ => Err(RestError::NotFound),
+    };
+    Ok(r.unwrap_or_else(|e| e.into()))
+}
+
+async fn orders(ctx: Arc<Context>, req: Request<Body>) -> Result<Response<Body>, Infallible> {
+    let path = req.uri().path().to_string();
+    let r = match (req.method(), path.strip_prefix("/orders")) {
+        (&Method::GET, Some("")) => query_orders(&ctx, &req).await,
+        (&Method::POST, Some("")) => place_order(&ctx, req).await,
+        (&Method::DELETE, Some(id)) if id.starts_with('/') => match id[1..].parse::<u64>() {
+            Ok(id) => cancel_order(&ctx, &req, id).await,
+            Err(_) => Err(RestError::BadRequest("invalid order id".to_string())),
+        },
+        _ => Err(RestError::NotFound),
+    };
+    Ok(r.unwrap_or_else(|e| e.into()))
+}
+
+#[derive(Debug)]
+enum RestError {
+    NotFound,
+    BadRequest(String),
+    /// rejected by the sidecar or galois, the codes are the same as the jsonrpc errors
+    Rejected(Option<i32>, String),
+}
+
+impl From<anyhow::Error> for RestError {
+    fn from(e: anyhow::Error) -> Self {
+        match e.downcast::<jsonrpsee::core::Error>() {
+            Ok(jsonrpsee::core::Error::Call(CallError::Custom(o))) => {
+                RestError::Rejected(Some(o.code()), o.message().to_string())
+            }
+            Ok(e) => RestError::Rejected(None, e.to_string()),
+            Err(e) => RestError::Rejected(None, e.to_string()),
+        }
+    }
+}
+
+impl From<RestError> for Response<Body> {
+    fn from(e: RestError) -> Self {
+        match e {
+            RestError::NotFound => error(StatusCode::NOT_FOUND, None, "not found"),
+            RestError::BadRequest(msg) => error(StatusCode::BAD_REQUEST, None, &msg),
+            RestError::Rejected(code, msg) => error(StatusCode::UNPROCESSABLE_ENTITY, code, &msg),
+        }
+    }
+}
+
+fn reply<T: Serialize>(status: StatusCode, body: &T) -> Response<Body> {
+    Response::builder()
+        .status(status)
+        .header(CONTENT_TYPE, "application/json")
+        .body(Body::from(serde_json::to_vec(body).unwrap_or_default()))
+        .expect("valid response;qed")
+}
+
+fn error(status: StatusCode, code: Option<i32>, msg: &str) -> Response<Body> {
+    match code {
+        Some(code) => reply(status, &json!({ "code": code, "error": msg })),
+        None => reply(status, &json!({ "error": msg })),
+    }
+}
+
+fn parse_query(req: &Request<Body>) -> HashMap<String, String> {
+    req.uri()
+        .query()
+        .unwrap_or_default()
+        .split('&')
+        .filter_map(|kv| kv.split_once('='))
+        .map(|(k, v)| (k.to_string(), v.to_string()))
+        .collect()
+}
+
+fn param<T: std::str::FromStr>(query: &HashMap<String, String>, key: &str) -> Result<T, RestError> {
+    query
+        .get(key)
+        .and_then(|v| v.parse().ok())
+        .ok_or_else(|| RestError::BadRequest(format!("invalid or missing `{}`", key)))
+}
+
+fn symbol_of(query: &HashMap<String, String>) -> Result<Symbol, RestError> {
+    Ok((param(query, "base")?, param(query, "quote")?))
+}
+
+/// the user, signature and nonce of the signed requests, in the same forms as the jsonrpc methods
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Signed {
+    user_id: String,
+    signature: String,
+    nonce: String,
+}
+
+impl Signed {
+    fn from_query(query: &HashMap<String, String>) -> Result<Self, RestError> {
+        Ok(Self {
+            user_id: param(query, "user_id")?,
+            signature: param(query, "signature")?,
+            nonce: param(query, "nonce")?,
+        })
+    }
+
+    /// verify the trading signature of `data`, returning the ss58 address of the user
+    async fn verify(self, ctx: &Context, data: &[u8]) -> Result<String, RestError> {
+        let user_id = crate::try_into_account(self.user_id)?;
+        let signature = crate::hexstr_to_vec(&self.signature)?;
+        let nonce = crate::hexstr_to_vec(&self.nonce)?;
+        ctx.verify_trading_signature(data, &user_id, &signature, &nonce)
+            .await?;
+        Ok(user_id.to_ss58check())
+    }
+}
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum Side {
+    Ask,
+    Bid,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct PlaceOrder {
+    base: u32,
+    quote: u32,
+    side: Side,
+    amount: String,
+    price: String,
+    #[serde(flatten)]
+    signed: Signed,
+}
+
+impl PlaceOrder {
+    fn to_cmd(&self) -> TradingCommand {
+        let (base, quote) = (self.base, self.quote);
+        let (amount, price) = (self.amount.clone(), self.price.clone());
+        match self.side {
+            Side::Ask => TradingCommand::Ask {
+                base,
+                quote,
+                amount,
+                price,
+            },
+            Side::Bid => TradingCommand::Bid {
+                base,
+                quote,
+                amount,
+                price,
+            },
+        }
+    }
+}
+
+fn broker_of(req: &Request<Body>) -> String {
+    // verified by the layer
+    req.headers()
+        .get("X-Broker-Account")
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or_default()
+        .to_string()
+}
+
+async fn query_depth(ctx: &Context, req: &Request<Body>) -> Result<Response<Body>, RestError> {
+    let query = parse_query(req);
+    let symbol = symbol_of(&query)?;
+    let limit = query
+        .contains_key("limit")
+        .then(|| param(&query, "limit"))
+        .transpose()?;
+    let tick = query
+        .contains_key("tick")
+        .then(|| param::<Price>(&query, "tick"))
+        .transpose()?;
+    if tick.map_or(false, |t| t <= Price::ZERO) {
+        return Err(RestError::BadRequest("invalid `tick`".to_string()));
+    }
+    let depth = ctx.backend.query_depth(symbol, limit, tick).await?;
+    Ok(reply(StatusCode::OK, &depth))
+}
+
+/// the statement in `[from, to]` as json or an attached csv
+async fn export_statement(ctx: &Context, req: &Request<Body>) -> Result<Response<Body>, RestError> {
+    let query = parse_query(req);
+    let (from, to) = (param::<u64>(&query, "from")?, param::<u64>(&query, "to")?);
+    if from > to {
+        return Err(RestError::BadRequest("`from` is after `to`".to_string()));
+    }
+    let format = query.get("format").map(|f| f.as_str()).unwrap_or("json");
+    if format != "json" && format != "csv" {
+        return Err(RestError::BadRequest("invalid `format`".to_string()));
+    }
+    let user_id = Signed::from_query(&query)?
+        .verify(ctx, &(from, to).encode())
+        .await?;
+    let entries = ctx.backend.query_statement(&user_id, from, to).await?;
+    if format == "json" {
+        return Ok(reply(StatusCode::OK, &entries));
+    }
+    Ok(Response::builder()
+        .status(StatusCode::OK)
+        .header(CONTENT_TYPE, "text/csv")
+        .header(
+            CONTENT_DISPOSITION,
+            format!("attachment; filename=\"statement-{}-{}.csv\"", from, to),
+        )
+        .body(Body::from(statement::to_csv(&entries)))
+        .expect("valid response;qed"))
+}
+
+async fn query_orders(ctx: &Context, req: &Request<Body>) -> Result<Response<Body>, RestError> {
+    let query = parse_query(req);
+    let symbol = symbol_of(&query)?;
+    let user_id = Signed::from_query(&query)?
+        .verify(ctx, &symbol.encode())
+        .await?;
+    let orders = ctx.backend.query_pending_orders(symbol, &user_id).await?;
+    Ok(reply(StatusCode::OK, &orders))
+}
+
+async fn place_order(ctx: &Context, req: Request<Body>) -> Result<Response<Body>, RestError> {
+    let broker = broker_of(&req);
+    let body = hyper::body::to_bytes(req.into_body())
+        .await
+        .map_err(|e| RestError::BadRequest(e.to_string()))?;
+    let order: PlaceOrder =
+        serde_json::from_slice(&body).map_err(|e| RestError::BadRequest(e.to_string()))?;
+    let cmd = order.to_cmd();
+    let user_id = order.signed.verify(ctx, &cmd.encode()).await?;
+    ctx.validate_cmd(&user_id, &cmd).await?;
+    let id = ctx
+        .backend
+        .submit_trading_command(user_id, cmd, broker)
+        .await?;
+    Ok(reply(StatusCode::CREATED, &json!({ "order_id": id })))
+}
+
+async fn cancel_order(
+    ctx: &Context,
+    req: &Request<Body>,
+    order_id: u64,
+) -> Result<Response<Body>, RestError> {
+    let query = parse_query(req);
+    let (base, quote) = symbol_of(&query)?;
+    let cmd = TradingCommand::Cancel {
+        base,
+        quote,
+        order_id,
+    };
+    let user_id = Signed::from_query(&query)?
+        .verify(ctx, &cmd.encode())
+        .await?;
+    ctx.validate_cmd(&user_id, &cmd).await?;
+    let id = ctx
+        .backend
+        .submit_trading_command(user_id, cmd, broker_of(req))
+        .await?;
+    Ok(reply(StatusCode::OK, &json!({ "order_id": id })))
+}
+
+fn param_spec(name: &str, description: &str, schema: JsonValue) -> JsonValue {
+    json!({
+        "name": name,
+        "in": "query",
+        "required": true,
+        "description": description,
+        "schema": schema,
+    })
+}
+
+fn optional(mut spec: JsonValue) -> JsonValue {
+    spec["required"] = json!(false);
+    spec
+}
+
+/// OpenAPI 3.0 schema of the gateway, served as `GET /openapi.json`
+pub fn openapi() -> JsonValue {
+    let symbol = vec![
+        param_spec(
+            "base",
+            "currency id of the base",
+            json!({"type": "integer"}),
+        ),
+        param_spec(
+            "quote",
+            "currency id of the quote",
+            json!({"type": "integer"}),
+        ),
+    ];
+    let depth = [
+        symbol.clone(),
+        vec![
+            optional(param_spec(
+                "limit",
+                "the levels of each side",
+                json!({"type": "integer"}),
+            )),
+            optional(param_spec(
+                "tick",
+                "the price step grouping the levels",
+                json!({"type": "string", "example": "0.5"}),
+            )),
+        ],
+    ]
+    .concat();
+    let signed = vec![
+        param_spec("user_id", "ss58 or hex address", json!({"type": "string"})),
+        param_spec(
+            "signature",
+            "hex of blake2_256(data ++ trading key ++ nonce)",
+            json!({"type": "string"}),
+        ),
+        param_spec(
+            "nonce",
+            "hex of SCALE encoded u32",
+            json!({"type": "string"}),
+        ),
+    ];
+    let decimal = json!({"type": "string", "example": "1.5"});
+    let broker = ["X-Broker-Account", "X-Broker-Nonce", "X-Broker-Signature"]
+        .iter()
+        .map(|h| json!({"name": h, "in": "header", "required": true, "schema": {"type": "string"}}))
+        .collect::<Vec<_>>();
+    let rejected = json!({"$ref": "#/components/schemas/Error"});
+    let errors = json!({
+        "400": {"description": "invalid request", "content": {"application/json": {"schema": rejected}}},
+        "401": {"description": "invalid broker signature", "content": {"application/json": {"schema": rejected}}},
+        "422": {"description": "rejected", "content": {"application/json": {"schema": rejected}}},
+    });
+    let with_errors = |ok: JsonValue| {
+        let mut r = errors.clone();
+        r.as_object_mut()
+            .expect("object;qed")
+            .extend(ok.as_object().expect("object;qed").clone());
+        r
+    };
+    let order_id = json!({
+        "type": "object",
+        "properties": {"order_id": {"type": "integer", "format": "int64"}},
+    });
+    let query_orders = [symbol.clone(), signed.clone(), broker.clone()].concat();
+    let path_id = json!({
+        "name": "order_id",
+        "in": "path",
+        "required": true,
+        "schema": {"type": "integer", "format": "int64"},
+    });
+    let cancel_order = [
+        vec![path_id],
+        symbol.clone(),
+        signed.clone(),
+        broker.clone(),
+    ]
+    .concat();
+    let export_statement = [
+        vec![
+            param_spec(
+                "from",
+                "unix seconds, inclusive",
+                json!({"type": "integer", "format": "int64"}),
+            ),
+            param_spec(
+                "to",
+                "unix seconds, inclusive",
+                json!({"type": "integer", "format": "int64"}),
+            ),
+            optional(param_spec(
+                "format",
+                "json by default",
+                json!({"type": "string", "enum": ["json", "csv"]}),
+            )),
+        ],
+        signed,
+    ]
+    .concat();
+    json!({
+        "openapi": "3.0.3",
+        "info": {"title": "Galois sidecar REST gateway", "version": env!("CARGO_PKG_VERSION")},
+        "paths": {
+            "/depth": {
+                "get": {
+                    "summary": "depth snapshot of a symbol, optionally the best `limit` levels grouped at the multiples of `tick`",
+                    "parameters": depth,
+                    "responses": with_errors(json!({
+                        "200": {"description": "ok", "content": {"application/json": {"schema": {"$ref": "#/components/schemas/Depth"}}}},
+                    })),
+                },
+            },
+            "/statement": {
+                "get": {
+                    "summary": "balance changes of the user by trades, fees and transfers, signing the SCALE encoded `(from, to)`",
+                    "parameters": export_statement,
+                    "responses": with_errors(json!({
+                        "200": {
+                            "description": "ok",
+                            "content": {
+                                "application/json": {"schema": {"type": "array", "items": {"$ref": "#/components/schemas/StatementEntry"}}},
+                                "text/csv": {"schema": {"type": "string"}},
+                            },
+                        },
+                    })),
+                },
+            },
+            "/orders": {
+                "get": {
+                    "summary": "pending orders of the user, signing the SCALE encoded symbol",
+                    "parameters": query_orders,
+                    "responses": with_errors(json!({
+                        "200": {"description": "ok", "content": {"application/json": {"schema": {"type": "array", "items": {"$ref": "#/components/schemas/Order"}}}}},
+                    })),
+                },
+                "post": {
+                    "summary": "place a limit order, signing the SCALE encoded `TradingCommand::Ask` or `Bid`",
+                    "parameters": broker.clone(),
+                    "requestBody": {
+                        "required": true,
+                        "content": {"application/json": {"schema": {"$ref": "#/components/schemas/PlaceOrder"}}},
+                    },
+                    "responses": with_errors(json!({
+                        "201": {"description": "placed", "content": {"application/json": {"schema": order_id}}},
+                    })),
+                },
+            },
+            "/orders/{order_id}": {
+                "delete": {
+                    "summary": "cancel an order, signing the SCALE encoded `TradingCommand::Cancel`",
+                    "parameters": cancel_order,
+                    "responses": with_errors(json!({
+                        "200": {"description": "canceled", "content": {"application/json": {"schema": order_id}}},
+                    })),
+                },
+            },
+        },
+        "components": {
+            "schemas": {
+                "Error": {
+                    "type": "object",
+                    "properties": {"code": {"type": "integer"}, "error": {"type": "string"}},
+                    "required": ["error"],
+                },
+                "Depth": {
+                    "type": "object",
+                    "properties": {
+                        "update_id": {"type": "integer", "format": "int64"},
+                        "symbol": {"type": "array", "items": {"type": "integer"}},
+                        "asks": {"type": "array", "items": {"type": "array", "items": decimal}},
+                        "bids": {"type": "array", "items": {"type": "array", "items": decimal}},
+                    },
+                },
+                "Order": {
+                    "type": "object",
+                    "properties": {
+                        "order_id": {"type": "integer", "format": "int64"},
+                        "symbol": {"type": "array", "items": {"type": "integer"}},
+                        "direction": {"type": "integer", "description": "0 ask, 1 bid"},
+                        "create_timestamp": {"type": "integer", "format": "int64"},
+                        "amount": decimal,
+                        "price": decimal,
+                        "status": {"type": "integer"},
+                        "matched_quote_amount": decimal,
+                        "matched_base_amount": decimal,
+                        "base_fee": decimal,
+                        "quote_fee": decimal,
+                    },
+                },
+                "StatementEntry": {
+                    "type": "object",
+                    "properties": {
+                        "event_id": {"type": "integer", "format": "int64"},
+                        "timestamp": {"type": "integer", "format": "int64"},
+                        "kind": {"type": "string", "enum": ["trade", "fee", "transfer_in", "transfer_out", "sub_transfer"]},
+                        "currency": {"type": "integer"},
+                        "amount": decimal,
+                        "balance": decimal,
+                        "symbol": {"type": "array", "items": {"type": "integer"}, "nullable": true},
+                        "order_id": {"type": "integer", "format": "int64", "nullable": true},
+                        "price": {"type": "string", "nullable": true},
+                        "block_number": {"type": "integer", "nullable": true},
+                    },
+                },
+                "PlaceOrder": {
+                    "type": "object",
+                    "properties": {
+                        "base": {"type": "integer"},
+                        "quote": {"type": "integer"},
+                        "side": {"type": "string", "enum": ["ask", "bid"]},
+                        "amount": decimal,
+                        "price": decimal,
+                        "user_id": {"type": "string"},
+                        "signature": {"type": "string"},
+                        "nonce": {"type": "string"},
+                    },
+                    "required": ["base", "quote", "side", "amount", "price", "user_id", "signature", "nonce"],
+                },
+            },
+        },
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    pub fn test_rest_requests() {
+        let req = Request::get("/orders?base=1&quote=0&user_id=5Grw&nonce=0x01000000")
+            .body(Body::empty())
+            .unwrap();
+        let query = parse_query(&req);
+        assert_eq!((1, 0), symbol_of(&query).unwrap());
+        assert!(matches!(
+            Signed::from_query(&query),
+            Err(RestError::BadRequest(_))
+        ));
+        let order: PlaceOrder = serde_json::from_str(
+            r#"{"base":1,"quote":0,"side":"bid","amount":"2","price":"10.5","user_id":"5Grw","signature":"0x00","nonce":"0x01000000"}"#,
+        )
+        .unwrap();
+        let cmd = order.to_cmd();
+        // the same payload signed for `trade`
+        assert_eq!(
+            TradingCommand::Bid {
+                base: 1,
+                quote: 0,
+                amount: "2".to_string(),
+                price: "10.5".to_string(),
+            },
+            cmd
+        );
+        let spec = openapi();
+        for path in ["/depth", "/statement", "/orders", "/orders/{order_id}"] {
+            assert!(spec["paths"][path].is_object());
+        }
+        assert_eq!(
+            Some(false),
+            spec["paths"]["/depth"]["get"]["parameters"][3]["required"].as_bool()
+        );
+        assert_eq!(
+            Some(false),
+            spec["paths"]["/statement"]["get"]["parameters"][2]["required"].as_bool()
+        );
+        assert_eq!(
+            9,
+            spec["paths"]["/orders/{order_id}"]["delete"]["parameters"]
+                .as_array()
+                .unwrap()
+                .len()
+        );
+    }
+}
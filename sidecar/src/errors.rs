@@ -49,4 +49,23 @@ impl CustomRpcError {
     pub fn invalid_signature() -> Error {
         rpc_error!(-32015, "invalid signature")
     }
+
+    /// rejected by the sidecar before forwarding to galois
+    pub fn invalid_order(msg: impl ToString) -> Error {
+        rpc_error!(-32016, msg.to_string())
+    }
+
+    pub fn invalid_webhook(msg: impl ToString) -> Error {
+        rpc_error!(-32017, msg.to_string())
+    }
+
+    /// now is after the expiry of the signature v2, or the expiry is too far away
+    pub fn signature_expired() -> Error {
+        rpc_error!(-32018, "signature expired")
+    }
+
+    /// galois reject codes are mapped to -32100 - code
+    pub fn rejected_by_galois(code: i64, msg: impl ToString) -> Error {
+        rpc_error!((-32100 - code) as i32, msg.to_string())
+    }
 }
\ No newline at end of file
@@ -19,6 +19,8 @@ pub mod backend;
 pub mod config;
 pub mod context;
 pub mod endpoint;
+pub mod tls;
+pub mod webhook;
 
 mod db;
 mod errors;
@@ -29,6 +31,9 @@ use parity_scale_codec::{Decode, Encode};
 
 pub use sp_core::crypto::AccountId32;
 pub use sp_core::ecdsa::{Pair as EcdsaPair, Public as EcdsaPublic, Signature as EcdsaSignature};
+pub use sp_core::ed25519::{
+    Pair as Ed25519Pair, Public as Ed25519Public, Signature as Ed25519Signature,
+};
 pub use sp_core::sr25519::{
     Pair as Sr25519Pair, Public as Sr25519Public, Signature as Sr25519Signature,
 };
@@ -42,6 +47,13 @@ pub fn to_hexstr<T: Encode>(t: T) -> String {
     format!("0x{}", hex::encode(t.encode()))
 }
 
+pub fn now_millis() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .expect("after epoch;qed")
+        .as_millis() as u64
+}
+
 pub fn verify_sr25519(sig: Vec<u8>, data: &[u8], account: &AccountId32) -> anyhow::Result<()> {
     let public = Sr25519Public::from_raw(*AsRef::<[u8; 32]>::as_ref(account));
     let sig = Sr25519Signature::decode(&mut &sig[..])
@@ -54,6 +66,26 @@ pub fn verify_sr25519(sig: Vec<u8>, data: &[u8], account: &AccountId32) -> anyho
     }
 }
 
+pub fn verify_ed25519(sig: Vec<u8>, data: &[u8], account: &AccountId32) -> anyhow::Result<()> {
+    let public = Ed25519Public::from_raw(*AsRef::<[u8; 32]>::as_ref(account));
+    let sig = Ed25519Signature::decode(&mut &sig[..])
+        .map_err(|_| anyhow::anyhow!("Invalid signature"))?;
+    if Ed25519Pair::verify(&sig, data, &public) {
+        Ok(())
+    } else {
+        Err(anyhow!(CustomRpcError::invalid_signature()))
+    }
+}
+
+/// the 64 bytes signatures of the account are either sr25519 or ed25519
+pub fn verify_sr25519_or_ed25519(
+    sig: Vec<u8>,
+    data: &[u8],
+    account: &AccountId32,
+) -> anyhow::Result<()> {
+    verify_sr25519(sig.clone(), data, account).or_else(|_| verify_ed25519(sig, data, account))
+}
+
 #[cfg(feature = "testenv")]
 const LEGACY_MAPPING_CODE: u16 = 5;
 #[cfg(not(feature = "testenv"))]
@@ -81,6 +113,72 @@ pub fn verify_ecdsa(sig: Vec<u8>, data: &str, mapping_addr: impl AsRef<str>) ->
     }
 }
 
+/// prefixed to the payloads of the signatures v2
+pub const SIGNATURE_V2: &str = "galois-signature-v2";
+
+/// the nonce of the signature v1 is a SCALE encoded `u32`, v2 is followed by the expiry in
+/// milliseconds, i.e. `(u32, u64)`
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct SignedNonce {
+    pub nonce: u32,
+    pub expiry: Option<u64>,
+}
+
+impl SignedNonce {
+    pub fn decode(raw: &[u8]) -> anyhow::Result<Self> {
+        match raw.len() {
+            4 => Ok(Self {
+                nonce: u32::decode(&mut &raw[..])?,
+                expiry: None,
+            }),
+            12 => {
+                let (nonce, expiry) = <(u32, u64)>::decode(&mut &raw[..])?;
+                Ok(Self {
+                    nonce,
+                    expiry: Some(expiry),
+                })
+            }
+            _ => Err(anyhow!("Invalid nonce")),
+        }
+    }
+}
+
+/// `now` and the expiry are in milliseconds, `max_expiry` in seconds, v1 never expires
+pub fn check_expiry(expiry: Option<u64>, now: u64, max_expiry: u64) -> anyhow::Result<()> {
+    match expiry {
+        Some(expiry) if expiry < now || expiry > now.saturating_add(max_expiry * 1000) => {
+            Err(anyhow!(CustomRpcError::signature_expired()))
+        }
+        _ => Ok(()),
+    }
+}
+
+/// the trading signature is the blake2 of the payload, v2 is bound to the domain additionally
+pub fn trading_payload(domain: &str, data: &[u8], key: &[u8], nonce: &[u8]) -> Vec<u8> {
+    match nonce.len() {
+        4 => [data, key, nonce].concat(),
+        _ => [
+            SIGNATURE_V2.as_bytes(),
+            &sp_core::blake2_256(domain.as_bytes())[..],
+            data,
+            key,
+            nonce,
+        ]
+        .concat(),
+    }
+}
+
+/// the text signed v2 to register the trading key, wrapped in `<Bytes>` by sr25519 as v1
+pub fn registration_text(domain: &str, expiry: u64, x25519: &[u8]) -> String {
+    format!(
+        "{}:{}:{}:{}",
+        SIGNATURE_V2,
+        domain,
+        expiry,
+        hex::encode(x25519)
+    )
+}
+
 pub fn try_into_ss58(addr: String) -> anyhow::Result<String> {
     try_into_account(addr).map(|a| a.to_ss58check())
 }
@@ -115,6 +213,51 @@ pub fn to_mapping_address(address: Vec<u8>) -> AccountId32 {
     Decode::decode(&mut h.as_ref()).expect("32 bytes; qed")
 }
 
+#[test]
+fn test_signature_v2() {
+    let v1 = SignedNonce::decode(&83143u32.encode()).unwrap();
+    assert_eq!(None, v1.expiry);
+    assert!(check_expiry(v1.expiry, u64::MAX, 0).is_ok());
+    let raw = (83143u32, 1_700_000_060_000u64).encode();
+    let v2 = SignedNonce::decode(&raw).unwrap();
+    assert_eq!((83143, Some(1_700_000_060_000)), (v2.nonce, v2.expiry));
+    assert!(check_expiry(v2.expiry, 1_700_000_000_000, 300).is_ok());
+    assert!(check_expiry(v2.expiry, 1_700_000_060_001, 300).is_err());
+    assert!(check_expiry(v2.expiry, 1_700_000_000_000, 30).is_err());
+    assert!(SignedNonce::decode(&[0u8; 8]).is_err());
+    let key = [7u8; 32];
+    assert_eq!(
+        [&b"data"[..], &key, &83143u32.encode()].concat(),
+        trading_payload("fusotao", b"data", &key, &83143u32.encode())
+    );
+    // the same command signed for another domain differs
+    assert_ne!(
+        trading_payload("fusotao", b"data", &key, &raw),
+        trading_payload("fusotao-testnet", b"data", &key, &raw)
+    );
+    assert_eq!(
+        "galois-signature-v2:fusotao:1:0a0b",
+        registration_text("fusotao", 1, &[10, 11])
+    );
+}
+
+#[test]
+fn test_verify_ed25519() {
+    let pair = Ed25519Pair::from_string("//Alice", None).unwrap();
+    let account = AccountId32::from(pair.public().0);
+    let message = b"<Bytes>0a0b</Bytes>";
+    let sig = pair.sign(message).0.to_vec();
+    assert!(verify_ed25519(sig.clone(), message, &account).is_ok());
+    assert!(verify_sr25519_or_ed25519(sig.clone(), message, &account).is_ok());
+    assert!(verify_sr25519(sig.clone(), message, &account).is_err());
+    assert!(verify_sr25519_or_ed25519(sig, b"<Bytes>0a0c</Bytes>", &account).is_err());
+    let pair = Sr25519Pair::from_string("//Alice", None).unwrap();
+    let account = AccountId32::from(pair.public().0);
+    let sig = pair.sign(message).0.to_vec();
+    assert!(verify_sr25519_or_ed25519(sig.clone(), message, &account).is_ok());
+    assert!(verify_ed25519(sig, message, &account).is_err());
+}
+
 #[test]
 fn test_derive_sub_account() {
     use sp_keyring::AccountKeyring;
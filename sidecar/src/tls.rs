@@ -0,0 +1,48 @@
+// Copyright 2021-2023 UINB Technologies Pte. Ltd.
+
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// 	http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use galois_engine::input::tls::TlsCerts;
+use std::{net::SocketAddr, sync::Arc, time::Duration};
+use tokio::net::{TcpListener, TcpStream};
+use tokio_rustls::TlsAcceptor;
+
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// terminate TLS of the connections accepted and relay the plain streams to the websocket
+/// endpoint listening on `upstream`, jsonrpsee has no TLS of its own
+pub async fn relay(
+    listener: TcpListener,
+    upstream: SocketAddr,
+    certs: Arc<TlsCerts>,
+) -> anyhow::Result<()> {
+    log::info!("TLS terminated on {:?}", listener.local_addr());
+    loop {
+        let (stream, peer) = listener.accept().await?;
+        let acceptor = TlsAcceptor::from(certs.server_config());
+        tokio::spawn(async move {
+            let relayed = async {
+                stream.set_nodelay(true)?;
+                let mut tls =
+                    tokio::time::timeout(HANDSHAKE_TIMEOUT, acceptor.accept(stream)).await??;
+                let mut plain = TcpStream::connect(upstream).await?;
+                plain.set_nodelay(true)?;
+                tokio::io::copy_bidirectional(&mut tls, &mut plain).await?;
+                Ok::<_, anyhow::Error>(())
+            };
+            if let Err(e) = relayed.await {
+                log::debug!("TLS connection {} closed, {:?}", peer, e);
+            }
+        });
+    }
+}
@@ -0,0 +1,429 @@
+// Copyright 2021-2023 UINB Technologies Pte. Ltd.
+
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// 	http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::{config::WebhookConfig, db::KeyStore, errors::CustomRpcError, AccountId32};
+use dashmap::DashMap;
+use galois_engine::core::*;
+use hmac::{Hmac, Mac};
+use hyper::{client::HttpConnector, header::CONTENT_TYPE, Body, Client, Method, Request, Uri};
+use hyper_rustls::{HttpsConnector, HttpsConnectorBuilder};
+use parity_scale_codec::{Decode, Encode};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value as JsonValue};
+use sp_core::crypto::Ss58Codec;
+use std::{sync::Arc, time::Duration};
+
+pub const MAX_URL_LEN: usize = 256;
+
+pub const MAX_SECRET_LEN: usize = 128;
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Encode, Decode, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum WebhookEvent {
+    /// filled entirely or partially
+    OrderFilled,
+    OrderCanceled,
+    /// from or to the chain or the sub-accounts
+    Transfer,
+}
+
+impl WebhookEvent {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            WebhookEvent::OrderFilled => "order_filled",
+            WebhookEvent::OrderCanceled => "order_canceled",
+            WebhookEvent::Transfer => "transfer",
+        }
+    }
+
+    /// the placed orders aren't pushed
+    pub fn of_order(status: u8) -> Option<Self> {
+        let filled: [u8; 2] = [
+            OrderState::Filled.into(),
+            OrderState::PartiallyFilled.into(),
+        ];
+        let canceled: [u8; 2] = [
+            OrderState::Canceled.into(),
+            OrderState::ConditionallyCanceled.into(),
+        ];
+        if filled.contains(&status) {
+            Some(WebhookEvent::OrderFilled)
+        } else if canceled.contains(&status) {
+            Some(WebhookEvent::OrderCanceled)
+        } else {
+            None
+        }
+    }
+}
+
+/// the callback of a user, the payloads are signed with `secret` by HMAC-SHA256
+#[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
+pub struct Webhook {
+    pub url: String,
+    pub secret: String,
+    pub events: Vec<WebhookEvent>,
+}
+
+/// signed by the trading key of the user the same as `trade`
+#[derive(Clone, Debug, Eq, PartialEq, Encode, Decode, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum WebhookCommand {
+    /// the one of the same `url` is replaced
+    Register {
+        url: String,
+        secret: String,
+        events: Vec<WebhookEvent>,
+    },
+    Unregister {
+        url: String,
+    },
+}
+
+/// `hex(HMAC-SHA256(secret, "<timestamp>.<body>"))`, sent as `X-Galois-Signature` along with
+/// `X-Galois-Timestamp` so the receivers could reject the replayed ones
+pub fn sign(secret: &[u8], timestamp: u64, body: &[u8]) -> String {
+    let mut mac = Hmac::<sha2::Sha256>::new_from_slice(secret).expect("any size of key;qed");
+    mac.update(format!("{}.", timestamp).as_bytes());
+    mac.update(body);
+    hex::encode(mac.finalize().into_bytes())
+}
+
+/// the webhooks registered by the users, the events are delivered at least once with the
+/// exponential backoff, which may be out of order so the receivers should dedup and sort them by
+/// `event_id`
+pub struct Webhooks {
+    config: WebhookConfig,
+    db: Arc<dyn KeyStore>,
+    // read through from the store, including the users without any
+    cache: DashMap<AccountId32, Arc<Vec<Webhook>>>,
+    client: Client<HttpsConnector<HttpConnector>>,
+}
+
+impl Webhooks {
+    pub(crate) fn new(config: WebhookConfig, db: Arc<dyn KeyStore>) -> Self {
+        let https = HttpsConnectorBuilder::new()
+            .with_webpki_roots()
+            .https_or_http()
+            .enable_http1()
+            .build();
+        Self {
+            config,
+            db,
+            cache: DashMap::new(),
+            client: Client::builder().build(https),
+        }
+    }
+
+    pub fn list(&self, user_id: &AccountId32) -> anyhow::Result<Arc<Vec<Webhook>>> {
+        if let Some(hooks) = self.cache.get(user_id) {
+            return Ok(hooks.clone());
+        }
+        let hooks = Arc::new(self.db.get_webhooks(user_id)?);
+        self.cache.insert(user_id.clone(), hooks.clone());
+        Ok(hooks)
+    }
+
+    pub fn execute(&self, user_id: &AccountId32, cmd: WebhookCommand) -> anyhow::Result<()> {
+        let mut hooks = self.list(user_id)?.as_ref().clone();
+        match cmd {
+            WebhookCommand::Register {
+                url,
+                secret,
+                events,
+            } => {
+                check_webhook(&url, &secret, &events)?;
+                hooks.retain(|h| h.url != url);
+                anyhow::ensure!(
+                    hooks.len() < self.config.max_webhooks,
+                    CustomRpcError::invalid_webhook(format!(
+                        "at most {} webhooks for each user",
+                        self.config.max_webhooks
+                    ))
+                );
+                hooks.push(Webhook {
+                    url,
+                    secret,
+                    events,
+                });
+            }
+            WebhookCommand::Unregister { url } => {
+                let before = hooks.len();
+                hooks.retain(|h| h.url != url);
+                anyhow::ensure!(
+                    hooks.len() < before,
+                    CustomRpcError::invalid_webhook("webhook not exists")
+                );
+            }
+        }
+        self.db.put_webhooks(user_id, &hooks)?;
+        self.cache.insert(user_id.clone(), Arc::new(hooks));
+        Ok(())
+    }
+
+    /// push `data` to the webhooks of the user subscribing `event` in the background
+    pub fn notify(self: &Arc<Self>, user_id: &AccountId32, event: WebhookEvent, data: &JsonValue) {
+        let hooks = match self.list(user_id) {
+            Ok(hooks) => hooks,
+            Err(e) => {
+                log::error!("unable to read the webhooks of {}, {:?}", user_id, e);
+                return;
+            }
+        };
+        for hook in hooks.iter().filter(|h| h.events.contains(&event)) {
+            let body = json!({
+                "event": event,
+                "user_id": user_id.to_ss58check(),
+                "data": data,
+            });
+            let body = serde_json::to_vec(&body).unwrap_or_default();
+            tokio::spawn(self.clone().deliver(hook.clone(), event, body));
+        }
+    }
+
+    fn backoff(&self, attempt: u32) -> Duration {
+        let delay = self
+            .config
+            .backoff
+            .saturating_mul(1u64.checked_shl(attempt).unwrap_or(u64::MAX))
+            .min(self.config.max_backoff);
+        Duration::from_millis(delay)
+    }
+
+    async fn deliver(self: Arc<Self>, hook: Webhook, event: WebhookEvent, body: Vec<u8>) {
+        for attempt in 0..self.config.max_attempts {
+            if attempt > 0 {
+                tokio::time::sleep(self.backoff(attempt - 1)).await;
+            }
+            let timestamp = std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .expect("clock;qed")
+                .as_secs();
+            let req = Request::builder()
+                .method(Method::POST)
+                .uri(&hook.url)
+                .header(CONTENT_TYPE, "application/json")
+                .header("X-Galois-Event", event.as_str())
+                .header("X-Galois-Timestamp", timestamp)
+                .header(
+                    "X-Galois-Signature",
+                    sign(hook.secret.as_bytes(), timestamp, &body),
+                )
+                .body(Body::from(body.clone()));
+            let req = match req {
+                Ok(req) => req,
+                Err(e) => {
+                    log::warn!("invalid webhook {}, {:?}", hook.url, e);
+                    return;
+                }
+            };
+            let timeout = Duration::from_millis(self.config.timeout);
+            match tokio::time::timeout(timeout, self.client.request(req)).await {
+                Ok(Ok(rsp)) if rsp.status().is_success() => return,
+                Ok(Ok(rsp)) => log::debug!("webhook {} replied {}", hook.url, rsp.status()),
+                Ok(Err(e)) => log::debug!("webhook {} failed, {:?}", hook.url, e),
+                Err(_) => log::debug!("webhook {} timeout", hook.url),
+            }
+        }
+        log::warn!(
+            "{} to webhook {} dropped after {} attempts",
+            event.as_str(),
+            hook.url,
+            self.config.max_attempts
+        );
+    }
+}
+
+fn check_webhook(url: &str, secret: &str, events: &[WebhookEvent]) -> anyhow::Result<()> {
+    let uri = url
+        .parse::<Uri>()
+        .ok()
+        .filter(|_| url.len() <= MAX_URL_LEN)
+        .filter(|u| matches!(u.scheme_str(), Some("http") | Some("https")))
+        .filter(|u| u.host().is_some());
+    anyhow::ensure!(
+        uri.is_some(),
+        CustomRpcError::invalid_webhook("invalid url")
+    );
+    anyhow::ensure!(
+        !secret.is_empty() && secret.len() <= MAX_SECRET_LEN,
+        CustomRpcError::invalid_webhook(format!("secret must be of 1 to {} bytes", MAX_SECRET_LEN))
+    );
+    anyhow::ensure!(
+        !events.is_empty(),
+        CustomRpcError::invalid_webhook("no events subscribed")
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::db::MemoryStore;
+    use hyper::{
+        service::{make_service_fn, service_fn},
+        Response, Server, StatusCode,
+    };
+    use std::{convert::Infallible, sync::Mutex};
+
+    fn config() -> WebhookConfig {
+        toml::from_str("backoff = 10\nmax_backoff = 25\nmax_webhooks = 2").unwrap()
+    }
+
+    #[test]
+    pub fn test_sign() {
+        // RFC 4231, test case 2
+        let mut mac = Hmac::<sha2::Sha256>::new_from_slice(b"Jefe").unwrap();
+        mac.update(b"what do ya want for nothing?");
+        assert_eq!(
+            "5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843",
+            hex::encode(mac.finalize().into_bytes())
+        );
+        let mut mac = Hmac::<sha2::Sha256>::new_from_slice(b"Jefe").unwrap();
+        mac.update(b"100.{}");
+        assert_eq!(
+            hex::encode(mac.finalize().into_bytes()),
+            sign(b"Jefe", 100, b"{}")
+        );
+        assert_eq!(
+            Some(WebhookEvent::OrderFilled),
+            WebhookEvent::of_order(OrderState::PartiallyFilled.into())
+        );
+        assert_eq!(
+            Some(WebhookEvent::OrderCanceled),
+            WebhookEvent::of_order(OrderState::ConditionallyCanceled.into())
+        );
+        assert_eq!(None, WebhookEvent::of_order(OrderState::Placed.into()));
+    }
+
+    #[tokio::test]
+    pub async fn test_webhooks() {
+        let webhooks = Arc::new(Webhooks::new(config(), Arc::new(MemoryStore::default())));
+        assert_eq!(Duration::from_millis(10), webhooks.backoff(0));
+        assert_eq!(Duration::from_millis(20), webhooks.backoff(1));
+        assert_eq!(Duration::from_millis(25), webhooks.backoff(70));
+        let user = AccountId32::new([1; 32]);
+        let register = |url: &str, events: Vec<WebhookEvent>| WebhookCommand::Register {
+            url: url.to_string(),
+            secret: "s3cret".to_string(),
+            events,
+        };
+        assert!(webhooks
+            .execute(
+                &user,
+                register("ftp://127.0.0.1/", vec![WebhookEvent::Transfer])
+            )
+            .is_err());
+        assert!(webhooks
+            .execute(&user, register("http://127.0.0.1/", vec![]))
+            .is_err());
+        webhooks
+            .execute(
+                &user,
+                register("http://a.com/", vec![WebhookEvent::Transfer]),
+            )
+            .unwrap();
+        webhooks
+            .execute(
+                &user,
+                register("http://b.com/", vec![WebhookEvent::Transfer]),
+            )
+            .unwrap();
+        // replaced
+        webhooks
+            .execute(
+                &user,
+                register("http://b.com/", vec![WebhookEvent::OrderFilled]),
+            )
+            .unwrap();
+        assert!(webhooks
+            .execute(
+                &user,
+                register("http://c.com/", vec![WebhookEvent::Transfer])
+            )
+            .is_err());
+        assert_eq!(2, webhooks.list(&user).unwrap().len());
+        let unregister = |url: &str| WebhookCommand::Unregister {
+            url: url.to_string(),
+        };
+        webhooks
+            .execute(&user, unregister("http://a.com/"))
+            .unwrap();
+        webhooks
+            .execute(&user, unregister("http://b.com/"))
+            .unwrap();
+        assert!(webhooks
+            .execute(&user, unregister("http://b.com/"))
+            .is_err());
+        assert!(webhooks.list(&user).unwrap().is_empty());
+
+        // the first attempt fails
+        let received = Arc::new(Mutex::new(vec![]));
+        let r = received.clone();
+        let make = make_service_fn(move |_| {
+            let r = r.clone();
+            async move {
+                Ok::<_, Infallible>(service_fn(move |req: Request<Body>| {
+                    let r = r.clone();
+                    async move {
+                        let header = |k: &str| req.headers()[k].to_str().unwrap().to_string();
+                        let (event, timestamp, signature) = (
+                            header("X-Galois-Event"),
+                            header("X-Galois-Timestamp"),
+                            header("X-Galois-Signature"),
+                        );
+                        let body = hyper::body::to_bytes(req.into_body()).await.unwrap();
+                        let mut received = r.lock().unwrap();
+                        received.push((event, timestamp, signature, body.to_vec()));
+                        let status = match received.len() {
+                            1 => StatusCode::INTERNAL_SERVER_ERROR,
+                            _ => StatusCode::OK,
+                        };
+                        Ok::<_, Infallible>(
+                            Response::builder()
+                                .status(status)
+                                .body(Body::empty())
+                                .unwrap(),
+                        )
+                    }
+                }))
+            }
+        });
+        let server = Server::bind(&"127.0.0.1:0".parse().unwrap()).serve(make);
+        let url = format!("http://{}/hook", server.local_addr());
+        tokio::spawn(server);
+        webhooks
+            .execute(&user, register(&url, vec![WebhookEvent::Transfer]))
+            .unwrap();
+        webhooks.notify(&user, WebhookEvent::OrderFilled, &json!({"event_id": 1}));
+        webhooks.notify(&user, WebhookEvent::Transfer, &json!({"event_id": 2}));
+        for _ in 0..100 {
+            if received.lock().unwrap().len() >= 2 {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        let received = received.lock().unwrap();
+        assert_eq!(2, received.len());
+        assert_eq!(received[0].3, received[1].3);
+        let (event, timestamp, signature, body) = &received[1];
+        assert_eq!("transfer", event);
+        assert_eq!(
+            &sign(b"s3cret", timestamp.parse().unwrap(), body),
+            signature
+        );
+        let body: JsonValue = serde_json::from_slice(body).unwrap();
+        assert_eq!(2, body["data"]["event_id"]);
+        assert_eq!(user.to_ss58check(), body["user_id"]);
+    }
+}